@@ -0,0 +1,390 @@
+//! Timing of `zpool(8)` and `zfs(8)` invocations made by open3 engines.
+//!
+//! Every spawned command is logged with its duration: at `debug` level when it succeeds and at
+//! `info` level when it fails. To ship metrics elsewhere (e.g. Prometheus) without parsing logs
+//! install a hook with `set_instrumentation` on [`ZpoolOpen3`](../zpool/struct.ZpoolOpen3.html)
+//! or [`ZfsOpen3`](../zfs/struct.ZfsOpen3.html).
+//!
+//! Commands are isolated from the process that spawns them: stdin is `/dev/null` unless the
+//! engine needs it, descriptors other than stdio aren't inherited, and every command runs in its
+//! own process group, so Ctrl-C in the terminal of a daemon doesn't kill `zpool` halfway through.
+use slog::Logger;
+use std::{
+    ffi::OsStr,
+    fmt,
+    io::{self, BufRead, BufReader, Read},
+    os::{raw::c_int, unix::process::CommandExt},
+    process::{Command, Output, Stdio},
+    sync::Arc,
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Callback that receives metrics of every command spawned by the engine.
+pub type InstrumentationHook = Box<dyn Fn(CommandMetrics) + Send + Sync>;
+
+/// How long a command took and how it ended.
+#[derive(Debug, Clone, PartialEq, Eq, Getters)]
+#[get = "pub"]
+pub struct CommandMetrics {
+    /// Name of the command, e.g. `zpool`.
+    command: String,
+    /// First argument, e.g. `import`.
+    subcommand: Option<String>,
+    /// Wall clock time from spawn to exit.
+    duration: Duration,
+    /// Exit code. `None` if process was killed by signal.
+    exit_code: Option<i32>,
+    /// Whether command exited successfully.
+    success: bool,
+    /// Amount of bytes command wrote to stderr.
+    stderr_len: usize,
+}
+
+/// Command that didn't exit within its timeout and was killed. `run_command_with_timeout` returns
+/// it inside of `io::Error`, so `?` keeps working; error enums of engines take it out in their
+/// `From<io::Error>`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct CommandTimedOut {
+    /// Command line, e.g. `zpool list -H`.
+    pub(crate) command: String,
+    /// Timeout command has exceeded.
+    pub(crate) after: Duration,
+}
+
+impl CommandTimedOut {
+    /// Details of a timeout if `err` came from `run_command_with_timeout`.
+    pub(crate) fn from_io(err: &io::Error) -> Option<&CommandTimedOut> {
+        err.get_ref()?.downcast_ref()
+    }
+}
+
+impl fmt::Display for CommandTimedOut {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} didn't exit within {:?}", self.command, self.after)
+    }
+}
+
+impl std::error::Error for CommandTimedOut {}
+
+/// Highest descriptor checked when `close_range(2)` isn't available. Scanning up to a limit of a
+/// million descriptors would make every spawn noticeably slower.
+const MAX_SCANNED_FD: c_int = 65536;
+
+/// How long `sysctl`, `glabel` and other tools of the system may run when they are asked outside
+/// of an engine. They answer right away unless the kernel is stuck.
+#[cfg(target_os = "freebsd")]
+pub(crate) const SYSTEM_COMMAND_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Pin locale of `cmd` to `C`. Output and error messages of spawned commands are parsed and only
+/// English ones are understood. The rest of the environment is passed through.
+pub(crate) fn pin_locale(cmd: &mut Command) -> &mut Command {
+    cmd.env("LC_ALL", "C")
+        .env_remove("LANG")
+        .env_remove("LC_MESSAGES")
+}
+
+/// Command for `program` isolated from this process: stdin is `/dev/null` until the caller sets
+/// it, descriptors other than stdio are closed on exec and the child leads a new process group.
+/// It runs in `C` locale, see [`pin_locale`](fn.pin_locale.html).
+pub(crate) fn isolated_command<S: AsRef<OsStr>>(program: S) -> Command {
+    let mut cmd = Command::new(program);
+    pin_locale(&mut cmd);
+    cmd.stdin(Stdio::null());
+    cmd.process_group(0);
+    let open_max = match unsafe { libc::sysconf(libc::_SC_OPEN_MAX) } {
+        max if max > 0 => max.min(MAX_SCANNED_FD.into()) as c_int,
+        _ => 1024,
+    };
+    // Descriptors are marked close-on-exec instead of being closed right away: the pipe that
+    // reports failed `exec` back to the parent is one of them.
+    unsafe {
+        cmd.pre_exec(move || {
+            cloexec_from(3, open_max);
+            Ok(())
+        });
+    }
+    cmd
+}
+
+/// Mark every descriptor from `first` on as close-on-exec. Runs between `fork` and `exec`, so it
+/// must only make async-signal-safe calls.
+fn cloexec_from(first: c_int, open_max: c_int) {
+    #[cfg(target_os = "linux")]
+    {
+        // Linux 5.11 and newer do it in one call.
+        let ret = unsafe {
+            libc::syscall(
+                libc::SYS_close_range,
+                first as libc::c_uint,
+                libc::c_uint::MAX,
+                libc::CLOSE_RANGE_CLOEXEC,
+            )
+        };
+        if ret == 0 {
+            return;
+        }
+    }
+    for fd in first..open_max {
+        unsafe {
+            let flags = libc::fcntl(fd, libc::F_GETFD);
+            if flags >= 0 && flags & libc::FD_CLOEXEC == 0 {
+                libc::fcntl(fd, libc::F_SETFD, flags | libc::FD_CLOEXEC);
+            }
+        }
+    }
+}
+
+/// Run command to completion collecting output, then log and report its metrics.
+pub(crate) fn run_command(
+    logger: &Logger,
+    hook: Option<&Arc<InstrumentationHook>>,
+    cmd: &mut Command,
+) -> io::Result<Output> {
+    let started_at = Instant::now();
+    let out = cmd.output()?;
+    report(logger, hook, cmd, started_at, &out);
+    Ok(out)
+}
+
+/// Same as `run_command`, but kill command if it doesn't exit within `timeout`. Command runs in
+/// its own process group and the whole group is killed, along with processes command has spawned.
+/// Killed command is reported like any other failed command and `io::ErrorKind::TimedOut`
+/// carrying `CommandTimedOut` is returned. Killed command is always reaped, but its output is
+/// abandoned: processes that left the group might keep the pipes open for a long time.
+pub(crate) fn run_command_with_timeout(
+    logger: &Logger,
+    hook: Option<&Arc<InstrumentationHook>>,
+    cmd: &mut Command,
+    timeout: Duration,
+) -> io::Result<Output> {
+    let started_at = Instant::now();
+    let mut child = cmd
+        .process_group(0)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    // Pipes are drained on separate threads, so chatty command can't block on a full pipe.
+    let stdout = drain(child.stdout.take());
+    let stderr = drain(child.stderr.take());
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if started_at.elapsed() >= timeout {
+            // Child isn't reaped yet, so its pid still names its group. Child might have exited
+            // since `try_wait`, then only processes it has left behind are killed.
+            unsafe {
+                libc::killpg(child.id() as libc::pid_t, libc::SIGKILL);
+            }
+            let out = Output {
+                status: child.wait()?,
+                stdout: Vec::new(),
+                stderr: Vec::new(),
+            };
+            report(logger, hook, cmd, started_at, &out);
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                CommandTimedOut {
+                    command: command_line(cmd),
+                    after: timeout,
+                },
+            ));
+        }
+        thread::sleep(POLL_INTERVAL);
+    };
+    let out = Output {
+        status,
+        stdout: stdout.join().unwrap_or_default(),
+        stderr: stderr.join().unwrap_or_default(),
+    };
+    report(logger, hook, cmd, started_at, &out);
+    Ok(out)
+}
+
+/// Run command with `timeout` if there is one, otherwise same as `run_command`.
+pub(crate) fn run_command_within(
+    logger: &Logger,
+    hook: Option<&Arc<InstrumentationHook>>,
+    cmd: &mut Command,
+    timeout: Option<Duration>,
+) -> io::Result<Output> {
+    match timeout {
+        Some(timeout) => run_command_with_timeout(logger, hook, cmd, timeout),
+        None => run_command(logger, hook, cmd),
+    }
+}
+
+/// Same as `run_command`, but `on_line` is called with every line of stderr as soon as command
+/// writes it. Command's stdin and stdout are left as configured by the caller, only stderr is
+/// collected into returned `Output`. If stderr can't be read, command is killed, reaped and the
+/// read error is returned.
+pub(crate) fn run_command_with_stderr_lines<F: FnMut(&str)>(
+    logger: &Logger,
+    hook: Option<&Arc<InstrumentationHook>>,
+    cmd: &mut Command,
+    mut on_line: F,
+) -> io::Result<Output> {
+    let started_at = Instant::now();
+    let mut child = cmd.stderr(Stdio::piped()).spawn()?;
+    let mut stderr = Vec::new();
+    let read = match child.stderr.take() {
+        Some(pipe) => {
+            let mut reader = BufReader::new(pipe);
+            let mut line = Vec::new();
+            loop {
+                line.clear();
+                match reader.read_until(b'\n', &mut line) {
+                    Ok(0) => break Ok(()),
+                    Ok(_) => {
+                        on_line(String::from_utf8_lossy(&line).trim_end());
+                        stderr.extend_from_slice(&line);
+                    }
+                    Err(e) => break Err(e),
+                }
+            }
+        }
+        None => Ok(()),
+    };
+    if read.is_err() {
+        let _ = child.kill();
+    }
+    let out = Output {
+        status: child.wait()?,
+        stdout: Vec::new(),
+        stderr,
+    };
+    report(logger, hook, cmd, started_at, &out);
+    read.map(|()| out)
+}
+
+/// How often `run_command_with_timeout` checks whether command has exited.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Program and arguments separated by spaces.
+fn command_line(cmd: &Command) -> String {
+    std::iter::once(cmd.get_program())
+        .chain(cmd.get_args())
+        .map(|arg| arg.to_string_lossy())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn drain<R: Read + Send + 'static>(pipe: Option<R>) -> thread::JoinHandle<Vec<u8>> {
+    thread::spawn(move || {
+        let mut buf = Vec::new();
+        if let Some(mut pipe) = pipe {
+            let _ = pipe.read_to_end(&mut buf);
+        }
+        buf
+    })
+}
+
+fn report(
+    logger: &Logger,
+    hook: Option<&Arc<InstrumentationHook>>,
+    cmd: &Command,
+    started_at: Instant,
+    out: &Output,
+) {
+    let metrics = CommandMetrics {
+        command: cmd.get_program().to_string_lossy().into(),
+        subcommand: cmd
+            .get_args()
+            .next()
+            .map(|arg| arg.to_string_lossy().into()),
+        duration: started_at.elapsed(),
+        exit_code: out.status.code(),
+        success: out.status.success(),
+        stderr_len: out.stderr.len(),
+    };
+    let subcommand = metrics.subcommand.as_deref().unwrap_or("");
+    let duration_ms = metrics.duration.as_millis() as u64;
+    if metrics.success {
+        debug!(logger, "command finished"; "cmd" => &metrics.command, "subcommand" => subcommand,
+            "duration_ms" => duration_ms, "exit_code" => metrics.exit_code,
+            "stderr_len" => metrics.stderr_len);
+    } else {
+        info!(logger, "command failed"; "cmd" => &metrics.command, "subcommand" => subcommand,
+            "duration_ms" => duration_ms, "exit_code" => metrics.exit_code,
+            "stderr_len" => metrics.stderr_len);
+    }
+    if let Some(hook) = hook {
+        hook(metrics);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::utils::shim;
+    use std::fs;
+
+    /// Whether process is gone or is a zombie nobody has reaped yet.
+    fn is_dead(pid: &str) -> bool {
+        match fs::read_to_string(format!("/proc/{}/stat", pid)) {
+            Ok(stat) => stat
+                .rsplit(')')
+                .next()
+                .map_or(false, |rest| rest.trim_start().starts_with('Z')),
+            Err(_) => true,
+        }
+    }
+
+    #[test]
+    fn pin_locale_sets_c_and_drops_overrides() {
+        let mut cmd = Command::new("env");
+        cmd.env("LANG", "de_DE.UTF-8")
+            .env("LC_MESSAGES", "de_DE.UTF-8");
+        pin_locale(&mut cmd);
+        let out = cmd.output().unwrap();
+        assert!(out.status.success());
+        let env = String::from_utf8(out.stdout).unwrap();
+        let lines: Vec<&str> = env.lines().collect();
+        assert!(lines.contains(&"LC_ALL=C"));
+        assert!(!lines
+            .iter()
+            .any(|line| line.starts_with("LANG=") || line.starts_with("LC_MESSAGES=")));
+        // The rest of the environment is passed through.
+        assert!(lines.iter().any(|line| line.starts_with("PATH=")));
+    }
+
+    #[test]
+    fn timeout_kills_process_group() {
+        let dir = tempfile::tempdir().unwrap();
+        let pid_file = dir.path().join("pid");
+        let script = format!(
+            "echo $$ > {pids}.sh\nsleep 30 &\necho $! > {pids}\nwait\n",
+            pids = pid_file.display()
+        );
+        let zpool = shim(dir.path(), "zpool", &script);
+        let mut cmd = isolated_command(&zpool);
+        cmd.arg("sync");
+        let logger = Logger::root(slog::Discard, o!());
+
+        let started_at = Instant::now();
+        let err = run_command_with_timeout(&logger, None, &mut cmd, Duration::from_millis(300))
+            .unwrap_err();
+        assert!(started_at.elapsed() < Duration::from_secs(10));
+        assert_eq!(io::ErrorKind::TimedOut, err.kind());
+        assert_eq!(
+            Some(&CommandTimedOut {
+                command: format!("{} sync", zpool.display()),
+                after: Duration::from_millis(300),
+            }),
+            CommandTimedOut::from_io(&err)
+        );
+
+        // Command itself is reaped right away, zombie would still be listed.
+        let pid = fs::read_to_string(pid_file.with_extension("sh")).unwrap();
+        assert!(fs::metadata(format!("/proc/{}", pid.trim())).is_err());
+        let pid = fs::read_to_string(&pid_file).unwrap();
+        let pid = pid.trim();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !is_dead(pid) {
+            assert!(Instant::now() < deadline, "sleep {} outlived zpool", pid);
+            thread::sleep(POLL_INTERVAL);
+        }
+    }
+}
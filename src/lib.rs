@@ -59,13 +59,20 @@ pub use pest;
 
 pub extern crate libnv;
 
+#[cfg(all(feature = "nv-fake", not(debug_assertions)))]
+compile_error!("`nv-fake` replaces libnv with an in-memory fake and is only meant for tests");
+
 // library modules
 pub mod parsers;
 pub mod zfs;
 pub mod zpool;
 
+pub mod instrumentation;
 pub mod utils;
 
+#[cfg(feature = "serde")]
+mod serde_impls;
+
 #[cfg(fuzzing)]
 pub mod fuzzy;
 
@@ -17,7 +17,7 @@ mod test {
         parsers::*,
         zpool::{
             vdev::{CreateVdevRequest, ErrorStatistics},
-            CreateZpoolRequestBuilder, Health, Reason, Zpool,
+            CreateZpoolRequestBuilder, Health, Reason, VdevType, Zpool,
         },
     };
 
@@ -158,8 +158,7 @@ mod test {
 
         assert_eq!(
             &Some(String::from(
-                "The pool cannot be imported. Attach the missing
-        devices and try again.\n"
+                "The pool cannot be imported. Attach the missing devices and try again."
             )),
             zpool.action()
         );
@@ -492,7 +491,7 @@ errors: No known data errors
 
     #[test]
     fn test_zpool_inuse_spares() {
-        let stdout = r#"  pool: sparepool
+        let stdout = r#"  pool: hotspares
  state: DEGRADED
 status: One or more devices are faulted in response to persistent errors.
 	Sufficient replicas exist for the pool to continue functioning in a
@@ -503,7 +502,7 @@ action: Replace the faulted device, or use 'zpool clear' to mark the device
 config:
 
 	NAME                          STATE     READ WRITE CKSUM
-	sparepool                     DEGRADED     0     0     0
+	hotspares                     DEGRADED     0     0     0
 	  raidz1-0                    DEGRADED     0     0     0
 	    spare-0                   DEGRADED     0     0     0
 	      wwn-0x500000000000000a  FAULTED      0 4.11K     0  too many errors
@@ -522,7 +521,6 @@ errors: No known data errors
         let zpool = Zpool::from_pest_pair(pair);
 
         let drives = vec![
-            PathBuf::from("spare-0"),
             PathBuf::from("wwn-0x500000000000000a"),
             PathBuf::from("wwn-0x500000000000000d"),
             PathBuf::from("wwn-0x500000000000000b"),
@@ -531,11 +529,16 @@ errors: No known data errors
         let spare = vec![PathBuf::from("wwn-0x500000000000000d")];
 
         let topo = CreateZpoolRequestBuilder::default()
-            .name("sparepool")
+            .name("hotspares")
             .vdevs(vec![CreateVdevRequest::RaidZ(drives.clone())])
             .spares(spare)
             .build()
             .unwrap();
         assert_eq!(&topo, &zpool);
+
+        let spare = &zpool.vdevs()[0].groups()[0];
+        assert_eq!(&VdevType::Spare, spare.kind());
+        assert_eq!(&Health::Degraded, spare.health());
+        assert_eq!(&drives[..2], &spare.disks()[..]);
     }
 }
@@ -0,0 +1,205 @@
+//! Optional [serde](https://serde.rs) support. Enabled by `serde` feature.
+//!
+//! Structures derive `Serialize` and `Deserialize`, while enums are represented by the same strings
+//! `zfs(8)` and `zpool(8)` use (e.g. `Health::Online` is `"ONLINE"` and `Compression::LZ4` is
+//! `"lz4"`) instead of Rust variant names, so the format doesn't change when variants are
+//! renamed.
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::{
+    zfs::properties::{
+        AclInheritMode, AclMode, CacheMode, CanMount, CaseSensitivity, Checksum, Compression,
+        Copies, Dedup, DnodeSize, LogBias, Normalization, RedundantMetadata, ShareOptions, SnapDev,
+        SnapDir, SyncMode, VolumeMode,
+    },
+    zpool::{
+        CacheType, FailMode, FeatureState, FeatureToggle, Health, PoolPropertySource, Reason,
+        ResilverStatus, VdevType,
+    },
+};
+
+/// Implement `Serialize` and `Deserialize` via `Display` and `FromStr`.
+macro_rules! impl_serde_via_str {
+    ($($type_:ty),*) => {
+        $(
+            impl Serialize for $type_ {
+                fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                    serializer.collect_str(self)
+                }
+            }
+
+            impl<'de> Deserialize<'de> for $type_ {
+                fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                    let value = String::deserialize(deserializer)?;
+                    value.parse().map_err(de::Error::custom)
+                }
+            }
+        )*
+    };
+}
+
+/// Implement `Serialize` and `Deserialize` via `as_str` and `try_from_str`.
+macro_rules! impl_serde_via_as_str {
+    ($($type_:ty),*) => {
+        $(
+            impl Serialize for $type_ {
+                fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                    serializer.serialize_str(self.as_str())
+                }
+            }
+
+            impl<'de> Deserialize<'de> for $type_ {
+                fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                    let value = String::deserialize(deserializer)?;
+                    <$type_>::try_from_str(Some(&value)).map_err(|_| {
+                        de::Error::invalid_value(de::Unexpected::Str(&value), &stringify!($type_))
+                    })
+                }
+            }
+        )*
+    };
+}
+
+impl_serde_via_str!(
+    AclInheritMode,
+    AclMode,
+    CacheMode,
+    CanMount,
+    CaseSensitivity,
+    Checksum,
+    Compression,
+    Copies,
+    Dedup,
+    DnodeSize,
+    LogBias,
+    Normalization,
+    RedundantMetadata,
+    ShareOptions,
+    SnapDev,
+    SnapDir,
+    SyncMode,
+    VolumeMode,
+    ResilverStatus,
+    VdevType,
+    PoolPropertySource,
+    FeatureState,
+    FeatureToggle
+);
+
+impl_serde_via_as_str!(CacheType, FailMode, Health);
+
+impl Serialize for Reason {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for Reason {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer).map(|reason| Reason::from(reason.as_str()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::PathBuf;
+
+    use crate::zfs::properties::{Compression, Copies, Dedup};
+    use crate::zpool::{vdev::ErrorStatistics, Disk, Health, Reason, Vdev, VdevType, Zpool};
+
+    fn disk(path: &str, health: Health) -> Disk {
+        Disk::builder().path(path).health(health).build().unwrap()
+    }
+
+    fn representative_zpool() -> Zpool {
+        let mirror = Vdev::builder()
+            .kind(VdevType::Mirror)
+            .health(Health::Degraded)
+            .disks(vec![
+                disk("/dev/ada0p3", Health::Online),
+                Disk::builder()
+                    .path("/dev/ada1p3")
+                    .health(Health::Faulted)
+                    .reason(Some(Reason::Other(String::from("too many errors"))))
+                    .error_statistics(ErrorStatistics {
+                        read: 3,
+                        write: 0,
+                        checksum: 12,
+                    })
+                    .build()
+                    .unwrap(),
+            ])
+            .build()
+            .unwrap();
+        let log = Vdev::builder()
+            .kind(VdevType::SingleDisk)
+            .health(Health::Online)
+            .disks(vec![disk("/dev/nvd0p1", Health::Online)])
+            .build()
+            .unwrap();
+        Zpool::builder()
+            .name("tank")
+            .id(Some(5_333_885_354_421_686_613))
+            .health(Health::Degraded)
+            .vdevs(vec![mirror])
+            .logs(vec![log])
+            .caches(vec![disk("/dev/nvd0p2", Health::Online)])
+            .spares(vec![disk("/dev/ada2p3", Health::Available)])
+            .errors(Some(String::from("No known data errors")))
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn zpool_json_snapshot() {
+        let zpool = representative_zpool();
+        let expected: serde_json::Value =
+            serde_json::from_str(include_str!("zpool/fixtures/zpool.json")).unwrap();
+
+        assert_eq!(expected, serde_json::to_value(&zpool).unwrap());
+
+        let round_trip: Zpool = serde_json::from_value(expected).unwrap();
+        assert_eq!(zpool, round_trip);
+        assert_eq!(
+            zpool.vdevs()[0].disks()[1].health(),
+            round_trip.vdevs()[0].disks()[1].health()
+        );
+    }
+
+    #[test]
+    fn enums_use_zfs_names() {
+        assert_eq!(
+            "\"ONLINE\"",
+            serde_json::to_string(&Health::Online).unwrap()
+        );
+        assert_eq!(
+            "\"AVAIL\"",
+            serde_json::to_string(&Health::Available).unwrap()
+        );
+        assert_eq!("\"lz4\"", serde_json::to_string(&Compression::LZ4).unwrap());
+        assert_eq!(
+            "\"gzip-9\"",
+            serde_json::to_string(&Compression::Gzip9).unwrap()
+        );
+        assert_eq!(
+            "\"sha256,verify\"",
+            serde_json::to_string(&Dedup::VerifySHA256).unwrap()
+        );
+        assert_eq!("\"2\"", serde_json::to_string(&Copies::Two).unwrap());
+        assert_eq!(
+            "\"raidz2\"",
+            serde_json::to_string(&VdevType::RaidZ2).unwrap()
+        );
+        assert_eq!(
+            "\"disk\"",
+            serde_json::to_string(&VdevType::SingleDisk).unwrap()
+        );
+
+        let compression: Compression = serde_json::from_str("\"zle\"").unwrap();
+        assert_eq!(Compression::ZLE, compression);
+        let health: Result<Health, _> = serde_json::from_str("\"Online\"");
+        assert!(health.is_err());
+        let path: PathBuf = serde_json::from_str("\"/dev/ada0\"").unwrap();
+        assert_eq!(PathBuf::from("/dev/ada0"), path);
+    }
+}
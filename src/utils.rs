@@ -1,3 +1,13 @@
+use std::{
+    fmt,
+    panic::{self, AssertUnwindSafe},
+    sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError},
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use slog::Logger;
+
 /// Very pricey way of parsing strings. Used because some ratios have `x` character, and some don't.
 #[inline(always)]
 pub fn parse_float(input: &mut String) -> Result<f64, std::num::ParseFloatError> {
@@ -10,3 +20,161 @@ pub fn parse_float(input: &mut String) -> Result<f64, std::num::ParseFloatError>
     }
     input.parse()
 }
+
+/// Call `f` on every item using at most `max_concurrency` threads at a time. Results are returned
+/// in the same order as `items`. Threads are scoped, so `f` may borrow from the caller. Zero
+/// `max_concurrency` is treated as one.
+pub fn bounded_parallel<T, R, F>(items: Vec<T>, max_concurrency: usize, f: F) -> Vec<R>
+where
+    T: Send,
+    R: Send,
+    F: Fn(T) -> R + Sync,
+{
+    let len = items.len();
+    let workers = max_concurrency.max(1).min(len);
+    if workers <= 1 {
+        return items.into_iter().map(f).collect();
+    }
+    let queue = Mutex::new(items.into_iter().enumerate());
+    let results = Mutex::new((0..len).map(|_| None).collect::<Vec<Option<R>>>());
+    std::thread::scope(|scope| {
+        for _ in 0..workers {
+            scope.spawn(|| loop {
+                let next = queue.lock().expect("poisoned queue").next();
+                let (idx, item) = match next {
+                    Some(next) => next,
+                    None => break,
+                };
+                let result = f(item);
+                results.lock().expect("poisoned results")[idx] = Some(result);
+            });
+        }
+    });
+    results
+        .into_inner()
+        .expect("poisoned results")
+        .into_iter()
+        .map(|result| result.expect("every item is processed"))
+        .collect()
+}
+
+/// Lock ignoring poisoning: a panic in a callback doesn't make the guarded state wrong.
+pub(crate) fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+type Callback<E> = Box<dyn Fn(&E) + Send>;
+
+/// State shared with the polling thread.
+struct PollerShared<E> {
+    callbacks: Mutex<Vec<Callback<E>>>,
+    stop: Mutex<bool>,
+    wake: Condvar,
+}
+
+/// Calls `poll` in a background thread every `interval` and passes events it returns to
+/// callbacks. The thread is stopped and joined when the poller is dropped.
+pub(crate) struct Poller<E> {
+    shared: Arc<PollerShared<E>>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl<E: fmt::Debug + 'static> Poller<E> {
+    /// Start polling right away. If `poll` panics, polling stops, see
+    /// [`is_running`](#method.is_running).
+    pub(crate) fn start<F>(mut poll: F, interval: Duration, logger: Logger) -> Poller<E>
+    where
+        F: FnMut() -> Vec<E> + Send + 'static,
+    {
+        let shared = Arc::new(PollerShared {
+            callbacks: Mutex::new(Vec::new()),
+            stop: Mutex::new(false),
+            wake: Condvar::new(),
+        });
+        let thread_shared = Arc::clone(&shared);
+        let handle = thread::spawn(move || loop {
+            let events = poll();
+            if !events.is_empty() {
+                deliver(&thread_shared, &events, &logger);
+            }
+            let stop = lock(&thread_shared.stop);
+            let (stop, _) = thread_shared
+                .wake
+                .wait_timeout_while(stop, interval, |stop| !*stop)
+                .unwrap_or_else(PoisonError::into_inner);
+            if *stop {
+                break;
+            }
+        });
+        Poller {
+            shared,
+            handle: Some(handle),
+        }
+    }
+
+    /// Call `callback` with every event from now on. Callbacks run on the polling thread, one that
+    /// panics is logged and polling goes on.
+    pub(crate) fn on_event<F>(&self, callback: F)
+    where
+        F: Fn(&E) + Send + 'static,
+    {
+        lock(&self.shared.callbacks).push(Box::new(callback));
+    }
+
+    /// Whether the polling thread is still alive. It only stops on its own if `poll` panicked.
+    pub(crate) fn is_running(&self) -> bool {
+        self.handle
+            .as_ref()
+            .is_some_and(|handle| !handle.is_finished())
+    }
+}
+
+fn deliver<E: fmt::Debug>(shared: &PollerShared<E>, events: &[E], logger: &Logger) {
+    let callbacks = lock(&shared.callbacks);
+    for event in events {
+        for callback in callbacks.iter() {
+            if panic::catch_unwind(AssertUnwindSafe(|| callback(event))).is_err() {
+                warn!(logger, "callback panicked"; "event" => ?event);
+            }
+        }
+    }
+}
+
+impl<E> Drop for Poller<E> {
+    fn drop(&mut self) {
+        *lock(&self.shared.stop) = true;
+        self.shared.wake.notify_all();
+        if let Some(handle) = self.handle.take() {
+            // A panic of `poll` already ended polling, nothing else to do about it.
+            let _ = handle.join();
+        }
+        // Callbacks may own senders of channels, drop them so receivers see the end.
+        lock(&self.shared.callbacks).clear();
+    }
+}
+
+/// Write executable `name` into `dir` that runs `script` with `/bin/sh`. Tests point open3
+/// engines at it instead of the real `zpool`, `zfs` or `zdb`.
+#[cfg(test)]
+pub(crate) fn shim(dir: &std::path::Path, name: &str, script: &str) -> std::path::PathBuf {
+    use std::{fs, os::unix::fs::PermissionsExt};
+
+    let path = dir.join(name);
+    fs::write(&path, format!("#!/bin/sh\n{}", script)).unwrap();
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+    path
+}
+
+#[cfg(test)]
+mod test {
+    use super::bounded_parallel;
+
+    #[test]
+    fn bounded_parallel_keeps_order() {
+        let items: Vec<usize> = (0..32).collect();
+        let expected: Vec<usize> = items.iter().map(|i| i * 2).collect();
+        assert_eq!(expected, bounded_parallel(items.clone(), 4, |i| i * 2));
+        assert_eq!(expected, bounded_parallel(items, 0, |i| i * 2));
+        assert!(bounded_parallel(Vec::<usize>::new(), 4, |i| i).is_empty());
+    }
+}
@@ -0,0 +1,319 @@
+//! Boot environments: a tree of file systems cloned from a recursive snapshot.
+//!
+//! [`clone_tree`](fn.clone_tree.html) clones a file system and its descendants from a snapshot
+//! that was taken recursively, keeping their relative layout under a new name.
+//! [`activate`](fn.activate.html) promotes the clones, so the original tree becomes dependent on
+//! them and can be destroyed. Both only use public [`ZfsEngine`](../trait.ZfsEngine.html)
+//! methods.
+//!
+//! Clones get `canmount=noauto`, so they don't get mounted over the running system. Mount points
+//! set locally on the original file systems are copied, inherited ones are inherited from the new
+//! tree. Volumes aren't cloned.
+//!
+//! ### Usage
+//! ```rust,no_run
+//! use libzetta::zfs::{be, DelegatingZfsEngine};
+//!
+//! let engine = DelegatingZfsEngine::new().unwrap();
+//! be::clone_tree(&engine, "zroot/ROOT/default@upgrade", "zroot/ROOT/upgrade").unwrap();
+//! // ...update files in the new boot environment...
+//! be::activate(&engine, "zroot/ROOT/upgrade").unwrap();
+//! ```
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+};
+
+use crate::zfs::{Error, PathExt, Properties, PropertySource, Result, ValidationError, ZfsEngine};
+
+/// Clone `source_snapshot` and the same snapshot of every descendant file system to `target`.
+/// Descendants without that snapshot are skipped along with their own descendants. Returns
+/// created clones, parents first.
+///
+/// If one of the clones can't be created, clones created before it are destroyed (best effort)
+/// and [`Error::CloneTreeFailed`](../enum.Error.html) says which of them are left behind.
+///
+///  * `source_snapshot` - Snapshot of the root of the tree, e.g. `zroot/ROOT/default@upgrade`.
+///  * `target` - Name of the clone of the root, e.g. `zroot/ROOT/upgrade`.
+pub fn clone_tree<E, N, T>(engine: &E, source_snapshot: N, target: T) -> Result<Vec<PathBuf>>
+where
+    E: ZfsEngine,
+    N: Into<PathBuf>,
+    T: Into<PathBuf>,
+{
+    let source_snapshot = source_snapshot.into();
+    let target = target.into();
+    let plan = plan_clones(engine, &source_snapshot, &target)?;
+    let mut created: Vec<PathBuf> = Vec::with_capacity(plan.len());
+    for (snapshot, clone) in plan {
+        if let Err(cause) = clone_one(engine, &snapshot, &clone) {
+            // Children go first, a file system with children can't be destroyed.
+            let left_behind = created
+                .into_iter()
+                .rev()
+                .filter(|created| engine.destroy(created.clone()).is_err())
+                .collect();
+            return Err(Error::CloneTreeFailed {
+                dataset: clone,
+                left_behind,
+                cause: Box::new(cause),
+            });
+        }
+        created.push(clone);
+    }
+    Ok(created)
+}
+
+/// Promote every clone in the tree rooted at `clone_root`, parents first. File systems that
+/// aren't clones are left alone, so it's safe to call again after a failure.
+pub fn activate<E: ZfsEngine, N: Into<PathBuf>>(engine: &E, clone_root: N) -> Result<()> {
+    let mut datasets = engine.list_filesystems(clone_root)?;
+    datasets.sort();
+    for dataset in datasets {
+        let origin = match engine.read_properties(dataset.clone())? {
+            Properties::Filesystem(props) => props.origin().clone(),
+            _ => None,
+        };
+        if origin.is_some_and(|origin| origin != "-") {
+            engine.promote(dataset)?;
+        }
+    }
+    Ok(())
+}
+
+/// Pairs of snapshot and name of its clone, parents first.
+fn plan_clones<E: ZfsEngine>(
+    engine: &E,
+    source_snapshot: &Path,
+    target: &Path,
+) -> Result<Vec<(PathBuf, PathBuf)>> {
+    let snap_name = match source_snapshot.snapshot_name() {
+        Some(snap_name) => snap_name,
+        None => {
+            return Err(ValidationError::MissingSnapshotName(source_snapshot.to_path_buf()).into())
+        }
+    };
+    let source = source_snapshot.dataset_of();
+    let snapshots: HashSet<PathBuf> = engine.list_snapshots(source.clone())?.into_iter().collect();
+    if !snapshots.contains(source_snapshot) {
+        return Err(Error::DatasetNotFound(source_snapshot.to_path_buf()));
+    }
+    let mut datasets = engine.list_filesystems(source.clone())?;
+    datasets.sort();
+
+    let mut cloned: HashSet<PathBuf> = HashSet::with_capacity(datasets.len());
+    let mut ret = Vec::with_capacity(datasets.len());
+    for dataset in datasets {
+        let snapshot = dataset.with_snapshot(snap_name)?;
+        let parent_cloned = dataset == source
+            || dataset
+                .parent()
+                .is_some_and(|parent| cloned.contains(parent));
+        if !parent_cloned || !snapshots.contains(&snapshot) {
+            continue;
+        }
+        let relative = dataset.strip_prefix(&source).map_err(|_| {
+            Error::UnknownSoFar(format!(
+                "{} listed outside of {}",
+                dataset.display(),
+                source.display()
+            ))
+        })?;
+        let clone = if relative.as_os_str().is_empty() {
+            target.to_path_buf()
+        } else {
+            target.join(relative)
+        };
+        cloned.insert(dataset);
+        ret.push((snapshot, clone));
+    }
+    Ok(ret)
+}
+
+fn clone_one<E: ZfsEngine>(engine: &E, snapshot: &Path, clone: &Path) -> Result<()> {
+    let (props, sources) = engine.read_properties_with_sources(snapshot.dataset_of())?;
+    let mut properties = HashMap::new();
+    properties.insert(String::from("canmount"), String::from("noauto"));
+    let mount_point_is_set = matches!(
+        sources.get("mountpoint"),
+        Some(PropertySource::Local) | Some(PropertySource::Received)
+    );
+    if let (true, Properties::Filesystem(props)) = (mount_point_is_set, props) {
+        let mount_point = props.mount_point().as_ref().map_or_else(
+            || String::from("none"),
+            |p| p.to_string_lossy().into_owned(),
+        );
+        properties.insert(String::from("mountpoint"), mount_point);
+    }
+    engine.clone_snapshot(snapshot, clone, Some(properties))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::zfs::{ErrorKind, FilesystemProperties};
+    use std::cell::RefCell;
+
+    /// Engine that knows a fixed tree and records what is done to it.
+    #[derive(Default)]
+    struct Tree {
+        filesystems: Vec<&'static str>,
+        snapshots: Vec<&'static str>,
+        local_mount_points: Vec<(&'static str, &'static str)>,
+        fail_clone: Option<&'static str>,
+        fail_destroy: Option<&'static str>,
+        clones: RefCell<Vec<(PathBuf, PathBuf, HashMap<String, String>)>>,
+        destroyed: RefCell<Vec<PathBuf>>,
+    }
+
+    impl ZfsEngine for Tree {
+        fn list_filesystems<N: Into<PathBuf>>(&self, _pool: N) -> Result<Vec<PathBuf>> {
+            Ok(self.filesystems.iter().map(PathBuf::from).collect())
+        }
+
+        fn list_snapshots<N: Into<PathBuf>>(&self, _pool: N) -> Result<Vec<PathBuf>> {
+            Ok(self.snapshots.iter().map(PathBuf::from).collect())
+        }
+
+        fn read_properties_with_sources<N: Into<PathBuf>>(
+            &self,
+            path: N,
+        ) -> Result<(Properties, HashMap<String, PropertySource>)> {
+            let path = path.into();
+            let mut builder = FilesystemProperties::builder(path.clone());
+            let mut sources = HashMap::new();
+            match self
+                .local_mount_points
+                .iter()
+                .find(|(name, _)| path == Path::new(name))
+            {
+                Some((_, mount_point)) => {
+                    builder.mount_point(Some(PathBuf::from(mount_point)));
+                    sources.insert(String::from("mountpoint"), PropertySource::Local);
+                }
+                None => {
+                    sources.insert(
+                        String::from("mountpoint"),
+                        PropertySource::Inherited(PathBuf::from("zroot")),
+                    );
+                }
+            }
+            let props = builder.build().unwrap();
+            Ok((Properties::Filesystem(props), sources))
+        }
+
+        fn clone_snapshot<N: Into<PathBuf>, T: Into<PathBuf>>(
+            &self,
+            snapshot: N,
+            target: T,
+            properties: Option<HashMap<String, String>>,
+        ) -> Result<()> {
+            let target = target.into();
+            if self
+                .fail_clone
+                .map_or(false, |fail| target == Path::new(fail))
+            {
+                return Err(Error::Unknown);
+            }
+            self.clones.borrow_mut().push((
+                snapshot.into(),
+                target,
+                properties.unwrap_or_default(),
+            ));
+            Ok(())
+        }
+
+        fn destroy<N: Into<PathBuf>>(&self, name: N) -> Result<()> {
+            let name = name.into();
+            if self
+                .fail_destroy
+                .map_or(false, |fail| name == Path::new(fail))
+            {
+                return Err(Error::Unknown);
+            }
+            self.destroyed.borrow_mut().push(name);
+            Ok(())
+        }
+    }
+
+    fn tree() -> Tree {
+        Tree {
+            filesystems: vec![
+                "zroot/ROOT/default",
+                "zroot/ROOT/default/var",
+                "zroot/ROOT/default/var/log",
+                "zroot/ROOT/default/usr",
+                "zroot/ROOT/default/tmp",
+                "zroot/ROOT/default/tmp/cache",
+            ],
+            snapshots: vec![
+                "zroot/ROOT/default@up",
+                "zroot/ROOT/default/var@up",
+                "zroot/ROOT/default/var/log@up",
+                "zroot/ROOT/default/usr@up",
+                // `tmp` wasn't snapshotted, so `tmp/cache` is skipped as well.
+                "zroot/ROOT/default/tmp/cache@up",
+            ],
+            local_mount_points: vec![("zroot/ROOT/default", "/")],
+            ..Tree::default()
+        }
+    }
+
+    #[test]
+    fn clones_keep_layout() {
+        let tree = tree();
+        let created = clone_tree(&tree, "zroot/ROOT/default@up", "zroot/ROOT/new").unwrap();
+        let expected: Vec<PathBuf> = vec![
+            "zroot/ROOT/new",
+            "zroot/ROOT/new/usr",
+            "zroot/ROOT/new/var",
+            "zroot/ROOT/new/var/log",
+        ]
+        .into_iter()
+        .map(PathBuf::from)
+        .collect();
+        assert_eq!(expected, created);
+
+        let clones = tree.clones.borrow();
+        assert_eq!(PathBuf::from("zroot/ROOT/default@up"), clones[0].0);
+        assert_eq!(Some(&String::from("/")), clones[0].2.get("mountpoint"));
+        assert_eq!(PathBuf::from("zroot/ROOT/default/var/log@up"), clones[3].0);
+        assert_eq!(None, clones[3].2.get("mountpoint"));
+        assert!(clones
+            .iter()
+            .all(|(_, _, props)| props.get("canmount") == Some(&String::from("noauto"))));
+    }
+
+    #[test]
+    fn failed_clone_is_rolled_back() {
+        let mut tree = tree();
+        tree.fail_clone = Some("zroot/ROOT/new/var/log");
+        tree.fail_destroy = Some("zroot/ROOT/new/usr");
+        let err = clone_tree(&tree, "zroot/ROOT/default@up", "zroot/ROOT/new").unwrap_err();
+        assert_eq!(
+            Error::CloneTreeFailed {
+                dataset: PathBuf::from("zroot/ROOT/new/var/log"),
+                left_behind: vec![PathBuf::from("zroot/ROOT/new/usr")],
+                cause: Box::new(Error::Unknown),
+            },
+            err
+        );
+        assert_eq!(
+            vec![
+                PathBuf::from("zroot/ROOT/new/var"),
+                PathBuf::from("zroot/ROOT/new"),
+            ],
+            *tree.destroyed.borrow()
+        );
+    }
+
+    #[test]
+    fn missing_snapshot() {
+        let tree = tree();
+        let err = clone_tree(&tree, "zroot/ROOT/default@nope", "zroot/ROOT/new").unwrap_err();
+        assert_eq!(ErrorKind::DatasetNotFound, err.kind());
+        let err = clone_tree(&tree, "zroot/ROOT/default", "zroot/ROOT/new").unwrap_err();
+        assert_eq!(ErrorKind::ValidationErrors, err.kind());
+        assert!(tree.clones.borrow().is_empty());
+    }
+}
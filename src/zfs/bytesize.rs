@@ -0,0 +1,201 @@
+use crate::zfs::Error;
+use std::{fmt, str::FromStr};
+
+const UNITS: [char; 7] = ['B', 'K', 'M', 'G', 'T', 'P', 'E'];
+
+/// Amount of bytes. Used for sizes, quotas, reservations and block sizes so that it's clear what
+/// unit a number is in.
+///
+/// Can be parsed from both exact (`zfs get -p`) and human-readable values ZFS prints, like `128K`
+/// or `1.50T`. Units are powers of 1024.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ByteSize(u64);
+
+impl ByteSize {
+    /// Smallest block size ZFS allows.
+    pub const MIN_BLOCK_SIZE: ByteSize = ByteSize(512);
+    /// Largest record size ZFS allows without raising `zfs_max_recordsize`.
+    pub const MAX_BLOCK_SIZE: ByteSize = ByteSize(1024 * 1024);
+
+    /// Size of `bytes` bytes.
+    pub const fn bytes(bytes: u64) -> ByteSize {
+        ByteSize(bytes)
+    }
+
+    /// Size of `kib` kibibytes (1024 bytes).
+    pub const fn kib(kib: u64) -> ByteSize {
+        ByteSize(kib * 1024)
+    }
+
+    /// Size of `mib` mebibytes (1024 KiB).
+    pub const fn mib(mib: u64) -> ByteSize {
+        ByteSize(mib * 1024 * 1024)
+    }
+
+    /// Size of `gib` gibibytes (1024 MiB).
+    pub const fn gib(gib: u64) -> ByteSize {
+        ByteSize(gib * 1024 * 1024 * 1024)
+    }
+
+    /// Size of `tib` tebibytes (1024 GiB).
+    pub const fn tib(tib: u64) -> ByteSize {
+        ByteSize(tib * 1024 * 1024 * 1024 * 1024)
+    }
+
+    /// Size in bytes.
+    pub const fn as_u64(self) -> u64 {
+        self.0
+    }
+
+    /// Whether this can be used as `recordsize` or `volblocksize`: a power of two between
+    /// [`MIN_BLOCK_SIZE`](#associatedconstant.MIN_BLOCK_SIZE) and
+    /// [`MAX_BLOCK_SIZE`](#associatedconstant.MAX_BLOCK_SIZE).
+    pub fn is_valid_block_size(self) -> bool {
+        self.0.is_power_of_two() && self >= Self::MIN_BLOCK_SIZE && self <= Self::MAX_BLOCK_SIZE
+    }
+
+    /// Parse value that can be unset, like `quota`. `none`, `-` and empty string are `None`.
+    pub fn parse_optional(value: &str) -> Result<Option<ByteSize>, Error> {
+        match value.trim() {
+            "none" | "-" | "" => Ok(None),
+            value => value.parse().map(Some),
+        }
+    }
+}
+
+impl From<u64> for ByteSize {
+    fn from(bytes: u64) -> ByteSize {
+        ByteSize(bytes)
+    }
+}
+
+impl From<ByteSize> for u64 {
+    fn from(size: ByteSize) -> u64 {
+        size.0
+    }
+}
+
+impl FromStr for ByteSize {
+    type Err = Error;
+
+    fn from_str(source: &str) -> Result<ByteSize, Error> {
+        let err = || Error::UnknownSoFar(format!("Invalid size: {}", source));
+        let value = source.trim();
+        // Both `128K`, `128KB` and `128KiB` are fine.
+        let value = value
+            .strip_suffix("iB")
+            .or_else(|| {
+                value
+                    .strip_suffix('B')
+                    .filter(|v| v.ends_with(|c: char| c.is_ascii_alphabetic()))
+            })
+            .unwrap_or(value);
+        let (number, exponent) = match value.chars().last() {
+            Some(c) if c.is_ascii_alphabetic() => {
+                let unit = c.to_ascii_uppercase();
+                let exponent = UNITS.iter().position(|u| *u == unit).ok_or_else(err)?;
+                (&value[..value.len() - 1], exponent as u32)
+            }
+            Some(_) => (value, 0),
+            None => return Err(err()),
+        };
+        let multiplier = 1024u64.checked_pow(exponent).ok_or_else(err)?;
+        if let Ok(number) = number.parse::<u64>() {
+            number.checked_mul(multiplier).map(ByteSize).ok_or_else(err)
+        } else {
+            let number: f64 = number.parse().map_err(|_| err())?;
+            let bytes = (number * multiplier as f64).round();
+            if number.is_sign_negative() || !bytes.is_finite() || bytes > u64::MAX as f64 {
+                return Err(err());
+            }
+            Ok(ByteSize(bytes as u64))
+        }
+    }
+}
+
+impl fmt::Display for ByteSize {
+    /// Formats using the largest unit that represents size exactly, e.g. `128K`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut value = self.0;
+        let mut unit = 0;
+        while value != 0 && value.is_multiple_of(1024) && unit < UNITS.len() - 1 {
+            value /= 1024;
+            unit += 1;
+        }
+        if unit == 0 {
+            write!(f, "{}", value)
+        } else {
+            write!(f, "{}{}", value, UNITS[unit])
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::ByteSize;
+
+    #[test]
+    fn constructors() {
+        assert_eq!(ByteSize::bytes(131072), ByteSize::kib(128));
+        assert_eq!(ByteSize::kib(1024), ByteSize::mib(1));
+        assert_eq!(ByteSize::mib(1024), ByteSize::gib(1));
+        assert_eq!(ByteSize::gib(1024), ByteSize::tib(1));
+        assert_eq!(1024, u64::from(ByteSize::kib(1)));
+        assert_eq!(ByteSize::kib(1), ByteSize::from(1024));
+    }
+
+    #[test]
+    fn parsing() {
+        let cases = [
+            ("0", 0),
+            ("512", 512),
+            ("512B", 512),
+            ("128K", 131072),
+            ("128k", 131072),
+            ("128KB", 131072),
+            ("128KiB", 131072),
+            ("1M", 1024 * 1024),
+            ("1.50G", 1610612736),
+            ("1.5T", 1649267441664),
+        ];
+        for (input, expected) in cases.iter() {
+            assert_eq!(Ok(ByteSize::bytes(*expected)), input.parse(), "{}", input);
+        }
+        assert!("16E".parse::<ByteSize>().is_err());
+        assert!("1Q".parse::<ByteSize>().is_err());
+        assert!("-1K".parse::<ByteSize>().is_err());
+        assert!("K".parse::<ByteSize>().is_err());
+        assert!("".parse::<ByteSize>().is_err());
+        assert!("none".parse::<ByteSize>().is_err());
+    }
+
+    #[test]
+    fn parsing_optional() {
+        assert_eq!(Ok(None), ByteSize::parse_optional("none"));
+        assert_eq!(Ok(None), ByteSize::parse_optional("-"));
+        assert_eq!(Ok(Some(ByteSize::gib(10))), ByteSize::parse_optional("10G"));
+        assert!(ByteSize::parse_optional("lots").is_err());
+    }
+
+    #[test]
+    fn display() {
+        assert_eq!("0", ByteSize::bytes(0).to_string());
+        assert_eq!("1000", ByteSize::bytes(1000).to_string());
+        assert_eq!("128K", ByteSize::kib(128).to_string());
+        assert_eq!("1536K", ByteSize::kib(1536).to_string());
+        assert_eq!("1T", ByteSize::tib(1).to_string());
+        let parsed: ByteSize = ByteSize::gib(3).to_string().parse().unwrap();
+        assert_eq!(ByteSize::gib(3), parsed);
+    }
+
+    #[test]
+    fn block_sizes() {
+        assert!(ByteSize::bytes(512).is_valid_block_size());
+        assert!(ByteSize::kib(128).is_valid_block_size());
+        assert!(ByteSize::mib(1).is_valid_block_size());
+        assert!(!ByteSize::bytes(256).is_valid_block_size());
+        assert!(!ByteSize::bytes(0).is_valid_block_size());
+        assert!(!ByteSize::kib(100).is_valid_block_size());
+        assert!(!ByteSize::mib(2).is_valid_block_size());
+    }
+}
@@ -0,0 +1,864 @@
+//! Caching wrapper around any [`ZfsEngine`](../trait.ZfsEngine.html).
+//!
+//! Every call to `ZfsOpen3` is a fork/exec of `zfs`, which adds up quickly when the same datasets
+//! are looked at over and over. [`CachedZfsEngine`](struct.CachedZfsEngine.html) remembers
+//! results of `exists`, `kind_of`, `list*` and `read_properties` for a given time-to-live.
+//! Mutating operations that go through the wrapper drop cached results for the affected dataset,
+//! its descendants and its ancestors. Changes made behind wrapper's back are only picked up once TTL
+//! expires or after [`invalidate`](struct.CachedZfsEngine.html#method.invalidate).
+//!
+//! ### Usage
+//! ```rust,no_run
+//! use libzetta::zfs::{CachedZfsEngine, DelegatingZfsEngine, ZfsEngine};
+//! use std::time::Duration;
+//!
+//! let engine = CachedZfsEngine::new(DelegatingZfsEngine::new().unwrap(), Duration::from_secs(5));
+//!
+//! // Only the first call runs `zfs get`.
+//! let props = engine.read_properties("z/usr").unwrap();
+//! let props = engine.read_properties("z/usr").unwrap();
+//! ```
+use crate::zfs::{
+    properties::VolumeMode, BookmarkProperties, BookmarkRequest, ByteSize, CreateDatasetRequest,
+    DatasetKind, Delegation, DelegationGrant, DestroyTiming, Error, PartialReceive, PathExt,
+    ProjectSpace, Properties, PropertySource, PropertyValue, ReceiveOptions, RenameOptions,
+    RenameOutcome, ReplicationSendOptions, Result, SendFlags, ShareOptions, SnapDev, SnapDir,
+    ZfsEngine,
+};
+use std::{
+    collections::HashMap,
+    os::unix::io::AsRawFd,
+    path::{Path, PathBuf},
+    sync::{Mutex, MutexGuard, PoisonError},
+    time::{Duration, Instant},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Op {
+    Exists,
+    List,
+    ListFilesystems,
+    ListSnapshots,
+    ListSnapshotsOf,
+    ListBookmarks,
+    ListVolumes,
+    ReadProperties,
+    KindOf,
+}
+
+#[derive(Debug, Clone)]
+enum Cached {
+    Exists(bool),
+    List(Vec<(DatasetKind, PathBuf)>),
+    Names(Vec<PathBuf>),
+    Properties(Box<Properties>),
+    Kind(DatasetKind),
+}
+
+type Entries = HashMap<(Op, PathBuf), (Instant, Cached)>;
+
+/// [`ZfsEngine`](../trait.ZfsEngine.html) that memoizes read-only calls of `inner` engine for
+/// `ttl`. Safe to share between threads as long as inner engine is.
+pub struct CachedZfsEngine<E: ZfsEngine> {
+    inner: E,
+    ttl: Duration,
+    entries: Mutex<Entries>,
+}
+
+impl<E: ZfsEngine> CachedZfsEngine<E> {
+    /// Wrap `inner` engine. Results are considered fresh for `ttl` after they were fetched.
+    pub fn new(inner: E, ttl: Duration) -> Self {
+        CachedZfsEngine {
+            inner,
+            ttl,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Engine calls are delegated to.
+    pub fn inner(&self) -> &E {
+        &self.inner
+    }
+
+    /// Drop cached results for `path`, its descendants and its ancestors.
+    pub fn invalidate<N: AsRef<Path>>(&self, path: N) {
+        let path = path.as_ref().dataset_of();
+        self.entries()
+            .retain(|(_, key), _| !is_related(&key.dataset_of(), &path));
+    }
+
+    /// Drop everything that was cached.
+    pub fn invalidate_all(&self) {
+        self.entries().clear();
+    }
+
+    fn entries(&self) -> MutexGuard<'_, Entries> {
+        // Cache is only a copy of what zfs reports, it can't be left in inconsistent state.
+        self.entries.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn get(&self, op: Op, path: &Path) -> Option<Cached> {
+        let mut entries = self.entries();
+        let key = (op, path.to_path_buf());
+        match entries.get(&key) {
+            Some((fetched_at, value)) if fetched_at.elapsed() < self.ttl => Some(value.clone()),
+            Some(_) => {
+                entries.remove(&key);
+                None
+            }
+            None => None,
+        }
+    }
+
+    fn put(&self, op: Op, path: PathBuf, value: Cached) {
+        self.entries().insert((op, path), (Instant::now(), value));
+    }
+
+    fn cached_names<F>(&self, op: Op, path: PathBuf, fetch: F) -> Result<Vec<PathBuf>>
+    where
+        F: FnOnce(PathBuf) -> Result<Vec<PathBuf>>,
+    {
+        if let Some(Cached::Names(names)) = self.get(op, &path) {
+            return Ok(names);
+        }
+        let names = fetch(path.clone())?;
+        self.put(op, path, Cached::Names(names.clone()));
+        Ok(names)
+    }
+}
+
+/// Whether cached result for dataset `key` might be affected by a change of dataset `changed`.
+/// Listings of ancestors include the dataset, and space accounting of ancestors changes with it.
+fn is_related(key: &Path, changed: &Path) -> bool {
+    key.starts_with(changed) || changed.starts_with(key)
+}
+
+/// File system `path` is on: the mounted one with the longest mount point `path` is under.
+fn file_system_of(mounted: &[(PathBuf, PathBuf)], path: &Path) -> Option<PathBuf> {
+    mounted
+        .iter()
+        .filter(|(_, mount_point)| path.starts_with(mount_point))
+        .max_by_key(|(_, mount_point)| mount_point.components().count())
+        .map(|(dataset, _)| dataset.clone())
+}
+
+impl<E: ZfsEngine> ZfsEngine for CachedZfsEngine<E> {
+    fn exists<N: Into<PathBuf>>(&self, name: N) -> Result<bool> {
+        let name = name.into();
+        if let Some(Cached::Exists(exists)) = self.get(Op::Exists, &name) {
+            return Ok(exists);
+        }
+        let exists = self.inner.exists(name.clone())?;
+        self.put(Op::Exists, name, Cached::Exists(exists));
+        Ok(exists)
+    }
+
+    fn exists_all<N: Into<PathBuf>>(&self, names: Vec<N>) -> Result<Vec<(PathBuf, bool)>> {
+        let names: Vec<PathBuf> = names.into_iter().map(Into::into).collect();
+        let cached: Vec<Option<bool>> = names
+            .iter()
+            .map(|name| match self.get(Op::Exists, name) {
+                Some(Cached::Exists(exists)) => Some(exists),
+                _ => None,
+            })
+            .collect();
+        let missing: Vec<PathBuf> = names
+            .iter()
+            .zip(&cached)
+            .filter(|(_, exists)| exists.is_none())
+            .map(|(name, _)| name.clone())
+            .collect();
+        let mut fetched = if missing.is_empty() {
+            Vec::new()
+        } else {
+            self.inner.exists_all(missing)?
+        }
+        .into_iter();
+        let mut ret = Vec::with_capacity(names.len());
+        for (name, exists) in names.into_iter().zip(cached) {
+            match exists {
+                Some(exists) => ret.push((name, exists)),
+                None => {
+                    let (name, exists) = fetched.next().ok_or_else(|| {
+                        Error::UnknownSoFar(String::from("exists_all skipped some names"))
+                    })?;
+                    self.put(Op::Exists, name.clone(), Cached::Exists(exists));
+                    ret.push((name, exists));
+                }
+            }
+        }
+        Ok(ret)
+    }
+
+    fn create(&self, request: CreateDatasetRequest) -> Result<()> {
+        let name = request.name().clone();
+        let ret = self.inner.create(request);
+        self.invalidate(name);
+        ret
+    }
+
+    fn snapshot(
+        &self,
+        snapshots: &[PathBuf],
+        user_properties: Option<HashMap<String, String>>,
+    ) -> Result<()> {
+        let ret = self.inner.snapshot(snapshots, user_properties);
+        snapshots
+            .iter()
+            .for_each(|snapshot| self.invalidate(snapshot));
+        ret
+    }
+
+    fn destroy_snapshot_recursive<N: Into<PathBuf>>(
+        &self,
+        dataset: N,
+        snap_name: &str,
+    ) -> Result<()> {
+        let dataset = dataset.into();
+        let ret = self
+            .inner
+            .destroy_snapshot_recursive(dataset.clone(), snap_name);
+        self.invalidate(&dataset);
+        ret
+    }
+
+    fn bookmark(&self, bookmarks: &[BookmarkRequest]) -> Result<()> {
+        let ret = self.inner.bookmark(bookmarks);
+        bookmarks
+            .iter()
+            .for_each(|request| self.invalidate(&request.bookmark));
+        ret
+    }
+
+    fn destroy<N: Into<PathBuf>>(&self, name: N) -> Result<()> {
+        let name = name.into();
+        let ret = self.inner.destroy(name.clone());
+        self.invalidate(name);
+        ret
+    }
+
+    fn rename<N: Into<PathBuf>, T: Into<PathBuf>>(
+        &self,
+        name: N,
+        new_name: T,
+        opts: RenameOptions,
+    ) -> Result<RenameOutcome> {
+        let name = name.into();
+        let new_name = new_name.into();
+        let ret = self.inner.rename(name.clone(), new_name.clone(), opts);
+        self.invalidate(name);
+        self.invalidate(new_name);
+        ret
+    }
+
+    fn clone_snapshot<N: Into<PathBuf>, T: Into<PathBuf>>(
+        &self,
+        snapshot: N,
+        target: T,
+        properties: Option<HashMap<String, String>>,
+    ) -> Result<()> {
+        let snapshot = snapshot.into();
+        let target = target.into();
+        let ret = self
+            .inner
+            .clone_snapshot(snapshot.clone(), target.clone(), properties);
+        self.invalidate(snapshot);
+        self.invalidate(target);
+        ret
+    }
+
+    fn promote<N: Into<PathBuf>>(&self, clone: N) -> Result<()> {
+        let ret = self.inner.promote(clone);
+        // Snapshots move from origin, which can be anywhere in the pool, to the clone.
+        self.invalidate_all();
+        ret
+    }
+
+    fn destroy_snapshots(&self, snapshots: &[PathBuf], timing: DestroyTiming) -> Result<()> {
+        let ret = self.inner.destroy_snapshots(snapshots, timing);
+        snapshots
+            .iter()
+            .for_each(|snapshot| self.invalidate(snapshot));
+        ret
+    }
+
+    fn hold(&self, holds: &[(PathBuf, String)]) -> Result<()> {
+        let ret = self.inner.hold(holds);
+        holds
+            .iter()
+            .for_each(|(snapshot, _)| self.invalidate(snapshot));
+        ret
+    }
+
+    fn release(&self, holds: &[(PathBuf, String)]) -> Result<()> {
+        let ret = self.inner.release(holds);
+        holds
+            .iter()
+            .for_each(|(snapshot, _)| self.invalidate(snapshot));
+        ret
+    }
+
+    fn holds<N: Into<PathBuf>>(&self, snapshot: N) -> Result<Vec<String>> {
+        self.inner.holds(snapshot)
+    }
+
+    fn deferred_snapshots<N: Into<PathBuf>>(&self, root: N) -> Result<Vec<PathBuf>> {
+        self.inner.deferred_snapshots(root)
+    }
+
+    fn destroy_bookmarks(&self, bookmarks: &[PathBuf]) -> Result<()> {
+        let ret = self.inner.destroy_bookmarks(bookmarks);
+        bookmarks
+            .iter()
+            .for_each(|bookmark| self.invalidate(bookmark));
+        ret
+    }
+
+    fn list<N: Into<PathBuf>>(&self, pool: N) -> Result<Vec<(DatasetKind, PathBuf)>> {
+        let pool = pool.into();
+        if let Some(Cached::List(datasets)) = self.get(Op::List, &pool) {
+            return Ok(datasets);
+        }
+        let datasets = self.inner.list(pool.clone())?;
+        self.put(Op::List, pool, Cached::List(datasets.clone()));
+        Ok(datasets)
+    }
+
+    fn list_filesystems<N: Into<PathBuf>>(&self, pool: N) -> Result<Vec<PathBuf>> {
+        self.cached_names(Op::ListFilesystems, pool.into(), |pool| {
+            self.inner.list_filesystems(pool)
+        })
+    }
+
+    fn list_snapshots<N: Into<PathBuf>>(&self, pool: N) -> Result<Vec<PathBuf>> {
+        self.cached_names(Op::ListSnapshots, pool.into(), |pool| {
+            self.inner.list_snapshots(pool)
+        })
+    }
+
+    fn list_snapshots_of<N: Into<PathBuf>>(&self, dataset: N) -> Result<Vec<PathBuf>> {
+        self.cached_names(Op::ListSnapshotsOf, dataset.into(), |dataset| {
+            self.inner.list_snapshots_of(dataset)
+        })
+    }
+
+    fn partial_receives<N: Into<PathBuf>>(&self, root: N) -> Result<Vec<PartialReceive>> {
+        self.inner.partial_receives(root)
+    }
+
+    fn abort_partial_receive<N: Into<PathBuf>>(&self, dataset: N) -> Result<()> {
+        let dataset = dataset.into();
+        let ret = self.inner.abort_partial_receive(dataset.clone());
+        self.invalidate(&dataset);
+        ret
+    }
+
+    fn latest_snapshot<N: Into<PathBuf>>(&self, dataset: N) -> Result<Option<PathBuf>> {
+        self.inner.latest_snapshot(dataset)
+    }
+
+    fn oldest_snapshot<N: Into<PathBuf>>(&self, dataset: N) -> Result<Option<PathBuf>> {
+        self.inner.oldest_snapshot(dataset)
+    }
+
+    fn list_bookmarks<N: Into<PathBuf>>(&self, pool: N) -> Result<Vec<PathBuf>> {
+        self.cached_names(Op::ListBookmarks, pool.into(), |pool| {
+            self.inner.list_bookmarks(pool)
+        })
+    }
+
+    fn list_snapshots_with_guids<N: Into<PathBuf>>(
+        &self,
+        dataset: N,
+    ) -> Result<Vec<(PathBuf, u64)>> {
+        self.inner.list_snapshots_with_guids(dataset)
+    }
+
+    fn list_origins<N: Into<PathBuf>>(&self, root: N) -> Result<Vec<(PathBuf, Option<PathBuf>)>> {
+        self.inner.list_origins(root)
+    }
+
+    fn written_since<N: Into<PathBuf>>(&self, dataset: N, since_snapshot: &str) -> Result<u64> {
+        self.inner.written_since(dataset, since_snapshot)
+    }
+
+    fn snapshot_deltas<N: Into<PathBuf>>(&self, dataset: N) -> Result<Vec<(PathBuf, u64)>> {
+        self.inner.snapshot_deltas(dataset)
+    }
+
+    fn set_project<P: AsRef<Path>>(&self, path: P, project_id: u32, recursive: bool) -> Result<()> {
+        // `path` is a file, project usage of its file system changes. Everything is dropped if
+        // the file system can't be told.
+        let path = path.as_ref();
+        let ret = self.inner.set_project(path, project_id, recursive);
+        let dataset = self
+            .inner
+            .mounted_datasets()
+            .ok()
+            .and_then(|mounted| file_system_of(&mounted, path));
+        match dataset {
+            Some(dataset) => self.invalidate(dataset),
+            None => self.invalidate_all(),
+        }
+        ret
+    }
+
+    fn get_project<P: AsRef<Path>>(&self, path: P) -> Result<u32> {
+        self.inner.get_project(path)
+    }
+
+    fn set_project_quota<N: Into<PathBuf>>(
+        &self,
+        dataset: N,
+        project_id: u32,
+        quota: Option<ByteSize>,
+    ) -> Result<()> {
+        let dataset = dataset.into();
+        let ret = self
+            .inner
+            .set_project_quota(dataset.clone(), project_id, quota);
+        self.invalidate(dataset);
+        ret
+    }
+
+    fn project_space<N: Into<PathBuf>>(&self, dataset: N) -> Result<Vec<ProjectSpace>> {
+        self.inner.project_space(dataset)
+    }
+
+    fn get_property<N: Into<PathBuf>, T: PropertyValue>(
+        &self,
+        dataset: N,
+        prop: &str,
+    ) -> Result<T> {
+        self.inner.get_property(dataset, prop)
+    }
+
+    fn snapshot_guid<N: Into<PathBuf>>(&self, snapshot: N) -> Result<u64> {
+        self.inner.snapshot_guid(snapshot)
+    }
+
+    fn list_bookmarks_detailed<N: Into<PathBuf>>(
+        &self,
+        dataset: N,
+    ) -> Result<Vec<BookmarkProperties>> {
+        self.inner.list_bookmarks_detailed(dataset)
+    }
+
+    fn list_volumes<N: Into<PathBuf>>(&self, pool: N) -> Result<Vec<PathBuf>> {
+        self.cached_names(Op::ListVolumes, pool.into(), |pool| {
+            self.inner.list_volumes(pool)
+        })
+    }
+
+    fn mounted_datasets(&self) -> Result<Vec<(PathBuf, PathBuf)>> {
+        self.inner.mounted_datasets()
+    }
+
+    fn kind_of<N: Into<PathBuf>>(&self, path: N) -> Result<DatasetKind> {
+        let path = path.into();
+        if let Some(Cached::Kind(kind)) = self.get(Op::KindOf, &path) {
+            return Ok(kind);
+        }
+        let kind = self.inner.kind_of(path.clone())?;
+        self.put(Op::KindOf, path, Cached::Kind(kind.clone()));
+        Ok(kind)
+    }
+
+    fn unmount<N: Into<PathBuf>>(&self, dataset: N) -> Result<()> {
+        // `mounted` property changes.
+        let dataset = dataset.into();
+        let ret = self.inner.unmount(dataset.clone());
+        self.invalidate(dataset);
+        ret
+    }
+
+    fn upgrade<N: Into<PathBuf>>(
+        &self,
+        dataset: N,
+        recursive: bool,
+        to_version: Option<u32>,
+    ) -> Result<()> {
+        // `version` property changes, descendants are invalidated along with the dataset.
+        let dataset = dataset.into();
+        let ret = self.inner.upgrade(dataset.clone(), recursive, to_version);
+        self.invalidate(dataset);
+        ret
+    }
+
+    fn upgradeable_datasets<N: Into<PathBuf>>(&self, pool: N) -> Result<Vec<(PathBuf, u32)>> {
+        self.inner.upgradeable_datasets(pool)
+    }
+
+    fn read_properties<N: Into<PathBuf>>(&self, path: N) -> Result<Properties> {
+        let path = path.into();
+        if let Some(Cached::Properties(properties)) = self.get(Op::ReadProperties, &path) {
+            return Ok(*properties);
+        }
+        let properties = self.inner.read_properties(path.clone())?;
+        self.put(
+            Op::ReadProperties,
+            path,
+            Cached::Properties(Box::new(properties.clone())),
+        );
+        Ok(properties)
+    }
+
+    fn read_properties_with_sources<N: Into<PathBuf>>(
+        &self,
+        path: N,
+    ) -> Result<(Properties, HashMap<String, PropertySource>)> {
+        self.inner.read_properties_with_sources(path)
+    }
+
+    fn read_property_values<N: Into<PathBuf>>(
+        &self,
+        root: N,
+        properties: &[String],
+    ) -> Result<Vec<(PathBuf, HashMap<String, String>)>> {
+        self.inner.read_property_values(root, properties)
+    }
+
+    fn read_snapshot_property_values<N: Into<PathBuf>>(
+        &self,
+        root: N,
+        properties: &[String],
+    ) -> Result<Vec<(PathBuf, HashMap<String, String>)>> {
+        self.inner.read_snapshot_property_values(root, properties)
+    }
+
+    fn resize_volume<N: Into<PathBuf>, S: Into<ByteSize>>(
+        &self,
+        volume: N,
+        new_size: S,
+        allow_shrink: bool,
+    ) -> Result<()> {
+        let volume = volume.into();
+        let ret = self
+            .inner
+            .resize_volume(volume.clone(), new_size, allow_shrink);
+        self.invalidate(volume);
+        ret
+    }
+
+    fn set_quota<N: Into<PathBuf>>(&self, dataset: N, quota: Option<ByteSize>) -> Result<()> {
+        let dataset = dataset.into();
+        let ret = self.inner.set_quota(dataset.clone(), quota);
+        self.invalidate(dataset);
+        ret
+    }
+
+    fn set_refquota<N: Into<PathBuf>>(&self, dataset: N, quota: Option<ByteSize>) -> Result<()> {
+        let dataset = dataset.into();
+        let ret = self.inner.set_refquota(dataset.clone(), quota);
+        self.invalidate(dataset);
+        ret
+    }
+
+    fn set_snap_dev<N: Into<PathBuf>>(&self, volume: N, snap_dev: SnapDev) -> Result<()> {
+        let volume = volume.into();
+        let ret = self.inner.set_snap_dev(volume.clone(), snap_dev);
+        self.invalidate(volume);
+        ret
+    }
+
+    fn set_snap_dir<N: Into<PathBuf>>(&self, dataset: N, snap_dir: SnapDir) -> Result<()> {
+        let dataset = dataset.into();
+        let ret = self.inner.set_snap_dir(dataset.clone(), snap_dir);
+        self.invalidate(dataset);
+        ret
+    }
+
+    fn set_share_nfs<N: Into<PathBuf>>(&self, dataset: N, options: ShareOptions) -> Result<()> {
+        let dataset = dataset.into();
+        let ret = self.inner.set_share_nfs(dataset.clone(), options);
+        self.invalidate(dataset);
+        ret
+    }
+
+    fn set_share_smb<N: Into<PathBuf>>(&self, dataset: N, options: ShareOptions) -> Result<()> {
+        let dataset = dataset.into();
+        let ret = self.inner.set_share_smb(dataset.clone(), options);
+        self.invalidate(dataset);
+        ret
+    }
+
+    fn share<N: Into<PathBuf>>(&self, dataset: N) -> Result<()> {
+        let dataset = dataset.into();
+        let ret = self.inner.share(dataset.clone());
+        self.invalidate(dataset);
+        ret
+    }
+
+    fn unshare<N: Into<PathBuf>>(&self, dataset: N) -> Result<()> {
+        let dataset = dataset.into();
+        let ret = self.inner.unshare(dataset.clone());
+        self.invalidate(dataset);
+        ret
+    }
+
+    fn share_all(&self) -> Result<()> {
+        self.inner.share_all()
+    }
+
+    fn set_volume_mode<N: Into<PathBuf>>(&self, volume: N, mode: VolumeMode) -> Result<bool> {
+        let volume = volume.into();
+        let ret = self.inner.set_volume_mode(volume.clone(), mode);
+        self.invalidate(volume);
+        ret
+    }
+
+    fn send_full<N: Into<PathBuf>, FD: AsRawFd>(
+        &self,
+        path: N,
+        fd: FD,
+        flags: SendFlags,
+    ) -> Result<()> {
+        self.inner.send_full(path, fd, flags)
+    }
+
+    fn send_incremental<N: Into<PathBuf>, F: Into<PathBuf>, FD: AsRawFd>(
+        &self,
+        path: N,
+        from: F,
+        fd: FD,
+        flags: SendFlags,
+    ) -> Result<()> {
+        self.inner.send_incremental(path, from, fd, flags)
+    }
+
+    fn send_replication<N: Into<PathBuf>, FD: AsRawFd>(
+        &self,
+        to_snapshot: N,
+        from: Option<PathBuf>,
+        fd: FD,
+        opts: ReplicationSendOptions,
+    ) -> Result<()> {
+        self.inner.send_replication(to_snapshot, from, fd, opts)
+    }
+
+    fn receive<N: Into<PathBuf>, FD: AsRawFd>(
+        &self,
+        target: N,
+        fd: FD,
+        opts: ReceiveOptions,
+    ) -> Result<()> {
+        let target = target.into();
+        let ret = self.inner.receive(target.clone(), fd, opts);
+        self.invalidate(target);
+        ret
+    }
+
+    fn allow<N: Into<PathBuf>>(&self, dataset: N, grant: DelegationGrant) -> Result<()> {
+        let dataset = dataset.into();
+        let ret = self.inner.allow(dataset.clone(), grant);
+        self.invalidate(dataset);
+        ret
+    }
+
+    fn unallow<N: Into<PathBuf>>(&self, dataset: N, grant: DelegationGrant) -> Result<()> {
+        let dataset = dataset.into();
+        let ret = self.inner.unallow(dataset.clone(), grant);
+        self.invalidate(dataset);
+        ret
+    }
+
+    fn read_delegations<N: Into<PathBuf>>(&self, dataset: N) -> Result<Vec<Delegation>> {
+        self.inner.read_delegations(dataset)
+    }
+
+    fn run_channel_program<N: Into<PathBuf>>(
+        &self,
+        pool: N,
+        program: &str,
+        instr_limit: u64,
+        mem_limit: u64,
+        sync: bool,
+        args: crate::zfs::nv::NvList,
+    ) -> Result<crate::zfs::nv::NvList> {
+        // Channel programs can change anything in the pool.
+        let pool = pool.into();
+        let ret = self.inner.run_channel_program(
+            pool.clone(),
+            program,
+            instr_limit,
+            mem_limit,
+            sync,
+            args,
+        );
+        self.invalidate(pool);
+        ret
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::zfs::properties::SnapshotProperties;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[derive(Default)]
+    struct CountingEngine {
+        read_properties: AtomicUsize,
+        list_filesystems: AtomicUsize,
+        exists: AtomicUsize,
+    }
+
+    impl ZfsEngine for CountingEngine {
+        fn exists<N: Into<PathBuf>>(&self, _name: N) -> Result<bool> {
+            self.exists.fetch_add(1, Ordering::SeqCst);
+            Ok(true)
+        }
+
+        fn destroy<N: Into<PathBuf>>(&self, _name: N) -> Result<()> {
+            Ok(())
+        }
+
+        fn list_filesystems<N: Into<PathBuf>>(&self, pool: N) -> Result<Vec<PathBuf>> {
+            self.list_filesystems.fetch_add(1, Ordering::SeqCst);
+            Ok(vec![pool.into()])
+        }
+
+        fn read_properties<N: Into<PathBuf>>(&self, path: N) -> Result<Properties> {
+            self.read_properties.fetch_add(1, Ordering::SeqCst);
+            let properties = SnapshotProperties::builder(path.into())
+                .build()
+                .map_err(|e| Error::UnknownSoFar(e.to_string()))?;
+            Ok(Properties::Snapshot(properties))
+        }
+    }
+
+    fn count(counter: &AtomicUsize) -> usize {
+        counter.load(Ordering::SeqCst)
+    }
+
+    #[test]
+    fn read_properties_within_ttl_is_cached() {
+        let engine = CachedZfsEngine::new(CountingEngine::default(), Duration::from_secs(60));
+
+        let first = engine.read_properties("z/usr@snap").unwrap();
+        let second = engine.read_properties("z/usr@snap").unwrap();
+        assert_eq!(first, second);
+        assert_eq!(1, count(&engine.inner().read_properties));
+
+        engine.read_properties("z/var@snap").unwrap();
+        assert_eq!(2, count(&engine.inner().read_properties));
+
+        engine.invalidate_all();
+        engine.read_properties("z/usr@snap").unwrap();
+        assert_eq!(3, count(&engine.inner().read_properties));
+    }
+
+    #[test]
+    fn expired_results_are_refetched() {
+        let engine = CachedZfsEngine::new(CountingEngine::default(), Duration::from_secs(0));
+
+        engine.exists("z/usr").unwrap();
+        engine.exists("z/usr").unwrap();
+        assert_eq!(2, count(&engine.inner().exists));
+    }
+
+    #[test]
+    fn exists_all_only_asks_for_unknown_names() {
+        let engine = CachedZfsEngine::new(CountingEngine::default(), Duration::from_secs(60));
+
+        engine.exists("z/usr").unwrap();
+        let answers = engine.exists_all(vec!["z/var", "z/usr", "z/var"]).unwrap();
+        let expected = vec![
+            (PathBuf::from("z/var"), true),
+            (PathBuf::from("z/usr"), true),
+            (PathBuf::from("z/var"), true),
+        ];
+        assert_eq!(expected, answers);
+        assert_eq!(3, count(&engine.inner().exists));
+
+        engine.exists_all(vec!["z/usr", "z/var"]).unwrap();
+        assert_eq!(3, count(&engine.inner().exists));
+    }
+
+    #[test]
+    fn destroy_invalidates_dataset_and_parent() {
+        let engine = CachedZfsEngine::new(CountingEngine::default(), Duration::from_secs(60));
+
+        engine.list_filesystems("z").unwrap();
+        engine.list_filesystems("z/usr").unwrap();
+        engine.list_filesystems("z/var").unwrap();
+        engine.exists("z/usr/local").unwrap();
+        engine.read_properties("z/usr/local@snap").unwrap();
+        assert_eq!(3, count(&engine.inner().list_filesystems));
+
+        engine.destroy("z/usr/local").unwrap();
+
+        engine.list_filesystems("z").unwrap();
+        engine.list_filesystems("z/usr").unwrap();
+        // Sibling isn't affected.
+        engine.list_filesystems("z/var").unwrap();
+        assert_eq!(5, count(&engine.inner().list_filesystems));
+
+        engine.exists("z/usr/local").unwrap();
+        assert_eq!(2, count(&engine.inner().exists));
+        engine.read_properties("z/usr/local@snap").unwrap();
+        assert_eq!(2, count(&engine.inner().read_properties));
+    }
+
+    #[test]
+    fn sharing_and_delegation_invalidate_dataset() {
+        use crate::zfs::{DelegationGrant, Permission, Principal};
+
+        let engine = CachedZfsEngine::new(CountingEngine::default(), Duration::from_secs(60));
+        let grant = DelegationGrant::builder()
+            .principal(Principal::Everyone)
+            .permissions(vec![Permission::Send])
+            .build()
+            .unwrap();
+
+        engine.read_properties("z/usr").unwrap();
+        let _ = engine.share("z/usr");
+        engine.read_properties("z/usr").unwrap();
+        let _ = engine.unshare("z/usr");
+        engine.read_properties("z/usr").unwrap();
+        let _ = engine.allow("z/usr", grant.clone());
+        engine.read_properties("z/usr").unwrap();
+        let _ = engine.unallow("z/usr", grant);
+        engine.read_properties("z/usr").unwrap();
+        assert_eq!(5, count(&engine.inner().read_properties));
+    }
+
+    #[test]
+    fn set_project_invalidates_file_system_of_path() {
+        let engine = CachedZfsEngine::new(CountingEngine::default(), Duration::from_secs(60));
+
+        engine.read_properties("z/usr").unwrap();
+        // `CountingEngine` can't list mounts, so everything is dropped.
+        let _ = engine.set_project("/usr/src", 7, true);
+        engine.read_properties("z/usr").unwrap();
+        assert_eq!(2, count(&engine.inner().read_properties));
+
+        let mounted = vec![
+            (PathBuf::from("z"), PathBuf::from("/")),
+            (PathBuf::from("z/usr"), PathBuf::from("/usr")),
+            (PathBuf::from("z/usr/src"), PathBuf::from("/usr/src")),
+        ];
+        assert_eq!(
+            Some(PathBuf::from("z/usr/src")),
+            file_system_of(&mounted, Path::new("/usr/src/sys"))
+        );
+        assert_eq!(
+            Some(PathBuf::from("z/usr")),
+            file_system_of(&mounted, Path::new("/usr/srcs"))
+        );
+        assert_eq!(
+            Some(PathBuf::from("z")),
+            file_system_of(&mounted, Path::new("/var"))
+        );
+        assert_eq!(None, file_system_of(&mounted[1..], Path::new("/var")));
+    }
+
+    #[test]
+    fn related_paths() {
+        assert!(is_related(Path::new("z"), Path::new("z/usr")));
+        assert!(is_related(Path::new("z/usr/local"), Path::new("z/usr")));
+        assert!(is_related(Path::new("z/usr"), Path::new("z/usr")));
+        assert!(!is_related(Path::new("z/var"), Path::new("z/usr")));
+        assert!(!is_related(Path::new("z/usr2"), Path::new("z/usr")));
+    }
+}
@@ -0,0 +1,97 @@
+//! Which `libzfs_core` calls installed library has.
+//!
+//! Newer calls (`lzc_trim`, `lzc_wait` and friends) are missing from older versions of
+//! `libzfs_core`, and linking against a symbol that isn't there fails at load time. Instead they are
+//! looked up with `dlsym(3)` once per process, so callers can fall back to open3 when a call is
+//! missing.
+//!
+//! Declarations of these calls come from bindings pregenerated in `zfs-core-sys`, which covers all
+//! of them. This crate has no `bindgen` feature or vendored bindings of its own: regenerating
+//! bindings for a platform belongs to `zfs-core-sys`, and a call must still be checked here before
+//! it's used.
+use std::ffi::CString;
+
+use once_cell::sync::OnceCell;
+
+static CAPABILITIES: OnceCell<LzcCapabilities> = OnceCell::new();
+
+/// Newer `libzfs_core` calls and whether they are available in this process.
+#[derive(Debug, Clone, Copy, Default, CopyGetters, PartialEq, Eq)]
+#[getset(get_copy = "pub")]
+pub struct LzcCapabilities {
+    /// `lzc_send_resume`: resuming interrupted send.
+    send_resume: bool,
+    /// `lzc_receive_with_cmdprops`: receive with properties overridden or excluded.
+    receive_with_cmdprops: bool,
+    /// `lzc_channel_program`: running channel programs.
+    channel_program: bool,
+    /// `lzc_sync`: forcing a pool to sync.
+    sync: bool,
+    /// `lzc_reopen`: reopening devices of a pool.
+    reopen: bool,
+    /// `lzc_initialize`: initializing free space of devices.
+    initialize: bool,
+    /// `lzc_trim`: trimming devices.
+    trim: bool,
+    /// `lzc_wait`: waiting for background activity of a pool.
+    wait: bool,
+}
+
+impl LzcCapabilities {
+    /// Capabilities of `libzfs_core` loaded into this process. Symbols are looked up on first call
+    /// only.
+    pub fn get() -> LzcCapabilities {
+        *CAPABILITIES.get_or_init(|| LzcCapabilities::probe_with(has_symbol))
+    }
+
+    /// Build capabilities using `has_symbol` to tell whether a symbol is available.
+    pub(crate) fn probe_with<F: Fn(&str) -> bool>(has_symbol: F) -> LzcCapabilities {
+        LzcCapabilities {
+            send_resume: has_symbol("lzc_send_resume"),
+            receive_with_cmdprops: has_symbol("lzc_receive_with_cmdprops"),
+            channel_program: has_symbol("lzc_channel_program"),
+            sync: has_symbol("lzc_sync"),
+            reopen: has_symbol("lzc_reopen"),
+            initialize: has_symbol("lzc_initialize"),
+            trim: has_symbol("lzc_trim"),
+            wait: has_symbol("lzc_wait"),
+        }
+    }
+}
+
+/// Whether symbol with given name is loaded into this process.
+pub fn has_symbol(name: &str) -> bool {
+    let name = match CString::new(name) {
+        Ok(name) => name,
+        Err(_) => return false,
+    };
+    // dlsym(3) only reads the name, RTLD_DEFAULT searches every loaded object.
+    !unsafe { libc::dlsym(libc::RTLD_DEFAULT, name.as_ptr()) }.is_null()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{has_symbol, LzcCapabilities};
+
+    #[test]
+    fn old_library() {
+        let old = ["lzc_channel_program", "lzc_send_resume"];
+        let capabilities = LzcCapabilities::probe_with(|name| old.contains(&name));
+        assert!(capabilities.channel_program());
+        assert!(capabilities.send_resume());
+        assert!(!capabilities.trim());
+        assert!(!capabilities.wait());
+        assert!(!capabilities.receive_with_cmdprops());
+
+        let nothing = LzcCapabilities::probe_with(|_| false);
+        assert_eq!(LzcCapabilities::default(), nothing);
+    }
+
+    #[test]
+    fn symbol_lookup() {
+        assert!(has_symbol("malloc"));
+        assert!(!has_symbol("lzc_definitely_not_there"));
+        assert!(!has_symbol("nul\0byte"));
+        assert_eq!(LzcCapabilities::get(), LzcCapabilities::get());
+    }
+}
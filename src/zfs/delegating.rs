@@ -1,30 +1,117 @@
 use crate::zfs::{
-    lzc::ZfsLzc, open3::ZfsOpen3, BookmarkRequest, CreateDatasetRequest, DatasetKind,
-    DestroyTiming, Properties, Result, SendFlags, ZfsEngine,
+    lzc::ZfsLzc, open3::ZfsOpen3, properties::VolumeMode, BookmarkProperties, BookmarkRequest,
+    ByteSize, CreateDatasetRequest, DatasetKind, Delegation, DelegationGrant, DestroyTiming, Error,
+    PartialReceive, ProjectSpace, Properties, PropertySource, PropertyValue, ReceiveOptions,
+    RenameOptions, RenameOutcome, ReplicationSendOptions, Result, SendFlags, ShareOptions, SnapDev,
+    SnapDir, ValidationError, ZfsEngine,
+};
+use std::{
+    collections::HashMap,
+    os::unix::io::AsRawFd,
+    path::{Path, PathBuf},
 };
-use std::{collections::HashMap, os::unix::io::AsRawFd, path::PathBuf};
 
 /// Handy wrapper that delegates your call to correct implementation.
 pub struct DelegatingZfsEngine {
-    lzc: ZfsLzc,
+    lzc: Option<ZfsLzc>,
     open3: ZfsOpen3,
+    check_kinds: bool,
+}
+
+/// Call `$method` on lzc engine if there is one, otherwise on open3 engine.
+macro_rules! lzc_or_open3 {
+    ($engine:ident.$method:ident($($arg:expr),*)) => {
+        match &$engine.lzc {
+            Some(lzc) => lzc.$method($($arg),*),
+            None => $engine.open3.$method($($arg),*),
+        }
+    };
 }
 
 impl DelegatingZfsEngine {
+    /// If `libzfs_core` can't be initialized, e.g. in a container without `/dev/zfs`, a warning
+    /// is logged and every call goes to `zfs` binary. Calls that only `libzfs_core` implements
+    /// fail with `Unimplemented` then.
     pub fn new() -> Result<Self> {
-        let lzc = ZfsLzc::new()?;
+        Ok(DelegatingZfsEngine::with_lzc(ZfsLzc::new()))
+    }
+
+    fn with_lzc(lzc: Result<ZfsLzc>) -> Self {
         let open3 = ZfsOpen3::new();
-        Ok(DelegatingZfsEngine { lzc, open3 })
+        let lzc = match lzc {
+            Ok(lzc) => Some(lzc),
+            Err(e) => {
+                warn!(open3.logger(), "libzfs_core is not available, using zfs binary only"; "error" => %e);
+                None
+            }
+        };
+        DelegatingZfsEngine {
+            lzc,
+            open3,
+            check_kinds: true,
+        }
+    }
+
+    /// Whether calls go to `libzfs_core` where possible.
+    pub fn has_lzc(&self) -> bool {
+        self.lzc.is_some()
+    }
+
+    /// Whether to check kind of the dataset before operations that only make sense for one kind,
+    /// e.g. `unmount` of a file system or `resize_volume`. Mismatch fails with
+    /// `ValidationError::WrongDatasetKind` instead of whatever `zfs` says. Check costs an extra
+    /// call to `zfs`, turn it off if that matters. On by default.
+    pub fn set_check_kinds(&mut self, check_kinds: bool) {
+        self.check_kinds = check_kinds;
+    }
+
+    fn check_kind(&self, path: &Path, expected: DatasetKind) -> Result<()> {
+        if self.check_kinds {
+            ensure_kind(self, path, expected)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Fail with `ValidationError::WrongDatasetKind` unless `path` is of `expected` kind.
+fn ensure_kind<E: ZfsEngine>(engine: &E, path: &Path, expected: DatasetKind) -> Result<()> {
+    let found = engine.kind_of(path)?;
+    if found == expected {
+        Ok(())
+    } else {
+        Err(ValidationError::WrongDatasetKind {
+            dataset: path.to_path_buf(),
+            expected,
+            found,
+        }
+        .into())
+    }
+}
+
+/// `zfs list` of a missing dataset fails with wording that depends on the platform. When
+/// `result` is a failure that wasn't recognized, ask `engine` whether `path` exists and report
+/// `DatasetNotFound` if it doesn't.
+fn not_found_if_missing<E: ZfsEngine, T>(engine: &E, path: &Path, result: Result<T>) -> Result<T> {
+    match result {
+        Err(Error::UnknownSoFar(_)) if !engine.exists(path).unwrap_or(true) => {
+            Err(Error::DatasetNotFound(path.to_path_buf()))
+        }
+        other => other,
     }
 }
 
 impl ZfsEngine for DelegatingZfsEngine {
     fn exists<N: Into<PathBuf>>(&self, name: N) -> Result<bool> {
-        self.lzc.exists(name)
+        lzc_or_open3!(self.exists(name))
+    }
+
+    fn exists_all<N: Into<PathBuf>>(&self, names: Vec<N>) -> Result<Vec<(PathBuf, bool)>> {
+        lzc_or_open3!(self.exists_all(names))
     }
 
     fn create(&self, request: CreateDatasetRequest) -> Result<()> {
-        self.lzc.create(request)
+        lzc_or_open3!(self.create(request))
     }
 
     fn snapshot(
@@ -32,56 +119,335 @@ impl ZfsEngine for DelegatingZfsEngine {
         snapshots: &[PathBuf],
         user_properties: Option<HashMap<String, String>>,
     ) -> Result<()> {
-        self.lzc.snapshot(snapshots, user_properties)
+        lzc_or_open3!(self.snapshot(snapshots, user_properties))
     }
 
     fn bookmark(&self, bookmarks: &[BookmarkRequest]) -> Result<()> {
-        self.lzc.bookmark(bookmarks)
+        lzc_or_open3!(self.bookmark(bookmarks))
     }
 
     fn destroy<N: Into<PathBuf>>(&self, name: N) -> Result<()> {
         self.open3.destroy(name)
     }
 
+    fn rename<N: Into<PathBuf>, T: Into<PathBuf>>(
+        &self,
+        name: N,
+        new_name: T,
+        opts: RenameOptions,
+    ) -> Result<RenameOutcome> {
+        self.open3.rename(name, new_name, opts)
+    }
+
+    fn clone_snapshot<N: Into<PathBuf>, T: Into<PathBuf>>(
+        &self,
+        snapshot: N,
+        target: T,
+        properties: Option<HashMap<String, String>>,
+    ) -> Result<()> {
+        self.open3.clone_snapshot(snapshot, target, properties)
+    }
+
+    fn promote<N: Into<PathBuf>>(&self, clone: N) -> Result<()> {
+        self.open3.promote(clone)
+    }
+
     fn destroy_snapshots(&self, snapshots: &[PathBuf], timing: DestroyTiming) -> Result<()> {
-        self.lzc.destroy_snapshots(snapshots, timing)
+        lzc_or_open3!(self.destroy_snapshots(snapshots, timing))
+    }
+
+    fn destroy_snapshot_recursive<N: Into<PathBuf>>(
+        &self,
+        dataset: N,
+        snap_name: &str,
+    ) -> Result<()> {
+        lzc_or_open3!(self.destroy_snapshot_recursive(dataset, snap_name))
+    }
+
+    fn hold(&self, holds: &[(PathBuf, String)]) -> Result<()> {
+        lzc_or_open3!(self.hold(holds))
+    }
+
+    fn release(&self, holds: &[(PathBuf, String)]) -> Result<()> {
+        lzc_or_open3!(self.release(holds))
+    }
+
+    fn holds<N: Into<PathBuf>>(&self, snapshot: N) -> Result<Vec<String>> {
+        lzc_or_open3!(self.holds(snapshot))
     }
 
     fn destroy_bookmarks(&self, bookmarks: &[PathBuf]) -> Result<()> {
-        self.lzc.destroy_bookmarks(bookmarks)
+        lzc_or_open3!(self.destroy_bookmarks(bookmarks))
+    }
+
+    fn deferred_snapshots<N: Into<PathBuf>>(&self, root: N) -> Result<Vec<PathBuf>> {
+        let root = root.into();
+        not_found_if_missing(self, &root, self.open3.deferred_snapshots(root.clone()))
     }
 
     fn list<N: Into<PathBuf>>(&self, pool: N) -> Result<Vec<(DatasetKind, PathBuf)>> {
-        self.open3.list(pool)
+        let pool = pool.into();
+        not_found_if_missing(self, &pool, self.open3.list(pool.clone()))
     }
 
     fn list_filesystems<N: Into<PathBuf>>(&self, pool: N) -> Result<Vec<PathBuf>> {
-        self.open3.list_filesystems(pool)
+        let pool = pool.into();
+        not_found_if_missing(self, &pool, self.open3.list_filesystems(pool.clone()))
     }
 
     fn list_snapshots<N: Into<PathBuf>>(&self, pool: N) -> Result<Vec<PathBuf>> {
-        self.open3.list_snapshots(pool)
+        let pool = pool.into();
+        not_found_if_missing(self, &pool, self.open3.list_snapshots(pool.clone()))
+    }
+
+    fn list_snapshots_of<N: Into<PathBuf>>(&self, dataset: N) -> Result<Vec<PathBuf>> {
+        let dataset = dataset.into();
+        not_found_if_missing(
+            self,
+            &dataset,
+            self.open3.list_snapshots_of(dataset.clone()),
+        )
+    }
+
+    fn partial_receives<N: Into<PathBuf>>(&self, root: N) -> Result<Vec<PartialReceive>> {
+        let root = root.into();
+        not_found_if_missing(self, &root, self.open3.partial_receives(root.clone()))
+    }
+
+    fn abort_partial_receive<N: Into<PathBuf>>(&self, dataset: N) -> Result<()> {
+        self.open3.abort_partial_receive(dataset)
+    }
+
+    fn latest_snapshot<N: Into<PathBuf>>(&self, dataset: N) -> Result<Option<PathBuf>> {
+        lzc_or_open3!(self.latest_snapshot(dataset))
+    }
+
+    fn oldest_snapshot<N: Into<PathBuf>>(&self, dataset: N) -> Result<Option<PathBuf>> {
+        lzc_or_open3!(self.oldest_snapshot(dataset))
     }
 
     fn list_bookmarks<N: Into<PathBuf>>(&self, pool: N) -> Result<Vec<PathBuf>> {
-        self.open3.list_bookmarks(pool)
+        let pool = pool.into();
+        not_found_if_missing(self, &pool, self.open3.list_bookmarks(pool.clone()))
+    }
+
+    fn list_snapshots_with_guids<N: Into<PathBuf>>(
+        &self,
+        dataset: N,
+    ) -> Result<Vec<(PathBuf, u64)>> {
+        let dataset = dataset.into();
+        not_found_if_missing(
+            self,
+            &dataset,
+            self.open3.list_snapshots_with_guids(dataset.clone()),
+        )
+    }
+
+    fn list_origins<N: Into<PathBuf>>(&self, root: N) -> Result<Vec<(PathBuf, Option<PathBuf>)>> {
+        let root = root.into();
+        not_found_if_missing(self, &root, self.open3.list_origins(root.clone()))
+    }
+
+    fn written_since<N: Into<PathBuf>>(&self, dataset: N, since_snapshot: &str) -> Result<u64> {
+        self.open3.written_since(dataset, since_snapshot)
+    }
+
+    fn snapshot_deltas<N: Into<PathBuf>>(&self, dataset: N) -> Result<Vec<(PathBuf, u64)>> {
+        self.open3.snapshot_deltas(dataset)
+    }
+
+    fn set_project<P: AsRef<Path>>(&self, path: P, project_id: u32, recursive: bool) -> Result<()> {
+        self.open3.set_project(path, project_id, recursive)
+    }
+
+    fn get_project<P: AsRef<Path>>(&self, path: P) -> Result<u32> {
+        self.open3.get_project(path)
+    }
+
+    fn set_project_quota<N: Into<PathBuf>>(
+        &self,
+        dataset: N,
+        project_id: u32,
+        quota: Option<ByteSize>,
+    ) -> Result<()> {
+        self.open3.set_project_quota(dataset, project_id, quota)
+    }
+
+    fn project_space<N: Into<PathBuf>>(&self, dataset: N) -> Result<Vec<ProjectSpace>> {
+        let dataset = dataset.into();
+        not_found_if_missing(self, &dataset, self.open3.project_space(dataset.clone()))
+    }
+
+    fn get_property<N: Into<PathBuf>, T: PropertyValue>(
+        &self,
+        dataset: N,
+        prop: &str,
+    ) -> Result<T> {
+        self.open3.get_property(dataset, prop)
+    }
+
+    fn snapshot_guid<N: Into<PathBuf>>(&self, snapshot: N) -> Result<u64> {
+        self.open3.snapshot_guid(snapshot)
+    }
+
+    fn list_bookmarks_detailed<N: Into<PathBuf>>(
+        &self,
+        dataset: N,
+    ) -> Result<Vec<BookmarkProperties>> {
+        let dataset = dataset.into();
+        not_found_if_missing(
+            self,
+            &dataset,
+            self.open3.list_bookmarks_detailed(dataset.clone()),
+        )
     }
 
     fn list_volumes<N: Into<PathBuf>>(&self, pool: N) -> Result<Vec<PathBuf>> {
-        self.open3.list_volumes(pool)
+        let pool = pool.into();
+        not_found_if_missing(self, &pool, self.open3.list_volumes(pool.clone()))
+    }
+
+    fn mounted_datasets(&self) -> Result<Vec<(PathBuf, PathBuf)>> {
+        self.open3.mounted_datasets()
+    }
+
+    fn kind_of<N: Into<PathBuf>>(&self, path: N) -> Result<DatasetKind> {
+        self.open3.kind_of(path)
+    }
+
+    fn unmount<N: Into<PathBuf>>(&self, dataset: N) -> Result<()> {
+        let dataset = dataset.into();
+        self.check_kind(&dataset, DatasetKind::Filesystem)?;
+        self.open3.unmount(dataset)
+    }
+
+    fn upgrade<N: Into<PathBuf>>(
+        &self,
+        dataset: N,
+        recursive: bool,
+        to_version: Option<u32>,
+    ) -> Result<()> {
+        self.open3.upgrade(dataset, recursive, to_version)
+    }
+
+    fn upgradeable_datasets<N: Into<PathBuf>>(&self, pool: N) -> Result<Vec<(PathBuf, u32)>> {
+        self.open3.upgradeable_datasets(pool)
     }
 
     fn read_properties<N: Into<PathBuf>>(&self, path: N) -> Result<Properties> {
         self.open3.read_properties(path)
     }
 
+    fn read_properties_with_sources<N: Into<PathBuf>>(
+        &self,
+        path: N,
+    ) -> Result<(Properties, HashMap<String, PropertySource>)> {
+        self.open3.read_properties_with_sources(path)
+    }
+
+    fn read_property_values<N: Into<PathBuf>>(
+        &self,
+        root: N,
+        properties: &[String],
+    ) -> Result<Vec<(PathBuf, HashMap<String, String>)>> {
+        let root = root.into();
+        not_found_if_missing(
+            self,
+            &root,
+            self.open3.read_property_values(root.clone(), properties),
+        )
+    }
+
+    fn read_snapshot_property_values<N: Into<PathBuf>>(
+        &self,
+        root: N,
+        properties: &[String],
+    ) -> Result<Vec<(PathBuf, HashMap<String, String>)>> {
+        let root = root.into();
+        not_found_if_missing(
+            self,
+            &root,
+            self.open3
+                .read_snapshot_property_values(root.clone(), properties),
+        )
+    }
+
+    fn resize_volume<N: Into<PathBuf>, S: Into<ByteSize>>(
+        &self,
+        volume: N,
+        new_size: S,
+        allow_shrink: bool,
+    ) -> Result<()> {
+        let volume = volume.into();
+        self.check_kind(&volume, DatasetKind::Volume)?;
+        self.open3.resize_volume(volume, new_size, allow_shrink)
+    }
+
+    fn set_quota<N: Into<PathBuf>>(&self, dataset: N, quota: Option<ByteSize>) -> Result<()> {
+        self.open3.set_quota(dataset, quota)
+    }
+
+    fn set_refquota<N: Into<PathBuf>>(&self, dataset: N, quota: Option<ByteSize>) -> Result<()> {
+        self.open3.set_refquota(dataset, quota)
+    }
+
+    fn set_snap_dev<N: Into<PathBuf>>(&self, volume: N, snap_dev: SnapDev) -> Result<()> {
+        let volume = volume.into();
+        self.check_kind(&volume, DatasetKind::Volume)?;
+        self.open3.set_snap_dev(volume, snap_dev)
+    }
+
+    fn set_snap_dir<N: Into<PathBuf>>(&self, dataset: N, snap_dir: SnapDir) -> Result<()> {
+        let dataset = dataset.into();
+        self.check_kind(&dataset, DatasetKind::Filesystem)?;
+        self.open3.set_snap_dir(dataset, snap_dir)
+    }
+
+    fn set_share_nfs<N: Into<PathBuf>>(&self, dataset: N, options: ShareOptions) -> Result<()> {
+        let dataset = dataset.into();
+        self.check_kind(&dataset, DatasetKind::Filesystem)?;
+        self.open3.set_share_nfs(dataset, options)
+    }
+
+    fn set_share_smb<N: Into<PathBuf>>(&self, dataset: N, options: ShareOptions) -> Result<()> {
+        let dataset = dataset.into();
+        self.check_kind(&dataset, DatasetKind::Filesystem)?;
+        self.open3.set_share_smb(dataset, options)
+    }
+
+    fn share<N: Into<PathBuf>>(&self, dataset: N) -> Result<()> {
+        let dataset = dataset.into();
+        self.check_kind(&dataset, DatasetKind::Filesystem)?;
+        self.open3.share(dataset)
+    }
+
+    fn unshare<N: Into<PathBuf>>(&self, dataset: N) -> Result<()> {
+        let dataset = dataset.into();
+        self.check_kind(&dataset, DatasetKind::Filesystem)?;
+        self.open3.unshare(dataset)
+    }
+
+    fn share_all(&self) -> Result<()> {
+        self.open3.share_all()
+    }
+
+    fn set_volume_mode<N: Into<PathBuf>>(&self, volume: N, mode: VolumeMode) -> Result<bool> {
+        let volume = volume.into();
+        self.check_kind(&volume, DatasetKind::Volume)?;
+        self.open3.set_volume_mode(volume, mode)
+    }
+
+    fn volume_device_path<N: Into<PathBuf>>(&self, volume: N) -> Result<PathBuf> {
+        self.open3.volume_device_path(volume)
+    }
+
     fn send_full<N: Into<PathBuf>, FD: AsRawFd>(
         &self,
         path: N,
         fd: FD,
         flags: SendFlags,
     ) -> Result<()> {
-        self.lzc.send_full(path, fd, flags)
+        lzc_or_open3!(self.send_full(path, fd, flags))
     }
 
     fn send_incremental<N: Into<PathBuf>, F: Into<PathBuf>, FD: AsRawFd>(
@@ -91,7 +457,38 @@ impl ZfsEngine for DelegatingZfsEngine {
         fd: FD,
         flags: SendFlags,
     ) -> Result<()> {
-        self.lzc.send_incremental(path, from, fd, flags)
+        lzc_or_open3!(self.send_incremental(path, from, fd, flags))
+    }
+
+    fn send_replication<N: Into<PathBuf>, FD: AsRawFd>(
+        &self,
+        to_snapshot: N,
+        from: Option<PathBuf>,
+        fd: FD,
+        opts: ReplicationSendOptions,
+    ) -> Result<()> {
+        self.open3.send_replication(to_snapshot, from, fd, opts)
+    }
+
+    fn receive<N: Into<PathBuf>, FD: AsRawFd>(
+        &self,
+        target: N,
+        fd: FD,
+        opts: ReceiveOptions,
+    ) -> Result<()> {
+        self.open3.receive(target, fd, opts)
+    }
+
+    fn allow<N: Into<PathBuf>>(&self, dataset: N, grant: DelegationGrant) -> Result<()> {
+        self.open3.allow(dataset, grant)
+    }
+
+    fn unallow<N: Into<PathBuf>>(&self, dataset: N, grant: DelegationGrant) -> Result<()> {
+        self.open3.unallow(dataset, grant)
+    }
+
+    fn read_delegations<N: Into<PathBuf>>(&self, dataset: N) -> Result<Vec<Delegation>> {
+        self.open3.read_delegations(dataset)
     }
 
     fn run_channel_program<N: Into<PathBuf>>(
@@ -101,9 +498,141 @@ impl ZfsEngine for DelegatingZfsEngine {
         instr_limit: u64,
         mem_limit: u64,
         sync: bool,
-        args: libnv::nvpair::NvList,
-    ) -> Result<libnv::nvpair::NvList> {
-        self.lzc
-            .run_channel_program(pool, program, instr_limit, mem_limit, sync, args)
+        args: crate::zfs::nv::NvList,
+    ) -> Result<crate::zfs::nv::NvList> {
+        lzc_or_open3!(self.run_channel_program(pool, program, instr_limit, mem_limit, sync, args))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Engine that only knows kinds of a few datasets.
+    struct Kinds;
+
+    impl ZfsEngine for Kinds {
+        fn kind_of<N: Into<PathBuf>>(&self, path: N) -> Result<DatasetKind> {
+            let path = path.into();
+            match path.to_str() {
+                Some("z/fs") => Ok(DatasetKind::Filesystem),
+                Some("z/vol") => Ok(DatasetKind::Volume),
+                Some("z/fs@snap") => Ok(DatasetKind::Snapshot),
+                Some("z/fs#mark") => Ok(DatasetKind::Bookmark),
+                _ => Err(Error::DatasetNotFound(path)),
+            }
+        }
+    }
+
+    /// Engine where only `z/fs` exists.
+    struct Existing;
+
+    impl ZfsEngine for Existing {
+        fn exists<N: Into<PathBuf>>(&self, name: N) -> Result<bool> {
+            Ok(name.into() == Path::new("z/fs"))
+        }
+    }
+
+    #[test]
+    fn unrecognized_list_failure_of_missing_dataset() {
+        let unknown = || Err::<(), _>(Error::UnknownSoFar(String::from("cannot list")));
+        assert_eq!(
+            Err(Error::DatasetNotFound(PathBuf::from("z/nope"))),
+            not_found_if_missing(&Existing, Path::new("z/nope"), unknown())
+        );
+        assert_eq!(
+            unknown(),
+            not_found_if_missing(&Existing, Path::new("z/fs"), unknown())
+        );
+        assert_eq!(
+            Err(Error::Unimplemented),
+            not_found_if_missing(
+                &Kinds,
+                Path::new("z/nope"),
+                Err::<(), _>(Error::Unimplemented)
+            )
+        );
+        assert_eq!(
+            Ok(()),
+            not_found_if_missing(&Existing, Path::new("z/nope"), Ok(()))
+        );
+    }
+
+    fn mismatch(dataset: &str, expected: DatasetKind, found: DatasetKind) -> Error {
+        ValidationError::WrongDatasetKind {
+            dataset: PathBuf::from(dataset),
+            expected,
+            found,
+        }
+        .into()
+    }
+
+    #[test]
+    fn ensure_kind_reports_mismatch() {
+        let engine = Kinds;
+        assert_eq!(
+            Ok(()),
+            ensure_kind(&engine, Path::new("z/fs"), DatasetKind::Filesystem)
+        );
+        assert_eq!(
+            Ok(()),
+            ensure_kind(&engine, Path::new("z/vol"), DatasetKind::Volume)
+        );
+        // unmount
+        assert_eq!(
+            Err(mismatch(
+                "z/vol",
+                DatasetKind::Filesystem,
+                DatasetKind::Volume
+            )),
+            ensure_kind(&engine, Path::new("z/vol"), DatasetKind::Filesystem)
+        );
+        assert_eq!(
+            Err(mismatch(
+                "z/fs@snap",
+                DatasetKind::Filesystem,
+                DatasetKind::Snapshot
+            )),
+            ensure_kind(&engine, Path::new("z/fs@snap"), DatasetKind::Filesystem)
+        );
+        // resize_volume
+        assert_eq!(
+            Err(mismatch(
+                "z/fs",
+                DatasetKind::Volume,
+                DatasetKind::Filesystem
+            )),
+            ensure_kind(&engine, Path::new("z/fs"), DatasetKind::Volume)
+        );
+        assert_eq!(
+            Err(mismatch(
+                "z/fs#mark",
+                DatasetKind::Volume,
+                DatasetKind::Bookmark
+            )),
+            ensure_kind(&engine, Path::new("z/fs#mark"), DatasetKind::Volume)
+        );
+        assert_eq!(
+            Err(Error::DatasetNotFound(PathBuf::from("z/nope"))),
+            ensure_kind(&engine, Path::new("z/nope"), DatasetKind::Volume)
+        );
+    }
+
+    #[test]
+    fn falls_back_to_open3_without_lzc() {
+        let logger = slog::Logger::root(slog::Discard, o!());
+        let failed = ZfsLzc::with_init(|| libc::ENOENT, logger);
+        match &failed {
+            Err(Error::LZCInitializationFailed(e)) => {
+                assert_eq!(Some(libc::ENOENT), e.raw_os_error())
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+
+        let engine = DelegatingZfsEngine::with_lzc(failed);
+        assert!(!engine.has_lzc());
+        // Only libzfs_core can hold snapshots.
+        let holds = [(PathBuf::from("z/fs@snap"), String::from("keep"))];
+        assert_eq!(Err(Error::Unimplemented), engine.hold(&holds));
     }
 }
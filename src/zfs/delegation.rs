@@ -0,0 +1,407 @@
+//! Delegated administration: `zfs allow` and `zfs unallow`.
+//!
+//! [`DelegationGrant`](struct.DelegationGrant.html) describes who gets which permissions and
+//! where, the same structure is used for both granting and revoking. What is already delegated
+//! can be read with [`read_delegations`](../trait.ZfsEngine.html#method.read_delegations).
+use std::{ffi::OsString, fmt, path::PathBuf, str::FromStr};
+
+use crate::zfs::{Error, Result};
+
+/// Who permissions are delegated to.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Principal {
+    /// User by name or uid (`-u`).
+    User(String),
+    /// Group by name or gid (`-g`).
+    Group(String),
+    /// Everyone (`-e`).
+    Everyone,
+    /// Creator of a descendent dataset, permissions are granted when dataset is created (`-c`).
+    Creator,
+    /// Named permission set (`-s @name`). Name is without `@`. Granting to a set defines it.
+    PermissionSet(String),
+}
+
+/// Where delegated permissions apply.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum DelegationScope {
+    /// Only the dataset itself (`-l`).
+    Local,
+    /// Only descendents of the dataset (`-d`).
+    Descendent,
+    /// Both the dataset and its descendents.
+    #[default]
+    LocalAndDescendent,
+}
+
+/// Subcommand or property that can be delegated. See `zfs-allow(8)` for full list.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Permission {
+    Allow,
+    Bookmark,
+    ChangeKey,
+    Clone,
+    Create,
+    Destroy,
+    Diff,
+    Hold,
+    LoadKey,
+    Mount,
+    Promote,
+    Receive,
+    Release,
+    Rename,
+    Rollback,
+    Send,
+    Share,
+    Snapshot,
+    UserProp,
+    /// Permission set, name is without `@`.
+    Set(String),
+    /// Anything else, like a property name or permission this enum doesn't know about yet.
+    Other(String),
+}
+
+impl fmt::Display for Permission {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Permission::Allow => "allow",
+            Permission::Bookmark => "bookmark",
+            Permission::ChangeKey => "change-key",
+            Permission::Clone => "clone",
+            Permission::Create => "create",
+            Permission::Destroy => "destroy",
+            Permission::Diff => "diff",
+            Permission::Hold => "hold",
+            Permission::LoadKey => "load-key",
+            Permission::Mount => "mount",
+            Permission::Promote => "promote",
+            Permission::Receive => "receive",
+            Permission::Release => "release",
+            Permission::Rename => "rename",
+            Permission::Rollback => "rollback",
+            Permission::Send => "send",
+            Permission::Share => "share",
+            Permission::Snapshot => "snapshot",
+            Permission::UserProp => "userprop",
+            Permission::Set(name) => return write!(f, "@{}", name),
+            Permission::Other(raw) => raw,
+        };
+        f.write_str(name)
+    }
+}
+
+impl FromStr for Permission {
+    type Err = Error;
+
+    fn from_str(source: &str) -> Result<Permission> {
+        let permission = match source {
+            "allow" => Permission::Allow,
+            "bookmark" => Permission::Bookmark,
+            "change-key" => Permission::ChangeKey,
+            "clone" => Permission::Clone,
+            "create" => Permission::Create,
+            "destroy" => Permission::Destroy,
+            "diff" => Permission::Diff,
+            "hold" => Permission::Hold,
+            "load-key" => Permission::LoadKey,
+            "mount" => Permission::Mount,
+            "promote" => Permission::Promote,
+            "receive" | "recv" => Permission::Receive,
+            "release" => Permission::Release,
+            "rename" => Permission::Rename,
+            "rollback" => Permission::Rollback,
+            "send" => Permission::Send,
+            "share" => Permission::Share,
+            "snapshot" => Permission::Snapshot,
+            "userprop" => Permission::UserProp,
+            "" => return Err(Error::UnknownSoFar(String::from("Empty permission"))),
+            other => match other.strip_prefix('@') {
+                Some(set) => Permission::Set(String::from(set)),
+                None => Permission::Other(String::from(other)),
+            },
+        };
+        Ok(permission)
+    }
+}
+
+/// Permissions to grant with `allow` or revoke with `unallow`.
+#[derive(Builder, Debug, Clone, Getters, PartialEq, Eq)]
+#[builder(setter(into))]
+#[get = "pub"]
+pub struct DelegationGrant {
+    /// Who permissions are for.
+    principal: Principal,
+    /// What is allowed. Empty list revokes every permission of the principal in `unallow`.
+    #[builder(default)]
+    permissions: Vec<Permission>,
+    /// Where permissions apply. Ignored for `Creator` and `PermissionSet`.
+    #[builder(default)]
+    scope: DelegationScope,
+}
+
+impl DelegationGrant {
+    /// A preferred way to create this.
+    pub fn builder() -> DelegationGrantBuilder {
+        DelegationGrantBuilder::default()
+    }
+
+    pub(crate) fn into_args(self) -> Vec<OsString> {
+        let mut ret: Vec<OsString> = Vec::with_capacity(4);
+        match self.principal {
+            Principal::Creator | Principal::PermissionSet(_) => {}
+            _ => match self.scope {
+                DelegationScope::Local => ret.push("-l".into()),
+                DelegationScope::Descendent => ret.push("-d".into()),
+                DelegationScope::LocalAndDescendent => {}
+            },
+        }
+        match self.principal {
+            Principal::User(user) => {
+                ret.push("-u".into());
+                ret.push(user.into());
+            }
+            Principal::Group(group) => {
+                ret.push("-g".into());
+                ret.push(group.into());
+            }
+            Principal::Everyone => ret.push("-e".into()),
+            Principal::Creator => ret.push("-c".into()),
+            Principal::PermissionSet(name) => {
+                ret.push("-s".into());
+                ret.push(format!("@{}", name).into());
+            }
+        }
+        if !self.permissions.is_empty() {
+            let permissions: Vec<String> =
+                self.permissions.iter().map(Permission::to_string).collect();
+            ret.push(permissions.join(",").into());
+        }
+        ret
+    }
+}
+
+/// Permissions delegated on a dataset, as `zfs allow <dataset>` reports them.
+#[derive(Debug, Clone, Getters, PartialEq, Eq)]
+#[get = "pub"]
+pub struct Delegation {
+    /// Dataset permissions are set on. Either the dataset that was asked about or one of its
+    /// ancestors.
+    dataset: PathBuf,
+    /// Who permissions are for.
+    principal: Principal,
+    /// What is allowed.
+    permissions: Vec<Permission>,
+    /// Where permissions apply. Always `LocalAndDescendent` for `Creator` and `PermissionSet`.
+    scope: DelegationScope,
+}
+
+fn parse_permissions(list: &str) -> Result<Vec<Permission>> {
+    list.split(',').map(|p| p.trim().parse()).collect()
+}
+
+/// Parse output of `zfs allow <dataset>`. It's split into sections per dataset starting with
+/// `---- Permissions on <dataset> ----`, each of them has subsections by kind of delegation with
+/// one principal per line.
+pub(crate) fn parse_delegations(stdout: &str) -> Result<Vec<Delegation>> {
+    let err = |line: &str| Error::UnknownSoFar(format!("Unexpected line: {}", line));
+    let mut ret = Vec::new();
+    let mut dataset: Option<PathBuf> = None;
+    // Subsection and scope of everything in it. `None` for create time permissions.
+    let mut section: Option<(bool, DelegationScope)> = None;
+    for line in stdout.lines() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        if let Some(header) = line.strip_prefix("---- Permissions on ") {
+            let name = header.trim_end_matches('-').trim();
+            dataset = Some(PathBuf::from(name));
+            section = None;
+            continue;
+        }
+        if !line.starts_with(char::is_whitespace) {
+            section = Some(match line.trim() {
+                "Permission sets:" => (false, DelegationScope::LocalAndDescendent),
+                "Create time permissions:" => (true, DelegationScope::LocalAndDescendent),
+                "Local permissions:" => (false, DelegationScope::Local),
+                "Descendent permissions:" => (false, DelegationScope::Descendent),
+                "Local+Descendent permissions:" => (false, DelegationScope::LocalAndDescendent),
+                _ => return Err(err(line)),
+            });
+            continue;
+        }
+        let dataset = dataset.clone().ok_or_else(|| err(line))?;
+        let (create_time, scope) = section.ok_or_else(|| err(line))?;
+        let line = line.trim();
+        let (principal, permissions) = if create_time {
+            (Principal::Creator, line)
+        } else {
+            let mut words = line.splitn(2, char::is_whitespace);
+            let kind = words.next().unwrap_or_default();
+            let rest = words.next().unwrap_or_default().trim();
+            let mut named = rest.splitn(2, char::is_whitespace);
+            let name = String::from(named.next().unwrap_or_default());
+            let named_permissions = named.next().unwrap_or_default().trim();
+            match kind {
+                "user" => (Principal::User(name), named_permissions),
+                "group" => (Principal::Group(name), named_permissions),
+                "everyone" => (Principal::Everyone, rest),
+                set if set.starts_with('@') => {
+                    (Principal::PermissionSet(String::from(&set[1..])), rest)
+                }
+                _ => return Err(err(line)),
+            }
+        };
+        ret.push(Delegation {
+            dataset,
+            principal,
+            permissions: parse_permissions(permissions)?,
+            scope,
+        });
+    }
+    Ok(ret)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn user(name: &str) -> Principal {
+        Principal::User(String::from(name))
+    }
+
+    #[test]
+    fn grant_args() {
+        let grant = DelegationGrant::builder()
+            .principal(user("backup"))
+            .permissions(vec![
+                Permission::Send,
+                Permission::Snapshot,
+                Permission::Hold,
+            ])
+            .build()
+            .unwrap();
+        let expected: Vec<OsString> =
+            vec!["-u".into(), "backup".into(), "send,snapshot,hold".into()];
+        assert_eq!(expected, grant.into_args());
+
+        let grant = DelegationGrant::builder()
+            .principal(Principal::Everyone)
+            .permissions(vec![Permission::Set(String::from("pset"))])
+            .scope(DelegationScope::Local)
+            .build()
+            .unwrap();
+        let expected: Vec<OsString> = vec!["-l".into(), "-e".into(), "@pset".into()];
+        assert_eq!(expected, grant.into_args());
+
+        let grant = DelegationGrant::builder()
+            .principal(Principal::PermissionSet(String::from("pset")))
+            .permissions(vec![
+                Permission::Mount,
+                Permission::Other(String::from("compression")),
+            ])
+            .scope(DelegationScope::Descendent)
+            .build()
+            .unwrap();
+        let expected: Vec<OsString> = vec!["-s".into(), "@pset".into(), "mount,compression".into()];
+        assert_eq!(expected, grant.into_args());
+
+        let grant = DelegationGrant::builder()
+            .principal(Principal::Group(String::from("staff")))
+            .scope(DelegationScope::Descendent)
+            .build()
+            .unwrap();
+        let expected: Vec<OsString> = vec!["-d".into(), "-g".into(), "staff".into()];
+        assert_eq!(expected, grant.into_args());
+    }
+
+    #[test]
+    fn permissions_round_trip() {
+        for raw in &["send", "load-key", "@backup", "quota", "receive"] {
+            let permission: Permission = raw.parse().unwrap();
+            assert_eq!(*raw, permission.to_string());
+        }
+        assert_eq!(Ok(Permission::Receive), "recv".parse());
+        assert!("".parse::<Permission>().is_err());
+    }
+
+    #[test]
+    fn parse_allow_freebsd() {
+        let stdout = include_str!("fixtures/allow_freebsd");
+        let delegations = parse_delegations(stdout).unwrap();
+        let home = PathBuf::from("tank/usr/home");
+        let tank = PathBuf::from("tank");
+        let expected = vec![
+            Delegation {
+                dataset: home.clone(),
+                principal: Principal::PermissionSet(String::from("backup")),
+                permissions: vec![Permission::Hold, Permission::Send, Permission::Snapshot],
+                scope: DelegationScope::LocalAndDescendent,
+            },
+            Delegation {
+                dataset: home.clone(),
+                principal: Principal::Creator,
+                permissions: vec![Permission::Destroy, Permission::Mount],
+                scope: DelegationScope::LocalAndDescendent,
+            },
+            Delegation {
+                dataset: home.clone(),
+                principal: user("cindys"),
+                permissions: vec![Permission::Create, Permission::Destroy],
+                scope: DelegationScope::Local,
+            },
+            Delegation {
+                dataset: home.clone(),
+                principal: Principal::Group(String::from("staff")),
+                permissions: vec![
+                    Permission::Other(String::from("quota")),
+                    Permission::Snapshot,
+                ],
+                scope: DelegationScope::Descendent,
+            },
+            Delegation {
+                dataset: home,
+                principal: user("backup"),
+                permissions: vec![Permission::Set(String::from("backup"))],
+                scope: DelegationScope::LocalAndDescendent,
+            },
+            Delegation {
+                dataset: tank,
+                principal: Principal::Everyone,
+                permissions: vec![Permission::Mount],
+                scope: DelegationScope::LocalAndDescendent,
+            },
+        ];
+        assert_eq!(expected, delegations);
+    }
+
+    #[test]
+    fn parse_allow_linux() {
+        let stdout = include_str!("fixtures/allow_linux");
+        let delegations = parse_delegations(stdout).unwrap();
+        assert_eq!(3, delegations.len());
+        assert!(delegations
+            .iter()
+            .all(|d| d.dataset() == &PathBuf::from("rpool/backup")));
+        assert_eq!(&user("syncoid"), delegations[0].principal());
+        assert_eq!(
+            &vec![
+                Permission::Create,
+                Permission::Hold,
+                Permission::Mount,
+                Permission::Receive,
+                Permission::Release,
+                Permission::Send,
+                Permission::Snapshot
+            ],
+            delegations[0].permissions()
+        );
+        assert_eq!(&user("1005"), delegations[1].principal());
+        assert_eq!(&DelegationScope::Local, delegations[2].scope());
+        assert_eq!(&Principal::Everyone, delegations[2].principal());
+
+        assert_eq!(Ok(Vec::new()), parse_delegations(""));
+        assert!(parse_delegations("Local permissions:\n\tuser x send\n").is_err());
+        assert!(parse_delegations("---- Permissions on tank ----\nWeird:\n").is_err());
+    }
+}
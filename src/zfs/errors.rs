@@ -1,6 +1,10 @@
-use crate::parsers::zfs::{Rule, ZfsParser};
+use crate::{
+    instrumentation::CommandTimedOut,
+    parsers::zfs::{Rule, ZfsParser},
+    zfs::{ByteSize, DatasetKind, SendFlags},
+};
 use pest::Parser;
-use std::{borrow::Cow, collections::HashMap, io, path::PathBuf};
+use std::{borrow::Cow, collections::HashMap, io, path::PathBuf, time::Duration};
 
 pub type Result<T, E = Error> = std::result::Result<T, E>;
 pub type ValidationResult<T = (), E = ValidationError> = std::result::Result<T, E>;
@@ -19,23 +23,109 @@ quick_error! {
         }
         Io(err: std::io::Error) {
             cause(err)
-            from()
+        }
+        /// Command didn't finish within the timeout set with `set_command_timeout` and was
+        /// killed. Contains command line and the timeout.
+        TimedOut { command: String, after: Duration } {
+            display("{} didn't exit within {:?}", command, after)
         }
         Unknown {}
         UnknownSoFar(err: String) {}
         DatasetNotFound(dataset: PathBuf) {}
+        /// Dataset with this name already exists. On file systems with
+        /// `casesensitivity=insensitive` snapshot names that only differ in case are the same name.
+        DatasetExists(dataset: PathBuf) {}
+        /// Installed `zfs` doesn't support this. Contains what is missing, e.g. `rename -u`.
+        Unsupported(feature: String) {}
+        /// Refused to shrink the volume because `allow_shrink` wasn't set.
+        VolumeShrinkRefused { volume: PathBuf, current: ByteSize, requested: ByteSize } {}
+        /// Volume isn't exposed as a device: its `volmode` is `none`, or platform doesn't expose
+        /// volumes at all.
+        NoDeviceExposed(volume: PathBuf) {}
+        /// Device node didn't show up within the timeout. Contains the node and the timeout.
+        DeviceTimedOut { device: PathBuf, after: Duration } {
+            display("{} didn't appear within {:?}", device.display(), after)
+        }
+        /// Quota, reservation or volume size is less than space that is already used or reserved.
+        SizeBelowUsage(dataset: PathBuf) {}
+        /// File system isn't mounted, so its files can't be reached.
+        NotMounted(dataset: PathBuf) {}
+        /// `zfs share` or `zfs unshare` failed. Contains the file system and what `zfs` said,
+        /// e.g. `share(1M) failed`.
+        ShareFailed { dataset: PathBuf, reason: String } {
+            display("failed to (un)share {}: {}", dataset.display(), reason)
+        }
+        /// `snapshot_limit` of the dataset or one of its ancestors is reached. Contains the
+        /// snapshot.
+        SnapshotLimitExceeded { dataset: PathBuf } {
+            display("cannot create {}: snapshot limit exceeded", dataset.display())
+        }
+        /// `filesystem_limit` of an ancestor is reached. Contains the file system or volume.
+        FilesystemLimitExceeded { dataset: PathBuf } {
+            display("cannot create {}: filesystem limit exceeded", dataset.display())
+        }
         ValidationErrors(errors: Vec<ValidationError>) {
             from()
         }
-        MultiOpError(err: HashMap<String, libnv::nvpair::Value>) {
+        MultiOpError(err: HashMap<String, crate::zfs::nv::Value>) {
             from()
         }
-        ChanProgInval(err: HashMap<String, libnv::nvpair::Value>) {}
-        ChanProgRuntime(err: HashMap<String, libnv::nvpair::Value>) {}
+        /// Snapshots failed to be created. Contains name of each snapshot that caused failure along
+        /// with errno. Creation is atomic, so none of requested snapshots were created.
+        SnapshotsFailed(errors: Vec<(PathBuf, i32)>) {}
+        /// Some of the snapshots failed to be destroyed. Contains name of each failed snapshot
+        /// along with errno.
+        DestroySnapshotsFailed(errors: Vec<(PathBuf, i32)>) {}
+        /// Holds failed to be placed. Contains name of each snapshot that caused failure along
+        /// with errno. None of requested holds were placed.
+        HoldsFailed(errors: Vec<(PathBuf, i32)>) {}
+        /// Some of the holds failed to be released. Contains name of each snapshot that caused
+        /// failure along with errno.
+        ReleaseFailed(errors: Vec<(PathBuf, i32)>) {}
+        /// Some of the bookmarks failed to be destroyed. Contains name of each failed bookmark
+        /// along with errno.
+        DestroyBookmarksFailed(errors: Vec<(PathBuf, i32)>) {}
+        /// Output of `zfs` has a property name or value that has to be text, but isn't valid
+        /// UTF-8. Contains raw bytes.
+        NonUtf8(bytes: Vec<u8>) {}
+        /// Output of `zfs get` has a value that doesn't parse as the type of its property.
+        InvalidPropertyValue { property: String, value: String } {
+            display("invalid value of {}: {:?}", property, value)
+        }
+        /// Receiving side doesn't accept a property carried by the stream. Contains name of the
+        /// property, so receive can be retried with it excluded.
+        InvalidReceiveProperty(property: String) {}
+        /// Data isn't a send stream or its header is damaged. Contains what is wrong with it.
+        InvalidSendStream(reason: String) {
+            display("invalid send stream: {}", reason)
+        }
+        /// Dataset has no state of an interrupted resumable receive to abort.
+        NoResumableReceive(dataset: PathBuf) {}
+        /// Installed ZFS doesn't support these send flags. Nothing was sent.
+        UnsupportedSendFlags(flags: SendFlags) {}
+        /// Cloning of a dataset tree failed at `dataset`. Clones created before the failure were
+        /// destroyed, except for those in `left_behind`.
+        CloneTreeFailed { dataset: PathBuf, left_behind: Vec<PathBuf>, cause: Box<Error> } {
+            display("failed to clone {}: {}", dataset.display(), cause)
+        }
+        ChanProgInval(err: HashMap<String, crate::zfs::nv::Value>) {}
+        ChanProgRuntime(err: HashMap<String, crate::zfs::nv::Value>) {}
         Unimplemented {}
     }
 }
 
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Error {
+        match CommandTimedOut::from_io(&err) {
+            Some(timed_out) => Error::TimedOut {
+                command: timed_out.command.clone(),
+                after: timed_out.after,
+            },
+            None => Error::Io(err),
+        }
+    }
+}
+
 impl From<ValidationError> for Error {
     fn from(err: ValidationError) -> Error {
         Error::ValidationErrors(vec![err])
@@ -49,10 +139,33 @@ impl Error {
             Error::LZCInitializationFailed(_) => ErrorKind::LZCInitializationFailed,
             Error::NvOpError(_) => ErrorKind::NvOpError,
             Error::Io(_) => ErrorKind::Io,
+            Error::TimedOut { .. } => ErrorKind::TimedOut,
             Error::DatasetNotFound(_) => ErrorKind::DatasetNotFound,
+            Error::VolumeShrinkRefused { .. } => ErrorKind::VolumeShrinkRefused,
+            Error::DatasetExists(_) => ErrorKind::DatasetExists,
+            Error::Unsupported(_) => ErrorKind::Unsupported,
+            Error::NoDeviceExposed(_) => ErrorKind::NoDeviceExposed,
+            Error::DeviceTimedOut { .. } => ErrorKind::DeviceTimedOut,
+            Error::SizeBelowUsage(_) => ErrorKind::SizeBelowUsage,
+            Error::NotMounted(_) => ErrorKind::NotMounted,
+            Error::ShareFailed { .. } => ErrorKind::ShareFailed,
+            Error::SnapshotLimitExceeded { .. } => ErrorKind::SnapshotLimitExceeded,
+            Error::FilesystemLimitExceeded { .. } => ErrorKind::FilesystemLimitExceeded,
             Error::Unknown | Error::UnknownSoFar(_) => ErrorKind::Unknown,
             Error::ValidationErrors(_) => ErrorKind::ValidationErrors,
             Error::MultiOpError(_) => ErrorKind::MultiOpError,
+            Error::SnapshotsFailed(_) => ErrorKind::SnapshotsFailed,
+            Error::DestroySnapshotsFailed(_) => ErrorKind::DestroySnapshotsFailed,
+            Error::DestroyBookmarksFailed(_) => ErrorKind::DestroyBookmarksFailed,
+            Error::HoldsFailed(_) => ErrorKind::HoldsFailed,
+            Error::ReleaseFailed(_) => ErrorKind::ReleaseFailed,
+            Error::NonUtf8(_) => ErrorKind::NonUtf8,
+            Error::InvalidPropertyValue { .. } => ErrorKind::InvalidPropertyValue,
+            Error::InvalidReceiveProperty(_) => ErrorKind::InvalidReceiveProperty,
+            Error::InvalidSendStream(_) => ErrorKind::InvalidSendStream,
+            Error::NoResumableReceive(_) => ErrorKind::NoResumableReceive,
+            Error::UnsupportedSendFlags(_) => ErrorKind::UnsupportedSendFlags,
+            Error::CloneTreeFailed { .. } => ErrorKind::CloneTreeFailed,
             Error::ChanProgInval(_) => ErrorKind::ChanProgInval,
             Error::ChanProgRuntime(_) => ErrorKind::ChanProgRuntime,
             Error::Unimplemented => ErrorKind::Unimplemented,
@@ -75,6 +188,38 @@ impl Error {
                     let dataset_name_pair = error_pair.into_inner().next().unwrap();
                     Error::DatasetNotFound(PathBuf::from(dataset_name_pair.as_str()))
                 }
+                Rule::dataset_exists => {
+                    let dataset_name_pair = error_pair.into_inner().next().unwrap();
+                    Error::DatasetExists(PathBuf::from(dataset_name_pair.as_str()))
+                }
+                Rule::size_below_usage => {
+                    let dataset_name_pair = error_pair.into_inner().next().unwrap();
+                    Error::SizeBelowUsage(PathBuf::from(dataset_name_pair.as_str()))
+                }
+                Rule::no_resumable_receive => {
+                    let dataset_name_pair = error_pair.into_inner().next().unwrap();
+                    Error::NoResumableReceive(PathBuf::from(dataset_name_pair.as_str()))
+                }
+                Rule::invalid_receive_property => {
+                    let property_pair = error_pair.into_inner().next().unwrap();
+                    Error::InvalidReceiveProperty(property_pair.as_str().into())
+                }
+                Rule::share_failed => {
+                    let mut inner = error_pair.into_inner();
+                    let dataset = PathBuf::from(inner.next().unwrap().as_str());
+                    let reason = String::from(inner.next().unwrap().as_str().trim_end());
+                    Error::ShareFailed { dataset, reason }
+                }
+                Rule::limit_exceeded => {
+                    let mut inner = error_pair.into_inner();
+                    let dataset = PathBuf::from(inner.next().unwrap().as_str());
+                    let kind = inner.next().unwrap().as_str();
+                    if kind == "snapshot" {
+                        Error::SnapshotLimitExceeded { dataset }
+                    } else {
+                        Error::FilesystemLimitExceeded { dataset }
+                    }
+                }
                 _ => Self::unknown_so_far(stderr),
             }
         } else {
@@ -94,11 +239,34 @@ pub enum ErrorKind {
     NvOpError,
     InvalidInput,
     Io,
+    TimedOut,
     Unknown,
     DatasetNotFound,
+    DatasetExists,
+    Unsupported,
+    NoDeviceExposed,
+    DeviceTimedOut,
+    VolumeShrinkRefused,
+    SizeBelowUsage,
+    NotMounted,
+    ShareFailed,
+    SnapshotLimitExceeded,
+    FilesystemLimitExceeded,
     ValidationErrors,
     Unimplemented,
     MultiOpError,
+    SnapshotsFailed,
+    DestroySnapshotsFailed,
+    DestroyBookmarksFailed,
+    HoldsFailed,
+    ReleaseFailed,
+    NonUtf8,
+    InvalidPropertyValue,
+    InvalidReceiveProperty,
+    InvalidSendStream,
+    NoResumableReceive,
+    UnsupportedSendFlags,
+    CloneTreeFailed,
     ChanProgInval,
     ChanProgRuntime,
 }
@@ -107,18 +275,102 @@ impl PartialEq for Error {
     fn eq(&self, other: &Self) -> bool {
         match (self, other) {
             (Error::ValidationErrors(l), Error::ValidationErrors(r)) => l == r,
+            (Error::NotMounted(l), Error::NotMounted(r)) => l == r,
+            (
+                Error::ShareFailed { dataset, reason },
+                Error::ShareFailed {
+                    dataset: r_dataset,
+                    reason: r_reason,
+                },
+            ) => dataset == r_dataset && reason == r_reason,
+            (
+                Error::SnapshotLimitExceeded { dataset },
+                Error::SnapshotLimitExceeded { dataset: r_dataset },
+            )
+            | (
+                Error::FilesystemLimitExceeded { dataset },
+                Error::FilesystemLimitExceeded { dataset: r_dataset },
+            ) => dataset == r_dataset,
+            (
+                Error::TimedOut { command, after },
+                Error::TimedOut {
+                    command: r_command,
+                    after: r_after,
+                },
+            ) => command == r_command && after == r_after,
+            (Error::DatasetExists(l), Error::DatasetExists(r)) => l == r,
+            (Error::Unsupported(l), Error::Unsupported(r)) => l == r,
+            (Error::NoDeviceExposed(l), Error::NoDeviceExposed(r)) => l == r,
+            (
+                Error::DeviceTimedOut { device, after },
+                Error::DeviceTimedOut {
+                    device: r_device,
+                    after: r_after,
+                },
+            ) => device == r_device && after == r_after,
+            (Error::SnapshotsFailed(l), Error::SnapshotsFailed(r)) => l == r,
+            (Error::DestroySnapshotsFailed(l), Error::DestroySnapshotsFailed(r)) => l == r,
+            (Error::DestroyBookmarksFailed(l), Error::DestroyBookmarksFailed(r)) => l == r,
+            (Error::HoldsFailed(l), Error::HoldsFailed(r)) => l == r,
+            (Error::ReleaseFailed(l), Error::ReleaseFailed(r)) => l == r,
+            (Error::NonUtf8(l), Error::NonUtf8(r)) => l == r,
+            (
+                Error::InvalidPropertyValue { property, value },
+                Error::InvalidPropertyValue {
+                    property: r_property,
+                    value: r_value,
+                },
+            ) => property == r_property && value == r_value,
+            (Error::InvalidReceiveProperty(l), Error::InvalidReceiveProperty(r)) => l == r,
+            (Error::InvalidSendStream(l), Error::InvalidSendStream(r)) => l == r,
+            (Error::NoResumableReceive(l), Error::NoResumableReceive(r)) => l == r,
+            (Error::UnsupportedSendFlags(l), Error::UnsupportedSendFlags(r)) => l == r,
+            (
+                Error::CloneTreeFailed {
+                    dataset,
+                    left_behind,
+                    cause,
+                },
+                Error::CloneTreeFailed {
+                    dataset: r_dataset,
+                    left_behind: r_left_behind,
+                    cause: r_cause,
+                },
+            ) => dataset == r_dataset && left_behind == r_left_behind && cause == r_cause,
             _ => self.kind() == other.kind(),
         }
     }
 }
+
 quick_error! {
-    #[derive(Debug, Eq, PartialEq)]
+    #[derive(Debug, Clone, Eq, PartialEq)]
     pub enum ValidationError {
         MultipleZpools(zpools: Vec<PathBuf>) {}
         NameTooLong(dataset: PathBuf) {}
         MissingName(dataset: PathBuf) {}
         MissingSnapshotName(dataset: PathBuf) {}
+        MissingBookmarkName(dataset: PathBuf) {}
         MissingPool(dataset: PathBuf) {}
+        /// Batch operation includes datasets from more than one pool. Contains datasets that
+        /// aren't in the pool of the first one.
+        CrossPoolOperation(datasets: Vec<PathBuf>) {}
+        /// Batch operation lists the same name more than once. Contains every repeated name once.
+        DuplicateName(datasets: Vec<PathBuf>) {}
+        /// Name contains a character ZFS doesn't allow, or `@`/`#` outside of the last component.
+        InvalidCharacter { dataset: PathBuf, component: String, ch: char } {}
+        /// One of the components (between `/`) of the name is too long.
+        ComponentTooLong { dataset: PathBuf, component: String } {}
+        /// Parent of the dataset doesn't exist.
+        ParentNotFound(dataset: PathBuf) {}
+        /// Operation doesn't make sense for this kind of dataset, e.g. resizing a file system.
+        WrongDatasetKind { dataset: PathBuf, expected: DatasetKind, found: DatasetKind } {}
+        /// Record size or volume block size isn't a power of two between 512 bytes and 1 MiB.
+        InvalidBlockSize { dataset: PathBuf, size: ByteSize } {}
+        /// These two properties can't be set to the requested values together, e.g. `utf8only=off`
+        /// with `normalization` other than `none`.
+        ConflictingProperties { dataset: PathBuf, first: String, second: String } {}
+        /// Snapshot name template has a specifier `chrono` doesn't know, e.g. `%Q`.
+        InvalidNameTemplate(template: String) {}
         Unknown(dataset: PathBuf) {}
     }
 }
@@ -0,0 +1,436 @@
+//! What depends on what in a tree of datasets.
+//!
+//! Destroying datasets one by one fails as soon as something still depends on them: child
+//! datasets, snapshots and bookmarks belong to their dataset, clones depend on their origin
+//! snapshot and user holds pin snapshots in place.
+//! [`build_dependency_graph`](fn.build_dependency_graph.html) collects these relations for
+//! everything under a root, [`DependencyGraph`](struct.DependencyGraph.html) answers what has to
+//! go first. Only public [`ZfsEngine`](../trait.ZfsEngine.html) methods are used.
+//!
+//! Clones outside of the root and origins outside of the root are part of the graph too, but only
+//! as leaves: their own snapshots and children aren't listed.
+//!
+//! ### Usage
+//! ```rust,no_run
+//! use libzetta::zfs::{graph, DelegatingZfsEngine, ZfsEngine};
+//!
+//! let engine = DelegatingZfsEngine::new().unwrap();
+//! let graph = graph::build_dependency_graph(&engine, "tank/old").unwrap();
+//! if !graph.would_orphan("tank/old") {
+//!     for dataset in graph.destruction_order(&["tank/old".into()]) {
+//!         engine.destroy(dataset).unwrap();
+//!     }
+//! }
+//! ```
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    path::{Path, PathBuf},
+};
+
+use crate::zfs::{DatasetKind, PathExt, PropertyValue, Result, ZfsEngine};
+
+/// Relations between datasets, snapshots and bookmarks of a tree. Dependents of a path can't
+/// outlive it: they have to be destroyed (or, for clones, promoted) first.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DependencyGraph {
+    /// Child datasets, snapshots and bookmarks of each dataset.
+    owned: BTreeMap<PathBuf, BTreeSet<PathBuf>>,
+    /// Clones of each snapshot.
+    clones: BTreeMap<PathBuf, BTreeSet<PathBuf>>,
+    /// Number of user holds of each held snapshot.
+    holds: BTreeMap<PathBuf, u64>,
+}
+
+/// List everything under `root` (root included), read `origin` of all datasets of the pool and
+/// `userrefs` of all snapshots under `root` at once, and build a graph out of them. Clones can be
+/// anywhere in the pool, that's why origins of the whole pool are read.
+///
+///  * `root` - Dataset to start from, e.g. `tank/old`.
+pub fn build_dependency_graph<E, N>(engine: &E, root: N) -> Result<DependencyGraph>
+where
+    E: ZfsEngine,
+    N: Into<PathBuf>,
+{
+    let root = root.into();
+    let listed = engine.list(root.clone())?;
+    let names: HashSet<&Path> = listed.iter().map(|(_, name)| name.as_path()).collect();
+    let mut graph = DependencyGraph::default();
+    for (kind, name) in &listed {
+        match kind {
+            DatasetKind::Snapshot | DatasetKind::Bookmark => {
+                graph.add_owned(name.dataset_of(), name.clone());
+            }
+            DatasetKind::Filesystem | DatasetKind::Volume => {
+                if let Some(parent) = name.parent().filter(|parent| names.contains(parent)) {
+                    graph.add_owned(parent.to_path_buf(), name.clone());
+                }
+            }
+        }
+    }
+    for (clone, origin) in engine.list_origins(root.pool())? {
+        if let Some(origin) = origin {
+            if names.contains(clone.as_path()) || names.contains(origin.as_path()) {
+                graph.add_clone(origin, clone);
+            }
+        }
+    }
+    let userrefs = [String::from("userrefs")];
+    for (snapshot, values) in engine.read_snapshot_property_values(root.clone(), &userrefs)? {
+        let holds = match values.get("userrefs") {
+            Some(value) => u64::from_value("userrefs", value)?,
+            None => 0,
+        };
+        if holds > 0 {
+            graph.holds.insert(snapshot, holds);
+        }
+    }
+    Ok(graph)
+}
+
+impl DependencyGraph {
+    fn add_owned(&mut self, owner: PathBuf, owned: PathBuf) {
+        self.owned.entry(owner).or_default().insert(owned);
+    }
+
+    fn add_clone(&mut self, origin: PathBuf, clone: PathBuf) {
+        self.clones.entry(origin).or_default().insert(clone);
+    }
+
+    /// Direct dependents of `path` sorted by name: child datasets, snapshots and bookmarks of a
+    /// dataset, clones of a snapshot.
+    pub fn dependents_of<N: AsRef<Path>>(&self, path: N) -> Vec<PathBuf> {
+        let path = path.as_ref();
+        let owned = self.owned.get(path).into_iter().flatten();
+        let clones = self.clones.get(path).into_iter().flatten();
+        owned
+            .chain(clones)
+            .cloned()
+            .collect::<BTreeSet<PathBuf>>()
+            .into_iter()
+            .collect()
+    }
+
+    /// Origin snapshot of a clone.
+    pub fn origin_of<N: AsRef<Path>>(&self, clone: N) -> Option<&Path> {
+        let clone = clone.as_ref();
+        self.clones
+            .iter()
+            .find(|(_, clones)| clones.contains(clone))
+            .map(|(origin, _)| origin.as_path())
+    }
+
+    /// Number of user holds pinning a snapshot. Held snapshots can only be destroyed after holds
+    /// are released.
+    pub fn user_holds<N: AsRef<Path>>(&self, snapshot: N) -> u64 {
+        self.holds.get(snapshot.as_ref()).copied().unwrap_or(0)
+    }
+
+    /// `paths` together with everything that depends on them, directly or not, in an order they
+    /// can be destroyed in: every path comes after all of its dependents, so clones come before
+    /// their origins and children before their parents. Clones that should survive have to be
+    /// promoted instead, after that the graph has to be built again.
+    pub fn destruction_order(&self, paths: &[PathBuf]) -> Vec<PathBuf> {
+        let mut visited = HashSet::new();
+        let mut ret = Vec::new();
+        for path in paths {
+            self.visit(path, &mut visited, &mut ret);
+        }
+        ret
+    }
+
+    /// Depth-first walk that emits dependents before the path itself. ZFS can't produce cycles,
+    /// `visited` is marked before descending anyway, so inconsistent input can't loop forever.
+    fn visit(&self, path: &Path, visited: &mut HashSet<PathBuf>, ret: &mut Vec<PathBuf>) {
+        if !visited.insert(path.to_path_buf()) {
+            return;
+        }
+        for dependent in self.dependents_of(path) {
+            self.visit(&dependent, visited, ret);
+        }
+        ret.push(path.to_path_buf());
+    }
+
+    /// Clones that would lose their origin if `path` was destroyed together with everything it
+    /// owns (`zfs destroy -r`), sorted by name. Those have to be promoted or destroyed first.
+    pub fn orphaned_by<N: AsRef<Path>>(&self, path: N) -> Vec<PathBuf> {
+        let mut destroyed = HashSet::new();
+        let mut pending = vec![path.as_ref().to_path_buf()];
+        while let Some(path) = pending.pop() {
+            if let Some(owned) = self.owned.get(&path) {
+                pending.extend(owned.iter().cloned());
+            }
+            destroyed.insert(path);
+        }
+        let mut ret: Vec<PathBuf> = destroyed
+            .iter()
+            .filter_map(|path| self.clones.get(path))
+            .flatten()
+            .filter(|clone| !destroyed.contains(*clone))
+            .cloned()
+            .collect();
+        ret.sort();
+        ret
+    }
+
+    /// Whether destroying `path` with everything it owns leaves any clone without its origin.
+    pub fn would_orphan<N: AsRef<Path>>(&self, path: N) -> bool {
+        !self.orphaned_by(path).is_empty()
+    }
+
+    /// Every edge of the graph as `(dependency, dependent)`.
+    fn edges(&self) -> impl Iterator<Item = (&PathBuf, &PathBuf)> {
+        self.owned
+            .iter()
+            .chain(self.clones.iter())
+            .flat_map(|(from, to)| to.iter().map(move |to| (from, to)))
+    }
+
+    /// Whether `order` puts every dependent before its dependency. Paths that aren't in `order`
+    /// are ignored.
+    pub fn is_valid_order(&self, order: &[PathBuf]) -> bool {
+        let positions: HashMap<&Path, usize> = order
+            .iter()
+            .enumerate()
+            .map(|(idx, path)| (path.as_path(), idx))
+            .collect();
+        self.edges().all(|(dependency, dependent)| {
+            match (
+                positions.get(dependency.as_path()),
+                positions.get(dependent.as_path()),
+            ) {
+                (Some(dependency), Some(dependent)) => dependent < dependency,
+                _ => true,
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{collections::HashMap, path::PathBuf};
+
+    use super::{build_dependency_graph, DependencyGraph};
+    use crate::zfs::{DatasetKind, Error, Result, ZfsEngine};
+
+    /// Engine that knows a fixed set of datasets, clones and holds.
+    struct Pool {
+        datasets: Vec<(DatasetKind, &'static str)>,
+        /// Clone and its origin.
+        origins: Vec<(&'static str, &'static str)>,
+        holds: Vec<(&'static str, u64)>,
+    }
+
+    impl ZfsEngine for Pool {
+        fn list<N: Into<PathBuf>>(&self, prefix: N) -> Result<Vec<(DatasetKind, PathBuf)>> {
+            let prefix = prefix.into().to_string_lossy().into_owned();
+            Ok(self
+                .datasets
+                .iter()
+                .filter(|(_, name)| {
+                    name.strip_prefix(prefix.as_str()).map_or(false, |rest| {
+                        rest.is_empty() || rest.starts_with(&['/', '@', '#'][..])
+                    })
+                })
+                .map(|(kind, name)| (kind.clone(), PathBuf::from(name)))
+                .collect())
+        }
+
+        fn list_origins<N: Into<PathBuf>>(
+            &self,
+            root: N,
+        ) -> Result<Vec<(PathBuf, Option<PathBuf>)>> {
+            Ok(self
+                .list(root)?
+                .into_iter()
+                .filter(|(kind, _)| {
+                    *kind == DatasetKind::Filesystem || *kind == DatasetKind::Volume
+                })
+                .map(|(_, name)| {
+                    let origin = self
+                        .origins
+                        .iter()
+                        .find(|(clone, _)| name == PathBuf::from(clone))
+                        .map(|(_, origin)| PathBuf::from(origin));
+                    (name, origin)
+                })
+                .collect())
+        }
+
+        fn read_snapshot_property_values<N: Into<PathBuf>>(
+            &self,
+            root: N,
+            properties: &[String],
+        ) -> Result<Vec<(PathBuf, HashMap<String, String>)>> {
+            if properties != [String::from("userrefs")] {
+                return Err(Error::Unimplemented);
+            }
+            Ok(self
+                .list(root)?
+                .into_iter()
+                .filter(|(kind, _)| *kind == DatasetKind::Snapshot)
+                .map(|(_, name)| {
+                    let holds = self
+                        .holds
+                        .iter()
+                        .find(|(snapshot, _)| name == PathBuf::from(snapshot))
+                        .map_or(0, |(_, holds)| *holds);
+                    let mut values = HashMap::new();
+                    values.insert(String::from("userrefs"), holds.to_string());
+                    (name, values)
+                })
+                .collect())
+        }
+    }
+
+    fn paths(names: &[&str]) -> Vec<PathBuf> {
+        names.iter().map(PathBuf::from).collect()
+    }
+
+    /// `tank/old/a@1` is cloned to `tank/old/b`, `tank/old/b@2` to `tank/old/c`, `tank/old/c@3`
+    /// to `tank/d` outside of the tree.
+    fn chain() -> Pool {
+        Pool {
+            datasets: vec![
+                (DatasetKind::Filesystem, "tank"),
+                (DatasetKind::Filesystem, "tank/old"),
+                (DatasetKind::Filesystem, "tank/old/a"),
+                (DatasetKind::Snapshot, "tank/old/a@1"),
+                (DatasetKind::Bookmark, "tank/old/a#1"),
+                (DatasetKind::Filesystem, "tank/old/b"),
+                (DatasetKind::Snapshot, "tank/old/b@2"),
+                (DatasetKind::Volume, "tank/old/c"),
+                (DatasetKind::Snapshot, "tank/old/c@3"),
+                (DatasetKind::Filesystem, "tank/d"),
+            ],
+            origins: vec![
+                ("tank/old/b", "tank/old/a@1"),
+                ("tank/old/c", "tank/old/b@2"),
+                ("tank/d", "tank/old/c@3"),
+            ],
+            holds: vec![("tank/old/b@2", 2)],
+        }
+    }
+
+    #[test]
+    fn clone_chain() {
+        let graph = build_dependency_graph(&chain(), "tank/old").unwrap();
+        assert_eq!(paths(&["tank/old/b"]), graph.dependents_of("tank/old/a@1"));
+        assert_eq!(
+            paths(&["tank/old/a#1", "tank/old/a@1"]),
+            graph.dependents_of("tank/old/a")
+        );
+        assert_eq!(
+            paths(&["tank/old/a", "tank/old/b", "tank/old/c"]),
+            graph.dependents_of("tank/old")
+        );
+        assert_eq!(
+            Some(PathBuf::from("tank/old/c@3").as_path()),
+            graph.origin_of("tank/d")
+        );
+        assert_eq!(None, graph.origin_of("tank/old/a"));
+        assert_eq!(2, graph.user_holds("tank/old/b@2"));
+        assert_eq!(0, graph.user_holds("tank/old/a@1"));
+
+        let order = graph.destruction_order(&paths(&["tank/old/a"]));
+        assert_eq!(
+            paths(&[
+                "tank/old/a#1",
+                "tank/d",
+                "tank/old/c@3",
+                "tank/old/c",
+                "tank/old/b@2",
+                "tank/old/b",
+                "tank/old/a@1",
+                "tank/old/a"
+            ]),
+            order
+        );
+        assert!(graph.is_valid_order(&order));
+        assert!(!graph.is_valid_order(&paths(&["tank/old/a@1", "tank/old/b"])));
+    }
+
+    #[test]
+    fn orphans() {
+        let graph = build_dependency_graph(&chain(), "tank/old").unwrap();
+        // Destroying the whole tree only orphans the clone outside of it.
+        assert_eq!(paths(&["tank/d"]), graph.orphaned_by("tank/old"));
+        assert_eq!(paths(&["tank/old/b"]), graph.orphaned_by("tank/old/a"));
+        assert_eq!(paths(&["tank/old/b"]), graph.orphaned_by("tank/old/a@1"));
+        assert!(graph.would_orphan("tank/old/c@3"));
+        assert!(!graph.would_orphan("tank/old/a#1"));
+        assert!(!graph.would_orphan("tank/d"));
+
+        let graph = build_dependency_graph(&chain(), "tank/d").unwrap();
+        assert_eq!(paths(&["tank/d"]), graph.dependents_of("tank/old/c@3"));
+        assert!(!graph.would_orphan("tank/d"));
+    }
+
+    #[test]
+    fn diamond() {
+        // tank owns both src and dst, dst/clone is owned by dst and cloned from src@s.
+        let pool = Pool {
+            datasets: vec![
+                (DatasetKind::Filesystem, "tank"),
+                (DatasetKind::Filesystem, "tank/src"),
+                (DatasetKind::Snapshot, "tank/src@s"),
+                (DatasetKind::Filesystem, "tank/dst"),
+                (DatasetKind::Filesystem, "tank/dst/clone"),
+                (DatasetKind::Snapshot, "tank/dst/clone@t"),
+            ],
+            origins: vec![("tank/dst/clone", "tank/src@s")],
+            holds: vec![],
+        };
+        let graph = build_dependency_graph(&pool, "tank").unwrap();
+        let order = graph.destruction_order(&paths(&["tank"]));
+        assert_eq!(
+            paths(&[
+                "tank/dst/clone@t",
+                "tank/dst/clone",
+                "tank/dst",
+                "tank/src@s",
+                "tank/src",
+                "tank"
+            ]),
+            order
+        );
+        assert!(graph.is_valid_order(&order));
+        assert!(!graph.would_orphan("tank"));
+        assert!(!graph.would_orphan("tank/dst"));
+        assert!(graph.would_orphan("tank/src"));
+
+        // Same paths requested again or in parts come out once.
+        let order = graph.destruction_order(&paths(&["tank/src", "tank/dst", "tank"]));
+        assert_eq!(6, order.len());
+        assert!(graph.is_valid_order(&order));
+    }
+
+    #[test]
+    fn cycles_are_impossible() {
+        // Edges of a real graph go from a dataset to something under it or from a snapshot to a
+        // clone, ordering all of it must satisfy every edge at once.
+        let graph = build_dependency_graph(&chain(), "tank/old").unwrap();
+        let everything = graph.destruction_order(&paths(&["tank/old", "tank/d"]));
+        assert_eq!(9, everything.len());
+        assert!(graph.is_valid_order(&everything));
+
+        // Inconsistent input still terminates.
+        let mut graph = DependencyGraph::default();
+        graph.add_clone("tank/a@1".into(), "tank/b".into());
+        graph.add_owned("tank/b".into(), "tank/a@1".into());
+        assert_eq!(2, graph.destruction_order(&paths(&["tank/b"])).len());
+    }
+
+    #[test]
+    fn list_error() {
+        struct Missing;
+        impl ZfsEngine for Missing {
+            fn list<N: Into<PathBuf>>(&self, prefix: N) -> Result<Vec<(DatasetKind, PathBuf)>> {
+                Err(Error::DatasetNotFound(prefix.into()))
+            }
+        }
+        assert_eq!(
+            Err(Error::DatasetNotFound(PathBuf::from("tank/missing"))),
+            build_dependency_graph(&Missing, "tank/missing")
+        );
+    }
+}
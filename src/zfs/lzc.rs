@@ -1,28 +1,74 @@
 use crate::{
     zfs::{
-        BookmarkRequest, Checksum, Compression, Copies, CreateDatasetRequest, DatasetKind,
-        DestroyTiming, Error, Result, SendFlags, SnapDir, ValidationError, ZfsEngine,
+        BookmarkProperties, BookmarkRequest, Checksum, Compression, Copies, CreateDatasetRequest,
+        DatasetKind, DestroyTiming, Error, LzcCapabilities, Result, SendFlags, SnapDir,
+        ValidationError, ZfsEngine,
     },
     GlobalLogger,
 };
 use cstr_argument::CStrArgument;
-use libnv::nvpair::NvList;
 use slog::Logger;
 
 use crate::zfs::{
     errors::Error::ValidationErrors,
-    properties::{AclInheritMode, AclMode, ZfsProp},
-    PathExt,
+    nv::{NvList, Value},
+    nvlist::{insert_nvlist, to_hashmap, with_nvlist, NvListDebug},
+    properties::{
+        AclInheritMode, AclMode, CaseSensitivity, Dedup, DnodeSize, LogBias, Normalization,
+        NvValueRepr, RedundantMetadata, SyncMode, ZfsProp,
+    },
+    validators, PathExt,
 };
 use std::{
-    collections::HashMap,
-    ffi::CString,
-    os::unix::io::{AsRawFd, RawFd},
-    path::PathBuf,
+    collections::{BTreeMap, HashMap},
+    ffi::{CString, OsStr},
+    os::{
+        raw::c_char,
+        unix::{
+            ffi::OsStrExt,
+            io::{AsRawFd, RawFd},
+        },
+    },
+    path::{Path, PathBuf},
     ptr::null_mut,
 };
 use zfs_core_sys as sys;
 
+/// `ZFS_ERR_IOC_ARG_UNAVAIL` from `sys/fs/zfs.h`: the kernel doesn't know one of ioctl arguments.
+const ZFS_ERR_IOC_ARG_UNAVAIL: i32 = 1030;
+
+/// `ZCP_DEFAULT_INSTRLIMIT` and `ZCP_DEFAULT_MEMLIMIT` from `sys/zcp.h`, what `zfs program` uses.
+const ZCP_DEFAULT_INSTR_LIMIT: u64 = 10_000_000;
+const ZCP_DEFAULT_MEM_LIMIT: u64 = 10 * 1024 * 1024;
+
+/// Channel program returning `{ [name] = true }` of snapshot `snap_name` of `dataset` and of
+/// every descendant that has it.
+const FIND_SNAPSHOTS_PROGRAM: &str = r#"
+local args = ...
+local found = {}
+local function walk(dataset)
+    local snapshot = dataset .. "@" .. args["snap_name"]
+    if zfs.exists(snapshot) then
+        found[snapshot] = true
+    end
+    for child in zfs.list.children(dataset) do
+        walk(child)
+    end
+end
+walk(args["dataset"])
+return found
+"#;
+
+// Snapshots of `args["dataset"]` itself mapped to their `createtxg`.
+const SNAPSHOT_TXGS_PROGRAM: &str = r#"
+local args = ...
+local found = {}
+for snapshot in zfs.list.snapshots(args["dataset"]) do
+    found[snapshot] = zfs.get_prop(snapshot, "createtxg")
+end
+return found
+"#;
+
 #[cfg(target_os = "freebsd")]
 const ECHRNG: libc::c_int = libc::ENXIO;
 #[cfg(target_os = "linux")]
@@ -36,14 +82,26 @@ pub struct ZfsLzc {
 impl ZfsLzc {
     /// Initialize libzfs_core backed ZfsEngine.
     /// If root logger is None, then StdLog drain used.
+    /// Fails with `LZCInitializationFailed` carrying errno if `libzfs_core_init` fails, e.g. when
+    /// there is no `/dev/zfs`.
     pub fn new() -> Result<Self> {
-        let errno = unsafe { sys::libzfs_core_init() };
+        ZfsLzc::with_logger(Logger::clone(GlobalLogger::get()))
+    }
+
+    /// Same as `new`, but log to a child of `logger` instead of the global logger.
+    pub fn with_logger(logger: Logger) -> Result<Self> {
+        ZfsLzc::with_init(|| unsafe { sys::libzfs_core_init() }, logger)
+    }
+
+    /// Initialize with `init` in place of `libzfs_core_init`.
+    pub(crate) fn with_init<F: FnOnce() -> i32>(init: F, logger: Logger) -> Result<Self> {
+        let errno = init();
 
         if errno != 0 {
             let io_error = std::io::Error::from_raw_os_error(errno);
             return Err(Error::LZCInitializationFailed(io_error));
         }
-        let logger = GlobalLogger::get().new(o!("zetta_module" => "zfs", "zfs_impl" => "lzc"));
+        let logger = logger.new(o!("zetta_module" => "zfs", "zfs_impl" => "lzc"));
 
         Ok(ZfsLzc { logger })
     }
@@ -52,6 +110,40 @@ impl ZfsLzc {
         &self.logger
     }
 
+    /// Which newer `libzfs_core` calls installed library has. Looked up once per process.
+    pub fn capabilities(&self) -> LzcCapabilities {
+        LzcCapabilities::get()
+    }
+
+    fn send_space(&self, snapshot: *const c_char, from: *const c_char, flags: SendFlags) -> i32 {
+        let mut space = 0u64;
+        unsafe { zfs_core_sys::lzc_send_space(snapshot, from, flags.to_lzc(), &mut space) }
+    }
+
+    /// Make sure the kernel understands send flags that older releases lack before anything is
+    /// written to the stream. `lzc_send_space` goes through the same argument checks as
+    /// `lzc_send`, so it fails the same way. If it fails with the flags and succeeds without them,
+    /// the flags are the problem. Any other failure is left for `lzc_send` to report.
+    fn probe_send_flags(
+        &self,
+        snapshot: *const c_char,
+        from: *const c_char,
+        flags: SendFlags,
+    ) -> Result<()> {
+        let probed = flags & SendFlags::probed();
+        if probed.is_empty() {
+            return Ok(());
+        }
+        match self.send_space(snapshot, from, flags) {
+            0 => Ok(()),
+            libc::ENOTSUP | ZFS_ERR_IOC_ARG_UNAVAIL => Err(Error::UnsupportedSendFlags(probed)),
+            _ if self.send_space(snapshot, from, flags - probed) == 0 => {
+                Err(Error::UnsupportedSendFlags(probed))
+            }
+            _ => Ok(()),
+        }
+    }
+
     fn send(
         &self,
         path: PathBuf,
@@ -59,27 +151,50 @@ impl ZfsLzc {
         fd: RawFd,
         flags: SendFlags,
     ) -> Result<()> {
+        if !path.is_snapshot() {
+            return Err(ValidationError::MissingSnapshotName(path).into());
+        }
         let snapshot =
             CString::new(path.to_str().unwrap()).expect("Failed to create CString from path");
         let snapshot_ptr = snapshot.as_ptr();
         let from_cstr = from.map(|f| {
             CString::new(f.to_str().unwrap()).expect("Failed to create CString from path")
         });
-        let fd_raw = fd;
-        let errno = if let Some(src) = from_cstr {
-            unsafe { zfs_core_sys::lzc_send(snapshot_ptr, src.as_ptr(), fd_raw, flags.bits) }
-        } else {
-            unsafe { zfs_core_sys::lzc_send(snapshot_ptr, std::ptr::null(), fd_raw, flags.bits) }
-        };
+        let from_ptr = from_cstr
+            .as_ref()
+            .map_or(std::ptr::null(), |src| src.as_ptr());
+        self.probe_send_flags(snapshot_ptr, from_ptr, flags)?;
+        let errno = unsafe { zfs_core_sys::lzc_send(snapshot_ptr, from_ptr, fd, flags.to_lzc()) };
 
         match errno {
             0 => Ok(()),
+            ZFS_ERR_IOC_ARG_UNAVAIL if flags.intersects(SendFlags::probed()) => {
+                Err(Error::UnsupportedSendFlags(flags & SendFlags::probed()))
+            }
             _ => {
                 let io_error = std::io::Error::from_raw_os_error(errno);
                 Err(Error::Io(io_error))
             }
         }
     }
+
+    /// Snapshots of the dataset itself sorted by `createtxg`, fetched by one channel program.
+    fn snapshots_by_create_txg(&self, dataset: PathBuf) -> Result<Vec<PathBuf>> {
+        if !self.exists(dataset.clone())? {
+            return Err(Error::DatasetNotFound(dataset));
+        }
+        let mut args = NvList::default();
+        args.insert_string("dataset", &*dataset.to_string_lossy())?;
+        let out = self.run_channel_program(
+            dataset.pool(),
+            SNAPSHOT_TXGS_PROGRAM,
+            ZCP_DEFAULT_INSTR_LIMIT,
+            ZCP_DEFAULT_MEM_LIMIT,
+            false,
+            args,
+        )?;
+        Ok(program_return_by_txg(out))
+    }
 }
 
 impl ZfsEngine for ZfsLzc {
@@ -98,89 +213,12 @@ impl ZfsEngine for ZfsLzc {
     fn create(&self, request: CreateDatasetRequest) -> Result<()> {
         request.validate()?;
 
-        //let mut props = nvpair::NvList::new()?;
-        let mut props = NvList::default();
         let name_c_string =
             CString::new(request.name().to_str().expect("Non UTF-8 name")).expect("NULL in name");
-        // LZC wants _everything_ as u64 even booleans.
-        if let Some(acl_inherit) = request.acl_inherit {
-            props.insert_u64(AclInheritMode::nv_key(), acl_inherit.as_nv_value())?;
-        }
-        if let Some(acl_mode) = request.acl_mode {
-            props.insert_u64(AclMode::nv_key(), acl_mode.as_nv_value())?;
-        }
-        if let Some(atime) = request.atime {
-            props.insert_u64("atime", bool_to_u64(atime))?;
-        }
-        if let Some(checksum) = request.checksum {
-            props.insert_u64(Checksum::nv_key(), checksum.as_nv_value())?;
-        }
-        if let Some(compression) = request.compression {
-            props.insert_u64(Compression::nv_key(), compression.as_nv_value())?;
-        }
-        if let Some(copies) = request.copies() {
-            props.insert_u64(Copies::nv_key(), copies.as_nv_value())?;
-        }
-        if let Some(devices) = request.devices {
-            props.insert_u64("devices", bool_to_u64(devices))?;
-        }
-        if let Some(exec) = request.exec {
-            props.insert_u64("exec", bool_to_u64(exec))?;
-        }
-        // saved fore mount point
-        if let Some(primary_cache) = request.primary_cache {
-            props.insert_u64("primarycache", primary_cache.as_nv_value())?;
-        }
-        if let Some(quota) = request.quota {
-            props.insert_u64("quota", quota)?;
-        }
-        if let Some(readonly) = request.readonly {
-            props.insert_u64("readonly", bool_to_u64(readonly))?;
-        }
-        if let Some(record_size) = request.record_size {
-            props.insert_u64("recordsize", record_size)?;
-        }
-        if let Some(ref_quota) = request.ref_quota {
-            props.insert_u64("refquota", ref_quota)?;
-        }
-        if let Some(ref_reservation) = request.ref_reservation {
-            props.insert_u64("refreservation", ref_reservation)?;
-        }
-        if let Some(secondary_cache) = request.secondary_cache {
-            props.insert_u64("secondarycache", secondary_cache.as_nv_value())?;
-        }
-        if let Some(setuid) = request.setuid {
-            props.insert_u64("setuid", bool_to_u64(setuid))?;
-        }
-        if let Some(snap_dir) = request.snap_dir {
-            props.insert_u64(SnapDir::nv_key(), snap_dir.as_nv_value())?;
-        }
-
-        if request.kind == DatasetKind::Filesystem
-            && (request.volume_size.is_some() || request.volume_block_size.is_some())
-        {
-            return Err(Error::invalid_input());
-        }
-
-        if request.kind == DatasetKind::Volume && request.volume_size.is_none() {
-            return Err(Error::invalid_input());
-        }
-
-        if let Some(vol_size) = request.volume_size {
-            props.insert_u64("volsize", vol_size)?;
-        }
-        if let Some(vol_block_size) = request.volume_block_size {
-            props.insert_u64("volblocksize", vol_block_size)?;
-        }
-
-        if let Some(xattr) = request.xattr {
-            props.insert("xattr", bool_to_u64(xattr))?;
-        }
-        if let Some(user_props) = request.user_properties() {
-            for (key, value) in user_props {
-                props.insert_string(key.as_str(), value.as_str())?;
-            }
-        }
+        let props = create_props(&request)?;
+        debug!(self.logger, "lzc_create";
+            "name" => format_args!("{:?}", name_c_string),
+            "props" => format_args!("{:?}", NvListDebug(&props)));
         let errno = unsafe {
             zfs_core_sys::lzc_create(
                 name_c_string.as_ref().as_ptr(),
@@ -193,10 +231,7 @@ impl ZfsEngine for ZfsLzc {
 
         match errno {
             0 => Ok(()),
-            _ => {
-                let io_error = std::io::Error::from_raw_os_error(errno);
-                Err(Error::Io(io_error))
-            }
+            _ => Err(create_error(request.name(), errno)),
         }
     }
 
@@ -205,14 +240,7 @@ impl ZfsEngine for ZfsLzc {
         snapshots: &[PathBuf],
         user_properties: Option<HashMap<String, String>>,
     ) -> Result<()> {
-        let validation_errors: Vec<ValidationError> = snapshots
-            .iter()
-            .map(PathBuf::validate)
-            .filter_map(Result::err)
-            .collect();
-        if !validation_errors.is_empty() {
-            return Err(ValidationErrors(validation_errors));
-        }
+        validators::validate_snapshot_batch(snapshots).map_err(ValidationErrors)?;
 
         let mut snapshots_list = NvList::default();
         let mut props = NvList::default();
@@ -225,6 +253,9 @@ impl ZfsEngine for ZfsLzc {
                 props.insert_string(key.as_str(), value.as_str())?;
             }
         }
+        debug!(self.logger, "lzc_snapshot";
+            "snapshots" => format_args!("{:?}", NvListDebug(&snapshots_list)),
+            "props" => format_args!("{:?}", NvListDebug(&props)));
         let errno = unsafe {
             zfs_core_sys::lzc_snapshot(
                 snapshots_list.as_ptr(),
@@ -235,7 +266,7 @@ impl ZfsEngine for ZfsLzc {
         if !errors_list_ptr.is_null() {
             let errors = unsafe { NvList::from_ptr(errors_list_ptr) };
             if !errors.is_empty() {
-                return Err(Error::from(errors.into_hashmap()));
+                return Err(snapshot_error(errlist_to_vec(errors)));
             }
         }
         match errno {
@@ -248,15 +279,7 @@ impl ZfsEngine for ZfsLzc {
     }
 
     fn bookmark(&self, bookmarks: &[BookmarkRequest]) -> Result<()> {
-        let validation_errors: Vec<ValidationError> = bookmarks
-            .iter()
-            .flat_map(|BookmarkRequest { snapshot, bookmark }| vec![snapshot, bookmark])
-            .map(PathBuf::validate)
-            .filter_map(Result::err)
-            .collect();
-        if !validation_errors.is_empty() {
-            return Err(ValidationErrors(validation_errors));
-        }
+        validators::validate_bookmark_batch(bookmarks).map_err(ValidationErrors)?;
 
         let mut bookmarks_list = NvList::default();
         for BookmarkRequest { snapshot, bookmark } in bookmarks {
@@ -267,6 +290,8 @@ impl ZfsEngine for ZfsLzc {
         }
 
         let mut errors_list_ptr = null_mut();
+        debug!(self.logger, "lzc_bookmark";
+            "bookmarks" => format_args!("{:?}", NvListDebug(&bookmarks_list)));
         let errno =
             unsafe { zfs_core_sys::lzc_bookmark(bookmarks_list.as_ptr(), &mut errors_list_ptr) };
         if !errors_list_ptr.is_null() {
@@ -302,6 +327,8 @@ impl ZfsEngine for ZfsLzc {
         }
 
         let mut errors_list_ptr = null_mut();
+        debug!(self.logger, "lzc_destroy_snaps";
+            "snapshots" => format_args!("{:?}", NvListDebug(&snapshots_list)));
         let errno = unsafe {
             zfs_core_sys::lzc_destroy_snaps(
                 snapshots_list.as_ptr(),
@@ -312,7 +339,75 @@ impl ZfsEngine for ZfsLzc {
         if !errors_list_ptr.is_null() {
             let errors = unsafe { NvList::from_ptr(errors_list_ptr) };
             if !errors.is_empty() {
-                return Err(Error::from(errors.into_hashmap()));
+                return Err(Error::DestroySnapshotsFailed(errlist_to_vec(errors)));
+            }
+        }
+        match errno {
+            0 => Ok(()),
+            _ => {
+                let io_error = std::io::Error::from_raw_os_error(errno);
+                Err(Error::Io(io_error))
+            }
+        }
+    }
+
+    /// Descendants are found with a read-only channel program, then every snapshot is destroyed
+    /// with one `lzc_destroy_snaps`. Fails with `DatasetNotFound` if none of them has it.
+    fn destroy_snapshot_recursive<N: Into<PathBuf>>(
+        &self,
+        dataset: N,
+        snap_name: &str,
+    ) -> Result<()> {
+        let dataset = dataset.into();
+        let snapshot = validators::validate_recursive_snapshot(&dataset, snap_name)?;
+        if !self.exists(dataset.clone())? {
+            return Err(Error::DatasetNotFound(dataset));
+        }
+        let mut args = NvList::default();
+        args.insert_string("dataset", &*dataset.to_string_lossy())?;
+        args.insert_string("snap_name", snap_name)?;
+        let out = self.run_channel_program(
+            dataset.pool(),
+            FIND_SNAPSHOTS_PROGRAM,
+            ZCP_DEFAULT_INSTR_LIMIT,
+            ZCP_DEFAULT_MEM_LIMIT,
+            false,
+            args,
+        )?;
+        let snapshots = program_return_keys(out);
+        if snapshots.is_empty() {
+            return Err(Error::DatasetNotFound(snapshot));
+        }
+        self.destroy_snapshots(&snapshots, DestroyTiming::RightNow)
+    }
+
+    fn latest_snapshot<N: Into<PathBuf>>(&self, dataset: N) -> Result<Option<PathBuf>> {
+        Ok(self.snapshots_by_create_txg(dataset.into())?.pop())
+    }
+
+    fn oldest_snapshot<N: Into<PathBuf>>(&self, dataset: N) -> Result<Option<PathBuf>> {
+        Ok(self
+            .snapshots_by_create_txg(dataset.into())?
+            .into_iter()
+            .next())
+    }
+
+    fn hold(&self, holds: &[(PathBuf, String)]) -> Result<()> {
+        validate_holds(holds)?;
+
+        let mut holds_list = NvList::default();
+        for (snap, tag) in holds {
+            holds_list.insert_string(&*snap.to_string_lossy(), tag.as_str())?;
+        }
+
+        let mut errors_list_ptr = null_mut();
+        debug!(self.logger, "lzc_hold"; "holds" => format_args!("{:?}", NvListDebug(&holds_list)));
+        let errno =
+            unsafe { zfs_core_sys::lzc_hold(holds_list.as_ptr(), -1, &mut errors_list_ptr) };
+        if !errors_list_ptr.is_null() {
+            let errors = unsafe { NvList::from_ptr(errors_list_ptr) };
+            if !errors.is_empty() {
+                return Err(Error::HoldsFailed(errlist_to_vec(errors)));
             }
         }
         match errno {
@@ -324,6 +419,57 @@ impl ZfsEngine for ZfsLzc {
         }
     }
 
+    fn release(&self, holds: &[(PathBuf, String)]) -> Result<()> {
+        validate_holds(holds)?;
+
+        let holds_list = release_list(holds)?;
+
+        let mut errors_list_ptr = null_mut();
+        debug!(self.logger, "lzc_release";
+            "holds" => format_args!("{:?}", NvListDebug(&holds_list)));
+        let errno = unsafe { zfs_core_sys::lzc_release(holds_list.as_ptr(), &mut errors_list_ptr) };
+        if !errors_list_ptr.is_null() {
+            let errors = unsafe { NvList::from_ptr(errors_list_ptr) };
+            if !errors.is_empty() {
+                return Err(Error::ReleaseFailed(errlist_to_vec(errors)));
+            }
+        }
+        match errno {
+            0 => Ok(()),
+            _ => {
+                let io_error = std::io::Error::from_raw_os_error(errno);
+                Err(Error::Io(io_error))
+            }
+        }
+    }
+
+    fn holds<N: Into<PathBuf>>(&self, snapshot: N) -> Result<Vec<String>> {
+        let snapshot = snapshot.into();
+        validators::validate_snapshot_name(&snapshot)?;
+        let snapshot_c_string = snapshot
+            .to_str()
+            .ok_or_else(|| Error::NonUtf8(snapshot.as_os_str().as_bytes().to_vec()))?
+            .into_cstr();
+
+        let mut holds_list_ptr = null_mut();
+        let errno = unsafe {
+            zfs_core_sys::lzc_get_holds(snapshot_c_string.as_ref().as_ptr(), &mut holds_list_ptr)
+        };
+        match errno {
+            0 => {
+                // Tag to timestamp of when hold was placed.
+                let holds = unsafe { NvList::from_ptr(holds_list_ptr) };
+                let mut ret: Vec<String> = holds.into_hashmap().into_keys().collect();
+                ret.sort();
+                Ok(ret)
+            }
+            _ => {
+                let io_error = std::io::Error::from_raw_os_error(errno);
+                Err(Error::Io(io_error))
+            }
+        }
+    }
+
     fn destroy_bookmarks(&self, bookmarks: &[PathBuf]) -> Result<()> {
         let validation_errors: Vec<ValidationError> = bookmarks
             .iter()
@@ -342,13 +488,15 @@ impl ZfsEngine for ZfsLzc {
         }
 
         let mut errors_list_ptr = null_mut();
+        debug!(self.logger, "lzc_destroy_bookmarks";
+            "bookmarks" => format_args!("{:?}", NvListDebug(&bookmarks_list)));
         let errno = unsafe {
             zfs_core_sys::lzc_destroy_bookmarks(bookmarks_list.as_ptr(), &mut errors_list_ptr)
         };
         if !errors_list_ptr.is_null() {
             let errors = unsafe { NvList::from_ptr(errors_list_ptr) };
             if !errors.is_empty() {
-                return Err(Error::from(errors.into_hashmap()));
+                return Err(Error::DestroyBookmarksFailed(errlist_to_vec(errors)));
             }
         }
         match errno {
@@ -360,6 +508,47 @@ impl ZfsEngine for ZfsLzc {
         }
     }
 
+    fn list_bookmarks_detailed<N: Into<PathBuf>>(
+        &self,
+        dataset: N,
+    ) -> Result<Vec<BookmarkProperties>> {
+        let dataset = dataset.into();
+        dataset.validate()?;
+        let dataset_c_string = dataset
+            .to_str()
+            .ok_or_else(|| Error::NonUtf8(dataset.as_os_str().as_bytes().to_vec()))?
+            .into_cstr();
+
+        let mut props = NvList::default();
+        props.insert_boolean("guid")?;
+        props.insert_boolean("createtxg")?;
+        props.insert_boolean("creation")?;
+
+        let mut bookmarks_list_ptr = null_mut();
+        debug!(self.logger, "lzc_get_bookmarks";
+            "dataset" => format_args!("{:?}", dataset),
+            "props" => format_args!("{:?}", NvListDebug(&props)));
+        let errno = unsafe {
+            zfs_core_sys::lzc_get_bookmarks(
+                dataset_c_string.as_ref().as_ptr(),
+                props.as_ptr(),
+                &mut bookmarks_list_ptr,
+            )
+        };
+        match errno {
+            0 => {
+                let bookmarks = unsafe { NvList::from_ptr(bookmarks_list_ptr) };
+                let mut ret = bookmarks_from_nvlist(&dataset, &bookmarks)?;
+                ret.sort_by(|a, b| a.name().cmp(b.name()));
+                Ok(ret)
+            }
+            _ => {
+                let io_error = std::io::Error::from_raw_os_error(errno);
+                Err(Error::Io(io_error))
+            }
+        }
+    }
+
     fn send_full<N: Into<PathBuf>, FD: AsRawFd>(
         &self,
         path: N,
@@ -393,6 +582,10 @@ impl ZfsEngine for ZfsLzc {
         let prog_c_string = program.into_cstr();
 
         let mut out_nvlist_ptr = null_mut();
+        debug!(self.logger, "lzc_channel_program";
+            "pool" => format_args!("{:?}", pool),
+            "sync" => sync,
+            "args" => format_args!("{:?}", NvListDebug(&args)));
         let errno = unsafe {
             if sync {
                 zfs_core_sys::lzc_channel_program(
@@ -438,3 +631,505 @@ fn bool_to_u64(src: bool) -> u64 {
         0
     }
 }
+
+/// Properties of the new dataset the way `lzc_create` wants them.
+fn create_props(request: &CreateDatasetRequest) -> Result<NvList> {
+    let mut props = NvList::default();
+    // LZC wants _everything_ as u64 even booleans.
+    if let Some(acl_inherit) = request.acl_inherit {
+        insert_prop(
+            &mut props,
+            AclInheritMode::nv_key(),
+            acl_inherit.as_nv_value(),
+        )?;
+    }
+    if let Some(acl_mode) = request.acl_mode {
+        insert_prop(&mut props, AclMode::nv_key(), acl_mode.as_nv_value())?;
+    }
+    if let Some(atime) = request.atime {
+        props.insert_u64("atime", bool_to_u64(atime))?;
+    }
+    if let Some(case_sensitivity) = request.case_sensitivity {
+        insert_prop(
+            &mut props,
+            CaseSensitivity::nv_key(),
+            case_sensitivity.as_nv_value(),
+        )?;
+    }
+    if let Some(checksum) = request.checksum {
+        insert_prop(&mut props, Checksum::nv_key(), checksum.as_nv_value())?;
+    }
+    if let Some(compression) = request.compression {
+        insert_prop(&mut props, Compression::nv_key(), compression.as_nv_value())?;
+    }
+    if let Some(copies) = request.copies() {
+        insert_prop(&mut props, Copies::nv_key(), copies.as_nv_value())?;
+    }
+    if let Some(dedup) = request.dedup {
+        insert_prop(&mut props, Dedup::nv_key(), dedup.as_nv_value())?;
+    }
+    if let Some(devices) = request.devices {
+        props.insert_u64("devices", bool_to_u64(devices))?;
+    }
+    if let Some(dnode_size) = request.dnode_size {
+        insert_prop(&mut props, DnodeSize::nv_key(), dnode_size.as_nv_value())?;
+    }
+    if let Some(exec) = request.exec {
+        props.insert_u64("exec", bool_to_u64(exec))?;
+    }
+    if let Some(log_bias) = request.log_bias {
+        insert_prop(&mut props, LogBias::nv_key(), log_bias.as_nv_value())?;
+    }
+    if let Some(normalization) = request.normalization {
+        insert_prop(
+            &mut props,
+            Normalization::nv_key(),
+            normalization.as_nv_value(),
+        )?;
+    }
+    // saved fore mount point
+    if let Some(primary_cache) = request.primary_cache {
+        insert_prop(&mut props, "primarycache", primary_cache.as_nv_value())?;
+    }
+    if let Some(quota) = request.quota {
+        props.insert_u64("quota", quota.as_u64())?;
+    }
+    if let Some(readonly) = request.readonly {
+        props.insert_u64("readonly", bool_to_u64(readonly))?;
+    }
+    if let Some(record_size) = request.record_size {
+        props.insert_u64("recordsize", record_size.as_u64())?;
+    }
+    if let Some(redundant_metadata) = request.redundant_metadata {
+        insert_prop(
+            &mut props,
+            RedundantMetadata::nv_key(),
+            redundant_metadata.as_nv_value(),
+        )?;
+    }
+    if let Some(ref_quota) = request.ref_quota {
+        props.insert_u64("refquota", ref_quota.as_u64())?;
+    }
+    if let Some(ref_reservation) = request.ref_reservation {
+        props.insert_u64("refreservation", ref_reservation.as_u64())?;
+    }
+    if let Some(reservation) = request.reservation {
+        props.insert_u64("reservation", reservation.as_u64())?;
+    }
+    if let Some(secondary_cache) = request.secondary_cache {
+        insert_prop(&mut props, "secondarycache", secondary_cache.as_nv_value())?;
+    }
+    if let Some(setuid) = request.setuid {
+        props.insert_u64("setuid", bool_to_u64(setuid))?;
+    }
+    if let Some(snap_dir) = request.snap_dir {
+        insert_prop(&mut props, SnapDir::nv_key(), snap_dir.as_nv_value())?;
+    }
+    if let Some(sync) = request.sync {
+        insert_prop(&mut props, SyncMode::nv_key(), sync.as_nv_value())?;
+    }
+    if let Some(utf8_only) = request.utf8_only {
+        props.insert_u64("utf8only", bool_to_u64(utf8_only))?;
+    }
+
+    if request.kind == DatasetKind::Filesystem
+        && (request.volume_size.is_some() || request.volume_block_size.is_some())
+    {
+        return Err(Error::invalid_input());
+    }
+
+    if request.kind == DatasetKind::Volume && request.volume_size.is_none() {
+        return Err(Error::invalid_input());
+    }
+
+    if let Some(vol_size) = request.volume_size {
+        props.insert_u64("volsize", vol_size.as_u64())?;
+    }
+    if let Some(vol_block_size) = request.volume_block_size {
+        props.insert_u64("volblocksize", vol_block_size.as_u64())?;
+    }
+
+    if let Some(xattr) = request.xattr {
+        props.insert("xattr", bool_to_u64(xattr))?;
+    }
+    if let Some(user_props) = request.user_properties() {
+        for (key, value) in user_props {
+            props.insert_string(key.as_str(), value.as_str())?;
+        }
+    }
+    Ok(props)
+}
+
+/// Holds to release the way `lzc_release` wants them: tags of every snapshot in a nested list.
+fn release_list(holds: &[(PathBuf, String)]) -> Result<NvList> {
+    let mut tags: BTreeMap<&PathBuf, NvList> = BTreeMap::new();
+    for (snap, tag) in holds {
+        tags.entry(snap).or_default().insert_boolean(tag.as_str())?;
+    }
+    let mut holds_list = NvList::default();
+    for (snap, snap_tags) in &tags {
+        insert_nvlist(&mut holds_list, &snap.to_string_lossy(), snap_tags)?;
+    }
+    Ok(holds_list)
+}
+
+fn insert_prop(props: &mut NvList, key: &str, value: NvValueRepr) -> Result<()> {
+    match value {
+        NvValueRepr::Index(index) => props.insert_u64(key, index)?,
+        NvValueRepr::String(value) => props.insert_string(key, value.as_str())?,
+    }
+    Ok(())
+}
+
+fn validate_holds(holds: &[(PathBuf, String)]) -> Result<()> {
+    let snapshots: Vec<PathBuf> = holds.iter().map(|(snap, _)| snap.clone()).collect();
+    let mut validation_errors: Vec<ValidationError> = snapshots
+        .iter()
+        .map(validators::validate_snapshot_name)
+        .filter_map(Result::err)
+        .collect();
+    if let Err(e) = validators::validate_same_pool(&snapshots) {
+        validation_errors.push(e);
+    }
+    if validation_errors.is_empty() {
+        Ok(())
+    } else {
+        Err(ValidationErrors(validation_errors))
+    }
+}
+
+/// Error of `lzc_create` of `name`. `EDQUOT` means `filesystem_limit` of an ancestor is reached.
+fn create_error(name: &Path, errno: i32) -> Error {
+    match errno {
+        libc::EDQUOT => Error::FilesystemLimitExceeded {
+            dataset: name.to_path_buf(),
+        },
+        _ => Error::Io(std::io::Error::from_raw_os_error(errno)),
+    }
+}
+
+/// Error of `lzc_snapshot` from its failures. `EDQUOT` on every snapshot means `snapshot_limit`
+/// is reached, anything else is reported as is.
+fn snapshot_error(failures: Vec<(PathBuf, i32)>) -> Error {
+    match failures.first() {
+        Some((dataset, _)) if failures.iter().all(|(_, errno)| *errno == libc::EDQUOT) => {
+            Error::SnapshotLimitExceeded {
+                dataset: dataset.clone(),
+            }
+        }
+        _ => Error::SnapshotsFailed(failures),
+    }
+}
+
+// `errlist` returned by libzfs_core maps name of each failed dataset to errno. Sorted by name.
+// "N_MORE_ERRORS" is skipped: it's a count of errors that didn't fit, not a dataset.
+#[allow(clippy::wildcard_enum_match_arm)]
+fn errlist_to_vec(errors: NvList) -> Vec<(PathBuf, i32)> {
+    let mut ret: Vec<(PathBuf, i32)> = errors
+        .into_hashmap()
+        .into_iter()
+        .filter(|(name, _)| name != "N_MORE_ERRORS")
+        .map(|(name, value)| {
+            let errno = match value {
+                Value::Int32(errno) => errno,
+                _ => 0,
+            };
+            (PathBuf::from(name), errno)
+        })
+        .collect();
+    ret.sort();
+    ret
+}
+
+/// Table returned by a channel program, it comes as a list nested under "return".
+fn program_return(out: NvList) -> HashMap<String, Value> {
+    out.iter()
+        .find(|pair| pair.key().to_bytes() == b"return")
+        .and_then(|pair| with_nvlist(&pair, to_hashmap))
+        .unwrap_or_default()
+}
+
+/// Keys of the table returned by a channel program, sorted.
+fn program_return_keys(out: NvList) -> Vec<PathBuf> {
+    let mut ret: Vec<PathBuf> = program_return(out).into_keys().map(PathBuf::from).collect();
+    ret.sort();
+    ret
+}
+
+// Channel programs return Lua integers as int64, while properties are uint64 everywhere else.
+#[allow(clippy::wildcard_enum_match_arm)]
+fn program_return_by_txg(out: NvList) -> Vec<PathBuf> {
+    let mut ret: Vec<(u64, PathBuf)> = program_return(out)
+        .into_iter()
+        .filter_map(|(name, txg)| match txg {
+            Value::Int64(txg) => Some((txg as u64, PathBuf::from(name))),
+            Value::Uint64(txg) => Some((txg, PathBuf::from(name))),
+            _ => None,
+        })
+        .collect();
+    ret.sort();
+    ret.into_iter().map(|(_, name)| name).collect()
+}
+
+/// Bookmarks returned by `lzc_get_bookmarks`: bookmark name to a list of its properties.
+fn bookmarks_from_nvlist(dataset: &Path, bookmarks: &NvList) -> Result<Vec<BookmarkProperties>> {
+    bookmarks
+        .iter()
+        .map(|pair| {
+            let name = OsStr::from_bytes(pair.key().to_bytes());
+            let props = with_nvlist(&pair, bookmark_prop_values).unwrap_or_default();
+            bookmark_properties(dataset, name, props)
+        })
+        .collect()
+}
+
+// Every property returned by `lzc_get_bookmarks` is wrapped into nvlist with a single "value" key.
+fn bookmark_prop_values(props: &NvList) -> HashMap<String, Value> {
+    props
+        .iter()
+        .map(|pair| {
+            let value = with_nvlist(&pair, |wrapped| {
+                wrapped
+                    .iter()
+                    .find(|inner| inner.key().to_bytes() == b"value")
+                    .map(|inner| inner.value())
+            })
+            .flatten()
+            .unwrap_or_else(|| pair.value());
+            (pair.key().to_string_lossy().into_owned(), value)
+        })
+        .collect()
+}
+
+#[allow(clippy::wildcard_enum_match_arm)]
+fn bookmark_properties(
+    dataset: &Path,
+    name: &OsStr,
+    props: HashMap<String, Value>,
+) -> Result<BookmarkProperties> {
+    let mut full_name = dataset.as_os_str().to_owned();
+    full_name.push("#");
+    full_name.push(name);
+    let mut properties = BookmarkProperties::builder(PathBuf::from(full_name));
+    properties.creation(0);
+
+    for (key, value) in props {
+        match (key.as_str(), value) {
+            ("guid", Value::Uint64(guid)) => {
+                properties.guid(Some(guid));
+            }
+            ("createtxg", Value::Uint64(txg)) => {
+                properties.create_txg(Some(txg));
+            }
+            ("creation", Value::Uint64(creation)) => {
+                properties.creation(creation as i64);
+            }
+            (_, Value::Uint64(value)) => properties.insert_unknown_property(key, value.to_string()),
+            (_, Value::String(value)) => properties.insert_unknown_property(key, value),
+            (_, value) => properties.insert_unknown_property(key, format!("{:?}", value)),
+        }
+    }
+    properties
+        .build()
+        .map_err(|err| Error::UnknownSoFar(err.to_string()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        bookmarks_from_nvlist, create_error, create_props, program_return_by_txg,
+        program_return_keys, release_list, snapshot_error,
+    };
+    use crate::zfs::{
+        nv::{NvList, Value},
+        nvlist::{insert_nvlist, with_nvlist},
+        BookmarkProperties, ByteSize, Compression, CreateDatasetRequest, DatasetKind, Error,
+    };
+    use std::{
+        collections::HashMap,
+        path::{Path, PathBuf},
+    };
+
+    #[test]
+    fn create_props_of_volume() {
+        let mut user_properties = HashMap::new();
+        user_properties.insert(String::from("org.freebsd:swap"), String::from("on"));
+        let request = CreateDatasetRequest::builder()
+            .name(PathBuf::from("tank/swap"))
+            .user_properties(user_properties)
+            .kind(DatasetKind::Volume)
+            .volume_size(ByteSize::gib(4))
+            .compression(Compression::LZ4)
+            .readonly(true)
+            .build()
+            .unwrap();
+        let props = create_props(&request).unwrap();
+        assert_eq!(4 << 30, props.get_u64("volsize").unwrap());
+        assert_eq!(1, props.get_u64("readonly").unwrap());
+        assert_eq!("on", props.get_str("org.freebsd:swap").unwrap());
+        assert!(props.exists("compression").unwrap());
+        assert!(!props.exists("volblocksize").unwrap());
+    }
+
+    #[test]
+    fn create_props_of_creation_only_properties() {
+        use crate::zfs::properties::{CaseSensitivity, Dedup, Normalization, SyncMode};
+
+        let request = CreateDatasetRequest::builder()
+            .name(PathBuf::from("tank/smb"))
+            .kind(DatasetKind::Filesystem)
+            .case_sensitivity(CaseSensitivity::Insensitive)
+            .normalization(Normalization::FormD)
+            .utf8_only(true)
+            .dedup(Dedup::VerifySHA256)
+            .sync(SyncMode::Always)
+            .build()
+            .unwrap();
+        let props = create_props(&request).unwrap();
+        assert_eq!(1, props.get_u64("casesensitivity").unwrap());
+        assert_eq!(0x10, props.get_u64("normalization").unwrap());
+        assert_eq!(1, props.get_u64("utf8only").unwrap());
+        assert_eq!(8 | 1 << 8, props.get_u64("dedup").unwrap());
+        assert_eq!(1, props.get_u64("sync").unwrap());
+        assert!(!props.exists("logbias").unwrap());
+        assert!(!props.exists("dnodesize").unwrap());
+    }
+
+    #[test]
+    fn create_props_of_invalid_request() {
+        let request = CreateDatasetRequest::builder()
+            .name(PathBuf::from("tank/data"))
+            .kind(DatasetKind::Filesystem)
+            .volume_size(ByteSize::gib(4))
+            .build()
+            .unwrap();
+        let err = create_props(&request).unwrap_err();
+        assert_eq!(Error::invalid_input(), err);
+    }
+
+    #[test]
+    fn release_list_groups_tags() {
+        let holds = vec![
+            (PathBuf::from("tank/b@snap"), String::from("backup")),
+            (PathBuf::from("tank/a@snap"), String::from("backup")),
+            (PathBuf::from("tank/a@snap"), String::from("replication")),
+        ];
+        let list = release_list(&holds).unwrap();
+        let names: Vec<String> = list
+            .iter()
+            .map(|pair| pair.key().to_string_lossy().into_owned())
+            .collect();
+        assert_eq!(vec!["tank/a@snap", "tank/b@snap"], names);
+
+        let tags = with_nvlist(&list.iter().next().unwrap(), |tags| {
+            tags.iter()
+                .map(|pair| (pair.key().to_string_lossy().into_owned(), pair.value()))
+                .collect::<Vec<_>>()
+        });
+        let expected = vec![
+            (String::from("backup"), Value::Bool(true)),
+            (String::from("replication"), Value::Bool(true)),
+        ];
+        assert_eq!(Some(expected), tags);
+    }
+
+    #[test]
+    fn snapshots_found_by_program() {
+        let mut found = NvList::default();
+        found.insert("tank/a/b@daily", true).unwrap();
+        found.insert("tank/a@daily", true).unwrap();
+        let mut out = NvList::default();
+        insert_nvlist(&mut out, "return", &found).unwrap();
+        assert_eq!(
+            vec![
+                PathBuf::from("tank/a/b@daily"),
+                PathBuf::from("tank/a@daily")
+            ],
+            program_return_keys(out)
+        );
+        assert!(program_return_keys(NvList::default()).is_empty());
+    }
+
+    #[test]
+    fn snapshots_sorted_by_program_txg() {
+        let mut found = NvList::default();
+        found.insert("tank/a@c", 30i64).unwrap();
+        found.insert("tank/a@a", 110i64).unwrap();
+        found.insert("tank/a@b", 20u64).unwrap();
+        let mut out = NvList::default();
+        insert_nvlist(&mut out, "return", &found).unwrap();
+        assert_eq!(
+            vec![
+                PathBuf::from("tank/a@b"),
+                PathBuf::from("tank/a@c"),
+                PathBuf::from("tank/a@a")
+            ],
+            program_return_by_txg(out)
+        );
+        assert!(program_return_by_txg(NvList::default()).is_empty());
+    }
+
+    #[test]
+    fn bookmarks_with_wrapped_values() {
+        let mut props = NvList::default();
+        for (key, value) in &[
+            ("guid", 42u64),
+            ("createtxg", 7),
+            ("creation", 1_565_321_370),
+        ] {
+            let mut wrapped = NvList::default();
+            wrapped.insert_u64("value", *value).unwrap();
+            insert_nvlist(&mut props, key, &wrapped).unwrap();
+        }
+        let mut wrapped = NvList::default();
+        wrapped.insert_string("value", "weekly").unwrap();
+        insert_nvlist(&mut props, "org.example:kind", &wrapped).unwrap();
+        let mut bookmarks = NvList::default();
+        insert_nvlist(&mut bookmarks, "daily", &props).unwrap();
+
+        let mut expected = BookmarkProperties::builder(PathBuf::from("tank/data#daily"));
+        expected
+            .guid(Some(42))
+            .create_txg(Some(7))
+            .creation(1_565_321_370)
+            .insert_unknown_property("org.example:kind".into(), "weekly".into());
+        assert_eq!(
+            vec![expected.build().unwrap()],
+            bookmarks_from_nvlist(Path::new("tank/data"), &bookmarks).unwrap()
+        );
+        assert!(
+            bookmarks_from_nvlist(Path::new("tank/data"), &NvList::default())
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn limits_from_errno() {
+        let dataset = PathBuf::from("tank/tenant/new");
+        let expected = Error::FilesystemLimitExceeded {
+            dataset: dataset.clone(),
+        };
+        assert_eq!(expected, create_error(&dataset, libc::EDQUOT));
+        assert_eq!(
+            Error::Io(std::io::Error::from_raw_os_error(libc::ENOSPC)).kind(),
+            create_error(&dataset, libc::ENOSPC).kind()
+        );
+
+        let first = PathBuf::from("tank/tenant@a");
+        let second = PathBuf::from("tank/tenant/fs@a");
+        let expected = Error::SnapshotLimitExceeded {
+            dataset: first.clone(),
+        };
+        let failures = vec![
+            (first.clone(), libc::EDQUOT),
+            (second.clone(), libc::EDQUOT),
+        ];
+        assert_eq!(expected, snapshot_error(failures));
+        let failures = vec![(first, libc::EDQUOT), (second, libc::EEXIST)];
+        assert_eq!(
+            Error::SnapshotsFailed(failures.clone()),
+            snapshot_error(failures)
+        );
+    }
+}
@@ -1,24 +1,59 @@
-use std::{os::unix::io::AsRawFd, path::PathBuf};
+use std::{
+    ffi::OsString,
+    os::unix::io::AsRawFd,
+    path::{Path, PathBuf},
+    time::Duration,
+};
 
 use bitflags::bitflags;
+use strum_macros::{Display, EnumString};
 
 pub mod description;
 pub use description::DatasetKind;
 
+mod bytesize;
+pub use bytesize::ByteSize;
+pub mod be;
+pub mod cached;
+pub mod capabilities;
+pub use cached::CachedZfsEngine;
+pub use capabilities::LzcCapabilities;
 pub mod delegating;
 pub use delegating::DelegatingZfsEngine;
+pub mod delegation;
+pub use delegation::{Delegation, DelegationGrant, DelegationScope, Permission, Principal};
+pub mod graph;
 pub mod open3;
 pub use open3::ZfsOpen3;
 
 pub mod lzc;
-use crate::zfs::properties::{AclInheritMode, AclMode};
+pub mod naming;
+#[cfg(feature = "nv-fake")]
+pub mod nv_fake;
+pub mod nvlist;
+pub mod orchestration;
+use crate::zfs::properties::{
+    AclInheritMode, AclMode, CaseSensitivity, Dedup, DnodeSize, LogBias, Normalization,
+    RedundantMetadata, SyncMode, VolumeMode,
+};
+/// nvlist types used by this crate: `libnv::nvpair`, or its in-memory fake with `nv-fake`
+/// feature.
+#[cfg(not(feature = "nv-fake"))]
+pub use libnv::nvpair as nv;
 pub use lzc::ZfsLzc;
+#[cfg(feature = "nv-fake")]
+pub use nv_fake as nv;
 use std::collections::HashMap;
 
 pub mod properties;
+pub mod replication;
+pub mod retention;
+pub mod stream;
+pub mod watch;
+mod zvol;
 pub use properties::{
-    CacheMode, CanMount, Checksum, Compression, Copies, FilesystemProperties, Properties, SnapDir,
-    VolumeProperties,
+    BookmarkProperties, CacheMode, CanMount, Checksum, Compression, Copies, FilesystemProperties,
+    Properties, PropertySource, PropertyValue, ShareOptions, SnapDev, SnapDir, VolumeProperties,
 };
 
 mod pathext;
@@ -32,13 +67,18 @@ pub use errors::{Error, ErrorKind, Result, ValidationError, ValidationResult};
 
 /// Whether to mark busy snapshots for deferred destruction rather than immediately failing if can't
 /// be destroyed right now.
-#[derive(Clone, PartialEq, Eq, Debug)]
+/// Displayed as `right-now` and `defer`, parsing ignores case and also takes `now` and
+/// `deferred`.
+#[derive(Clone, PartialEq, Eq, Debug, Display, EnumString)]
+#[strum(ascii_case_insensitive)]
 pub enum DestroyTiming {
     /// If a snapshot has user holds or clones, destroy operation will fail and none of the
     /// snapshots will be destroyed.
+    #[strum(to_string = "right-now", serialize = "now")]
     RightNow,
     /// If a snapshot has user holds or clones, it will be marked for deferred destruction, and
     /// will be destroyed when the last hold or clone is removed/destroyed.
+    #[strum(to_string = "defer", serialize = "deferred")]
     Defer,
 }
 
@@ -51,6 +91,34 @@ impl DestroyTiming {
     }
 }
 
+/// What happened to a snapshot after
+/// [`destroy_snapshots_verbose`](trait.ZfsEngine.html#method.destroy_snapshots_verbose).
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum DestroyOutcome {
+    /// Snapshot is gone.
+    Destroyed,
+    /// Snapshot is marked for deferred destruction. Contains tags of user holds keeping it
+    /// around. Empty if only clones are in the way.
+    Deferred { holds: Vec<String> },
+    /// Snapshot wasn't destroyed. Contains errno. Destruction is atomic, so snapshots that were
+    /// fine are reported with `ECANCELED` when any other snapshot failed.
+    Failed(i32),
+}
+
+/// Snapshot marked for deferred destruction and what keeps it around, see
+/// [`reap_deferred`](trait.ZfsEngine.html#method.reap_deferred). Snapshot is destroyed as soon
+/// as both lists are empty.
+#[derive(Clone, PartialEq, Eq, Debug, Getters)]
+#[get = "pub"]
+pub struct DeferredSnapshot {
+    /// Full name of the snapshot.
+    snapshot: PathBuf,
+    /// Tags of user holds, sorted.
+    holds: Vec<String>,
+    /// Clones of the snapshot, sorted.
+    clones: Vec<PathBuf>,
+}
+
 pub struct BookmarkRequest {
     pub snapshot: PathBuf,
     pub bookmark: PathBuf,
@@ -62,6 +130,306 @@ impl BookmarkRequest {
     }
 }
 
+/// Options for [`rename`](trait.ZfsEngine.html#method.rename).
+#[derive(Default, Builder, Debug, Clone, Getters, PartialEq, Eq)]
+#[builder(setter(into))]
+#[get = "pub"]
+pub struct RenameOptions {
+    /// Create all the non-existing parent datasets of the new name (`-p`).
+    #[builder(default)]
+    create_parents: bool,
+    /// Force unmount any file systems that need to be unmounted in the process (`-f`).
+    #[builder(default)]
+    force_unmount: bool,
+    /// Don't remount file systems (`-u`): they stay mounted where they were, so processes that
+    /// hold the mount point open aren't disturbed. Older `zfs` doesn't have it, rename fails with
+    /// `Error::Unsupported` there.
+    #[builder(default)]
+    no_remount: bool,
+}
+
+impl RenameOptions {
+    /// A preferred way to create this.
+    pub fn builder() -> RenameOptionsBuilder {
+        RenameOptionsBuilder::default()
+    }
+
+    pub(crate) fn into_args(self) -> Vec<OsString> {
+        let mut ret: Vec<OsString> = Vec::with_capacity(2);
+        if self.create_parents {
+            ret.push("-p".into());
+        }
+        if self.force_unmount {
+            ret.push("-f".into());
+        }
+        if self.no_remount {
+            ret.push("-u".into());
+        }
+        ret
+    }
+}
+
+/// Options for [`send_replication`](trait.ZfsEngine.html#method.send_replication).
+#[derive(Default, Builder, Debug, Clone, Getters, PartialEq, Eq)]
+#[builder(setter(into))]
+#[get = "pub"]
+pub struct ReplicationSendOptions {
+    /// When sending from a snapshot, include all intermediary snapshots (`-I`) instead of a
+    /// single increment (`-i`).
+    #[builder(default)]
+    intermediary: bool,
+    /// Include dataset properties in the stream (`-p`). Replication stream always includes
+    /// properties of descendants.
+    #[builder(default)]
+    properties: bool,
+    /// Same meaning as for lzc send: `LZC_SEND_FLAG_LARGE_BLOCK` is `-L`,
+    /// `LZC_SEND_FLAG_EMBED_DATA` is `-e`, `LZC_SEND_FLAG_COMPRESS` is `-c` and
+    /// `LZC_SEND_FLAG_RAW` is `-w`.
+    #[builder(default)]
+    flags: SendFlags,
+}
+
+impl ReplicationSendOptions {
+    /// A preferred way to create this.
+    pub fn builder() -> ReplicationSendOptionsBuilder {
+        ReplicationSendOptionsBuilder::default()
+    }
+
+    /// Arguments of `zfs send` after `-R`, except the snapshots.
+    pub(crate) fn to_args(&self) -> Vec<OsString> {
+        let mut ret: Vec<OsString> = Vec::with_capacity(5);
+        if self.properties {
+            ret.push("-p".into());
+        }
+        ret.extend(self.flags.to_args());
+        ret
+    }
+}
+
+/// Progress update of `zfs send -v`, see
+/// [`ZfsOpen3::send_full_with_progress`](struct.ZfsOpen3.html#method.send_full_with_progress).
+#[derive(Debug, Clone, Getters, PartialEq, Eq)]
+#[get = "pub"]
+pub struct SendProgress {
+    /// Time since `zfs send` was started.
+    elapsed: Duration,
+    /// Amount of bytes of the snapshot sent so far. `zfs send` rounds it, so it's approximate.
+    bytes_sent: u64,
+    /// Snapshot that is being sent.
+    snapshot: PathBuf,
+}
+
+impl SendProgress {
+    pub(crate) fn new(elapsed: Duration, bytes_sent: u64, snapshot: PathBuf) -> SendProgress {
+        SendProgress {
+            elapsed,
+            bytes_sent,
+            snapshot,
+        }
+    }
+}
+
+/// Options for [`receive`](trait.ZfsEngine.html#method.receive).
+#[derive(Default, Builder, Debug, Clone, Getters, PartialEq, Eq)]
+#[builder(setter(into))]
+#[get = "pub"]
+pub struct ReceiveOptions {
+    /// Roll back target to its most recent snapshot and destroy snapshots and file systems that
+    /// don't exist on sending side (`-F`).
+    #[builder(default)]
+    force: bool,
+    /// Discard the pool name of the sent snapshot and use the rest of its name under target
+    /// (`-d`).
+    #[builder(default)]
+    discard_pool_name: bool,
+    /// Use only the last element of the sent snapshot's name under target (`-e`).
+    #[builder(default)]
+    discard_all_but_last: bool,
+    /// Don't mount received file systems (`-u`).
+    #[builder(default)]
+    no_mount: bool,
+    /// Save partially received state so that interrupted receive can be resumed (`-s`).
+    #[builder(default)]
+    resumable: bool,
+    /// Properties to ignore in the stream, as if they weren't included (`-x`).
+    #[builder(default)]
+    exclude_properties: Vec<String>,
+    /// If receiving side rejects a property of the stream, exclude it and try again, up to
+    /// [`RECEIVE_ATTEMPTS`](constant.RECEIVE_ATTEMPTS.html) times in total. Only works if the
+    /// stream can be rewound, e.g. it's read from a file. Stream from a pipe fails with
+    /// [`Error::InvalidReceiveProperty`](enum.Error.html) right away.
+    #[builder(default)]
+    retry_excluding_invalid: bool,
+}
+
+/// State an interrupted resumable receive (`zfs receive -s`) left behind. See
+/// [`partial_receives`](trait.ZfsEngine.html#method.partial_receives).
+#[derive(Debug, Clone, PartialEq, Eq, Getters)]
+#[get = "pub"]
+pub struct PartialReceive {
+    /// Dataset the stream was received into.
+    dataset: PathBuf,
+    /// `receive_resume_token` to pass to `zfs send -t` on sending side.
+    token: String,
+    /// Space used by what was received so far: `used` of the hidden `%recv` child for incremental
+    /// streams and of the dataset itself for full ones. `None` if it couldn't be read.
+    bytes_received: Option<u64>,
+}
+
+impl PartialReceive {
+    pub(crate) fn new(dataset: PathBuf, token: String, bytes_received: Option<u64>) -> Self {
+        PartialReceive {
+            dataset,
+            token,
+            bytes_received,
+        }
+    }
+}
+
+/// Space used by files of one project, row of `zfs projectspace`. See
+/// [`project_space`](trait.ZfsEngine.html#method.project_space).
+#[derive(Debug, Clone, PartialEq, Eq, Getters)]
+#[get = "pub"]
+pub struct ProjectSpace {
+    /// Project ID.
+    project_id: u32,
+    /// Bytes used by files of the project.
+    used: u64,
+    /// `projectquota@<id>`, `None` if it isn't set.
+    quota: Option<u64>,
+    /// Number of objects (files and directories) of the project, `None` if it isn't tracked.
+    objects_used: Option<u64>,
+    /// `projectobjquota@<id>`, `None` if it isn't set.
+    object_quota: Option<u64>,
+}
+
+impl ProjectSpace {
+    pub(crate) fn new(
+        project_id: u32,
+        used: u64,
+        quota: Option<u64>,
+        objects_used: Option<u64>,
+        object_quota: Option<u64>,
+    ) -> Self {
+        ProjectSpace {
+            project_id,
+            used,
+            quota,
+            objects_used,
+            object_quota,
+        }
+    }
+}
+
+/// How many times receive is attempted with
+/// [`retry_excluding_invalid`](struct.ReceiveOptions.html#method.retry_excluding_invalid) set.
+pub const RECEIVE_ATTEMPTS: usize = 8;
+
+/// Platform a stream is received on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TargetPlatform {
+    Linux,
+    FreeBSD,
+}
+
+/// Properties that only one platform has, along with that platform. Receiving a stream that
+/// carries one of them on the other platform fails.
+pub const PLATFORM_SPECIFIC_PROPERTIES: &[(&str, TargetPlatform)] = &[
+    ("aclmode", TargetPlatform::FreeBSD),
+    ("jailed", TargetPlatform::FreeBSD),
+    ("acltype", TargetPlatform::Linux),
+    ("relatime", TargetPlatform::Linux),
+    ("zoned", TargetPlatform::Linux),
+    ("context", TargetPlatform::Linux),
+    ("fscontext", TargetPlatform::Linux),
+    ("defcontext", TargetPlatform::Linux),
+    ("rootcontext", TargetPlatform::Linux),
+];
+
+impl ReceiveOptions {
+    /// A preferred way to create this.
+    pub fn builder() -> ReceiveOptionsBuilder {
+        ReceiveOptionsBuilder::default()
+    }
+
+    /// Exclude properties `platform` doesn't have (see
+    /// [`PLATFORM_SPECIFIC_PROPERTIES`](constant.PLATFORM_SPECIFIC_PROPERTIES.html)), so that a
+    /// stream sent from another platform can be received.
+    pub fn sanitize_for_platform(mut self, platform: TargetPlatform) -> Self {
+        for (property, only_on) in PLATFORM_SPECIFIC_PROPERTIES {
+            if *only_on != platform && !self.exclude_properties.iter().any(|p| p == property) {
+                self.exclude_properties.push(String::from(*property));
+            }
+        }
+        self
+    }
+
+    pub(crate) fn exclude_property(mut self, property: String) -> Self {
+        self.exclude_properties.push(property);
+        self
+    }
+
+    pub(crate) fn into_args(self) -> Vec<OsString> {
+        let mut ret: Vec<OsString> = Vec::with_capacity(5 + self.exclude_properties.len() * 2);
+        if self.force {
+            ret.push("-F".into());
+        }
+        if self.discard_pool_name {
+            ret.push("-d".into());
+        }
+        if self.discard_all_but_last {
+            ret.push("-e".into());
+        }
+        if self.no_mount {
+            ret.push("-u".into());
+        }
+        if self.resumable {
+            ret.push("-s".into());
+        }
+        for property in self.exclude_properties {
+            ret.push("-x".into());
+            ret.push(property.into());
+        }
+        ret
+    }
+}
+
+/// Result of successful [`rename`](trait.ZfsEngine.html#method.rename).
+#[derive(Debug, Clone, Getters, PartialEq, Eq)]
+#[get = "pub"]
+pub struct RenameOutcome {
+    /// Every dataset, snapshot and bookmark that got new name as `(old name, new name)` pairs.
+    /// Renamed dataset itself goes first, then its descendants in the order `zfs list` reports
+    /// them.
+    renamed: Vec<(PathBuf, PathBuf)>,
+}
+
+impl RenameOutcome {
+    pub(crate) fn new(renamed: Vec<(PathBuf, PathBuf)>) -> Self {
+        RenameOutcome { renamed }
+    }
+
+    /// New name of given dataset if it was affected by rename.
+    pub fn new_name_of<N: AsRef<Path>>(&self, old: N) -> Option<&PathBuf> {
+        let old = old.as_ref();
+        self.renamed
+            .iter()
+            .find(|(from, _)| from == old)
+            .map(|(_, to)| to)
+    }
+}
+
+/// `enum lzc_send_flags` from `libzfs_core.h`. `libzetta-zfs-core-sys` only declares the first two,
+/// the rest are copied from OpenZFS headers.
+pub(crate) mod lzc_send_flags {
+    pub use zfs_core_sys::lzc_send_flags::{
+        Type, LZC_SEND_FLAG_EMBED_DATA, LZC_SEND_FLAG_LARGE_BLOCK,
+    };
+    pub const LZC_SEND_FLAG_COMPRESS: Type = 1 << 2;
+    pub const LZC_SEND_FLAG_RAW: Type = 1 << 3;
+    pub const LZC_SEND_FLAG_SAVED: Type = 1 << 4;
+}
+
 bitflags! {
     #[derive(Default)]
     pub struct SendFlags: u32 {
@@ -72,6 +440,73 @@ bitflags! {
         const LZC_SEND_FLAG_SAVED = 1 << 4;
     }
 }
+
+// Bits that are declared by sys crate must never drift from what `to_lzc` assumes.
+const _: () =
+    assert!(SendFlags::LZC_SEND_FLAG_EMBED_DATA.bits() == lzc_send_flags::LZC_SEND_FLAG_EMBED_DATA);
+const _: () = assert!(
+    SendFlags::LZC_SEND_FLAG_LARGE_BLOCK.bits() == lzc_send_flags::LZC_SEND_FLAG_LARGE_BLOCK
+);
+
+impl SendFlags {
+    /// Flags that weren't in the first release of `lzc_send`. Older `libzfs_core` might ignore
+    /// them instead of failing, so they are checked before sending.
+    pub(crate) const fn probed() -> SendFlags {
+        SendFlags::from_bits_truncate(
+            SendFlags::LZC_SEND_FLAG_COMPRESS.bits()
+                | SendFlags::LZC_SEND_FLAG_RAW.bits()
+                | SendFlags::LZC_SEND_FLAG_SAVED.bits(),
+        )
+    }
+
+    /// Arguments of `zfs send` for these flags. `LZC_SEND_FLAG_SAVED` is not passed on.
+    pub(crate) fn to_args(self) -> Vec<OsString> {
+        let mut ret: Vec<OsString> = Vec::with_capacity(4);
+        if self.contains(SendFlags::LZC_SEND_FLAG_LARGE_BLOCK) {
+            ret.push("-L".into());
+        }
+        if self.contains(SendFlags::LZC_SEND_FLAG_EMBED_DATA) {
+            ret.push("-e".into());
+        }
+        if self.contains(SendFlags::LZC_SEND_FLAG_COMPRESS) {
+            ret.push("-c".into());
+        }
+        if self.contains(SendFlags::LZC_SEND_FLAG_RAW) {
+            ret.push("-w".into());
+        }
+        ret
+    }
+
+    /// Value of `enum lzc_send_flags` for these flags.
+    pub fn to_lzc(self) -> lzc_send_flags::Type {
+        let mapping = [
+            (
+                SendFlags::LZC_SEND_FLAG_EMBED_DATA,
+                lzc_send_flags::LZC_SEND_FLAG_EMBED_DATA,
+            ),
+            (
+                SendFlags::LZC_SEND_FLAG_LARGE_BLOCK,
+                lzc_send_flags::LZC_SEND_FLAG_LARGE_BLOCK,
+            ),
+            (
+                SendFlags::LZC_SEND_FLAG_COMPRESS,
+                lzc_send_flags::LZC_SEND_FLAG_COMPRESS,
+            ),
+            (
+                SendFlags::LZC_SEND_FLAG_RAW,
+                lzc_send_flags::LZC_SEND_FLAG_RAW,
+            ),
+            (
+                SendFlags::LZC_SEND_FLAG_SAVED,
+                lzc_send_flags::LZC_SEND_FLAG_SAVED,
+            ),
+        ];
+        mapping
+            .iter()
+            .filter(|(flag, _)| self.contains(*flag))
+            .fold(0, |acc, (_, lzc)| acc | lzc)
+    }
+}
 pub trait ZfsEngine {
     /// Check if a dataset (a filesystem, or a volume, or a snapshot with the given name exists.
     ///
@@ -82,13 +517,29 @@ pub trait ZfsEngine {
         Err(Error::Unimplemented)
     }
 
+    /// Check existence of many datasets at once. Answers are in the same order as `names`,
+    /// duplicates are answered every time. `ZfsOpen3` lists common ancestors of `names` with a
+    /// single `zfs list` and can tell bookmarks too, other engines call `exists` for each name.
+    ///  * `names` - The dataset names to check.
+    fn exists_all<N: Into<PathBuf>>(&self, names: Vec<N>) -> Result<Vec<(PathBuf, bool)>> {
+        names
+            .into_iter()
+            .map(|name| {
+                let name = name.into();
+                self.exists(name.clone()).map(|exists| (name, exists))
+            })
+            .collect()
+    }
+
     /// Create a new dataset.
     #[cfg_attr(tarpaulin, skip)]
     fn create(&self, _request: CreateDatasetRequest) -> Result<()> {
         Err(Error::Unimplemented)
     }
 
-    /// Create snapshots as one atomic operation.
+    /// Create snapshots as one atomic operation: either all of them are created or none. All
+    /// snapshots must be in the same pool. If some snapshots can't be created, `ZfsLzc` returns
+    /// [`Error::SnapshotsFailed`](enum.Error.html) with errno of each of them.
     #[cfg_attr(tarpaulin, skip)]
     fn snapshot(
         &self,
@@ -111,12 +562,192 @@ pub trait ZfsEngine {
         Err(Error::Unimplemented)
     }
 
+    /// Destroy every dataset in `paths` using at most `max_concurrency` threads at a time. A
+    /// failure doesn't stop the rest, result of each `destroy` is returned in the order of `paths`.
+    ///
+    /// NOTE: Datasets are destroyed in no particular order, so don't pass a dataset along with its
+    /// descendants or snapshots.
+    fn destroy_many<N: Into<PathBuf>>(
+        &self,
+        paths: Vec<N>,
+        max_concurrency: usize,
+    ) -> Vec<(PathBuf, Result<()>)>
+    where
+        Self: Sync,
+    {
+        let paths: Vec<PathBuf> = paths.into_iter().map(Into::into).collect();
+        in_parallel(paths, max_concurrency, |path| self.destroy(path))
+    }
+
+    /// Rename a filesystem, volume or snapshot. Descendants of a renamed dataset get their names
+    /// changed as well, and mounted file systems are remounted under the new name. Returns every
+    /// affected name so that anyone depending on the old mount points can follow.
+    ///
+    /// Fails with `ValidationError::ParentNotFound` if parent of `new_name` doesn't exist and
+    /// `create_parents` isn't set, and with `Error::DatasetExists` if `new_name` is taken.
+    ///  * `name` - Current name.
+    ///  * `new_name` - New name.
+    ///  * `opts` - Parents creation, forced unmount and remounting.
+    #[cfg_attr(tarpaulin, skip)]
+    fn rename<N: Into<PathBuf>, T: Into<PathBuf>>(
+        &self,
+        _name: N,
+        _new_name: T,
+        _opts: RenameOptions,
+    ) -> Result<RenameOutcome> {
+        Err(Error::Unimplemented)
+    }
+
+    /// Create a writable file system or volume whose initial contents are the same as the
+    /// snapshot. Snapshot can't be destroyed while the clone exists.
+    ///  * `snapshot` - The snapshot to clone.
+    ///  * `target` - Name of the clone. Must be in the same pool as `snapshot`.
+    ///  * `properties` - Native or user properties to set on the clone, e.g. `canmount`.
+    #[cfg_attr(tarpaulin, skip)]
+    fn clone_snapshot<N: Into<PathBuf>, T: Into<PathBuf>>(
+        &self,
+        _snapshot: N,
+        _target: T,
+        _properties: Option<HashMap<String, String>>,
+    ) -> Result<()> {
+        Err(Error::Unimplemented)
+    }
+
+    /// Make clone independent of its origin snapshot. Snapshots of the origin file system up to
+    /// and including the origin snapshot move to the clone, and the origin file system becomes a
+    /// clone of the promoted one.
+    ///  * `clone` - The clone to promote.
+    #[cfg_attr(tarpaulin, skip)]
+    fn promote<N: Into<PathBuf>>(&self, _clone: N) -> Result<()> {
+        Err(Error::Unimplemented)
+    }
+
     /// Delete snapshots as one atomic operation
     #[cfg_attr(tarpaulin, skip)]
     fn destroy_snapshots(&self, _snapshots: &[PathBuf], _timing: DestroyTiming) -> Result<()> {
         Err(Error::Unimplemented)
     }
 
+    /// Destroy snapshot `snap_name` of `dataset` and of every descendant that has it, like
+    /// `zfs destroy -r dataset@snap_name`. Descendants without the snapshot are skipped.
+    ///
+    /// NOTE: `ZfsLzc` destroys all of them with a single `lzc_destroy_snaps`: either every
+    /// snapshot is destroyed or none. `ZfsOpen3` runs `zfs destroy -r`, which isn't atomic, and
+    /// some snapshots can be gone when it fails.
+    ///  * `dataset` - Root of the tree, e.g. `tank/home`.
+    ///  * `snap_name` - Short name of the snapshot, without `@`.
+    #[cfg_attr(tarpaulin, skip)]
+    fn destroy_snapshot_recursive<N: Into<PathBuf>>(
+        &self,
+        _dataset: N,
+        _snap_name: &str,
+    ) -> Result<()> {
+        Err(Error::Unimplemented)
+    }
+
+    /// Delete snapshots as one atomic operation, then report what happened to each of them.
+    /// Unlike [`destroy_snapshots`](#method.destroy_snapshots) failure of some snapshots isn't
+    /// an error, it's reported as [`DestroyOutcome::Failed`](enum.DestroyOutcome.html). With
+    /// `DestroyTiming::Defer` snapshots that are kept by holds or clones are reported as
+    /// `DestroyOutcome::Deferred` along with tags of the holds.
+    fn destroy_snapshots_verbose(
+        &self,
+        snapshots: &[PathBuf],
+        timing: DestroyTiming,
+    ) -> Result<Vec<(PathBuf, DestroyOutcome)>> {
+        let failed = match self.destroy_snapshots(snapshots, timing) {
+            Ok(()) => Vec::new(),
+            Err(Error::DestroySnapshotsFailed(failed)) => failed,
+            Err(e) => return Err(e),
+        };
+        let mut ret = Vec::with_capacity(snapshots.len());
+        for snapshot in snapshots {
+            let outcome = if let Some((_, errno)) = failed.iter().find(|(s, _)| s == snapshot) {
+                DestroyOutcome::Failed(*errno)
+            } else if !failed.is_empty() {
+                DestroyOutcome::Failed(libc::ECANCELED)
+            } else if !self.exists(snapshot.clone())? {
+                DestroyOutcome::Destroyed
+            } else {
+                match self.read_properties(snapshot.clone())? {
+                    Properties::Snapshot(props) if *props.defer_destroy() => {
+                        let holds = if *props.user_refs() > 0 {
+                            self.holds(snapshot.clone())?
+                        } else {
+                            Vec::new()
+                        };
+                        DestroyOutcome::Deferred { holds }
+                    }
+                    _ => DestroyOutcome::Failed(libc::EBUSY),
+                }
+            };
+            ret.push((snapshot.clone(), outcome));
+        }
+        Ok(ret)
+    }
+
+    /// Place user holds on snapshots as one atomic operation. Held snapshot can't be destroyed
+    /// right away, only marked for deferred destruction.
+    ///  * `holds` - Snapshot and tag of the hold. Every snapshot must be in the same pool.
+    #[cfg_attr(tarpaulin, skip)]
+    fn hold(&self, _holds: &[(PathBuf, String)]) -> Result<()> {
+        Err(Error::Unimplemented)
+    }
+
+    /// Release user holds on snapshots. Snapshot marked for deferred destruction is destroyed
+    /// once its last hold is released.
+    ///  * `holds` - Snapshot and tag of the hold. Every snapshot must be in the same pool.
+    #[cfg_attr(tarpaulin, skip)]
+    fn release(&self, _holds: &[(PathBuf, String)]) -> Result<()> {
+        Err(Error::Unimplemented)
+    }
+
+    /// Tags of user holds on the snapshot, sorted.
+    #[cfg_attr(tarpaulin, skip)]
+    fn holds<N: Into<PathBuf>>(&self, _snapshot: N) -> Result<Vec<String>> {
+        Err(Error::Unimplemented)
+    }
+
+    /// Snapshots of `root` and its descendants that are marked for deferred destruction
+    /// (`defer_destroy=on`) but still exist, ordered by `createtxg`.
+    ///  * `root` - The dataset to look under.
+    #[cfg_attr(tarpaulin, skip)]
+    fn deferred_snapshots<N: Into<PathBuf>>(&self, _root: N) -> Result<Vec<PathBuf>> {
+        Err(Error::Unimplemented)
+    }
+
+    /// What pins every snapshot from [`deferred_snapshots`](#method.deferred_snapshots): its user
+    /// holds and clones. Nothing is destroyed or released, snapshots go away by themselves once
+    /// nothing pins them. Snapshots that are gone by the time they are looked at are skipped.
+    ///  * `root` - The dataset to look under.
+    fn reap_deferred<N: Into<PathBuf>>(&self, root: N) -> Result<Vec<DeferredSnapshot>> {
+        let mut ret = Vec::new();
+        for snapshot in self.deferred_snapshots(root)? {
+            let pins = self.holds(snapshot.clone()).and_then(|holds| {
+                let clones: Option<String> = self.get_property(snapshot.clone(), "clones")?;
+                Ok((holds, clones))
+            });
+            let (holds, clones) = match pins {
+                Ok(pins) => pins,
+                // Engines report missing snapshot differently, so ask once more.
+                Err(_) if self.exists(snapshot.clone()) == Ok(false) => continue,
+                Err(e) => return Err(e),
+            };
+            let mut clones: Vec<PathBuf> = clones
+                .iter()
+                .flat_map(|clones| clones.split(','))
+                .map(PathBuf::from)
+                .collect();
+            clones.sort();
+            ret.push(DeferredSnapshot {
+                snapshot,
+                holds,
+                clones,
+            });
+        }
+        Ok(ret)
+    }
+
     /// Delete bookmarks as one atomic operation
     #[cfg_attr(tarpaulin, skip)]
     fn destroy_bookmarks(&self, _bookmarks: &[PathBuf]) -> Result<()> {
@@ -135,92 +766,618 @@ pub trait ZfsEngine {
     fn list_snapshots<N: Into<PathBuf>>(&self, _pool: N) -> Result<Vec<PathBuf>> {
         Err(Error::Unimplemented)
     }
+    /// Snapshots of the dataset itself (not of its descendants), ordered by `createtxg`. Cheaper
+    /// than `list_snapshots` of a dataset with many children.
+    ///  * `dataset` - The dataset to list snapshots of.
+    #[cfg_attr(tarpaulin, skip)]
+    fn list_snapshots_of<N: Into<PathBuf>>(&self, _dataset: N) -> Result<Vec<PathBuf>> {
+        Err(Error::Unimplemented)
+    }
+    /// Snapshot of the dataset itself (not of its descendants) with the highest `createtxg`, or
+    /// `None` if it has no snapshots. Handy as the source of the next incremental send.
+    ///  * `dataset` - The dataset to find snapshot of.
+    fn latest_snapshot<N: Into<PathBuf>>(&self, dataset: N) -> Result<Option<PathBuf>> {
+        Ok(snapshots_by_create_txg(self, dataset.into())?.pop())
+    }
+    /// Same as [`latest_snapshot`](#method.latest_snapshot), but with the lowest `createtxg`.
+    ///  * `dataset` - The dataset to find snapshot of.
+    fn oldest_snapshot<N: Into<PathBuf>>(&self, dataset: N) -> Result<Option<PathBuf>> {
+        Ok(snapshots_by_create_txg(self, dataset.into())?
+            .into_iter()
+            .next())
+    }
     #[cfg_attr(tarpaulin, skip)]
     fn list_bookmarks<N: Into<PathBuf>>(&self, _pool: N) -> Result<Vec<PathBuf>> {
         Err(Error::Unimplemented)
     }
+    /// Snapshots of the dataset itself (not of its descendants) along with their `guid`, ordered
+    /// by `createtxg`.
+    ///  * `dataset` - The dataset to list snapshots of.
     #[cfg_attr(tarpaulin, skip)]
-    fn list_volumes<N: Into<PathBuf>>(&self, _pool: N) -> Result<Vec<PathBuf>> {
+    fn list_snapshots_with_guids<N: Into<PathBuf>>(
+        &self,
+        _dataset: N,
+    ) -> Result<Vec<(PathBuf, u64)>> {
         Err(Error::Unimplemented)
     }
-    /// Read all properties of filesystem/volume/snapshot/bookmark.
+    /// Amount of referenced space written to `dataset` since `since_snapshot` was taken
+    /// (`written@<snapshot>` property).
+    ///  * `dataset` - The file system or volume.
+    ///  * `since_snapshot` - Short name of the snapshot, without dataset and `@`.
     #[cfg_attr(tarpaulin, skip)]
-    fn read_properties<N: Into<PathBuf>>(&self, _path: N) -> Result<Properties> {
+    fn written_since<N: Into<PathBuf>>(&self, _dataset: N, _since_snapshot: &str) -> Result<u64> {
         Err(Error::Unimplemented)
     }
 
-    /// Send a full snapshot to a specified file descriptor.
+    /// Assign project `project_id` to a file or directory and set its inherit flag, so new files
+    /// of the directory get the same project, like `zfs project -s -p <id> [-r] <path>`. Project
+    /// quotas then account space per directory inside one file system.
+    ///
+    /// NOTE: Project IDs are kept by Linux file system attributes, other platforms fail with
+    /// `Unsupported`.
+    ///  * `path` - File or directory on a mounted file system.
+    ///  * `project_id` - Project to assign.
+    ///  * `recursive` - Assign the project to everything under the directory as well.
     #[cfg_attr(tarpaulin, skip)]
-    fn send_full<N: Into<PathBuf>, FD: AsRawFd>(
+    fn set_project<P: AsRef<Path>>(
         &self,
-        _path: N,
-        _fd: FD,
-        _flags: SendFlags,
+        _path: P,
+        _project_id: u32,
+        _recursive: bool,
     ) -> Result<()> {
         Err(Error::Unimplemented)
     }
 
-    /// Send an incremental snapshot to a specified file descriptor.
+    /// Project of a file or directory, like `zfs project -d <path>`. Linux only, see
+    /// [`set_project`](#method.set_project).
+    ///  * `path` - File or directory on a mounted file system.
     #[cfg_attr(tarpaulin, skip)]
-    fn send_incremental<N: Into<PathBuf>, F: Into<PathBuf>, FD: AsRawFd>(
+    fn get_project<P: AsRef<Path>>(&self, _path: P) -> Result<u32> {
+        Err(Error::Unimplemented)
+    }
+
+    /// Set or remove (`None`) `projectquota@<id>` of the file system. Linux only, see
+    /// [`set_project`](#method.set_project).
+    ///  * `dataset` - The file system to update.
+    ///  * `project_id` - Project the quota is for.
+    ///  * `quota` - New quota.
+    #[cfg_attr(tarpaulin, skip)]
+    fn set_project_quota<N: Into<PathBuf>>(
         &self,
-        _path: N,
-        _from: F,
-        _fd: FD,
-        _flags: SendFlags,
+        _dataset: N,
+        _project_id: u32,
+        _quota: Option<ByteSize>,
     ) -> Result<()> {
         Err(Error::Unimplemented)
     }
 
-    /// Run a channel program
+    /// Space used by every project of the file system along with its quotas, like
+    /// `zfs projectspace`. Linux only, see [`set_project`](#method.set_project).
+    ///  * `dataset` - The file system.
     #[cfg_attr(tarpaulin, skip)]
-    fn run_channel_program<N: Into<PathBuf>>(
+    fn project_space<N: Into<PathBuf>>(&self, _dataset: N) -> Result<Vec<ProjectSpace>> {
+        Err(Error::Unimplemented)
+    }
+    /// Snapshots of the dataset in `createtxg` order along with how much was written between
+    /// previous snapshot and this one (`written` property of the snapshot).
+    ///  * `dataset` - The file system or volume.
+    fn snapshot_deltas<N: Into<PathBuf>>(&self, dataset: N) -> Result<Vec<(PathBuf, u64)>> {
+        let mut ret = Vec::new();
+        for (snapshot, _guid) in self.list_snapshots_with_guids(dataset)? {
+            match self.read_properties(&snapshot)? {
+                Properties::Snapshot(properties) => {
+                    let written = *properties.written();
+                    ret.push((snapshot, written));
+                }
+                _ => {
+                    return Err(Error::UnknownSoFar(format!(
+                        "{:?} is not a snapshot",
+                        snapshot
+                    )))
+                }
+            }
+        }
+        Ok(ret)
+    }
+    /// `root` and every filesystem and volume under it along with its `origin`, `None` if it isn't
+    /// a clone. Read in one go, unlike `get_property` of every dataset.
+    ///  * `root` - Dataset to start from.
+    #[cfg_attr(tarpaulin, skip)]
+    fn list_origins<N: Into<PathBuf>>(&self, _root: N) -> Result<Vec<(PathBuf, Option<PathBuf>)>> {
+        Err(Error::Unimplemented)
+    }
+    /// Value of a single property converted to `T`, e.g. `u64` for `guid` or `Option<PathBuf>`
+    /// for `origin`. Much cheaper than reading all properties when only one value is needed.
+    ///  * `dataset` - The dataset to read property of.
+    ///  * `prop` - Name of the property.
+    #[cfg_attr(tarpaulin, skip)]
+    fn get_property<N: Into<PathBuf>, T: PropertyValue>(
         &self,
-        _pool: N,
-        _program: &str,
-        _instr_limit: u64,
-        _mem_limit: u64,
-        _sync: bool,
-        _args: libnv::nvpair::NvList,
-    ) -> Result<libnv::nvpair::NvList> {
+        _dataset: N,
+        _prop: &str,
+    ) -> Result<T> {
+        Err(Error::Unimplemented)
+    }
+    /// `guid` of the snapshot. Cheaper than reading all properties.
+    ///  * `snapshot` - The snapshot to read `guid` of.
+    #[cfg_attr(tarpaulin, skip)]
+    fn snapshot_guid<N: Into<PathBuf>>(&self, _snapshot: N) -> Result<u64> {
+        Err(Error::Unimplemented)
+    }
+    /// List bookmarks along with their `guid`, `createtxg` and `creation` in a single call.
+    ///
+    /// NOTE: `ZfsLzc` only returns bookmarks of the given dataset, while `ZfsOpen3` includes
+    /// bookmarks of its descendants as well. `DelegatingZfsEngine` always uses `ZfsOpen3`.
+    ///  * `dataset` - The dataset to list bookmarks of.
+    #[cfg_attr(tarpaulin, skip)]
+    fn list_bookmarks_detailed<N: Into<PathBuf>>(
+        &self,
+        _dataset: N,
+    ) -> Result<Vec<BookmarkProperties>> {
+        Err(Error::Unimplemented)
+    }
+    #[cfg_attr(tarpaulin, skip)]
+    fn list_volumes<N: Into<PathBuf>>(&self, _pool: N) -> Result<Vec<PathBuf>> {
+        Err(Error::Unimplemented)
+    }
+    /// List currently mounted datasets as `(dataset, mount point)` pairs in the order `zfs mount`
+    /// reports them. Unlike `mountpoint` property this is where dataset is actually mounted right
+    /// now.
+    #[cfg_attr(tarpaulin, skip)]
+    fn mounted_datasets(&self) -> Result<Vec<(PathBuf, PathBuf)>> {
         Err(Error::Unimplemented)
     }
-}
 
-#[derive(Default, Builder, Debug, Clone, Getters)]
-#[builder(setter(into))]
-#[get = "pub"]
-/// Consumer friendly builder for NvPair. Use this to create your datasets. Some properties only
-/// work on filesystems, some only on volumes.
-pub struct CreateDatasetRequest {
-    /// Name of the dataset. First crumb of path is name of zpool.
-    name: PathBuf,
-    /// Filesystem or Volume.
-    kind: DatasetKind,
-    /// Optional user defined properties. User property names must conform to the following
-    /// characteristics:
+    /// Kind of the dataset: file system, volume, snapshot or bookmark. Cheaper than
+    /// `read_properties` because only `type` is read.
     ///
-    ///  - Contain a colon (':') character to distinguish them from native properties.
-    ///  - Contain lowercase letters, numbers, and the following punctuation characters: ':',
-    ///    '+','.', '_'.
-    ///  - Maximum user property name is 256 characters.
-    #[builder(default)]
-    user_properties: Option<HashMap<String, String>>,
+    ///  * `path` - The dataset to look up.
+    #[cfg_attr(tarpaulin, skip)]
+    fn kind_of<N: Into<PathBuf>>(&self, _path: N) -> Result<DatasetKind> {
+        Err(Error::Unimplemented)
+    }
 
-    //
-    // the rest is zfs native properties
-    /// Controls how ACL entries inherited when files and directories created.
-    #[builder(default)]
-    acl_inherit: Option<AclInheritMode>,
-    /// Controls how an ACL entry modified during a `chmod` operation.
-    #[builder(default)]
-    acl_mode: Option<AclMode>,
-    /// Controls whether the access time for files updated when they are read.
-    #[builder(default)]
-    atime: Option<bool>,
-    /// Controls whether a file system can be mounted.
+    /// Unmount given file system. Fails if file system is busy.
+    ///
+    ///  * `dataset` - The file system to unmount.
+    #[cfg_attr(tarpaulin, skip)]
+    fn unmount<N: Into<PathBuf>>(&self, _dataset: N) -> Result<()> {
+        Err(Error::Unimplemented)
+    }
+
+    /// Upgrade on-disk version of the file system. Upgrading a file system that is already at the
+    /// requested version is not an error.
+    ///
+    /// NOTE: Upgraded file systems (and streams sent from them) can't be accessed by older
+    /// software versions.
+    ///
+    ///  * `dataset` - The file system to upgrade.
+    ///  * `recursive` - Upgrade descendant file systems as well.
+    ///  * `to_version` - Version to upgrade to. `None` means the latest supported version.
+    #[cfg_attr(tarpaulin, skip)]
+    fn upgrade<N: Into<PathBuf>>(
+        &self,
+        _dataset: N,
+        _recursive: bool,
+        _to_version: Option<u32>,
+    ) -> Result<()> {
+        Err(Error::Unimplemented)
+    }
+
+    /// File systems of the pool that are not at the latest version along with their current
+    /// version.
+    ///
+    ///  * `pool` - The pool to look in.
+    #[cfg_attr(tarpaulin, skip)]
+    fn upgradeable_datasets<N: Into<PathBuf>>(&self, _pool: N) -> Result<Vec<(PathBuf, u32)>> {
+        Err(Error::Unimplemented)
+    }
+
+    /// Where given dataset is mounted right now. Returns `Ok(None)` if dataset isn't mounted (for
+    /// example, `mountpoint` is `none` or `legacy` and nobody mounted it). If dataset is mounted
+    /// more than once, the first entry reported by `zfs mount` wins.
+    ///
+    ///  * `dataset` - The dataset to look up.
+    fn mount_point_of<N: Into<PathBuf>>(&self, dataset: N) -> Result<Option<PathBuf>> {
+        let dataset = dataset.into();
+        let mount_point = self
+            .mounted_datasets()?
+            .into_iter()
+            .find(|(name, _)| name == &dataset)
+            .map(|(_, mount_point)| mount_point);
+        Ok(mount_point)
+    }
+
+    /// Read all properties of filesystem/volume/snapshot/bookmark. Properties that aren't reported
+    /// by installed version of ZFS are set to their defaults.
+    #[cfg_attr(tarpaulin, skip)]
+    fn read_properties<N: Into<PathBuf>>(&self, _path: N) -> Result<Properties> {
+        Err(Error::Unimplemented)
+    }
+
+    /// Read properties of every dataset in `paths` using at most `max_concurrency` threads at a
+    /// time. A failure doesn't stop the rest, results are returned in the order of `paths`.
+    fn read_properties_parallel<N: Into<PathBuf>>(
+        &self,
+        paths: Vec<N>,
+        max_concurrency: usize,
+    ) -> Vec<(PathBuf, Result<Properties>)>
+    where
+        Self: Sync,
+    {
+        let paths: Vec<PathBuf> = paths.into_iter().map(Into::into).collect();
+        in_parallel(paths, max_concurrency, |path| self.read_properties(path))
+    }
+
+    /// Read all properties of filesystem/volume/snapshot/bookmark along with source of each
+    /// property, keyed by property name as `zfs get` reports it (e.g. `compression`).
+    #[cfg_attr(tarpaulin, skip)]
+    fn read_properties_with_sources<N: Into<PathBuf>>(
+        &self,
+        _path: N,
+    ) -> Result<(Properties, HashMap<String, PropertySource>)> {
+        Err(Error::Unimplemented)
+    }
+
+    /// Values of `properties` of `root` and every filesystem and volume under it, as `zfs get -p`
+    /// prints them, read in one go. Datasets are listed in the order `zfs` lists them.
+    #[cfg_attr(tarpaulin, skip)]
+    fn read_property_values<N: Into<PathBuf>>(
+        &self,
+        _root: N,
+        _properties: &[String],
+    ) -> Result<Vec<(PathBuf, HashMap<String, String>)>> {
+        Err(Error::Unimplemented)
+    }
+
+    /// Values of `properties` of every snapshot of `root` and of datasets under it, like
+    /// [`read_property_values`](#method.read_property_values) does for filesystems and volumes.
+    #[cfg_attr(tarpaulin, skip)]
+    fn read_snapshot_property_values<N: Into<PathBuf>>(
+        &self,
+        _root: N,
+        _properties: &[String],
+    ) -> Result<Vec<(PathBuf, HashMap<String, String>)>> {
+        Err(Error::Unimplemented)
+    }
+
+    /// Change size of the volume. Shrinking a volume destroys data past the new end, so it's
+    /// refused with [`Error::VolumeShrinkRefused`](enum.Error.html) unless `allow_shrink` is set.
+    ///
+    ///  * `volume` - The volume to resize.
+    ///  * `new_size` - New `volsize`. Must be a multiple of `volblocksize`.
+    ///  * `allow_shrink` - Confirm that shrinking the volume is intended.
+    #[cfg_attr(tarpaulin, skip)]
+    fn resize_volume<N: Into<PathBuf>, S: Into<ByteSize>>(
+        &self,
+        _volume: N,
+        _new_size: S,
+        _allow_shrink: bool,
+    ) -> Result<()> {
+        Err(Error::Unimplemented)
+    }
+
+    /// Set or remove (`None`) `quota` of the file system. Quota below space that is already used
+    /// fails with [`Error::SizeBelowUsage`](enum.Error.html).
+    ///
+    ///  * `dataset` - The file system to update.
+    ///  * `quota` - New quota, `None` sets it to `none`, i.e. no limit.
+    #[cfg_attr(tarpaulin, skip)]
+    fn set_quota<N: Into<PathBuf>>(&self, _dataset: N, _quota: Option<ByteSize>) -> Result<()> {
+        Err(Error::Unimplemented)
+    }
+
+    /// Set or remove (`None`) `refquota` of the file system. Quota below space that is already
+    /// referenced fails with [`Error::SizeBelowUsage`](enum.Error.html).
+    ///
+    ///  * `dataset` - The file system to update.
+    ///  * `quota` - New quota, `None` sets it to `none`, i.e. no limit.
+    #[cfg_attr(tarpaulin, skip)]
+    fn set_refquota<N: Into<PathBuf>>(&self, _dataset: N, _quota: Option<ByteSize>) -> Result<()> {
+        Err(Error::Unimplemented)
+    }
+
+    /// Set `snapdev` of the volume, i.e. whether its snapshots are exposed as block devices.
+    ///
+    ///  * `volume` - The volume to update.
+    ///  * `snap_dev` - New `snapdev`.
+    #[cfg_attr(tarpaulin, skip)]
+    fn set_snap_dev<N: Into<PathBuf>>(&self, _volume: N, _snap_dev: SnapDev) -> Result<()> {
+        Err(Error::Unimplemented)
+    }
+
+    /// Set `snapdir` of the file system, i.e. whether `.zfs` directory is listed in its root.
+    ///
+    ///  * `dataset` - The file system to update.
+    ///  * `snap_dir` - New `snapdir`.
+    #[cfg_attr(tarpaulin, skip)]
+    fn set_snap_dir<N: Into<PathBuf>>(&self, _dataset: N, _snap_dir: SnapDir) -> Result<()> {
+        Err(Error::Unimplemented)
+    }
+
+    /// Set `sharenfs` of the file system. Takes effect right away, no need to call `share`.
+    ///
+    ///  * `dataset` - The file system to update.
+    ///  * `options` - New `sharenfs`.
+    #[cfg_attr(tarpaulin, skip)]
+    fn set_share_nfs<N: Into<PathBuf>>(&self, _dataset: N, _options: ShareOptions) -> Result<()> {
+        Err(Error::Unimplemented)
+    }
+
+    /// Set `sharesmb` of the file system. Takes effect right away, no need to call `share`.
+    ///
+    ///  * `dataset` - The file system to update.
+    ///  * `options` - New `sharesmb`.
+    #[cfg_attr(tarpaulin, skip)]
+    fn set_share_smb<N: Into<PathBuf>>(&self, _dataset: N, _options: ShareOptions) -> Result<()> {
+        Err(Error::Unimplemented)
+    }
+
+    /// Share the file system according to its `sharenfs` and `sharesmb` (`zfs share`). Fails
+    /// with [`Error::ShareFailed`](enum.Error.html) if the platform refuses.
+    ///
+    ///  * `dataset` - The file system to share.
+    #[cfg_attr(tarpaulin, skip)]
+    fn share<N: Into<PathBuf>>(&self, _dataset: N) -> Result<()> {
+        Err(Error::Unimplemented)
+    }
+
+    /// Stop sharing the file system (`zfs unshare`). Fails with
+    /// [`Error::ShareFailed`](enum.Error.html) if it isn't shared.
+    ///
+    ///  * `dataset` - The file system to unshare.
+    #[cfg_attr(tarpaulin, skip)]
+    fn unshare<N: Into<PathBuf>>(&self, _dataset: N) -> Result<()> {
+        Err(Error::Unimplemented)
+    }
+
+    /// Share every file system that has `sharenfs` or `sharesmb` set (`zfs share -a`).
+    #[cfg_attr(tarpaulin, skip)]
+    fn share_all(&self) -> Result<()> {
+        Err(Error::Unimplemented)
+    }
+
+    /// Call `f` with the directory snapshot is mounted at (`.zfs/snapshot/<name>` under mount
+    /// point of its file system). Snapshot directories are mounted on first access, so nothing has
+    /// to be unmounted afterwards.
+    ///
+    /// `snapdir` of the file system is set to `visible` for the duration of the call and restored
+    /// afterwards, even if `f` panics. Volumes have no snapshot directory, they fail with
+    /// `ValidationError::WrongDatasetKind`. File systems that aren't mounted fail with
+    /// `Error::NotMounted`.
+    ///
+    ///  * `snapshot` - The snapshot to look into, e.g. `tank/home@yesterday`.
+    ///  * `f` - Gets path to the root of the snapshot.
+    fn with_snapshot_mounted<N, F, R>(&self, snapshot: N, f: F) -> Result<R>
+    where
+        N: Into<PathBuf>,
+        F: FnOnce(&Path) -> R,
+    {
+        let snapshot = snapshot.into();
+        let snap_name = match snapshot.snapshot_name() {
+            Some(snap_name) => snap_name.to_owned(),
+            None => return Err(ValidationError::MissingSnapshotName(snapshot).into()),
+        };
+        let dataset = snapshot.dataset_of();
+        let previous = match self.read_properties(&dataset)? {
+            Properties::Filesystem(props) => *props.snap_dir(),
+            Properties::Volume(_) => {
+                return Err(ValidationError::WrongDatasetKind {
+                    dataset,
+                    expected: DatasetKind::Filesystem,
+                    found: DatasetKind::Volume,
+                }
+                .into())
+            }
+            _ => return Err(Error::invalid_input()),
+        };
+        let mount_point = self
+            .mount_point_of(&dataset)?
+            .ok_or_else(|| Error::NotMounted(dataset.clone()))?;
+
+        let _restore = if previous == SnapDir::Visible {
+            None
+        } else {
+            self.set_snap_dir(&dataset, SnapDir::Visible)?;
+            Some(RestoreSnapDir {
+                engine: self,
+                dataset: dataset.clone(),
+                snap_dir: previous,
+            })
+        };
+        let root = mount_point.join(".zfs").join("snapshot").join(snap_name);
+        // `stat` is what triggers mount of snapshot directory.
+        if !root.is_dir() {
+            return Err(Error::DatasetNotFound(snapshot));
+        }
+        Ok(f(&root))
+    }
+
+    /// Set `volmode` of the volume. Returns whether `volmode` reported by ZFS changed.
+    ///
+    /// Device nodes of a volume that is already exposed aren't always recreated right away. On
+    /// some platforms new mode only takes effect after the pool is imported again or on reboot.
+    ///
+    ///  * `volume` - The volume to update.
+    ///  * `mode` - New `volmode`.
+    #[cfg_attr(tarpaulin, skip)]
+    fn set_volume_mode<N: Into<PathBuf>>(&self, _volume: N, _mode: VolumeMode) -> Result<bool> {
+        Err(Error::Unimplemented)
+    }
+
+    /// Path of the device node of the volume, `/dev/zvol/<volume>` on both Linux and FreeBSD.
+    /// With `volmode=default` the system-wide default (`zvol_volmode` on Linux, `vfs.zfs.vol.mode`
+    /// on FreeBSD) is checked. Node may not exist yet, see
+    /// [`wait_for_volume_device`](#method.wait_for_volume_device).
+    ///
+    /// Fails with `Error::NoDeviceExposed` if the volume isn't exposed as a device and with
+    /// `ValidationError::WrongDatasetKind` if it's a file system, a snapshot or a bookmark.
+    ///
+    ///  * `volume` - The volume, e.g. `tank/vm/disk0`.
+    fn volume_device_path<N: Into<PathBuf>>(&self, volume: N) -> Result<PathBuf> {
+        volume_device_path_with(self, volume.into(), || {
+            zvol::system_volume_mode(crate::GlobalLogger::get(), None)
+        })
+    }
+
+    /// Wait for the device node of the volume to show up, e.g. right after the volume is created,
+    /// and return its path. Node is polled with backoff. On Linux a node left behind by a renamed
+    /// or destroyed volume doesn't count: the volume behind it is checked when the node can be
+    /// opened.
+    ///
+    /// Fails with `Error::DeviceTimedOut` if the node isn't there within `timeout` and right away
+    /// with `Error::NoDeviceExposed` if the volume has no node to wait for.
+    ///
+    ///  * `volume` - The volume, e.g. `tank/vm/disk0`.
+    ///  * `timeout` - How long to wait.
+    fn wait_for_volume_device<N: Into<PathBuf>>(
+        &self,
+        volume: N,
+        timeout: Duration,
+    ) -> Result<PathBuf> {
+        let volume = volume.into();
+        let device = self.volume_device_path(volume.clone())?;
+        zvol::wait_for_device(&volume, &device, timeout, zvol::volume_behind)?;
+        Ok(device)
+    }
+
+    /// Send a full snapshot to a specified file descriptor.
+    /// Flags that installed ZFS doesn't support fail with
+    /// [`Error::UnsupportedSendFlags`](enum.Error.html) before anything is written.
+    #[cfg_attr(tarpaulin, skip)]
+    fn send_full<N: Into<PathBuf>, FD: AsRawFd>(
+        &self,
+        _path: N,
+        _fd: FD,
+        _flags: SendFlags,
+    ) -> Result<()> {
+        Err(Error::Unimplemented)
+    }
+
+    /// Send an incremental snapshot to a specified file descriptor.
+    /// Flags that installed ZFS doesn't support fail with
+    /// [`Error::UnsupportedSendFlags`](enum.Error.html) before anything is written.
+    #[cfg_attr(tarpaulin, skip)]
+    fn send_incremental<N: Into<PathBuf>, F: Into<PathBuf>, FD: AsRawFd>(
+        &self,
+        _path: N,
+        _from: F,
+        _fd: FD,
+        _flags: SendFlags,
+    ) -> Result<()> {
+        Err(Error::Unimplemented)
+    }
+
+    /// Send a replication stream (`zfs send -R`) of `to_snapshot`: dataset with all its
+    /// descendants, snapshots, properties and clones. If `from` is set, stream is incremental.
+    /// Stream is written directly into `fd`.
+    #[cfg_attr(tarpaulin, skip)]
+    fn send_replication<N: Into<PathBuf>, FD: AsRawFd>(
+        &self,
+        _to_snapshot: N,
+        _from: Option<PathBuf>,
+        _fd: FD,
+        _opts: ReplicationSendOptions,
+    ) -> Result<()> {
+        Err(Error::Unimplemented)
+    }
+
+    /// Receive a stream (`zfs receive`) read from `fd` into `target`.
+    #[cfg_attr(tarpaulin, skip)]
+    fn receive<N: Into<PathBuf>, FD: AsRawFd>(
+        &self,
+        _target: N,
+        _fd: FD,
+        _opts: ReceiveOptions,
+    ) -> Result<()> {
+        Err(Error::Unimplemented)
+    }
+
+    /// Interrupted resumable receives into `root` or its descendants. Each one keeps its dataset
+    /// busy until it's resumed or [aborted](#method.abort_partial_receive).
+    #[cfg_attr(tarpaulin, skip)]
+    fn partial_receives<N: Into<PathBuf>>(&self, _root: N) -> Result<Vec<PartialReceive>> {
+        Err(Error::Unimplemented)
+    }
+
+    /// Discard state of an interrupted resumable receive into `dataset` (`zfs receive -A`). Fails
+    /// with `NoResumableReceive` if there is none.
+    #[cfg_attr(tarpaulin, skip)]
+    fn abort_partial_receive<N: Into<PathBuf>>(&self, _dataset: N) -> Result<()> {
+        Err(Error::Unimplemented)
+    }
+
+    /// Delegate permissions on `dataset` to non-privileged users (`zfs allow`).
+    #[cfg_attr(tarpaulin, skip)]
+    fn allow<N: Into<PathBuf>>(&self, _dataset: N, _grant: DelegationGrant) -> Result<()> {
+        Err(Error::Unimplemented)
+    }
+
+    /// Revoke permissions previously delegated on `dataset` (`zfs unallow`). Grant without
+    /// permissions revokes everything delegated to the principal.
+    #[cfg_attr(tarpaulin, skip)]
+    fn unallow<N: Into<PathBuf>>(&self, _dataset: N, _grant: DelegationGrant) -> Result<()> {
+        Err(Error::Unimplemented)
+    }
+
+    /// Permissions delegated on `dataset`, including ones inherited from its ancestors.
+    #[cfg_attr(tarpaulin, skip)]
+    fn read_delegations<N: Into<PathBuf>>(&self, _dataset: N) -> Result<Vec<Delegation>> {
+        Err(Error::Unimplemented)
+    }
+
+    /// Run a channel program
+    #[cfg_attr(tarpaulin, skip)]
+    fn run_channel_program<N: Into<PathBuf>>(
+        &self,
+        _pool: N,
+        _program: &str,
+        _instr_limit: u64,
+        _mem_limit: u64,
+        _sync: bool,
+        _args: crate::zfs::nv::NvList,
+    ) -> Result<crate::zfs::nv::NvList> {
+        Err(Error::Unimplemented)
+    }
+}
+
+#[derive(Default, Builder, Debug, Clone, Getters)]
+#[builder(setter(into))]
+#[get = "pub"]
+/// Consumer friendly builder for NvPair. Use this to create your datasets. Some properties only
+/// work on filesystems, some only on volumes.
+pub struct CreateDatasetRequest {
+    /// Name of the dataset. First crumb of path is name of zpool.
+    name: PathBuf,
+    /// Filesystem or Volume.
+    kind: DatasetKind,
+    /// Optional user defined properties. User property names must conform to the following
+    /// characteristics:
+    ///
+    ///  - Contain a colon (':') character to distinguish them from native properties.
+    ///  - Contain lowercase letters, numbers, and the following punctuation characters: ':',
+    ///    '+','.', '_'.
+    ///  - Maximum user property name is 256 characters.
+    #[builder(default)]
+    user_properties: Option<HashMap<String, String>>,
+
+    //
+    // the rest is zfs native properties
+    /// Controls how ACL entries inherited when files and directories created.
+    #[builder(default)]
+    acl_inherit: Option<AclInheritMode>,
+    /// Controls how an ACL entry modified during a `chmod` operation.
+    #[builder(default)]
+    acl_mode: Option<AclMode>,
+    /// Controls whether the access time for files updated when they are read.
+    #[builder(default)]
+    atime: Option<bool>,
+    /// Controls whether a file system can be mounted.
     #[builder(default)]
     can_mount: CanMount,
+    /// Whether file name matching is case-sensitive. Can only be set at creation.
+    #[builder(default)]
+    case_sensitivity: Option<CaseSensitivity>,
     /// Controls the checksum used to verify data integrity.
     #[builder(default)]
     checksum: Option<Checksum>,
@@ -235,42 +1392,58 @@ pub struct CreateDatasetRequest {
     /// property on an existing file system only affects newly written data.
     #[builder(default)]
     copies: Option<Copies>,
+    /// Configures deduplication for a dataset.
+    #[builder(default)]
+    dedup: Option<Dedup>,
     /// Controls whether device files in a file system can be opened.
     #[builder(default)]
     devices: Option<bool>,
+    /// Size of dnodes in the file system.
+    #[builder(default)]
+    dnode_size: Option<DnodeSize>,
     /// Controls whether programs in a file system allowed to be executed. Also, when set to
     /// `false`, `mmap(2)` calls with `PROT_EXEC` disallowed.
     #[builder(default)]
     exec: Option<bool>,
+    /// Provide a hint to ZFS about handling of synchronous requests in this dataset.
+    #[builder(default)]
+    log_bias: Option<LogBias>,
     /// Controls the mount point used for this file system.
     #[builder(default)]
     mount_point: Option<PathBuf>,
+    /// Unicode normalization of file names before they are compared. Can only be set at
+    /// creation.
+    #[builder(default)]
+    normalization: Option<Normalization>,
     /// Controls what is cached in the primary cache (ARC).
     #[builder(default)]
     primary_cache: Option<CacheMode>,
     /// Limits the amount of disk space a dataset and its descendants can consume.
-    #[builder(default)]
-    quota: Option<u64>,
+    #[builder(setter(into, strip_option), default)]
+    quota: Option<ByteSize>,
     /// Controls whether a dataset can be modified.
     #[builder(default)]
     readonly: Option<bool>,
     /// Specifies a suggested block size for files in a file system in bytes. The size specified
     /// must be a power of two greater than or equal to 512 and less than or equal to 128 KiB.
     /// If the large_blocks feature is enabled on the pool, the size may be up to 1 MiB.
+    #[builder(setter(into, strip_option), default)]
+    record_size: Option<ByteSize>,
+    /// Controls what types of metadata are stored redundantly.
     #[builder(default)]
-    record_size: Option<u64>,
+    redundant_metadata: Option<RedundantMetadata>,
     /// Sets the amount of disk space a dataset can consume. This property enforces a hard limit on
     /// the amount of space used. This hard limit does not include disk space used by descendents,
     /// such as snapshots and clones.
-    #[builder(default)]
-    ref_quota: Option<u64>,
+    #[builder(setter(into, strip_option), default)]
+    ref_quota: Option<ByteSize>,
     /// Sets the minimum amount of disk space is guaranteed to a dataset, not including
     /// descendants, such as snapshots and clones.
-    #[builder(default)]
-    ref_reservation: Option<u64>,
+    #[builder(setter(into, strip_option), default)]
+    ref_reservation: Option<ByteSize>,
     /// Sets the minimum amount of disk space guaranteed to a dataset and its descendants.
-    #[builder(default)]
-    reservation: Option<u64>,
+    #[builder(setter(into, strip_option), default)]
+    reservation: Option<ByteSize>,
     /// Controls what is cached in the secondary cache (L2ARC).
     #[builder(default)]
     secondary_cache: Option<CacheMode>,
@@ -280,15 +1453,22 @@ pub struct CreateDatasetRequest {
     /// Controls whether the .zfs directory is hidden or visible in the root of the file system
     #[builder(default)]
     snap_dir: Option<SnapDir>,
-    /// For volumes, specifies the logical size of the volume.
+    /// Controls the behavior of synchronous requests.
     #[builder(default)]
-    volume_size: Option<u64>,
+    sync: Option<SyncMode>,
+    /// Whether file names that aren't valid UTF-8 are rejected. Can only be set at creation.
+    /// If it's off, `normalization` must be unset or `none`.
+    #[builder(default)]
+    utf8_only: Option<bool>,
+    /// For volumes, specifies the logical size of the volume.
+    #[builder(setter(into, strip_option), default)]
+    volume_size: Option<ByteSize>,
     /// For volumes, specifies the block size of the volume in bytes. The block size cannot be
     /// changed after the volume has been written, so set the block size at volume creation time.
     /// The default block size for volumes is 8 KB. Any power of 2 from 512 bytes to 128 KB is
     /// valid.
-    #[builder(default)]
-    volume_block_size: Option<u64>,
+    #[builder(setter(into, strip_option), default)]
+    volume_block_size: Option<ByteSize>,
     /// Indicates whether extended attributes are enabled or disabled.
     #[builder(default)]
     xattr: Option<bool>,
@@ -305,6 +1485,29 @@ impl CreateDatasetRequest {
         if let Err(e) = validators::validate_name(self.name()) {
             errors.push(e);
         }
+        let block_size = match self.kind {
+            DatasetKind::Filesystem => self.record_size,
+            DatasetKind::Volume => self.volume_block_size,
+            DatasetKind::Snapshot | DatasetKind::Bookmark => None,
+        };
+        if let Some(size) = block_size {
+            if !size.is_valid_block_size() {
+                errors.push(ValidationError::InvalidBlockSize {
+                    dataset: self.name.clone(),
+                    size,
+                });
+            }
+        }
+        let normalized = self
+            .normalization
+            .is_some_and(|normalization| normalization != Normalization::None);
+        if self.utf8_only == Some(false) && normalized {
+            errors.push(ValidationError::ConflictingProperties {
+                dataset: self.name.clone(),
+                first: String::from("utf8only"),
+                second: String::from("normalization"),
+            });
+        }
 
         if errors.is_empty() {
             Ok(())
@@ -315,13 +1518,172 @@ impl CreateDatasetRequest {
 }
 
 pub(crate) mod validators {
-    use crate::zfs::{errors::ValidationResult, ValidationError, DATASET_NAME_MAX_LENGTH};
-    use std::path::Path;
+    use crate::zfs::{
+        errors::ValidationResult, BookmarkRequest, DatasetKind, PathExt, ValidationError,
+        DATASET_NAME_MAX_LENGTH,
+    };
+    use std::{
+        collections::HashSet,
+        path::{Path, PathBuf},
+    };
 
     pub fn validate_name<P: AsRef<Path>>(dataset: P) -> ValidationResult {
         _validate_name(dataset.as_ref())
     }
 
+    /// Same as [`validate_name`](fn.validate_name.html), but name must point to a snapshot.
+    pub fn validate_snapshot_name<P: AsRef<Path>>(snapshot: P) -> ValidationResult {
+        let snapshot = snapshot.as_ref();
+        _validate_name(snapshot)?;
+        if snapshot.is_snapshot() {
+            Ok(())
+        } else {
+            Err(ValidationError::MissingSnapshotName(snapshot.to_owned()))
+        }
+    }
+
+    /// Same as [`validate_name`](fn.validate_name.html), but name must point to a bookmark.
+    pub fn validate_bookmark_name<P: AsRef<Path>>(bookmark: P) -> ValidationResult {
+        let bookmark = bookmark.as_ref();
+        _validate_name(bookmark)?;
+        if bookmark.is_bookmark() {
+            Ok(())
+        } else {
+            Err(ValidationError::MissingBookmarkName(bookmark.to_owned()))
+        }
+    }
+
+    /// Full name of snapshot `snap_name` of `dataset`, for operations on the same snapshot
+    /// across descendants. `snap_name` is a short name, so `@`, `#` and `/` in it are rejected.
+    /// `dataset` must be a filesystem or a volume.
+    pub fn validate_recursive_snapshot(
+        dataset: &Path,
+        snap_name: &str,
+    ) -> ValidationResult<PathBuf> {
+        let found = if dataset.is_snapshot() {
+            DatasetKind::Snapshot
+        } else if dataset.is_bookmark() {
+            DatasetKind::Bookmark
+        } else {
+            return dataset.with_snapshot(snap_name);
+        };
+        Err(ValidationError::WrongDatasetKind {
+            dataset: dataset.to_owned(),
+            expected: DatasetKind::Filesystem,
+            found,
+        })
+    }
+
+    /// All datasets must belong to the same pool, like batch operations of `libzfs_core` require.
+    /// Returns paths that are not in the pool of the first one.
+    pub fn validate_same_pool(datasets: &[PathBuf]) -> ValidationResult {
+        let pool = match datasets.first() {
+            Some(first) => first.pool(),
+            None => return Ok(()),
+        };
+        let offending: Vec<PathBuf> = datasets
+            .iter()
+            .filter(|dataset| dataset.pool() != pool)
+            .cloned()
+            .collect();
+        if offending.is_empty() {
+            Ok(())
+        } else {
+            Err(ValidationError::CrossPoolOperation(offending))
+        }
+    }
+
+    /// Names in a batch must be unique. Returns names that are listed more than once.
+    pub fn validate_unique(names: &[PathBuf]) -> ValidationResult {
+        let mut seen = HashSet::with_capacity(names.len());
+        let mut repeated: Vec<PathBuf> = Vec::new();
+        for name in names {
+            if !seen.insert(name) && !repeated.contains(name) {
+                repeated.push(name.clone());
+            }
+        }
+        if repeated.is_empty() {
+            Ok(())
+        } else {
+            Err(ValidationError::DuplicateName(repeated))
+        }
+    }
+
+    /// Snapshots to create in one go: every name must be valid, in the same pool and listed
+    /// once. Returns every problem, not just the first one.
+    pub fn validate_snapshot_batch(
+        snapshots: &[PathBuf],
+    ) -> ValidationResult<(), Vec<ValidationError>> {
+        let mut errors: Vec<ValidationError> = snapshots
+            .iter()
+            .map(validate_snapshot_name)
+            .filter_map(Result::err)
+            .collect();
+        errors.extend(validate_same_pool(snapshots).err());
+        errors.extend(validate_unique(snapshots).err());
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Same as [`validate_snapshot_batch`](fn.validate_snapshot_batch.html), but for bookmarks.
+    /// Source of a bookmark can be either a snapshot or another bookmark. Sources and new
+    /// bookmarks all must be in the same pool.
+    pub fn validate_bookmark_batch(
+        bookmarks: &[BookmarkRequest],
+    ) -> ValidationResult<(), Vec<ValidationError>> {
+        let mut errors: Vec<ValidationError> = Vec::new();
+        for BookmarkRequest { snapshot, bookmark } in bookmarks {
+            if let Err(e) = validate_name(snapshot) {
+                errors.push(e);
+            } else if !snapshot.is_snapshot() && !snapshot.is_bookmark() {
+                errors.push(ValidationError::MissingSnapshotName(snapshot.clone()));
+            }
+            if let Err(e) = validate_bookmark_name(bookmark) {
+                errors.push(e);
+            }
+        }
+        let names: Vec<PathBuf> = bookmarks
+            .iter()
+            .flat_map(|request| vec![request.snapshot.clone(), request.bookmark.clone()])
+            .collect();
+        errors.extend(validate_same_pool(&names).err());
+        let created: Vec<PathBuf> = bookmarks
+            .iter()
+            .map(|request| request.bookmark.clone())
+            .collect();
+        errors.extend(validate_unique(&created).err());
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Characters ZFS allows in a name component.
+    pub(crate) fn is_valid_char(ch: char) -> bool {
+        ch.is_ascii_alphanumeric()
+            || ch == '_'
+            || ch == '-'
+            || ch == ':'
+            || ch == '.'
+            || ch == '%'
+            || ch == ' '
+    }
+
+    fn validate_chars(dataset: &Path, component: &str) -> ValidationResult {
+        match component.chars().find(|ch| !is_valid_char(*ch)) {
+            Some(ch) => Err(ValidationError::InvalidCharacter {
+                dataset: dataset.to_owned(),
+                component: component.to_owned(),
+                ch,
+            }),
+            None => Ok(()),
+        }
+    }
+
     pub fn _validate_name(dataset: &Path) -> ValidationResult {
         let name = dataset.to_string_lossy();
         if name.ends_with('/') {
@@ -330,23 +1692,152 @@ pub(crate) mod validators {
         if dataset.has_root() {
             return Err(ValidationError::MissingPool(dataset.to_owned()));
         }
-        dataset
-            .file_name()
-            .ok_or_else(|| ValidationError::MissingName(dataset.to_owned()))
-            .and_then(|name| {
-                if name.len() > DATASET_NAME_MAX_LENGTH {
-                    return Err(ValidationError::NameTooLong(dataset.to_owned()));
+        let mut components = name.split('/').peekable();
+        while let Some(component) = components.next() {
+            let is_last = components.peek().is_none();
+            if component.len() > DATASET_NAME_MAX_LENGTH {
+                return Err(if is_last {
+                    ValidationError::NameTooLong(dataset.to_owned())
+                } else {
+                    ValidationError::ComponentTooLong {
+                        dataset: dataset.to_owned(),
+                        component: component.to_owned(),
+                    }
+                });
+            }
+            // Only the last component can have snapshot or bookmark name.
+            let separator = if is_last {
+                component.find(['@', '#'])
+            } else {
+                None
+            };
+            let (base, suffix) = match separator {
+                Some(idx) => (&component[..idx], Some(&component[idx..])),
+                None => (component, None),
+            };
+            if base.is_empty() {
+                return Err(ValidationError::MissingName(dataset.to_owned()));
+            }
+            validate_chars(dataset, base)?;
+            if let Some(suffix) = suffix {
+                let (separator, short_name) = suffix.split_at(1);
+                if short_name.is_empty() {
+                    return Err(if separator == "@" {
+                        ValidationError::MissingSnapshotName(dataset.to_owned())
+                    } else {
+                        ValidationError::MissingBookmarkName(dataset.to_owned())
+                    });
                 }
-                Ok(())
-            })
+                validate_chars(dataset, short_name)?;
+            }
+        }
+        if name.len() > DATASET_NAME_MAX_LENGTH {
+            return Err(ValidationError::NameTooLong(dataset.to_owned()));
+        }
+        Ok(())
+    }
+}
+
+/// Puts `snapdir` back when dropped.
+struct RestoreSnapDir<'a, E: ZfsEngine + ?Sized> {
+    engine: &'a E,
+    dataset: PathBuf,
+    snap_dir: SnapDir,
+}
+
+impl<E: ZfsEngine + ?Sized> Drop for RestoreSnapDir<'_, E> {
+    fn drop(&mut self) {
+        // Nothing to do about a failure here, at worst `.zfs` stays visible.
+        let _ = self.engine.set_snap_dir(&self.dataset, self.snap_dir);
+    }
+}
+
+/// Path of the device node of `volume`. `volmode=default` is resolved with `system_default`.
+fn volume_device_path_with<E, F>(engine: &E, volume: PathBuf, system_default: F) -> Result<PathBuf>
+where
+    E: ZfsEngine + ?Sized,
+    F: FnOnce() -> Option<VolumeMode>,
+{
+    let found = match engine.read_properties(&volume)? {
+        Properties::Volume(props) => {
+            let mode = props.volume_mode().unwrap_or_default();
+            return zvol::device_path(&volume, mode, system_default);
+        }
+        Properties::Filesystem(_) => DatasetKind::Filesystem,
+        Properties::Snapshot(_) => DatasetKind::Snapshot,
+        Properties::Bookmark(_) => DatasetKind::Bookmark,
+        Properties::Unknown(_) => return Err(Error::invalid_input()),
+    };
+    Err(ValidationError::WrongDatasetKind {
+        dataset: volume,
+        expected: DatasetKind::Volume,
+        found,
     }
+    .into())
+}
+
+/// Snapshots of `dataset` sorted by `createtxg` read one by one. Fallback for engines that can't
+/// sort them in a single call.
+fn snapshots_by_create_txg<E: ZfsEngine + ?Sized>(
+    engine: &E,
+    dataset: PathBuf,
+) -> Result<Vec<PathBuf>> {
+    let mut snapshots = Vec::new();
+    for snapshot in engine.list_snapshots_of(dataset)? {
+        let create_txg: u64 = engine.get_property(snapshot.clone(), "createtxg")?;
+        snapshots.push((create_txg, snapshot));
+    }
+    snapshots.sort();
+    Ok(snapshots
+        .into_iter()
+        .map(|(_, snapshot)| snapshot)
+        .collect())
+}
+
+/// Run `f` on every path using at most `max_concurrency` threads at a time.
+fn in_parallel<T, F>(paths: Vec<PathBuf>, max_concurrency: usize, f: F) -> Vec<(PathBuf, Result<T>)>
+where
+    T: Send,
+    F: Fn(PathBuf) -> Result<T> + Sync,
+{
+    crate::utils::bounded_parallel(paths, max_concurrency, |path| {
+        let result = f(path.clone());
+        (path, result)
+    })
 }
 
 #[cfg(test)]
 mod test {
-    use super::{CreateDatasetRequest, DatasetKind, Error, ErrorKind, ValidationError};
+    use super::{
+        CreateDatasetRequest, DatasetKind, DestroyTiming, Error, ErrorKind, Result,
+        ValidationError, ZfsEngine,
+    };
     use std::path::PathBuf;
 
+    #[test]
+    fn destroy_timing_round_trip() {
+        for timing in &[DestroyTiming::RightNow, DestroyTiming::Defer] {
+            assert_eq!(Ok(timing), timing.to_string().parse().as_ref());
+        }
+        assert_eq!("right-now", DestroyTiming::RightNow.to_string());
+        assert_eq!("defer", DestroyTiming::Defer.to_string());
+        assert_eq!(Ok(DestroyTiming::RightNow), "Now".parse());
+        assert_eq!(Ok(DestroyTiming::Defer), "DEFERRED".parse());
+        assert!("later".parse::<DestroyTiming>().is_err());
+    }
+
+    struct MountTable(Vec<(&'static str, &'static str)>);
+
+    impl ZfsEngine for MountTable {
+        fn mounted_datasets(&self) -> Result<Vec<(PathBuf, PathBuf)>> {
+            Ok(self
+                .0
+                .iter()
+                .map(|(dataset, mount_point)| (PathBuf::from(dataset), PathBuf::from(mount_point)))
+                .collect())
+        }
+    }
+
     #[test]
     fn test_error_ds_not_found() {
         let stderr = b"cannot open 's/asd/asd': dataset does not exist";
@@ -356,6 +1847,82 @@ mod test {
         assert_eq!(ErrorKind::DatasetNotFound, err.kind());
     }
 
+    #[test]
+    fn test_error_size_below_usage() {
+        let stderr = b"cannot set property for 'z/quota': size is less than current used or reserved space\n";
+
+        let err = Error::from_stderr(stderr);
+        assert_eq!(ErrorKind::SizeBelowUsage, err.kind());
+        if let Error::SizeBelowUsage(dataset) = err {
+            assert_eq!(PathBuf::from("z/quota"), dataset);
+        }
+    }
+
+    #[test]
+    fn test_error_invalid_receive_property() {
+        let stderr = b"cannot receive: invalid property 'jailed'\n";
+        let err = Error::from_stderr(stderr);
+        assert_eq!(Error::InvalidReceiveProperty(String::from("jailed")), err);
+
+        let stderr = b"cannot receive new filesystem stream: invalid property 'acltype'\n";
+        let err = Error::from_stderr(stderr);
+        assert_eq!(Error::InvalidReceiveProperty(String::from("acltype")), err);
+    }
+
+    #[test]
+    fn test_error_share_failed() {
+        let stderr = b"cannot share 'z/export': share(1M) failed\n";
+        let expected = Error::ShareFailed {
+            dataset: PathBuf::from("z/export"),
+            reason: String::from("share(1M) failed"),
+        };
+        assert_eq!(expected, Error::from_stderr(stderr));
+
+        let stderr = b"cannot share 'z/export: system error': NFS share creation failed\n";
+        let expected = Error::ShareFailed {
+            dataset: PathBuf::from("z/export"),
+            reason: String::from("NFS share creation failed"),
+        };
+        assert_eq!(expected, Error::from_stderr(stderr));
+
+        let stderr = b"cannot unshare 'z/export': not currently shared\n";
+        let err = Error::from_stderr(stderr);
+        assert_eq!(ErrorKind::ShareFailed, err.kind());
+    }
+
+    #[test]
+    fn test_error_limit_exceeded() {
+        let stderr = b"cannot create snapshot 'tank/tenant@daily': snapshot limit exceeded\n";
+        let expected = Error::SnapshotLimitExceeded {
+            dataset: PathBuf::from("tank/tenant@daily"),
+        };
+        assert_eq!(expected, Error::from_stderr(stderr));
+
+        let stderr = b"cannot create 'tank/tenant/db': filesystem limit exceeded\n";
+        let expected = Error::FilesystemLimitExceeded {
+            dataset: PathBuf::from("tank/tenant/db"),
+        };
+        assert_eq!(expected, Error::from_stderr(stderr));
+        assert_ne!(
+            ErrorKind::SnapshotLimitExceeded,
+            Error::from_stderr(stderr).kind()
+        );
+    }
+
+    #[test]
+    fn test_error_no_resumable_receive() {
+        let stderr = b"'tank/backup' does not have any resumable receive state to abort\n";
+        assert_eq!(
+            Error::NoResumableReceive(PathBuf::from("tank/backup")),
+            Error::from_stderr(stderr)
+        );
+        let stderr = b"cannot open 'tank/backup/%recv': dataset does not exist\n";
+        assert_eq!(
+            Error::DatasetNotFound(PathBuf::from("tank/backup/%recv")),
+            Error::from_stderr(stderr)
+        );
+    }
+
     #[test]
     fn test_error_rubbish() {
         let stderr = b"there is no way there is an error like this";
@@ -390,4 +1957,732 @@ mod test {
         let expected = Error::from(vec![ValidationError::NameTooLong(path.clone())]);
         assert_eq!(expected, result);
     }
+
+    #[test]
+    fn test_block_size_validator() {
+        use super::ByteSize;
+        let path = PathBuf::from("z/asd");
+        let request = CreateDatasetRequest::builder()
+            .name(path.clone())
+            .kind(DatasetKind::Filesystem)
+            .record_size(ByteSize::kib(100))
+            .quota(ByteSize::gib(1))
+            .build()
+            .unwrap();
+        let result = request.validate().unwrap_err();
+        let expected = Error::from(vec![ValidationError::InvalidBlockSize {
+            dataset: path.clone(),
+            size: ByteSize::kib(100),
+        }]);
+        assert_eq!(expected, result);
+
+        let request = CreateDatasetRequest::builder()
+            .name(path)
+            .kind(DatasetKind::Volume)
+            .volume_size(ByteSize::gib(1))
+            .volume_block_size(16384)
+            .build()
+            .unwrap();
+        assert!(request.validate().is_ok());
+        assert_eq!(&Some(ByteSize::kib(16)), request.volume_block_size());
+    }
+
+    #[test]
+    fn test_utf8_only_validator() {
+        use super::properties::Normalization;
+        let path = PathBuf::from("z/asd");
+        let request = CreateDatasetRequest::builder()
+            .name(path.clone())
+            .kind(DatasetKind::Filesystem)
+            .utf8_only(false)
+            .normalization(Normalization::FormD)
+            .build()
+            .unwrap();
+        let expected = Error::from(vec![ValidationError::ConflictingProperties {
+            dataset: path.clone(),
+            first: String::from("utf8only"),
+            second: String::from("normalization"),
+        }]);
+        assert_eq!(expected, request.validate().unwrap_err());
+
+        for (utf8_only, normalization) in &[
+            (false, Normalization::None),
+            (true, Normalization::FormD),
+            (true, Normalization::None),
+        ] {
+            let request = CreateDatasetRequest::builder()
+                .name(path.clone())
+                .kind(DatasetKind::Filesystem)
+                .utf8_only(*utf8_only)
+                .normalization(*normalization)
+                .build()
+                .unwrap();
+            assert!(request.validate().is_ok());
+        }
+    }
+
+    #[test]
+    fn test_name_validator_rejects() {
+        use super::validators::validate_name;
+        let invalid_char =
+            |name: &str, component: &str, ch: char| ValidationError::InvalidCharacter {
+                dataset: PathBuf::from(name),
+                component: component.to_string(),
+                ch,
+            };
+
+        let cases = vec![
+            (
+                "z/tab\there",
+                invalid_char("z/tab\there", "tab\there", '\t'),
+            ),
+            ("z/$(reboot)", invalid_char("z/$(reboot)", "$(reboot)", '$')),
+            ("z/a;b", invalid_char("z/a;b", "a;b", ';')),
+            ("z/ünicode", invalid_char("z/ünicode", "ünicode", 'ü')),
+            ("z@snap/child", invalid_char("z@snap/child", "z@snap", '@')),
+            ("z/a#b/c", invalid_char("z/a#b/c", "a#b", '#')),
+            ("z/a@b@c", invalid_char("z/a@b@c", "b@c", '@')),
+            ("z/a@b#c", invalid_char("z/a@b#c", "b#c", '#')),
+            ("z//a", ValidationError::MissingName(PathBuf::from("z//a"))),
+            (
+                "z/@snap",
+                ValidationError::MissingName(PathBuf::from("z/@snap")),
+            ),
+            (
+                "z/a@",
+                ValidationError::MissingSnapshotName(PathBuf::from("z/a@")),
+            ),
+            (
+                "z/a#",
+                ValidationError::MissingBookmarkName(PathBuf::from("z/a#")),
+            ),
+            ("/z/a", ValidationError::MissingPool(PathBuf::from("/z/a"))),
+        ];
+        for (name, expected) in cases {
+            assert_eq!(Err(expected), validate_name(name), "{}", name);
+        }
+
+        let long_component = "a".repeat(256);
+        let name = format!("{}/b", long_component);
+        assert_eq!(
+            Err(ValidationError::ComponentTooLong {
+                dataset: PathBuf::from(&name),
+                component: long_component,
+            }),
+            validate_name(&name)
+        );
+    }
+
+    #[test]
+    fn test_same_pool_validator() {
+        use super::validators::validate_same_pool;
+
+        assert_eq!(Ok(()), validate_same_pool(&[]));
+        assert_eq!(
+            Ok(()),
+            validate_same_pool(&[PathBuf::from("z/a@s"), PathBuf::from("z/b/c@s")])
+        );
+        assert_eq!(
+            Err(ValidationError::CrossPoolOperation(vec![
+                PathBuf::from("y/b@s"),
+                PathBuf::from("x@s"),
+            ])),
+            validate_same_pool(&[
+                PathBuf::from("z/a@s"),
+                PathBuf::from("y/b@s"),
+                PathBuf::from("z@s"),
+                PathBuf::from("x@s"),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_snapshot_batch_validator() {
+        use super::validators::validate_snapshot_batch;
+
+        let long = format!("z/{}@snap", "a".repeat(250));
+        let cases = vec![
+            (vec!["z/a@s", "z/b/c@s"], vec![]),
+            (vec![], vec![]),
+            (
+                vec!["z/a@s", "y/b@s", "z/a@s", "x/c@s", "z/a@s"],
+                vec![
+                    ValidationError::CrossPoolOperation(vec![
+                        PathBuf::from("y/b@s"),
+                        PathBuf::from("x/c@s"),
+                    ]),
+                    ValidationError::DuplicateName(vec![PathBuf::from("z/a@s")]),
+                ],
+            ),
+            (
+                vec!["z/a", "z/b@s@t", long.as_str()],
+                vec![
+                    ValidationError::MissingSnapshotName(PathBuf::from("z/a")),
+                    ValidationError::InvalidCharacter {
+                        dataset: PathBuf::from("z/b@s@t"),
+                        component: String::from("s@t"),
+                        ch: '@',
+                    },
+                    ValidationError::NameTooLong(PathBuf::from(&long)),
+                ],
+            ),
+        ];
+        for (snapshots, expected) in cases {
+            let snapshots: Vec<PathBuf> = snapshots.into_iter().map(PathBuf::from).collect();
+            let result = validate_snapshot_batch(&snapshots);
+            if expected.is_empty() {
+                assert_eq!(Ok(()), result, "{:?}", snapshots);
+            } else {
+                assert_eq!(Err(expected), result, "{:?}", snapshots);
+            }
+        }
+    }
+
+    #[test]
+    fn test_bookmark_batch_validator() {
+        use super::{validators::validate_bookmark_batch, BookmarkRequest};
+
+        let cases = vec![
+            (vec![("z/a@s", "z/a#b"), ("z/a#b", "z/c#b")], vec![]),
+            (
+                vec![("z/a@s", "y/a#b"), ("z/a@s", "z/a#b"), ("z/b@s", "z/a#b")],
+                vec![
+                    ValidationError::CrossPoolOperation(vec![PathBuf::from("y/a#b")]),
+                    ValidationError::DuplicateName(vec![PathBuf::from("z/a#b")]),
+                ],
+            ),
+            (
+                vec![("z/a", "z/a#b"), ("z/a@s", "z/a@t")],
+                vec![
+                    ValidationError::MissingSnapshotName(PathBuf::from("z/a")),
+                    ValidationError::MissingBookmarkName(PathBuf::from("z/a@t")),
+                ],
+            ),
+        ];
+        for (bookmarks, expected) in cases {
+            let requests: Vec<BookmarkRequest> = bookmarks
+                .iter()
+                .map(|(snapshot, bookmark)| {
+                    BookmarkRequest::new(PathBuf::from(snapshot), PathBuf::from(bookmark))
+                })
+                .collect();
+            let result = validate_bookmark_batch(&requests);
+            if expected.is_empty() {
+                assert_eq!(Ok(()), result, "{:?}", bookmarks);
+            } else {
+                assert_eq!(Err(expected), result, "{:?}", bookmarks);
+            }
+        }
+    }
+
+    #[test]
+    fn test_snapshot_and_bookmark_name_validators() {
+        use super::validators::{validate_bookmark_name, validate_snapshot_name};
+
+        assert_eq!(Ok(()), validate_snapshot_name("z/a@snap"));
+        assert_eq!(
+            Err(ValidationError::MissingSnapshotName(PathBuf::from("z/a"))),
+            validate_snapshot_name("z/a")
+        );
+        assert_eq!(
+            Err(ValidationError::MissingSnapshotName(PathBuf::from(
+                "z/a#book"
+            ))),
+            validate_snapshot_name("z/a#book")
+        );
+        assert_eq!(Ok(()), validate_bookmark_name("z/a#book"));
+        assert_eq!(
+            Err(ValidationError::MissingBookmarkName(PathBuf::from(
+                "z/a@snap"
+            ))),
+            validate_bookmark_name("z/a@snap")
+        );
+    }
+
+    #[test]
+    fn test_recursive_snapshot_validator() {
+        use super::validators::validate_recursive_snapshot;
+        use std::path::Path;
+
+        let dataset = Path::new("z/a");
+        assert_eq!(
+            Ok(PathBuf::from("z/a@daily")),
+            validate_recursive_snapshot(dataset, "daily")
+        );
+        assert_eq!(
+            Err(ValidationError::InvalidCharacter {
+                dataset: PathBuf::from("z/a@@daily"),
+                component: String::from("@daily"),
+                ch: '@',
+            }),
+            validate_recursive_snapshot(dataset, "@daily")
+        );
+        assert_eq!(
+            Err(ValidationError::MissingSnapshotName(PathBuf::from("z/a@"))),
+            validate_recursive_snapshot(dataset, "")
+        );
+        assert_eq!(
+            Err(ValidationError::WrongDatasetKind {
+                dataset: PathBuf::from("z/a@snap"),
+                expected: DatasetKind::Filesystem,
+                found: DatasetKind::Snapshot,
+            }),
+            validate_recursive_snapshot(Path::new("z/a@snap"), "daily")
+        );
+        assert!(validate_recursive_snapshot(dataset, "a#b").is_err());
+    }
+
+    #[test]
+    fn test_name_validator_corpus() {
+        use super::{validators::validate_name, PathExt};
+        use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+
+        const CHARSET: &[u8] =
+            b"abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789_-:.% ";
+        // Fixed seed, so a failure can be reproduced.
+        let mut rng = StdRng::seed_from_u64(0x5eed);
+        let component = |rng: &mut StdRng| -> String {
+            let len = rng.gen_range(1..12);
+            (0..len)
+                .map(|_| *CHARSET.choose(rng).unwrap() as char)
+                .collect()
+        };
+
+        for _ in 0..500 {
+            let depth = rng.gen_range(1..5);
+            let name: Vec<String> = (0..depth).map(|_| component(&mut rng)).collect();
+            let dataset = PathBuf::from(name.join("/"));
+            assert_eq!(Ok(()), validate_name(&dataset), "{}", dataset.display());
+
+            let short_name = component(&mut rng);
+            let snapshot = dataset.with_snapshot(&short_name).unwrap();
+            assert_eq!(Ok(()), validate_name(&snapshot), "{}", snapshot.display());
+            assert_eq!(dataset, snapshot.dataset_of());
+            assert_eq!(Some(short_name.as_str()), snapshot.snapshot_name());
+
+            let bookmark = dataset.with_bookmark(&short_name).unwrap();
+            assert_eq!(Ok(()), validate_name(&bookmark), "{}", bookmark.display());
+            assert_eq!(dataset, bookmark.dataset_of());
+            assert_eq!(Some(short_name.as_str()), bookmark.bookmark_name());
+        }
+    }
+
+    #[test]
+    fn errors_cross_threads() {
+        fn assert_send<T: Send>() {}
+        assert_send::<Error>();
+    }
+
+    #[test]
+    fn send_flags_to_lzc() {
+        use super::SendFlags;
+
+        assert_eq!(0, SendFlags::empty().to_lzc());
+        assert_eq!(1, SendFlags::LZC_SEND_FLAG_EMBED_DATA.to_lzc());
+        assert_eq!(2, SendFlags::LZC_SEND_FLAG_LARGE_BLOCK.to_lzc());
+        assert_eq!(4, SendFlags::LZC_SEND_FLAG_COMPRESS.to_lzc());
+        assert_eq!(8, SendFlags::LZC_SEND_FLAG_RAW.to_lzc());
+        assert_eq!(16, SendFlags::LZC_SEND_FLAG_SAVED.to_lzc());
+        assert_eq!(
+            10,
+            (SendFlags::LZC_SEND_FLAG_LARGE_BLOCK | SendFlags::LZC_SEND_FLAG_RAW).to_lzc()
+        );
+    }
+
+    #[test]
+    fn replication_and_receive_options_into_args() {
+        use super::{ReceiveOptions, ReplicationSendOptions, SendFlags};
+        use std::ffi::OsString;
+
+        assert!(ReplicationSendOptions::default().to_args().is_empty());
+        let args = ReplicationSendOptions::builder()
+            .properties(true)
+            .flags(SendFlags::LZC_SEND_FLAG_LARGE_BLOCK | SendFlags::LZC_SEND_FLAG_COMPRESS)
+            .build()
+            .unwrap()
+            .to_args();
+        let expected: Vec<OsString> = ["-p", "-L", "-c"].iter().map(OsString::from).collect();
+        assert_eq!(expected, args);
+
+        assert!(ReceiveOptions::default().into_args().is_empty());
+        let args = ReceiveOptions::builder()
+            .force(true)
+            .discard_pool_name(true)
+            .no_mount(true)
+            .exclude_properties(vec![String::from("mountpoint")])
+            .build()
+            .unwrap()
+            .into_args();
+        let expected: Vec<OsString> = ["-F", "-d", "-u", "-x", "mountpoint"]
+            .iter()
+            .map(OsString::from)
+            .collect();
+        assert_eq!(expected, args);
+    }
+
+    #[test]
+    fn receive_options_sanitized_for_platform() {
+        use super::{ReceiveOptions, TargetPlatform};
+
+        let opts = ReceiveOptions::builder()
+            .exclude_properties(vec![String::from("mountpoint"), String::from("jailed")])
+            .build()
+            .unwrap()
+            .sanitize_for_platform(TargetPlatform::Linux);
+        assert_eq!(
+            &vec![
+                String::from("mountpoint"),
+                String::from("jailed"),
+                String::from("aclmode")
+            ],
+            opts.exclude_properties()
+        );
+
+        let opts = ReceiveOptions::default().sanitize_for_platform(TargetPlatform::FreeBSD);
+        assert!(opts.exclude_properties().iter().any(|p| p == "acltype"));
+        assert!(opts.exclude_properties().iter().any(|p| p == "relatime"));
+        assert!(!opts.exclude_properties().iter().any(|p| p == "jailed"));
+    }
+
+    #[test]
+    fn test_mount_point_of() {
+        let engine = MountTable(vec![
+            ("z/ROOT/default", "/"),
+            ("z/legacy", "/srv/legacy"),
+            ("z/legacy", "/srv/legacy-bind"),
+        ]);
+
+        assert_eq!(
+            Some(PathBuf::from("/")),
+            engine.mount_point_of("z/ROOT/default").unwrap()
+        );
+        assert_eq!(
+            Some(PathBuf::from("/srv/legacy")),
+            engine.mount_point_of("z/legacy").unwrap()
+        );
+        assert_eq!(None, engine.mount_point_of("z/unmounted").unwrap());
+    }
+
+    /// Engine that keeps track of how many calls are in flight and fails on `fail`.
+    #[derive(Default)]
+    struct Busy {
+        fail: &'static str,
+        active: std::sync::atomic::AtomicUsize,
+        max_active: std::sync::atomic::AtomicUsize,
+    }
+
+    impl Busy {
+        fn call(&self, path: PathBuf) -> Result<()> {
+            use std::sync::atomic::Ordering;
+            let active = self.active.fetch_add(1, Ordering::SeqCst) + 1;
+            self.max_active.fetch_max(active, Ordering::SeqCst);
+            std::thread::sleep(std::time::Duration::from_millis(20));
+            self.active.fetch_sub(1, Ordering::SeqCst);
+            if path == PathBuf::from(self.fail) {
+                return Err(Error::DatasetNotFound(path));
+            }
+            Ok(())
+        }
+    }
+
+    impl ZfsEngine for Busy {
+        fn destroy<N: Into<PathBuf>>(&self, name: N) -> Result<()> {
+            self.call(name.into())
+        }
+
+        fn read_properties<N: Into<PathBuf>>(&self, path: N) -> Result<super::Properties> {
+            let path = path.into();
+            self.call(path.clone())?;
+            let props = super::FilesystemProperties::builder(path).build().unwrap();
+            Ok(super::Properties::Filesystem(props))
+        }
+    }
+
+    fn busy_paths() -> Vec<String> {
+        (0..12).map(|idx| format!("z/ds{}", idx)).collect()
+    }
+
+    #[test]
+    fn destroy_many_is_bounded() {
+        use std::sync::atomic::Ordering;
+        let engine = Busy {
+            fail: "z/ds3",
+            ..Busy::default()
+        };
+        let results = engine.destroy_many(busy_paths(), 3);
+
+        let max_active = engine.max_active.load(Ordering::SeqCst);
+        assert!(max_active <= 3, "{} calls at once", max_active);
+        assert_eq!(12, results.len());
+        for (idx, (path, result)) in results.into_iter().enumerate() {
+            assert_eq!(PathBuf::from(format!("z/ds{}", idx)), path);
+            if idx == 3 {
+                assert_eq!(Err(Error::DatasetNotFound(path)), result);
+            } else {
+                assert_eq!(Ok(()), result);
+            }
+        }
+    }
+
+    #[test]
+    fn read_properties_parallel_keeps_going() {
+        use std::sync::atomic::Ordering;
+        let engine = Busy {
+            fail: "z/ds0",
+            ..Busy::default()
+        };
+        let results = engine.read_properties_parallel(busy_paths(), 4);
+
+        assert!(engine.max_active.load(Ordering::SeqCst) <= 4);
+        let failed: Vec<&PathBuf> = results
+            .iter()
+            .filter(|(_, result)| result.is_err())
+            .map(|(path, _)| path)
+            .collect();
+        assert_eq!(vec![&PathBuf::from("z/ds0")], failed);
+        match &results[5] {
+            (path, Ok(super::Properties::Filesystem(props))) => {
+                assert_eq!(&PathBuf::from("z/ds5"), path);
+                assert_eq!(path, props.name());
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    /// Engine with a single file system mounted at `root` and a volume `z/vol`.
+    struct SnapDirs {
+        root: PathBuf,
+        snap_dir: std::cell::RefCell<Vec<super::SnapDir>>,
+    }
+
+    impl ZfsEngine for SnapDirs {
+        fn mounted_datasets(&self) -> Result<Vec<(PathBuf, PathBuf)>> {
+            Ok(vec![(PathBuf::from("z/fs"), self.root.clone())])
+        }
+
+        fn read_properties<N: Into<PathBuf>>(&self, path: N) -> Result<super::Properties> {
+            let path = path.into();
+            if path == PathBuf::from("z/vol") {
+                let props = super::VolumeProperties::builder(path).build().unwrap();
+                return Ok(super::Properties::Volume(props));
+            }
+            let props = super::FilesystemProperties::builder(path)
+                .snap_dir(*self.snap_dir.borrow().last().unwrap())
+                .build()
+                .unwrap();
+            Ok(super::Properties::Filesystem(props))
+        }
+
+        fn set_snap_dir<N: Into<PathBuf>>(
+            &self,
+            dataset: N,
+            snap_dir: super::SnapDir,
+        ) -> Result<()> {
+            assert_eq!(PathBuf::from("z/fs"), dataset.into());
+            self.snap_dir.borrow_mut().push(snap_dir);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn with_snapshot_mounted_restores_snap_dir() {
+        use super::SnapDir;
+        use std::{fs, panic};
+
+        let dir = tempfile::tempdir().unwrap();
+        let snapshot_root = dir.path().join(".zfs/snapshot/monday");
+        fs::create_dir_all(&snapshot_root).unwrap();
+        fs::write(snapshot_root.join("file"), "old").unwrap();
+        let engine = SnapDirs {
+            root: dir.path().to_path_buf(),
+            snap_dir: std::cell::RefCell::new(vec![SnapDir::Hidden]),
+        };
+
+        let content = engine
+            .with_snapshot_mounted("z/fs@monday", |root| {
+                fs::read_to_string(root.join("file")).unwrap()
+            })
+            .unwrap();
+        assert_eq!("old", content);
+        assert_eq!(
+            vec![SnapDir::Hidden, SnapDir::Visible, SnapDir::Hidden],
+            *engine.snap_dir.borrow()
+        );
+
+        let result = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+            engine.with_snapshot_mounted("z/fs@monday", |_| panic!("oops"))
+        }));
+        assert!(result.is_err());
+        assert_eq!(Some(&SnapDir::Hidden), engine.snap_dir.borrow().last());
+
+        // Already visible, nothing to restore.
+        engine.snap_dir.borrow_mut().push(SnapDir::Visible);
+        engine.with_snapshot_mounted("z/fs@monday", |_| ()).unwrap();
+        assert_eq!(6, engine.snap_dir.borrow().len());
+
+        let err = engine
+            .with_snapshot_mounted("z/fs@sunday", |_| ())
+            .unwrap_err();
+        assert_eq!(Error::DatasetNotFound(PathBuf::from("z/fs@sunday")), err);
+    }
+
+    #[test]
+    fn with_snapshot_mounted_refuses_volumes() {
+        let engine = SnapDirs {
+            root: PathBuf::from("/nonexistent"),
+            snap_dir: std::cell::RefCell::new(vec![super::SnapDir::Hidden]),
+        };
+        let err = engine
+            .with_snapshot_mounted("z/vol@monday", |_| ())
+            .unwrap_err();
+        let expected = ValidationError::WrongDatasetKind {
+            dataset: PathBuf::from("z/vol"),
+            expected: DatasetKind::Filesystem,
+            found: DatasetKind::Volume,
+        };
+        assert_eq!(Error::ValidationErrors(vec![expected]), err);
+
+        let err = engine.with_snapshot_mounted("z/fs", |_| ()).unwrap_err();
+        assert_eq!(ErrorKind::ValidationErrors, err.kind());
+    }
+
+    /// Engine with deferred snapshots: `z/fs@held` has holds, `z/fs@cloned` has clones and
+    /// `z/fs@gone` is destroyed right after it's listed.
+    struct Deferred;
+
+    impl ZfsEngine for Deferred {
+        fn exists<N: Into<PathBuf>>(&self, name: N) -> Result<bool> {
+            Ok(name.into() != PathBuf::from("z/fs@gone"))
+        }
+
+        fn deferred_snapshots<N: Into<PathBuf>>(&self, root: N) -> Result<Vec<PathBuf>> {
+            assert_eq!(PathBuf::from("z"), root.into());
+            Ok(vec![
+                PathBuf::from("z/fs@held"),
+                PathBuf::from("z/fs@gone"),
+                PathBuf::from("z/fs@cloned"),
+            ])
+        }
+
+        fn holds<N: Into<PathBuf>>(&self, snapshot: N) -> Result<Vec<String>> {
+            let snapshot = snapshot.into();
+            match snapshot.to_str().unwrap() {
+                "z/fs@held" => Ok(vec![String::from("backup"), String::from("replication")]),
+                "z/fs@gone" => Err(Error::Io(std::io::Error::from_raw_os_error(libc::ENOENT))),
+                _ => Ok(Vec::new()),
+            }
+        }
+
+        fn get_property<N: Into<PathBuf>, T: super::PropertyValue>(
+            &self,
+            dataset: N,
+            property: &str,
+        ) -> Result<T> {
+            assert_eq!("clones", property);
+            let value = match dataset.into().to_str().unwrap() {
+                "z/fs@cloned" => "z/b,z/a",
+                _ => "",
+            };
+            T::from_value(property, value)
+        }
+    }
+
+    #[test]
+    fn reap_deferred_reports_pins() {
+        use super::DeferredSnapshot;
+
+        let report = Deferred.reap_deferred("z").unwrap();
+        let expected = vec![
+            DeferredSnapshot {
+                snapshot: PathBuf::from("z/fs@held"),
+                holds: vec![String::from("backup"), String::from("replication")],
+                clones: Vec::new(),
+            },
+            DeferredSnapshot {
+                snapshot: PathBuf::from("z/fs@cloned"),
+                holds: Vec::new(),
+                clones: vec![PathBuf::from("z/a"), PathBuf::from("z/b")],
+            },
+        ];
+        assert_eq!(expected, report);
+    }
+
+    /// Engine that lists snapshots out of `createtxg` order.
+    struct Txgs;
+
+    impl ZfsEngine for Txgs {
+        fn list_snapshots_of<N: Into<PathBuf>>(&self, dataset: N) -> Result<Vec<PathBuf>> {
+            match dataset.into().to_str().unwrap() {
+                "z/empty" => Ok(Vec::new()),
+                "z/one" => Ok(vec![PathBuf::from("z/one@only")]),
+                "z/many" => Ok(vec![
+                    PathBuf::from("z/many@b"),
+                    PathBuf::from("z/many@c"),
+                    PathBuf::from("z/many@a"),
+                ]),
+                other => Err(Error::DatasetNotFound(PathBuf::from(other))),
+            }
+        }
+
+        fn get_property<N: Into<PathBuf>, T: super::PropertyValue>(
+            &self,
+            dataset: N,
+            property: &str,
+        ) -> Result<T> {
+            assert_eq!("createtxg", property);
+            let value = match dataset.into().to_str().unwrap() {
+                "z/many@a" => "9",
+                "z/many@b" => "100",
+                "z/many@c" => "12",
+                _ => "1",
+            };
+            T::from_value(property, value)
+        }
+    }
+
+    #[test]
+    fn latest_and_oldest_snapshot_by_txg() {
+        assert_eq!(Ok(None), Txgs.latest_snapshot("z/empty"));
+        assert_eq!(Ok(None), Txgs.oldest_snapshot("z/empty"));
+        assert_eq!(
+            Ok(Some(PathBuf::from("z/one@only"))),
+            Txgs.latest_snapshot("z/one")
+        );
+        assert_eq!(
+            Ok(Some(PathBuf::from("z/one@only"))),
+            Txgs.oldest_snapshot("z/one")
+        );
+        assert_eq!(
+            Ok(Some(PathBuf::from("z/many@b"))),
+            Txgs.latest_snapshot("z/many")
+        );
+        assert_eq!(
+            Ok(Some(PathBuf::from("z/many@a"))),
+            Txgs.oldest_snapshot("z/many")
+        );
+        assert_eq!(
+            Err(Error::DatasetNotFound(PathBuf::from("z/missing"))),
+            Txgs.latest_snapshot("z/missing")
+        );
+    }
+
+    /// Engine where only `z/fs` exists.
+    struct OnlyFs;
+
+    impl ZfsEngine for OnlyFs {
+        fn exists<N: Into<PathBuf>>(&self, name: N) -> Result<bool> {
+            Ok(name.into() == PathBuf::from("z/fs"))
+        }
+    }
+
+    #[test]
+    fn exists_all_keeps_order_and_duplicates() {
+        let answers = OnlyFs.exists_all(vec!["z/fsx", "z/fs", "z/fs"]).unwrap();
+        let expected = vec![
+            (PathBuf::from("z/fsx"), false),
+            (PathBuf::from("z/fs"), true),
+            (PathBuf::from("z/fs"), true),
+        ];
+        assert_eq!(expected, answers);
+    }
 }
@@ -0,0 +1,592 @@
+//! Names of automatic snapshots.
+//!
+//! [`SnapshotNameTemplate`](struct.SnapshotNameTemplate.html) turns a template like
+//! `auto-%Y%m%d-%H%M%S` into a short snapshot name. Besides `strftime` specifiers of `chrono` it
+//! knows two tokens of its own:
+//!
+//!  - `%uuid4` - a random UUID, e.g. `5c0f3f8e-5b1d-4c4e-9d35-8c1f0e6f9a31`.
+//!  - `%seq` - sequence number used to tell apart names that would be the same otherwise,
+//!    starting at `0`. Templates without `%seq` get `-1`, `-2`, ... appended instead, the first
+//!    name has no suffix.
+//!
+//! [`next_available`](fn.next_available.html) finds the first sequence number that isn't taken
+//! and [`snapshot_next`](fn.snapshot_next.html) creates the snapshot right away, moving on to the
+//! next number if another run took the name in between.
+//!
+//! Snapshot of a deeply nested dataset has less room for its name:
+//! [`length_budget`](fn.length_budget.html) tells how long a name can be to fit every dataset of a
+//! recursive snapshot, and templates limited with it refuse to render anything longer.
+//!
+//! ### Usage
+//! ```rust,no_run
+//! use libzetta::zfs::{
+//!     naming::{self, SnapshotNameTemplate},
+//!     DelegatingZfsEngine, ZfsEngine,
+//! };
+//!
+//! let engine = DelegatingZfsEngine::new().unwrap();
+//! let datasets: Vec<_> = engine
+//!     .list("tank/usr/home")
+//!     .unwrap()
+//!     .into_iter()
+//!     .map(|(_, dataset)| dataset)
+//!     .collect();
+//! let template = SnapshotNameTemplate::new("auto-%Y-%m-%d-%H%M")
+//!     .unwrap()
+//!     .with_length_budget(naming::length_budget(&datasets));
+//! let snapshot = naming::snapshot_next(&engine, "tank/usr/home", &template, None).unwrap();
+//! println!("created {}", snapshot.display());
+//! ```
+use std::{
+    collections::HashMap,
+    fmt,
+    path::{Path, PathBuf},
+};
+
+use chrono::{
+    format::{Item, StrftimeItems},
+    DateTime, Local, TimeZone, Utc,
+};
+
+use crate::zfs::{
+    validators, Error, Result, ValidationError, ValidationResult, ZfsEngine,
+    DATASET_NAME_MAX_LENGTH,
+};
+
+/// How many times [`snapshot_next`](fn.snapshot_next.html) tries the next name after the one it
+/// picked was taken before it could create it.
+pub const MAX_ATTEMPTS: usize = 16;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    /// Text and `strftime` specifiers.
+    Time(String),
+    Uuid4,
+    Seq,
+}
+
+/// Template of short snapshot names. See [module documentation](index.html) for tokens.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SnapshotNameTemplate {
+    template: String,
+    tokens: Vec<Token>,
+    max_len: usize,
+}
+
+impl SnapshotNameTemplate {
+    /// Parse `template`. Fails with `InvalidNameTemplate` if it has a specifier `chrono` doesn't
+    /// know and with `InvalidCharacter` if it renders characters ZFS doesn't allow in names, e.g.
+    /// `/` of `%D`.
+    pub fn new(template: &str) -> ValidationResult<SnapshotNameTemplate> {
+        let mut tokens = Vec::new();
+        let mut time = String::new();
+        let mut rest = template;
+        while let Some(idx) = rest.find('%') {
+            time.push_str(&rest[..idx]);
+            rest = &rest[idx..];
+            let token = if rest.starts_with("%uuid4") {
+                Token::Uuid4
+            } else if rest.starts_with("%seq") {
+                Token::Seq
+            } else {
+                // `%%` has to stay together, so `%%seq` is a literal `%seq`.
+                let len = if rest.starts_with("%%") { 2 } else { 1 };
+                time.push_str(&rest[..len]);
+                rest = &rest[len..];
+                continue;
+            };
+            rest = &rest[if token == Token::Seq { 4 } else { 6 }..];
+            if !time.is_empty() {
+                tokens.push(Token::Time(std::mem::take(&mut time)));
+            }
+            tokens.push(token);
+        }
+        time.push_str(rest);
+        if !time.is_empty() {
+            tokens.push(Token::Time(time));
+        }
+        let invalid = tokens.iter().any(|token| match token {
+            Token::Time(format) => StrftimeItems::new(format).any(|item| item == Item::Error),
+            _ => false,
+        });
+        if invalid {
+            return Err(ValidationError::InvalidNameTemplate(template.to_string()));
+        }
+        let parsed = SnapshotNameTemplate {
+            template: template.to_string(),
+            tokens,
+            max_len: DATASET_NAME_MAX_LENGTH,
+        };
+        parsed.render(&Utc.timestamp_opt(0, 0).unwrap())?;
+        Ok(parsed)
+    }
+
+    /// Refuse to render names longer than `max_len`, e.g. what
+    /// [`length_budget`](fn.length_budget.html) allows.
+    pub fn with_length_budget(mut self, max_len: usize) -> SnapshotNameTemplate {
+        self.max_len = max_len;
+        self
+    }
+
+    /// Template this was parsed from.
+    pub fn template(&self) -> &str {
+        &self.template
+    }
+
+    /// Longest name this template renders.
+    pub fn max_len(&self) -> usize {
+        self.max_len
+    }
+
+    /// Whether template has `%seq` in it.
+    pub fn has_sequence(&self) -> bool {
+        self.tokens.contains(&Token::Seq)
+    }
+
+    /// Name for a snapshot taken at `now`, with sequence number `0`.
+    pub fn render<Tz: TimeZone>(&self, now: &DateTime<Tz>) -> ValidationResult<String>
+    where
+        Tz::Offset: fmt::Display,
+    {
+        self.render_seq(now, 0)
+    }
+
+    /// Name for a snapshot taken at `now` with sequence number `seq`. Fails if name has
+    /// characters ZFS doesn't allow, is empty or is longer than the length budget.
+    pub fn render_seq<Tz: TimeZone>(&self, now: &DateTime<Tz>, seq: u32) -> ValidationResult<String>
+    where
+        Tz::Offset: fmt::Display,
+    {
+        let mut name = String::new();
+        for token in &self.tokens {
+            match token {
+                Token::Time(format) => name.push_str(&now.format(format).to_string()),
+                Token::Uuid4 => name.push_str(&uuid4()),
+                Token::Seq => name.push_str(&seq.to_string()),
+            }
+        }
+        if seq > 0 && !self.has_sequence() {
+            name.push_str(&format!("-{}", seq));
+        }
+        validate_short_name(&name, self.max_len)?;
+        Ok(name)
+    }
+}
+
+/// How long a short snapshot name can be to snapshot every dataset in `datasets`: what is left of
+/// the name length limit after the longest dataset name and `@`.
+pub fn length_budget<P: AsRef<Path>>(datasets: &[P]) -> usize {
+    let longest = datasets
+        .iter()
+        .map(|dataset| dataset.as_ref().as_os_str().len())
+        .max()
+        .unwrap_or(0);
+    DATASET_NAME_MAX_LENGTH.saturating_sub(longest + 1)
+}
+
+/// First snapshot of `dataset` rendered from `template` for the current local time that doesn't
+/// exist yet. Someone else can take the name before it's created, so prefer
+/// [`snapshot_next`](fn.snapshot_next.html) for creating snapshots.
+pub fn next_available<E: ZfsEngine + ?Sized, P: AsRef<Path>>(
+    engine: &E,
+    dataset: P,
+    template: &SnapshotNameTemplate,
+) -> Result<PathBuf> {
+    first_free(engine, dataset.as_ref(), template, &Local::now(), 0).map(|(snapshot, _)| snapshot)
+}
+
+/// Create the first snapshot of `dataset` rendered from `template` for the current local time
+/// that doesn't exist yet. If the name is taken between the check and creation, the next one is
+/// tried, up to [`MAX_ATTEMPTS`](constant.MAX_ATTEMPTS.html) times. Returns the snapshot.
+pub fn snapshot_next<E: ZfsEngine + ?Sized, P: AsRef<Path>>(
+    engine: &E,
+    dataset: P,
+    template: &SnapshotNameTemplate,
+    user_properties: Option<HashMap<String, String>>,
+) -> Result<PathBuf> {
+    snapshot_next_at(
+        engine,
+        dataset.as_ref(),
+        template,
+        &Local::now(),
+        user_properties,
+    )
+}
+
+fn snapshot_next_at<E: ZfsEngine + ?Sized, Tz: TimeZone>(
+    engine: &E,
+    dataset: &Path,
+    template: &SnapshotNameTemplate,
+    now: &DateTime<Tz>,
+    user_properties: Option<HashMap<String, String>>,
+) -> Result<PathBuf>
+where
+    Tz::Offset: fmt::Display,
+{
+    let mut from = 0;
+    let mut attempt = 1;
+    loop {
+        let (snapshot, seq) = first_free(engine, dataset, template, now, from)?;
+        match engine.snapshot(std::slice::from_ref(&snapshot), user_properties.clone()) {
+            Ok(()) => return Ok(snapshot),
+            Err(ref e) if is_name_taken(e) && attempt < MAX_ATTEMPTS => {
+                from = seq + 1;
+                attempt += 1;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+fn first_free<E: ZfsEngine + ?Sized, Tz: TimeZone>(
+    engine: &E,
+    dataset: &Path,
+    template: &SnapshotNameTemplate,
+    now: &DateTime<Tz>,
+    from: u32,
+) -> Result<(PathBuf, u32)>
+where
+    Tz::Offset: fmt::Display,
+{
+    let mut seq = from;
+    loop {
+        let name = template.render_seq(now, seq)?;
+        let snapshot = validators::validate_recursive_snapshot(dataset, &name)?;
+        if !engine.exists(snapshot.clone())? {
+            return Ok((snapshot, seq));
+        }
+        seq = seq.checked_add(1).ok_or(Error::DatasetExists(snapshot))?;
+    }
+}
+
+/// `ZfsOpen3` reports a taken name with `DatasetExists`, `ZfsLzc` with `EEXIST`.
+fn is_name_taken(err: &Error) -> bool {
+    match err {
+        Error::DatasetExists(_) => true,
+        Error::SnapshotsFailed(errors) => {
+            !errors.is_empty() && errors.iter().all(|(_, errno)| *errno == libc::EEXIST)
+        }
+        _ => false,
+    }
+}
+
+fn validate_short_name(name: &str, max_len: usize) -> ValidationResult {
+    let path = PathBuf::from(name);
+    if name.is_empty() {
+        return Err(ValidationError::MissingSnapshotName(path));
+    }
+    if let Some(ch) = name.chars().find(|ch| !validators::is_valid_char(*ch)) {
+        return Err(ValidationError::InvalidCharacter {
+            dataset: path,
+            component: name.to_string(),
+            ch,
+        });
+    }
+    if name.len() > max_len {
+        return Err(ValidationError::NameTooLong(path));
+    }
+    Ok(())
+}
+
+/// Random version 4 UUID.
+fn uuid4() -> String {
+    let mut bits: u128 = rand::random();
+    // Version 4 and RFC 4122 variant.
+    bits = (bits & !(0xf << 76)) | (0x4 << 76);
+    bits = (bits & !(0x3 << 62)) | (0x2 << 62);
+    format!(
+        "{:08x}-{:04x}-{:04x}-{:04x}-{:012x}",
+        bits >> 96,
+        (bits >> 80) & 0xffff,
+        (bits >> 64) & 0xffff,
+        (bits >> 48) & 0xffff,
+        bits & 0xffff_ffff_ffff
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use std::{cell::RefCell, collections::HashSet};
+
+    use regex::Regex;
+
+    use super::*;
+
+    fn at(y: i32, mo: u32, d: u32, h: u32, mi: u32, s: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(y, mo, d, h, mi, s).unwrap()
+    }
+
+    fn render(template: &str) -> String {
+        SnapshotNameTemplate::new(template)
+            .unwrap()
+            .render(&at(2024, 5, 1, 3, 0, 7))
+            .unwrap()
+    }
+
+    #[test]
+    fn time_tokens() {
+        assert_eq!("auto-20240501-030007", render("auto-%Y%m%d-%H%M%S"));
+        assert_eq!("auto-2024-05-01-0300", render("auto-%Y-%m-%d-%H%M"));
+        assert_eq!("daily_May.Wed", render("daily_%b.%a"));
+        assert_eq!("plain", render("plain"));
+        assert_eq!("100%", render("100%%"));
+        assert_eq!("%seq", render("%%seq"));
+        assert_eq!("1714532407", render("%s"));
+    }
+
+    #[test]
+    fn sequence() {
+        let now = at(2024, 5, 1, 3, 0, 7);
+        let explicit = SnapshotNameTemplate::new("auto-%Y%m%d.%seq").unwrap();
+        assert!(explicit.has_sequence());
+        assert_eq!("auto-20240501.0", explicit.render(&now).unwrap());
+        assert_eq!("auto-20240501.12", explicit.render_seq(&now, 12).unwrap());
+
+        let appended = SnapshotNameTemplate::new("auto-%Y%m%d").unwrap();
+        assert!(!appended.has_sequence());
+        assert_eq!("auto-20240501", appended.render_seq(&now, 0).unwrap());
+        assert_eq!("auto-20240501-1", appended.render_seq(&now, 1).unwrap());
+        assert_eq!("auto-20240501-42", appended.render_seq(&now, 42).unwrap());
+
+        let only = SnapshotNameTemplate::new("%seq%seq").unwrap();
+        assert_eq!("33", only.render_seq(&now, 3).unwrap());
+    }
+
+    #[test]
+    fn uuid_token() {
+        let re = Regex::new(
+            r"^backup-[0-9a-f]{8}-[0-9a-f]{4}-4[0-9a-f]{3}-[89ab][0-9a-f]{3}-[0-9a-f]{12}$",
+        )
+        .unwrap();
+        let template = SnapshotNameTemplate::new("backup-%uuid4").unwrap();
+        let now = at(2024, 5, 1, 3, 0, 7);
+        let names: HashSet<String> = (0..64).map(|_| template.render(&now).unwrap()).collect();
+        assert_eq!(64, names.len());
+        assert!(names.iter().all(|name| re.is_match(name)), "{:?}", names);
+    }
+
+    #[test]
+    fn invalid_templates() {
+        for template in &["auto-%Q", "auto-%", "%Ez"] {
+            assert_eq!(
+                Err(ValidationError::InvalidNameTemplate(template.to_string())),
+                SnapshotNameTemplate::new(template),
+                "{}",
+                template
+            );
+        }
+        assert_eq!(
+            Err(ValidationError::InvalidCharacter {
+                dataset: PathBuf::from("auto 01/01/70"),
+                component: String::from("auto 01/01/70"),
+                ch: '/',
+            }),
+            SnapshotNameTemplate::new("auto %D")
+        );
+        assert_eq!(
+            Err(ValidationError::InvalidCharacter {
+                dataset: PathBuf::from("01/01/70"),
+                component: String::from("01/01/70"),
+                ch: '/',
+            }),
+            SnapshotNameTemplate::new("%D")
+        );
+        assert_eq!(
+            Err(ValidationError::InvalidCharacter {
+                dataset: PathBuf::from("a@b"),
+                component: String::from("a@b"),
+                ch: '@',
+            }),
+            SnapshotNameTemplate::new("a@b")
+        );
+        assert_eq!(
+            Err(ValidationError::MissingSnapshotName(PathBuf::new())),
+            SnapshotNameTemplate::new("")
+        );
+    }
+
+    #[test]
+    fn length_budget_of_datasets() {
+        let empty: [&str; 0] = [];
+        assert_eq!(DATASET_NAME_MAX_LENGTH - 1, length_budget(&empty));
+
+        let datasets = vec![
+            PathBuf::from("tank"),
+            PathBuf::from("tank/usr/home/someone"),
+            PathBuf::from("tank/usr"),
+        ];
+        assert_eq!(
+            DATASET_NAME_MAX_LENGTH - "tank/usr/home/someone@".len(),
+            length_budget(&datasets)
+        );
+
+        let deep = "d".repeat(DATASET_NAME_MAX_LENGTH);
+        assert_eq!(0, length_budget(&[deep.as_str()]));
+
+        // A name that exactly fills the budget passes full validation.
+        let longest = format!("tank/{}", "x".repeat(200));
+        let budget = length_budget(&[longest.as_str(), "tank"]);
+        assert_eq!(DATASET_NAME_MAX_LENGTH - 206, budget);
+        let name = "n".repeat(budget);
+        let template = SnapshotNameTemplate::new(&name)
+            .unwrap()
+            .with_length_budget(budget);
+        let rendered = template.render(&Utc::now()).unwrap();
+        let snapshot = validators::validate_recursive_snapshot(Path::new(&longest), &rendered);
+        assert!(snapshot.is_ok());
+    }
+
+    #[test]
+    fn length_budget_is_enforced() {
+        let now = at(2024, 5, 1, 3, 0, 7);
+        let template = SnapshotNameTemplate::new("auto-%Y%m%d")
+            .unwrap()
+            .with_length_budget(13);
+        assert_eq!(13, template.max_len());
+        assert_eq!("auto-20240501", template.render(&now).unwrap());
+        // Sequence suffix counts too.
+        assert_eq!(
+            Err(ValidationError::NameTooLong(PathBuf::from(
+                "auto-20240501-1"
+            ))),
+            template.render_seq(&now, 1)
+        );
+
+        let uuid = SnapshotNameTemplate::new("%uuid4")
+            .unwrap()
+            .with_length_budget(35);
+        assert!(matches!(
+            uuid.render(&now),
+            Err(ValidationError::NameTooLong(_))
+        ));
+
+        let fits = SnapshotNameTemplate::new("%uuid4")
+            .unwrap()
+            .with_length_budget(36);
+        assert_eq!(36, fits.render(&now).unwrap().len());
+    }
+
+    /// Engine with some snapshots in place. Names in `racing` show up right before they are
+    /// created, like another run took them, and are reported with `errno` if it's set or
+    /// `DatasetExists` otherwise.
+    #[derive(Default)]
+    struct Snapshots {
+        existing: RefCell<HashSet<PathBuf>>,
+        racing: HashSet<PathBuf>,
+        errno: Option<i32>,
+        created: RefCell<Vec<PathBuf>>,
+    }
+
+    impl Snapshots {
+        fn with(existing: &[&str]) -> Snapshots {
+            Snapshots {
+                existing: RefCell::new(existing.iter().map(PathBuf::from).collect()),
+                ..Snapshots::default()
+            }
+        }
+    }
+
+    impl ZfsEngine for Snapshots {
+        fn exists<N: Into<PathBuf>>(&self, name: N) -> Result<bool> {
+            Ok(self.existing.borrow().contains(&name.into()))
+        }
+
+        fn snapshot(
+            &self,
+            snapshots: &[PathBuf],
+            _user_properties: Option<HashMap<String, String>>,
+        ) -> Result<()> {
+            let snapshot = snapshots[0].clone();
+            if self.racing.contains(&snapshot) || self.existing.borrow().contains(&snapshot) {
+                self.existing.borrow_mut().insert(snapshot.clone());
+                return Err(match self.errno {
+                    Some(errno) => Error::SnapshotsFailed(vec![(snapshot, errno)]),
+                    None => Error::DatasetExists(snapshot),
+                });
+            }
+            self.existing.borrow_mut().insert(snapshot.clone());
+            self.created.borrow_mut().push(snapshot);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn first_free_name() {
+        let now = at(2024, 5, 1, 3, 0, 7);
+        let template = SnapshotNameTemplate::new("auto-%Y%m%d").unwrap();
+        let dataset = Path::new("tank/fs");
+
+        let engine = Snapshots::with(&[]);
+        assert_eq!(
+            (PathBuf::from("tank/fs@auto-20240501"), 0),
+            first_free(&engine, dataset, &template, &now, 0).unwrap()
+        );
+
+        let engine = Snapshots::with(&["tank/fs@auto-20240501", "tank/fs@auto-20240501-1"]);
+        assert_eq!(
+            (PathBuf::from("tank/fs@auto-20240501-2"), 2),
+            first_free(&engine, dataset, &template, &now, 0).unwrap()
+        );
+        let next = next_available(&engine, dataset, &template).unwrap();
+        assert!(next.to_str().unwrap().starts_with("tank/fs@auto-"));
+
+        let err = first_free(&engine, Path::new("tank/fs@snap"), &template, &now, 0).unwrap_err();
+        assert_eq!(crate::zfs::ErrorKind::ValidationErrors, err.kind());
+    }
+
+    #[test]
+    fn snapshot_next_skips_taken_names() {
+        let now = at(2024, 5, 1, 3, 0, 7);
+        let template = SnapshotNameTemplate::new("auto-%Y%m%d.%seq").unwrap();
+        let dataset = Path::new("tank/fs");
+
+        let engine = Snapshots::with(&["tank/fs@auto-20240501.0"]);
+        let snapshot = snapshot_next_at(&engine, dataset, &template, &now, None).unwrap();
+        assert_eq!(PathBuf::from("tank/fs@auto-20240501.1"), snapshot);
+
+        // Two names are taken between the check and creation.
+        for errno in &[None, Some(libc::EEXIST)] {
+            let engine = Snapshots {
+                racing: vec!["tank/fs@auto-20240501.1", "tank/fs@auto-20240501.2"]
+                    .into_iter()
+                    .map(PathBuf::from)
+                    .collect(),
+                errno: *errno,
+                ..Snapshots::with(&["tank/fs@auto-20240501.0"])
+            };
+            let snapshot = snapshot_next_at(&engine, dataset, &template, &now, None).unwrap();
+            assert_eq!(PathBuf::from("tank/fs@auto-20240501.3"), snapshot);
+            assert_eq!(vec![snapshot], *engine.created.borrow());
+        }
+    }
+
+    #[test]
+    fn snapshot_next_gives_up() {
+        let now = at(2024, 5, 1, 3, 0, 7);
+        let template = SnapshotNameTemplate::new("auto.%seq").unwrap();
+        let dataset = Path::new("tank/fs");
+
+        let engine = Snapshots {
+            racing: (0..=MAX_ATTEMPTS)
+                .map(|seq| PathBuf::from(format!("tank/fs@auto.{}", seq)))
+                .collect(),
+            ..Snapshots::default()
+        };
+        let err = snapshot_next_at(&engine, dataset, &template, &now, None).unwrap_err();
+        let last = format!("tank/fs@auto.{}", MAX_ATTEMPTS - 1);
+        assert_eq!(Error::DatasetExists(PathBuf::from(last)), err);
+        assert!(engine.created.borrow().is_empty());
+
+        // Other errors are not retried.
+        let engine = Snapshots {
+            racing: vec![PathBuf::from("tank/fs@auto.0")].into_iter().collect(),
+            errno: Some(libc::EDQUOT),
+            ..Snapshots::default()
+        };
+        let err = snapshot_next_at(&engine, dataset, &template, &now, None).unwrap_err();
+        assert_eq!(
+            Error::SnapshotsFailed(vec![(PathBuf::from("tank/fs@auto.0"), libc::EDQUOT)]),
+            err
+        );
+    }
+}
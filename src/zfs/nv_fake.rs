@@ -0,0 +1,522 @@
+//! In-memory stand-in for `libnv::nvpair`, enabled with `nv-fake` feature.
+//!
+//! Code that builds nvlists for `libzfs_core` is hard to unit test because `libnv` needs native
+//! `libnvpair` at link time. With `nv-fake` enabled `libzetta::zfs::nv` points here instead
+//! and nvlists live in plain Rust memory, so such tests run anywhere. Signatures are the same as
+//! in `libnv` 0.4.3, `Value`, `NvFlag` and `NvEncoding` are `libnv`'s own, so code that compiles
+//! against the fake compiles against `libnv`. `save_as_json` and `NvPairRef::from_ptr` are left
+//! out, there is nothing behind a pointer here. Semantics match `libnvpair`: names are unique
+//! according to list flags, lookup returns the first pair of the name and type and copies are
+//! deep. Lists made here can't be handed to `libzfs_core`, that's why this is refused in release
+//! builds.
+//!
+//! ```rust
+//! # #[cfg(feature = "nv-fake")] {
+//! use libzetta::zfs::nv::{NvFlag, NvList, Value};
+//!
+//! let mut list = NvList::new(NvFlag::None).unwrap();
+//! list.insert_u64("quota", 1024).unwrap();
+//! list.insert_u64("quota", 2048).unwrap();
+//! assert_eq!(1024, list.get_u64("quota").unwrap());
+//! assert!(list.exists("quota").unwrap());
+//! assert_eq!(Some(&Value::Uint64(1024)), list.into_hashmap().get("quota"));
+//! # }
+//! ```
+use std::{
+    collections::HashMap,
+    ffi::{CStr, CString},
+    fmt,
+    mem::discriminant,
+    ptr::null_mut,
+};
+
+pub use libnv::nvpair::{NvEncoding, NvFlag, Value};
+use libnv::{IntoCStr, NvError, NvResult};
+use nvpair_sys as sys;
+
+/// Same as `libnv::nvpair::NvTypeOp`: types that can be added with
+/// [`NvList::insert`](struct.NvList.html#method.insert).
+pub trait NvTypeOp {
+    /// Add self to given list.
+    fn add_to_list<'a, N: IntoCStr<'a>>(&self, list: &mut NvList, name: N) -> NvResult<()>;
+}
+
+/// What a pair holds. Variants map to `data_type_t`, pairs of different variants are of
+/// different types even if they read back as the same `Value`.
+enum Data {
+    Boolean,
+    BooleanValue(bool),
+    Int8(i8),
+    Uint8(u8),
+    Int16(i16),
+    Uint16(u16),
+    Int32(i32),
+    Uint32(u32),
+    Int64(i64),
+    Uint64(u64),
+    Int8Array(Vec<i8>),
+    Uint8Array(Vec<u8>),
+    Int16Array(Vec<i16>),
+    Uint16Array(Vec<u16>),
+    Int32Array(Vec<i32>),
+    Uint32Array(Vec<u32>),
+    Int64Array(Vec<i64>),
+    Uint64Array(Vec<u64>),
+    String(CString),
+    NvList(NvList),
+}
+
+impl Data {
+    fn dup(&self) -> Data {
+        match self {
+            Data::Boolean => Data::Boolean,
+            Data::BooleanValue(v) => Data::BooleanValue(*v),
+            Data::Int8(v) => Data::Int8(*v),
+            Data::Uint8(v) => Data::Uint8(*v),
+            Data::Int16(v) => Data::Int16(*v),
+            Data::Uint16(v) => Data::Uint16(*v),
+            Data::Int32(v) => Data::Int32(*v),
+            Data::Uint32(v) => Data::Uint32(*v),
+            Data::Int64(v) => Data::Int64(*v),
+            Data::Uint64(v) => Data::Uint64(*v),
+            Data::Int8Array(v) => Data::Int8Array(v.clone()),
+            Data::Uint8Array(v) => Data::Uint8Array(v.clone()),
+            Data::Int16Array(v) => Data::Int16Array(v.clone()),
+            Data::Uint16Array(v) => Data::Uint16Array(v.clone()),
+            Data::Int32Array(v) => Data::Int32Array(v.clone()),
+            Data::Uint32Array(v) => Data::Uint32Array(v.clone()),
+            Data::Int64Array(v) => Data::Int64Array(v.clone()),
+            Data::Uint64Array(v) => Data::Uint64Array(v.clone()),
+            Data::String(v) => Data::String(v.clone()),
+            Data::NvList(v) => Data::NvList(v.dup()),
+        }
+    }
+
+    /// Same as `NvPairRef::value` of `libnv`: arrays and nested lists are `Unknown`.
+    fn value(&self) -> Value {
+        match self {
+            Data::Boolean => Value::Bool(true),
+            Data::BooleanValue(v) => Value::Bool(*v),
+            Data::Int8(v) => Value::Int8(*v),
+            Data::Uint8(v) => Value::Uint8(*v),
+            Data::Int16(v) => Value::Int16(*v),
+            Data::Uint16(v) => Value::Uint16(*v),
+            Data::Int32(v) => Value::Int32(*v),
+            Data::Uint32(v) => Value::Uint32(*v),
+            Data::Int64(v) => Value::Int64(*v),
+            Data::Uint64(v) => Value::Uint64(*v),
+            Data::String(v) => Value::String(v.to_string_lossy().into_owned()),
+            Data::Int8Array(_)
+            | Data::Uint8Array(_)
+            | Data::Int16Array(_)
+            | Data::Uint16Array(_)
+            | Data::Int32Array(_)
+            | Data::Uint32Array(_)
+            | Data::Int64Array(_)
+            | Data::Uint64Array(_)
+            | Data::NvList(_) => Value::Unknown,
+        }
+    }
+}
+
+struct Pair {
+    name: CString,
+    data: Data,
+}
+
+pub struct NvList {
+    flags: NvFlag,
+    pairs: Vec<Pair>,
+}
+
+/// Return new list with no flags.
+impl Default for NvList {
+    fn default() -> NvList {
+        NvList::new(NvFlag::UniqueNameType).expect("Failed to create new list")
+    }
+}
+
+macro_rules! impl_list_op {
+    ($type_:ty, $method:ident) => {
+        impl NvTypeOp for $type_ {
+            fn add_to_list<'a, N: IntoCStr<'a>>(&self, list: &mut NvList, name: N) -> NvResult<()> {
+                list.$method(name, *self)
+            }
+        }
+    };
+}
+
+macro_rules! nvpair_type_method {
+    ($type_:ty, $variant:ident, $insert:ident, $get:ident) => {
+        /// Add `$type_` value to the list.
+        pub fn $insert<'a, N: IntoCStr<'a>>(&mut self, name: N, value: $type_) -> NvResult<()> {
+            self.add(name, Data::$variant(value))
+        }
+
+        /// Get a `$type_` value by given name from the list.
+        pub fn $get<'a, N: IntoCStr<'a>>(&self, name: N) -> NvResult<$type_> {
+            match self.lookup(name, &Data::$variant(Default::default()))? {
+                Data::$variant(value) => Ok(*value),
+                _ => Err(NvError::NotFound),
+            }
+        }
+    };
+}
+
+macro_rules! nvpair_type_array_method {
+    ($type_:ty, $variant:ident, $insert:ident, $get:ident) => {
+        /// Add `&[$type_]` value to the list.
+        pub fn $insert<'a, N: IntoCStr<'a>>(
+            &mut self,
+            name: N,
+            value: &mut [$type_],
+        ) -> NvResult<()> {
+            self.add(name, Data::$variant(value.to_vec()))
+        }
+
+        /// Get a `$type_` value by given name from the list.
+        pub fn $get<'a, 'b, N: IntoCStr<'b>>(&'a self, name: N) -> NvResult<&'a [$type_]> {
+            match self.lookup(name, &Data::$variant(Vec::new()))? {
+                Data::$variant(value) => Ok(value.as_slice()),
+                _ => Err(NvError::NotFound),
+            }
+        }
+    };
+}
+
+impl NvList {
+    nvpair_type_method!(i8, Int8, insert_i8, get_i8);
+
+    nvpair_type_method!(u8, Uint8, insert_u8, get_u8);
+
+    nvpair_type_method!(i16, Int16, insert_i16, get_i16);
+
+    nvpair_type_method!(u16, Uint16, insert_u16, get_u16);
+
+    nvpair_type_method!(i32, Int32, insert_i32, get_i32);
+
+    nvpair_type_method!(u32, Uint32, insert_u32, get_u32);
+
+    nvpair_type_method!(i64, Int64, insert_i64, get_i64);
+
+    nvpair_type_method!(u64, Uint64, insert_u64, get_u64);
+
+    nvpair_type_array_method!(i8, Int8Array, insert_i8_array, get_i8_array);
+
+    nvpair_type_array_method!(u8, Uint8Array, insert_u8_array, get_u8_array);
+
+    nvpair_type_array_method!(i16, Int16Array, insert_i16_array, get_i16_array);
+
+    nvpair_type_array_method!(u16, Uint16Array, insert_u16_array, get_u16_array);
+
+    nvpair_type_array_method!(i32, Int32Array, insert_i32_array, get_i32_array);
+
+    nvpair_type_array_method!(u32, Uint32Array, insert_u32_array, get_u32_array);
+
+    nvpair_type_array_method!(i64, Int64Array, insert_i64_array, get_i64_array);
+
+    nvpair_type_array_method!(u64, Uint64Array, insert_u64_array, get_u64_array);
+
+    /// Always null: fake lists can't be passed to `libzfs_core`.
+    pub fn as_ptr(&self) -> *mut sys::nvlist_t {
+        null_mut()
+    }
+
+    pub fn new(flags: NvFlag) -> NvResult<Self> {
+        Ok(NvList {
+            flags,
+            pairs: Vec::new(),
+        })
+    }
+
+    /// Fake lists have nothing behind a pointer, so this is always an empty list.
+    ///
+    /// # Safety
+    /// Pointer is never dereferenced.
+    pub unsafe fn from_ptr(_ptr: *mut sys::nvlist_t) -> Self {
+        NvList::default()
+    }
+
+    /// Pairs in order they were added. Each pair is a copy, changes of the list don't show up in
+    /// pairs taken before.
+    pub fn iter(&self) -> impl Iterator<Item = NvPairRef> + '_ {
+        self.pairs.iter().map(|pair| NvPairRef {
+            name: pair.name.clone(),
+            data: pair.data.dup(),
+        })
+    }
+
+    /// Pairs by name. If names repeat, the last pair wins, same as with `libnv`.
+    pub fn into_hashmap(self) -> HashMap<String, Value> {
+        let mut ret = HashMap::new();
+        for pair in self.iter() {
+            let key = pair.key().to_string_lossy().to_string();
+            ret.insert(key, pair.value());
+        }
+        ret
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pairs.is_empty()
+    }
+
+    pub fn exists<'a, N: IntoCStr<'a>>(&self, name: N) -> NvResult<bool> {
+        let name = name.into_c_str()?;
+        Ok(self.pairs.iter().any(|pair| *pair.name == *name))
+    }
+
+    pub fn insert<'a, N: IntoCStr<'a>, T: NvTypeOp>(&mut self, name: N, value: T) -> NvResult<()> {
+        value.add_to_list(self, name)
+    }
+
+    /// Add a `bool` to the list.
+    pub fn insert_boolean_value<'a, N: IntoCStr<'a>>(
+        &mut self,
+        name: N,
+        value: bool,
+    ) -> NvResult<()> {
+        self.add(name, Data::BooleanValue(value))
+    }
+
+    /// Add a `bool` to the list.
+    pub fn insert_boolean<'a, N: IntoCStr<'a>>(&mut self, name: N) -> NvResult<()> {
+        self.add(name, Data::Boolean)
+    }
+
+    /// Get a `bool` from the list.
+    pub fn get_boolean_value<'a, N: IntoCStr<'a>>(&self, name: N) -> NvResult<bool> {
+        match self.lookup(name, &Data::BooleanValue(false))? {
+            Data::BooleanValue(value) => Ok(*value),
+            _ => Err(NvError::NotFound),
+        }
+    }
+
+    /// Get a `bool` from the list.
+    pub fn get_boolean<'a, N: IntoCStr<'a>>(&self, name: N) -> NvResult<bool> {
+        self.lookup(name, &Data::Boolean).map(|_| true)
+    }
+
+    /// Add a `&str` to the list.
+    pub fn insert_string<'a, 'b, N: IntoCStr<'a>, V: IntoCStr<'b>>(
+        &mut self,
+        name: N,
+        value: V,
+    ) -> NvResult<()> {
+        let value = value.into_c_str()?.into_owned();
+        self.add(name, Data::String(value))
+    }
+
+    pub fn get_cstr<'a, N: IntoCStr<'a>>(&self, name: N) -> NvResult<&CStr> {
+        match self.lookup(name, &Data::String(CString::default()))? {
+            Data::String(value) => Ok(value.as_c_str()),
+            _ => Err(NvError::NotFound),
+        }
+    }
+
+    /// Get a `String` from the list.
+    pub fn get_string<'a, N: IntoCStr<'a>>(&self, name: N) -> NvResult<String> {
+        self.get_str(name).map(str::to_owned)
+    }
+
+    /// Get a `String` from the list.
+    pub fn get_str<'a, N: IntoCStr<'a>>(&self, name: N) -> NvResult<&str> {
+        self.get_cstr(name)
+            .and_then(|v| v.to_str().map_err(NvError::from))
+    }
+
+    /// Stand-in for `nvlist_add_nvlist` used by `zfs::nvlist::insert_nvlist`: adds a copy of
+    /// `value`, later changes of `value` don't affect this list.
+    pub(crate) fn add_nvlist<'a, N: IntoCStr<'a>>(
+        &mut self,
+        name: N,
+        value: &NvList,
+    ) -> NvResult<()> {
+        self.add(name, Data::NvList(value.dup()))
+    }
+
+    /// Deep copy, same as `nvlist_dup`.
+    fn dup(&self) -> NvList {
+        NvList {
+            flags: self.flags,
+            pairs: self
+                .pairs
+                .iter()
+                .map(|pair| Pair {
+                    name: pair.name.clone(),
+                    data: pair.data.dup(),
+                })
+                .collect(),
+        }
+    }
+
+    /// First pair of the name and of the same type as `like`, what `nvlist_lookup_*` returns.
+    fn lookup<'a, N: IntoCStr<'a>>(&self, name: N, like: &Data) -> NvResult<&Data> {
+        let name = name.into_c_str()?;
+        self.pairs
+            .iter()
+            .find(|pair| *pair.name == *name && discriminant(&pair.data) == discriminant(like))
+            .map(|pair| &pair.data)
+            .ok_or(NvError::NotFound)
+    }
+
+    fn add<'a, N: IntoCStr<'a>>(&mut self, name: N, data: Data) -> NvResult<()> {
+        let name = name.into_c_str()?.into_owned();
+        match self.flags {
+            NvFlag::None => {}
+            NvFlag::UniqueName => self.pairs.retain(|pair| pair.name != name),
+            NvFlag::UniqueNameType => self.pairs.retain(|pair| {
+                pair.name != name || discriminant(&pair.data) != discriminant(&data)
+            }),
+        }
+        self.pairs.push(Pair { name, data });
+        Ok(())
+    }
+}
+
+impl_list_op! {bool, insert_boolean_value}
+impl_list_op! {i8, insert_i8}
+impl_list_op! {u8, insert_u8}
+impl_list_op! {i16, insert_i16}
+impl_list_op! {u16, insert_u16}
+impl_list_op! {i32, insert_i32}
+impl_list_op! {u32, insert_u32}
+impl_list_op! {i64, insert_i64}
+impl_list_op! {u64, insert_u64}
+impl_list_op! {&str, insert_string}
+
+impl fmt::Debug for NvList {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map()
+            .entries(
+                self.iter()
+                    .map(|ref pair| (pair.key().to_string_lossy().to_string(), pair.value())),
+            )
+            .finish()
+    }
+}
+
+/// Copy of a pair of the list it was taken from.
+pub struct NvPairRef {
+    name: CString,
+    data: Data,
+}
+
+impl NvPairRef {
+    /// Always null: there is no native pair behind it.
+    pub fn as_ptr(&self) -> *mut sys::nvpair_t {
+        null_mut()
+    }
+
+    pub fn key(&self) -> &CStr {
+        &self.name
+    }
+
+    pub fn value(&self) -> Value {
+        self.data.value()
+    }
+
+    /// Stand-in for `nvpair_value_nvlist` used by `zfs::nvlist::with_nvlist`.
+    pub(crate) fn nvlist(&self) -> Option<&NvList> {
+        match &self.data {
+            Data::NvList(list) => Some(list),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Debug for NvPairRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("NvPair")
+            .field(&self.key())
+            .field(&self.value())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{NvFlag, NvList, Value};
+    use libnv::NvError;
+
+    #[test]
+    fn unique_names() {
+        let mut list = NvList::new(NvFlag::UniqueName).unwrap();
+        list.insert_u64("quota", 1).unwrap();
+        list.insert_string("quota", "none").unwrap();
+        assert_eq!("none", list.get_str("quota").unwrap());
+        assert!(list.get_u64("quota").is_err());
+        assert_eq!(1, list.iter().count());
+
+        let mut list = NvList::new(NvFlag::UniqueNameType).unwrap();
+        list.insert_u64("quota", 1).unwrap();
+        list.insert_string("quota", "none").unwrap();
+        list.insert_u64("quota", 2).unwrap();
+        let values: Vec<Value> = list.iter().map(|pair| pair.value()).collect();
+        assert_eq!(vec![Value::String("none".into()), Value::Uint64(2)], values);
+
+        let mut list = NvList::new(NvFlag::None).unwrap();
+        list.insert_u64("quota", 1).unwrap();
+        list.insert_u64("quota", 2).unwrap();
+        assert_eq!(1, list.get_u64("quota").unwrap());
+        assert_eq!(2, list.iter().count());
+    }
+
+    #[test]
+    fn lookup_by_type() {
+        let mut list = NvList::default();
+        list.insert_boolean("hold").unwrap();
+        list.insert("hold", true).unwrap();
+        list.insert("txg", 7u64).unwrap();
+        assert!(list.get_boolean("hold").unwrap());
+        assert!(list.get_boolean_value("hold").unwrap());
+        assert!(matches!(list.get_u32("txg"), Err(NvError::NotFound)));
+        assert!(matches!(list.get_u64("missing"), Err(NvError::NotFound)));
+        assert!(list.exists("txg").unwrap());
+        assert!(!list.exists("missing").unwrap());
+        assert_eq!(
+            vec![Value::Bool(true), Value::Bool(true), Value::Uint64(7)],
+            list.iter().map(|pair| pair.value()).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn copies_are_deep() {
+        let mut inner = NvList::default();
+        inner.insert_boolean("backup").unwrap();
+        let mut outer = NvList::default();
+        outer.add_nvlist("tank@snap", &inner).unwrap();
+        outer.insert_u64_array("txgs", &mut [1u64, 2]).unwrap();
+        let copy = outer.dup();
+
+        // Same name and type, so both pairs of the source list are replaced.
+        inner.insert_boolean("other").unwrap();
+        outer.add_nvlist("tank@snap", &inner).unwrap();
+        outer.insert_u64_array("txgs", &mut [3u64]).unwrap();
+        assert_eq!(&[3], outer.get_u64_array("txgs").unwrap());
+        let changed: Vec<_> = outer.iter().collect();
+        assert_eq!(2, changed[0].nvlist().unwrap().iter().count());
+
+        let pairs: Vec<_> = copy.iter().collect();
+        assert_eq!(Value::Unknown, pairs[0].value());
+        let nested = pairs[0].nvlist().unwrap();
+        assert_eq!(1, nested.iter().count());
+        assert!(nested.get_boolean("backup").unwrap());
+        assert_eq!(&[1, 2], copy.get_u64_array("txgs").unwrap());
+        assert!(pairs[1].nvlist().is_none());
+    }
+
+    #[test]
+    fn nul_is_rejected() {
+        let mut list = NvList::default();
+        assert!(matches!(
+            list.insert_u64("a\0b", 1),
+            Err(NvError::InvalidString(_))
+        ));
+        assert!(matches!(
+            list.insert_string("a", "b\0"),
+            Err(NvError::InvalidString(_))
+        ));
+        assert!(list.is_empty());
+        assert!(unsafe { NvList::from_ptr(list.as_ptr()) }.is_empty());
+    }
+}
@@ -0,0 +1,261 @@
+//! Looking inside nvlists without consuming them, and nesting them.
+//!
+//! `NvList` comes from `libnv`, which neither renders nested lists in `Debug` nor can add or read
+//! them. [`NvListDebug`](struct.NvListDebug.html) renders keys and values, nested lists
+//! included, so nvlists handed to `libzfs_core` can be logged. Nested lists are added and read
+//! with `libnvpair` directly.
+use std::{collections::HashMap, fmt};
+
+use libnv::NvResult;
+
+use crate::zfs::nv::{NvList, NvPairRef, Value};
+
+/// Strings longer than this are cut in `Debug` output.
+const MAX_STRING_LEN: usize = 256;
+
+/// `Debug` that shows what is inside of an nvlist: `{"key": value, ...}` in the order pairs were
+/// added. The list is only read.
+///
+/// ```rust
+/// use libzetta::zfs::{nv::NvList, nvlist::NvListDebug};
+///
+/// let mut list = NvList::default();
+/// list.insert_u64("quota", 1024).unwrap();
+/// list.insert_string("compression", "lz4").unwrap();
+/// assert_eq!(
+///     r#"{"quota": Uint64(1024), "compression": "lz4"}"#,
+///     format!("{:?}", NvListDebug(&list))
+/// );
+/// ```
+pub struct NvListDebug<'a>(pub &'a NvList);
+
+impl fmt::Debug for NvListDebug<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map()
+            .entries(self.0.iter().map(|pair| {
+                let key = pair.key().to_string_lossy().into_owned();
+                (key, PairDebug(pair))
+            }))
+            .finish()
+    }
+}
+
+/// `Debug` of the value of a pair. Nested lists are rendered with `NvListDebug`.
+struct PairDebug(NvPairRef);
+
+impl fmt::Debug for PairDebug {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        with_nvlist(&self.0, |list| NvListDebug(list).fmt(f))
+            .unwrap_or_else(|| ValueDebug(self.0.value()).fmt(f))
+    }
+}
+
+/// `Debug` of a single value. Long strings are cut down to their beginning and length.
+struct ValueDebug(Value);
+
+impl fmt::Debug for ValueDebug {
+    #[allow(clippy::wildcard_enum_match_arm)]
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.0 {
+            Value::String(value) if value.len() > MAX_STRING_LEN => {
+                let mut end = MAX_STRING_LEN;
+                while !value.is_char_boundary(end) {
+                    end -= 1;
+                }
+                write!(f, "{:?}... ({} bytes)", &value[..end], value.len())
+            }
+            Value::String(value) => value.fmt(f),
+            Value::Unknown => f.write_str("<unknown>"),
+            other => other.fmt(f),
+        }
+    }
+}
+
+/// Copy top-level pairs of the list into a map. Unlike `NvList::into_hashmap` the list stays
+/// usable. Nested lists are `Value::Unknown`, same as with `into_hashmap`.
+///
+/// ```rust
+/// use libzetta::zfs::{
+///     nv::{NvList, Value},
+///     nvlist::to_hashmap,
+/// };
+///
+/// let mut list = NvList::default();
+/// list.insert_boolean("hold").unwrap();
+/// assert_eq!(Some(&Value::Bool(true)), to_hashmap(&list).get("hold"));
+/// assert!(!list.is_empty());
+/// ```
+pub fn to_hashmap(list: &NvList) -> HashMap<String, Value> {
+    list.iter()
+        .map(|pair| (pair.key().to_string_lossy().into_owned(), pair.value()))
+        .collect()
+}
+
+/// Add a copy of `value` to `list` as `name`.
+#[cfg(not(feature = "nv-fake"))]
+pub(crate) fn insert_nvlist(list: &mut NvList, name: &str, value: &NvList) -> NvResult<()> {
+    let name = std::ffi::CString::new(name)?;
+    let errno =
+        unsafe { nvpair_sys::nvlist_add_nvlist(list.as_ptr(), name.as_ptr(), value.as_ptr()) };
+    match errno {
+        0 => Ok(()),
+        libc::ENOMEM => Err(libnv::NvError::OutOfMemory),
+        errno => Err(libnv::NvError::NativeError(errno)),
+    }
+}
+
+/// Add a copy of `value` to `list` as `name`.
+#[cfg(feature = "nv-fake")]
+pub(crate) fn insert_nvlist(list: &mut NvList, name: &str, value: &NvList) -> NvResult<()> {
+    list.add_nvlist(name, value)
+}
+
+/// Call `f` with the list `pair` holds, `None` if it holds something else. The list is borrowed
+/// from the pair, nothing is copied.
+#[cfg(not(feature = "nv-fake"))]
+pub(crate) fn with_nvlist<R, F: FnOnce(&NvList) -> R>(pair: &NvPairRef, f: F) -> Option<R> {
+    use nvpair_sys as sys;
+
+    let pair = pair.as_ptr();
+    if unsafe { sys::nvpair_type(pair) } != sys::data_type_t::DATA_TYPE_NVLIST {
+        return None;
+    }
+    let mut ptr = std::ptr::null_mut();
+    if unsafe { sys::nvpair_value_nvlist(pair, &mut ptr) } != 0 || ptr.is_null() {
+        return None;
+    }
+    // The list belongs to the pair, it must not be freed here.
+    let list = std::mem::ManuallyDrop::new(unsafe { NvList::from_ptr(ptr) });
+    Some(f(&list))
+}
+
+/// Call `f` with the list `pair` holds, `None` if it holds something else.
+#[cfg(feature = "nv-fake")]
+pub(crate) fn with_nvlist<R, F: FnOnce(&NvList) -> R>(pair: &NvPairRef, f: F) -> Option<R> {
+    pair.nvlist().map(f)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{insert_nvlist, with_nvlist, NvListDebug, ValueDebug, MAX_STRING_LEN};
+    use crate::zfs::nv::{NvList, Value};
+
+    #[test]
+    fn values() {
+        let rendered: Vec<String> = vec![
+            Value::Bool(true),
+            Value::Int8(-8),
+            Value::Uint8(8),
+            Value::Int16(-16),
+            Value::Uint16(16),
+            Value::Int32(-32),
+            Value::Uint32(32),
+            Value::Int64(-64),
+            Value::Uint64(64),
+            Value::String(String::from("tank/data")),
+            Value::Unknown,
+        ]
+        .into_iter()
+        .map(|value| format!("{:?}", ValueDebug(value)))
+        .collect();
+        let expected = vec![
+            "Bool(true)",
+            "Int8(-8)",
+            "Uint8(8)",
+            "Int16(-16)",
+            "Uint16(16)",
+            "Int32(-32)",
+            "Uint32(32)",
+            "Int64(-64)",
+            "Uint64(64)",
+            "\"tank/data\"",
+            "<unknown>",
+        ];
+        assert_eq!(expected, rendered);
+    }
+
+    #[test]
+    fn long_strings_are_cut() {
+        let value = "ä".repeat(MAX_STRING_LEN);
+        let rendered = format!("{:?}", ValueDebug(Value::String(value)));
+        let expected = format!(
+            "{:?}... ({} bytes)",
+            "ä".repeat(MAX_STRING_LEN / 2),
+            MAX_STRING_LEN * 2
+        );
+        assert_eq!(expected, rendered);
+    }
+
+    #[test]
+    fn empty_list() {
+        let list = NvList::default();
+        assert_eq!("{}", format!("{:?}", NvListDebug(&list)));
+        assert!(super::to_hashmap(&list).is_empty());
+        assert!(list.is_empty());
+    }
+
+    #[test]
+    fn populated_list() {
+        let mut list = NvList::default();
+        list.insert_string("snapname", "tank/data@today").unwrap();
+        list.insert_u64("refquota", 4096).unwrap();
+        list.insert_boolean("force").unwrap();
+        list.insert_string("comment", "x".repeat(MAX_STRING_LEN + 1))
+            .unwrap();
+        let expected = format!(
+            r#"{{"snapname": "tank/data@today", "refquota": Uint64(4096), "force": Bool(true), "comment": {:?}... ({} bytes)}}"#,
+            "x".repeat(MAX_STRING_LEN),
+            MAX_STRING_LEN + 1
+        );
+        assert_eq!(expected, format!("{:?}", NvListDebug(&list)));
+
+        let map = super::to_hashmap(&list);
+        assert_eq!(4, map.len());
+        assert_eq!(Some(&Value::Uint64(4096)), map.get("refquota"));
+        assert_eq!(4096, list.get_u64("refquota").unwrap());
+    }
+
+    #[test]
+    fn nested_lists() {
+        let mut holds = NvList::default();
+        holds.insert_string("tank/data@today", "backup").unwrap();
+        let mut inner = NvList::default();
+        inner.insert_u64("cleanup_fd", 3).unwrap();
+        let mut middle = NvList::default();
+        insert_nvlist(&mut middle, "options", &inner).unwrap();
+        insert_nvlist(&mut middle, "empty", &NvList::default()).unwrap();
+        let mut list = NvList::default();
+        insert_nvlist(&mut list, "holds", &holds).unwrap();
+        insert_nvlist(&mut list, "args", &middle).unwrap();
+
+        assert_eq!(
+            r#"{"holds": {"tank/data@today": "backup"}, "args": {"options": {"cleanup_fd": Uint64(3)}, "empty": {}}}"#,
+            format!("{:?}", NvListDebug(&list))
+        );
+        assert_eq!(
+            r#"{
+    "holds": {
+        "tank/data@today": "backup",
+    },
+    "args": {
+        "options": {
+            "cleanup_fd": Uint64(
+                3,
+            ),
+        },
+        "empty": {},
+    },
+}"#,
+            format!("{:#?}", NvListDebug(&list))
+        );
+        assert_eq!(Some(&Value::Unknown), super::to_hashmap(&list).get("holds"));
+        let pair = list.iter().next().unwrap();
+        let tag = with_nvlist(&pair, |nested| {
+            nested.get_string("tank/data@today").unwrap()
+        });
+        assert_eq!(Some(String::from("backup")), tag);
+        let pair = list.iter().last().unwrap();
+        assert!(with_nvlist(&pair, |_| ()).is_some());
+        assert!(with_nvlist(&holds.iter().next().unwrap(), |_| ()).is_none());
+    }
+}
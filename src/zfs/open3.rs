@@ -1,29 +1,50 @@
 use crate::zfs::{
-    DatasetKind, Error, FilesystemProperties, Properties, Result, VolumeProperties, ZfsEngine,
+    delegation::parse_delegations, validators, BookmarkProperties, ByteSize, DatasetKind,
+    Delegation, DelegationGrant, DestroyTiming, Error, FilesystemProperties, PartialReceive,
+    PathExt, ProjectSpace, Properties, PropertySource, PropertyValue, ReceiveOptions,
+    RenameOptions, RenameOutcome, ReplicationSendOptions, Result, SendFlags, SendProgress,
+    ShareOptions, SnapDev, SnapDir, ValidationError, ValidationResult, VolumeProperties, ZfsEngine,
+    RECEIVE_ATTEMPTS,
 };
 use chrono::NaiveDateTime;
+use once_cell::sync::OnceCell;
 use slog::Logger;
 use std::{
-    ffi::OsString,
-    path::PathBuf,
-    process::{Command, Stdio},
+    collections::{BTreeMap, HashMap, HashSet},
+    ffi::{OsStr, OsString},
+    io,
+    os::unix::{
+        ffi::OsStrExt,
+        io::{AsRawFd, FromRawFd, RawFd},
+    },
+    path::{Path, PathBuf},
+    process::{Command, Output, Stdio},
+    str::FromStr,
+    sync::Arc,
+    time::{Duration, Instant},
 };
 
 use crate::{
-    parsers::zfs::{Rule, ZfsParser},
+    instrumentation::{self, InstrumentationHook},
     utils::parse_float,
-    zfs::properties::{BookmarkProperties, SnapshotProperties},
+    zfs::{
+        pathext::join_with_separator,
+        properties::{SnapshotProperties, VolumeMode},
+        volume_device_path_with, zvol,
+    },
     GlobalLogger,
 };
-use pest::Parser;
 use std::str::Lines;
 
-static FAILED_TO_PARSE: &str = "Failed to parse value";
 static DATE_FORMAT: &str = "%a %b %e %k:%M %Y";
 
 pub struct ZfsOpen3 {
     cmd_name: OsString,
     logger: Logger,
+    instrumentation: Option<Arc<InstrumentationHook>>,
+    command_timeout: Option<Duration>,
+    /// Whether `zfs rename` takes `-u`, checked on first use.
+    rename_no_remount: OnceCell<bool>,
 }
 
 impl ZfsOpen3 {
@@ -36,15 +57,112 @@ impl ZfsOpen3 {
             None => "zfs".into(),
         };
 
-        ZfsOpen3 { logger, cmd_name }
+        ZfsOpen3 {
+            logger,
+            cmd_name,
+            instrumentation: None,
+            command_timeout: None,
+            rename_no_remount: OnceCell::new(),
+        }
     }
 
     pub fn logger(&self) -> &Logger {
         &self.logger
     }
 
+    /// Install a hook that receives [`CommandMetrics`](../instrumentation/struct.CommandMetrics.html)
+    /// of every spawned command.
+    pub fn set_instrumentation(&mut self, hook: InstrumentationHook) {
+        self.instrumentation = Some(Arc::new(hook));
+    }
+
+    pub(crate) fn share_instrumentation(&mut self, hook: Arc<InstrumentationHook>) {
+        self.instrumentation = Some(hook);
+    }
+
+    /// Kill commands that don't exit within `timeout` and fail with `Error::TimedOut`. Commands
+    /// that hang in the kernel (e.g. on a suspended pool) would otherwise block the caller
+    /// forever. Send and receive stream for as long as they need and are never killed. `None`,
+    /// the default, waits forever.
+    pub fn set_command_timeout(&mut self, timeout: Option<Duration>) {
+        self.command_timeout = timeout;
+    }
+
+    /// Run command to completion within command timeout, timing it.
+    fn output(&self, z: &mut Command) -> io::Result<Output> {
+        instrumentation::run_command_within(
+            &self.logger,
+            self.instrumentation.as_ref(),
+            z,
+            self.command_timeout,
+        )
+    }
+
+    /// Run command that streams to or from a file descriptor to completion, timing it. There is
+    /// no telling how long a stream takes, so command timeout doesn't apply.
+    fn stream_output(&self, z: &mut Command) -> io::Result<Output> {
+        instrumentation::run_command(&self.logger, self.instrumentation.as_ref(), z)
+    }
+
     fn zfs(&self) -> Command {
-        Command::new(&self.cmd_name)
+        instrumentation::isolated_command(&self.cmd_name)
+    }
+
+    /// Send a full snapshot to `fd` using `zfs send -v`. Stream is written directly into `fd`,
+    /// while `progress` is called every time `zfs send` reports how much it has sent, about once
+    /// a second.
+    pub fn send_full_with_progress<N, FD, P>(
+        &self,
+        path: N,
+        fd: FD,
+        flags: SendFlags,
+        progress: P,
+    ) -> Result<()>
+    where
+        N: Into<PathBuf>,
+        FD: AsRawFd,
+        P: FnMut(SendProgress),
+    {
+        self.send_with_progress(path.into(), None, fd.as_raw_fd(), flags, progress)
+    }
+
+    /// Same as [`send_full_with_progress`](#method.send_full_with_progress), but sends an
+    /// increment from snapshot or bookmark `from` (`-i`).
+    pub fn send_incremental_with_progress<N, F, FD, P>(
+        &self,
+        path: N,
+        from: F,
+        fd: FD,
+        flags: SendFlags,
+        progress: P,
+    ) -> Result<()>
+    where
+        N: Into<PathBuf>,
+        F: Into<PathBuf>,
+        FD: AsRawFd,
+        P: FnMut(SendProgress),
+    {
+        self.send_with_progress(
+            path.into(),
+            Some(from.into()),
+            fd.as_raw_fd(),
+            flags,
+            progress,
+        )
+    }
+
+    /// Whether installed `zfs rename` takes `-u`. Told by usage `zfs rename` prints when called
+    /// without arguments.
+    fn rename_supports_no_remount(&self) -> Result<bool> {
+        self.rename_no_remount
+            .get_or_try_init(|| {
+                let mut z = self.zfs();
+                z.arg("rename");
+                debug!(self.logger, "executing"; "cmd" => format_args!("{:?}", z));
+                let out = self.output(&mut z)?;
+                Ok(usage_has_flag(&out.stderr, "rename", 'u'))
+            })
+            .copied()
     }
 
     #[allow(dead_code)]
@@ -58,13 +176,135 @@ impl ZfsOpen3 {
 }
 
 impl ZfsEngine for ZfsOpen3 {
+    fn exists<N: Into<PathBuf>>(&self, name: N) -> Result<bool> {
+        let mut z = self.zfs();
+        z.args(["list", "-t", "all", "-H", "-o", "name"]);
+        z.arg(name.into().as_os_str());
+        debug!(self.logger, "executing"; "cmd" => format_args!("{:?}", z));
+        let out = self.output(&mut z)?;
+        if out.status.success() {
+            Ok(true)
+        } else {
+            match Error::from_stderr(&out.stderr) {
+                Error::DatasetNotFound(_) => Ok(false),
+                e => Err(e),
+            }
+        }
+    }
+
+    fn exists_all<N: Into<PathBuf>>(&self, names: Vec<N>) -> Result<Vec<(PathBuf, bool)>> {
+        let names: Vec<PathBuf> = names.into_iter().map(Into::into).collect();
+        let roots = common_roots(&names);
+        if roots.is_empty() {
+            return Ok(names.into_iter().map(|name| (name, false)).collect());
+        }
+        let mut z = self.zfs();
+        z.args(["list", "-t", "all", "-H", "-o", "name", "-r"]);
+        z.args(&roots);
+        debug!(self.logger, "executing"; "cmd" => format_args!("{:?}", z));
+        let out = self.output(&mut z)?;
+        if !out.status.success() {
+            // Missing root means nothing under it exists, other roots are still listed.
+            for line in out.stderr.split(|byte| *byte == b'\n') {
+                match Error::from_stderr(line) {
+                    Error::DatasetNotFound(_) => {}
+                    _ if line.is_empty() => {}
+                    e => return Err(e),
+                }
+            }
+        }
+        let found: HashSet<PathBuf> = parse_dataset_list(&out.stdout)?.into_iter().collect();
+        Ok(names
+            .into_iter()
+            .map(|name| {
+                let exists = found.contains(&name);
+                (name, exists)
+            })
+            .collect())
+    }
+
+    fn clone_snapshot<N: Into<PathBuf>, T: Into<PathBuf>>(
+        &self,
+        snapshot: N,
+        target: T,
+        properties: Option<HashMap<String, String>>,
+    ) -> Result<()> {
+        let mut z = self.zfs();
+        z.arg("clone");
+        z.args(clone_property_args(properties));
+        z.arg(snapshot.into().as_os_str());
+        z.arg(target.into().as_os_str());
+        debug!(self.logger, "executing"; "cmd" => format_args!("{:?}", z));
+        let out = self.output(&mut z)?;
+        if out.status.success() {
+            Ok(())
+        } else {
+            Err(Error::from_stderr(&out.stderr))
+        }
+    }
+
+    fn promote<N: Into<PathBuf>>(&self, clone: N) -> Result<()> {
+        let mut z = self.zfs();
+        z.arg("promote");
+        z.arg(clone.into().as_os_str());
+        debug!(self.logger, "executing"; "cmd" => format_args!("{:?}", z));
+        let out = self.output(&mut z)?;
+        if out.status.success() {
+            Ok(())
+        } else {
+            Err(Error::from_stderr(&out.stderr))
+        }
+    }
+
+    fn rename<N: Into<PathBuf>, T: Into<PathBuf>>(
+        &self,
+        name: N,
+        new_name: T,
+        opts: RenameOptions,
+    ) -> Result<RenameOutcome> {
+        let name = name.into();
+        let new_name = new_name.into();
+        let validation_errors: Vec<ValidationError> = [&name, &new_name]
+            .iter()
+            .map(|n| n.validate())
+            .filter_map(Result::err)
+            .collect();
+        if !validation_errors.is_empty() {
+            return Err(Error::ValidationErrors(validation_errors));
+        }
+        if !opts.create_parents() && new_name.is_volume_or_dataset() {
+            if let Some(parent) = new_name.parent().filter(|p| !p.as_os_str().is_empty()) {
+                if !self.exists(parent)? {
+                    return Err(ValidationError::ParentNotFound(new_name).into());
+                }
+            }
+        }
+        if *opts.no_remount() && !self.rename_supports_no_remount()? {
+            return Err(Error::Unsupported(String::from("rename -u")));
+        }
+        let affected: Vec<PathBuf> = self.list(&name)?.into_iter().map(|(_, n)| n).collect();
+
+        let mut z = self.zfs();
+        z.arg("rename");
+        z.args(opts.into_args());
+        z.arg(name.as_os_str());
+        z.arg(new_name.as_os_str());
+        debug!(self.logger, "executing"; "cmd" => format_args!("{:?}", z));
+        let out = self.output(&mut z)?;
+        if out.status.success() {
+            Ok(RenameOutcome::new(map_renamed(&name, &new_name, affected)))
+        } else {
+            Err(Error::from_stderr(&out.stderr))
+        }
+    }
+
     fn destroy<N: Into<PathBuf>>(&self, name: N) -> Result<()> {
         let mut z = self.zfs_mute();
         z.arg("destroy");
         z.arg(name.into().as_os_str());
 
         debug!(self.logger, "executing"; "cmd" => format_args!("{:?}", z));
-        let out = z.output()?;
+        let out = self.output(&mut z)?;
         if out.status.success() {
             Ok(())
         } else {
@@ -72,137 +312,1274 @@ impl ZfsEngine for ZfsOpen3 {
         }
     }
 
-    #[allow(clippy::option_unwrap_used)]
-    #[allow(clippy::result_unwrap_used)]
+    /// Destroys snapshots with as few `zfs destroy` calls as possible: one per dataset (more if
+    /// argument gets too long), with contiguous runs of snapshots collapsed into `first%last`
+    /// ranges. Order of snapshots is taken from a single `zfs list` call. Unlike `ZfsLzc`, this
+    /// isn't atomic across datasets. If any snapshot doesn't exist, nothing is destroyed and
+    /// [`Error::DestroySnapshotsFailed`](enum.Error.html) lists missing snapshots with `ENOENT`.
+    fn destroy_snapshots(&self, snapshots: &[PathBuf], timing: DestroyTiming) -> Result<()> {
+        let validation_errors: Vec<ValidationError> = snapshots
+            .iter()
+            .map(|snapshot| validate_destroyable_snapshot(snapshot))
+            .filter_map(Result::err)
+            .collect();
+        if !validation_errors.is_empty() {
+            return Err(Error::ValidationErrors(validation_errors));
+        }
+        if snapshots.is_empty() {
+            return Ok(());
+        }
+        let mut datasets: Vec<PathBuf> = snapshots.iter().map(PathExt::dataset_of).collect();
+        datasets.sort();
+        datasets.dedup();
+
+        let mut z = self.zfs();
+        z.args([
+            "list",
+            "-t",
+            "snapshot",
+            "-Hp",
+            "-o",
+            "name",
+            "-s",
+            "createtxg",
+            "-d",
+            "1",
+        ]);
+        z.args(&datasets);
+        debug!(self.logger, "executing"; "cmd" => format_args!("{:?}", z));
+        let existing = self.stdout_to_list_of_datasets(&mut z)?;
+        let args =
+            destroy_snapshots_args(snapshots, &existing).map_err(Error::DestroySnapshotsFailed)?;
+
+        for arg in args {
+            let mut z = self.zfs();
+            z.arg("destroy");
+            if timing == DestroyTiming::Defer {
+                z.arg("-d");
+            }
+            z.arg(arg);
+            debug!(self.logger, "executing"; "cmd" => format_args!("{:?}", z));
+            let out = self.output(&mut z)?;
+            if !out.status.success() {
+                return Err(Error::from_stderr(&out.stderr));
+            }
+        }
+        Ok(())
+    }
+
+    fn destroy_snapshot_recursive<N: Into<PathBuf>>(
+        &self,
+        dataset: N,
+        snap_name: &str,
+    ) -> Result<()> {
+        let snapshot = validators::validate_recursive_snapshot(&dataset.into(), snap_name)?;
+        let mut z = self.zfs();
+        z.args(["destroy", "-r"]);
+        z.arg(&snapshot);
+        debug!(self.logger, "executing"; "cmd" => format_args!("{:?}", z));
+        let out = self.output(&mut z)?;
+        if out.status.success() {
+            Ok(())
+        } else {
+            Err(Error::from_stderr(&out.stderr))
+        }
+    }
+
     fn list<N: Into<PathBuf>>(&self, prefix: N) -> Result<Vec<(DatasetKind, PathBuf)>> {
         let mut z = self.zfs();
+        let prefix = prefix.into();
         z.args(&["list", "-t", "all", "-o", "type,name", "-Hpr"]);
-        z.arg(prefix.into().as_os_str());
+        z.arg(prefix.as_os_str());
         debug!(self.logger, "executing"; "cmd" => format_args!("{:?}", z));
 
-        let out = z.output()?;
+        let out = self.output(&mut z)?;
         if out.status.success() {
-            let stdout = String::from_utf8_lossy(&out.stdout);
-            ZfsParser::parse(Rule::datasets_with_type, &stdout)
-                .map(|mut pairs| {
-                    pairs
-                        .next()
-                        .unwrap()
-                        .into_inner()
-                        .map(|pair| {
-                            //
-                            // - datasets_with_type
-                            //   - dataset_with_type
-                            //     - dataset_type: "volume"
-                            //     - dataset_name: "z/iohyve/rancher/disk0"
-                            debug_assert_eq!(Rule::dataset_with_type, pair.as_rule());
-                            let mut inner = pair.into_inner();
-
-                            let dataset_type_pair = inner.next().unwrap();
-                            let dataset_name_pair = inner.next().unwrap();
-                            let dataset_type = dataset_type_pair.as_str().parse().unwrap();
-                            let dataset_name = PathBuf::from(dataset_name_pair.as_str());
-                            (dataset_type, dataset_name)
-                        })
-                        .collect()
-                })
-                .map_err(|_| Error::UnknownSoFar(String::from(stdout)))
+            parse_typed_dataset_list(&out.stdout)
         } else {
-            Err(Error::from_stderr(&out.stderr))
+            Err(list_error(&prefix, &out.stderr))
         }
     }
 
     fn list_filesystems<N: Into<PathBuf>>(&self, pool: N) -> Result<Vec<PathBuf>> {
         let mut z = self.zfs();
+        let pool = pool.into();
         z.args(&["list", "-t", "filesystem", "-o", "name", "-Hpr"]);
-        z.arg(pool.into().as_os_str());
+        z.arg(pool.as_os_str());
         debug!(self.logger, "executing"; "cmd" => format_args!("{:?}", z));
-        ZfsOpen3::stdout_to_list_of_datasets(&mut z)
+        self.list_names(&mut z, &pool)
     }
 
     fn list_snapshots<N: Into<PathBuf>>(&self, pool: N) -> Result<Vec<PathBuf>> {
         let mut z = self.zfs();
+        let pool = pool.into();
         z.args(&["list", "-t", "snapshot", "-o", "name", "-Hpr"]);
-        z.arg(pool.into().as_os_str());
+        z.arg(pool.as_os_str());
         debug!(self.logger, "executing"; "cmd" => format_args!("{:?}", z));
-        ZfsOpen3::stdout_to_list_of_datasets(&mut z)
+        self.list_names(&mut z, &pool)
+    }
+
+    fn list_snapshots_of<N: Into<PathBuf>>(&self, dataset: N) -> Result<Vec<PathBuf>> {
+        let dataset = dataset.into();
+        let mut z = self.zfs();
+        z.args([
+            "list",
+            "-t",
+            "snapshot",
+            "-Hp",
+            "-o",
+            "name",
+            "-s",
+            "createtxg",
+            "-d",
+            "1",
+        ]);
+        z.arg(dataset.as_os_str());
+        debug!(self.logger, "executing"; "cmd" => format_args!("{:?}", z));
+        self.list_names(&mut z, &dataset)
+    }
+
+    fn latest_snapshot<N: Into<PathBuf>>(&self, dataset: N) -> Result<Option<PathBuf>> {
+        self.first_snapshot(dataset.into(), "-S")
+    }
+
+    fn oldest_snapshot<N: Into<PathBuf>>(&self, dataset: N) -> Result<Option<PathBuf>> {
+        self.first_snapshot(dataset.into(), "-s")
+    }
+
+    fn deferred_snapshots<N: Into<PathBuf>>(&self, root: N) -> Result<Vec<PathBuf>> {
+        let root = root.into();
+        let mut z = self.zfs();
+        z.args([
+            "list",
+            "-t",
+            "snapshot",
+            "-Hp",
+            "-o",
+            "name,defer_destroy",
+            "-s",
+            "createtxg",
+            "-r",
+        ]);
+        z.arg(root.as_os_str());
+        debug!(self.logger, "executing"; "cmd" => format_args!("{:?}", z));
+        let out = self.output(&mut z)?;
+        if out.status.success() {
+            parse_deferred_list(&out.stdout)
+        } else {
+            Err(list_error(&root, &out.stderr))
+        }
     }
 
     fn list_bookmarks<N: Into<PathBuf>>(&self, pool: N) -> Result<Vec<PathBuf>> {
         let mut z = self.zfs();
+        let pool = pool.into();
         z.args(&["list", "-t", "bookmark", "-o", "name", "-Hpr"]);
-        z.arg(pool.into().as_os_str());
+        z.arg(pool.as_os_str());
+        debug!(self.logger, "executing"; "cmd" => format_args!("{:?}", z));
+        self.list_names(&mut z, &pool)
+    }
+
+    fn list_bookmarks_detailed<N: Into<PathBuf>>(
+        &self,
+        dataset: N,
+    ) -> Result<Vec<BookmarkProperties>> {
+        let dataset = dataset.into();
+        let mut z = self.zfs();
+        z.args([
+            "list",
+            "-t",
+            "bookmark",
+            "-Hp",
+            "-o",
+            "name,guid,createtxg,creation",
+            "-r",
+        ]);
+        z.arg(dataset.as_os_str());
+        debug!(self.logger, "executing"; "cmd" => format_args!("{:?}", z));
+        let out = self.output(&mut z)?;
+        if out.status.success() {
+            parse_bookmark_list(&out.stdout)
+        } else {
+            Err(list_error(&dataset, &out.stderr))
+        }
+    }
+
+    fn list_snapshots_with_guids<N: Into<PathBuf>>(
+        &self,
+        dataset: N,
+    ) -> Result<Vec<(PathBuf, u64)>> {
+        let dataset = dataset.into();
+        let mut z = self.zfs();
+        z.args([
+            "list",
+            "-t",
+            "snapshot",
+            "-Hp",
+            "-o",
+            "name,guid",
+            "-s",
+            "createtxg",
+            "-d",
+            "1",
+        ]);
+        z.arg(dataset.as_os_str());
+        debug!(self.logger, "executing"; "cmd" => format_args!("{:?}", z));
+        let out = self.output(&mut z)?;
+        if out.status.success() {
+            parse_name_value_list(&out.stdout)
+        } else {
+            Err(list_error(&dataset, &out.stderr))
+        }
+    }
+
+    fn list_origins<N: Into<PathBuf>>(&self, root: N) -> Result<Vec<(PathBuf, Option<PathBuf>)>> {
+        let root = root.into();
+        let mut z = self.zfs();
+        z.args([
+            "list",
+            "-t",
+            "filesystem,volume",
+            "-o",
+            "name,origin",
+            "-Hpr",
+        ]);
+        z.arg(root.as_os_str());
         debug!(self.logger, "executing"; "cmd" => format_args!("{:?}", z));
-        ZfsOpen3::stdout_to_list_of_datasets(&mut z)
+        let out = self.output(&mut z)?;
+        if out.status.success() {
+            parse_origin_list(&out.stdout)
+        } else {
+            Err(list_error(&root, &out.stderr))
+        }
+    }
+
+    fn written_since<N: Into<PathBuf>>(&self, dataset: N, since_snapshot: &str) -> Result<u64> {
+        let dataset = dataset.into();
+        dataset.with_snapshot(since_snapshot)?;
+        let property = format!("written@{}", since_snapshot);
+        let mut z = self.zfs();
+        z.args(["get", "-Hp", &property]);
+        z.arg(dataset.as_os_str());
+        debug!(self.logger, "executing"; "cmd" => format_args!("{:?}", z));
+        let out = self.output(&mut z)?;
+        if out.status.success() {
+            parse_written_since(&String::from_utf8_lossy(&out.stdout), &property)
+        } else {
+            Err(Error::from_stderr(&out.stderr))
+        }
+    }
+
+    /// Single `zfs list` instead of reading properties of every snapshot.
+    fn snapshot_deltas<N: Into<PathBuf>>(&self, dataset: N) -> Result<Vec<(PathBuf, u64)>> {
+        let dataset = dataset.into();
+        let mut z = self.zfs();
+        z.args([
+            "list",
+            "-t",
+            "snapshot",
+            "-Hp",
+            "-o",
+            "name,written",
+            "-s",
+            "createtxg",
+            "-d",
+            "1",
+        ]);
+        z.arg(dataset.as_os_str());
+        debug!(self.logger, "executing"; "cmd" => format_args!("{:?}", z));
+        let out = self.output(&mut z)?;
+        if out.status.success() {
+            parse_name_value_list(&out.stdout)
+        } else {
+            Err(list_error(&dataset, &out.stderr))
+        }
+    }
+
+    fn get_property<N: Into<PathBuf>, T: PropertyValue>(
+        &self,
+        dataset: N,
+        prop: &str,
+    ) -> Result<T> {
+        let mut z = self.zfs();
+        z.args(["get", "-Hp", "-o", "value", prop]);
+        z.arg(dataset.into().as_os_str());
+        debug!(self.logger, "executing"; "cmd" => format_args!("{:?}", z));
+        let out = self.output(&mut z)?;
+        if out.status.success() {
+            let stdout = str_from_bytes(&out.stdout)?;
+            T::from_value(prop, stdout.trim_end_matches('\n'))
+        } else {
+            Err(Error::from_stderr(&out.stderr))
+        }
+    }
+
+    fn snapshot_guid<N: Into<PathBuf>>(&self, snapshot: N) -> Result<u64> {
+        let snapshot = snapshot.into();
+        if !snapshot.is_snapshot() {
+            return Err(ValidationError::MissingSnapshotName(snapshot).into());
+        }
+        let mut z = self.zfs();
+        z.args(["get", "-Hp", "-o", "value", "guid"]);
+        z.arg(snapshot.as_os_str());
+        debug!(self.logger, "executing"; "cmd" => format_args!("{:?}", z));
+        let out = self.output(&mut z)?;
+        if out.status.success() {
+            let stdout = String::from_utf8_lossy(&out.stdout);
+            stdout
+                .trim()
+                .parse()
+                .map_err(|_| Error::UnknownSoFar(format!("Invalid guid: {}", stdout.trim())))
+        } else {
+            Err(Error::from_stderr(&out.stderr))
+        }
     }
 
     fn list_volumes<N: Into<PathBuf>>(&self, pool: N) -> Result<Vec<PathBuf>> {
         let mut z = self.zfs();
+        let pool = pool.into();
         z.args(&["list", "-t", "volume", "-o", "name", "-Hpr"]);
-        z.arg(pool.into().as_os_str());
+        z.arg(pool.as_os_str());
         debug!(self.logger, "executing"; "cmd" => format_args!("{:?}", z));
-        ZfsOpen3::stdout_to_list_of_datasets(&mut z)
+        self.list_names(&mut z, &pool)
     }
 
-    fn read_properties<N: Into<PathBuf>>(&self, path: N) -> Result<Properties> {
-        let path = path.into();
+    fn mounted_datasets(&self) -> Result<Vec<(PathBuf, PathBuf)>> {
         let mut z = self.zfs();
-        z.args(&["get", "-Hp", "all"]);
-        z.arg(path.clone().as_os_str());
+        z.arg("mount");
+        debug!(self.logger, "executing"; "cmd" => format_args!("{:?}", z));
+        let out = self.output(&mut z)?;
+        if out.status.success() {
+            Ok(parse_mount_table(&out.stdout))
+        } else {
+            Err(Error::from_stderr(&out.stderr))
+        }
+    }
+
+    fn kind_of<N: Into<PathBuf>>(&self, path: N) -> Result<DatasetKind> {
+        let mut z = self.zfs();
+        z.args(["get", "-Hp", "-o", "value", "type"]);
+        z.arg(path.into().as_os_str());
         debug!(self.logger, "executing"; "cmd" => format_args!("{:?}", z));
-        let out = z.output()?;
+        let out = self.output(&mut z)?;
         if out.status.success() {
             let stdout = String::from_utf8_lossy(&out.stdout);
-            let mut lines = stdout.lines();
-
-            let first = lines.next().expect("Empty stdout with 0 exit code");
-            let kind = parse_prop_line(&first).1;
-            let ret = match kind.as_ref() {
-                "filesystem" => parse_filesystem_lines(&mut lines, path),
-                "snapshot" => parse_snapshot_lines(&mut lines, path),
-                "volume" => parse_volume_lines(&mut lines, path),
-                "bookmark" => parse_bookmark_lines(&mut lines, path),
-                _ => parse_unknown_lines(&mut lines),
+            stdout
+                .trim()
+                .parse()
+                .map_err(|_| Error::UnknownSoFar(stdout.trim().into()))
+        } else {
+            Err(Error::from_stderr(&out.stderr))
+        }
+    }
+
+    fn unmount<N: Into<PathBuf>>(&self, dataset: N) -> Result<()> {
+        let mut z = self.zfs();
+        z.arg("unmount");
+        z.arg(dataset.into().as_os_str());
+        debug!(self.logger, "executing"; "cmd" => format_args!("{:?}", z));
+        let out = self.output(&mut z)?;
+        if out.status.success() {
+            Ok(())
+        } else {
+            Err(Error::from_stderr(&out.stderr))
+        }
+    }
+
+    fn upgrade<N: Into<PathBuf>>(
+        &self,
+        dataset: N,
+        recursive: bool,
+        to_version: Option<u32>,
+    ) -> Result<()> {
+        let mut z = self.zfs();
+        z.arg("upgrade");
+        if recursive {
+            z.arg("-r");
+        }
+        if let Some(version) = to_version {
+            z.arg("-V");
+            z.arg(version.to_string());
+        }
+        z.arg(dataset.into().as_os_str());
+        debug!(self.logger, "executing"; "cmd" => format_args!("{:?}", z));
+        let out = self.output(&mut z)?;
+        let already_upgraded =
+            String::from_utf8_lossy(&out.stderr).contains("already at this version");
+        if out.status.success() || already_upgraded {
+            Ok(())
+        } else {
+            Err(Error::from_stderr(&out.stderr))
+        }
+    }
+
+    fn upgradeable_datasets<N: Into<PathBuf>>(&self, pool: N) -> Result<Vec<(PathBuf, u32)>> {
+        let pool = pool.into();
+        let mut z = self.zfs();
+        z.arg("upgrade");
+        debug!(self.logger, "executing"; "cmd" => format_args!("{:?}", z));
+        let out = self.output(&mut z)?;
+        if out.status.success() {
+            Ok(parse_upgrade_listing(&out.stdout)
+                .into_iter()
+                .filter(|(dataset, _)| dataset.starts_with(&pool))
+                .collect())
+        } else {
+            Err(Error::from_stderr(&out.stderr))
+        }
+    }
+
+    fn resize_volume<N: Into<PathBuf>, S: Into<ByteSize>>(
+        &self,
+        volume: N,
+        new_size: S,
+        allow_shrink: bool,
+    ) -> Result<()> {
+        let volume = volume.into();
+        let new_size = new_size.into();
+        let current = match self.read_properties(volume.clone())? {
+            Properties::Volume(props) => props.volume_size_bytes(),
+            _ => return Err(Error::invalid_input()),
+        };
+        if new_size < current && !allow_shrink {
+            return Err(Error::VolumeShrinkRefused {
+                volume,
+                current,
+                requested: new_size,
+            });
+        }
+        self.set_property(&volume, "volsize", &new_size.as_u64().to_string())
+    }
+
+    fn set_quota<N: Into<PathBuf>>(&self, dataset: N, quota: Option<ByteSize>) -> Result<()> {
+        self.set_property(&dataset.into(), "quota", &size_or_none(quota))
+    }
+
+    fn set_refquota<N: Into<PathBuf>>(&self, dataset: N, quota: Option<ByteSize>) -> Result<()> {
+        self.set_property(&dataset.into(), "refquota", &size_or_none(quota))
+    }
+
+    fn set_project<P: AsRef<Path>>(&self, path: P, project_id: u32, recursive: bool) -> Result<()> {
+        require_projects()?;
+        let mut z = self.zfs();
+        z.args(["project", "-s", "-p", &project_id.to_string()]);
+        if recursive {
+            z.arg("-r");
+        }
+        z.arg(path.as_ref());
+        debug!(self.logger, "executing"; "cmd" => format_args!("{:?}", z));
+        let out = self.output(&mut z)?;
+        if out.status.success() {
+            Ok(())
+        } else {
+            Err(Error::from_stderr(&out.stderr))
+        }
+    }
+
+    fn get_project<P: AsRef<Path>>(&self, path: P) -> Result<u32> {
+        require_projects()?;
+        let mut z = self.zfs();
+        z.args(["project", "-d"]);
+        z.arg(path.as_ref());
+        debug!(self.logger, "executing"; "cmd" => format_args!("{:?}", z));
+        let out = self.output(&mut z)?;
+        if out.status.success() {
+            parse_project(&String::from_utf8_lossy(&out.stdout))
+        } else {
+            Err(Error::from_stderr(&out.stderr))
+        }
+    }
+
+    fn set_project_quota<N: Into<PathBuf>>(
+        &self,
+        dataset: N,
+        project_id: u32,
+        quota: Option<ByteSize>,
+    ) -> Result<()> {
+        require_projects()?;
+        let property = format!("projectquota@{}", project_id);
+        self.set_property(&dataset.into(), &property, &size_or_none(quota))
+    }
+
+    fn project_space<N: Into<PathBuf>>(&self, dataset: N) -> Result<Vec<ProjectSpace>> {
+        require_projects()?;
+        let dataset = dataset.into();
+        let mut z = self.zfs();
+        z.args([
+            "projectspace",
+            "-Hp",
+            "-o",
+            "name,used,quota,objused,objquota",
+        ]);
+        z.arg(dataset.as_os_str());
+        debug!(self.logger, "executing"; "cmd" => format_args!("{:?}", z));
+        let out = self.output(&mut z)?;
+        if out.status.success() {
+            parse_project_space(&out.stdout)
+        } else {
+            Err(list_error(&dataset, &out.stderr))
+        }
+    }
+
+    fn set_snap_dev<N: Into<PathBuf>>(&self, volume: N, snap_dev: SnapDev) -> Result<()> {
+        self.set_property(&volume.into(), "snapdev", snap_dev.as_ref())
+    }
+
+    fn set_snap_dir<N: Into<PathBuf>>(&self, dataset: N, snap_dir: SnapDir) -> Result<()> {
+        self.set_property(&dataset.into(), "snapdir", snap_dir.as_ref())
+    }
+
+    fn set_share_nfs<N: Into<PathBuf>>(&self, dataset: N, options: ShareOptions) -> Result<()> {
+        self.set_property(&dataset.into(), "sharenfs", &options.to_string())
+    }
+
+    fn set_share_smb<N: Into<PathBuf>>(&self, dataset: N, options: ShareOptions) -> Result<()> {
+        self.set_property(&dataset.into(), "sharesmb", &options.to_string())
+    }
+
+    fn share<N: Into<PathBuf>>(&self, dataset: N) -> Result<()> {
+        self.run_sharing("share", &[dataset.into().as_os_str()])
+    }
+
+    fn unshare<N: Into<PathBuf>>(&self, dataset: N) -> Result<()> {
+        self.run_sharing("unshare", &[dataset.into().as_os_str()])
+    }
+
+    fn share_all(&self) -> Result<()> {
+        self.run_sharing("share", &["-a"])
+    }
+
+    fn set_volume_mode<N: Into<PathBuf>>(&self, volume: N, mode: VolumeMode) -> Result<bool> {
+        let volume = volume.into();
+        let before = self.volume_mode_of(&volume)?;
+        self.set_property(&volume, "volmode", mode.as_ref())?;
+        Ok(self.volume_mode_of(&volume)? != before)
+    }
+
+    fn volume_device_path<N: Into<PathBuf>>(&self, volume: N) -> Result<PathBuf> {
+        volume_device_path_with(self, volume.into(), || {
+            zvol::system_volume_mode(&self.logger, self.instrumentation.as_ref())
+        })
+    }
+
+    fn send_replication<N: Into<PathBuf>, FD: AsRawFd>(
+        &self,
+        to_snapshot: N,
+        from: Option<PathBuf>,
+        fd: FD,
+        opts: ReplicationSendOptions,
+    ) -> Result<()> {
+        let mut z = self.zfs();
+        z.args(["send", "-R"]);
+        z.args(opts.to_args());
+        if let Some(from) = from {
+            z.arg(if *opts.intermediary() { "-I" } else { "-i" });
+            z.arg(from.as_os_str());
+        }
+        z.arg(to_snapshot.into().as_os_str());
+        z.stdout(ZfsOpen3::stdio_from_fd(fd.as_raw_fd())?);
+        debug!(self.logger, "executing"; "cmd" => format_args!("{:?}", z));
+        let out = self.stream_output(&mut z)?;
+        if out.status.success() {
+            Ok(())
+        } else {
+            Err(Error::from_stderr(&out.stderr))
+        }
+    }
+
+    fn receive<N: Into<PathBuf>, FD: AsRawFd>(
+        &self,
+        target: N,
+        fd: FD,
+        mut opts: ReceiveOptions,
+    ) -> Result<()> {
+        let target = target.into();
+        let fd = fd.as_raw_fd();
+        // Offset of the stream start, if stream can be rewound at all.
+        let start = if *opts.retry_excluding_invalid() {
+            Some(unsafe { libc::lseek(fd, 0, libc::SEEK_CUR) }).filter(|offset| *offset >= 0)
+        } else {
+            None
+        };
+        let mut attempt = 1;
+        loop {
+            let ret = self.receive_once(&target, fd, opts.clone());
+            let property = match (&ret, start) {
+                (Err(Error::InvalidReceiveProperty(property)), Some(start))
+                    if attempt < RECEIVE_ATTEMPTS
+                        && !opts.exclude_properties().contains(property) =>
+                {
+                    if unsafe { libc::lseek(fd, start, libc::SEEK_SET) } < 0 {
+                        return ret;
+                    }
+                    property.clone()
+                }
+                _ => return ret,
             };
-            Ok(ret)
+            debug!(self.logger, "retrying receive"; "exclude" => &property, "attempt" => attempt);
+            opts = opts.exclude_property(property);
+            attempt += 1;
+        }
+    }
+
+    fn partial_receives<N: Into<PathBuf>>(&self, root: N) -> Result<Vec<PartialReceive>> {
+        let root = root.into();
+        let mut z = self.zfs();
+        z.args([
+            "get",
+            "-Hpr",
+            "-t",
+            "filesystem,volume",
+            "-o",
+            "name,property,value",
+            "receive_resume_token,used",
+        ]);
+        z.arg(root.as_os_str());
+        debug!(self.logger, "executing"; "cmd" => format_args!("{:?}", z));
+        let out = self.output(&mut z)?;
+        if !out.status.success() {
+            return Err(list_error(&root, &out.stderr));
+        }
+        let rows = parse_resume_rows(&out.stdout)?;
+        let mut ret = Vec::new();
+        for (dataset, (token, used)) in &rows {
+            let token = match token {
+                Some(token) if !dataset.ends_with(RECV_CHILD) => token.clone(),
+                _ => continue,
+            };
+            // Incremental receive keeps its state in a hidden child. Some versions list it, others
+            // only show it when asked by name. Full receive keeps its state in the dataset itself.
+            let child = dataset.join(RECV_CHILD);
+            let bytes_received = match rows.get(&child) {
+                Some((_, used)) => *used,
+                None => match self.get_property::<_, u64>(child, "used") {
+                    Ok(used) => Some(used),
+                    Err(Error::DatasetNotFound(_)) => *used,
+                    Err(_) => None,
+                },
+            };
+            ret.push(PartialReceive::new(dataset.clone(), token, bytes_received));
+        }
+        Ok(ret)
+    }
+
+    fn abort_partial_receive<N: Into<PathBuf>>(&self, dataset: N) -> Result<()> {
+        let mut z = self.zfs();
+        z.args(["receive", "-A"]);
+        z.arg(dataset.into().as_os_str());
+        debug!(self.logger, "executing"; "cmd" => format_args!("{:?}", z));
+        let out = self.output(&mut z)?;
+        if out.status.success() {
+            Ok(())
+        } else {
+            Err(Error::from_stderr(&out.stderr))
+        }
+    }
+
+    fn allow<N: Into<PathBuf>>(&self, dataset: N, grant: DelegationGrant) -> Result<()> {
+        let mut z = self.zfs();
+        z.arg("allow");
+        z.args(grant.into_args());
+        z.arg(dataset.into().as_os_str());
+        debug!(self.logger, "executing"; "cmd" => format_args!("{:?}", z));
+        let out = self.output(&mut z)?;
+        if out.status.success() {
+            Ok(())
+        } else {
+            Err(Error::from_stderr(&out.stderr))
+        }
+    }
+
+    fn unallow<N: Into<PathBuf>>(&self, dataset: N, grant: DelegationGrant) -> Result<()> {
+        let mut z = self.zfs();
+        z.arg("unallow");
+        z.args(grant.into_args());
+        z.arg(dataset.into().as_os_str());
+        debug!(self.logger, "executing"; "cmd" => format_args!("{:?}", z));
+        let out = self.output(&mut z)?;
+        if out.status.success() {
+            Ok(())
+        } else {
+            Err(Error::from_stderr(&out.stderr))
+        }
+    }
+
+    fn read_delegations<N: Into<PathBuf>>(&self, dataset: N) -> Result<Vec<Delegation>> {
+        let mut z = self.zfs();
+        z.arg("allow");
+        z.arg(dataset.into().as_os_str());
+        debug!(self.logger, "executing"; "cmd" => format_args!("{:?}", z));
+        let out = self.output(&mut z)?;
+        if out.status.success() {
+            parse_delegations(&String::from_utf8_lossy(&out.stdout))
         } else {
             Err(Error::from_stderr(&out.stderr))
         }
     }
+
+    fn read_properties<N: Into<PathBuf>>(&self, path: N) -> Result<Properties> {
+        let path = path.into();
+        let stdout = self.get_all_properties(&path)?;
+        parse_properties(&stdout, path)
+    }
+
+    fn read_properties_with_sources<N: Into<PathBuf>>(
+        &self,
+        path: N,
+    ) -> Result<(Properties, HashMap<String, PropertySource>)> {
+        let path = path.into();
+        let stdout = self.get_all_properties(&path)?;
+        let sources = parse_property_sources(&stdout)?;
+        Ok((parse_properties(&stdout, path)?, sources))
+    }
+
+    fn read_property_values<N: Into<PathBuf>>(
+        &self,
+        root: N,
+        properties: &[String],
+    ) -> Result<Vec<(PathBuf, HashMap<String, String>)>> {
+        self.property_values(&root.into(), "filesystem,volume", properties)
+    }
+
+    fn read_snapshot_property_values<N: Into<PathBuf>>(
+        &self,
+        root: N,
+        properties: &[String],
+    ) -> Result<Vec<(PathBuf, HashMap<String, String>)>> {
+        self.property_values(&root.into(), "snapshot", properties)
+    }
 }
 
 impl ZfsOpen3 {
-    #[allow(clippy::option_unwrap_used)]
-    fn stdout_to_list_of_datasets(z: &mut Command) -> Result<Vec<PathBuf>, Error> {
-        let out = z.output()?;
+    /// `zfs get -Hpr` of `properties` of every dataset of `types` under `root`.
+    fn property_values(
+        &self,
+        root: &Path,
+        types: &str,
+        properties: &[String],
+    ) -> Result<Vec<(PathBuf, HashMap<String, String>)>> {
+        let mut z = self.zfs();
+        z.args(["get", "-Hpr", "-t", types, "-o", "name,property,value"]);
+        z.arg(properties.join(","));
+        z.arg(root.as_os_str());
+        debug!(self.logger, "executing"; "cmd" => format_args!("{:?}", z));
+        let out = self.output(&mut z)?;
+        if !out.status.success() {
+            return Err(list_error(root, &out.stderr));
+        }
+        parse_property_values(&out.stdout)
+    }
+
+    /// Duplicate `fd` so that child process can use it as stdin or stdout. Caller keeps
+    /// ownership of the original.
+    fn stdio_from_fd(fd: RawFd) -> Result<Stdio> {
+        let dup = unsafe { libc::dup(fd) };
+        if dup < 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+        Ok(unsafe { Stdio::from_raw_fd(dup) })
+    }
+
+    fn send_with_progress<P: FnMut(SendProgress)>(
+        &self,
+        path: PathBuf,
+        from: Option<PathBuf>,
+        fd: RawFd,
+        flags: SendFlags,
+        mut progress: P,
+    ) -> Result<()> {
+        let mut z = self.zfs();
+        z.args(["send", "-v"]);
+        z.args(flags.to_args());
+        if let Some(from) = from {
+            z.arg("-i");
+            z.arg(from.as_os_str());
+        }
+        z.arg(path.as_os_str());
+        z.stdout(ZfsOpen3::stdio_from_fd(fd)?);
+        debug!(self.logger, "executing"; "cmd" => format_args!("{:?}", z));
+        let started_at = Instant::now();
+        // Progress is interleaved with errors, only the rest of stderr is used to tell what failed.
+        let mut errors = Vec::new();
+        let out = instrumentation::run_command_with_stderr_lines(
+            &self.logger,
+            self.instrumentation.as_ref(),
+            &mut z,
+            |line| {
+                if let Some((bytes_sent, snapshot)) = parse_send_progress(line) {
+                    progress(SendProgress::new(
+                        started_at.elapsed(),
+                        bytes_sent,
+                        snapshot,
+                    ));
+                } else if !is_send_estimate(line) {
+                    errors.extend_from_slice(line.as_bytes());
+                    errors.push(b'\n');
+                }
+            },
+        )?;
         if out.status.success() {
-            let stdout = String::from_utf8_lossy(&out.stdout);
-            ZfsParser::parse(Rule::datasets, &stdout)
-                .map(|mut pairs| {
-                    pairs
-                        .next()
-                        .unwrap()
-                        .into_inner()
-                        .map(|pair| {
-                            debug_assert_eq!(Rule::dataset_name, pair.as_rule());
-                            PathBuf::from(pair.as_str())
-                        })
-                        .collect()
-                })
-                .map_err(|_| Error::UnknownSoFar(String::from(stdout)))
+            Ok(())
+        } else {
+            Err(Error::from_stderr(&errors))
+        }
+    }
+
+    fn receive_once(&self, target: &Path, fd: RawFd, opts: ReceiveOptions) -> Result<()> {
+        let mut z = self.zfs();
+        z.arg("receive");
+        z.args(opts.into_args());
+        z.arg(target.as_os_str());
+        z.stdin(ZfsOpen3::stdio_from_fd(fd)?);
+        debug!(self.logger, "executing"; "cmd" => format_args!("{:?}", z));
+        let out = self.stream_output(&mut z)?;
+        if out.status.success() {
+            Ok(())
+        } else {
+            Err(Error::from_stderr(&out.stderr))
+        }
+    }
+
+    fn volume_mode_of(&self, volume: &Path) -> Result<Option<VolumeMode>> {
+        match self.read_properties(volume)? {
+            Properties::Volume(props) => Ok(*props.volume_mode()),
+            _ => Err(Error::invalid_input()),
+        }
+    }
+
+    /// `zfs set <key>=<value>` on given dataset.
+    fn set_property(&self, dataset: &Path, key: &str, value: &str) -> Result<()> {
+        let mut z = self.zfs();
+        z.arg("set");
+        z.arg(format!("{}={}", key, value));
+        z.arg(dataset.as_os_str());
+        debug!(self.logger, "executing"; "cmd" => format_args!("{:?}", z));
+        let out = self.output(&mut z)?;
+        if out.status.success() {
+            Ok(())
         } else {
             Err(Error::from_stderr(&out.stderr))
         }
     }
+
+    /// Output of `zfs get -Hp all` for given dataset.
+    fn get_all_properties(&self, path: &Path) -> Result<String> {
+        let mut z = self.zfs();
+        z.args(&["get", "-Hp", "all"]);
+        z.arg(path.as_os_str());
+        debug!(self.logger, "executing"; "cmd" => format_args!("{:?}", z));
+        let out = self.output(&mut z)?;
+        if out.status.success() {
+            properties_text(&out.stdout)
+        } else {
+            Err(Error::from_stderr(&out.stderr))
+        }
+    }
+
+    /// `zfs share` or `zfs unshare` with `args`.
+    fn run_sharing<S: AsRef<OsStr>>(&self, subcommand: &str, args: &[S]) -> Result<()> {
+        let mut z = self.zfs();
+        z.arg(subcommand);
+        z.args(args);
+        debug!(self.logger, "executing"; "cmd" => format_args!("{:?}", z));
+        let out = self.output(&mut z)?;
+        if out.status.success() {
+            Ok(())
+        } else {
+            Err(Error::from_stderr(&out.stderr))
+        }
+    }
+
+    /// Names printed by `zfs list` of `requested` dataset.
+    fn list_names(&self, z: &mut Command, requested: &Path) -> Result<Vec<PathBuf>> {
+        let out = self.output(z)?;
+        if out.status.success() {
+            parse_dataset_list(&out.stdout)
+        } else {
+            Err(list_error(requested, &out.stderr))
+        }
+    }
+
+    /// First line of snapshots of `dataset` sorted by `createtxg` using `sort` (`-s` or `-S`).
+    fn first_snapshot(&self, dataset: PathBuf, sort: &str) -> Result<Option<PathBuf>> {
+        let mut z = self.zfs();
+        z.args([
+            "list",
+            "-t",
+            "snapshot",
+            "-Hp",
+            "-o",
+            "name",
+            sort,
+            "createtxg",
+            "-d",
+            "1",
+        ]);
+        z.arg(dataset.as_os_str());
+        debug!(self.logger, "executing"; "cmd" => format_args!("{:?}", z));
+        let out = self.output(&mut z)?;
+        if !out.status.success() {
+            return Err(list_error(&dataset, &out.stderr));
+        }
+        Ok(out
+            .stdout
+            .split(|byte| *byte == b'\n')
+            .find(|line| !line.is_empty())
+            .map(|line| PathBuf::from(OsStr::from_bytes(line))))
+    }
+
+    fn stdout_to_list_of_datasets(&self, z: &mut Command) -> Result<Vec<PathBuf>, Error> {
+        let out = self.output(z)?;
+        if out.status.success() {
+            parse_dataset_list(&out.stdout)
+        } else {
+            Err(Error::from_stderr(&out.stderr))
+        }
+    }
+}
+
+/// Whether usage of `subcommand` that `zfs` prints lists `flag`, either alone (`-u`) or among
+/// others (`[-fu]`).
+pub(crate) fn usage_has_flag(usage: &[u8], subcommand: &str, flag: char) -> bool {
+    String::from_utf8_lossy(usage)
+        .lines()
+        .filter(|line| line.split_whitespace().next() == Some(subcommand))
+        .flat_map(str::split_whitespace)
+        .filter(|word| !word.starts_with("--"))
+        .filter_map(|word| word.trim_start_matches('[').strip_prefix('-'))
+        .any(|flags| flags.trim_end_matches(']').contains(flag))
+}
+
+/// Error of `zfs list` of `requested` dataset. Depending on version, missing dataset is reported
+/// with `@` appended, as a missing pool or under a name of its parent; either way it's
+/// `DatasetNotFound` of `requested`.
+fn list_error(requested: &Path, stderr: &[u8]) -> Error {
+    match Error::from_stderr(stderr) {
+        Error::DatasetNotFound(_) => Error::DatasetNotFound(requested.to_path_buf()),
+        other => other,
+    }
 }
 
-fn parse_prop_line(line: &str) -> (String, String) {
+/// Datasets to list recursively to see every one of `names`: per pool, the deepest dataset that
+/// contains all of them. Snapshots and bookmarks count as the dataset they belong to, empty names
+/// are left out.
+pub(crate) fn common_roots(names: &[PathBuf]) -> Vec<PathBuf> {
+    let mut roots: BTreeMap<PathBuf, PathBuf> = BTreeMap::new();
+    for name in names {
+        let bytes = name.as_os_str().as_bytes();
+        let end = bytes
+            .iter()
+            .position(|byte| *byte == b'@' || *byte == b'#')
+            .unwrap_or(bytes.len());
+        let dataset = Path::new(OsStr::from_bytes(&bytes[..end]));
+        let pool = match dataset.components().next() {
+            Some(pool) => PathBuf::from(pool.as_os_str()),
+            None => continue,
+        };
+        let root = roots.entry(pool).or_insert_with(|| dataset.to_path_buf());
+        *root = root
+            .components()
+            .zip(dataset.components())
+            .take_while(|(a, b)| a == b)
+            .map(|(a, _)| a)
+            .collect();
+    }
+    roots.into_values().collect()
+}
+
+/// Non-empty lines of `zfs` output split into tab separated fields. Splitting is done on bytes,
+/// so names that aren't valid UTF-8 survive.
+fn byte_fields(stdout: &[u8]) -> impl Iterator<Item = Vec<&[u8]>> {
+    stdout
+        .split(|byte| *byte == b'\n')
+        .filter(|line| !line.is_empty())
+        .map(|line| line.split(|byte| *byte == b'\t').collect())
+}
+
+/// Names and mount points are arbitrary bytes on Unix, `PathBuf` keeps them as they are.
+fn path_from_bytes(bytes: &[u8]) -> PathBuf {
+    PathBuf::from(OsStr::from_bytes(bytes))
+}
+
+/// For fields that have to be text, like property names and values.
+fn str_from_bytes(bytes: &[u8]) -> Result<&str> {
+    std::str::from_utf8(bytes).map_err(|_| Error::NonUtf8(bytes.to_vec()))
+}
+
+/// Parse output of `zfs list -H -o name`.
+pub(crate) fn parse_dataset_list(stdout: &[u8]) -> Result<Vec<PathBuf>> {
+    Ok(byte_fields(stdout)
+        .map(|fields| path_from_bytes(fields[0]))
+        .collect())
+}
+
+/// Parse output of `zfs list -H -o type,name`.
+pub(crate) fn parse_typed_dataset_list(stdout: &[u8]) -> Result<Vec<(DatasetKind, PathBuf)>> {
+    byte_fields(stdout)
+        .map(|fields| match fields.as_slice() {
+            [kind, name] => {
+                let kind = str_from_bytes(kind)?
+                    .parse()
+                    .map_err(|_| Error::UnknownSoFar(String::from_utf8_lossy(kind).into()))?;
+                Ok((kind, path_from_bytes(name)))
+            }
+            _ => Err(Error::UnknownSoFar(
+                String::from_utf8_lossy(&fields.join(&b'\t')).into(),
+            )),
+        })
+        .collect()
+}
+
+/// Parse output of `zfs mount`. Dataset names can't contain whitespace, so everything after the
+/// first run of whitespace is a mount point, even if it contains spaces.
+pub(crate) fn parse_mount_table(stdout: &[u8]) -> Vec<(PathBuf, PathBuf)> {
+    stdout
+        .split(|byte| *byte == b'\n')
+        .filter_map(|line| {
+            let split_at = line.iter().position(u8::is_ascii_whitespace)?;
+            let (dataset, mount_point) = line.split_at(split_at);
+            let start = mount_point
+                .iter()
+                .position(|byte| !byte.is_ascii_whitespace())
+                .unwrap_or(mount_point.len());
+            let mount_point = &mount_point[start..];
+            if dataset.is_empty() || mount_point.is_empty() {
+                return None;
+            }
+            Some((path_from_bytes(dataset), path_from_bytes(mount_point)))
+        })
+        .collect()
+}
+
+/// Out of date file systems from `zfs upgrade` listing. The listing has two tables with the same
+/// `VER FILESYSTEM` header: file systems that can be upgraded and file systems formatted by newer
+/// software. Only the first one is of interest, everything else is prose.
+pub(crate) fn parse_upgrade_listing(stdout: &[u8]) -> Vec<(PathBuf, u32)> {
+    let mut out_of_date = false;
+    let mut ret = Vec::new();
+    for line in stdout.split(|byte| *byte == b'\n') {
+        let text = String::from_utf8_lossy(line);
+        if text.contains("are out of date") {
+            out_of_date = true;
+            continue;
+        }
+        if text.contains("newer software version") {
+            out_of_date = false;
+            continue;
+        }
+        if !out_of_date {
+            continue;
+        }
+        let start = match line.iter().position(|byte| !byte.is_ascii_whitespace()) {
+            Some(start) => start,
+            None => continue,
+        };
+        let line = &line[start..];
+        let split_at = match line.iter().position(u8::is_ascii_whitespace) {
+            Some(split_at) => split_at,
+            None => continue,
+        };
+        let (version, dataset) = line.split_at(split_at);
+        let version = match std::str::from_utf8(version)
+            .ok()
+            .and_then(|v| v.parse().ok())
+        {
+            Some(version) => version,
+            None => continue,
+        };
+        let start = dataset
+            .iter()
+            .position(|byte| !byte.is_ascii_whitespace())
+            .unwrap_or(dataset.len());
+        let dataset = &dataset[start..];
+        if !dataset.is_empty() {
+            ret.push((path_from_bytes(dataset), version));
+        }
+    }
+    ret
+}
+
+/// Parse progress line of `zfs send -v`: time of day, amount sent so far and snapshot name, e.g.
+/// `12:01:02   1.21G   tank/data@today`. Returns `None` for any other line.
+pub(crate) fn parse_send_progress(line: &str) -> Option<(u64, PathBuf)> {
+    let mut columns = line.split_whitespace();
+    let (time, sent, snapshot) = (columns.next()?, columns.next()?, columns.next()?);
+    if columns.next().is_some() || !is_time_of_day(time) {
+        return None;
+    }
+    Some((parse_human_size(sent)?, PathBuf::from(snapshot)))
+}
+
+/// Whether line is one of the estimates `zfs send -v` prints before sending, including the
+/// header of progress table.
+fn is_send_estimate(line: &str) -> bool {
+    line.contains("estimated size is") || line.starts_with("TIME ")
+}
+
+/// `HH:MM:SS`, the way `zfs send -v` prints it regardless of locale.
+fn is_time_of_day(value: &str) -> bool {
+    let parts: Vec<&str> = value.split(':').collect();
+    parts.len() == 3
+        && parts
+            .iter()
+            .all(|part| part.len() == 2 && part.bytes().all(|b| b.is_ascii_digit()))
+}
+
+/// Size as printed by `zfs(8)`: either amount of bytes or a number with a binary suffix, e.g.
+/// `1.21G`.
+pub(crate) fn parse_human_size(value: &str) -> Option<u64> {
+    let (number, shift) = match value.as_bytes().last()? {
+        b'B' => (&value[..value.len() - 1], 0),
+        b'K' => (&value[..value.len() - 1], 10),
+        b'M' => (&value[..value.len() - 1], 20),
+        b'G' => (&value[..value.len() - 1], 30),
+        b'T' => (&value[..value.len() - 1], 40),
+        b'P' => (&value[..value.len() - 1], 50),
+        b'E' => (&value[..value.len() - 1], 60),
+        _ => (value, 0),
+    };
+    if let Ok(number) = number.parse::<u64>() {
+        return number.checked_mul(1 << shift);
+    }
+    let number: f64 = number.parse().ok()?;
+    if !number.is_finite() || number < 0.0 {
+        return None;
+    }
+    Some((number * (1u64 << shift) as f64) as u64)
+}
+
+/// Output of `zfs get -Hp` with name column dropped, so it can go through text parsers. Name is
+/// known to caller anyway and might not be valid UTF-8. Property names and values have to be.
+fn properties_text(stdout: &[u8]) -> Result<String> {
+    let mut ret = String::with_capacity(stdout.len());
+    for fields in byte_fields(stdout) {
+        ret.push('-');
+        for field in fields.iter().skip(1) {
+            ret.push('\t');
+            ret.push_str(str_from_bytes(field)?);
+        }
+        ret.push('\n');
+    }
+    Ok(ret)
+}
+
+/// Map names listed before rename of `from` to their names after it was renamed to `to`. Names
+/// that don't belong to `from` are dropped.
+pub(crate) fn map_renamed(
+    from: &Path,
+    to: &Path,
+    affected: Vec<PathBuf>,
+) -> Vec<(PathBuf, PathBuf)> {
+    let from_bytes = from.as_os_str().as_bytes();
+    let to_bytes = to.as_os_str().as_bytes();
+    affected
+        .into_iter()
+        .filter_map(|old| {
+            let new = {
+                let rest = old.as_os_str().as_bytes().strip_prefix(from_bytes)?;
+                if !(rest.is_empty() || b"/@#".contains(&rest[0])) {
+                    return None;
+                }
+                path_from_bytes(&[to_bytes, rest].concat())
+            };
+            Some((old, new))
+        })
+        .collect()
+}
+
+/// Value of size property for `zfs set`.
+fn size_or_none(size: Option<ByteSize>) -> String {
+    size.map_or_else(|| String::from("none"), |size| size.as_u64().to_string())
+}
+
+/// `-o key=value` for every property, sorted by name so command line is the same every time.
+fn clone_property_args(properties: Option<HashMap<String, String>>) -> Vec<String> {
+    let mut properties: Vec<(String, String)> =
+        properties.unwrap_or_default().into_iter().collect();
+    properties.sort();
+    properties
+        .into_iter()
+        .flat_map(|(key, value)| vec![String::from("-o"), format!("{}={}", key, value)])
+        .collect()
+}
+
+pub(crate) fn parse_properties(stdout: &str, path: PathBuf) -> Result<Properties> {
+    let mut lines = stdout.lines();
+
+    let first = lines
+        .next()
+        .ok_or_else(|| Error::UnknownSoFar(String::from(stdout)))?;
+    let kind = parse_prop_line(first)?.1;
+    match kind.as_ref() {
+        "filesystem" => parse_filesystem_lines(&mut lines, path),
+        "snapshot" => parse_snapshot_lines(&mut lines, path),
+        "volume" => parse_volume_lines(&mut lines, path),
+        "bookmark" => parse_bookmark_lines(&mut lines, path),
+        _ => parse_unknown_lines(&mut lines),
+    }
+}
+
+/// Collect `SOURCE` column of `zfs get -Hp` output.
+pub(crate) fn parse_property_sources(stdout: &str) -> Result<HashMap<String, PropertySource>> {
+    stdout
+        .lines()
+        .filter(|line| !line.is_empty())
+        .map(|line| {
+            let mut splits = line.split('\t').skip(1);
+            let name = splits
+                .next()
+                .ok_or_else(|| Error::UnknownSoFar(String::from(line)))?;
+            let source = splits.nth(1).unwrap_or("-");
+            Ok((name.to_string(), source.parse()?))
+        })
+        .collect()
+}
+
+/// Name and value columns of a `zfs get -Hp` line.
+fn parse_prop_line(line: &str) -> Result<(String, String)> {
     let mut splits = line.split('\t');
     // consume dataset name
-    splits.next().expect("Failed to parse output");
-    let name = splits.next().expect("failed to extract key").to_string();
-    let value = splits.next().expect("Failed to extract value").to_string();
-    (name, value)
+    splits.next();
+    match (splits.next(), splits.next()) {
+        (Some(name), Some(value)) => Ok((name.to_string(), value.to_string())),
+        _ => Err(Error::UnknownSoFar(String::from(line))),
+    }
+}
+
+/// Parse output of `zfs get -Hp written@<snapshot> <dataset>`. Property name contains `@`, so it's
+/// matched as a whole and never treated as a snapshot name.
+pub(crate) fn parse_written_since(stdout: &str, property: &str) -> Result<u64> {
+    stdout
+        .lines()
+        .filter(|line| !line.is_empty())
+        .filter_map(|line| parse_prop_line(line).ok())
+        .find(|(key, _)| key == property)
+        .and_then(|(_, value)| value.parse().ok())
+        .ok_or_else(|| Error::UnknownSoFar(String::from(stdout)))
+}
+
+/// Sizes are exact with `-p`, but go through the same parser as human-readable ones. Unset
+/// values (`none`) are `0` just like `zfs get -p` reports them.
+fn parse_byte_size(key: &str, value: &str) -> Result<u64> {
+    ByteSize::parse_optional(value)
+        .map(|size| size.map_or(0, ByteSize::as_u64))
+        .map_err(|_| invalid_value(key, value))
+}
+
+fn parse_value<T: FromStr>(key: &str, value: &str) -> Result<T> {
+    value.parse().map_err(|_| invalid_value(key, value))
+}
+
+/// Ratios are printed with `x` suffix, e.g. `1.50x`.
+fn parse_ratio(key: &str, value: &str) -> Result<f64> {
+    parse_float(&mut value.to_string()).map_err(|_| invalid_value(key, value))
+}
+
+fn invalid_value(key: &str, value: &str) -> Error {
+    Error::InvalidPropertyValue {
+        property: key.to_string(),
+        value: value.to_string(),
+    }
 }
 
 fn parse_list_of_pathbufs(value: &str) -> Option<Vec<PathBuf>> {
@@ -213,88 +1590,89 @@ fn parse_list_of_pathbufs(value: &str) -> Option<Vec<PathBuf>> {
     Some(clones)
 }
 
-fn parse_creation_into_timestamp(value: &str) -> i64 {
+fn parse_creation_into_timestamp(key: &str, value: &str) -> Result<i64> {
     if let Ok(timestamp) = value.parse() {
-        timestamp
+        Ok(timestamp)
     } else {
-        let date = NaiveDateTime::parse_from_str(value, DATE_FORMAT).expect(FAILED_TO_PARSE);
-        date.timestamp()
+        NaiveDateTime::parse_from_str(value, DATE_FORMAT)
+            .map(|date| date.timestamp())
+            .map_err(|_| invalid_value(key, value))
     }
 }
 
-pub(crate) fn parse_filesystem_lines(lines: &mut Lines, name: PathBuf) -> Properties {
+pub(crate) fn parse_filesystem_lines(lines: &mut Lines, name: PathBuf) -> Result<Properties> {
     let mut properties = FilesystemProperties::builder(name);
-    for (key, value) in lines.map(parse_prop_line) {
+    for line in lines.filter(|line| !line.is_empty()) {
+        let (key, value) = parse_prop_line(line)?;
         match key.as_ref() {
             "aclinherit" => {
-                properties.acl_inherit(value.parse().expect(FAILED_TO_PARSE));
+                properties.acl_inherit(parse_value(&key, &value)?);
             }
             "aclmode" => {
-                properties.acl_mode(Some(value.parse().expect(FAILED_TO_PARSE)));
+                properties.acl_mode(Some(parse_value(&key, &value)?));
             }
             "atime" => {
                 properties.atime(parse_bool(&value));
             }
             "available" => {
-                properties.available(value.parse().expect(FAILED_TO_PARSE));
+                properties.available(parse_value(&key, &value)?);
             }
             "canmount" => {
-                properties.can_mount(value.parse().expect(FAILED_TO_PARSE));
+                properties.can_mount(parse_value(&key, &value)?);
             }
             "casesensitivity" => {
-                properties.case_sensitivity(value.parse().expect(FAILED_TO_PARSE));
+                properties.case_sensitivity(parse_value(&key, &value)?);
             }
             "checksum" => {
-                properties.checksum(value.parse().expect(FAILED_TO_PARSE));
+                properties.checksum(parse_value(&key, &value)?);
             }
             "compression" => {
-                properties.compression(value.parse().expect(FAILED_TO_PARSE));
+                properties.compression(parse_value(&key, &value)?);
             }
             "compressratio" => {
-                properties
-                    .compression_ratio(parse_float(&mut value.clone()).expect(FAILED_TO_PARSE));
+                properties.compression_ratio(parse_ratio(&key, &value)?);
             }
             "copies" => {
-                properties.copies(value.parse().expect(FAILED_TO_PARSE));
+                properties.copies(parse_value(&key, &value)?);
             }
             "createtxg" => {
-                properties.create_txg(Some(value.parse().expect(FAILED_TO_PARSE)));
+                properties.create_txg(Some(parse_value(&key, &value)?));
             }
             "creation" => {
-                properties.creation(value.parse().expect(FAILED_TO_PARSE));
+                properties.creation(parse_value(&key, &value)?);
             }
             "dedup" => {
-                properties.dedup(value.parse().expect(FAILED_TO_PARSE));
+                properties.dedup(parse_value(&key, &value)?);
             }
             "devices" => {
                 properties.devices(parse_bool(&value));
             }
             "dnodesize" => {
-                properties.dnode_size(value.parse().expect(FAILED_TO_PARSE));
+                properties.dnode_size(parse_value(&key, &value)?);
             }
             "exec" => {
                 properties.exec(parse_bool(&value));
             }
             "filesystem_count" => {
-                properties.filesystem_count(parse_opt_num(&value));
+                properties.filesystem_count(parse_opt_num(&key, &value)?);
             }
             "filesystem_limit" => {
-                properties.filesystem_limit(parse_opt_num(&value));
+                properties.filesystem_limit(parse_opt_num(&key, &value)?);
             }
             "guid" => {
-                properties.guid(Some(value.parse().expect(FAILED_TO_PARSE)));
+                properties.guid(Some(parse_value(&key, &value)?));
             }
             "jailed" => {
                 properties.jailed(Some(parse_bool(&value)));
             }
             "logbias" => {
-                properties.log_bias(value.parse().expect(FAILED_TO_PARSE));
+                properties.log_bias(parse_value(&key, &value)?);
             }
             "logicalreferenced" => {
-                properties.logical_referenced(value.parse().expect(FAILED_TO_PARSE));
+                properties.logical_referenced(parse_value(&key, &value)?);
             }
             "logicalused" => {
-                properties.logical_used(value.parse().expect(FAILED_TO_PARSE));
+                properties.logical_used(parse_value(&key, &value)?);
             }
             "mlslabel" => {
                 properties.mls_label(parse_mls_label(value));
@@ -309,89 +1687,94 @@ pub(crate) fn parse_filesystem_lines(lines: &mut Lines, name: PathBuf) -> Proper
                 properties.nbmand(parse_bool(&value));
             }
             "normalization" => {
-                properties.normalization(value.parse().expect(FAILED_TO_PARSE));
+                properties.normalization(parse_value(&key, &value)?);
             }
             "origin" => {
                 properties.origin(Some(value));
             }
             "primarycache" => {
-                properties.primary_cache(value.parse().expect(FAILED_TO_PARSE));
+                properties.primary_cache(parse_value(&key, &value)?);
             }
             "quota" => {
-                properties.quota(value.parse().expect(FAILED_TO_PARSE));
+                properties.quota(parse_byte_size(&key, &value)?);
             }
             "readonly" => {
                 properties.readonly(parse_bool(&value));
             }
             "recordsize" => {
-                properties.record_size(value.parse().expect(FAILED_TO_PARSE));
+                properties.record_size(parse_byte_size(&key, &value)?);
             }
             "redundant_metadata" => {
-                properties.redundant_metadata(value.parse().expect(FAILED_TO_PARSE));
+                properties.redundant_metadata(parse_value(&key, &value)?);
             }
             "refcompressratio" => {
-                properties
-                    .ref_compression_ratio(parse_float(&mut value.clone()).expect(FAILED_TO_PARSE));
+                properties.ref_compression_ratio(parse_ratio(&key, &value)?);
             }
             "refquota" => {
-                properties.ref_quota(value.parse().expect(FAILED_TO_PARSE));
+                properties.ref_quota(parse_byte_size(&key, &value)?);
             }
             "refreservation" => {
-                properties.ref_reservation(value.parse().expect(FAILED_TO_PARSE));
+                properties.ref_reservation(parse_byte_size(&key, &value)?);
             }
             "referenced" => {
-                properties.referenced(value.parse().expect(FAILED_TO_PARSE));
+                properties.referenced(parse_value(&key, &value)?);
             }
             "reservation" => {
-                properties.reservation(value.parse().expect(FAILED_TO_PARSE));
+                properties.reservation(parse_byte_size(&key, &value)?);
             }
             "secondarycache" => {
-                properties.secondary_cache(value.parse().expect(FAILED_TO_PARSE));
+                properties.secondary_cache(parse_value(&key, &value)?);
             }
             "setuid" => {
                 properties.setuid(parse_bool(&value));
             }
+            "sharenfs" => {
+                properties.share_nfs(parse_value(&key, &value)?);
+            }
+            "sharesmb" => {
+                properties.share_smb(parse_value(&key, &value)?);
+            }
             "snapdir" => {
-                properties.snap_dir(value.parse().expect(FAILED_TO_PARSE));
+                properties.snap_dir(parse_value(&key, &value)?);
             }
             "snapshot_count" => {
-                properties.snapshot_count(parse_opt_num(&value));
+                properties.snapshot_count(parse_opt_num(&key, &value)?);
             }
             "snapshot_limit" => {
-                properties.snapshot_limit(parse_opt_num(&value));
+                properties.snapshot_limit(parse_opt_num(&key, &value)?);
             }
             "sync" => {
-                properties.sync(value.parse().expect(FAILED_TO_PARSE));
+                properties.sync(parse_value(&key, &value)?);
             }
             "used" => {
-                properties.used(value.parse().expect(FAILED_TO_PARSE));
+                properties.used(parse_value(&key, &value)?);
             }
             "usedbychildren" => {
-                properties.used_by_children(value.parse().expect(FAILED_TO_PARSE));
+                properties.used_by_children(parse_value(&key, &value)?);
             }
             "usedbydataset" => {
-                properties.used_by_dataset(value.parse().expect(FAILED_TO_PARSE));
+                properties.used_by_dataset(parse_value(&key, &value)?);
             }
             "usedbyrefreservation" => {
-                properties.used_by_ref_reservation(value.parse().expect(FAILED_TO_PARSE));
+                properties.used_by_ref_reservation(parse_value(&key, &value)?);
             }
             "usedbysnapshots" => {
-                properties.used_by_snapshots(value.parse().expect(FAILED_TO_PARSE));
+                properties.used_by_snapshots(parse_value(&key, &value)?);
             }
             "utf8only" => {
                 properties.utf8_only(Some(parse_bool(&value)));
             }
             "version" => {
-                properties.version(value.parse().expect(FAILED_TO_PARSE));
+                properties.version(parse_value(&key, &value)?);
             }
             "volmode" => {
-                properties.volume_mode(Some(value.parse().expect(FAILED_TO_PARSE)));
+                properties.volume_mode(Some(parse_value(&key, &value)?));
             }
             "vscan" => {
                 properties.vscan(parse_bool(&value));
             }
             "written" => {
-                properties.written(value.parse().expect(FAILED_TO_PARSE));
+                properties.written(parse_value(&key, &value)?);
             }
             "xattr" => {
                 properties.xattr(parse_bool(&value));
@@ -401,28 +1784,31 @@ pub(crate) fn parse_filesystem_lines(lines: &mut Lines, name: PathBuf) -> Proper
             _ => properties.insert_unknown_property(key, value),
         };
     }
-    Properties::Filesystem(properties.build().expect("Failed to build properties"))
+    properties
+        .build()
+        .map(Properties::Filesystem)
+        .map_err(|err| Error::UnknownSoFar(err.to_string()))
 }
 
-pub(crate) fn parse_snapshot_lines(lines: &mut Lines, name: PathBuf) -> Properties {
+pub(crate) fn parse_snapshot_lines(lines: &mut Lines, name: PathBuf) -> Result<Properties> {
     let mut properties = SnapshotProperties::builder(name);
-    for (key, value) in lines.map(parse_prop_line) {
+    for line in lines.filter(|line| !line.is_empty()) {
+        let (key, value) = parse_prop_line(line)?;
         match key.as_ref() {
             "casesensitivity" => {
-                properties.case_sensitivity(value.parse().expect(FAILED_TO_PARSE));
+                properties.case_sensitivity(parse_value(&key, &value)?);
             }
             "clones" => {
                 properties.clones(parse_list_of_pathbufs(&value));
             }
             "compressratio" => {
-                properties
-                    .compression_ratio(parse_float(&mut value.clone()).expect(FAILED_TO_PARSE));
+                properties.compression_ratio(parse_ratio(&key, &value)?);
             }
             "createtxg" => {
-                properties.create_txg(Some(value.parse().expect(FAILED_TO_PARSE)));
+                properties.create_txg(Some(parse_value(&key, &value)?));
             }
             "creation" => {
-                properties.creation(parse_creation_into_timestamp(&value));
+                properties.creation(parse_creation_into_timestamp(&key, &value)?);
             }
             "defer_destroy" => {
                 properties.defer_destroy(parse_bool(&value));
@@ -434,10 +1820,10 @@ pub(crate) fn parse_snapshot_lines(lines: &mut Lines, name: PathBuf) -> Properti
                 properties.exec(parse_bool(&value));
             }
             "guid" => {
-                properties.guid(Some(value.parse().expect(FAILED_TO_PARSE)));
+                properties.guid(Some(parse_value(&key, &value)?));
             }
             "logicalreferenced" => {
-                properties.logically_referenced(value.parse().expect(FAILED_TO_PARSE));
+                properties.logically_referenced(parse_value(&key, &value)?);
             }
             "mlslabel" => {
                 properties.mls_label(parse_mls_label(value));
@@ -446,41 +1832,40 @@ pub(crate) fn parse_snapshot_lines(lines: &mut Lines, name: PathBuf) -> Properti
                 properties.nbmand(parse_bool(&value));
             }
             "normalization" => {
-                properties.normalization(value.parse().expect(FAILED_TO_PARSE));
+                properties.normalization(parse_value(&key, &value)?);
             }
             "primarycache" => {
-                properties.primary_cache(value.parse().expect(FAILED_TO_PARSE));
+                properties.primary_cache(parse_value(&key, &value)?);
             }
             "refcompressratio" => {
-                properties
-                    .ref_compression_ratio(parse_float(&mut value.clone()).expect(FAILED_TO_PARSE));
+                properties.ref_compression_ratio(parse_ratio(&key, &value)?);
             }
             "referenced" => {
-                properties.referenced(value.parse().expect(FAILED_TO_PARSE));
+                properties.referenced(parse_value(&key, &value)?);
             }
             "secondarycache" => {
-                properties.secondary_cache(value.parse().expect(FAILED_TO_PARSE));
+                properties.secondary_cache(parse_value(&key, &value)?);
             }
             "setuid" => {
                 properties.setuid(parse_bool(&value));
             }
             "used" => {
-                properties.used(value.parse().expect(FAILED_TO_PARSE));
+                properties.used(parse_value(&key, &value)?);
             }
             "userrefs" => {
-                properties.user_refs(value.parse().expect(FAILED_TO_PARSE));
+                properties.user_refs(parse_value(&key, &value)?);
             }
             "utf8only" => {
                 properties.utf8_only(Some(parse_bool(&value)));
             }
             "version" => {
-                properties.version(value.parse().expect(FAILED_TO_PARSE));
+                properties.version(parse_value(&key, &value)?);
             }
             "volmode" => {
-                properties.volume_mode(Some(value.parse().expect(FAILED_TO_PARSE)));
+                properties.volume_mode(Some(parse_value(&key, &value)?));
             }
             "written" => {
-                properties.written(value.parse().expect(FAILED_TO_PARSE));
+                properties.written(parse_value(&key, &value)?);
             }
             "xattr" => {
                 properties.xattr(parse_bool(&value));
@@ -490,156 +1875,422 @@ pub(crate) fn parse_snapshot_lines(lines: &mut Lines, name: PathBuf) -> Properti
             _ => properties.insert_unknown_property(key, value),
         };
     }
-    Properties::Snapshot(properties.build().expect("Failed to build properties"))
+    properties
+        .build()
+        .map(Properties::Snapshot)
+        .map_err(|err| Error::UnknownSoFar(err.to_string()))
 }
 
-pub(crate) fn parse_volume_lines(lines: &mut Lines, name: PathBuf) -> Properties {
+pub(crate) fn parse_volume_lines(lines: &mut Lines, name: PathBuf) -> Result<Properties> {
     let mut properties = VolumeProperties::builder(name);
-    for (key, value) in lines.map(parse_prop_line) {
+    for line in lines.filter(|line| !line.is_empty()) {
+        let (key, value) = parse_prop_line(line)?;
         match key.as_ref() {
             "available" => {
-                properties.available(value.parse().expect(FAILED_TO_PARSE));
+                properties.available(parse_value(&key, &value)?);
             }
             "checksum" => {
-                properties.checksum(value.parse().expect(FAILED_TO_PARSE));
+                properties.checksum(parse_value(&key, &value)?);
             }
             "compression" => {
-                properties.compression(value.parse().expect(FAILED_TO_PARSE));
+                properties.compression(parse_value(&key, &value)?);
             }
             "compressratio" => {
-                properties
-                    .compression_ratio(parse_float(&mut value.clone()).expect(FAILED_TO_PARSE));
+                properties.compression_ratio(parse_ratio(&key, &value)?);
             }
             "copies" => {
-                properties.copies(value.parse().expect(FAILED_TO_PARSE));
+                properties.copies(parse_value(&key, &value)?);
             }
             "createtxg" => {
-                properties.create_txg(Some(value.parse().expect(FAILED_TO_PARSE)));
+                properties.create_txg(Some(parse_value(&key, &value)?));
             }
             "creation" => {
-                properties.creation(value.parse().expect(FAILED_TO_PARSE));
+                properties.creation(parse_value(&key, &value)?);
             }
             "dedup" => {
-                properties.dedup(value.parse().expect(FAILED_TO_PARSE));
+                properties.dedup(parse_value(&key, &value)?);
             }
             "guid" => {
-                properties.guid(Some(value.parse().expect(FAILED_TO_PARSE)));
+                properties.guid(Some(parse_value(&key, &value)?));
             }
             "logbias" => {
-                properties.log_bias(value.parse().expect(FAILED_TO_PARSE));
+                properties.log_bias(parse_value(&key, &value)?);
             }
             "logicalreferenced" => {
-                properties.logical_referenced(value.parse().expect(FAILED_TO_PARSE));
+                properties.logical_referenced(parse_value(&key, &value)?);
             }
             "logicalused" => {
-                properties.logical_used(value.parse().expect(FAILED_TO_PARSE));
+                properties.logical_used(parse_value(&key, &value)?);
             }
             "mlslabel" => {
                 properties.mls_label(parse_mls_label(value));
             }
             "primarycache" => {
-                properties.primary_cache(value.parse().expect(FAILED_TO_PARSE));
+                properties.primary_cache(parse_value(&key, &value)?);
             }
             "readonly" => {
                 properties.readonly(parse_bool(&value));
             }
             "redundant_metadata" => {
-                properties.redundant_metadata(value.parse().expect(FAILED_TO_PARSE));
+                properties.redundant_metadata(parse_value(&key, &value)?);
             }
             "refcompressratio" => {
-                properties
-                    .ref_compression_ratio(parse_float(&mut value.clone()).expect(FAILED_TO_PARSE));
+                properties.ref_compression_ratio(parse_ratio(&key, &value)?);
             }
             "referenced" => {
-                properties.referenced(value.parse().expect(FAILED_TO_PARSE));
+                properties.referenced(parse_value(&key, &value)?);
             }
             "refreservation" => {
-                properties.ref_reservation(value.parse().expect(FAILED_TO_PARSE));
+                properties.ref_reservation(parse_byte_size(&key, &value)?);
             }
             "reservation" => {
-                properties.reservation(value.parse().expect(FAILED_TO_PARSE));
+                properties.reservation(parse_byte_size(&key, &value)?);
             }
             "secondarycache" => {
-                properties.secondary_cache(value.parse().expect(FAILED_TO_PARSE));
+                properties.secondary_cache(parse_value(&key, &value)?);
+            }
+            "snapdev" => {
+                properties.snap_dev(Some(parse_value(&key, &value)?));
             }
             "snapshot_count" => {
-                properties.snapshot_count(parse_opt_num(&value));
+                properties.snapshot_count(parse_opt_num(&key, &value)?);
             }
             "snapshot_limit" => {
-                properties.snapshot_limit(parse_opt_num(&value));
+                properties.snapshot_limit(parse_opt_num(&key, &value)?);
             }
             "sync" => {
-                properties.sync(value.parse().expect(FAILED_TO_PARSE));
+                properties.sync(parse_value(&key, &value)?);
             }
             "used" => {
-                properties.used(value.parse().expect(FAILED_TO_PARSE));
+                properties.used(parse_value(&key, &value)?);
             }
             "usedbychildren" => {
-                properties.used_by_children(value.parse().expect(FAILED_TO_PARSE));
+                properties.used_by_children(parse_value(&key, &value)?);
             }
             "usedbydataset" => {
-                properties.used_by_dataset(value.parse().expect(FAILED_TO_PARSE));
+                properties.used_by_dataset(parse_value(&key, &value)?);
             }
             "usedbyrefreservation" => {
-                properties.used_by_ref_reservation(value.parse().expect(FAILED_TO_PARSE));
+                properties.used_by_ref_reservation(parse_value(&key, &value)?);
             }
             "usedbysnapshots" => {
-                properties.used_by_snapshots(value.parse().expect(FAILED_TO_PARSE));
+                properties.used_by_snapshots(parse_value(&key, &value)?);
             }
             "volblocksize" => {
-                properties.volume_block_size(value.parse().expect(FAILED_TO_PARSE));
+                properties.volume_block_size(parse_byte_size(&key, &value)?);
             }
             "volmode" => {
-                properties.volume_mode(Some(value.parse().expect(FAILED_TO_PARSE)));
+                properties.volume_mode(Some(parse_value(&key, &value)?));
+            }
+            "volsize" => {
+                properties.volume_size(parse_byte_size(&key, &value)?);
+            }
+            "written" => {
+                properties.written(parse_value(&key, &value)?);
+            }
+            "type" => { /* no-op */ }
+
+            _ => properties.insert_unknown_property(key, value),
+        };
+    }
+    properties
+        .build()
+        .map(Properties::Volume)
+        .map_err(|err| Error::UnknownSoFar(err.to_string()))
+}
+
+/// Longest argument built for a single `zfs destroy`. Linux refuses arguments over 128 KiB.
+const DESTROY_ARG_MAX_LEN: usize = 64 * 1024;
+
+/// `%` separates range ends in `zfs destroy`, so it can't be part of a name passed there.
+fn validate_destroyable_snapshot(snapshot: &Path) -> ValidationResult {
+    validators::validate_snapshot_name(snapshot)?;
+    match snapshot.snapshot_name().filter(|name| name.contains('%')) {
+        Some(name) => Err(ValidationError::InvalidCharacter {
+            dataset: snapshot.to_owned(),
+            component: name.to_owned(),
+            ch: '%',
+        }),
+        None => Ok(()),
+    }
+}
+
+/// Arguments for `zfs destroy`, one per invocation. `existing` is every snapshot of the affected
+/// datasets in `createtxg` order. Requested snapshots that are next to each other among snapshots
+/// of their dataset are turned into `first%last` ranges, the rest are listed with commas. Returns
+/// requested snapshots missing from `existing` as error.
+pub(crate) fn destroy_snapshots_args(
+    requested: &[PathBuf],
+    existing: &[PathBuf],
+) -> std::result::Result<Vec<OsString>, Vec<(PathBuf, i32)>> {
+    // Group by dataset, stable sort keeps `createtxg` order within each one.
+    let mut existing = existing.to_vec();
+    existing.sort_by_key(PathExt::dataset_of);
+    let position: HashMap<&PathBuf, usize> = existing
+        .iter()
+        .enumerate()
+        .map(|(idx, s)| (s, idx))
+        .collect();
+    let missing: Vec<(PathBuf, i32)> = requested
+        .iter()
+        .filter(|snapshot| !position.contains_key(snapshot))
+        .map(|snapshot| (snapshot.clone(), libc::ENOENT))
+        .collect();
+    if !missing.is_empty() {
+        return Err(missing);
+    }
+    let mut indexes: Vec<usize> = requested.iter().map(|s| position[s]).collect();
+    indexes.sort_unstable();
+    indexes.dedup();
+
+    let mut ret = Vec::new();
+    let mut current: Option<(PathBuf, String)> = None;
+    let mut idx = 0;
+    while idx < indexes.len() {
+        let first = &existing[indexes[idx]];
+        let dataset = first.dataset_of();
+        // Extend the run while next requested snapshot is the next one of the same dataset.
+        let mut last = idx;
+        while last + 1 < indexes.len()
+            && indexes[last + 1] == indexes[last] + 1
+            && existing[indexes[last + 1]].dataset_of() == dataset
+        {
+            last += 1;
+        }
+        let first_name = first.snapshot_name().unwrap_or_default();
+        let spec = if last > idx {
+            let last_name = existing[indexes[last]].snapshot_name().unwrap_or_default();
+            format!("{}%{}", first_name, last_name)
+        } else {
+            first_name.to_string()
+        };
+        idx = last + 1;
+
+        current = match current.take() {
+            Some((current_dataset, mut specs))
+                if current_dataset == dataset
+                    && current_dataset.as_os_str().len() + specs.len() + spec.len() + 2
+                        <= DESTROY_ARG_MAX_LEN =>
+            {
+                specs.push(',');
+                specs.push_str(&spec);
+                Some((current_dataset, specs))
+            }
+            finished => {
+                if let Some((dataset, specs)) = finished {
+                    ret.push(join_with_separator(dataset, '@', &specs).into_os_string());
+                }
+                Some((dataset, spec))
             }
-            "volsize" => {
-                properties.volume_size(value.parse().expect(FAILED_TO_PARSE));
+        };
+    }
+    if let Some((dataset, specs)) = current {
+        ret.push(join_with_separator(dataset, '@', &specs).into_os_string());
+    }
+    Ok(ret)
+}
+
+/// Parse output of `zfs list -Hp -o name,<numeric property>`, like `guid` or `written`.
+pub(crate) fn parse_name_value_list(stdout: &[u8]) -> Result<Vec<(PathBuf, u64)>> {
+    byte_fields(stdout)
+        .map(|fields| match fields.as_slice() {
+            [name, value] => str_from_bytes(value)?
+                .parse()
+                .map(|value| (path_from_bytes(name), value))
+                .map_err(|_| Error::UnknownSoFar(String::from_utf8_lossy(value).into())),
+            _ => Err(Error::UnknownSoFar(
+                String::from_utf8_lossy(&fields.join(&b'\t')).into(),
+            )),
+        })
+        .collect()
+}
+
+/// Datasets and their origins in output of `zfs list -Hp -o name,origin`.
+fn parse_origin_list(stdout: &[u8]) -> Result<Vec<(PathBuf, Option<PathBuf>)>> {
+    byte_fields(stdout)
+        .map(|fields| match fields.as_slice() {
+            [name, origin] => Ok((
+                path_from_bytes(name),
+                PropertyValue::from_value("origin", str_from_bytes(origin)?)?,
+            )),
+            _ => Err(Error::UnknownSoFar(
+                String::from_utf8_lossy(&fields.join(&b'\t')).into(),
+            )),
+        })
+        .collect()
+}
+
+/// Names of snapshots that have `defer_destroy` on in output of
+/// `zfs list -t snapshot -Hp -o name,defer_destroy`.
+pub(crate) fn parse_deferred_list(stdout: &[u8]) -> Result<Vec<PathBuf>> {
+    let mut ret = Vec::new();
+    for fields in byte_fields(stdout) {
+        match fields.as_slice() {
+            [name, value] => {
+                if bool::from_value("defer_destroy", str_from_bytes(value)?)? {
+                    ret.push(path_from_bytes(name));
+                }
+            }
+            _ => {
+                return Err(Error::UnknownSoFar(
+                    String::from_utf8_lossy(&fields.join(&b'\t')).into(),
+                ))
             }
-            "written" => {
-                properties.written(value.parse().expect(FAILED_TO_PARSE));
+        }
+    }
+    Ok(ret)
+}
+
+/// Parse output of `zfs get -Hp -o name,property,value`, keeping order of datasets.
+fn parse_property_values(stdout: &[u8]) -> Result<Vec<(PathBuf, HashMap<String, String>)>> {
+    let mut datasets: Vec<(PathBuf, HashMap<String, String>)> = Vec::new();
+    for fields in byte_fields(stdout) {
+        if fields.len() != 3 {
+            return Err(Error::UnknownSoFar(String::from_utf8_lossy(stdout).into()));
+        }
+        let dataset = path_from_bytes(fields[0]);
+        let property = String::from(str_from_bytes(fields[1])?);
+        let value = String::from(str_from_bytes(fields[2])?);
+        match datasets.last_mut() {
+            Some((last, values)) if *last == dataset => {
+                values.insert(property, value);
+            }
+            _ => {
+                let mut values = HashMap::new();
+                values.insert(property, value);
+                datasets.push((dataset, values));
             }
-            "type" => { /* no-op */ }
+        }
+    }
+    Ok(datasets)
+}
 
-            _ => properties.insert_unknown_property(key, value),
-        };
+/// Hidden child a resumable receive of an incremental stream keeps its state in.
+const RECV_CHILD: &str = "%recv";
+
+/// `receive_resume_token` and `used` of a dataset.
+type ResumeRow = (Option<String>, Option<u64>);
+
+/// Parse output of `zfs get -Hp -o name,property,value receive_resume_token,used` into token and
+/// `used` of every dataset, sorted by name.
+fn parse_resume_rows(stdout: &[u8]) -> Result<BTreeMap<PathBuf, ResumeRow>> {
+    let mut rows: BTreeMap<PathBuf, ResumeRow> = BTreeMap::new();
+    for fields in byte_fields(stdout) {
+        if fields.len() != 3 {
+            return Err(Error::UnknownSoFar(String::from_utf8_lossy(stdout).into()));
+        }
+        let row = rows.entry(path_from_bytes(fields[0])).or_default();
+        let property = str_from_bytes(fields[1])?;
+        let value = str_from_bytes(fields[2])?;
+        match property {
+            "receive_resume_token" => row.0 = PropertyValue::from_value(property, value)?,
+            "used" => row.1 = Some(PropertyValue::from_value(property, value)?),
+            _ => {}
+        }
+    }
+    Ok(rows)
+}
+
+/// Project IDs are file system attributes only Linux has.
+fn require_projects() -> Result<()> {
+    if cfg!(target_os = "linux") {
+        Ok(())
+    } else {
+        Err(Error::Unsupported(String::from("project")))
     }
-    Properties::Volume(properties.build().expect("Failed to build properties"))
 }
 
-pub(crate) fn parse_bookmark_lines(lines: &mut Lines, name: PathBuf) -> Properties {
+/// Parse output of `zfs project -d <path>`: project ID, inherit flag and path.
+fn parse_project(stdout: &str) -> Result<u32> {
+    stdout
+        .split_whitespace()
+        .next()
+        .and_then(|id| id.parse().ok())
+        .ok_or_else(|| Error::UnknownSoFar(String::from(stdout)))
+}
+
+/// Parse output of `zfs projectspace -Hp -o name,used,quota,objused,objquota`.
+fn parse_project_space(stdout: &[u8]) -> Result<Vec<ProjectSpace>> {
+    byte_fields(stdout)
+        .map(|fields| {
+            if fields.len() != 5 {
+                return Err(Error::UnknownSoFar(String::from_utf8_lossy(stdout).into()));
+            }
+            let value = |idx: usize| str_from_bytes(fields[idx]);
+            Ok(ProjectSpace::new(
+                PropertyValue::from_value("name", value(0)?)?,
+                PropertyValue::from_value("used", value(1)?)?,
+                PropertyValue::from_value("quota", value(2)?)?,
+                PropertyValue::from_value("objused", value(3)?)?,
+                PropertyValue::from_value("objquota", value(4)?)?,
+            ))
+        })
+        .collect()
+}
+
+/// Parse output of `zfs list -t bookmark -Hp -o name,guid,createtxg,creation`.
+pub(crate) fn parse_bookmark_list(stdout: &[u8]) -> Result<Vec<BookmarkProperties>> {
+    byte_fields(stdout)
+        .map(|fields| {
+            if fields.len() != 4 {
+                let line = fields.join(&b'\t');
+                return Err(Error::UnknownSoFar(String::from_utf8_lossy(&line).into()));
+            }
+            let mut properties = BookmarkProperties::builder(path_from_bytes(fields[0]));
+            properties.guid(parse_opt_num("guid", str_from_bytes(fields[1])?)?);
+            properties.create_txg(parse_opt_num("createtxg", str_from_bytes(fields[2])?)?);
+            properties.creation(parse_creation_into_timestamp(
+                "creation",
+                str_from_bytes(fields[3])?,
+            )?);
+            properties
+                .build()
+                .map_err(|err| Error::UnknownSoFar(err.to_string()))
+        })
+        .collect()
+}
+
+pub(crate) fn parse_bookmark_lines(lines: &mut Lines, name: PathBuf) -> Result<Properties> {
     let mut properties = BookmarkProperties::builder(name);
-    for (key, value) in lines.map(parse_prop_line) {
+    for line in lines.filter(|line| !line.is_empty()) {
+        let (key, value) = parse_prop_line(line)?;
         match key.as_ref() {
             "createtxg" => {
-                properties.create_txg(Some(value.parse().expect(FAILED_TO_PARSE)));
+                properties.create_txg(Some(parse_value(&key, &value)?));
             }
             "creation" => {
-                properties.creation(value.parse().expect(FAILED_TO_PARSE));
+                properties.creation(parse_value(&key, &value)?);
             }
             "guid" => {
-                properties.guid(Some(value.parse().expect(FAILED_TO_PARSE)));
+                properties.guid(Some(parse_value(&key, &value)?));
             }
             "type" => { /* no-op */ }
 
             _ => properties.insert_unknown_property(key, value),
         }
     }
-    Properties::Bookmark(properties.build().expect("Failed to build properties"))
+    properties
+        .build()
+        .map(Properties::Bookmark)
+        .map_err(|err| Error::UnknownSoFar(err.to_string()))
 }
 
-fn parse_unknown_lines(lines: &mut Lines) -> Properties {
-    let props = lines.map(parse_prop_line).collect();
-    Properties::Unknown(props)
+fn parse_unknown_lines(lines: &mut Lines) -> Result<Properties> {
+    lines
+        .filter(|line| !line.is_empty())
+        .map(parse_prop_line)
+        .collect::<Result<_>>()
+        .map(Properties::Unknown)
 }
 
 fn parse_bool(val: &str) -> bool {
     val == "yes" || val == "on"
 }
 
-fn parse_opt_num(val: &str) -> Option<u64> {
+fn parse_opt_num(key: &str, val: &str) -> Result<Option<u64>> {
     match val {
-        "-" | "none" | "" => None,
-        _ => Some(val.parse().expect(FAILED_TO_PARSE)),
+        "-" | "none" | "" => Ok(None),
+        _ => parse_value(key, val).map(Some),
     }
 }
 
@@ -658,6 +2309,7 @@ fn parse_mls_label(val: String) -> Option<String> {
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::utils::shim;
     use crate::zfs::{
         properties::{
             AclInheritMode, AclMode, BookmarkProperties, CaseSensitivity, Dedup, DnodeSize,
@@ -677,18 +2329,58 @@ mod test {
         right.insert("foo", "bar");
         assert_eq!(left, right);
     }
+    #[test]
+    fn filesystem_properties_missing_keys() {
+        let stdout = include_str!("fixtures/filesystem_properties_missing_keys");
+
+        let name = PathBuf::from("z/usr/home");
+        let result = parse_filesystem_lines(&mut stdout.lines(), name.clone()).unwrap();
+
+        match result {
+            Properties::Filesystem(properties) => {
+                assert_eq!(&name, properties.name());
+                assert_eq!(&None, properties.acl_mode());
+                assert_eq!(&None, properties.filesystem_count());
+                assert_eq!(&Compression::LZ4, properties.compression());
+            }
+            other => panic!("Expected filesystem properties, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn parse_get_output_of_captured_output() {
+        let stdout = include_str!("fixtures/filesystem_properties_freebsd");
+        let name = PathBuf::from("z/usr/home");
+        match Properties::parse_get_output(name.clone(), stdout).unwrap() {
+            Properties::Filesystem(properties) => {
+                assert_eq!(&name, properties.name());
+                assert_eq!(102_563_762_176, *properties.used());
+            }
+            other => panic!("Expected filesystem properties, got {:?}", other),
+        }
+
+        let broken = stdout.replace("\tused\t102563762176", "\tused\tlots");
+        assert_eq!(
+            Error::InvalidPropertyValue {
+                property: String::from("used"),
+                value: String::from("lots"),
+            },
+            Properties::parse_get_output(name.clone(), &broken).unwrap_err()
+        );
+        let truncated = "z/usr/home\ttype\tfilesystem\t-\nz/usr/home\tused\n";
+        assert_eq!(
+            Error::UnknownSoFar(String::from("z/usr/home\tused")),
+            Properties::parse_get_output(name.clone(), truncated).unwrap_err()
+        );
+        assert!(Properties::parse_get_output(name, "").is_err());
+    }
+
     #[test]
     fn filesystem_properties_freebsd() {
         let stdout = include_str!("fixtures/filesystem_properties_freebsd.sorted");
 
         let name = PathBuf::from("z/usr/home");
-        let result = parse_filesystem_lines(&mut stdout.lines(), name.clone());
-
-        // Goal to have zero unknown before 1.0
-        let unknown = [("sharenfs", "off"), ("sharesmb", "off")]
-            .iter()
-            .map(|(k, v)| (k.to_string(), v.to_string()))
-            .collect();
+        let result = parse_filesystem_lines(&mut stdout.lines(), name.clone()).unwrap();
 
         let expected = FilesystemProperties::builder(name)
             .acl_inherit(AclInheritMode::Restricted)
@@ -746,17 +2438,22 @@ mod test {
             .written(35_372_666_880)
             .xattr(false)
             .volume_mode(Some(VolumeMode::Default))
-            .unknown_properties(unknown)
+            .share_nfs(crate::zfs::ShareOptions::Off)
+            .share_smb(crate::zfs::ShareOptions::Off)
             .build()
             .unwrap();
 
         assert_eq!(Properties::Filesystem(expected), result);
+        if let Properties::Filesystem(props) = result {
+            assert_eq!(ByteSize::kib(128), props.record_size_bytes());
+            assert_eq!(None, props.quota_size());
+        }
     }
     #[test]
     fn volume_properties_freebsd() {
         let stdout = include_str!("fixtures/volume_properties_freebsd.sorted");
         let name = PathBuf::from("z/iohyve/rancher/disk0");
-        let result = parse_volume_lines(&mut stdout.lines(), name.clone());
+        let result = parse_volume_lines(&mut stdout.lines(), name.clone()).unwrap();
 
         // Goal to have zero unknown before 1.0
         let unknown = HashMap::new();
@@ -782,6 +2479,7 @@ mod test {
             .ref_reservation(70_871_154_688)
             .reservation(0)
             .secondary_cache(CacheMode::All)
+            .snap_dev(Some(SnapDev::Hidden))
             .snapshot_count(Some(0xFFFF_FFFF_FFFF_FFFF))
             .snapshot_limit(Some(0xFFFF_FFFF_FFFF_FFFF))
             .sync(SyncMode::Standard)
@@ -799,13 +2497,22 @@ mod test {
             .unwrap();
 
         assert_eq!(Properties::Volume(expected), result);
+        if let Properties::Volume(props) = result {
+            assert_eq!(ByteSize::gib(64), props.volume_size_bytes());
+            assert_eq!(ByteSize::kib(8), props.volume_block_size_bytes());
+            assert_eq!(
+                Some(ByteSize::bytes(70_871_154_688)),
+                props.ref_reservation_size()
+            );
+            assert_eq!(None, props.reservation_size());
+        }
     }
 
     #[test]
     fn snapshot_properties_freebsd() {
         let stdout = include_str!("fixtures/snapshot_properties_freebsd.sorted");
         let name = PathBuf::from("z/usr@backup-2019-11-24");
-        let result = parse_snapshot_lines(&mut stdout.lines(), name.clone());
+        let result = parse_snapshot_lines(&mut stdout.lines(), name.clone()).unwrap();
 
         // Goal to have zero unknown before 1.0
         let unknown = HashMap::new();
@@ -843,11 +2550,40 @@ mod test {
         assert_eq!(Properties::Snapshot(expected), result);
     }
 
+    #[test]
+    fn creation_in_other_locale_is_an_error() {
+        let name = PathBuf::from("z/usr@backup-2019-11-24");
+        let stdout = "z/usr@backup-2019-11-24\tcreation\tDi Mär 10 12:34 2020\t-\n";
+        let err = parse_snapshot_lines(&mut stdout.lines(), name.clone()).unwrap_err();
+        assert_eq!(
+            Error::InvalidPropertyValue {
+                property: String::from("creation"),
+                value: String::from("Di Mär 10 12:34 2020"),
+            },
+            err
+        );
+        assert_eq!(
+            "invalid value of creation: \"Di Mär 10 12:34 2020\"",
+            err.to_string()
+        );
+
+        let stdout = "z/usr@backup-2019-11-24\tcreation\tTue Mar 10 12:34 2020\t-\n";
+        match parse_snapshot_lines(&mut stdout.lines(), name.clone()).unwrap() {
+            Properties::Snapshot(properties) => {
+                let expected = NaiveDateTime::parse_from_str("2020-03-10 12:34", "%Y-%m-%d %H:%M")
+                    .unwrap()
+                    .and_utc();
+                assert_eq!(Some(expected), properties.creation_datetime());
+            }
+            other => panic!("Expected snapshot properties, got {:?}", other),
+        }
+    }
+
     #[test]
     fn bookmark_properties_freebsd() {
         let stdout = include_str!("fixtures/bookmark_properties_freebsd.sorted");
         let name = PathBuf::from("z/var/tmp#backup-2019-08-08");
-        let result = parse_bookmark_lines(&mut stdout.lines(), name.clone());
+        let result = parse_bookmark_lines(&mut stdout.lines(), name.clone()).unwrap();
 
         let expected = BookmarkProperties::builder(name)
             .create_txg(Some(2_967_653))
@@ -858,4 +2594,1177 @@ mod test {
 
         assert_eq!(Properties::Bookmark(expected), result);
     }
+
+    #[test]
+    fn bookmark_list_freebsd() {
+        let stdout = include_str!("fixtures/bookmark_list_freebsd");
+        let result = parse_bookmark_list(stdout.as_bytes()).unwrap();
+
+        let expected = vec![
+            BookmarkProperties::builder(PathBuf::from("z/var/tmp#backup-2019-08-08"))
+                .create_txg(Some(2_967_653))
+                .creation(1_565_321_370)
+                .guid(Some(12_396_914_211_240_477_066))
+                .build()
+                .unwrap(),
+            BookmarkProperties::builder(PathBuf::from("z/var/tmp/nested#backup-2019-08-09"))
+                .create_txg(Some(2_990_121))
+                .creation(1_565_407_770)
+                .guid(Some(3_021_442_718_302_519_237))
+                .build()
+                .unwrap(),
+        ];
+
+        assert_eq!(expected, result);
+        assert_eq!(Ok(Vec::new()), parse_bookmark_list(b""));
+    }
+
+    #[test]
+    fn bookmark_list_truncated() {
+        let stdout = b"z/var/tmp#backup-2019-08-08\t12396914211240477066\t2967653\n";
+        let err = parse_bookmark_list(stdout).unwrap_err();
+        assert_eq!(
+            Error::UnknownSoFar(String::from(
+                "z/var/tmp#backup-2019-08-08\t12396914211240477066\t2967653"
+            )),
+            err
+        );
+    }
+
+    #[test]
+    fn destroy_snapshots_collapses_ranges() {
+        let snaps = |dataset: &str, range: std::ops::Range<usize>| -> Vec<PathBuf> {
+            range
+                .map(|i| PathBuf::from(format!("{}@auto-{}", dataset, i)))
+                .collect()
+        };
+        let existing = snaps("tank/a", 0..100);
+
+        let args = destroy_snapshots_args(&existing, &existing).unwrap();
+        assert_eq!(vec![OsString::from("tank/a@auto-0%auto-99")], args);
+
+        // Keep every 25th snapshot, order of request doesn't matter.
+        let mut requested: Vec<PathBuf> = existing
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| i % 25 != 0)
+            .map(|(_, s)| s.clone())
+            .rev()
+            .collect();
+        requested.push(existing[50].clone());
+        let args = destroy_snapshots_args(&requested, &existing).unwrap();
+        assert_eq!(
+            vec![OsString::from(
+                "tank/a@auto-1%auto-24,auto-26%auto-74,auto-76%auto-99"
+            )],
+            args
+        );
+
+        // Interleaved listing of two datasets, lone snapshots are listed with commas.
+        let mut existing: Vec<PathBuf> = snaps("tank/a", 0..5)
+            .into_iter()
+            .zip(snaps("tank/b", 0..5))
+            .flat_map(|(a, b)| vec![a, b])
+            .collect();
+        existing.extend(snaps("tank/c", 0..100));
+        let mut requested = snaps("tank/a", 0..3);
+        requested.extend(vec![
+            PathBuf::from("tank/b@auto-1"),
+            PathBuf::from("tank/b@auto-3"),
+        ]);
+        requested.extend(snaps("tank/c", 10..100));
+        let args = destroy_snapshots_args(&requested, &existing).unwrap();
+        let expected: Vec<OsString> = vec![
+            "tank/a@auto-0%auto-2",
+            "tank/b@auto-1,auto-3",
+            "tank/c@auto-10%auto-99",
+        ]
+        .into_iter()
+        .map(OsString::from)
+        .collect();
+        assert_eq!(expected, args);
+
+        let missing = PathBuf::from("tank/a@nope");
+        assert_eq!(
+            Err(vec![(missing.clone(), libc::ENOENT)]),
+            destroy_snapshots_args(&[existing[0].clone(), missing], &existing)
+        );
+    }
+
+    #[test]
+    fn destroy_snapshots_splits_long_arguments() {
+        let name = "x".repeat(200);
+        let existing: Vec<PathBuf> = (0..1000)
+            .map(|i| PathBuf::from(format!("tank/a@{}-{}", name, i)))
+            .collect();
+        let requested: Vec<PathBuf> = existing.iter().step_by(2).cloned().collect();
+        let args = destroy_snapshots_args(&requested, &existing).unwrap();
+        assert!(args.len() > 1 && args.len() < 5);
+        assert!(args.iter().all(|arg| arg.len() <= DESTROY_ARG_MAX_LEN));
+        let destroyed: usize = args
+            .iter()
+            .map(|arg| arg.to_string_lossy().matches(',').count() + 1)
+            .sum();
+        assert_eq!(500, destroyed);
+    }
+
+    #[test]
+    fn receive_retries_excluding_invalid_properties() {
+        use std::{
+            fs,
+            io::{Seek, SeekFrom, Write},
+            os::unix::net::UnixStream,
+        };
+
+        let dir = tempfile::tempdir().unwrap();
+        let args = dir.path().join("args");
+        let shim = shim(
+            dir.path(),
+            "zfs",
+            &format!(
+                "bytes=$(wc -c | tr -d ' ')\necho \"$* $bytes\" >> {}\nfor p in jailed \
+             aclmode; do\n  case \" $* \" in\n    *\" -x $p \"*) ;;\n    *) echo \"cannot \
+             receive: invalid property '$p'\" >&2; exit 1;;\n  esac\ndone\n",
+                args.display()
+            ),
+        );
+        let mut zfs = ZfsOpen3::new();
+        zfs.cmd_name = shim.into_os_string();
+
+        let mut stream = tempfile::tempfile().unwrap();
+        stream.write_all(b"stream").unwrap();
+        stream.seek(SeekFrom::Start(0)).unwrap();
+        let opts = ReceiveOptions::builder()
+            .retry_excluding_invalid(true)
+            .build()
+            .unwrap();
+        zfs.receive("tank/b", stream.try_clone().unwrap(), opts.clone())
+            .unwrap();
+        assert_eq!(
+            "receive tank/b 6\nreceive -x jailed tank/b 6\nreceive -x jailed -x aclmode tank/b 6\n",
+            fs::read_to_string(&args).unwrap()
+        );
+
+        // Without retries.
+        fs::remove_file(&args).unwrap();
+        stream.seek(SeekFrom::Start(0)).unwrap();
+        let err = zfs
+            .receive("tank/b", stream, ReceiveOptions::default())
+            .unwrap_err();
+        assert_eq!(Error::InvalidReceiveProperty(String::from("jailed")), err);
+        assert_eq!("receive tank/b 6\n", fs::read_to_string(&args).unwrap());
+
+        // Stream from a socket can't be rewound.
+        fs::remove_file(&args).unwrap();
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        sender.write_all(b"stream").unwrap();
+        drop(sender);
+        let err = zfs.receive("tank/b", receiver, opts).unwrap_err();
+        assert_eq!(Error::InvalidReceiveProperty(String::from("jailed")), err);
+        assert_eq!("receive tank/b 6\n", fs::read_to_string(&args).unwrap());
+    }
+
+    #[test]
+    fn set_volume_mode_reports_change() {
+        use std::fs;
+
+        let dir = tempfile::tempdir().unwrap();
+        let state = dir.path().join("volmode");
+        fs::write(&state, "default").unwrap();
+        let shim = shim(
+            dir.path(),
+            "zfs",
+            &format!(
+                "case \"$1\" in
+  get) printf 'tank/vol\\ttype\\tvolume\\t-\\n\
+             tank/vol\\tvolmode\\t%s\\tlocal\\n' \"$(cat {state})\";;
+  set) printf %s \
+             \"${{2#volmode=}}\" > {state};;
+esac
+",
+                state = state.display()
+            ),
+        );
+        let mut zfs = ZfsOpen3::new();
+        zfs.cmd_name = shim.into_os_string();
+
+        assert!(zfs.set_volume_mode("tank/vol", VolumeMode::Dev).unwrap());
+        assert_eq!("dev", fs::read_to_string(&state).unwrap());
+        assert!(!zfs.set_volume_mode("tank/vol", VolumeMode::Dev).unwrap());
+    }
+
+    #[test]
+    fn share_through_shim() {
+        use std::fs;
+
+        let dir = tempfile::tempdir().unwrap();
+        let args = dir.path().join("args");
+        let shim = shim(
+            dir.path(),
+            "zfs",
+            &format!(
+                "echo \"$@\" >> {args}
+case \"$*\" in
+  'unshare tank/idle') echo \"cannot unshare 'tank/idle': not currently shared\" >&2; exit 1;;
+esac
+",
+                args = args.display()
+            ),
+        );
+        let mut zfs = ZfsOpen3::new();
+        zfs.cmd_name = shim.into_os_string();
+
+        let options = ShareOptions::Options(String::from("-maproot=root -network 10.0.0.0/8"));
+        zfs.set_share_nfs("tank/export", options).unwrap();
+        zfs.set_share_smb("tank/export", ShareOptions::Off).unwrap();
+        zfs.share("tank/export").unwrap();
+        zfs.share_all().unwrap();
+        zfs.unshare("tank/export").unwrap();
+        assert_eq!(
+            Err(Error::ShareFailed {
+                dataset: PathBuf::from("tank/idle"),
+                reason: String::from("not currently shared"),
+            }),
+            zfs.unshare("tank/idle")
+        );
+        assert_eq!(
+            "set sharenfs=-maproot=root -network 10.0.0.0/8 tank/export\n\
+             set sharesmb=off tank/export\n\
+             share tank/export\n\
+             share -a\n\
+             unshare tank/export\n\
+             unshare tank/idle\n",
+            fs::read_to_string(&args).unwrap()
+        );
+    }
+
+    #[test]
+    fn share_options_are_carried_as_is() {
+        let stdout = "z/export\ttype\tfilesystem\t-\n\
+                      z/export\tsharenfs\trw=@10.0.0.0/8,no_root_squash\tlocal\n\
+                      z/export\tsharesmb\ton\tlocal\n";
+        let name = PathBuf::from("z/export");
+        match Properties::parse_get_output(name, stdout).unwrap() {
+            Properties::Filesystem(properties) => {
+                assert_eq!(
+                    &ShareOptions::Options(String::from("rw=@10.0.0.0/8,no_root_squash")),
+                    properties.share_nfs()
+                );
+                assert_eq!(&ShareOptions::On, properties.share_smb());
+                assert!(properties.unknown_properties().is_empty());
+            }
+            other => panic!("Expected filesystem properties, got {:?}", other),
+        }
+        assert_eq!("off", ShareOptions::Off.to_string());
+    }
+
+    #[test]
+    fn upgrade_listing() {
+        let stdout = include_str!("fixtures/upgrade_freebsd");
+        let expected = vec![
+            (PathBuf::from("tank/legacy"), 4),
+            (PathBuf::from("tank/legacy/home"), 4),
+            (PathBuf::from("tank2/jails"), 3),
+        ];
+        assert_eq!(expected, parse_upgrade_listing(stdout.as_bytes()));
+
+        let stdout = include_str!("fixtures/upgrade_current");
+        assert!(parse_upgrade_listing(stdout.as_bytes()).is_empty());
+    }
+
+    #[test]
+    fn command_timeout_through_shim() {
+        let dir = tempfile::tempdir().unwrap();
+        let shim = shim(dir.path(), "zfs", "exec sleep 30\n");
+        let mut zfs = ZfsOpen3::new();
+        zfs.cmd_name = shim.clone().into_os_string();
+        zfs.set_command_timeout(Some(Duration::from_millis(200)));
+
+        assert_eq!(
+            Err(Error::TimedOut {
+                command: format!("{} list -t all -H -o name tank/data", shim.display()),
+                after: Duration::from_millis(200),
+            }),
+            zfs.exists("tank/data")
+        );
+    }
+
+    #[test]
+    fn upgrade_through_shim() {
+        use std::fs;
+
+        let dir = tempfile::tempdir().unwrap();
+        let args = dir.path().join("args");
+        let shim = shim(
+            dir.path(),
+            "zfs",
+            &format!(
+                "if [ \"$#\" = 1 ]; then
+  cat {listing}
+  exit 0
+fi
+echo \"$@\" >> {args}
+case \"$*\" in
+  *tank/current) echo \"tank/current: can not be upgraded; the filesystem version (5) is \
+             already at this version\" >&2; exit 1;;
+  *tank/missing) echo \"cannot open 'tank/missing': dataset does not exist\" >&2; exit 1;;
+esac
+",
+                listing = concat!(
+                    env!("CARGO_MANIFEST_DIR"),
+                    "/src/zfs/fixtures/upgrade_freebsd"
+                ),
+                args = args.display()
+            ),
+        );
+        let mut zfs = ZfsOpen3::new();
+        zfs.cmd_name = shim.into_os_string();
+
+        let expected = vec![
+            (PathBuf::from("tank/legacy"), 4),
+            (PathBuf::from("tank/legacy/home"), 4),
+        ];
+        assert_eq!(Ok(expected), zfs.upgradeable_datasets("tank"));
+
+        assert_eq!(Ok(()), zfs.upgrade("tank/legacy", true, Some(5)));
+        assert_eq!(Ok(()), zfs.upgrade("tank/current", false, None));
+        assert_eq!(
+            Err(Error::DatasetNotFound(PathBuf::from("tank/missing"))),
+            zfs.upgrade("tank/missing", false, None)
+        );
+        assert_eq!(
+            "upgrade -r -V 5 tank/legacy\nupgrade tank/current\nupgrade tank/missing\n",
+            fs::read_to_string(&args).unwrap()
+        );
+    }
+
+    #[test]
+    fn get_property_through_shim() {
+        let dir = tempfile::tempdir().unwrap();
+        let shim = shim(
+            dir.path(),
+            "zfs",
+            "case \"$*\" in
+  \"get -Hp -o value guid tank/data@snap\") echo 12396914211240477066;;
+  \"get -Hp -o value origin tank/data\") echo -;;
+  \"get -Hp -o value origin tank/clone\") echo tank/data@snap;;
+  \"get -Hp -o value mounted tank/data\") echo yes;;
+  \"get -Hp -o value comment tank/data\") echo \"two words\";;
+  *tank/missing) echo \"cannot open 'tank/missing': dataset does not exist\" >&2; exit 1;;
+  *) exit 2;;
+esac
+",
+        );
+        let mut zfs = ZfsOpen3::new();
+        zfs.cmd_name = shim.into_os_string();
+
+        assert_eq!(
+            Ok(12_396_914_211_240_477_066u64),
+            zfs.get_property("tank/data@snap", "guid")
+        );
+        assert_eq!(Ok(None::<PathBuf>), zfs.get_property("tank/data", "origin"));
+        assert_eq!(
+            Ok(Some(PathBuf::from("tank/data@snap"))),
+            zfs.get_property("tank/clone", "origin")
+        );
+        assert_eq!(Ok(true), zfs.get_property("tank/data", "mounted"));
+        assert_eq!(
+            Ok(String::from("two words")),
+            zfs.get_property("tank/data", "comment")
+        );
+        assert_eq!(
+            Err(Error::InvalidPropertyValue {
+                property: String::from("mounted"),
+                value: String::from("yes"),
+            }),
+            zfs.get_property::<_, u64>("tank/data", "mounted")
+        );
+        let err = zfs
+            .get_property::<_, u64>("tank/missing", "guid")
+            .unwrap_err();
+        assert_eq!(crate::zfs::ErrorKind::DatasetNotFound, err.kind());
+    }
+
+    #[test]
+    fn deferred_list() {
+        let stdout = b"tank/a@1\toff\ntank/a@2\ton\ntank/a/b@3\ton\n";
+        assert_eq!(
+            Ok(vec![PathBuf::from("tank/a@2"), PathBuf::from("tank/a/b@3")]),
+            parse_deferred_list(stdout)
+        );
+        assert_eq!(Ok(Vec::new()), parse_deferred_list(b""));
+        assert_eq!(
+            crate::zfs::ErrorKind::InvalidPropertyValue,
+            parse_deferred_list(b"tank/a@1\tmaybe\n")
+                .unwrap_err()
+                .kind()
+        );
+    }
+
+    #[test]
+    fn list_not_found_wordings() {
+        let requested = Path::new("tank/nonexistent");
+        let stderrs: [&[u8]; 3] = [
+            include_bytes!("fixtures/list_not_found_freebsd"),
+            include_bytes!("fixtures/list_not_found_zol_0_7"),
+            include_bytes!("fixtures/list_not_found_zol_0_8"),
+        ];
+        for stderr in stderrs.iter() {
+            assert_eq!(
+                Error::DatasetNotFound(requested.to_path_buf()),
+                list_error(requested, stderr)
+            );
+        }
+        assert_eq!(
+            Error::DatasetNotFound(PathBuf::from("tank/nonexistent")),
+            Error::from_stderr(include_bytes!("fixtures/list_not_found_zol_0_7"))
+        );
+        assert_eq!(
+            Error::UnknownSoFar(String::from("cannot list: permission denied")),
+            list_error(requested, b"cannot list: permission denied")
+        );
+    }
+
+    #[test]
+    fn roots_of_names() {
+        let names: Vec<PathBuf> = vec![
+            "pool/data/a",
+            "pool/database@snap",
+            "pool/data/b#mark",
+            "other/x/y",
+            "other/x/y@z",
+            "",
+        ]
+        .into_iter()
+        .map(PathBuf::from)
+        .collect();
+        assert_eq!(
+            vec![PathBuf::from("other/x/y"), PathBuf::from("pool")],
+            common_roots(&names)
+        );
+        assert_eq!(
+            vec![PathBuf::from("pool/data")],
+            common_roots(&[PathBuf::from("pool/data/a"), PathBuf::from("pool/data@s")])
+        );
+        assert!(common_roots(&[PathBuf::new()]).is_empty());
+    }
+
+    #[test]
+    fn exists_all_through_shim() {
+        use std::fs;
+
+        let dir = tempfile::tempdir().unwrap();
+        let args = dir.path().join("args");
+        let shim = shim(
+            dir.path(),
+            "zfs",
+            &format!(
+                "echo \"$@\" > {args}
+printf 'tank\\ntank/data\\ntank/data@a\\n'
+case \"$*\" in
+  *gone*) cat {missing} >&2; exit 1;;
+  *denied*) echo 'cannot list: permission denied' >&2; exit 1;;
+esac
+",
+                args = args.display(),
+                missing = concat!(
+                    env!("CARGO_MANIFEST_DIR"),
+                    "/src/zfs/fixtures/list_not_found_zol_0_8"
+                )
+            ),
+        );
+        let mut zfs = ZfsOpen3::new();
+        zfs.cmd_name = shim.into_os_string();
+
+        let answers = zfs
+            .exists_all(vec![
+                "tank/data",
+                "tank/database",
+                "tank/data@a",
+                "tank/data",
+                "gone/x",
+            ])
+            .unwrap();
+        let expected = vec![
+            (PathBuf::from("tank/data"), true),
+            (PathBuf::from("tank/database"), false),
+            (PathBuf::from("tank/data@a"), true),
+            (PathBuf::from("tank/data"), true),
+            (PathBuf::from("gone/x"), false),
+        ];
+        assert_eq!(expected, answers);
+        assert_eq!(
+            "list -t all -H -o name -r gone/x tank\n",
+            fs::read_to_string(&args).unwrap()
+        );
+
+        assert_eq!(
+            Err(Error::UnknownSoFar(String::from(
+                "cannot list: permission denied\n"
+            ))),
+            zfs.exists_all(vec!["denied/x"])
+        );
+        assert_eq!(Ok(Vec::new()), zfs.exists_all(Vec::<PathBuf>::new()));
+    }
+
+    #[test]
+    fn list_snapshots_of_through_shim() {
+        use std::fs;
+
+        let dir = tempfile::tempdir().unwrap();
+        let args = dir.path().join("args");
+        let shim = shim(
+            dir.path(),
+            "zfs",
+            &format!(
+                "echo \"$@\" >> {args}
+case \"$*\" in
+  *tank/data) printf 'tank/data@a\\ntank/data@b\\n';;
+  *) cat {missing} >&2; exit 1;;
+esac
+",
+                args = args.display(),
+                missing = concat!(
+                    env!("CARGO_MANIFEST_DIR"),
+                    "/src/zfs/fixtures/list_not_found_zol_0_7"
+                )
+            ),
+        );
+        let mut zfs = ZfsOpen3::new();
+        zfs.cmd_name = shim.into_os_string();
+
+        assert_eq!(
+            Ok(vec![
+                PathBuf::from("tank/data@a"),
+                PathBuf::from("tank/data@b")
+            ]),
+            zfs.list_snapshots_of("tank/data")
+        );
+        assert_eq!(
+            Err(Error::DatasetNotFound(PathBuf::from("tank/missing"))),
+            zfs.list_snapshots_of("tank/missing")
+        );
+        assert_eq!(
+            Err(Error::DatasetNotFound(PathBuf::from("tank/missing"))),
+            zfs.list_snapshots("tank/missing")
+        );
+        assert_eq!(
+            "list -t snapshot -Hp -o name -s createtxg -d 1 tank/data\n\
+             list -t snapshot -Hp -o name -s createtxg -d 1 tank/missing\n\
+             list -t snapshot -o name -Hpr tank/missing\n",
+            fs::read_to_string(&args).unwrap()
+        );
+    }
+
+    #[test]
+    fn partial_receives_through_shim() {
+        use std::fs;
+
+        let dir = tempfile::tempdir().unwrap();
+        let args = dir.path().join("args");
+        // `backup/inc` lists its `%recv` child, `backup/hidden` doesn't, `backup/full` is a full
+        // receive and has no child at all.
+        let shim = shim(dir.path(), "zfs", &format!(
+"echo \"$@\" >> {args}
+case \"$*\" in
+  \"get -Hpr\"*backup)
+    printf 'backup\\treceive_resume_token\\t-\\nbackup\\tused\\t900\\n'
+    printf 'backup/inc\\treceive_resume_token\\t1-inc-c0\\nbackup/inc\\tused\\t500\\n'
+    printf 'backup/inc/%%recv\\treceive_resume_token\\t1-inc-c0\\nbackup/inc/%%recv\\tused\\t40\\n'
+    printf 'backup/hidden\\treceive_resume_token\\t1-hid-c0\\nbackup/hidden\\tused\\t300\\n'
+    printf 'backup/full\\treceive_resume_token\\t1-full-c0\\nbackup/full\\tused\\t70\\n';;
+  *backup/hidden/%recv) echo 20;;
+  *backup/full/%recv) echo \"cannot open 'backup/full/%recv': dataset does not exist\" >&2; exit 1;;
+  \"receive -A backup/inc\") ;;
+  \"receive -A backup\") echo \"'backup' does not have any resumable receive state to abort\" >&2; exit 1;;
+  *) cat {missing} >&2; exit 1;;
+esac
+",
+args = args.display(),
+            missing = concat!(
+                env!("CARGO_MANIFEST_DIR"),
+                "/src/zfs/fixtures/list_not_found_zol_0_7"
+            )
+));
+        let mut zfs = ZfsOpen3::new();
+        zfs.cmd_name = shim.into_os_string();
+
+        let expected = vec![
+            PartialReceive::new("backup/full".into(), "1-full-c0".into(), Some(70)),
+            PartialReceive::new("backup/hidden".into(), "1-hid-c0".into(), Some(20)),
+            PartialReceive::new("backup/inc".into(), "1-inc-c0".into(), Some(40)),
+        ];
+        assert_eq!(Ok(expected), zfs.partial_receives("backup"));
+        assert_eq!(
+            Err(Error::DatasetNotFound(PathBuf::from("tank/missing"))),
+            zfs.partial_receives("tank/missing")
+        );
+
+        assert_eq!(Ok(()), zfs.abort_partial_receive("backup/inc"));
+        assert_eq!(
+            Err(Error::NoResumableReceive(PathBuf::from("backup"))),
+            zfs.abort_partial_receive("backup")
+        );
+
+        let called = fs::read_to_string(&args).unwrap();
+        let expected_get = "get -Hpr -t filesystem,volume -o name,property,value \
+                            receive_resume_token,used backup\n";
+        assert!(called.starts_with(expected_get));
+        assert!(called.contains("get -Hp -o value used backup/hidden/%recv\n"));
+        assert!(!called.contains("backup/inc/%recv"));
+    }
+
+    #[test]
+    fn first_snapshot_through_shim() {
+        use std::fs;
+
+        let dir = tempfile::tempdir().unwrap();
+        let args = dir.path().join("args");
+        let shim = shim(
+            dir.path(),
+            "zfs",
+            &format!(
+                "echo \"$@\" >> {args}
+case \"$*\" in
+  *-S*tank/data) printf 'tank/data@b\\ntank/data@a\\n';;
+  *-s*tank/data) printf 'tank/data@a\\ntank/data@b\\n';;
+  *tank/one) printf 'tank/one@only\\n';;
+  *tank/empty) ;;
+  *) cat {missing} >&2; exit 1;;
+esac
+",
+                args = args.display(),
+                missing = concat!(
+                    env!("CARGO_MANIFEST_DIR"),
+                    "/src/zfs/fixtures/list_not_found_zol_0_7"
+                )
+            ),
+        );
+        let mut zfs = ZfsOpen3::new();
+        zfs.cmd_name = shim.into_os_string();
+
+        assert_eq!(
+            Ok(Some(PathBuf::from("tank/data@b"))),
+            zfs.latest_snapshot("tank/data")
+        );
+        assert_eq!(
+            Ok(Some(PathBuf::from("tank/data@a"))),
+            zfs.oldest_snapshot("tank/data")
+        );
+        assert_eq!(
+            Ok(Some(PathBuf::from("tank/one@only"))),
+            zfs.latest_snapshot("tank/one")
+        );
+        assert_eq!(Ok(None), zfs.oldest_snapshot("tank/empty"));
+        assert_eq!(
+            Err(Error::DatasetNotFound(PathBuf::from("tank/missing"))),
+            zfs.latest_snapshot("tank/missing")
+        );
+        assert_eq!(
+            "list -t snapshot -Hp -o name -S createtxg -d 1 tank/data\n\
+             list -t snapshot -Hp -o name -s createtxg -d 1 tank/data\n\
+             list -t snapshot -Hp -o name -S createtxg -d 1 tank/one\n\
+             list -t snapshot -Hp -o name -s createtxg -d 1 tank/empty\n\
+             list -t snapshot -Hp -o name -S createtxg -d 1 tank/missing\n",
+            fs::read_to_string(&args).unwrap()
+        );
+    }
+
+    #[test]
+    fn rename_usage_flags() {
+        let old = include_bytes!("fixtures/rename_usage_zol_0_8");
+        let new = include_bytes!("fixtures/rename_usage_openzfs_2");
+        assert!(!usage_has_flag(old, "rename", 'u'));
+        assert!(usage_has_flag(new, "rename", 'u'));
+        assert!(usage_has_flag(old, "rename", 'p'));
+        assert!(!usage_has_flag(new, "set", 'u'));
+        assert!(usage_has_flag(b"\trename [-fu] <a> <b>\n", "rename", 'u'));
+        assert_eq!(
+            Error::DatasetExists(PathBuf::from("tank/ci@Monday")),
+            Error::from_stderr(include_bytes!("fixtures/rename_exists_case_insensitive"))
+        );
+    }
+
+    #[test]
+    fn rename_no_remount_through_shim() {
+        use std::fs;
+
+        let dir = tempfile::tempdir().unwrap();
+        let fixtures = concat!(env!("CARGO_MANIFEST_DIR"), "/src/zfs/fixtures/");
+        let shim_with = |usage: &str| {
+            let shim = shim(
+                dir.path(),
+                usage,
+                &format!(
+                    "echo \"$@\" >> {args}
+case \"$*\" in
+  rename) cat {usage} >&2; exit 2;;
+  list*) printf 'filesystem\\t%s\\n' \"$7\";;
+  *tank/ci@Monday) cat {exists} >&2; exit 1;;
+esac
+",
+                    args = dir.path().join("args").display(),
+                    usage = format!("{}{}", fixtures, usage),
+                    exists = format!("{}rename_exists_case_insensitive", fixtures)
+                ),
+            );
+            let mut zfs = ZfsOpen3::new();
+            zfs.cmd_name = shim.into_os_string();
+            zfs
+        };
+        let no_remount = RenameOptions::builder().no_remount(true).build().unwrap();
+
+        let zfs = shim_with("rename_usage_openzfs_2");
+        assert!(zfs.rename("tank/a", "tank/b", no_remount.clone()).is_ok());
+        assert!(zfs.rename("tank/b", "tank/c", no_remount.clone()).is_ok());
+        assert_eq!(
+            Err(Error::DatasetExists(PathBuf::from("tank/ci@Monday"))),
+            zfs.rename("tank/ci@monday", "tank/ci@Monday", RenameOptions::default())
+                .map(|_| ())
+        );
+
+        let zfs = shim_with("rename_usage_zol_0_8");
+        assert_eq!(
+            Err(Error::Unsupported(String::from("rename -u"))),
+            zfs.rename("tank/c", "tank/d", no_remount).map(|_| ())
+        );
+        assert!(zfs
+            .rename("tank/c", "tank/d", RenameOptions::default())
+            .is_ok());
+
+        assert_eq!(
+            "list -t all -H -o name tank\n\
+             rename\n\
+             list -t all -o type,name -Hpr tank/a\n\
+             rename -u tank/a tank/b\n\
+             list -t all -H -o name tank\n\
+             list -t all -o type,name -Hpr tank/b\n\
+             rename -u tank/b tank/c\n\
+             list -t all -o type,name -Hpr tank/ci@monday\n\
+             rename tank/ci@monday tank/ci@Monday\n\
+             list -t all -H -o name tank\n\
+             rename\n\
+             list -t all -H -o name tank\n\
+             list -t all -o type,name -Hpr tank/c\n\
+             rename tank/c tank/d\n",
+            fs::read_to_string(dir.path().join("args")).unwrap()
+        );
+    }
+
+    #[test]
+    fn destroy_snapshots_through_shim() {
+        use std::fs;
+
+        let dir = tempfile::tempdir().unwrap();
+        let args = dir.path().join("args");
+        let shim = shim(
+            dir.path(),
+            "zfs",
+            &format!(
+                "if [ \"$1\" = list ]; then\n  for i in $(seq 0 99); do echo \"tank/a@s$i\"; \
+             done\nelse\n  echo \"$@\" >> {}\nfi\n",
+                args.display()
+            ),
+        );
+
+        let mut zfs = ZfsOpen3::new();
+        zfs.cmd_name = shim.into_os_string();
+        let requested: Vec<PathBuf> = (0..100)
+            .filter(|i| i % 10 != 5)
+            .map(|i| PathBuf::from(format!("tank/a@s{}", i)))
+            .collect();
+        zfs.destroy_snapshots(&requested, DestroyTiming::Defer)
+            .unwrap();
+        assert_eq!(
+            "destroy -d tank/a@s0%s4,s6%s14,s16%s24,s26%s34,s36%s44,s46%s54,s56%s64,s66%s74,s76%s84,\
+             s86%s94,s96%s99\n",
+            fs::read_to_string(&args).unwrap()
+        );
+
+        let invalid = vec![PathBuf::from("tank/a@s%1"), PathBuf::from("tank/a@s,1")];
+        let err = zfs
+            .destroy_snapshots(&invalid, DestroyTiming::RightNow)
+            .unwrap_err();
+        if let Error::ValidationErrors(errors) = err {
+            assert_eq!(2, errors.len());
+        } else {
+            panic!("Expected validation errors");
+        }
+    }
+
+    #[test]
+    fn destroy_snapshot_recursive_through_shim() {
+        use std::fs;
+
+        let dir = tempfile::tempdir().unwrap();
+        let args = dir.path().join("args");
+        let shim = shim(
+            dir.path(),
+            "zfs",
+            &format!("echo \"$@\" >> {}\n", args.display()),
+        );
+
+        let mut zfs = ZfsOpen3::new();
+        zfs.cmd_name = shim.into_os_string();
+        zfs.destroy_snapshot_recursive("tank/a", "daily").unwrap();
+        let err = zfs
+            .destroy_snapshot_recursive("tank/a", "tank/a@daily")
+            .unwrap_err();
+        assert_eq!(crate::zfs::ErrorKind::ValidationErrors, err.kind());
+        assert_eq!(
+            "destroy -r tank/a@daily\n",
+            fs::read_to_string(&args).unwrap()
+        );
+    }
+
+    #[test]
+    fn snapshot_guids() {
+        let stdout = "z/var/tmp@a\t12396914211240477066\nz/var/tmp@b\t3021442718302519237\n";
+        let expected = vec![
+            (PathBuf::from("z/var/tmp@a"), 12_396_914_211_240_477_066),
+            (PathBuf::from("z/var/tmp@b"), 3_021_442_718_302_519_237),
+        ];
+        assert_eq!(Ok(expected), parse_name_value_list(stdout.as_bytes()));
+        assert_eq!(Ok(Vec::new()), parse_name_value_list(b""));
+        assert!(parse_name_value_list(b"z/var/tmp@a\t-\n").is_err());
+    }
+
+    #[test]
+    fn origins() {
+        let stdout = "tank\t-\ntank/a\t-\ntank/b\ttank/a@1\ntank/c\ttank/b@2\n";
+        let expected = vec![
+            (PathBuf::from("tank"), None),
+            (PathBuf::from("tank/a"), None),
+            (PathBuf::from("tank/b"), Some(PathBuf::from("tank/a@1"))),
+            (PathBuf::from("tank/c"), Some(PathBuf::from("tank/b@2"))),
+        ];
+        assert_eq!(Ok(expected), parse_origin_list(stdout.as_bytes()));
+        assert!(parse_origin_list(b"tank/b\n").is_err());
+    }
+
+    #[test]
+    fn written_since_property() {
+        let stdout = "z/usr\twritten@backup-2019-11-24\t1048576\t-\n";
+        assert_eq!(
+            Ok(1_048_576),
+            parse_written_since(stdout, "written@backup-2019-11-24")
+        );
+        assert!(parse_written_since(stdout, "written@other").is_err());
+        assert!(parse_written_since("", "written@backup-2019-11-24").is_err());
+
+        // Key with `@` among regular properties ends up as unknown one and doesn't clobber
+        // `written`.
+        let stdout = format!(
+            "{}z/usr@backup-2019-11-24\twritten@backup-2019-11-01\t4096\t-\n",
+            include_str!("fixtures/snapshot_properties_freebsd.sorted")
+        );
+        let name = PathBuf::from("z/usr@backup-2019-11-24");
+        let result = parse_snapshot_lines(&mut stdout.lines(), name.clone()).unwrap();
+        let clean = parse_snapshot_lines(
+            &mut include_str!("fixtures/snapshot_properties_freebsd.sorted").lines(),
+            name,
+        )
+        .unwrap();
+        if let (Properties::Snapshot(result), Properties::Snapshot(clean)) = (result, clean) {
+            assert_eq!(clean.written(), result.written());
+            assert_eq!(
+                Some(&String::from("4096")),
+                result.unknown_properties().get("written@backup-2019-11-01")
+            );
+        } else {
+            panic!("Parsed not a snapshot");
+        }
+    }
+
+    #[test]
+    fn project_parsers() {
+        assert_eq!(Ok(42), parse_project("    42 P /mnt/tank/projects/a\n"));
+        assert_eq!(Ok(0), parse_project("     0 - /mnt/tank\n"));
+        assert!(parse_project("").is_err());
+
+        let stdout = b"0\t1536\tnone\t3\tnone\n42\t1048576\t10485760\t12\t1000\n";
+        assert_eq!(
+            Ok(vec![
+                ProjectSpace::new(0, 1536, None, Some(3), None),
+                ProjectSpace::new(42, 1_048_576, Some(10_485_760), Some(12), Some(1000)),
+            ]),
+            parse_project_space(stdout)
+        );
+        assert!(parse_project_space(b"42\t1048576\n").is_err());
+        assert!(parse_project_space(b"web\t1\tnone\t1\tnone\n").is_err());
+    }
+
+    #[test]
+    fn property_values_parser() {
+        let stdout = b"tank\tquota\t0\ntank\tmountpoint\t/tank\ntank/vol\tquota\t-\ntank/vol\tmountpoint\t-\ntank/a\tquota\t1073741824\ntank/a\tmountpoint\t/srv/a b\n";
+        let datasets = parse_property_values(stdout).unwrap();
+        let names: Vec<&Path> = datasets.iter().map(|(name, _)| name.as_path()).collect();
+        assert_eq!(
+            vec![
+                Path::new("tank"),
+                Path::new("tank/vol"),
+                Path::new("tank/a")
+            ],
+            names
+        );
+        assert_eq!("1073741824", datasets[2].1["quota"]);
+        assert_eq!("/srv/a b", datasets[2].1["mountpoint"]);
+        assert_eq!("-", datasets[1].1["mountpoint"]);
+        assert!(parse_property_values(b"tank\tquota\n").is_err());
+    }
+
+    #[test]
+    fn project_quotas_of_unknown_properties() {
+        let stdout = format!(
+            "{}z/usr/home\tprojectquota@42\t10485760\tlocal\nz/usr/home\tprojectused@42\t4096\t-\nz/usr/home\tprojectquota@7\tnone\tlocal\nz/usr/home\tprojectquota@web\t1\tlocal\n",
+            include_str!("fixtures/filesystem_properties_freebsd.sorted")
+        );
+        let name = PathBuf::from("z/usr/home");
+        match parse_filesystem_lines(&mut stdout.lines(), name).unwrap() {
+            Properties::Filesystem(props) => {
+                let quotas: Vec<(u32, u64)> = props.project_quotas().into_iter().collect();
+                assert_eq!(vec![(42, 10_485_760)], quotas);
+                let used: Vec<(u32, u64)> = props.project_used().into_iter().collect();
+                assert_eq!(vec![(42, 4096)], used);
+            }
+            _ => panic!("Parsed not a filesystem"),
+        }
+    }
+
+    #[test]
+    fn non_utf8_names_are_preserved() {
+        use std::os::unix::ffi::OsStrExt;
+
+        let raw = |bytes: &[u8]| PathBuf::from(OsStr::from_bytes(bytes));
+        let stdout = include_bytes!("fixtures/list_non_utf8");
+        let expected = vec![raw(b"z/caf\xff"), raw(b"z/caf\xff@snap"), raw(b"z/plain")];
+        assert_eq!(Ok(expected), parse_dataset_list(stdout));
+
+        let typed = parse_typed_dataset_list(b"filesystem\tz/caf\xff\nsnapshot\tz/caf\xff@s\n");
+        let expected = vec![
+            (DatasetKind::Filesystem, raw(b"z/caf\xff")),
+            (DatasetKind::Snapshot, raw(b"z/caf\xff@s")),
+        ];
+        assert_eq!(Ok(expected), typed);
+
+        let mounts = parse_mount_table(b"z/caf\xff   /srv/caf\xff dir\n");
+        assert_eq!(vec![(raw(b"z/caf\xff"), raw(b"/srv/caf\xff dir"))], mounts);
+
+        let guids = parse_name_value_list(b"z/caf\xff@a\t42\n");
+        assert_eq!(Ok(vec![(raw(b"z/caf\xff@a"), 42)]), guids);
+
+        let renamed = map_renamed(
+            &raw(b"z/caf\xff"),
+            Path::new("z/cafe"),
+            vec![raw(b"z/caf\xff@s"), raw(b"z/caf\xffe")],
+        );
+        assert_eq!(vec![(raw(b"z/caf\xff@s"), raw(b"z/cafe@s"))], renamed);
+
+        // Name column is dropped, values have to be text.
+        let text = properties_text(b"z/caf\xff\ttype\tfilesystem\t-\n").unwrap();
+        assert_eq!("-\ttype\tfilesystem\t-\n", text);
+        assert_eq!(
+            Err(Error::NonUtf8(b"/srv/caf\xff".to_vec())),
+            properties_text(b"z/cafe\tmountpoint\t/srv/caf\xff\tlocal\n")
+        );
+    }
+
+    #[test]
+    fn mount_table_freebsd() {
+        let stdout = include_str!("fixtures/mount_freebsd");
+        let result = parse_mount_table(stdout.as_bytes());
+
+        assert_eq!(6, result.len());
+        assert_eq!(
+            (PathBuf::from("z/ROOT/default"), PathBuf::from("/")),
+            result[0]
+        );
+        assert_eq!(
+            (
+                PathBuf::from("z/data/media"),
+                PathBuf::from("/mnt/Media Library")
+            ),
+            result[5]
+        );
+    }
+
+    #[test]
+    fn mount_table_linux() {
+        let stdout = include_str!("fixtures/mount_linux");
+        let result = parse_mount_table(stdout.as_bytes());
+
+        assert_eq!(7, result.len());
+        assert_eq!(
+            (
+                PathBuf::from("bpool/BOOT/ubuntu_9b7kxa"),
+                PathBuf::from("/boot")
+            ),
+            result[1]
+        );
+        // Mounted twice: both entries are kept in order.
+        assert_eq!(
+            (PathBuf::from("rpool/legacy"), PathBuf::from("/srv/legacy")),
+            result[4]
+        );
+        assert_eq!(
+            (
+                PathBuf::from("rpool/legacy"),
+                PathBuf::from("/srv/legacy-bind")
+            ),
+            result[5]
+        );
+        assert_eq!(
+            (PathBuf::from("rpool/data"), PathBuf::from("/srv/my data")),
+            result[6]
+        );
+        assert!(parse_mount_table(b"").is_empty());
+    }
+
+    #[test]
+    fn map_renamed_children() {
+        let affected = vec![
+            PathBuf::from("z/a"),
+            PathBuf::from("z/a@snap"),
+            PathBuf::from("z/a/b"),
+            PathBuf::from("z/a/b#mark"),
+            PathBuf::from("z/a/b/c"),
+            PathBuf::from("z/ab"),
+        ];
+        let result = map_renamed(Path::new("z/a"), Path::new("z/x"), affected);
+        let expected: Vec<(PathBuf, PathBuf)> = vec![
+            ("z/a", "z/x"),
+            ("z/a@snap", "z/x@snap"),
+            ("z/a/b", "z/x/b"),
+            ("z/a/b#mark", "z/x/b#mark"),
+            ("z/a/b/c", "z/x/b/c"),
+        ]
+        .into_iter()
+        .map(|(from, to)| (PathBuf::from(from), PathBuf::from(to)))
+        .collect();
+        assert_eq!(expected, result);
+
+        let result = map_renamed(
+            Path::new("z/a@old"),
+            Path::new("z/a@new"),
+            vec![PathBuf::from("z/a@old")],
+        );
+        assert_eq!(
+            vec![(PathBuf::from("z/a@old"), PathBuf::from("z/a@new"))],
+            result
+        );
+    }
+
+    #[test]
+    fn property_sources_freebsd() {
+        let stdout = include_str!("fixtures/filesystem_properties_freebsd.sorted");
+        let sources = parse_property_sources(stdout).unwrap();
+
+        assert_eq!(
+            Some(&PropertySource::Inherited(PathBuf::from("z"))),
+            sources.get("compression")
+        );
+        assert_eq!(Some(&PropertySource::Local), sources.get("mountpoint"));
+        assert_eq!(Some(&PropertySource::Temporary), sources.get("xattr"));
+        assert_eq!(Some(&PropertySource::Default), sources.get("checksum"));
+        assert_eq!(Some(&PropertySource::None), sources.get("used"));
+    }
+
+    #[test]
+    fn property_source_from_str() {
+        assert_eq!(
+            PropertySource::Inherited(PathBuf::from("z/parent with space")),
+            "inherited from z/parent with space".parse().unwrap()
+        );
+        assert_eq!(
+            PropertySource::Received,
+            "received".parse::<PropertySource>().unwrap()
+        );
+        assert!("inherited from ".parse::<PropertySource>().is_err());
+        assert!("inherited".parse::<PropertySource>().is_err());
+    }
+
+    #[test]
+    fn send_progress_lines() {
+        let transcript = include_str!("fixtures/send_verbose");
+        let progress: Vec<(u64, PathBuf)> =
+            transcript.lines().filter_map(parse_send_progress).collect();
+        let snapshot = PathBuf::from("tank/data@today");
+        let expected = vec![
+            (312 * 1024 * 1024, snapshot.clone()),
+            (824 * 1024 * 1024, snapshot.clone()),
+            ((1.21 * (1u64 << 30) as f64) as u64, snapshot),
+        ];
+        assert_eq!(expected, progress);
+        assert_eq!(
+            3,
+            transcript.lines().filter(|l| is_send_estimate(l)).count()
+        );
+
+        assert_eq!(Some(0), parse_human_size("0"));
+        assert_eq!(Some(512), parse_human_size("512B"));
+        assert_eq!(Some(4096), parse_human_size("4K"));
+        assert_eq!(Some(123_456_789_012), parse_human_size("123456789012"));
+        assert_eq!(None, parse_human_size("-1K"));
+        assert_eq!(None, parse_human_size("lots"));
+        assert_eq!(None, parse_send_progress("12:01  1.21G  tank/data@today"));
+        assert_eq!(None, parse_send_progress("12:01:02  1.21G"));
+    }
+
+    #[test]
+    fn send_with_progress_through_shim() {
+        use std::io::{Read, Seek};
+
+        let dir = tempfile::tempdir().unwrap();
+        let fixtures = concat!(env!("CARGO_MANIFEST_DIR"), "/src/zfs/fixtures");
+        let shim = shim(
+            dir.path(),
+            "zfs",
+            &format!(
+                "for name; do :; done
+printf stream
+case \"$*\" in
+  *-i*) cat {fixtures}/send_verbose_failed >&2; exit 1;;
+  *) cat {fixtures}/send_verbose >&2;;
+esac
+",
+                fixtures = fixtures
+            ),
+        );
+        let mut zfs = ZfsOpen3::new();
+        zfs.cmd_name = shim.into_os_string();
+
+        let mut stream = tempfile::tempfile().unwrap();
+        let mut updates = Vec::new();
+        zfs.send_full_with_progress(
+            "tank/data@today",
+            stream.as_raw_fd(),
+            SendFlags::empty(),
+            |p| updates.push(p),
+        )
+        .unwrap();
+        assert_eq!(3, updates.len());
+        assert_eq!(&(312 * 1024 * 1024), updates[0].bytes_sent());
+        assert_eq!(Path::new("tank/data@today"), updates[2].snapshot());
+        assert!(updates[0].elapsed() <= updates[2].elapsed());
+        let mut sent = String::new();
+        stream.seek(io::SeekFrom::Start(0)).unwrap();
+        stream.read_to_string(&mut sent).unwrap();
+        assert_eq!("stream", sent);
+
+        let mut updates = 0;
+        let err = zfs
+            .send_incremental_with_progress(
+                "tank/data@today",
+                "@yesterday",
+                stream.as_raw_fd(),
+                SendFlags::empty(),
+                |_| updates += 1,
+            )
+            .unwrap_err();
+        assert_eq!(3, updates);
+        assert_eq!(
+            Error::DatasetNotFound(PathBuf::from("tank/data@today")),
+            err
+        );
+    }
 }
@@ -0,0 +1,460 @@
+//! Operations that span more than one pool.
+//!
+//! `libzfs_core` can snapshot many datasets atomically, but only within one pool.
+//! [`snapshot_group`](fn.snapshot_group.html) takes a batch per pool, validates all of them and
+//! then snapshots every pool at the same time, each batch atomically. Report tells what happened
+//! to each pool and how far apart the snapshots are.
+//!
+//! ### Usage
+//! ```rust,no_run
+//! use libzetta::zfs::{orchestration, DelegatingZfsEngine};
+//! use std::path::PathBuf;
+//!
+//! let engine = DelegatingZfsEngine::new().unwrap();
+//! let report = orchestration::snapshot_group(
+//!     vec![
+//!         (&engine, vec![PathBuf::from("fast/db"), PathBuf::from("fast/wal")]),
+//!         (&engine, vec![PathBuf::from("bulk/blobs")]),
+//!     ],
+//!     "nightly",
+//!     true,
+//! );
+//! if !report.is_complete() {
+//!     eprintln!("{:?}", report.pools());
+//! }
+//! println!("pools are {:?} apart", report.creation_skew());
+//! ```
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    utils::bounded_parallel,
+    zfs::{validators, DestroyTiming, Error, PathExt, Result, ValidationError, ZfsEngine},
+};
+
+/// Object-safe subset of [`ZfsEngine`](../trait.ZfsEngine.html), so batches of one group can use
+/// different engines. Implemented for every engine that can be shared between threads.
+pub trait ZfsEngineObj: Sync {
+    /// Same as [`ZfsEngine::snapshot`](../trait.ZfsEngine.html#method.snapshot).
+    fn snapshot(
+        &self,
+        snapshots: &[PathBuf],
+        user_properties: Option<HashMap<String, String>>,
+    ) -> Result<()>;
+
+    /// Same as
+    /// [`ZfsEngine::destroy_snapshots`](../trait.ZfsEngine.html#method.destroy_snapshots).
+    fn destroy_snapshots(&self, snapshots: &[PathBuf], timing: DestroyTiming) -> Result<()>;
+
+    /// `creation` and `createtxg` of a snapshot.
+    fn snapshot_stamp(&self, snapshot: &Path) -> Result<SnapshotStamp>;
+}
+
+impl<E: ZfsEngine + Sync> ZfsEngineObj for E {
+    fn snapshot(
+        &self,
+        snapshots: &[PathBuf],
+        user_properties: Option<HashMap<String, String>>,
+    ) -> Result<()> {
+        ZfsEngine::snapshot(self, snapshots, user_properties)
+    }
+
+    fn destroy_snapshots(&self, snapshots: &[PathBuf], timing: DestroyTiming) -> Result<()> {
+        ZfsEngine::destroy_snapshots(self, snapshots, timing)
+    }
+
+    fn snapshot_stamp(&self, snapshot: &Path) -> Result<SnapshotStamp> {
+        Ok(SnapshotStamp {
+            creation: self.get_property(snapshot, "creation")?,
+            create_txg: self.get_property(snapshot, "createtxg")?,
+        })
+    }
+}
+
+/// When a snapshot was created, read back from the snapshot itself.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Getters)]
+#[get = "pub"]
+pub struct SnapshotStamp {
+    /// Seconds since epoch, value of `creation`.
+    creation: u64,
+    /// Transaction group of the pool the snapshot was created in, value of `createtxg`.
+    create_txg: u64,
+}
+
+/// What happened to a batch of [`snapshot_group`](fn.snapshot_group.html).
+#[derive(Debug)]
+pub enum GroupSnapshotStatus {
+    /// Batch didn't pass validation, so no pool was snapshotted.
+    Invalid(Vec<ValidationError>),
+    /// Batch is fine, but another one is invalid, so no pool was snapshotted.
+    Skipped,
+    /// Snapshots are created. Stamp is `None` if it couldn't be read back.
+    Created(Option<SnapshotStamp>),
+    /// Snapshots couldn't be created.
+    Failed(Error),
+    /// Snapshots were created, then destroyed because another pool failed.
+    RolledBack,
+    /// Snapshots were created, but couldn't be destroyed after another pool failed.
+    RollbackFailed(Error),
+}
+
+/// Outcome of one batch of [`snapshot_group`](fn.snapshot_group.html).
+#[derive(Debug, Getters)]
+#[get = "pub"]
+pub struct PoolSnapshotOutcome {
+    /// Pool of the batch, empty if the batch is empty.
+    pool: PathBuf,
+    /// Full names of snapshots of the batch.
+    snapshots: Vec<PathBuf>,
+    /// What happened.
+    status: GroupSnapshotStatus,
+    /// When the snapshot call returned, counted from the start of the group. Zero if it wasn't
+    /// made.
+    finished_after: Duration,
+}
+
+/// Report of [`snapshot_group`](fn.snapshot_group.html), outcomes are in order of batches.
+#[derive(Debug, Getters)]
+#[get = "pub"]
+pub struct GroupSnapshotReport {
+    /// Outcome of every batch.
+    pools: Vec<PoolSnapshotOutcome>,
+}
+
+impl GroupSnapshotReport {
+    /// Snapshots of every pool are created and kept.
+    pub fn is_complete(&self) -> bool {
+        self.pools
+            .iter()
+            .all(|outcome| matches!(outcome.status, GroupSnapshotStatus::Created(_)))
+    }
+
+    /// Difference between the oldest and the newest `creation` of created snapshots. `creation`
+    /// has one second resolution, so zero means within the same second. `None` unless stamps of
+    /// at least two pools are known.
+    pub fn creation_skew(&self) -> Option<Duration> {
+        let creations: Vec<u64> = self
+            .pools
+            .iter()
+            .filter_map(|outcome| match outcome.status {
+                GroupSnapshotStatus::Created(Some(stamp)) => Some(stamp.creation),
+                _ => None,
+            })
+            .collect();
+        if creations.len() < 2 {
+            return None;
+        }
+        let newest = creations.iter().max()?;
+        let oldest = creations.iter().min()?;
+        Some(Duration::from_secs(newest - oldest))
+    }
+
+    /// Difference between the first and the last snapshot call to return, as measured here. Finer
+    /// than [`creation_skew`](#method.creation_skew), but includes time spent in `zfs` and the
+    /// kernel. `None` unless at least two pools are snapshotted.
+    pub fn completion_skew(&self) -> Option<Duration> {
+        let finished: Vec<Duration> = self
+            .pools
+            .iter()
+            .filter(|outcome| {
+                matches!(
+                    outcome.status,
+                    GroupSnapshotStatus::Created(_)
+                        | GroupSnapshotStatus::RolledBack
+                        | GroupSnapshotStatus::RollbackFailed(_)
+                )
+            })
+            .map(|outcome| outcome.finished_after)
+            .collect();
+        if finished.len() < 2 {
+            return None;
+        }
+        Some(*finished.iter().max()? - *finished.iter().min()?)
+    }
+}
+
+/// Create snapshot `snap_name` of every dataset of every batch, each batch atomically with its
+/// own engine. Every batch must be in a single pool, datasets can't repeat across batches.
+///
+/// Batches are validated before anything is created: if any of them is invalid, nothing is
+/// snapshotted. Then every batch is snapshotted at once, each in its own thread, and stamps of
+/// the first snapshot of each batch are read back.
+///
+/// With `rollback_on_partial_failure` snapshots of pools that succeeded are destroyed if any
+/// pool failed, so either every pool has the snapshot or none.
+///  * `batches` - Engine and datasets of each pool, e.g. `tank/db` and `tank/wal`.
+///  * `snap_name` - Short name of the snapshot, without `@`.
+pub fn snapshot_group(
+    batches: Vec<(&dyn ZfsEngineObj, Vec<PathBuf>)>,
+    snap_name: &str,
+    rollback_on_partial_failure: bool,
+) -> GroupSnapshotReport {
+    let mut names: Vec<PathBuf> = Vec::new();
+    let mut checked = Vec::with_capacity(batches.len());
+    for (engine, datasets) in batches {
+        let pool = datasets
+            .first()
+            .map(|dataset| PathBuf::from(dataset.pool()))
+            .unwrap_or_default();
+        let mut errors = Vec::new();
+        let mut snapshots = Vec::with_capacity(datasets.len());
+        for dataset in &datasets {
+            match validators::validate_recursive_snapshot(dataset, snap_name) {
+                Ok(snapshot) => snapshots.push(snapshot),
+                Err(e) => errors.push(e),
+            }
+        }
+        if errors.is_empty() {
+            if let Err(batch) = validators::validate_snapshot_batch(&snapshots) {
+                errors.extend(batch);
+            }
+        }
+        names.extend(snapshots.iter().cloned());
+        checked.push((engine, pool, snapshots, errors));
+    }
+    if let Err(e) = validators::validate_unique(&names) {
+        for (_, _, snapshots, errors) in &mut checked {
+            if let ValidationError::DuplicateName(ref repeated) = e {
+                if snapshots.iter().any(|snapshot| repeated.contains(snapshot)) {
+                    errors.push(ValidationError::DuplicateName(repeated.clone()));
+                }
+            }
+        }
+    }
+
+    if checked.iter().any(|(_, _, _, errors)| !errors.is_empty()) {
+        let pools = checked
+            .into_iter()
+            .map(|(_, pool, snapshots, errors)| PoolSnapshotOutcome {
+                pool,
+                snapshots,
+                status: if errors.is_empty() {
+                    GroupSnapshotStatus::Skipped
+                } else {
+                    GroupSnapshotStatus::Invalid(errors)
+                },
+                finished_after: Duration::default(),
+            })
+            .collect();
+        return GroupSnapshotReport { pools };
+    }
+
+    let started = Instant::now();
+    let workers = checked.len();
+    let results = bounded_parallel(checked, workers, |(engine, pool, snapshots, _)| {
+        let result = engine.snapshot(&snapshots, None);
+        let finished_after = started.elapsed();
+        (engine, pool, snapshots, result, finished_after)
+    });
+
+    let any_failed = results.iter().any(|(_, _, _, result, _)| result.is_err());
+    let rollback = rollback_on_partial_failure && any_failed;
+    let pools = results
+        .into_iter()
+        .map(|(engine, pool, snapshots, result, finished_after)| {
+            let status = match result {
+                Err(e) => GroupSnapshotStatus::Failed(e),
+                Ok(()) if rollback => {
+                    match engine.destroy_snapshots(&snapshots, DestroyTiming::RightNow) {
+                        Ok(()) => GroupSnapshotStatus::RolledBack,
+                        Err(e) => GroupSnapshotStatus::RollbackFailed(e),
+                    }
+                }
+                Ok(()) => GroupSnapshotStatus::Created(
+                    snapshots
+                        .first()
+                        .and_then(|snapshot| engine.snapshot_stamp(snapshot).ok()),
+                ),
+            };
+            PoolSnapshotOutcome {
+                pool,
+                snapshots,
+                status,
+                finished_after,
+            }
+        })
+        .collect();
+    GroupSnapshotReport { pools }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Mutex;
+
+    use super::*;
+
+    /// Engine of one pool that records destroyed snapshots and can be told to fail.
+    struct Pool {
+        fail: bool,
+        creation: &'static str,
+        destroyed: Mutex<Vec<PathBuf>>,
+    }
+
+    impl Pool {
+        fn new(fail: bool, creation: &'static str) -> Pool {
+            Pool {
+                fail,
+                creation,
+                destroyed: Mutex::default(),
+            }
+        }
+    }
+
+    impl ZfsEngine for Pool {
+        fn snapshot(
+            &self,
+            _snapshots: &[PathBuf],
+            _user_properties: Option<HashMap<String, String>>,
+        ) -> Result<()> {
+            if self.fail {
+                Err(Error::SnapshotsFailed(Vec::new()))
+            } else {
+                Ok(())
+            }
+        }
+
+        fn destroy_snapshots(&self, snapshots: &[PathBuf], _timing: DestroyTiming) -> Result<()> {
+            self.destroyed.lock().unwrap().extend_from_slice(snapshots);
+            Ok(())
+        }
+
+        fn get_property<N: Into<PathBuf>, T: crate::zfs::PropertyValue>(
+            &self,
+            _dataset: N,
+            property: &str,
+        ) -> Result<T> {
+            match property {
+                "creation" => T::from_value(property, self.creation),
+                "createtxg" => T::from_value(property, "42"),
+                _ => Err(Error::Unimplemented),
+            }
+        }
+    }
+
+    fn paths(names: &[&str]) -> Vec<PathBuf> {
+        names.iter().map(|name| PathBuf::from(name)).collect()
+    }
+
+    #[test]
+    fn group_snapshot_reports_skew() {
+        let fast = Pool::new(false, "1700000000");
+        let bulk = Pool::new(false, "1700000002");
+        let report = snapshot_group(
+            vec![
+                (&fast, paths(&["fast/db", "fast/wal"])),
+                (&bulk, paths(&["bulk/blobs"])),
+            ],
+            "nightly",
+            true,
+        );
+        assert!(report.is_complete());
+        assert_eq!(Some(Duration::from_secs(2)), report.creation_skew());
+        assert!(report.completion_skew().is_some());
+        let first = &report.pools()[0];
+        assert_eq!(&PathBuf::from("fast"), first.pool());
+        assert_eq!(
+            &paths(&["fast/db@nightly", "fast/wal@nightly"]),
+            first.snapshots()
+        );
+        match first.status() {
+            GroupSnapshotStatus::Created(Some(stamp)) => assert_eq!(42, *stamp.create_txg()),
+            other => panic!("unexpected {:?}", other),
+        }
+        assert!(fast.destroyed.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn group_snapshot_rolls_back_partial_failure() {
+        let fast = Pool::new(false, "1700000000");
+        let bulk = Pool::new(true, "1700000000");
+        let report = snapshot_group(
+            vec![
+                (&fast, paths(&["fast/db", "fast/wal"])),
+                (&bulk, paths(&["bulk/blobs"])),
+            ],
+            "nightly",
+            true,
+        );
+        assert!(!report.is_complete());
+        assert_eq!(None, report.creation_skew());
+        match report.pools()[0].status() {
+            GroupSnapshotStatus::RolledBack => {}
+            other => panic!("unexpected {:?}", other),
+        }
+        match report.pools()[1].status() {
+            GroupSnapshotStatus::Failed(Error::SnapshotsFailed(_)) => {}
+            other => panic!("unexpected {:?}", other),
+        }
+        assert_eq!(
+            paths(&["fast/db@nightly", "fast/wal@nightly"]),
+            *fast.destroyed.lock().unwrap()
+        );
+        assert!(bulk.destroyed.lock().unwrap().is_empty());
+
+        let fast = Pool::new(false, "1700000000");
+        let report = snapshot_group(
+            vec![
+                (&fast, paths(&["fast/db"])),
+                (&bulk, paths(&["bulk/blobs"])),
+            ],
+            "nightly",
+            false,
+        );
+        match report.pools()[0].status() {
+            GroupSnapshotStatus::Created(_) => {}
+            other => panic!("unexpected {:?}", other),
+        }
+        assert!(fast.destroyed.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn group_snapshot_validates_before_snapshotting() {
+        let fast = Pool::new(false, "1700000000");
+        let bulk = Pool::new(true, "1700000000");
+        let report = snapshot_group(
+            vec![
+                (&fast, paths(&["fast/db"])),
+                (&bulk, paths(&["bulk/blobs", "fast/wal"])),
+            ],
+            "nightly",
+            true,
+        );
+        match report.pools()[0].status() {
+            GroupSnapshotStatus::Skipped => {}
+            other => panic!("unexpected {:?}", other),
+        }
+        match report.pools()[1].status() {
+            GroupSnapshotStatus::Invalid(errors) => assert_eq!(
+                &vec![ValidationError::CrossPoolOperation(paths(&[
+                    "fast/wal@nightly"
+                ]))],
+                errors
+            ),
+            other => panic!("unexpected {:?}", other),
+        }
+
+        let report = snapshot_group(
+            vec![(&fast, paths(&["fast/db"])), (&fast, paths(&["fast/db"]))],
+            "nightly",
+            true,
+        );
+        let duplicate = ValidationError::DuplicateName(paths(&["fast/db@nightly"]));
+        for outcome in report.pools() {
+            match outcome.status() {
+                GroupSnapshotStatus::Invalid(errors) => {
+                    assert_eq!(&vec![duplicate.clone()], errors)
+                }
+                other => panic!("unexpected {:?}", other),
+            }
+        }
+
+        let report = snapshot_group(vec![(&fast, paths(&["fast/db"]))], "bad@name", true);
+        match report.pools()[0].status() {
+            GroupSnapshotStatus::Invalid(errors) => assert_eq!(1, errors.len()),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+}
@@ -1,11 +1,42 @@
-use crate::zfs::ValidationResult;
-use std::path::Path;
+use crate::zfs::{validators, ValidationError, ValidationResult};
+use std::{
+    ffi::OsString,
+    path::{Path, PathBuf},
+};
 
 pub trait PathExt {
     fn get_pool(&self) -> Option<String>;
     fn get_snapshot(&self) -> Option<String>;
     fn get_bookmark(&self) -> Option<String>;
 
+    /// Name of the pool this dataset belongs to. Empty string if path has a root or isn't UTF-8.
+    fn pool(&self) -> &str;
+    /// Part of the name after `@` if this is a snapshot.
+    fn snapshot_name(&self) -> Option<&str>;
+    /// Part of the name after `#` if this is a bookmark.
+    fn bookmark_name(&self) -> Option<&str>;
+    /// Dataset this snapshot or bookmark belongs to. Returns dataset itself for filesystems and
+    /// volumes.
+    fn dataset_of(&self) -> PathBuf;
+
+    /// Build snapshot name for this dataset. If path already points to a snapshot or a bookmark,
+    /// then it's replaced. `name` is a short name, so `@`, `#` and `/` in it are rejected.
+    fn with_snapshot(&self, name: &str) -> ValidationResult<PathBuf> {
+        let snapshot = join_with_separator(self.dataset_of(), '@', name);
+        validate_short_name(&snapshot, name)?;
+        validators::validate_snapshot_name(&snapshot)?;
+        Ok(snapshot)
+    }
+
+    /// Build bookmark name for this dataset. If path already points to a snapshot or a bookmark,
+    /// then it's replaced. `name` is a short name, so `@`, `#` and `/` in it are rejected.
+    fn with_bookmark(&self, name: &str) -> ValidationResult<PathBuf> {
+        let bookmark = join_with_separator(self.dataset_of(), '#', name);
+        validate_short_name(&bookmark, name)?;
+        validators::validate_bookmark_name(&bookmark)?;
+        Ok(bookmark)
+    }
+
     fn is_snapshot(&self) -> bool {
         self.get_snapshot().is_some()
     }
@@ -27,6 +58,35 @@ pub trait PathExt {
     fn validate(&self) -> ValidationResult;
 }
 
+pub(crate) fn join_with_separator(dataset: PathBuf, separator: char, name: &str) -> PathBuf {
+    let mut ret = OsString::with_capacity(dataset.as_os_str().len() + name.len() + 1);
+    ret.push(dataset.into_os_string());
+    ret.push(separator.to_string());
+    ret.push(name);
+    PathBuf::from(ret)
+}
+
+fn validate_short_name(full_name: &Path, name: &str) -> ValidationResult {
+    match name
+        .chars()
+        .find(|ch| *ch == '@' || *ch == '#' || *ch == '/')
+    {
+        Some(ch) => Err(ValidationError::InvalidCharacter {
+            dataset: full_name.to_path_buf(),
+            component: name.to_owned(),
+            ch,
+        }),
+        None => Ok(()),
+    }
+}
+
+/// Split last component of the path on given separator.
+fn split_last_component(path: &Path, separator: char) -> Option<(&str, &str)> {
+    let last = path.file_name()?.to_str()?;
+    let idx = last.find(separator)?;
+    Some((&last[..idx], &last[idx + 1..]))
+}
+
 impl PathExt for Path {
     fn get_pool(&self) -> Option<String> {
         if self.has_root() || self.components().count() < 2 {
@@ -59,6 +119,31 @@ impl PathExt for Path {
         None
     }
 
+    fn pool(&self) -> &str {
+        if self.has_root() {
+            return "";
+        }
+        self.to_str()
+            .and_then(|name| name.split(['/', '@', '#']).next())
+            .unwrap_or("")
+    }
+
+    fn snapshot_name(&self) -> Option<&str> {
+        split_last_component(self, '@').map(|(_, snapshot)| snapshot)
+    }
+
+    fn bookmark_name(&self) -> Option<&str> {
+        split_last_component(self, '#').map(|(_, bookmark)| bookmark)
+    }
+
+    fn dataset_of(&self) -> PathBuf {
+        let dataset = split_last_component(self, '@').or_else(|| split_last_component(self, '#'));
+        match dataset {
+            Some((last, _)) => self.with_file_name(last),
+            None => self.to_path_buf(),
+        }
+    }
+
     fn validate(&self) -> ValidationResult {
         crate::zfs::validators::validate_name(self)
     }
@@ -77,6 +162,22 @@ impl<P: AsRef<Path>> PathExt for P {
         self.as_ref().get_bookmark()
     }
 
+    fn pool(&self) -> &str {
+        self.as_ref().pool()
+    }
+
+    fn snapshot_name(&self) -> Option<&str> {
+        self.as_ref().snapshot_name()
+    }
+
+    fn bookmark_name(&self) -> Option<&str> {
+        self.as_ref().bookmark_name()
+    }
+
+    fn dataset_of(&self) -> PathBuf {
+        self.as_ref().dataset_of()
+    }
+
     fn validate(&self) -> ValidationResult {
         self.as_ref().validate()
     }
@@ -85,6 +186,7 @@ impl<P: AsRef<Path>> PathExt for P {
 #[cfg(test)]
 mod test {
     use super::PathExt;
+    use crate::zfs::ValidationError;
     use std::path::PathBuf;
 
     #[test]
@@ -148,4 +250,153 @@ mod test {
         let path = PathBuf::from("tank/usr#wat/home");
         assert!(!path.is_bookmark());
     }
+
+    #[test]
+    fn name_helpers() {
+        // (path, pool, dataset, snapshot, bookmark)
+        let table = [
+            ("tank", "tank", "tank", None, None),
+            ("tank@snap", "tank", "tank", Some("snap"), None),
+            ("tank#mark", "tank", "tank", None, Some("mark")),
+            ("tank/usr/home", "tank", "tank/usr/home", None, None),
+            (
+                "tank/usr/home@snap",
+                "tank",
+                "tank/usr/home",
+                Some("snap"),
+                None,
+            ),
+            (
+                "tank/usr/home#mark",
+                "tank",
+                "tank/usr/home",
+                None,
+                Some("mark"),
+            ),
+            (
+                "my-pool/foo.bar/baz-qux@auto-2019.08.01",
+                "my-pool",
+                "my-pool/foo.bar/baz-qux",
+                Some("auto-2019.08.01"),
+                None,
+            ),
+            (
+                "my.pool/foo-bar#daily.1-2",
+                "my.pool",
+                "my.pool/foo-bar",
+                None,
+                Some("daily.1-2"),
+            ),
+            ("/usr/home", "", "/usr/home", None, None),
+        ];
+
+        for (name, pool, dataset, snapshot, bookmark) in table.iter() {
+            let path = PathBuf::from(name);
+            assert_eq!(*pool, path.pool(), "pool of {}", name);
+            assert_eq!(
+                PathBuf::from(dataset),
+                path.dataset_of(),
+                "dataset of {}",
+                name
+            );
+            assert_eq!(*snapshot, path.snapshot_name(), "snapshot of {}", name);
+            assert_eq!(*bookmark, path.bookmark_name(), "bookmark of {}", name);
+            assert_eq!(
+                snapshot.is_some(),
+                path.is_snapshot(),
+                "is_snapshot of {}",
+                name
+            );
+            assert_eq!(
+                bookmark.is_some(),
+                path.is_bookmark(),
+                "is_bookmark of {}",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn with_snapshot_and_bookmark() {
+        // (path, new name, expected snapshot, expected bookmark)
+        let table = [
+            ("tank", "snap", "tank@snap", "tank#snap"),
+            (
+                "tank/usr/home",
+                "snap",
+                "tank/usr/home@snap",
+                "tank/usr/home#snap",
+            ),
+            (
+                "tank/usr/home@old",
+                "new",
+                "tank/usr/home@new",
+                "tank/usr/home#new",
+            ),
+            (
+                "tank/usr/home#old",
+                "new",
+                "tank/usr/home@new",
+                "tank/usr/home#new",
+            ),
+            (
+                "tank/foo-bar/baz.qux",
+                "auto-2019.08.01",
+                "tank/foo-bar/baz.qux@auto-2019.08.01",
+                "tank/foo-bar/baz.qux#auto-2019.08.01",
+            ),
+        ];
+
+        for (name, new_name, snapshot, bookmark) in table.iter() {
+            let path = PathBuf::from(name);
+            let with_snapshot = path.with_snapshot(new_name).unwrap();
+            let with_bookmark = path.with_bookmark(new_name).unwrap();
+            assert_eq!(PathBuf::from(snapshot), with_snapshot);
+            assert_eq!(PathBuf::from(bookmark), with_bookmark);
+            assert_eq!(Some(*new_name), with_snapshot.snapshot_name());
+            assert_eq!(Some(*new_name), with_bookmark.bookmark_name());
+        }
+    }
+
+    #[test]
+    fn with_snapshot_too_long() {
+        let path = PathBuf::from("tank/usr/home");
+        let name = "a".repeat(crate::zfs::DATASET_NAME_MAX_LENGTH);
+        let snapshot = PathBuf::from(format!("tank/usr/home@{}", name));
+        assert_eq!(
+            Err(ValidationError::NameTooLong(snapshot)),
+            path.with_snapshot(&name)
+        );
+        assert!(path.with_bookmark(&name).is_err());
+    }
+
+    #[test]
+    fn with_snapshot_rejects_separators() {
+        let path = PathBuf::from("tank/usr/home");
+        // (new name, offending character)
+        let table = [("a@b", '@'), ("a#b", '#'), ("a/b", '/'), ("@", '@')];
+        for (name, ch) in table.iter() {
+            assert_eq!(
+                Err(ValidationError::InvalidCharacter {
+                    dataset: PathBuf::from(format!("tank/usr/home@{}", name)),
+                    component: name.to_string(),
+                    ch: *ch,
+                }),
+                path.with_snapshot(name)
+            );
+            assert_eq!(
+                Err(ValidationError::InvalidCharacter {
+                    dataset: PathBuf::from(format!("tank/usr/home#{}", name)),
+                    component: name.to_string(),
+                    ch: *ch,
+                }),
+                path.with_bookmark(name)
+            );
+        }
+        assert!(path.with_snapshot("").is_err());
+        assert_eq!(
+            Ok(PathBuf::from("tank/usr/home#a b")),
+            path.with_bookmark("a b")
+        );
+    }
 }
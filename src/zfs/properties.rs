@@ -1,8 +1,12 @@
-use std::{default::Default, path::PathBuf};
+use chrono::{DateTime, TimeZone, Utc};
+use std::{default::Default, path::PathBuf, str::FromStr};
 use strum_macros::{AsRefStr, Display, EnumString};
 
-use std::collections::HashMap;
+use crate::zfs::{ByteSize, Error};
 
+use std::collections::{BTreeMap, HashMap};
+
+// Discriminants of these enums are indexes from kernel's property tables.
 macro_rules! impl_zfs_prop {
     ($type_:ty, $as_str:literal) => {
         impl ZfsProp for $type_ {
@@ -10,16 +14,31 @@ macro_rules! impl_zfs_prop {
                 $as_str
             }
 
-            fn as_nv_value(&self) -> u64 {
-                *self as u64
+            fn as_nv_value(&self) -> NvValueRepr {
+                NvValueRepr::Index(*self as u64)
             }
         }
     };
 }
+
+/// How property value is passed to `libzfs_core`.
+///
+/// Kernel only accepts numeric index for enum-like properties (e.g. `compression`), string nvpair
+/// for those fails with `EINVAL`. Index is the position of the value in the property table of
+/// OpenZFS, which is the same on every platform.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NvValueRepr {
+    /// Index of the value in kernel's property table.
+    Index(u64),
+    /// Value of string property, passed as is.
+    String(String),
+}
+
 pub trait ZfsProp {
     /// String representation of ZFS Property
     fn nv_key() -> &'static str;
-    fn as_nv_value(&self) -> u64;
+    /// Value in a form `libzfs_core` accepts.
+    fn as_nv_value(&self) -> NvValueRepr;
 }
 /// Controls how ACL entries inherited when files and directories created. Default value is
 /// `Restricted`.
@@ -227,8 +246,8 @@ impl ZfsProp for CacheMode {
     }
 
     #[allow(clippy::as_conversion)]
-    fn as_nv_value(&self) -> u64 {
-        *self as u64
+    fn as_nv_value(&self) -> NvValueRepr {
+        NvValueRepr::Index(*self as u64)
     }
 }
 
@@ -254,6 +273,55 @@ impl Default for SnapDir {
     }
 }
 
+/// Controls whether snapshots of a volume are exposed as block devices, e.g. under `/dev/zvol`.
+#[derive(AsRefStr, EnumString, Display, Eq, PartialEq, Debug, Clone, Copy, Default)]
+#[repr(u64)]
+pub enum SnapDev {
+    #[strum(serialize = "hidden")]
+    #[default]
+    Hidden = 0,
+    #[strum(serialize = "visible")]
+    Visible = 1,
+}
+
+/// Whether and how a file system is shared over NFS (`sharenfs`) or SMB (`sharesmb`).
+///
+/// Options are passed to the platform as they are: FreeBSD expects `exports(5)` syntax, Linux
+/// expects `exportfs` options, so they aren't parsed.
+#[derive(Eq, PartialEq, Debug, Clone, Default)]
+pub enum ShareOptions {
+    /// Not shared.
+    #[default]
+    Off,
+    /// Shared with default options.
+    On,
+    /// Shared with these options.
+    Options(String),
+}
+
+impl std::fmt::Display for ShareOptions {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ShareOptions::Off => f.write_str("off"),
+            ShareOptions::On => f.write_str("on"),
+            ShareOptions::Options(options) => f.write_str(options),
+        }
+    }
+}
+
+impl FromStr for ShareOptions {
+    type Err = Error;
+
+    /// Never fails, anything but `on` and `off` is options.
+    fn from_str(value: &str) -> Result<ShareOptions, Error> {
+        Ok(match value {
+            "off" => ShareOptions::Off,
+            "on" => ShareOptions::On,
+            options => ShareOptions::Options(String::from(options)),
+        })
+    }
+}
+
 #[derive(AsRefStr, EnumString, Display, Eq, PartialEq, Debug, Clone, Copy)]
 #[repr(u64)]
 pub enum CanMount {
@@ -303,7 +371,8 @@ pub enum VolumeMode {
     #[strum(serialize = "default")]
     Default = 0,
     /// Volumes with this property are exposed as [`geom(4)`](https://www.freebsd.org/cgi/man.cgi?geom(4)) device.
-    #[strum(serialize = "geom")]
+    /// Linux calls it `full`: volume and its partitions get block devices.
+    #[strum(to_string = "geom", serialize = "full")]
     GEOM = 1,
     /// Volumes with this property are exposed as cdev in devfs.
     #[strum(serialize = "dev")]
@@ -340,29 +409,32 @@ impl Default for CaseSensitivity {
     }
 }
 
+/// Flag added to checksum of `dedup` to compare blocks byte-to-byte.
+const ZIO_CHECKSUM_VERIFY: u64 = 1 << 8;
+
 /// Configures deduplication for a dataset. If set to verify, ZFS will do a byte-to-byte comparision
 /// in case of two blocks having the same signature to make sure the block contents are identical.
 #[derive(AsRefStr, EnumString, Display, Eq, PartialEq, Debug, Clone, Copy)]
 #[repr(u64)]
 pub enum Dedup {
     #[strum(serialize = "on")]
-    On,
+    On = 1,
     #[strum(serialize = "off")]
-    Off,
+    Off = 2,
     #[strum(serialize = "verify")]
-    Verify,
+    Verify = 1 | ZIO_CHECKSUM_VERIFY,
     #[strum(serialize = "sha256")]
-    SHA256,
+    SHA256 = 8,
     #[strum(serialize = "sha256,verify")]
-    VerifySHA256,
+    VerifySHA256 = 8 | ZIO_CHECKSUM_VERIFY,
     #[strum(serialize = "sha512")]
-    SHA512,
+    SHA512 = 11,
     #[strum(serialize = "sha512,verify")]
-    VerifySHA512,
+    VerifySHA512 = 11 | ZIO_CHECKSUM_VERIFY,
     #[strum(serialize = "skein")]
-    Skein,
+    Skein = 12,
     #[strum(serialize = "skein,verify")]
-    VerifySkein,
+    VerifySkein = 12 | ZIO_CHECKSUM_VERIFY,
 }
 
 impl Default for Dedup {
@@ -376,15 +448,15 @@ impl Default for Dedup {
 #[repr(u64)]
 pub enum Normalization {
     #[strum(serialize = "none")]
-    None,
+    None = 0,
     #[strum(serialize = "formc", serialize = "formC")]
-    FormC,
+    FormC = 0x50,
     #[strum(serialize = "formd", serialize = "formD")]
-    FormD,
+    FormD = 0x10,
     #[strum(serialize = "formkc", serialize = "formKC")]
-    FormKC,
+    FormKC = 0x60,
     #[strum(serialize = "formkd", serialize = "formKD")]
-    FormKD,
+    FormKD = 0x20,
 }
 
 impl Default for Normalization {
@@ -438,19 +510,19 @@ impl Default for RedundantMetadata {
 #[repr(u64)]
 pub enum DnodeSize {
     #[strum(serialize = "legacy")]
-    Legacy,
+    Legacy = 0,
     #[strum(serialize = "auto")]
-    Auto,
+    Auto = 1,
     #[strum(serialize = "1k")]
-    _1K,
+    _1K = 1024,
     #[strum(serialize = "2k")]
-    _2K,
+    _2K = 2048,
     #[strum(serialize = "4k")]
-    _4K,
+    _4K = 4096,
     #[strum(serialize = "8k")]
-    _8K,
+    _8K = 8192,
     #[strum(serialize = "16k")]
-    _16K,
+    _16K = 16384,
 }
 
 impl Default for DnodeSize {
@@ -463,39 +535,46 @@ impl Default for DnodeSize {
 ///
 /// Notable missing properties:
 ///  - shareiscsi
-///  - sharenfs
-///  - sharesmb
 ///  - version
 ///  - zoned
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[derive(Debug, Clone, PartialEq, Getters, Builder)]
 #[builder(derive(Debug))]
 #[get = "pub"]
 pub struct FilesystemProperties {
     name: PathBuf,
     /// Controls how ACL entries inherited when files and directories created.
+    #[builder(default)]
     acl_inherit: AclInheritMode,
     /// Controls how an ACL entry modified during a `chmod` operation.
     #[builder(default)]
     acl_mode: Option<AclMode>,
     /// Controls whether the access time for files updated when they are read.
+    #[builder(default)]
     atime: bool,
     /// Read-only property that identifies the amount of disk space available to a dataset and all
     /// its children, assuming no other activity in the pool. Because disk space shared within a
     /// pool, available space can be limited by various factors including physical pool size,
     /// quotas, reservations, and other datasets within the pool.
+    #[builder(default)]
     available: i64,
     /// Controls whether a file system can be mounted.
+    #[builder(default)]
     can_mount: CanMount,
     /// Indicates whether the file name matching algorithm used by the file system should be
     /// case-sensitive, case-insensitive, or allow a combination of both styles of matching.
+    #[builder(default)]
     case_sensitivity: CaseSensitivity,
     /// [Security label](https://docs.oracle.com/cd/E23824_01/html/821-1482/managezones-18.html)
     /// Controls the checksum used to verify data integrity.
+    #[builder(default)]
     checksum: Checksum,
     /// Enables or disables compression for a dataset.
+    #[builder(default)]
     compression: Compression,
     /// Read-only property that identifies the compression ratio achieved for a dataset, expressed
     /// as a multiplier.
+    #[builder(default)]
     compression_ratio: f64,
     /// Sets the number of copies of user data per file system. Available values are 1, 2, or 3.
     /// These copies are in addition to any pool-level redundancy. Disk space used by multiple
@@ -503,36 +582,44 @@ pub struct FilesystemProperties {
     /// quotas and reservations. In addition, the used property updated when multiple copies
     /// enabled. Consider setting this property when the file system created because changing this
     /// property on an existing file system only affects newly written data.
+    #[builder(default)]
     copies: Copies,
     /// The birth time transaction group (TXG) of the object.
     #[builder(default)]
     create_txg: Option<u64>,
     /// Read-only property that identifies the date and time a dataset created.
+    #[builder(default)]
     creation: i64,
     /// Configures deduplication for a dataset.
     #[builder(default)]
     dedup: Dedup,
     /// Controls whether device files in a file system can be opened.
+    #[builder(default)]
     devices: bool,
     /// Specifies a compatibility mode or literal value for the size of dnodes in the file system.
     #[builder(default)]
     dnode_size: DnodeSize,
     /// Controls whether programs in a file system allowed to be executed. Also, when set to
     /// `false`, `mmap(2)` calls with `PROT_EXEC` disallowed.
+    #[builder(default)]
     exec: bool,
     /// The total number of filesystems that exist under this location in the dataset tree.  This
     /// value is only available when a filesystem_limit has been set somewhere in the tree under
     /// which the dataset resides.
+    #[builder(default)]
     filesystem_count: Option<u64>,
     /// Limits the number of filesystems that can be created on a dataset and its descendents.
+    #[builder(default)]
     filesystem_limit: Option<u64>,
     /// GUID of the dataset
     #[builder(default)]
     guid: Option<u64>,
     /// Read-only property that indicates whether a file system, clone, or snapshot is currently
     /// mounted.
+    #[builder(default)]
     mounted: bool,
     /// Controls the mount point used for this file system.
+    #[builder(default)]
     mount_point: Option<PathBuf>,
     /// [Cross-protocol locking](https://docs.oracle.com/cd/E19120-01/open.solaris/820-2429/configurecrossprotocollockingtask/index.html)
     #[builder(default)]
@@ -542,73 +629,104 @@ pub struct FilesystemProperties {
     #[builder(default)]
     normalization: Normalization,
     /// Controls what is cached in the primary cache (ARC).
+    #[builder(default)]
     primary_cache: CacheMode,
     // Read-only property for cloned file systems or volumes that identifies the snapshot from
     // which the clone was created.
     #[builder(default)]
     origin: Option<String>,
     /// Limits the amount of disk space a dataset and its descendants can consume.
+    #[builder(default)]
     quota: u64,
     /// Controls whether a dataset can be modified.
+    #[builder(default)]
     readonly: bool,
     /// Specifies a suggested block size for files in a file system in bytes. The size specified
     /// must be a power of two greater than or equal to 512 and less than or equal to 128 KiB.
     /// If the large_blocks feature is enabled on the pool, the size may be up to 1 MiB.
+    #[builder(default)]
     record_size: u64,
     /// Controls what types of metadata are stored redundantly
+    #[builder(default)]
     redundant_metadata: RedundantMetadata,
     /// Compression ratio achieved for the referenced space of this snapshot.
+    #[builder(default)]
     ref_compression_ratio: f64,
     /// Read-only property that identifies the amount of data accessible by a dataset, which might
     /// or might not be shared with other datasets in the pool.
+    #[builder(default)]
     referenced: u64,
     /// Sets the amount of disk space a dataset can consume. This property enforces a hard limit on
     /// the amount of space used. This hard limit does not include disk space used by descendents,
     /// such as snapshots and clones.
+    #[builder(default)]
     ref_quota: u64,
     /// Sets the minimum amount of disk space is guaranteed to a dataset, not including
     /// descendants, such as snapshots and clones.
+    #[builder(default)]
     ref_reservation: u64,
     /// Sets the minimum amount of disk space guaranteed to a dataset and its descendants.
+    #[builder(default)]
     reservation: u64,
     /// Controls what is cached in the secondary cache (L2ARC).
+    #[builder(default)]
     secondary_cache: CacheMode,
     /// Controls whether the `setuid` bit is honored in a file system.
+    #[builder(default)]
     setuid: bool,
+    /// Controls whether the file system is shared over NFS.
+    #[builder(default)]
+    share_nfs: ShareOptions,
+    /// Controls whether the file system is shared over SMB.
+    #[builder(default)]
+    share_smb: ShareOptions,
     /// Controls whether the .zfs directory is hidden or visible in the root of the file system
+    #[builder(default)]
     snap_dir: SnapDir,
     /// The total number of snapshots that exist under this location in the dataset tree.  This
     /// value is only available when a snapshot_limit has been set somewhere in the tree under
     /// which the dataset resides.
+    #[builder(default)]
     snapshot_count: Option<u64>,
     /// Limits the number of snapshots that can be created on a dataset and its descendents.
+    #[builder(default)]
     snapshot_limit: Option<u64>,
     /// Controls the behavior of synchronous requests.
+    #[builder(default)]
     sync: SyncMode,
     /// Read-only property that identifies the amount of disk space consumed by a dataset and all
     /// its descendants.
+    #[builder(default)]
     used: u64,
     /// Read-only property that identifies the amount of disk space is used by children of this
     /// dataset, which would be freed if all the dataset's children were destroyed.
+    #[builder(default)]
     used_by_children: u64,
     /// Read-only property that identifies the amount of disk space is used by a dataset itself.
+    #[builder(default)]
     used_by_dataset: u64,
     /// Read-only property that identifies the amount of disk space is used by a refreservation set
     /// on a dataset.
+    #[builder(default)]
     used_by_ref_reservation: u64,
     /// Read-only property that identifies the amount of disk space is consumed by snapshots of a
     /// dataset.
+    #[builder(default)]
     used_by_snapshots: u64,
     /// Indicates whether extended attributes are enabled or disabled.
+    #[builder(default)]
     xattr: bool,
     /// Controls whether the dataset is managed from a jail.
     #[builder(default)]
     jailed: Option<bool>,
     /// Provide a hint to ZFS about handling of synchronous requests in this dataset.
+    #[builder(default)]
     log_bias: LogBias,
     /// The amount of space is "logically" accessible by this dataset.
+    #[builder(default)]
     logical_referenced: u64,
     ///  The amount of space is "logically" consumed by this dataset and all its descendents.
+    #[builder(default)]
     logical_used: u64,
     /// [Security label](https://docs.oracle.com/cd/E23824_01/html/821-1482/managezones-18.html)
     #[builder(default)]
@@ -619,18 +737,49 @@ pub struct FilesystemProperties {
     #[builder(default)]
     utf8_only: Option<bool>,
     /// Version (should 5)
+    #[builder(default)]
     version: u64,
     /// Written?
+    #[builder(default)]
     written: u64,
     /// Controls how the volume is exposed to the OS
+    #[builder(default)]
     volume_mode: Option<VolumeMode>,
     /// Virus scan - not used outside solaris
     #[builder(default)]
     vscan: bool,
     /// User defined properties and properties this library failed to recognize.
+    #[builder(default)]
     unknown_properties: HashMap<String, String>,
 }
 
+/// `0` means the limit isn't set.
+fn optional_size(bytes: u64) -> Option<ByteSize> {
+    if bytes == 0 {
+        None
+    } else {
+        Some(ByteSize::bytes(bytes))
+    }
+}
+
+/// Values of `<prefix><id>` properties, e.g. `projectquota@42`, by numeric ID. Unset values and
+/// keys with non-numeric IDs are skipped.
+fn numbered_properties(unknown: &HashMap<String, String>, prefix: &str) -> BTreeMap<u32, u64> {
+    unknown
+        .iter()
+        .filter_map(|(key, value)| {
+            let id = key.strip_prefix(prefix)?.parse().ok()?;
+            let value = value.parse().ok()?;
+            Some((id, value))
+        })
+        .collect()
+}
+
+/// Seconds since epoch. `None` if it's out of range of `DateTime`.
+fn creation_datetime(creation: i64) -> Option<DateTime<Utc>> {
+    Utc.timestamp_opt(creation, 0).single()
+}
+
 impl FilesystemProperties {
     pub fn builder(name: PathBuf) -> FilesystemPropertiesBuilder {
         let mut ret = FilesystemPropertiesBuilder::default();
@@ -638,6 +787,48 @@ impl FilesystemProperties {
         ret.unknown_properties(HashMap::new());
         ret
     }
+
+    /// Typed [`creation`](#method.creation).
+    pub fn creation_datetime(&self) -> Option<DateTime<Utc>> {
+        creation_datetime(self.creation)
+    }
+
+    /// Typed [`quota`](#method.quota). `None` if quota isn't set.
+    pub fn quota_size(&self) -> Option<ByteSize> {
+        optional_size(self.quota)
+    }
+
+    /// Typed [`record_size`](#method.record_size).
+    pub fn record_size_bytes(&self) -> ByteSize {
+        ByteSize::bytes(self.record_size)
+    }
+
+    /// Typed [`ref_quota`](#method.ref_quota). `None` if quota isn't set.
+    pub fn ref_quota_size(&self) -> Option<ByteSize> {
+        optional_size(self.ref_quota)
+    }
+
+    /// Typed [`ref_reservation`](#method.ref_reservation). `None` if reservation isn't set.
+    pub fn ref_reservation_size(&self) -> Option<ByteSize> {
+        optional_size(self.ref_reservation)
+    }
+
+    /// Typed [`reservation`](#method.reservation). `None` if reservation isn't set.
+    pub fn reservation_size(&self) -> Option<ByteSize> {
+        optional_size(self.reservation)
+    }
+
+    /// `projectquota@<id>` properties among [`unknown_properties`](#method.unknown_properties),
+    /// by project ID.
+    pub fn project_quotas(&self) -> BTreeMap<u32, u64> {
+        numbered_properties(&self.unknown_properties, "projectquota@")
+    }
+
+    /// `projectused@<id>` properties among [`unknown_properties`](#method.unknown_properties), by
+    /// project ID.
+    pub fn project_used(&self) -> BTreeMap<u32, u64> {
+        numbered_properties(&self.unknown_properties, "projectused@")
+    }
 }
 
 impl FilesystemPropertiesBuilder {
@@ -660,6 +851,7 @@ impl FilesystemPropertiesBuilder {
 ///  - sharesmb
 ///  - version
 ///  - zoned
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[derive(Debug, Clone, PartialEq, Getters, Builder)]
 #[get = "pub"]
 pub struct VolumeProperties {
@@ -668,13 +860,17 @@ pub struct VolumeProperties {
     /// its children, assuming no other activity in the pool. Because disk space shared within a
     /// pool, available space can be limited by various factors including physical pool size,
     /// quotas, reservations, and other datasets within the pool.
+    #[builder(default)]
     available: i64,
     /// Controls the checksum used to verify data integrity.
+    #[builder(default)]
     checksum: Checksum,
     /// Enables or disables compression for a dataset.
+    #[builder(default)]
     compression: Compression,
     /// Read-only property that identifies the compression ratio achieved for a dataset, expressed
     /// as a multiplier.
+    #[builder(default)]
     compression_ratio: f64,
     /// Sets the number of copies of user data per file system. Available values are 1, 2, or 3.
     /// These copies are in addition to any pool-level redundancy. Disk space used by multiple
@@ -682,11 +878,13 @@ pub struct VolumeProperties {
     /// quotas and reservations. In addition, the used property updated when multiple copies
     /// enabled. Consider setting this property when the file system created because changing this
     /// property on an existing file system only affects newly written data.
+    #[builder(default)]
     copies: Copies,
     /// The birth time transaction group (TXG) of the object.
     #[builder(default)]
     create_txg: Option<u64>,
     /// Read-only property that identifies the date and time a dataset created.
+    #[builder(default)]
     creation: i64,
     /// Configures deduplication for a dataset.
     #[builder(default)]
@@ -695,67 +893,94 @@ pub struct VolumeProperties {
     #[builder(default)]
     guid: Option<u64>,
     /// Provide a hint to ZFS about handling of synchronous requests in this dataset.
+    #[builder(default)]
     log_bias: LogBias,
     /// The amount of space is "logically" accessible by this dataset.
+    #[builder(default)]
     logical_referenced: u64,
     ///  The amount of space is "logically" consumed by this dataset and all its descendents.
+    #[builder(default)]
     logical_used: u64,
     /// [Security label](https://docs.oracle.com/cd/E23824_01/html/821-1482/managezones-18.html)
     #[builder(default)]
     mls_label: Option<String>,
     /// Read-only property that indicates whether a file system, clone, or snapshot is currently
     /// Controls what is cached in the primary cache (ARC).
+    #[builder(default)]
     primary_cache: CacheMode,
     /// Controls whether a dataset can be modified.
+    #[builder(default)]
     readonly: bool,
     /// Controls what types of metadata are stored redundantly
+    #[builder(default)]
     redundant_metadata: RedundantMetadata,
     /// Compression ratio achieved for the referenced space of this snapshot.
+    #[builder(default)]
     ref_compression_ratio: f64,
     /// Read-only property that identifies the amount of data accessible by a dataset, which might
     /// or might not be shared with other datasets in the pool.
+    #[builder(default)]
     referenced: u64,
     /// Sets the minimum amount of disk space is guaranteed to a dataset, not including
     /// descendants, such as snapshots and clones.
+    #[builder(default)]
     ref_reservation: u64,
     /// Sets the minimum amount of disk space guaranteed to a dataset and its descendants.
+    #[builder(default)]
     reservation: u64,
     /// Controls what is cached in the secondary cache (L2ARC).
+    #[builder(default)]
     secondary_cache: CacheMode,
+    /// Whether snapshots of the volume are exposed as block devices.
+    #[builder(default)]
+    snap_dev: Option<SnapDev>,
     /// The total number of snapshots that exist under this location in the dataset tree.  This
     /// value is only available when a snapshot_limit has been set somewhere in the tree under
     /// which the dataset resides.
+    #[builder(default)]
     snapshot_count: Option<u64>,
     /// Limits the number of snapshots that can be created on a dataset and its descendents.
+    #[builder(default)]
     snapshot_limit: Option<u64>,
     /// Controls the behavior of synchronous requests.
+    #[builder(default)]
     sync: SyncMode,
     /// Read-only property that identifies the amount of disk space consumed by a dataset and all
     /// its descendants.
+    #[builder(default)]
     used: u64,
     /// Read-only property that identifies the amount of disk space is used by children of this
     /// dataset, which would be freed if all the dataset's children were destroyed.
+    #[builder(default)]
     used_by_children: u64,
     /// Read-only property that identifies the amount of disk space is used by a dataset itself.
+    #[builder(default)]
     used_by_dataset: u64,
     /// Read-only property that identifies the amount of disk space is used by a refreservation set
     /// on a dataset.
+    #[builder(default)]
     used_by_ref_reservation: u64,
     /// Read-only property that identifies the amount of disk space is consumed by snapshots of a
     /// dataset.
+    #[builder(default)]
     used_by_snapshots: u64,
     /// For volumes, specifies the block size of the volume in bytes. The block size cannot be
     /// changed after the volume has been written, so set the block size at volume creation time.
     /// The default block size for volumes is 8 KB. Any power of 2 from 512 bytes to 128 KB is
     /// valid.
+    #[builder(default)]
     volume_block_size: u64,
     /// Controls how the volume is exposed to the OS
+    #[builder(default)]
     volume_mode: Option<VolumeMode>,
     /// For volumes, specifies the logical size of the volume.
+    #[builder(default)]
     volume_size: u64,
     /// Written?
+    #[builder(default)]
     written: u64,
     /// User defined properties and properties this library failed to recognize.
+    #[builder(default)]
     unknown_properties: HashMap<String, String>,
 }
 
@@ -766,6 +991,31 @@ impl VolumeProperties {
         ret.unknown_properties(HashMap::new());
         ret
     }
+
+    /// Typed [`creation`](#method.creation).
+    pub fn creation_datetime(&self) -> Option<DateTime<Utc>> {
+        creation_datetime(self.creation)
+    }
+
+    /// Typed [`ref_reservation`](#method.ref_reservation). `None` if reservation isn't set.
+    pub fn ref_reservation_size(&self) -> Option<ByteSize> {
+        optional_size(self.ref_reservation)
+    }
+
+    /// Typed [`reservation`](#method.reservation). `None` if reservation isn't set.
+    pub fn reservation_size(&self) -> Option<ByteSize> {
+        optional_size(self.reservation)
+    }
+
+    /// Typed [`volume_block_size`](#method.volume_block_size).
+    pub fn volume_block_size_bytes(&self) -> ByteSize {
+        ByteSize::bytes(self.volume_block_size)
+    }
+
+    /// Typed [`volume_size`](#method.volume_size).
+    pub fn volume_size_bytes(&self) -> ByteSize {
+        ByteSize::bytes(self.volume_size)
+    }
 }
 
 impl VolumePropertiesBuilder {
@@ -779,6 +1029,7 @@ impl VolumePropertiesBuilder {
     }
 }
 
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[derive(Debug, Clone, PartialEq, Getters, Builder)]
 #[builder(derive(Debug))]
 #[get = "pub"]
@@ -788,26 +1039,35 @@ pub struct SnapshotProperties {
     #[builder(default)]
     create_txg: Option<u64>,
     /// Read-only property that identifies the date and time a dataset created.
+    #[builder(default)]
     creation: i64,
     /// Read-only property that identifies the amount of disk space consumed by a dataset and all
     /// its descendants.
+    #[builder(default)]
     used: u64,
     /// Read-only property that identifies the amount of data accessible by a dataset, which might
     /// or might not be shared with other datasets in the pool.
+    #[builder(default)]
     referenced: u64,
     /// Read-only property that identifies the compression ratio achieved for a dataset, expressed
     /// as a multiplier.
+    #[builder(default)]
     compression_ratio: f64,
     /// Controls whether device files in a file system can be opened.
+    #[builder(default)]
     devices: bool,
     /// Controls whether programs in a file system allowed to be executed. Also, when set to
     /// `false`, `mmap(2)` calls with `PROT_EXEC` disallowed.
+    #[builder(default)]
     exec: bool,
     /// Controls whether the `setuid` bit is honored in a file system.
+    #[builder(default)]
     setuid: bool,
     /// Indicates whether extended attributes are enabled or disabled.
+    #[builder(default)]
     xattr: bool,
     /// Version (should 5)
+    #[builder(default)]
     version: u64,
     /// Indicates whether the file system should reject file names that include characters that are
     /// not present in the UTF-8 character code set. If this property is explicitly set to off, the
@@ -818,27 +1078,35 @@ pub struct SnapshotProperties {
     #[builder(default)]
     guid: Option<u64>,
     /// Controls what is cached in the primary cache (ARC).
+    #[builder(default)]
     primary_cache: CacheMode,
     /// Controls what is cached in the secondary cache (L2ARC).
+    #[builder(default)]
     secondary_cache: CacheMode,
     /// Snapshot marked for deferred destroy.
+    #[builder(default)]
     defer_destroy: bool,
     /// Number of holds on this snapshot.
+    #[builder(default)]
     user_refs: u64,
     /// Compression ratio achieved for the referenced space of this snapshot.
+    #[builder(default)]
     ref_compression_ratio: f64,
     /// The amount of referenced space written to this dataset since the previous snapshot.
+    #[builder(default)]
     written: u64,
     /// List of datasets which are clones of this snapshot.
     #[builder(default)]
     clones: Option<Vec<PathBuf>>,
     /// The amount of space that is "logically" accessible by this dataset.
+    #[builder(default)]
     logically_referenced: u64,
     /// Controls how the volume is exposed to the OS
     #[builder(default)]
     volume_mode: Option<VolumeMode>,
     /// Indicates whether the file name matching algorithm used by the file system should be
     /// case-sensitive, case-insensitive, or allow a combination of both styles of matching.
+    #[builder(default)]
     case_sensitivity: CaseSensitivity,
     /// [Security label](https://docs.oracle.com/cd/E23824_01/html/821-1482/managezones-18.html)
     #[builder(default)]
@@ -851,6 +1119,7 @@ pub struct SnapshotProperties {
     #[builder(default)]
     normalization: Normalization,
     /// User defined properties and properties this library failed to recognize.
+    #[builder(default)]
     unknown_properties: HashMap<String, String>,
 }
 
@@ -861,6 +1130,11 @@ impl SnapshotProperties {
         ret.name(name);
         ret
     }
+
+    /// Typed [`creation`](#method.creation).
+    pub fn creation_datetime(&self) -> Option<DateTime<Utc>> {
+        creation_datetime(self.creation)
+    }
 }
 
 impl SnapshotPropertiesBuilder {
@@ -874,6 +1148,7 @@ impl SnapshotPropertiesBuilder {
     }
 }
 
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[derive(Debug, Clone, PartialEq, Getters, Builder)]
 #[builder(derive(Debug))]
 #[get = "pub"]
@@ -897,6 +1172,11 @@ impl BookmarkProperties {
         ret.name(name);
         ret
     }
+
+    /// Typed [`creation`](#method.creation).
+    pub fn creation_datetime(&self) -> Option<DateTime<Utc>> {
+        creation_datetime(self.creation)
+    }
 }
 
 impl BookmarkPropertiesBuilder {
@@ -919,11 +1199,293 @@ pub enum Properties {
     Unknown(HashMap<String, String>),
 }
 
-impl_zfs_prop!(AclInheritMode, "aclinherit");
+impl Properties {
+    /// Parse output of `zfs get -Hp all <dataset>` captured elsewhere, e.g. on a remote machine.
+    /// Kind of the dataset is taken from `type`, the first property `zfs get all` prints.
+    pub fn parse_get_output(dataset: PathBuf, text: &str) -> Result<Properties, Error> {
+        crate::zfs::open3::parse_properties(text, dataset)
+    }
+}
+
+/// Where value of a property comes from. `SOURCE` column of `zfs get`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PropertySource {
+    /// Set on this dataset.
+    Local,
+    /// Not set anywhere, default value is used.
+    Default,
+    /// Inherited from given ancestor.
+    Inherited(PathBuf),
+    /// Set for the lifetime of the mount, e.g. `mount -o ro`.
+    Temporary,
+    /// Received with `zfs receive`.
+    Received,
+    /// Read-only or otherwise not settable property (`-`).
+    None,
+}
+
+impl FromStr for PropertySource {
+    type Err = Error;
+
+    fn from_str(source: &str) -> Result<PropertySource, Error> {
+        match source {
+            "local" => Ok(PropertySource::Local),
+            "default" => Ok(PropertySource::Default),
+            "temporary" => Ok(PropertySource::Temporary),
+            "received" => Ok(PropertySource::Received),
+            "-" | "" => Ok(PropertySource::None),
+            other => match other.strip_prefix("inherited from ") {
+                Some(parent) if !parent.is_empty() => {
+                    Ok(PropertySource::Inherited(PathBuf::from(parent)))
+                }
+                _ => Err(Error::UnknownSoFar(String::from(other))),
+            },
+        }
+    }
+}
+
+/// Type a single value printed by `zfs get -Hp -o value` converts into. See
+/// [`ZfsEngine::get_property`](../trait.ZfsEngine.html#method.get_property).
+///
+/// `Option` of any of these is `None` when property is unset (`-` or `none`).
+pub trait PropertyValue: Sized {
+    /// Convert raw `value` of `property`. Fails with `InvalidPropertyValue` naming both.
+    fn from_value(property: &str, value: &str) -> Result<Self, Error>;
+}
+
+fn invalid_value(property: &str, value: &str) -> Error {
+    Error::InvalidPropertyValue {
+        property: property.to_string(),
+        value: value.to_string(),
+    }
+}
+
+impl PropertyValue for u64 {
+    fn from_value(property: &str, value: &str) -> Result<Self, Error> {
+        value.parse().map_err(|_| invalid_value(property, value))
+    }
+}
+
+impl PropertyValue for u32 {
+    fn from_value(property: &str, value: &str) -> Result<Self, Error> {
+        value.parse().map_err(|_| invalid_value(property, value))
+    }
+}
+
+/// Ratios are accepted with `x` suffix as well, e.g. `1.50x`.
+impl PropertyValue for f64 {
+    fn from_value(property: &str, value: &str) -> Result<Self, Error> {
+        value
+            .strip_suffix('x')
+            .unwrap_or(value)
+            .parse()
+            .map_err(|_| invalid_value(property, value))
+    }
+}
+
+/// `on`/`off` of settable properties and `yes`/`no` of read-only ones like `mounted`.
+impl PropertyValue for bool {
+    fn from_value(property: &str, value: &str) -> Result<Self, Error> {
+        match value {
+            "on" | "yes" => Ok(true),
+            "off" | "no" => Ok(false),
+            _ => Err(invalid_value(property, value)),
+        }
+    }
+}
+
+impl PropertyValue for String {
+    fn from_value(_property: &str, value: &str) -> Result<Self, Error> {
+        Ok(String::from(value))
+    }
+}
+
+impl PropertyValue for PathBuf {
+    fn from_value(_property: &str, value: &str) -> Result<Self, Error> {
+        Ok(PathBuf::from(value))
+    }
+}
+
+impl<T: PropertyValue> PropertyValue for Option<T> {
+    fn from_value(property: &str, value: &str) -> Result<Self, Error> {
+        match value {
+            "-" | "none" | "" => Ok(None),
+            value => T::from_value(property, value).map(Some),
+        }
+    }
+}
+
+impl ZfsProp for AclInheritMode {
+    fn nv_key() -> &'static str {
+        "aclinherit"
+    }
+
+    // `secure` is an old name of `restricted`, so it can't be a discriminant.
+    fn as_nv_value(&self) -> NvValueRepr {
+        match self {
+            AclInheritMode::Secure => NvValueRepr::Index(AclInheritMode::Restricted as u64),
+            other => NvValueRepr::Index(*other as u64),
+        }
+    }
+}
+
 impl_zfs_prop!(AclMode, "aclmode");
 impl_zfs_prop!(CanMount, "canmount");
+impl_zfs_prop!(CaseSensitivity, "casesensitivity");
 impl_zfs_prop!(Checksum, "checksum");
 impl_zfs_prop!(Compression, "compression");
 impl_zfs_prop!(Copies, "copies");
+impl_zfs_prop!(Dedup, "dedup");
+impl_zfs_prop!(DnodeSize, "dnodesize");
+impl_zfs_prop!(LogBias, "logbias");
+impl_zfs_prop!(Normalization, "normalization");
+impl_zfs_prop!(RedundantMetadata, "redundant_metadata");
 impl_zfs_prop!(SnapDir, "snapdir");
-impl_zfs_prop!(VolumeMode, "volmod");
+impl_zfs_prop!(SnapDev, "snapdev");
+impl_zfs_prop!(SyncMode, "sync");
+impl_zfs_prop!(VolumeMode, "volmode");
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn index<P: ZfsProp>(prop: P) -> NvValueRepr {
+        prop.as_nv_value()
+    }
+
+    fn value<T: PropertyValue>(raw: &str) -> Result<T, Error> {
+        T::from_value("prop", raw)
+    }
+
+    fn invalid(raw: &str) -> Error {
+        Error::InvalidPropertyValue {
+            property: String::from("prop"),
+            value: String::from(raw),
+        }
+    }
+
+    #[test]
+    fn property_value_numbers() {
+        assert_eq!(
+            Ok(12_396_914_211_240_477_066u64),
+            value("12396914211240477066")
+        );
+        assert_eq!(Ok(0u64), value("0"));
+        assert_eq!(Err(invalid("-")), value::<u64>("-"));
+        assert_eq!(Err(invalid("1.5")), value::<u64>("1.5"));
+        assert_eq!(Err(invalid("-1")), value::<u64>("-1"));
+
+        assert_eq!(Ok(1.5f64), value("1.50"));
+        assert_eq!(Ok(1.5f64), value("1.50x"));
+        assert_eq!(Err(invalid("x")), value::<f64>("x"));
+        assert_eq!(Err(invalid("none")), value::<f64>("none"));
+    }
+
+    #[test]
+    fn property_value_bool() {
+        assert_eq!(Ok(true), value("on"));
+        assert_eq!(Ok(true), value("yes"));
+        assert_eq!(Ok(false), value("off"));
+        assert_eq!(Ok(false), value("no"));
+        assert_eq!(Err(invalid("noauto")), value::<bool>("noauto"));
+        assert_eq!(Err(invalid("-")), value::<bool>("-"));
+    }
+
+    #[test]
+    fn property_value_text() {
+        assert_eq!(Ok(String::from("lz4")), value("lz4"));
+        assert_eq!(Ok(String::from("two words")), value("two words"));
+        assert_eq!(Ok(String::from("-")), value("-"));
+        assert_eq!(Ok(PathBuf::from("/usr/home")), value("/usr/home"));
+        assert_eq!(Ok(PathBuf::from("none")), value("none"));
+    }
+
+    #[test]
+    fn property_value_unset() {
+        for sentinel in &["-", "none", ""] {
+            assert_eq!(Ok(None), value::<Option<u64>>(sentinel));
+            assert_eq!(Ok(None), value::<Option<f64>>(sentinel));
+            assert_eq!(Ok(None), value::<Option<bool>>(sentinel));
+            assert_eq!(Ok(None), value::<Option<String>>(sentinel));
+            assert_eq!(Ok(None), value::<Option<PathBuf>>(sentinel));
+        }
+        assert_eq!(Ok(Some(PathBuf::from("z/usr@base"))), value("z/usr@base"));
+        assert_eq!(Ok(Some(1024u64)), value("1024"));
+        assert_eq!(Ok(Some(false)), value("off"));
+        assert_eq!(Err(invalid("lots")), value::<Option<u64>>("lots"));
+    }
+
+    // Indexes from property tables in OpenZFS `zfs_prop.c`, `zio.h` and `zfs.h`.
+    #[test]
+    fn nv_values_match_kernel_tables() {
+        let acl_inherit = [
+            (AclInheritMode::Discard, 0),
+            (AclInheritMode::Noallow, 1),
+            (AclInheritMode::Restricted, 4),
+            (AclInheritMode::Secure, 4),
+            (AclInheritMode::Passthrough, 3),
+            (AclInheritMode::PassthroughX, 5),
+        ];
+        for (value, expected) in acl_inherit.iter() {
+            assert_eq!(NvValueRepr::Index(*expected), index(*value), "{}", value);
+        }
+        let checksum = [
+            (Checksum::On, 1),
+            (Checksum::Off, 2),
+            (Checksum::Fletcher2, 6),
+            (Checksum::Fletcher4, 7),
+            (Checksum::SHA256, 8),
+            (Checksum::NoParity, 10),
+            (Checksum::SHA512, 11),
+            (Checksum::Skein, 12),
+        ];
+        for (value, expected) in checksum.iter() {
+            assert_eq!(NvValueRepr::Index(*expected), index(*value), "{}", value);
+        }
+        let compression = [
+            (Compression::On, 1),
+            (Compression::Off, 2),
+            (Compression::LZJB, 3),
+            (Compression::Gzip1, 5),
+            (Compression::Gzip6, 10),
+            (Compression::Gzip9, 13),
+            (Compression::ZLE, 14),
+            (Compression::LZ4, 15),
+        ];
+        for (value, expected) in compression.iter() {
+            assert_eq!(NvValueRepr::Index(*expected), index(*value), "{}", value);
+        }
+        assert_eq!(NvValueRepr::Index(2), index(AclMode::GroupMask));
+        assert_eq!(NvValueRepr::Index(0), index(CanMount::Off));
+        assert_eq!(NvValueRepr::Index(2), index(CanMount::NoAuto));
+        assert_eq!(NvValueRepr::Index(3), index(Copies::Three));
+        assert_eq!(NvValueRepr::Index(2), index(CacheMode::All));
+        assert_eq!(NvValueRepr::Index(1), index(SnapDir::Visible));
+        assert_eq!(NvValueRepr::Index(3), index(VolumeMode::None));
+        assert_eq!("volmode", VolumeMode::nv_key());
+        let dedup = [
+            (Dedup::On, 1),
+            (Dedup::Off, 2),
+            (Dedup::Verify, 257),
+            (Dedup::SHA256, 8),
+            (Dedup::VerifySHA256, 264),
+            (Dedup::VerifySHA512, 267),
+            (Dedup::Skein, 12),
+        ];
+        for (value, expected) in dedup.iter() {
+            assert_eq!(NvValueRepr::Index(*expected), index(*value), "{}", value);
+        }
+        assert_eq!(NvValueRepr::Index(0), index(Normalization::None));
+        assert_eq!(NvValueRepr::Index(0x10), index(Normalization::FormD));
+        assert_eq!(NvValueRepr::Index(0x50), index(Normalization::FormC));
+        assert_eq!(NvValueRepr::Index(0x20), index(Normalization::FormKD));
+        assert_eq!(NvValueRepr::Index(0x60), index(Normalization::FormKC));
+        assert_eq!(NvValueRepr::Index(1), index(DnodeSize::Auto));
+        assert_eq!(NvValueRepr::Index(4096), index(DnodeSize::_4K));
+        assert_eq!(NvValueRepr::Index(1), index(CaseSensitivity::Insensitive));
+        assert_eq!(NvValueRepr::Index(2), index(SyncMode::Disabled));
+        assert_eq!(NvValueRepr::Index(1), index(LogBias::Throughput));
+        assert_eq!(NvValueRepr::Index(1), index(RedundantMetadata::Most));
+        assert_eq!("redundant_metadata", RedundantMetadata::nv_key());
+    }
+}
@@ -0,0 +1,455 @@
+//! Checks for replicated datasets.
+//!
+//! Snapshot names mean nothing after replication: a snapshot can be renamed or re-created with
+//! the same name and different content. What matters is `guid`, it's preserved by `zfs send` and
+//! `zfs receive`, so two snapshots with the same `guid` are the same snapshot.
+//!
+//! ### Usage
+//! ```rust,no_run
+//! use libzetta::zfs::{replication, DelegatingZfsEngine};
+//!
+//! let engine = DelegatingZfsEngine::new().unwrap();
+//! let common = replication::common_snapshots(&engine, "tank/home", &engine, "backup/home").unwrap();
+//! if let Some((source, dest)) = common.last() {
+//!     assert!(replication::verify_replication(&engine, source, &engine, dest).unwrap());
+//! }
+//! ```
+//!
+//! Before receiving a stream from elsewhere, [`validate_receive`](fn.validate_receive.html) tells
+//! whether it will apply cleanly to its destination and lists everything that would stop it.
+use std::{
+    collections::HashMap,
+    io::Read,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    zfs::{stream::inspect_stream, stream::StreamInfo, Error, PathExt, Result, ZfsEngine},
+    zpool::{FeatureState, ZpoolEngine, ZpoolErrorKind},
+};
+
+/// Why a stream won't apply cleanly to its destination.
+#[derive(Debug, PartialEq)]
+pub enum ReceiveProblem {
+    /// Stream is incremental, but destination doesn't exist or has no snapshots.
+    MissingBase {
+        /// GUID of the snapshot the stream is incremental from.
+        from_guid: u64,
+    },
+    /// Stream is incremental from a snapshot other than the newest snapshot of destination.
+    BaseMismatch {
+        /// GUID of the snapshot the stream is incremental from.
+        from_guid: u64,
+        /// Newest snapshot of destination.
+        latest: PathBuf,
+        /// GUID of that snapshot.
+        latest_guid: u64,
+    },
+    /// Destination pool doesn't have a feature the stream needs enabled.
+    MissingFeature {
+        /// Name of the feature without `feature@` prefix, e.g. `large_blocks`.
+        feature: String,
+        /// State of the feature. `None` if installed ZFS doesn't know it.
+        state: Option<FeatureState>,
+    },
+    /// Destination has a partially received stream. It has to be resumed or aborted first.
+    ReceiveInProgress {
+        /// `receive_resume_token` of destination.
+        token: String,
+    },
+    /// Destination couldn't be inspected, so some checks weren't done.
+    Lookup(Error),
+    /// Features of destination pool couldn't be read.
+    PoolLookup {
+        /// Name of the pool.
+        pool: String,
+        /// What went wrong.
+        kind: ZpoolErrorKind,
+    },
+}
+
+/// Whether `dest_snap` is the same snapshot as `source_snap`, that is has the same `guid`.
+pub fn verify_replication<S, D, N, M>(
+    source_engine: &S,
+    source_snap: N,
+    dest_engine: &D,
+    dest_snap: M,
+) -> Result<bool>
+where
+    S: ZfsEngine,
+    D: ZfsEngine,
+    N: Into<PathBuf>,
+    M: Into<PathBuf>,
+{
+    let source = source_engine.snapshot_guid(source_snap)?;
+    let dest = dest_engine.snapshot_guid(dest_snap)?;
+    Ok(source == dest)
+}
+
+/// Snapshots `source_ds` and `dest_ds` have in common, matched by `guid`. Each pair is a snapshot
+/// of `source_ds` and the same snapshot of `dest_ds`, ordered by `createtxg` on the source side.
+/// The last pair is the newest snapshot an incremental stream can start from.
+pub fn common_snapshots<S, D, N, M>(
+    source_engine: &S,
+    source_ds: N,
+    dest_engine: &D,
+    dest_ds: M,
+) -> Result<Vec<(PathBuf, PathBuf)>>
+where
+    S: ZfsEngine,
+    D: ZfsEngine,
+    N: Into<PathBuf>,
+    M: Into<PathBuf>,
+{
+    let mut dest: HashMap<u64, PathBuf> = dest_engine
+        .list_snapshots_with_guids(dest_ds)?
+        .into_iter()
+        .map(|(snapshot, guid)| (guid, snapshot))
+        .collect();
+    let ret = source_engine
+        .list_snapshots_with_guids(source_ds)?
+        .into_iter()
+        .filter_map(|(snapshot, guid)| dest.remove(&guid).map(|dest| (snapshot, dest)))
+        .collect();
+    Ok(ret)
+}
+
+/// Whether stream described by `stream_info` will apply cleanly to `dest_dataset`, given features
+/// of destination pool. All checks are done and every problem found is returned:
+///  * every pool feature the stream needs is enabled or active,
+///  * newest snapshot of destination is the one incremental stream starts from,
+///  * destination isn't in the middle of a resumable receive.
+///
+/// Nothing is written, so this is fine to run against production destinations.
+pub fn validate_receive<E, N>(
+    engine: &E,
+    dest_dataset: N,
+    stream_info: &StreamInfo,
+    pool_features: &HashMap<String, FeatureState>,
+) -> std::result::Result<(), Vec<ReceiveProblem>>
+where
+    E: ZfsEngine,
+    N: Into<PathBuf>,
+{
+    let dest = dest_dataset.into();
+    let mut problems = Vec::new();
+    for feature in stream_info.features().required_pool_features() {
+        let state = pool_features.get(feature).copied();
+        if !state.is_some_and(|state| state.is_usable()) {
+            problems.push(ReceiveProblem::MissingFeature {
+                feature: String::from(feature),
+                state,
+            });
+        }
+    }
+    match engine.exists(dest.clone()) {
+        Ok(true) => {
+            if let Some(from_guid) = *stream_info.from_guid() {
+                if let Some(problem) = check_base(engine, &dest, from_guid) {
+                    problems.push(problem);
+                }
+            }
+            match engine.get_property::<_, Option<String>>(dest, "receive_resume_token") {
+                Ok(Some(token)) => problems.push(ReceiveProblem::ReceiveInProgress { token }),
+                Ok(None) => {}
+                Err(e) => problems.push(ReceiveProblem::Lookup(e)),
+            }
+        }
+        Ok(false) => {
+            if let Some(from_guid) = *stream_info.from_guid() {
+                problems.push(ReceiveProblem::MissingBase { from_guid });
+            }
+        }
+        Err(e) => problems.push(ReceiveProblem::Lookup(e)),
+    }
+    if problems.is_empty() {
+        Ok(())
+    } else {
+        Err(problems)
+    }
+}
+
+/// Same as [`validate_receive`](fn.validate_receive.html), but reads stream header from `reader`
+/// and features of destination pool from `zpool`. Returns every problem found, empty if the stream
+/// should apply cleanly. Header is consumed, so `reader` can't be received from afterwards.
+///
+/// Fails only if `reader` doesn't start with a valid stream header.
+pub fn check_receive<E, Z, N, R>(
+    engine: &E,
+    zpool: &Z,
+    dest_dataset: N,
+    reader: R,
+) -> Result<Vec<ReceiveProblem>>
+where
+    E: ZfsEngine,
+    Z: ZpoolEngine,
+    N: Into<PathBuf>,
+    R: Read,
+{
+    let dest = dest_dataset.into();
+    let stream_info = inspect_stream(reader)?;
+    let mut problems = Vec::new();
+    let features = match zpool.read_properties(dest.pool()) {
+        Ok(props) => props.features().clone(),
+        Err(e) => {
+            problems.push(ReceiveProblem::PoolLookup {
+                pool: String::from(dest.pool()),
+                kind: e.kind(),
+            });
+            HashMap::new()
+        }
+    };
+    if let Err(found) = validate_receive(engine, dest, &stream_info, &features) {
+        problems.extend(found);
+    }
+    Ok(problems)
+}
+
+fn check_base<E: ZfsEngine>(engine: &E, dest: &Path, from_guid: u64) -> Option<ReceiveProblem> {
+    let latest = match engine.latest_snapshot(dest) {
+        Ok(Some(latest)) => latest,
+        Ok(None) => return Some(ReceiveProblem::MissingBase { from_guid }),
+        Err(e) => return Some(ReceiveProblem::Lookup(e)),
+    };
+    match engine.snapshot_guid(latest.clone()) {
+        Ok(latest_guid) if latest_guid == from_guid => None,
+        Ok(latest_guid) => Some(ReceiveProblem::BaseMismatch {
+            from_guid,
+            latest,
+            latest_guid,
+        }),
+        Err(e) => Some(ReceiveProblem::Lookup(e)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::zfs::stream::StreamFeatures;
+
+    /// Engine that knows snapshots of a single dataset.
+    struct Snapshots(Vec<(&'static str, u64)>);
+
+    impl ZfsEngine for Snapshots {
+        fn snapshot_guid<N: Into<PathBuf>>(&self, snapshot: N) -> Result<u64> {
+            let snapshot = snapshot.into();
+            self.0
+                .iter()
+                .find(|(name, _)| PathBuf::from(name) == snapshot)
+                .map(|(_, guid)| *guid)
+                .ok_or(Error::DatasetNotFound(snapshot))
+        }
+
+        fn list_snapshots_with_guids<N: Into<PathBuf>>(
+            &self,
+            _dataset: N,
+        ) -> Result<Vec<(PathBuf, u64)>> {
+            Ok(self
+                .0
+                .iter()
+                .map(|(name, guid)| (PathBuf::from(name), *guid))
+                .collect())
+        }
+    }
+
+    fn source() -> Snapshots {
+        Snapshots(vec![
+            ("tank/home@a", 1),
+            ("tank/home@b", 2),
+            ("tank/home@c", 3),
+            ("tank/home@d", 4),
+        ])
+    }
+
+    fn dest() -> Snapshots {
+        // `b` is renamed, `c` is re-created locally and `x` never existed on source.
+        Snapshots(vec![
+            ("backup/home@a", 1),
+            ("backup/home@renamed", 2),
+            ("backup/home@c", 30),
+            ("backup/home@x", 5),
+            ("backup/home@d", 4),
+        ])
+    }
+
+    #[test]
+    fn verify_by_guid() {
+        let (source, dest) = (source(), dest());
+        assert_eq!(
+            Ok(true),
+            verify_replication(&source, "tank/home@b", &dest, "backup/home@renamed")
+        );
+        assert_eq!(
+            Ok(false),
+            verify_replication(&source, "tank/home@c", &dest, "backup/home@c")
+        );
+        assert!(verify_replication(&source, "tank/home@z", &dest, "backup/home@c").is_err());
+    }
+
+    #[test]
+    fn common_in_source_order() {
+        let common = common_snapshots(&source(), "tank/home", &dest(), "backup/home").unwrap();
+        let expected: Vec<(PathBuf, PathBuf)> = vec![
+            ("tank/home@a", "backup/home@a"),
+            ("tank/home@b", "backup/home@renamed"),
+            ("tank/home@d", "backup/home@d"),
+        ]
+        .into_iter()
+        .map(|(source, dest)| (PathBuf::from(source), PathBuf::from(dest)))
+        .collect();
+        assert_eq!(expected, common);
+
+        let empty = Snapshots(Vec::new());
+        assert_eq!(
+            Ok(Vec::new()),
+            common_snapshots(&source(), "tank/home", &empty, "backup/home")
+        );
+    }
+
+    /// Destination side of a receive: datasets with their snapshots and resume tokens.
+    struct Dest(Vec<(&'static str, Vec<(&'static str, u64)>, &'static str)>);
+
+    impl Dest {
+        fn dataset(
+            &self,
+            name: &Path,
+        ) -> Option<&(&'static str, Vec<(&'static str, u64)>, &'static str)> {
+            self.0
+                .iter()
+                .find(|(dataset, _, _)| Path::new(dataset) == name)
+        }
+    }
+
+    impl ZfsEngine for Dest {
+        fn exists<N: Into<PathBuf>>(&self, name: N) -> Result<bool> {
+            let name = name.into();
+            if name == Path::new("backup/broken") {
+                return Err(Error::Io(std::io::Error::from_raw_os_error(libc::EIO)));
+            }
+            Ok(self.dataset(&name).is_some())
+        }
+
+        fn latest_snapshot<N: Into<PathBuf>>(&self, dataset: N) -> Result<Option<PathBuf>> {
+            let dataset = dataset.into();
+            let (_, snapshots, _) = self.dataset(&dataset).unwrap();
+            Ok(snapshots.last().map(|(name, _)| PathBuf::from(name)))
+        }
+
+        fn snapshot_guid<N: Into<PathBuf>>(&self, snapshot: N) -> Result<u64> {
+            let snapshot = snapshot.into();
+            self.0
+                .iter()
+                .flat_map(|(_, snapshots, _)| snapshots)
+                .find(|(name, _)| Path::new(name) == snapshot)
+                .map(|(_, guid)| *guid)
+                .ok_or(Error::DatasetNotFound(snapshot))
+        }
+
+        fn get_property<N: Into<PathBuf>, T: crate::zfs::PropertyValue>(
+            &self,
+            dataset: N,
+            property: &str,
+        ) -> Result<T> {
+            assert_eq!("receive_resume_token", property);
+            let dataset = dataset.into();
+            let (_, _, token) = self.dataset(&dataset).unwrap();
+            T::from_value(property, token)
+        }
+    }
+
+    fn dest_side() -> Dest {
+        Dest(vec![
+            (
+                "backup/home",
+                vec![("backup/home@a", 1), ("backup/home@b", 2)],
+                "-",
+            ),
+            ("backup/empty", Vec::new(), "-"),
+            (
+                "backup/partial",
+                vec![("backup/partial@a", 1)],
+                "1-e604ea4bf-e0",
+            ),
+        ])
+    }
+
+    fn features(enabled: &[(&str, FeatureState)]) -> HashMap<String, FeatureState> {
+        enabled
+            .iter()
+            .map(|(name, state)| (String::from(*name), *state))
+            .collect()
+    }
+
+    #[test]
+    fn receive_applies_cleanly() {
+        let pool = features(&[
+            ("large_blocks", FeatureState::Active),
+            ("embedded_data", FeatureState::Enabled),
+        ]);
+        let flags = StreamFeatures::DMU_BACKUP_FEATURE_LARGE_BLOCKS
+            | StreamFeatures::DMU_BACKUP_FEATURE_EMBED_DATA;
+        let incremental = StreamInfo::substream("tank/home@c", 3, Some(2), flags);
+        assert_eq!(
+            Ok(()),
+            validate_receive(&dest_side(), "backup/home", &incremental, &pool)
+        );
+        let full = StreamInfo::substream("tank/home@c", 3, None, flags);
+        assert_eq!(
+            Ok(()),
+            validate_receive(&dest_side(), "backup/new", &full, &pool)
+        );
+    }
+
+    #[test]
+    fn receive_problems_are_all_reported() {
+        let pool = features(&[("large_blocks", FeatureState::Disabled)]);
+        let flags = StreamFeatures::DMU_BACKUP_FEATURE_LARGE_BLOCKS
+            | StreamFeatures::DMU_BACKUP_FEATURE_EMBED_DATA;
+        let stream = StreamInfo::substream("tank/home@c", 3, Some(1), flags);
+
+        let expected = vec![
+            ReceiveProblem::MissingFeature {
+                feature: String::from("embedded_data"),
+                state: None,
+            },
+            ReceiveProblem::MissingFeature {
+                feature: String::from("large_blocks"),
+                state: Some(FeatureState::Disabled),
+            },
+            ReceiveProblem::BaseMismatch {
+                from_guid: 1,
+                latest: PathBuf::from("backup/home@b"),
+                latest_guid: 2,
+            },
+        ];
+        assert_eq!(
+            Err(expected),
+            validate_receive(&dest_side(), "backup/home", &stream, &pool)
+        );
+
+        let pool = features(&[
+            ("large_blocks", FeatureState::Enabled),
+            ("embedded_data", FeatureState::Enabled),
+        ]);
+        assert_eq!(
+            Err(vec![ReceiveProblem::ReceiveInProgress {
+                token: String::from("1-e604ea4bf-e0")
+            }]),
+            validate_receive(&dest_side(), "backup/partial", &stream, &pool)
+        );
+        let missing_base = Err(vec![ReceiveProblem::MissingBase { from_guid: 1 }]);
+        assert_eq!(
+            missing_base,
+            validate_receive(&dest_side(), "backup/empty", &stream, &pool)
+        );
+        assert_eq!(
+            missing_base,
+            validate_receive(&dest_side(), "backup/new", &stream, &pool)
+        );
+        let lookup = validate_receive(&dest_side(), "backup/broken", &stream, &pool);
+        assert!(matches!(
+            lookup.unwrap_err().as_slice(),
+            [ReceiveProblem::Lookup(_)]
+        ));
+    }
+}
@@ -0,0 +1,384 @@
+//! Grandfather-father-son retention of snapshots.
+//!
+//! [`plan`](fn.plan.html) decides which snapshots to keep and which to destroy according to
+//! [`RetentionPolicy`](struct.RetentionPolicy.html). It only looks at names and creation times, so
+//! it never touches the system. [`apply`](fn.apply.html) then destroys what the plan says.
+//!
+//! Policy follows the same rules as most backup tools: for every period (hour, day, week, month,
+//! year) the newest snapshot of each of the last N periods that have snapshots is kept. Periods
+//! are calendar periods in local time, weeks are ISO weeks.
+//!
+//! ### Usage
+//! ```rust,no_run
+//! use libzetta::zfs::{
+//!     retention::{self, RetentionPolicy},
+//!     DelegatingZfsEngine, DestroyTiming, Properties, ZfsEngine,
+//! };
+//!
+//! let engine = DelegatingZfsEngine::new().unwrap();
+//! let mut snapshots = Vec::new();
+//! for snapshot in engine.list_snapshots("tank/usr/home").unwrap() {
+//!     if let Properties::Snapshot(props) = engine.read_properties(&snapshot).unwrap() {
+//!         snapshots.push((snapshot, *props.creation()));
+//!     }
+//! }
+//! let policy = RetentionPolicy::builder()
+//!     .keep_daily(7)
+//!     .keep_weekly(4)
+//!     .keep_monthly(12)
+//!     .build()
+//!     .unwrap();
+//! let plan = retention::plan(&snapshots, &policy);
+//! retention::apply(&engine, &plan, 32, DestroyTiming::RightNow).unwrap();
+//! ```
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use chrono::{Datelike, Local, TimeZone, Timelike};
+use regex::Regex;
+
+use crate::zfs::{DestroyTiming, Result, ZfsEngine};
+
+/// What to keep. Counts that are zero are not used. Policy without any `keep_*` rule keeps every
+/// snapshot.
+#[derive(Default, Builder, Debug, Clone, Getters)]
+#[get = "pub"]
+pub struct RetentionPolicy {
+    /// Keep this many newest snapshots.
+    #[builder(default)]
+    keep_last: usize,
+    /// Keep the newest snapshot of this many last hours.
+    #[builder(default)]
+    keep_hourly: usize,
+    /// Keep the newest snapshot of this many last days.
+    #[builder(default)]
+    keep_daily: usize,
+    /// Keep the newest snapshot of this many last weeks.
+    #[builder(default)]
+    keep_weekly: usize,
+    /// Keep the newest snapshot of this many last months.
+    #[builder(default)]
+    keep_monthly: usize,
+    /// Keep the newest snapshot of this many last years.
+    #[builder(default)]
+    keep_yearly: usize,
+    /// Never destroy snapshots younger than this.
+    #[builder(default)]
+    min_age: Option<Duration>,
+    /// Only look at snapshots whose name (part after `@`) matches. Other snapshots are kept.
+    #[builder(default)]
+    name_filter: Option<Regex>,
+}
+
+impl RetentionPolicy {
+    /// A preferred way to create this.
+    pub fn builder() -> RetentionPolicyBuilder {
+        RetentionPolicyBuilder::default()
+    }
+
+    fn has_rules(&self) -> bool {
+        self.keep_last > 0
+            || self.keep_hourly > 0
+            || self.keep_daily > 0
+            || self.keep_weekly > 0
+            || self.keep_monthly > 0
+            || self.keep_yearly > 0
+    }
+
+    fn matches(&self, snapshot: &Path) -> bool {
+        match self.name_filter {
+            Some(ref filter) => {
+                let name = snapshot.to_string_lossy();
+                let name = name.rsplit('@').next().unwrap_or_default();
+                filter.is_match(name)
+            }
+            None => true,
+        }
+    }
+}
+
+/// Outcome of [`plan`](fn.plan.html). Both lists are in the same order as the input.
+#[derive(Default, Debug, Clone, PartialEq, Eq, Getters)]
+#[get = "pub"]
+pub struct RetentionPlan {
+    /// Snapshots to keep.
+    keep: Vec<PathBuf>,
+    /// Snapshots to destroy.
+    destroy: Vec<PathBuf>,
+}
+
+/// Decide what to keep using current time and local time zone.
+///
+/// * `snapshots` - Snapshots along with their creation time in seconds since epoch.
+/// * `policy` - What to keep.
+pub fn plan(snapshots: &[(PathBuf, i64)], policy: &RetentionPolicy) -> RetentionPlan {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or_default();
+    plan_at(snapshots, policy, now, &Local)
+}
+
+/// Key of the period a snapshot was made in, snapshots with equal keys are in the same period.
+type PeriodOf<Tz> = fn(&chrono::DateTime<Tz>) -> (i32, u32, u32);
+
+/// Same as [`plan`](fn.plan.html), but with given current time (seconds since epoch) and time
+/// zone periods are calculated in.
+pub fn plan_at<Tz: TimeZone>(
+    snapshots: &[(PathBuf, i64)],
+    policy: &RetentionPolicy,
+    now: i64,
+    tz: &Tz,
+) -> RetentionPlan {
+    if !policy.has_rules() {
+        return RetentionPlan {
+            keep: snapshots.iter().map(|(s, _)| s.clone()).collect(),
+            destroy: Vec::new(),
+        };
+    }
+
+    let mut candidates: Vec<&(PathBuf, i64)> = snapshots
+        .iter()
+        .filter(|(s, _)| policy.matches(s))
+        .collect();
+    // Newest first, ties are broken by name to keep result stable.
+    candidates.sort_by(|(a_name, a), (b_name, b)| b.cmp(a).then_with(|| b_name.cmp(a_name)));
+
+    let mut keep: HashSet<&PathBuf> = candidates
+        .iter()
+        .take(policy.keep_last)
+        .map(|(s, _)| s)
+        .collect();
+
+    let periods: [(usize, PeriodOf<Tz>); 5] = [
+        (policy.keep_hourly, |t| (t.year(), t.ordinal(), t.hour())),
+        (policy.keep_daily, |t| (t.year(), t.ordinal(), 0)),
+        (policy.keep_weekly, |t| {
+            let week = t.iso_week();
+            (week.year(), week.week(), 0)
+        }),
+        (policy.keep_monthly, |t| (t.year(), t.month(), 0)),
+        (policy.keep_yearly, |t| (t.year(), 0, 0)),
+    ];
+    for (count, period_of) in periods.iter() {
+        let mut seen = HashSet::new();
+        for (snapshot, creation) in &candidates {
+            if seen.len() == *count {
+                break;
+            }
+            let period = match tz.timestamp_opt(*creation, 0).earliest() {
+                Some(time) => period_of(&time),
+                None => continue,
+            };
+            if seen.insert(period) {
+                keep.insert(snapshot);
+            }
+        }
+    }
+
+    if let Some(min_age) = policy.min_age {
+        let min_age = min_age.as_secs() as i64;
+        keep.extend(
+            candidates
+                .iter()
+                .filter(|(_, creation)| now - creation < min_age)
+                .map(|(s, _)| s),
+        );
+    }
+
+    let mut plan = RetentionPlan::default();
+    for (snapshot, _) in snapshots {
+        if !policy.matches(snapshot) || keep.contains(snapshot) {
+            plan.keep.push(snapshot.clone());
+        } else {
+            plan.destroy.push(snapshot.clone());
+        }
+    }
+    plan
+}
+
+/// Destroy snapshots the plan says to destroy, `chunk_size` snapshots per
+/// [`destroy_snapshots`](../trait.ZfsEngine.html#method.destroy_snapshots) call. Stops at the
+/// first failed chunk, earlier chunks stay destroyed.
+pub fn apply<Z: ZfsEngine>(
+    engine: &Z,
+    plan: &RetentionPlan,
+    chunk_size: usize,
+    timing: DestroyTiming,
+) -> Result<()> {
+    for chunk in plan.destroy.chunks(chunk_size.max(1)) {
+        engine.destroy_snapshots(chunk, timing.clone())?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::{FixedOffset, NaiveDate, Utc};
+    use std::cell::RefCell;
+
+    const HOUR: i64 = 60 * 60;
+    const DAY: i64 = 24 * HOUR;
+
+    fn at(y: i32, m: u32, d: u32, h: u32) -> i64 {
+        NaiveDate::from_ymd_opt(y, m, d)
+            .unwrap()
+            .and_hms_opt(h, 0, 0)
+            .unwrap()
+            .and_utc()
+            .timestamp()
+    }
+
+    fn snapshots(times: &[i64]) -> Vec<(PathBuf, i64)> {
+        times
+            .iter()
+            .enumerate()
+            .map(|(i, t)| (PathBuf::from(format!("tank/home@auto-{}", i)), *t))
+            .collect()
+    }
+
+    fn names(paths: &[PathBuf]) -> Vec<String> {
+        paths
+            .iter()
+            .map(|p| p.to_string_lossy().rsplit('@').next().unwrap().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn no_rules_keeps_everything() {
+        let snaps = snapshots(&[at(2020, 1, 1, 0), at(2020, 1, 2, 0)]);
+        let plan = plan_at(&snaps, &RetentionPolicy::default(), 0, &Utc);
+        assert_eq!(2, plan.keep().len());
+        assert!(plan.destroy().is_empty());
+    }
+
+    #[test]
+    fn keep_last() {
+        let snaps = snapshots(&[at(2020, 1, 1, 0), at(2020, 1, 3, 0), at(2020, 1, 2, 0)]);
+        let policy = RetentionPolicy::builder().keep_last(2).build().unwrap();
+        let plan = plan_at(&snaps, &policy, 0, &Utc);
+        assert_eq!(vec!["auto-1", "auto-2"], names(plan.keep()));
+        assert_eq!(vec!["auto-0"], names(plan.destroy()));
+    }
+
+    #[test]
+    fn daily_keeps_newest_of_each_day() {
+        // Every 6 hours for 5 days.
+        let times: Vec<i64> = (0..20).map(|i| at(2020, 3, 1, 0) + i * 6 * HOUR).collect();
+        let snaps = snapshots(&times);
+        let policy = RetentionPolicy::builder().keep_daily(3).build().unwrap();
+        let plan = plan_at(&snaps, &policy, 0, &Utc);
+        // 18:00 of the last three days.
+        assert_eq!(vec!["auto-11", "auto-15", "auto-19"], names(plan.keep()));
+        assert_eq!(17, plan.destroy().len());
+    }
+
+    #[test]
+    fn gfs() {
+        // One snapshot a day for two years.
+        let times: Vec<i64> = (0..730).map(|i| at(2019, 1, 1, 12) + i * DAY).collect();
+        let snaps = snapshots(&times);
+        let policy = RetentionPolicy::builder()
+            .keep_daily(7)
+            .keep_weekly(4)
+            .keep_monthly(12)
+            .keep_yearly(5)
+            .build()
+            .unwrap();
+        let plan = plan_at(&snaps, &policy, 0, &Utc);
+        let kept: Vec<i64> = snaps
+            .iter()
+            .filter(|(s, _)| plan.keep().contains(s))
+            .map(|(_, t)| *t)
+            .collect();
+        let last = at(2020, 12, 30, 12);
+        // Last 7 days.
+        assert!((0..7).all(|i| kept.contains(&(last - i * DAY))));
+        // Sunday of the last 4 ISO weeks (2020-12-30 is Wednesday).
+        for sunday in &[
+            at(2020, 12, 27, 12),
+            at(2020, 12, 20, 12),
+            at(2020, 12, 13, 12),
+        ] {
+            assert!(kept.contains(sunday));
+        }
+        // Last day of the last 12 months and of both years.
+        assert!(kept.contains(&at(2020, 1, 31, 12)));
+        assert!(!kept.contains(&at(2019, 12, 30, 12)));
+        assert!(kept.contains(&at(2019, 12, 31, 12)));
+        // Sunday of the week before is already kept as a daily snapshot, the same goes for the
+        // last day of the last month and year.
+        assert_eq!(7 + 2 + 11 + 1, kept.len());
+        assert_eq!(snaps.len(), plan.keep().len() + plan.destroy().len());
+    }
+
+    #[test]
+    fn periods_follow_time_zone() {
+        // 23:00 and 01:00 UTC are different days in UTC, but the same day at UTC+3.
+        let snaps = snapshots(&[at(2020, 6, 1, 23), at(2020, 6, 2, 1)]);
+        let policy = RetentionPolicy::builder().keep_daily(2).build().unwrap();
+        let plan = plan_at(&snaps, &policy, 0, &Utc);
+        assert_eq!(2, plan.keep().len());
+
+        let tz = FixedOffset::east_opt(3 * 3600).unwrap();
+        let plan = plan_at(&snaps, &policy, 0, &tz);
+        assert_eq!(vec!["auto-1"], names(plan.keep()));
+        assert_eq!(vec!["auto-0"], names(plan.destroy()));
+    }
+
+    #[test]
+    fn hourly_keeps_newest_of_each_hour() {
+        let start = at(2020, 11, 1, 1);
+        let snaps = snapshots(&[start, start + 10 * 60, start + 50 * 60, start + 70 * 60]);
+        let policy = RetentionPolicy::builder().keep_hourly(24).build().unwrap();
+        let plan = plan_at(&snaps, &policy, 0, &Utc);
+        assert_eq!(vec!["auto-2", "auto-3"], names(plan.keep()));
+        // Half an hour offset moves the boundary.
+        let tz = FixedOffset::east_opt(30 * 60).unwrap();
+        let plan = plan_at(&snaps, &policy, 0, &tz);
+        assert_eq!(vec!["auto-1", "auto-3"], names(plan.keep()));
+    }
+
+    #[test]
+    fn min_age_and_name_filter() {
+        let now = at(2020, 1, 10, 0);
+        let mut snaps = snapshots(&[now - 3 * DAY, now - 2 * DAY, now - HOUR]);
+        snaps.push((PathBuf::from("tank/home@manual"), now - 5 * DAY));
+        let policy = RetentionPolicy::builder()
+            .keep_last(1)
+            .min_age(Some(Duration::from_secs(2 * DAY as u64 + 1)))
+            .name_filter(Some(Regex::new("^auto-").unwrap()))
+            .build()
+            .unwrap();
+        let plan = plan_at(&snaps, &policy, now, &Utc);
+        assert_eq!(vec!["auto-1", "auto-2", "manual"], names(plan.keep()));
+        assert_eq!(vec!["auto-0"], names(plan.destroy()));
+    }
+
+    struct Recorder(RefCell<Vec<Vec<PathBuf>>>);
+
+    impl ZfsEngine for Recorder {
+        fn destroy_snapshots(&self, snapshots: &[PathBuf], _timing: DestroyTiming) -> Result<()> {
+            self.0.borrow_mut().push(snapshots.to_vec());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn apply_in_chunks() {
+        let times: Vec<i64> = (0..5).map(|i| i * DAY).collect();
+        let snaps = snapshots(&times);
+        let policy = RetentionPolicy::builder().keep_last(1).build().unwrap();
+        let plan = plan_at(&snaps, &policy, 0, &Utc);
+        let engine = Recorder(RefCell::new(Vec::new()));
+        apply(&engine, &plan, 3, DestroyTiming::RightNow).unwrap();
+        let calls = engine.0.into_inner();
+        assert_eq!(2, calls.len());
+        assert_eq!(vec!["auto-0", "auto-1", "auto-2"], names(&calls[0]));
+        assert_eq!(vec!["auto-3"], names(&calls[1]));
+    }
+}
@@ -0,0 +1,427 @@
+//! What's inside of a send stream, without receiving it.
+//!
+//! Every stream made by `zfs send` starts with a `DRR_BEGIN` record of `dmu_replay_record_t`: name
+//! and GUID of the snapshot, GUID of the snapshot it's incremental from and which features the
+//! stream uses. [`inspect_stream`](fn.inspect_stream.html) reads and checks that record and its
+//! payload only, the rest of the stream is left in the reader. Records are written in byte order
+//! of the sending host, both orders are understood.
+//!
+//! ### Usage
+//! ```rust,no_run
+//! use libzetta::zfs::stream::inspect_stream;
+//! use std::fs::File;
+//!
+//! let mut file = File::open("/backup/tank@monday.zfs").unwrap();
+//! let info = inspect_stream(&mut file).unwrap();
+//! println!("{} (incremental: {})", info.snapshot().display(), info.is_incremental());
+//! ```
+use std::{
+    io::{self, Read},
+    path::PathBuf,
+};
+
+use bitflags::bitflags;
+
+use crate::zfs::{DatasetKind, Error, Result};
+
+/// `DMU_BACKUP_MAGIC`.
+const DMU_BACKUP_MAGIC: u64 = 0x2_f5ba_cbac;
+/// Size of `dmu_replay_record_t`.
+const RECORD_LEN: usize = 312;
+/// `DRR_BEGIN` of `drr_type`.
+const DRR_BEGIN: u32 = 0;
+/// Offset of `drr_toname`, it runs to the end of the record.
+const TONAME_OFFSET: usize = 56;
+/// Longest payload of `DRR_BEGIN` that is accepted, `drr_payloadlen` comes from the stream.
+const MAX_PAYLOAD_LEN: u32 = 16 << 20;
+/// `DRR_FLAG_CLONE` of `drr_flags`.
+const DRR_FLAG_CLONE: u32 = 1 << 0;
+/// `DMU_OST_ZFS` and `DMU_OST_ZVOL` of `dmu_objset_type_t`.
+const DMU_OST_ZFS: u32 = 2;
+const DMU_OST_ZVOL: u32 = 3;
+
+bitflags! {
+    /// `DMU_BACKUP_FEATURE_*` from `zfs_ioctl.h`: what a receiving side has to support. Bits
+    /// unknown to this crate are dropped.
+    #[derive(Default)]
+    pub struct StreamFeatures: u32 {
+        const DMU_BACKUP_FEATURE_DEDUP = 1 << 0;
+        const DMU_BACKUP_FEATURE_DEDUPPROPS = 1 << 1;
+        const DMU_BACKUP_FEATURE_SA_SPILL = 1 << 2;
+        const DMU_BACKUP_FEATURE_EMBED_DATA = 1 << 16;
+        const DMU_BACKUP_FEATURE_LZ4 = 1 << 17;
+        const DMU_BACKUP_FEATURE_LARGE_BLOCKS = 1 << 19;
+        const DMU_BACKUP_FEATURE_RESUMING = 1 << 20;
+        const DMU_BACKUP_FEATURE_REDACTED = 1 << 21;
+        const DMU_BACKUP_FEATURE_COMPRESSED = 1 << 22;
+        const DMU_BACKUP_FEATURE_LARGE_DNODE = 1 << 23;
+        const DMU_BACKUP_FEATURE_RAW = 1 << 24;
+        const DMU_BACKUP_FEATURE_ZSTD = 1 << 25;
+        const DMU_BACKUP_FEATURE_HOLDS = 1 << 26;
+    }
+}
+
+/// Stream features that the receiving pool needs a feature (`feature@<name>`) enabled for.
+const POOL_FEATURES: [(StreamFeatures, &str); 7] = [
+    (
+        StreamFeatures::DMU_BACKUP_FEATURE_EMBED_DATA,
+        "embedded_data",
+    ),
+    (StreamFeatures::DMU_BACKUP_FEATURE_LZ4, "lz4_compress"),
+    (
+        StreamFeatures::DMU_BACKUP_FEATURE_LARGE_BLOCKS,
+        "large_blocks",
+    ),
+    (
+        StreamFeatures::DMU_BACKUP_FEATURE_REDACTED,
+        "redacted_datasets",
+    ),
+    (
+        StreamFeatures::DMU_BACKUP_FEATURE_LARGE_DNODE,
+        "large_dnode",
+    ),
+    (StreamFeatures::DMU_BACKUP_FEATURE_RAW, "encryption"),
+    (StreamFeatures::DMU_BACKUP_FEATURE_ZSTD, "zstd_compress"),
+];
+
+impl StreamFeatures {
+    /// Names of pool features the receiving pool must have enabled, e.g. `large_blocks`.
+    pub fn required_pool_features(&self) -> Vec<&'static str> {
+        POOL_FEATURES
+            .iter()
+            .filter(|(flag, _)| self.contains(*flag))
+            .map(|(_, feature)| *feature)
+            .collect()
+    }
+}
+
+/// Header type of the stream (`DMU_GET_STREAM_HDRTYPE`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StreamKind {
+    /// Stream of a single snapshot (`DMU_SUBSTREAM`).
+    Substream,
+    /// Replication stream (`zfs send -R`, `DMU_COMPOUNDSTREAM`): the header is followed by an
+    /// nvlist describing datasets and then by a substream for every snapshot.
+    Compound,
+}
+
+/// Header of a send stream.
+#[derive(Debug, Clone, Getters, PartialEq, Eq)]
+#[get = "pub"]
+pub struct StreamInfo {
+    /// Snapshot the stream recreates, as it was named on the sending side.
+    snapshot: PathBuf,
+    /// GUID of that snapshot.
+    to_guid: u64,
+    /// GUID of the snapshot the stream is incremental from. Always `None` for compound
+    /// streams: their header doesn't carry it.
+    from_guid: Option<u64>,
+    /// When the snapshot was created, seconds since epoch.
+    creation: i64,
+    /// File system or volume. `None` for compound streams and unknown types.
+    dataset_kind: Option<DatasetKind>,
+    /// Single snapshot or replication stream.
+    kind: StreamKind,
+    /// Features receiving side has to support.
+    features: StreamFeatures,
+    /// Stream was made from a clone and is incremental from its origin (`DRR_FLAG_CLONE`).
+    clone: bool,
+    /// Stream was written on a host with the other byte order.
+    byte_swapped: bool,
+}
+
+impl StreamInfo {
+    /// Stream only has changes since another snapshot (`zfs send -i`/`-I`).
+    pub fn is_incremental(&self) -> bool {
+        self.from_guid.is_some()
+    }
+
+    /// Blocks are sent as they are on disk, encrypted included (`zfs send -w`).
+    pub fn is_raw(&self) -> bool {
+        self.features
+            .contains(StreamFeatures::DMU_BACKUP_FEATURE_RAW)
+    }
+
+    /// Compressed blocks are sent compressed (`zfs send -c`).
+    pub fn is_compressed(&self) -> bool {
+        self.features
+            .contains(StreamFeatures::DMU_BACKUP_FEATURE_COMPRESSED)
+    }
+
+    /// Blocks with data embedded into block pointers are sent as is (`zfs send -e`).
+    pub fn has_embedded_data(&self) -> bool {
+        self.features
+            .contains(StreamFeatures::DMU_BACKUP_FEATURE_EMBED_DATA)
+    }
+}
+
+#[cfg(test)]
+impl StreamInfo {
+    /// Header of a file system substream, for tests of code that checks streams.
+    pub(crate) fn substream(
+        snapshot: &str,
+        to_guid: u64,
+        from_guid: Option<u64>,
+        features: StreamFeatures,
+    ) -> StreamInfo {
+        StreamInfo {
+            snapshot: PathBuf::from(snapshot),
+            to_guid,
+            from_guid,
+            creation: 0,
+            dataset_kind: Some(DatasetKind::Filesystem),
+            kind: StreamKind::Substream,
+            features,
+            clone: false,
+            byte_swapped: false,
+        }
+    }
+}
+
+/// Read the `DRR_BEGIN` record from `reader` and describe the stream. Exactly one record and its
+/// payload (resume state of `zfs send -t`, dataset list of `zfs send -R`) are read, so `reader` is
+/// left at the first record after it.
+///
+/// Fails with `InvalidSendStream` if the record isn't a valid stream header or its payload is
+/// longer than 16 MiB and with `Io` if the stream is shorter than that.
+pub fn inspect_stream<R: Read>(mut reader: R) -> Result<StreamInfo> {
+    let mut record = [0u8; RECORD_LEN];
+    reader.read_exact(&mut record)?;
+    let (info, payload_len) = parse_begin(&record)?;
+    if payload_len > MAX_PAYLOAD_LEN {
+        return Err(invalid("payload of DRR_BEGIN is too long"));
+    }
+    let discarded = io::copy(
+        &mut reader.by_ref().take(payload_len.into()),
+        &mut io::sink(),
+    )?;
+    if discarded != u64::from(payload_len) {
+        return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+    }
+    Ok(info)
+}
+
+/// Fields of a record in byte order of the host that wrote it.
+struct Record<'a> {
+    bytes: &'a [u8; RECORD_LEN],
+    byte_swapped: bool,
+}
+
+impl<'a> Record<'a> {
+    fn u32_at(&self, offset: usize) -> u32 {
+        let mut raw = [0u8; 4];
+        raw.copy_from_slice(&self.bytes[offset..offset + 4]);
+        let value = u32::from_le_bytes(raw);
+        if self.byte_swapped {
+            value.swap_bytes()
+        } else {
+            value
+        }
+    }
+
+    fn u64_at(&self, offset: usize) -> u64 {
+        let mut raw = [0u8; 8];
+        raw.copy_from_slice(&self.bytes[offset..offset + 8]);
+        let value = u64::from_le_bytes(raw);
+        if self.byte_swapped {
+            value.swap_bytes()
+        } else {
+            value
+        }
+    }
+}
+
+fn invalid(reason: &str) -> Error {
+    Error::InvalidSendStream(reason.into())
+}
+
+/// Header of the stream and length of the payload following the record.
+fn parse_begin(bytes: &[u8; RECORD_LEN]) -> Result<(StreamInfo, u32)> {
+    // Magic is the only field with a known value, it tells the byte order.
+    let mut record = Record {
+        bytes,
+        byte_swapped: false,
+    };
+    match record.u64_at(8) {
+        DMU_BACKUP_MAGIC => {}
+        magic if magic == DMU_BACKUP_MAGIC.swap_bytes() => record.byte_swapped = true,
+        _ => return Err(invalid("bad magic")),
+    }
+    if record.u32_at(0) != DRR_BEGIN {
+        return Err(invalid("first record isn't DRR_BEGIN"));
+    }
+
+    let version_info = record.u64_at(16);
+    let kind = match version_info & 0b11 {
+        1 => StreamKind::Substream,
+        2 => StreamKind::Compound,
+        _ => return Err(invalid("unknown stream header type")),
+    };
+    let features = StreamFeatures::from_bits_truncate(((version_info >> 2) & 0x3fff_ffff) as u32);
+    let dataset_kind = match record.u32_at(32) {
+        DMU_OST_ZFS => Some(DatasetKind::Filesystem),
+        DMU_OST_ZVOL => Some(DatasetKind::Volume),
+        _ => None,
+    };
+    let from_guid = match record.u64_at(48) {
+        0 => None,
+        guid => Some(guid),
+    };
+
+    let toname = &bytes[TONAME_OFFSET..];
+    let len = toname
+        .iter()
+        .position(|b| *b == 0)
+        .ok_or_else(|| invalid("snapshot name isn't terminated"))?;
+    let snapshot =
+        String::from_utf8(toname[..len].to_vec()).map_err(|e| Error::NonUtf8(e.into_bytes()))?;
+
+    let info = StreamInfo {
+        snapshot: PathBuf::from(snapshot),
+        to_guid: record.u64_at(40),
+        from_guid,
+        creation: record.u64_at(24) as i64,
+        dataset_kind,
+        kind,
+        features,
+        clone: record.u32_at(36) & DRR_FLAG_CLONE != 0,
+        byte_swapped: record.byte_swapped,
+    };
+    Ok((info, record.u32_at(4)))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::zfs::ErrorKind;
+    use std::io::Cursor;
+
+    /// `zfs send -e -L tank/usr/home@monday`, cut after the first record following the header.
+    const FULL: &[u8] = include_bytes!("../../tests/fixtures/send_full");
+    /// `zfs send -c -i @monday tank/usr/home@tuesday` on a big-endian host, cut the same way.
+    const INCREMENTAL_BE: &[u8] = include_bytes!("../../tests/fixtures/send_incremental_be");
+    /// `zfs send -c -t <token>` of `tank/vol@wednesday`, header carries the resume state.
+    const RESUMABLE: &[u8] = include_bytes!("../../tests/fixtures/send_resumable_compressed");
+    /// `drr_type` of `DRR_FREEOBJECTS` and `DRR_OBJECT`.
+    const DRR_FREEOBJECTS: u8 = 2;
+    const DRR_OBJECT: u8 = 1;
+
+    #[test]
+    fn full_stream() {
+        let mut reader = Cursor::new(FULL);
+        let info = inspect_stream(&mut reader).unwrap();
+        // Next record must stay in the reader.
+        assert_eq!(RECORD_LEN as u64, reader.position());
+        assert_eq!(DRR_FREEOBJECTS, FULL[RECORD_LEN]);
+        let expected = StreamInfo {
+            snapshot: PathBuf::from("tank/usr/home@monday"),
+            to_guid: 0x8e3f_2d6b_4c9a_1f07,
+            from_guid: None,
+            creation: 1_589_000_000,
+            dataset_kind: Some(DatasetKind::Filesystem),
+            kind: StreamKind::Substream,
+            features: StreamFeatures::DMU_BACKUP_FEATURE_SA_SPILL
+                | StreamFeatures::DMU_BACKUP_FEATURE_EMBED_DATA
+                | StreamFeatures::DMU_BACKUP_FEATURE_LZ4
+                | StreamFeatures::DMU_BACKUP_FEATURE_LARGE_BLOCKS,
+            clone: false,
+            byte_swapped: false,
+        };
+        assert_eq!(expected, info);
+        assert!(!info.is_incremental());
+        assert!(info.has_embedded_data());
+        assert_eq!(
+            vec!["embedded_data", "lz4_compress", "large_blocks"],
+            info.features().required_pool_features()
+        );
+        assert!(!info.is_raw());
+        assert!(!info.is_compressed());
+    }
+
+    #[test]
+    fn incremental_stream_from_big_endian_host() {
+        let mut reader = Cursor::new(INCREMENTAL_BE);
+        let info = inspect_stream(&mut reader).unwrap();
+        assert_eq!(RECORD_LEN as u64, reader.position());
+        assert!(info.byte_swapped());
+        assert!(info.is_incremental());
+        assert_eq!(&Some(0x8e3f_2d6b_4c9a_1f07), info.from_guid());
+        assert_eq!(&0x51c0_e6a2_d97b_3348, info.to_guid());
+        assert_eq!(&1_589_086_400, info.creation());
+        assert_eq!(&PathBuf::from("tank/usr/home@tuesday"), info.snapshot());
+        assert_eq!(&Some(DatasetKind::Filesystem), info.dataset_kind());
+        assert!(info.is_compressed());
+        assert!(!info.is_raw());
+        assert!(!info.clone());
+    }
+
+    #[test]
+    fn resumable_compressed_stream() {
+        let mut reader = Cursor::new(RESUMABLE);
+        let info = inspect_stream(&mut reader).unwrap();
+        // Resume state is the payload of the header, it must be consumed too.
+        assert_eq!(DRR_OBJECT, RESUMABLE[reader.position() as usize]);
+        assert_eq!(RESUMABLE.len() - RECORD_LEN, reader.position() as usize);
+        assert_eq!(&PathBuf::from("tank/vol@wednesday"), info.snapshot());
+        assert_eq!(&Some(DatasetKind::Volume), info.dataset_kind());
+        assert!(!info.is_incremental());
+        assert!(info.is_compressed());
+        assert!(info
+            .features()
+            .contains(StreamFeatures::DMU_BACKUP_FEATURE_RESUMING));
+        assert_eq!(
+            vec!["lz4_compress"],
+            info.features().required_pool_features()
+        );
+
+        let err = inspect_stream(Cursor::new(&RESUMABLE[..RECORD_LEN + 8])).unwrap_err();
+        assert_eq!(ErrorKind::Io, err.kind());
+    }
+
+    #[test]
+    fn compound_stream() {
+        // `zfs send -R` header: only the header type is set in `drr_versioninfo`.
+        let mut header = FULL[..RECORD_LEN].to_vec();
+        header[16..24].copy_from_slice(&2u64.to_le_bytes());
+        let info = inspect_stream(Cursor::new(header)).unwrap();
+        assert_eq!(&StreamKind::Compound, info.kind());
+        assert_eq!(&StreamFeatures::empty(), info.features());
+    }
+
+    #[test]
+    fn invalid_headers() {
+        let mut bad_magic = FULL.to_vec();
+        bad_magic[8] ^= 0xff;
+        let err = inspect_stream(Cursor::new(bad_magic)).unwrap_err();
+        assert_eq!(Error::InvalidSendStream("bad magic".into()), err);
+
+        let mut not_begin = FULL.to_vec();
+        not_begin[0] = DRR_FREEOBJECTS;
+        let err = inspect_stream(Cursor::new(not_begin)).unwrap_err();
+        assert_eq!(ErrorKind::InvalidSendStream, err.kind());
+
+        let mut bad_version = FULL.to_vec();
+        bad_version[16] |= 0b11;
+        let err = inspect_stream(Cursor::new(bad_version)).unwrap_err();
+        assert_eq!(ErrorKind::InvalidSendStream, err.kind());
+
+        let mut unterminated = FULL.to_vec();
+        for b in &mut unterminated[TONAME_OFFSET..RECORD_LEN] {
+            *b = b'a';
+        }
+        let err = inspect_stream(Cursor::new(unterminated)).unwrap_err();
+        assert_eq!(ErrorKind::InvalidSendStream, err.kind());
+
+        let err = inspect_stream(Cursor::new(&FULL[..100])).unwrap_err();
+        assert_eq!(ErrorKind::Io, err.kind());
+
+        // `drr_payloadlen` of 4 GiB must not be allocated or read.
+        let mut huge_payload = FULL.to_vec();
+        huge_payload[4..8].copy_from_slice(&u32::MAX.to_le_bytes());
+        let err = inspect_stream(Cursor::new(huge_payload)).unwrap_err();
+        assert_eq!(
+            Error::InvalidSendStream("payload of DRR_BEGIN is too long".into()),
+            err
+        );
+    }
+}
@@ -0,0 +1,404 @@
+//! Watch properties of datasets for changes made outside of the program.
+//!
+//! ZFS doesn't tell anyone when a property changes, e.g. when someone sets `quota` with `zfs set`.
+//! [`PropertyWatcher`](struct.PropertyWatcher.html) reads watched properties of every dataset
+//! under a root in a background thread, compares them with the previous read and reports what
+//! changed, as well as datasets that appeared or disappeared in between.
+//!
+//! ### Usage
+//! ```rust,no_run
+//! use libzetta::zfs::{watch::{PropertyWatcher, WatchEvent}, DelegatingZfsEngine};
+//! use std::time::Duration;
+//!
+//! let engine = DelegatingZfsEngine::new().unwrap();
+//! let properties = vec![String::from("quota"), String::from("mountpoint")];
+//! let watcher = PropertyWatcher::new(engine, "tank/home", properties, Duration::from_secs(10));
+//! for event in watcher.subscribe() {
+//!     match event {
+//!         WatchEvent::Changed(change) => println!(
+//!             "{} {}: {} -> {}",
+//!             change.dataset().display(),
+//!             change.property(),
+//!             change.old(),
+//!             change.new()
+//!         ),
+//!         other => println!("{:?}", other),
+//!     }
+//! }
+//! ```
+use std::{collections::HashMap, path::PathBuf, sync::mpsc, time::Duration};
+
+use crate::{
+    utils::Poller,
+    zfs::{Result, ZfsEngine},
+    GlobalLogger,
+};
+
+/// Value of a watched property that changed between two reads.
+#[derive(Debug, Clone, PartialEq, Eq, Getters)]
+#[get = "pub"]
+pub struct PropertyChange {
+    /// Dataset the property belongs to.
+    dataset: PathBuf,
+    /// Name of the property.
+    property: String,
+    /// Value before, as `zfs get -p` prints it.
+    old: String,
+    /// Value now, as `zfs get -p` prints it.
+    new: String,
+}
+
+/// Something that happened under the watched root since the previous read.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WatchEvent {
+    /// Dataset that wasn't there before, with values of watched properties.
+    Created {
+        /// Name of the dataset.
+        dataset: PathBuf,
+        /// Values of watched properties.
+        values: HashMap<String, String>,
+    },
+    /// Dataset that is gone. Renamed dataset is reported as destroyed and created.
+    Destroyed {
+        /// Name of the dataset.
+        dataset: PathBuf,
+    },
+    /// Watched property of a dataset changed.
+    Changed(PropertyChange),
+}
+
+impl WatchEvent {
+    /// Dataset the event is about.
+    pub fn dataset(&self) -> &PathBuf {
+        match self {
+            WatchEvent::Created { dataset, .. } | WatchEvent::Destroyed { dataset } => dataset,
+            WatchEvent::Changed(change) => &change.dataset,
+        }
+    }
+}
+
+/// Watched values of every dataset at one point in time, in order of watched properties.
+struct Reading {
+    datasets: HashMap<PathBuf, Vec<String>>,
+}
+
+impl Reading {
+    fn of(datasets: Vec<(PathBuf, HashMap<String, String>)>, properties: &[String]) -> Reading {
+        let datasets = datasets
+            .into_iter()
+            .map(|(dataset, mut read)| {
+                let values = properties
+                    .iter()
+                    .map(|property| read.remove(property).unwrap_or_else(|| String::from("-")))
+                    .collect();
+                (dataset, values)
+            })
+            .collect();
+        Reading { datasets }
+    }
+
+    /// What changed since `earlier`, sorted by dataset name.
+    fn events_since(&self, earlier: &Reading, properties: &[String]) -> Vec<WatchEvent> {
+        let mut events = Vec::new();
+        for (dataset, now) in &self.datasets {
+            let then = match earlier.datasets.get(dataset) {
+                Some(then) if then == now => continue,
+                Some(then) => then,
+                None => {
+                    events.push(WatchEvent::Created {
+                        dataset: dataset.clone(),
+                        values: properties.iter().cloned().zip(now.clone()).collect(),
+                    });
+                    continue;
+                }
+            };
+            let changed = properties.iter().zip(then.iter().zip(now));
+            for (property, (old, new)) in changed.filter(|(_, (old, new))| old != new) {
+                events.push(WatchEvent::Changed(PropertyChange {
+                    dataset: dataset.clone(),
+                    property: property.clone(),
+                    old: old.clone(),
+                    new: new.clone(),
+                }));
+            }
+        }
+        events.extend(
+            earlier
+                .datasets
+                .keys()
+                .filter(|dataset| !self.datasets.contains_key(*dataset))
+                .map(|dataset| WatchEvent::Destroyed {
+                    dataset: dataset.clone(),
+                }),
+        );
+        // Changes of one dataset keep order of watched properties, sort is stable.
+        events.sort_by(|a, b| a.dataset().cmp(b.dataset()));
+        events
+    }
+}
+
+/// Polls properties of datasets under a root in a background thread and reports changes. The
+/// first read is the baseline, events are about changes after it. The thread is stopped and
+/// joined when the watcher is dropped.
+pub struct PropertyWatcher {
+    poller: Poller<WatchEvent>,
+}
+
+impl PropertyWatcher {
+    /// Start reading `properties` of `root` and every filesystem and volume under it every
+    /// `interval`, see
+    /// [`ZfsEngine::read_property_values`](../trait.ZfsEngine.html#method.read_property_values).
+    /// The engine is moved to the polling thread. Failed reads are logged and skipped, changes
+    /// are then reported against the last successful read.
+    pub fn new<E, N>(
+        engine: E,
+        root: N,
+        properties: Vec<String>,
+        interval: Duration,
+    ) -> PropertyWatcher
+    where
+        E: ZfsEngine + Send + 'static,
+        N: Into<PathBuf>,
+    {
+        let root = root.into();
+        let read_properties = properties.clone();
+        PropertyWatcher::with_reader(
+            move || engine.read_property_values(root.clone(), &read_properties),
+            properties,
+            interval,
+        )
+    }
+
+    /// Start polling with `read` in place of the engine.
+    pub(crate) fn with_reader<F>(
+        mut read: F,
+        properties: Vec<String>,
+        interval: Duration,
+    ) -> PropertyWatcher
+    where
+        F: FnMut() -> Result<Vec<(PathBuf, HashMap<String, String>)>> + Send + 'static,
+    {
+        let logger = GlobalLogger::get().new(o!("zetta_module" => "zfs", "zfs_impl" => "watch"));
+        let poll_logger = logger.clone();
+        let mut previous: Option<Reading> = None;
+        let poller = Poller::start(
+            move || match read() {
+                Ok(datasets) => {
+                    let reading = Reading::of(datasets, &properties);
+                    let events = previous
+                        .as_ref()
+                        .map(|previous| reading.events_since(previous, &properties))
+                        .unwrap_or_default();
+                    previous = Some(reading);
+                    events
+                }
+                Err(e) => {
+                    warn!(poll_logger, "failed to read properties"; "error" => %e);
+                    Vec::new()
+                }
+            },
+            interval,
+            logger,
+        );
+        PropertyWatcher { poller }
+    }
+
+    /// Call `callback` with every event. Callbacks run on the polling thread, one that panics is
+    /// logged and polling goes on.
+    pub fn on_event<F>(&self, callback: F)
+    where
+        F: Fn(&WatchEvent) + Send + 'static,
+    {
+        self.poller.on_event(callback);
+    }
+
+    /// Channel that receives every event from now on. It's closed when the watcher is dropped.
+    pub fn subscribe(&self) -> mpsc::Receiver<WatchEvent> {
+        let (sender, receiver) = mpsc::channel();
+        self.on_event(move |event| {
+            let _ = sender.send(event.clone());
+        });
+        receiver
+    }
+
+    /// Whether the polling thread is still alive. It only stops on its own if the engine
+    /// panicked.
+    pub fn is_running(&self) -> bool {
+        self.poller.is_running()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{utils::lock, zfs::Error};
+    use std::{collections::VecDeque, sync::Mutex, time::Instant};
+
+    type Read = Vec<(PathBuf, HashMap<String, String>)>;
+
+    fn read(datasets: &[(&str, &str, &str)]) -> Read {
+        datasets
+            .iter()
+            .map(|(dataset, quota, mountpoint)| {
+                let mut values = HashMap::new();
+                values.insert(String::from("quota"), String::from(*quota));
+                values.insert(String::from("mountpoint"), String::from(*mountpoint));
+                (PathBuf::from(*dataset), values)
+            })
+            .collect()
+    }
+
+    fn watched() -> Vec<String> {
+        vec![String::from("quota"), String::from("mountpoint")]
+    }
+
+    fn change(dataset: &str, property: &str, old: &str, new: &str) -> WatchEvent {
+        WatchEvent::Changed(PropertyChange {
+            dataset: PathBuf::from(dataset),
+            property: String::from(property),
+            old: String::from(old),
+            new: String::from(new),
+        })
+    }
+
+    /// Engine that returns scripted reads one by one, `None` is a failed read. Once the script
+    /// runs out it keeps returning the last read and reports on `done`.
+    struct Scripted {
+        script: Mutex<VecDeque<Option<Read>>>,
+        last: Mutex<Read>,
+        done: Mutex<mpsc::Sender<()>>,
+    }
+
+    impl ZfsEngine for Scripted {
+        fn read_property_values<N: Into<PathBuf>>(
+            &self,
+            root: N,
+            properties: &[String],
+        ) -> Result<Read> {
+            assert_eq!(watched(), properties);
+            match lock(&self.script).pop_front() {
+                Some(Some(read)) => {
+                    *lock(&self.last) = read.clone();
+                    Ok(read)
+                }
+                Some(None) => Err(Error::DatasetNotFound(root.into())),
+                None => {
+                    let _ = lock(&self.done).send(());
+                    Ok(lock(&self.last).clone())
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn changes_creations_and_removals() {
+        let (done, finished) = mpsc::channel();
+        let script = vec![
+            Some(read(&[("tank", "0", "/tank"), ("tank/a", "0", "/tank/a")])),
+            Some(read(&[
+                ("tank", "0", "/tank"),
+                ("tank/a", "1024", "/srv/a"),
+            ])),
+            None,
+            Some(read(&[
+                ("tank", "0", "/tank"),
+                ("tank/a", "1024", "/srv/a"),
+                ("tank/b", "0", "/tank/b"),
+            ])),
+            Some(read(&[("tank", "0", "/tank"), ("tank/b", "0", "/tank/b")])),
+        ];
+        let engine = Scripted {
+            script: Mutex::new(script.into_iter().collect()),
+            last: Mutex::new(Vec::new()),
+            done: Mutex::new(done),
+        };
+        // Reads are only taken once the channel is subscribed and the gate is opened.
+        let (gate, opened) = mpsc::channel::<()>();
+        let engine = Gated(engine, Mutex::new(opened));
+        let watcher = PropertyWatcher::new(engine, "tank", watched(), Duration::from_millis(1));
+        let events = watcher.subscribe();
+        drop(gate);
+        finished.recv_timeout(Duration::from_secs(10)).unwrap();
+        drop(watcher);
+
+        let mut values = HashMap::new();
+        values.insert(String::from("quota"), String::from("0"));
+        values.insert(String::from("mountpoint"), String::from("/tank/b"));
+        let expected = vec![
+            change("tank/a", "quota", "0", "1024"),
+            change("tank/a", "mountpoint", "/tank/a", "/srv/a"),
+            WatchEvent::Created {
+                dataset: PathBuf::from("tank/b"),
+                values,
+            },
+            WatchEvent::Destroyed {
+                dataset: PathBuf::from("tank/a"),
+            },
+        ];
+        // Channel is closed once the watcher is gone.
+        assert_eq!(expected, events.iter().collect::<Vec<WatchEvent>>());
+    }
+
+    /// Waits for the gate to be opened (or dropped) before the first read.
+    struct Gated(Scripted, Mutex<mpsc::Receiver<()>>);
+
+    impl ZfsEngine for Gated {
+        fn read_property_values<N: Into<PathBuf>>(
+            &self,
+            root: N,
+            properties: &[String],
+        ) -> Result<Read> {
+            let _ = lock(&self.1).recv_timeout(Duration::from_secs(10));
+            self.0.read_property_values(root, properties)
+        }
+    }
+
+    #[test]
+    fn unchanged_datasets_are_skipped() {
+        let properties = watched();
+        let datasets: Vec<(String, String, String)> = (0..5000)
+            .map(|i| {
+                (
+                    format!("tank/d{}", i),
+                    format!("{}", i),
+                    format!("/d/{}", i),
+                )
+            })
+            .collect();
+        let rows: Vec<(&str, &str, &str)> = datasets
+            .iter()
+            .map(|(d, q, m)| (d.as_str(), q.as_str(), m.as_str()))
+            .collect();
+        let before = Reading::of(read(&rows), &properties);
+        let mut after = read(&rows);
+        after[4321]
+            .1
+            .insert(String::from("quota"), String::from("none"));
+        let after = Reading::of(after, &properties);
+        assert_eq!(
+            vec![change("tank/d4321", "quota", "4321", "none")],
+            after.events_since(&before, &properties)
+        );
+        assert!(before.events_since(&before, &properties).is_empty());
+
+        // Property zfs didn't report reads as `-`.
+        let mut partial = read(&[("tank", "0", "/tank")]);
+        partial[0].1.remove("mountpoint");
+        let partial = Reading::of(partial, &properties);
+        let full = Reading::of(read(&[("tank", "0", "/tank")]), &properties);
+        assert_eq!(
+            vec![change("tank", "mountpoint", "-", "/tank")],
+            full.events_since(&partial, &properties)
+        );
+    }
+
+    #[test]
+    fn drop_stops_polling_promptly() {
+        let watcher =
+            PropertyWatcher::with_reader(|| Ok(Vec::new()), watched(), Duration::from_secs(3600));
+        assert!(watcher.is_running());
+        let started = Instant::now();
+        drop(watcher);
+        assert!(started.elapsed() < Duration::from_secs(10));
+    }
+}
@@ -0,0 +1,253 @@
+//! Device nodes of volumes, used by
+//! [`ZfsEngine::volume_device_path`](../trait.ZfsEngine.html#method.volume_device_path) and
+//! [`ZfsEngine::wait_for_volume_device`](../trait.ZfsEngine.html#method.wait_for_volume_device).
+//!
+//! Both Linux and FreeBSD expose a volume as `/dev/zvol/<volume>` unless its `volmode` is `none`.
+//! Node shows up some time after the volume is created: udev creates a link to `/dev/zdN` on
+//! Linux, devfs creates the node on FreeBSD.
+use slog::Logger;
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    instrumentation::InstrumentationHook,
+    zfs::{properties::VolumeMode, Error, Result},
+};
+
+const FIRST_POLL: Duration = Duration::from_millis(10);
+const MAX_POLL: Duration = Duration::from_millis(500);
+
+/// Path of the node of `volume` with `volmode` set to `mode`. `volmode=default` is resolved with
+/// `system_default`, if it can tell.
+pub(crate) fn device_path<F>(volume: &Path, mode: VolumeMode, system_default: F) -> Result<PathBuf>
+where
+    F: FnOnce() -> Option<VolumeMode>,
+{
+    if cfg!(not(any(target_os = "linux", target_os = "freebsd"))) {
+        return Err(Error::NoDeviceExposed(volume.to_path_buf()));
+    }
+    let mode = match mode {
+        VolumeMode::Default => system_default().unwrap_or(VolumeMode::GEOM),
+        mode => mode,
+    };
+    match mode {
+        VolumeMode::None => Err(Error::NoDeviceExposed(volume.to_path_buf())),
+        _ => Ok(Path::new("/dev/zvol").join(volume)),
+    }
+}
+
+/// Mode volumes with `volmode=default` get: `zvol_volmode` module parameter on Linux.
+#[cfg(target_os = "linux")]
+pub(crate) fn system_volume_mode(
+    _logger: &Logger,
+    _hook: Option<&Arc<InstrumentationHook>>,
+) -> Option<VolumeMode> {
+    let raw = std::fs::read_to_string("/sys/module/zfs/parameters/zvol_volmode").ok()?;
+    volume_mode_from_tunable(&raw)
+}
+
+/// Mode volumes with `volmode=default` get: `vfs.zfs.vol.mode` sysctl on FreeBSD. `sysctl` is
+/// timed and reported to `hook` like every other command.
+#[cfg(target_os = "freebsd")]
+pub(crate) fn system_volume_mode(
+    logger: &Logger,
+    hook: Option<&Arc<InstrumentationHook>>,
+) -> Option<VolumeMode> {
+    let mut sysctl = crate::instrumentation::isolated_command("sysctl");
+    sysctl.args(["-n", "vfs.zfs.vol.mode"]);
+    debug!(logger, "executing"; "cmd" => format_args!("{:?}", sysctl));
+    let out = crate::instrumentation::run_command(logger, hook, &mut sysctl).ok()?;
+    if !out.status.success() {
+        return None;
+    }
+    volume_mode_from_tunable(&String::from_utf8_lossy(&out.stdout))
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "freebsd")))]
+pub(crate) fn system_volume_mode(
+    _logger: &Logger,
+    _hook: Option<&Arc<InstrumentationHook>>,
+) -> Option<VolumeMode> {
+    None
+}
+
+/// Both tunables use the numbers of `volmode` values: `1` is `geom` (`full` on Linux), `2` is
+/// `dev` and `3` is `none`.
+#[cfg_attr(not(any(target_os = "linux", target_os = "freebsd")), allow(dead_code))]
+fn volume_mode_from_tunable(raw: &str) -> Option<VolumeMode> {
+    match raw.trim() {
+        "1" => Some(VolumeMode::GEOM),
+        "2" => Some(VolumeMode::Dev),
+        "3" => Some(VolumeMode::None),
+        _ => None,
+    }
+}
+
+/// Name of the volume behind a `/dev/zdN` node, asked with `BLKZNAME` ioctl. `None` if it can't
+/// be asked, e.g. without permission to open the device.
+#[cfg(target_os = "linux")]
+pub(crate) fn volume_behind(device: &Path) -> Option<PathBuf> {
+    use std::os::unix::io::AsRawFd;
+
+    // _IOR(0x12, 125, char[ZFS_MAX_DATASET_NAME_LEN])
+    const BLKZNAME: u64 = 0x8100_127d;
+    let file = std::fs::File::open(device).ok()?;
+    let mut name = [0u8; 256];
+    // Safe: buffer is as large as the kernel expects and outlives the call.
+    let ret = unsafe { libc::ioctl(file.as_raw_fd(), BLKZNAME as _, name.as_mut_ptr()) };
+    if ret != 0 {
+        return None;
+    }
+    let len = name.iter().position(|byte| *byte == 0)?;
+    Some(PathBuf::from(
+        String::from_utf8_lossy(&name[..len]).into_owned(),
+    ))
+}
+
+/// Nodes on FreeBSD are named by devfs after the volume itself, so they can't be stale.
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn volume_behind(_device: &Path) -> Option<PathBuf> {
+    None
+}
+
+/// Poll for `device` of `volume` until it exists and, if `volume_behind` can tell, belongs to
+/// `volume` rather than being a leftover of a renamed or destroyed volume. Polls start at 10ms
+/// apart and back off to 500ms.
+pub(crate) fn wait_for_device<F>(
+    volume: &Path,
+    device: &Path,
+    timeout: Duration,
+    volume_behind: F,
+) -> Result<()>
+where
+    F: Fn(&Path) -> Option<PathBuf>,
+{
+    let started = Instant::now();
+    let mut pause = FIRST_POLL;
+    loop {
+        let ready = device.exists()
+            && volume_behind(device).is_none_or(|behind| behind.as_path() == volume);
+        if ready {
+            return Ok(());
+        }
+        let elapsed = started.elapsed();
+        if elapsed >= timeout {
+            return Err(Error::DeviceTimedOut {
+                device: device.to_path_buf(),
+                after: timeout,
+            });
+        }
+        thread::sleep(pause.min(timeout - elapsed));
+        pause = (pause * 2).min(MAX_POLL);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{cell::Cell, fs};
+
+    use super::*;
+    use crate::zfs::ErrorKind;
+
+    #[test]
+    fn device_path_respects_volmode() {
+        let volume = Path::new("tank/vol");
+        let node = PathBuf::from("/dev/zvol/tank/vol");
+        let never = || -> Option<VolumeMode> { panic!("system default isn't needed") };
+
+        assert_eq!(
+            Ok(node.clone()),
+            device_path(volume, VolumeMode::GEOM, never)
+        );
+        assert_eq!(
+            Ok(node.clone()),
+            device_path(volume, VolumeMode::Dev, never)
+        );
+        assert_eq!(
+            Err(Error::NoDeviceExposed(volume.to_path_buf())),
+            device_path(volume, VolumeMode::None, never)
+        );
+        assert_eq!(
+            Ok(node.clone()),
+            device_path(volume, VolumeMode::Default, || None)
+        );
+        assert_eq!(
+            Ok(node),
+            device_path(volume, VolumeMode::Default, || Some(VolumeMode::Dev))
+        );
+        assert_eq!(
+            Err(Error::NoDeviceExposed(volume.to_path_buf())),
+            device_path(volume, VolumeMode::Default, || Some(VolumeMode::None))
+        );
+    }
+
+    #[test]
+    fn tunable_values() {
+        assert_eq!(Some(VolumeMode::GEOM), volume_mode_from_tunable("1\n"));
+        assert_eq!(Some(VolumeMode::Dev), volume_mode_from_tunable("2"));
+        assert_eq!(Some(VolumeMode::None), volume_mode_from_tunable("3\n"));
+        assert_eq!(None, volume_mode_from_tunable("0"));
+        assert_eq!(None, volume_mode_from_tunable(""));
+    }
+
+    #[test]
+    fn waits_for_node_to_appear() {
+        let dir = tempfile::tempdir().unwrap();
+        let device = dir.path().join("vol");
+        let created = device.clone();
+        let creator = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            fs::write(created, b"").unwrap();
+        });
+        let volume = Path::new("tank/vol");
+        wait_for_device(volume, &device, Duration::from_secs(10), |_| None).unwrap();
+        creator.join().unwrap();
+    }
+
+    #[test]
+    fn times_out() {
+        let dir = tempfile::tempdir().unwrap();
+        let device = dir.path().join("vol");
+        let timeout = Duration::from_millis(100);
+        let started = Instant::now();
+        let err = wait_for_device(Path::new("tank/vol"), &device, timeout, |_| None).unwrap_err();
+        assert!(started.elapsed() >= timeout);
+        assert_eq!(ErrorKind::DeviceTimedOut, err.kind());
+        assert_eq!(
+            Error::DeviceTimedOut {
+                device,
+                after: timeout
+            },
+            err
+        );
+    }
+
+    #[test]
+    fn skips_stale_node() {
+        let dir = tempfile::tempdir().unwrap();
+        let device = dir.path().join("vol");
+        fs::write(&device, b"").unwrap();
+        let volume = Path::new("tank/vol");
+
+        // Node is left behind by `tank/old` until udev catches up with the rename.
+        let polls = Cell::new(0);
+        let behind = |_: &Path| {
+            polls.set(polls.get() + 1);
+            if polls.get() < 3 {
+                Some(PathBuf::from("tank/old"))
+            } else {
+                Some(PathBuf::from("tank/vol"))
+            }
+        };
+        wait_for_device(volume, &device, Duration::from_secs(10), behind).unwrap();
+        assert_eq!(3, polls.get());
+
+        let stale = |_: &Path| Some(PathBuf::from("tank/old"));
+        let err = wait_for_device(volume, &device, Duration::from_millis(50), stale).unwrap_err();
+        assert_eq!(ErrorKind::DeviceTimedOut, err.kind());
+    }
+}
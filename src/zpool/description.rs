@@ -1,27 +1,318 @@
 //! If anyone has a better name for this module - hit me up. This module is where consumer friendly
 //! representation of Zpool is defined. This is where pest's
 //! [Pairs](../../../pest/iterators/struct.Pair.html) turned into [Zpool](struct.Zpool.html).
-use std::{path::PathBuf, str::FromStr};
+use std::{collections::HashMap, path::PathBuf, str::FromStr};
 
-use pest::iterators::{Pair, Pairs};
+use chrono::{Local, NaiveDateTime, TimeZone};
+use pest::{
+    error::InputLocation,
+    iterators::{Pair, Pairs},
+    Parser,
+};
+use regex::Regex;
 
 use crate::{
-    parsers::Rule,
+    parsers::{Rule, StdoutParser},
     zpool::{
-        vdev::{ErrorStatistics, Vdev, VdevType},
-        CreateZpoolRequest, Disk, Health,
+        device_metadata::{DeviceMetadata, DeviceMetadataResolver},
+        vdev::{ErrorStatistics, ResilverStatus, Vdev, VdevType},
+        CreateZpoolRequest, Disk, Health, ZpoolError, ZpoolResult,
     },
 };
 
-/// The reason why zpool is in this state. Right now it's just a wrapper around `String`, but in the
-/// future there _might_ be a more machine friendly format.
+/// The reason why zpool, vdev or device is in this state. Message `zpool status` prints after error
+/// counters.
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub enum Reason {
+    /// Too many devices are missing for pool or vdev to work (`insufficient replicas`).
+    InsufficientReplicas,
+    /// Device is there, but its label or data is damaged (`corrupted data`).
+    CorruptedData,
     /// Not yet classified reason.
     Other(String),
 }
+
+impl Reason {
+    /// Message as `zpool status` prints it.
+    pub fn as_str(&self) -> &str {
+        match self {
+            Reason::InsufficientReplicas => "insufficient replicas",
+            Reason::CorruptedData => "corrupted data",
+            Reason::Other(reason) => reason,
+        }
+    }
+}
+
+impl From<&str> for Reason {
+    fn from(reason: &str) -> Reason {
+        match reason.trim() {
+            "insufficient replicas" => Reason::InsufficientReplicas,
+            "corrupted data" => Reason::CorruptedData,
+            other => Reason::Other(String::from(other)),
+        }
+    }
+}
+lazy_static! {
+    static ref RE_SCAN_FINISHED: Regex =
+        Regex::new(r"^(scrub repaired|resilvered) \S+ in .+ with (\d+) errors on (.+)$")
+            .expect("failed to compile RE_SCAN_FINISHED");
+    static ref RE_SCAN_SINCE: Regex =
+        Regex::new(r"^(scrub in progress|resilver in progress|scrub paused) since (.+)$")
+            .expect("failed to compile RE_SCAN_SINCE");
+    static ref RE_SCAN_CANCELED: Regex =
+        Regex::new(r"^scrub canceled on (.+)$").expect("failed to compile RE_SCAN_CANCELED");
+}
+
+static SCAN_DATE_FORMAT: &str = "%a %b %e %H:%M:%S %Y";
+
+/// Typed `scan:` section of `zpool status`. Timestamps are seconds since epoch, converted from
+/// local time `zpool` prints them in.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum ScanStatus {
+    /// Neither scrub nor resilver were ever requested.
+    NoneRequested,
+    /// Scrub is running right now.
+    ScrubInProgress {
+        /// When scrub started.
+        since: i64,
+    },
+    /// Scrub was paused with `zpool scrub -p`.
+    ScrubPaused {
+        /// When scrub was paused.
+        since: i64,
+    },
+    /// Scrub completed.
+    ScrubFinished {
+        /// Number of errors scrub found.
+        errors: u64,
+        /// When scrub completed.
+        finished_at: i64,
+    },
+    /// Scrub was stopped with `zpool scrub -s`.
+    ScrubCanceled {
+        /// When scrub was canceled.
+        at: i64,
+    },
+    /// Resilver is running right now.
+    ResilverInProgress {
+        /// When resilver started.
+        since: i64,
+    },
+    /// Resilver completed.
+    ResilverFinished {
+        /// Number of errors resilver found.
+        errors: u64,
+        /// When resilver completed.
+        finished_at: i64,
+    },
+    /// Not yet classified. Contains the whole `scan:` text.
+    Other(String),
+}
+
+impl ScanStatus {
+    /// Parse text after `scan:`. Only the first line is looked at, the rest is progress report.
+    pub(crate) fn from_scan_text(text: &str) -> ScanStatus {
+        let line = text.lines().next().unwrap_or_default().trim();
+        let parsed = if line == "none requested" {
+            Some(ScanStatus::NoneRequested)
+        } else if let Some(caps) = RE_SCAN_FINISHED.captures(line) {
+            let errors = caps[2].parse().ok();
+            let finished_at = parse_scan_date(&caps[3]);
+            match (&caps[1], errors, finished_at) {
+                ("resilvered", Some(errors), Some(finished_at)) => {
+                    Some(ScanStatus::ResilverFinished {
+                        errors,
+                        finished_at,
+                    })
+                }
+                (_, Some(errors), Some(finished_at)) => Some(ScanStatus::ScrubFinished {
+                    errors,
+                    finished_at,
+                }),
+                _ => None,
+            }
+        } else if let Some(caps) = RE_SCAN_SINCE.captures(line) {
+            parse_scan_date(&caps[2]).map(|since| match &caps[1] {
+                "scrub in progress" => ScanStatus::ScrubInProgress { since },
+                "scrub paused" => ScanStatus::ScrubPaused { since },
+                _ => ScanStatus::ResilverInProgress { since },
+            })
+        } else if let Some(caps) = RE_SCAN_CANCELED.captures(line) {
+            parse_scan_date(&caps[1]).map(|at| ScanStatus::ScrubCanceled { at })
+        } else {
+            None
+        };
+        parsed.unwrap_or_else(|| ScanStatus::Other(String::from(text.trim_end())))
+    }
+
+    /// When last scrub completed, if this is what `scan:` is about.
+    pub fn last_scrub_finished_at(&self) -> Option<i64> {
+        match *self {
+            ScanStatus::ScrubFinished { finished_at, .. } => Some(finished_at),
+            _ => None,
+        }
+    }
+
+    /// Whether scrub is running right now. Paused scrub is not running.
+    pub fn is_scrubbing(&self) -> bool {
+        matches!(*self, ScanStatus::ScrubInProgress { .. })
+    }
+}
+
+/// Typed `status:` section of `zpool status` and `zpool import`. Only conditions worth acting on
+/// programmatically are told apart, the rest is kept as text.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum PoolStatus {
+    /// Pool is suspended because multihost writes failed or were delayed, so another system could
+    /// import it undetected.
+    MultihostSuspended,
+    /// Pool has `multihost=on` and is imported by another system.
+    ActiveOnOtherHost,
+    /// Pool has `multihost=on`, but host ID of this system isn't set.
+    HostidRequired,
+    /// Not yet classified. Contains the whole `status:` text with lines joined.
+    Other(String),
+}
+
+impl PoolStatus {
+    /// Parse text after `status:`. Message is wrapped over several lines, so it's unwrapped before
+    /// it's looked at.
+    pub(crate) fn from_status_text(text: &str) -> PoolStatus {
+        let text = unwrap_text(text);
+        if text.contains("suspended because multihost writes failed") {
+            PoolStatus::MultihostSuspended
+        } else if text.contains("currently imported by another system") {
+            PoolStatus::ActiveOnOtherHost
+        } else if text.contains("multihost property on") && text.contains("hostid is not set") {
+            PoolStatus::HostidRequired
+        } else {
+            PoolStatus::Other(text)
+        }
+    }
+}
+
+/// Damaged file or object listed by `zpool status -v` under `errors:`. Objects are referred to by
+/// number when there is no path to show, e.g. the file was deleted since.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum PermanentError {
+    /// File that still exists. Path is absolute if its dataset is mounted, otherwise it's
+    /// relative to the root of `dataset`.
+    File {
+        /// Dataset of the file, only shown when it isn't mounted.
+        dataset: Option<String>,
+        /// Path to the file.
+        path: PathBuf,
+    },
+    /// Object of a dataset that has no path, e.g. `tank/data:<0x5>`.
+    Object {
+        /// Name of the dataset.
+        dataset: String,
+        /// Object number.
+        object: u64,
+    },
+    /// Object of a dataset that no longer exists, e.g. `<0x1a3b>:<0x4>`.
+    DeletedDatasetObject {
+        /// Object number of the dataset.
+        dataset: u64,
+        /// Object number.
+        object: u64,
+    },
+    /// Pool metadata object, e.g. `<metadata>:<0x0>`.
+    Metadata {
+        /// Object number.
+        object: u64,
+    },
+    /// Not yet classified entry. Contains the whole line.
+    Other(String),
+}
+
+impl PermanentError {
+    /// Parse one entry of the list `zpool status -v` prints.
+    pub(crate) fn from_entry(entry: &str) -> PermanentError {
+        let entry = entry.trim();
+        let parsed = if entry.starts_with('/') {
+            Some(PermanentError::File {
+                dataset: None,
+                path: PathBuf::from(entry),
+            })
+        } else if let Some(idx) = entry.rfind(":<0x") {
+            let (dataset, object) = (&entry[..idx], parse_object_number(&entry[idx + 1..]));
+            match (dataset, object) {
+                ("<metadata>", Some(object)) => Some(PermanentError::Metadata { object }),
+                (dataset, Some(object)) if dataset.starts_with('<') => parse_object_number(dataset)
+                    .map(|dataset| PermanentError::DeletedDatasetObject { dataset, object }),
+                (dataset, Some(object)) => Some(PermanentError::Object {
+                    dataset: String::from(dataset),
+                    object,
+                }),
+                _ => None,
+            }
+        } else {
+            entry.find(":/").map(|idx| PermanentError::File {
+                dataset: Some(String::from(&entry[..idx])),
+                path: PathBuf::from(&entry[idx + 1..]),
+            })
+        };
+        parsed.unwrap_or_else(|| PermanentError::Other(String::from(entry)))
+    }
+}
+
+/// `<0x1a3b>` to `0x1a3b`.
+fn parse_object_number(text: &str) -> Option<u64> {
+    let hex = text.strip_prefix("<0x")?.strip_suffix('>')?;
+    u64::from_str_radix(hex, 16).ok()
+}
+
+/// `-T d` date looks like `date(1)` output, with time zone on Linux and without it on FreeBSD.
+/// Time zone is assumed to be local either way.
+#[inline]
+#[allow(clippy::option_unwrap_used, clippy::wildcard_enum_match_arm)]
+fn get_timestamp_from_pair(pair: Pair<'_, Rule>) -> Option<i64> {
+    let value = get_value_from_pair(pair);
+    match value.as_rule() {
+        Rule::unix_timestamp => value.as_str().parse().ok(),
+        Rule::date_timestamp => {
+            let mut words: Vec<&str> = value.as_str().split_whitespace().collect();
+            if words.len() == 6 {
+                words.remove(4);
+            }
+            parse_scan_date(&words.join(" "))
+        }
+        _ => unreachable!(),
+    }
+}
+
+pub(crate) fn parse_scan_date(date: &str) -> Option<i64> {
+    let naive = NaiveDateTime::parse_from_str(date.trim(), SCAN_DATE_FORMAT).ok()?;
+    Local
+        .from_local_datetime(&naive)
+        .earliest()
+        .map(|date| date.timestamp())
+}
+
+/// What a device is used for in the pool.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum DiskRole {
+    /// Part of a data vdev.
+    Data,
+    /// Part of a ZFS Intent Log vdev.
+    Log,
+    /// L2ARC device.
+    Cache,
+    /// Hot spare.
+    Spare,
+    /// Part of a `special` allocation class vdev.
+    Special,
+    /// Part of a `dedup` allocation class vdev.
+    Dedup,
+}
+
 /// Consumer friendly Zpool representation. It has generic health status information, structure of
 /// vdevs, devices used to create said vdevs as well as error statistics.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[derive(Getters, Builder, Debug, Eq, PartialEq, Clone)]
 #[builder(setter(into))]
 #[get = "pub"]
@@ -44,15 +335,39 @@ pub struct Zpool {
     /// Spare devices.
     #[builder(default)]
     spares: Vec<Disk>,
-    /// Value of action field what ever it is.
+    /// VDEVs of `special` allocation class that hold metadata and small blocks.
+    #[builder(default)]
+    special_vdevs: Vec<Vdev>,
+    /// VDEVs of `dedup` allocation class that hold deduplication table.
+    #[builder(default)]
+    dedup_vdevs: Vec<Vdev>,
+    /// Sections of `config` this parser doesn't know about. Name of the heading and lines below
+    /// it as they are.
+    #[builder(default)]
+    other_sections: Vec<(String, String)>,
+    /// What `status:` section says about the pool.
+    #[builder(default)]
+    status: Option<PoolStatus>,
+    /// Value of action field what ever it is, with lines joined.
     #[builder(default)]
     action: Option<String>,
     /// Errors?
     #[builder(default)]
     errors: Option<String>,
+    /// Damaged files and objects. Only listed when
+    /// [`StatusOptions`](../open3/struct.StatusOptions.html) `verbose` is set, empty otherwise.
+    #[builder(default)]
+    permanent_errors: Vec<PermanentError>,
     /// Reason why this Zpool is not healthy.
     #[builder(default)]
     reason: Option<Reason>,
+    /// Current or last scrub/resilver.
+    #[builder(default)]
+    scan: Option<ScanStatus>,
+    /// When status was queried, in seconds since epoch. Only known if
+    /// [`StatusOptions`](../open3/struct.StatusOptions.html) `timestamp` was set.
+    #[builder(default)]
+    queried_at: Option<i64>,
     /// Error statistics
     #[builder(default)]
     error_statistics: ErrorStatistics,
@@ -64,16 +379,123 @@ impl Zpool {
         ZpoolBuilder::default()
     }
 
+    /// Every device of the pool along with what it's used for. Devices of data, log, special and
+    /// dedup vdevs come first, in order `zpool status` shows them, then caches and spares. A hot
+    /// spare that took over a device is listed twice: as part of the vdev and as a spare.
+    pub fn all_disks(&self) -> Vec<(DiskRole, &Disk)> {
+        let vdevs = [
+            (DiskRole::Data, &self.vdevs),
+            (DiskRole::Log, &self.logs),
+            (DiskRole::Special, &self.special_vdevs),
+            (DiskRole::Dedup, &self.dedup_vdevs),
+        ];
+        let mut disks: Vec<(DiskRole, &Disk)> = vdevs
+            .iter()
+            .flat_map(|(role, vdevs)| {
+                vdevs
+                    .iter()
+                    .flat_map(Vdev::disks)
+                    .map(move |disk| (*role, disk))
+            })
+            .collect();
+        disks.extend(self.caches.iter().map(|disk| (DiskRole::Cache, disk)));
+        disks.extend(self.spares.iter().map(|disk| (DiskRole::Spare, disk)));
+        disks
+    }
+
+    /// How many devices can fail before data is lost: the smallest
+    /// [`Vdev::fault_tolerance`](../vdev/struct.Vdev.html#method.fault_tolerance) of data,
+    /// special and dedup vdevs. Log devices, caches and spares can be lost without losing the
+    /// pool, so they are not considered. `None` if pool has no data vdevs.
+    pub fn fault_tolerance(&self) -> Option<u32> {
+        self.vdevs
+            .iter()
+            .chain(&self.special_vdevs)
+            .chain(&self.dedup_vdevs)
+            .map(Vdev::fault_tolerance)
+            .min()
+    }
+
+    /// Devices that are not online. Hot spares waiting for their turn (`AVAIL`) or in use
+    /// (`INUSE`) are not considered degraded.
+    pub fn degraded_devices(&self) -> Vec<&Disk> {
+        self.all_disks()
+            .into_iter()
+            .filter(|(role, disk)| {
+                !matches!(
+                    (role, disk.health()),
+                    (_, Health::Online)
+                        | (DiskRole::Spare, Health::Available)
+                        | (DiskRole::Spare, Health::Inuse)
+                )
+            })
+            .map(|(_, disk)| disk)
+            .collect()
+    }
+
+    /// Fill in model and serial number of every device with `resolver`, e.g.
+    /// [`SystemResolver`](../device_metadata/struct.SystemResolver.html). Files and missing devices
+    /// reported by their GUID are skipped. A device that can't be resolved is left as it is and
+    /// returned along with the error, the rest of the pool is enriched anyway. Every device is
+    /// passed to a single
+    /// [`resolve_all`](../device_metadata/trait.DeviceMetadataResolver.html#method.resolve_all)
+    /// call.
+    pub fn enrich_devices(
+        &mut self,
+        resolver: &dyn DeviceMetadataResolver,
+    ) -> Vec<(PathBuf, ZpoolError)> {
+        let mut paths: Vec<PathBuf> = Vec::new();
+        for disk in self.block_devices_mut() {
+            if !paths.contains(disk.path()) {
+                paths.push(disk.path().clone());
+            }
+        }
+        let results = resolver.resolve_all(&paths);
+        let mut resolved: HashMap<PathBuf, DeviceMetadata> = HashMap::new();
+        let mut failed: Vec<(PathBuf, ZpoolError)> = Vec::new();
+        for (path, result) in paths.into_iter().zip(results) {
+            match result {
+                Ok(metadata) => {
+                    resolved.insert(path, metadata);
+                }
+                Err(e) => failed.push((path, e)),
+            }
+        }
+        for disk in self.block_devices_mut() {
+            if let Some(metadata) = resolved.get(disk.path()) {
+                disk.set_metadata(metadata.clone());
+            }
+        }
+        failed
+    }
+
+    /// Every disk of the pool that is a block device, hot spares used by a vdev show up twice.
+    fn block_devices_mut(&mut self) -> impl Iterator<Item = &mut Disk> {
+        self.vdevs
+            .iter_mut()
+            .chain(&mut self.logs)
+            .chain(&mut self.special_vdevs)
+            .chain(&mut self.dedup_vdevs)
+            .flat_map(Vdev::disks_mut)
+            .chain(&mut self.caches)
+            .chain(&mut self.spares)
+            .filter(|disk| disk.is_block_device())
+    }
+
     #[allow(clippy::option_unwrap_used, clippy::wildcard_enum_match_arm)]
     pub(crate) fn from_pest_pair(pair: Pair<'_, Rule>) -> Zpool {
         debug_assert!(pair.as_rule() == Rule::zpool);
         let pairs = pair.into_inner();
         let mut zpool = ZpoolBuilder::default();
+        let mut other_sections = Vec::new();
         for pair in pairs {
             match pair.as_rule() {
                 Rule::pool_name => {
                     zpool.name(get_string_from_pair(pair));
                 }
+                Rule::timestamp => {
+                    zpool.queried_at(get_timestamp_from_pair(pair));
+                }
                 Rule::pool_id => {
                     zpool.id(Some(get_u64_from_pair(pair)));
                 }
@@ -81,10 +503,12 @@ impl Zpool {
                     zpool.health(get_health_from_pair(pair));
                 }
                 Rule::action => {
-                    zpool.action(Some(get_string_from_pair(pair)));
+                    zpool.action(Some(unwrap_text(get_value_from_pair(pair).as_str())));
                 }
                 Rule::errors => {
-                    zpool.errors(get_error_from_pair(pair));
+                    let (errors, permanent_errors) = get_errors_from_pair(pair);
+                    zpool.errors(errors);
+                    zpool.permanent_errors(permanent_errors);
                 }
                 Rule::vdevs => {
                     zpool.vdevs(get_vdevs_from_pair(pair));
@@ -101,8 +525,105 @@ impl Zpool {
                 Rule::spares => {
                     zpool.spares(get_spares_from_pair(pair));
                 }
-                Rule::config | Rule::status | Rule::see | Rule::pool_headers | Rule::comment => {}
-                Rule::scan_line => {}
+                Rule::special => {
+                    zpool.special_vdevs(get_vdevs_from_section(pair));
+                }
+                Rule::dedup => {
+                    zpool.dedup_vdevs(get_vdevs_from_section(pair));
+                }
+                Rule::other_section => {
+                    other_sections.push(get_other_section_from_pair(pair));
+                }
+                Rule::status => {
+                    zpool.status(Some(PoolStatus::from_status_text(
+                        get_value_from_pair(pair).as_str(),
+                    )));
+                }
+                Rule::config | Rule::see | Rule::pool_headers | Rule::comment => {}
+                Rule::scan_line => {
+                    let text = get_value_from_pair(pair);
+                    zpool.scan(Some(ScanStatus::from_scan_text(text.as_str())));
+                }
+                _ => unreachable!(),
+            }
+        }
+        zpool.other_sections(other_sections);
+        zpool.build().expect("Can't build zpool out of pair. Please report at: https://github.com/Inner-Heaven/libzetta-rs")
+    }
+
+    /// Parse output of `zpool status` or `zpool import` captured elsewhere, e.g. on a remote
+    /// machine. Output must be understood as a whole, otherwise
+    /// [`ZpoolError::UnparsedStatus`](../enum.ZpoolError.html) points at the line where parsing
+    /// failed.
+    pub fn parse_status(text: &str) -> ZpoolResult<Vec<Zpool>> {
+        StdoutParser::parse(Rule::status_output, text)
+            .map(Zpool::from_pest_pairs)
+            .map_err(|err| {
+                let (start, end) = match err.location {
+                    InputLocation::Pos(pos) => (pos, pos),
+                    InputLocation::Span(span) => span,
+                };
+                let start = text[..start].rfind('\n').map_or(0, |idx| idx + 1);
+                let end = text[end..].find('\n').map_or(text.len(), |idx| end + idx);
+                ZpoolError::UnparsedStatus(text.into(), start..end)
+            })
+    }
+
+    /// Parse output of `zpool status -j` captured elsewhere. It's mapped to the same structure text
+    /// output would give, except devices have no resilver notes.
+    #[cfg(feature = "serde")]
+    pub fn parse_status_json(json: &str) -> ZpoolResult<Vec<Zpool>> {
+        crate::zpool::status_json::zpools_from_json(json, None)
+    }
+
+    /// Build every zpool out of `zpool status` or `zpool import` output. Timestamp is printed
+    /// only once, so pools after the first one get it from the pool before them.
+    pub(crate) fn from_pest_pairs(pairs: Pairs<'_, Rule>) -> Vec<Zpool> {
+        let mut queried_at = None;
+        pairs
+            .filter(|pair| pair.as_rule() != Rule::EOI)
+            .map(|pair| {
+                let mut zpool = Zpool::from_pest_pair(pair);
+                match zpool.queried_at {
+                    Some(_) => queried_at = zpool.queried_at,
+                    None => zpool.queried_at = queried_at,
+                }
+                zpool
+            })
+            .collect()
+    }
+
+    /// Build zpool out of `zpool create -n` output. There is no state in it, so everything is
+    /// reported as online.
+    #[allow(clippy::option_unwrap_used, clippy::wildcard_enum_match_arm)]
+    pub(crate) fn from_dry_run_pair(pair: Pair<'_, Rule>) -> Zpool {
+        debug_assert!(pair.as_rule() == Rule::dry_run);
+        let mut zpool = ZpoolBuilder::default();
+        zpool.health(Health::Online);
+        for pair in pair.into_inner() {
+            match pair.as_rule() {
+                Rule::dry_run_header => {
+                    zpool.name(get_string_from_pair(pair));
+                }
+                Rule::dry_vdevs => {
+                    zpool.vdevs(get_vdevs_from_dry_vdevs(pair));
+                }
+                Rule::dry_logs => {
+                    zpool.logs(get_vdevs_from_dry_vdevs(get_value_from_pair(pair)));
+                }
+                Rule::dry_special => {
+                    zpool.special_vdevs(get_vdevs_from_dry_vdevs(get_value_from_pair(pair)));
+                }
+                Rule::dry_dedup => {
+                    zpool.dedup_vdevs(get_vdevs_from_dry_vdevs(get_value_from_pair(pair)));
+                }
+                Rule::dry_caches => {
+                    zpool.caches(get_disks_from_dry_disks(get_value_from_pair(pair)));
+                }
+                Rule::dry_spares => {
+                    zpool.spares(get_disks_from_dry_disks(get_value_from_pair(pair)));
+                }
+                Rule::name | Rule::EOI => {}
                 _ => unreachable!(),
             }
         }
@@ -168,7 +689,7 @@ fn set_stats_and_reason_from_pool_line(pool_line: Pair<'_, Rule>, zpool: &mut Zp
     for pair in pool_line.into_inner() {
         match pair.as_rule() {
             Rule::reason => {
-                zpool.reason(Some(Reason::Other(String::from(pair.as_span().as_str()))));
+                zpool.reason(Some(Reason::from(pair.as_span().as_str())));
             }
             Rule::error_statistics => {
                 zpool.error_statistics(get_error_statistics_from_pair(pair));
@@ -188,6 +709,16 @@ fn get_vdev_type(raid_name: Pair<'_, Rule>) -> VdevType {
     VdevType::from_str(raid_enum.as_str()).expect("Failed to parse raid type")
 }
 
+#[inline]
+fn get_group_type(group_name: Pair<'_, Rule>) -> VdevType {
+    let group_enum = group_name
+        .into_inner()
+        .next()
+        .expect("Failed to parse group_enum");
+    debug_assert!(group_enum.as_rule() == Rule::group_enum);
+    VdevType::from_str(group_enum.as_str()).expect("Failed to parse group type")
+}
+
 #[inline]
 fn get_path_from_path(path: Option<Pair<'_, Rule>>) -> PathBuf {
     let path = path.expect("Missing path from disk line");
@@ -208,34 +739,92 @@ fn get_disk_from_disk_line(disk_line: Pair<'_, Rule>) -> Disk {
 
     let mut inner = disk_line.into_inner();
 
-    let path = get_path_from_path(inner.next());
+    let name = get_path_from_path(inner.next());
     let health = get_health_from_health(inner.next());
 
-    let (error_statics, reason) = get_stats_and_reason_from_pairs(inner);
+    let annotations = get_annotations_from_pairs(inner);
+    // Missing devices are reported by their GUID.
+    let guid = name.to_str().and_then(|name| name.parse::<u64>().ok());
+    let path = match (guid, &annotations.was) {
+        (Some(_), Some(was)) => was.clone(),
+        _ => name,
+    };
     Disk::builder()
         .path(path)
+        .guid(guid)
         .health(health)
-        .error_statistics(error_statics)
-        .reason(reason)
+        .error_statistics(annotations.error_statistics)
+        .reason(annotations.reason)
+        .was(annotations.was)
+        .resilver(annotations.resilver)
         .build()
         .expect("Failed to build disk")
 }
 
+/// Everything that can follow device state on a line of `zpool status`.
+#[derive(Default)]
+struct Annotations {
+    error_statistics: ErrorStatistics,
+    reason: Option<Reason>,
+    was: Option<PathBuf>,
+    resilver: Option<ResilverStatus>,
+}
+
 #[inline]
 #[allow(clippy::option_unwrap_used, clippy::wildcard_enum_match_arm)]
-fn get_stats_and_reason_from_pairs(pairs: Pairs<'_, Rule>) -> (ErrorStatistics, Option<Reason>) {
-    let mut stats = None;
-    let mut reason = None;
+fn get_annotations_from_pairs(pairs: Pairs<'_, Rule>) -> Annotations {
+    let mut annotations = Annotations::default();
     for pair in pairs {
         match pair.as_rule() {
-            Rule::error_statistics => stats = Some(get_error_statistics_from_pair(pair)),
-            Rule::reason => reason = Some(Reason::Other(String::from(pair.as_span().as_str()))),
+            Rule::error_statistics => {
+                annotations.error_statistics = get_error_statistics_from_pair(pair)
+            }
+            Rule::reason => {
+                annotations.reason = Some(Reason::from(pair.as_span().as_str()));
+                if let Some(was_note) = pair.into_inner().next() {
+                    debug_assert!(was_note.as_rule() == Rule::was_note);
+                    annotations.was = Some(get_path_from_path(was_note.into_inner().next()));
+                }
+            }
+            Rule::resilver_note => {
+                let resilver_enum = pair.into_inner().next().unwrap();
+                annotations.resilver = Some(
+                    ResilverStatus::from_str(resilver_enum.as_str())
+                        .expect("Failed to parse resilver status"),
+                );
+            }
             _ => {
                 unreachable!();
             }
         }
     }
-    (stats.unwrap_or_default(), reason)
+    annotations
+}
+
+#[inline]
+fn get_stats_and_reason_from_pairs(pairs: Pairs<'_, Rule>) -> (ErrorStatistics, Option<Reason>) {
+    let annotations = get_annotations_from_pairs(pairs);
+    (annotations.error_statistics, annotations.reason)
+}
+
+#[inline]
+#[allow(clippy::option_unwrap_used)]
+fn get_vdev_from_disk_group(disk_group: Pair<'_, Rule>) -> Vdev {
+    debug_assert!(disk_group.as_rule() == Rule::disk_group);
+    let mut inner = disk_group.into_inner();
+    let mut group_line = inner.next().unwrap().into_inner();
+    let group_name = group_line.next().unwrap();
+    let health = get_health_from_health(group_line.next());
+    let (error_statics, reason) = get_stats_and_reason_from_pairs(group_line);
+
+    Vdev::builder()
+        .kind(get_group_type(group_name))
+        .health(health)
+        .disks(inner.map(get_disk_from_disk_line).collect())
+        .error_statistics(error_statics)
+        .reason(reason)
+        .build()
+        .expect("Failed to build vdev")
 }
 
 #[inline]
@@ -253,7 +842,7 @@ fn get_vdevs_from_pair(pair: Pair<'_, Rule>) -> Vec<Vdev> {
                 Vdev::builder()
                     .kind(VdevType::SingleDisk)
                     .health(disk.health().clone())
-                    .reason(None)
+                    .reason(disk.reason().clone())
                     .disks(vec![disk])
                     .build()
                     .expect("Failed to build Vdev")
@@ -269,15 +858,31 @@ fn get_vdevs_from_pair(pair: Pair<'_, Rule>) -> Vec<Vdev> {
 
                 let (error_statics, reason) = get_stats_and_reason_from_pairs(raid_line);
 
+                let mut disks = Vec::new();
+                let mut groups = Vec::new();
+                for member in inner {
+                    match member.as_rule() {
+                        Rule::disk_line => disks.push(get_disk_from_disk_line(member)),
+                        Rule::disk_group => {
+                            let group = get_vdev_from_disk_group(member);
+                            disks.extend(group.disks().iter().cloned());
+                            groups.push(group);
+                        }
+                        _ => unreachable!(),
+                    }
+                }
+
                 Vdev::builder()
                     .kind(get_vdev_type(raid_name))
                     .health(health)
-                    .disks(inner.map(get_disk_from_disk_line).collect())
+                    .disks(disks)
                     .error_statistics(error_statics)
                     .reason(reason)
+                    .groups(groups)
                     .build()
                     .expect("Failed to build vdev")
             }
+            Rule::disk_group => get_vdev_from_disk_group(vdev),
             _ => {
                 unreachable!();
             }
@@ -285,6 +890,48 @@ fn get_vdevs_from_pair(pair: Pair<'_, Rule>) -> Vec<Vdev> {
         .collect()
 }
 
+#[inline]
+fn get_disk_from_dry_disk(dry_disk: Pair<'_, Rule>) -> Disk {
+    debug_assert!(dry_disk.as_rule() == Rule::dry_disk);
+    Disk::builder()
+        .path(get_path_from_path(dry_disk.into_inner().next()))
+        .health(Health::Online)
+        .build()
+        .expect("Failed to build disk")
+}
+
+#[inline]
+fn get_disks_from_dry_disks(pair: Pair<'_, Rule>) -> Vec<Disk> {
+    debug_assert!(pair.as_rule() == Rule::dry_disks);
+    pair.into_inner().map(get_disk_from_dry_disk).collect()
+}
+
+#[inline]
+#[allow(clippy::option_unwrap_used, clippy::wildcard_enum_match_arm)]
+fn get_vdevs_from_dry_vdevs(pair: Pair<'_, Rule>) -> Vec<Vdev> {
+    debug_assert!(pair.as_rule() == Rule::dry_vdevs);
+    pair.into_inner()
+        .map(|vdev| {
+            let (kind, disks) = match vdev.as_rule() {
+                Rule::dry_disk => (VdevType::SingleDisk, vec![get_disk_from_dry_disk(vdev)]),
+                Rule::dry_raided_vdev => {
+                    let mut inner = vdev.into_inner();
+                    let kind = get_vdev_type(inner.next().unwrap());
+                    (kind, inner.map(get_disk_from_dry_disk).collect())
+                }
+                _ => unreachable!(),
+            };
+            Vdev::builder()
+                .kind(kind)
+                .health(Health::Online)
+                .reason(None)
+                .disks(disks)
+                .build()
+                .expect("Failed to build vdev")
+        })
+        .collect()
+}
+
 #[inline]
 fn get_health_from_pair(pair: Pair<'_, Rule>) -> Health {
     let health = get_string_from_pair(pair);
@@ -299,6 +946,11 @@ fn get_u64_from_pair(pair: Pair<'_, Rule>) -> u64 {
         .expect("Failed to unwrap u64")
 }
 
+/// Join a message `zpool` wrapped over several lines into one line with single spaces.
+pub(crate) fn unwrap_text(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
 #[inline]
 fn get_string_from_pair(pair: Pair<'_, Rule>) -> String {
     String::from(get_value_from_pair(pair).as_str())
@@ -312,18 +964,28 @@ fn get_value_from_pair(pair: Pair<'_, Rule>) -> Pair<'_, Rule> {
 
 #[inline]
 #[allow(clippy::option_unwrap_used, clippy::wildcard_enum_match_arm)]
-fn get_error_from_pair(pair: Pair<'_, Rule>) -> Option<String> {
+fn get_errors_from_pair(pair: Pair<'_, Rule>) -> (Option<String>, Vec<PermanentError>) {
     let mut pairs = pair.into_inner();
     let error_pair = pairs.next().expect("Failed to unwrap error");
-    match error_pair.as_rule() {
+    let errors = match error_pair.as_rule() {
         Rule::no_errors => None,
         _ => Some(String::from(error_pair.as_str())),
-    }
+    };
+    let permanent_errors = pairs
+        .flat_map(Pair::into_inner)
+        .map(|file| PermanentError::from_entry(file.as_str()))
+        .collect();
+    (errors, permanent_errors)
 }
 
 #[inline]
 fn get_logs_from_pair(pair: Pair<'_, Rule>) -> Vec<Vdev> {
     debug_assert!(pair.as_rule() == Rule::logs);
+    get_vdevs_from_section(pair)
+}
+
+#[inline]
+fn get_vdevs_from_section(pair: Pair<'_, Rule>) -> Vec<Vdev> {
     if let Some(vdevs) = pair.into_inner().next() {
         get_vdevs_from_pair(vdevs)
     } else {
@@ -331,6 +993,18 @@ fn get_logs_from_pair(pair: Pair<'_, Rule>) -> Vec<Vdev> {
     }
 }
 
+#[inline]
+#[allow(clippy::option_unwrap_used)]
+fn get_other_section_from_pair(pair: Pair<'_, Rule>) -> (String, String) {
+    debug_assert!(pair.as_rule() == Rule::other_section);
+    let mut inner = pair.into_inner();
+    let name = inner.next().unwrap();
+    debug_assert!(name.as_rule() == Rule::section_name);
+    let body = inner.next().unwrap();
+    debug_assert!(body.as_rule() == Rule::section_body);
+    (String::from(name.as_str()), String::from(body.as_str()))
+}
+
 #[inline]
 fn get_caches_from_pair(pair: Pair<'_, Rule>) -> Vec<Disk> {
     debug_assert!(pair.as_rule() == Rule::caches);
@@ -347,9 +1021,365 @@ fn get_spares_from_pair(pair: Pair<'_, Rule>) -> Vec<Disk> {
 mod test {
     use std::path::PathBuf;
 
-    use crate::zpool::{CreateVdevRequest, Disk, Health, Vdev, VdevType};
+    use pest::Parser;
+
+    use crate::{
+        parsers::{Rule, StdoutParser},
+        zpool::{CreateVdevRequest, Disk, Health, Vdev, VdevType, ZpoolError},
+    };
+
+    use super::{
+        parse_scan_date, CreateZpoolRequest, DiskRole, PermanentError, Reason, ScanStatus, Zpool,
+    };
+
+    fn parse_zpool(stdout: &str) -> Zpool {
+        let mut pairs = StdoutParser::parse(Rule::zpools, stdout).unwrap();
+        Zpool::from_pest_pair(pairs.next().unwrap())
+    }
+
+    #[test]
+    fn parse_status_of_captured_output() {
+        let zpools = Zpool::parse_status(include_str!("fixtures/status_with_spares")).unwrap();
+        assert_eq!(1, zpools.len());
+        assert_eq!("tank", zpools[0].name());
+
+        assert_eq!(Vec::<Zpool>::new(), Zpool::parse_status("").unwrap());
+        let text = "  pool: tank\n state: who knows\n";
+        match Zpool::parse_status(text) {
+            Err(ZpoolError::UnparsedStatus(stdout, span)) => {
+                assert_eq!(text, stdout);
+                assert_eq!(" state: who knows", &text[span]);
+            }
+            other => panic!("Expected unparsed output, got {:?}", other),
+        }
+
+        // Second pool is cut short, the first one must not be returned alone.
+        let good = include_str!("fixtures/status_with_spares");
+        let text = format!("{}\n  pool: broken\n state: ONLINE\nconfig\n", good);
+        match Zpool::parse_status(&text) {
+            Err(ZpoolError::UnparsedStatus(_, span)) => {
+                assert!(span.start > good.len(), "{:?}", span);
+                assert_eq!("config", &text[span]);
+            }
+            other => panic!("Expected unparsed output, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn permanent_errors() {
+        let zpools =
+            Zpool::parse_status(include_str!("fixtures/status_with_permanent_errors")).unwrap();
+        assert_eq!(2, zpools.len());
+        let expected = vec![
+            PermanentError::File {
+                dataset: None,
+                path: PathBuf::from("/tank/data/photo.jpg"),
+            },
+            PermanentError::File {
+                dataset: Some(String::from("tank/backup")),
+                path: PathBuf::from("/db/dump 1:2.sql"),
+            },
+            PermanentError::Object {
+                dataset: String::from("tank/data"),
+                object: 5,
+            },
+            PermanentError::DeletedDatasetObject {
+                dataset: 0x1a3b,
+                object: 4,
+            },
+            PermanentError::Metadata { object: 0 },
+        ];
+        assert_eq!(&expected, zpools[0].permanent_errors());
+        assert!(zpools[0].errors().is_some());
+
+        assert_eq!("zroot", zpools[1].name());
+        assert!(zpools[1].permanent_errors().is_empty());
+        assert_eq!(&None, zpools[1].errors());
+
+        let zpool = parse_zpool(include_str!("fixtures/status_with_missing_device"));
+        assert!(zpool.permanent_errors().is_empty());
+
+        assert_eq!(
+            PermanentError::Other(String::from("tank/data:<0xnope>")),
+            PermanentError::from_entry("tank/data:<0xnope>")
+        );
+    }
+
+    #[test]
+    fn reasons_of_top_level_vdevs() {
+        let zpool = parse_zpool(include_str!("fixtures/status_with_unavailable_raidz"));
+        assert_eq!(&Health::Unavailable, zpool.health());
+        assert_eq!(&Some(Reason::InsufficientReplicas), zpool.reason());
+
+        let vdevs = zpool.vdevs();
+        assert_eq!(3, vdevs.len());
+        assert_eq!(&Health::Online, vdevs[0].health());
+        assert_eq!(&None, vdevs[0].reason());
 
-    use super::{CreateZpoolRequest, Zpool};
+        let raidz = &vdevs[1];
+        assert_eq!(&VdevType::RaidZ, raidz.kind());
+        assert_eq!(&Health::Unavailable, raidz.health());
+        assert_eq!(&Some(Reason::InsufficientReplicas), raidz.reason());
+        let healths: Vec<&Health> = raidz.disks().iter().map(Disk::health).collect();
+        assert_eq!(
+            vec![&Health::Online, &Health::Unavailable, &Health::Unavailable],
+            healths
+        );
+        assert_eq!(
+            &Some(Reason::Other(String::from("was /vdevs/vdev3"))),
+            raidz.disks()[1].reason()
+        );
+
+        // Single disk vdev shares reason with its disk.
+        assert_eq!(&VdevType::SingleDisk, vdevs[2].kind());
+        assert_eq!(&Some(Reason::CorruptedData), vdevs[2].reason());
+        assert_eq!(&Some(Reason::CorruptedData), vdevs[2].disks()[0].reason());
+
+        assert_eq!(
+            "insufficient replicas",
+            Reason::InsufficientReplicas.as_str()
+        );
+        assert_eq!(Reason::CorruptedData, Reason::from("corrupted data"));
+        assert_eq!(
+            Reason::Other(String::from("cannot open")),
+            Reason::from("cannot open")
+        );
+    }
+
+    #[test]
+    fn all_disks_with_roles() {
+        let zpool = parse_zpool(include_str!("fixtures/status_replacing_linux"));
+        let disks = zpool.all_disks();
+        assert_eq!(6, disks.len());
+        assert!(disks[..5].iter().all(|(role, _)| *role == DiskRole::Data));
+        assert_eq!(DiskRole::Spare, disks[5].0);
+        assert_eq!(disks[4].1.path(), disks[5].1.path());
+
+        let zpool = parse_zpool(include_str!("fixtures/status_with_special_linux"));
+        let roles: Vec<DiskRole> = zpool.all_disks().into_iter().map(|(r, _)| r).collect();
+        let count = |role| roles.iter().filter(|r| **r == role).count();
+        assert_eq!(
+            zpool.vdevs().iter().map(|v| v.disks().len()).sum::<usize>(),
+            count(DiskRole::Data)
+        );
+        assert_eq!(
+            zpool.logs().iter().map(|v| v.disks().len()).sum::<usize>(),
+            count(DiskRole::Log)
+        );
+        assert_eq!(
+            zpool
+                .special_vdevs()
+                .iter()
+                .map(|v| v.disks().len())
+                .sum::<usize>(),
+            count(DiskRole::Special)
+        );
+        assert_eq!(
+            zpool
+                .dedup_vdevs()
+                .iter()
+                .map(|v| v.disks().len())
+                .sum::<usize>(),
+            count(DiskRole::Dedup)
+        );
+        assert_eq!(zpool.caches().len(), count(DiskRole::Cache));
+        assert_eq!(zpool.spares().len(), count(DiskRole::Spare));
+        assert!(count(DiskRole::Special) > 0);
+        assert!(count(DiskRole::Dedup) > 0);
+    }
+
+    #[test]
+    fn fault_tolerance() {
+        let zpool = parse_zpool(include_str!("fixtures/status_replacing_linux"));
+        assert_eq!(Some(1), zpool.fault_tolerance());
+
+        let zpool = parse_zpool(include_str!("fixtures/status_with_missing_device"));
+        assert_eq!(Some(1), zpool.fault_tolerance());
+
+        let disk = |path: &str| {
+            Disk::builder()
+                .path(path)
+                .health(Health::Online)
+                .build()
+                .unwrap()
+        };
+        let vdev = |kind, disks: &[&str]| {
+            Vdev::builder()
+                .kind(kind)
+                .health(Health::Online)
+                .disks(disks.iter().map(|d| disk(d)).collect::<Vec<_>>())
+                .build()
+                .unwrap()
+        };
+        let zpool = Zpool::builder()
+            .name("tank")
+            .health(Health::Online)
+            .vdevs(vec![
+                vdev(VdevType::Mirror, &["a", "b", "c"]),
+                vdev(VdevType::RaidZ2, &["d", "e", "f", "g", "h"]),
+            ])
+            .logs(vec![vdev(VdevType::SingleDisk, &["i"])])
+            .build()
+            .unwrap();
+        assert_eq!(Some(2), zpool.fault_tolerance());
+        assert_eq!(2, zpool.vdevs()[0].fault_tolerance());
+
+        // Mirror with a `replacing-N` group: two sides, whether or not devices of the group are
+        // listed among devices of the mirror.
+        let replacing = vdev(VdevType::Replacing, &["b", "c"]);
+        let mirror = Vdev::builder()
+            .kind(VdevType::Mirror)
+            .health(Health::Online)
+            .disks(vec![disk("a"), disk("b"), disk("c")])
+            .groups(vec![replacing.clone()])
+            .build()
+            .unwrap();
+        assert_eq!(1, mirror.fault_tolerance());
+        let mirror = Vdev::builder()
+            .kind(VdevType::Mirror)
+            .health(Health::Online)
+            .disks(vec![disk("a")])
+            .groups(vec![replacing.clone()])
+            .build()
+            .unwrap();
+        assert_eq!(1, mirror.fault_tolerance());
+        let mirror = Vdev::builder()
+            .kind(VdevType::Mirror)
+            .health(Health::Online)
+            .disks(Vec::new())
+            .groups(vec![replacing])
+            .build()
+            .unwrap();
+        assert_eq!(0, mirror.fault_tolerance());
+
+        let zpool = Zpool::builder()
+            .name("tank")
+            .health(Health::Online)
+            .vdevs(vec![vdev(VdevType::RaidZ3, &["a", "b", "c", "d", "e"])])
+            .special_vdevs(vec![vdev(VdevType::SingleDisk, &["f"])])
+            .build()
+            .unwrap();
+        assert_eq!(Some(0), zpool.fault_tolerance());
+
+        let zpool = Zpool::builder()
+            .name("tank")
+            .health(Health::Online)
+            .vdevs(Vec::new())
+            .build()
+            .unwrap();
+        assert_eq!(None, zpool.fault_tolerance());
+    }
+
+    #[test]
+    fn enrich_devices() {
+        use std::{cell::RefCell, path::Path};
+
+        use crate::zpool::{
+            device_metadata::{DeviceMetadata, DeviceMetadataResolver},
+            ZpoolErrorKind, ZpoolResult,
+        };
+
+        struct Resolver(RefCell<Vec<PathBuf>>, RefCell<usize>);
+        impl DeviceMetadataResolver for Resolver {
+            fn resolve_all(&self, devices: &[PathBuf]) -> Vec<ZpoolResult<DeviceMetadata>> {
+                *self.1.borrow_mut() += 1;
+                devices.iter().map(|device| self.resolve(device)).collect()
+            }
+
+            fn resolve(&self, device: &Path) -> ZpoolResult<DeviceMetadata> {
+                self.0.borrow_mut().push(device.to_path_buf());
+                let name = device.to_str().unwrap();
+                if name.ends_with("0003") {
+                    return Err(ZpoolError::DeviceNotFound(device.to_path_buf()));
+                }
+                let serial = name.rsplit('_').next().unwrap().to_string();
+                Ok(DeviceMetadata::new(
+                    Some(String::from("WDC WD40EFRX-68N32N0")),
+                    Some(serial),
+                ))
+            }
+        }
+
+        let mut zpool = parse_zpool(include_str!("fixtures/status_replacing_linux"));
+        let resolver = Resolver(RefCell::new(Vec::new()), RefCell::new(0));
+        let failed = zpool.enrich_devices(&resolver);
+        assert_eq!(1, failed.len());
+        assert_eq!(
+            PathBuf::from("ata-WDC_WD40EFRX-68N32N0_WD-WCC7K0000003"),
+            failed[0].0
+        );
+        assert_eq!(ZpoolErrorKind::DeviceNotFound, failed[0].1.kind());
+        // Hot spare is listed both in the vdev and in spares, but only resolved once.
+        assert_eq!(5, resolver.0.borrow().len());
+        assert_eq!(1, *resolver.1.borrow());
+
+        let serials: Vec<Option<&str>> = zpool
+            .all_disks()
+            .into_iter()
+            .map(|(_, disk)| disk.serial().as_deref())
+            .collect();
+        assert_eq!(
+            vec![
+                Some("WD-WCC7K0000001"),
+                Some("WD-WCC7K0000002"),
+                Some("WD-WCC7K0000004"),
+                None,
+                Some("WD-WCC7K0000005"),
+                Some("WD-WCC7K0000005"),
+            ],
+            serials
+        );
+        let group = &zpool.vdevs()[0].groups()[0];
+        assert_eq!(
+            &Some(String::from("WD-WCC7K0000004")),
+            group.disks()[1].serial()
+        );
+        assert_eq!(
+            &Some(String::from("WDC WD40EFRX-68N32N0")),
+            group.disks()[1].model()
+        );
+
+        // Files and devices known only by GUID are skipped.
+        let mut zpool = parse_zpool(include_str!("fixtures/status_with_missing_device"));
+        let resolver = Resolver(RefCell::new(Vec::new()), RefCell::new(0));
+        assert!(zpool.enrich_devices(&resolver).is_empty());
+        assert!(resolver.0.borrow().is_empty());
+        assert!(zpool
+            .all_disks()
+            .iter()
+            .all(|(_, disk)| disk.model().is_none()));
+    }
+
+    #[test]
+    fn degraded_devices() {
+        let zpool = parse_zpool(include_str!("fixtures/status_replacing_linux"));
+        let degraded: Vec<&str> = zpool
+            .degraded_devices()
+            .into_iter()
+            .map(|d| d.path().to_str().unwrap())
+            .collect();
+        assert_eq!(
+            vec![
+                "ata-WDC_WD40EFRX-68N32N0_WD-WCC7K0000002",
+                "ata-WDC_WD40EFRX-68N32N0_WD-WCC7K0000003"
+            ],
+            degraded
+        );
+
+        let zpool = parse_zpool(include_str!("fixtures/status_with_missing_device"));
+        let degraded: Vec<(&Health, Option<u64>)> = zpool
+            .degraded_devices()
+            .into_iter()
+            .map(|d| (d.health(), *d.guid()))
+            .collect();
+        assert_eq!(
+            vec![
+                (&Health::Offline, Some(14808325297596192025)),
+                (&Health::Unavailable, Some(9123456789012345678))
+            ],
+            degraded
+        );
+    }
 
     #[test]
     fn test_eq_zpool() {
@@ -399,4 +1429,64 @@ mod test {
             .unwrap();
         assert_ne!(request, zpool);
     }
+
+    #[test]
+    fn test_scan_status() {
+        let finished_at = parse_scan_date("Sat Mar  4 01:12:20 2023").unwrap();
+        assert_eq!(
+            ScanStatus::ScrubFinished {
+                errors: 0,
+                finished_at
+            },
+            ScanStatus::from_scan_text(
+                "scrub repaired 1M in 15:03:34 with 0 errors on Sat Mar  4 01:12:20 2023\n"
+            )
+        );
+        let finished_at = parse_scan_date("Tue Nov 28 02:04:11 2017").unwrap();
+        assert_eq!(
+            ScanStatus::ScrubFinished {
+                errors: 2,
+                finished_at
+            },
+            ScanStatus::from_scan_text(
+                "scrub repaired 0 in 0 days 00:00:00 with 2 errors on Tue Nov 28 02:04:11 2017\n"
+            )
+        );
+        let finished_at = parse_scan_date("Thu Jan 19 12:32:16 2023").unwrap();
+        assert_eq!(
+            ScanStatus::ResilverFinished {
+                errors: 0,
+                finished_at
+            },
+            ScanStatus::from_scan_text(
+                "resilvered 5.00G in 00:01:16 with 0 errors on Thu Jan 19 12:32:16 2023\n"
+            )
+        );
+
+        let since = parse_scan_date("Tue Aug 13 23:03:11 2019").unwrap();
+        let scan = ScanStatus::from_scan_text("scrub in progress since Tue Aug 13 23:03:11 2019\n\t42.5K scanned at 42.5K/s, 80K issued at 80K/s, 83K total\n");
+        assert_eq!(ScanStatus::ScrubInProgress { since }, scan);
+        assert!(scan.is_scrubbing());
+        assert_eq!(None, scan.last_scrub_finished_at());
+        assert_eq!(
+            ScanStatus::ResilverInProgress { since },
+            ScanStatus::from_scan_text("resilver in progress since Tue Aug 13 23:03:11 2019\n")
+        );
+        assert_eq!(
+            ScanStatus::ScrubPaused { since },
+            ScanStatus::from_scan_text("scrub paused since Tue Aug 13 23:03:11 2019\n")
+        );
+        assert_eq!(
+            ScanStatus::ScrubCanceled { at: since },
+            ScanStatus::from_scan_text("scrub canceled on Tue Aug 13 23:03:11 2019\n")
+        );
+        assert_eq!(
+            ScanStatus::NoneRequested,
+            ScanStatus::from_scan_text("none requested\n")
+        );
+        assert_eq!(
+            ScanStatus::Other(String::from("trim in progress")),
+            ScanStatus::from_scan_text("trim in progress\n")
+        );
+    }
 }
@@ -0,0 +1,222 @@
+//! Sector sizes of block devices.
+//!
+//! Mixing 512e and 4Kn disks in one vdev silently costs a lot of performance: a vdev with
+//! `ashift` smaller than physical sector size of its disk does read-modify-write on every small
+//! write. [`sector_size`](fn.sector_size.html) tells what a device reports, so it can be compared
+//! with `ashift` of the pool before device is added. On Linux it's read from `/sys/class/block`, on
+//! FreeBSD from `sysctl kern.geom.confxml`.
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use regex::Regex;
+use slog::Logger;
+
+#[cfg(target_os = "freebsd")]
+use crate::{instrumentation, GlobalLogger};
+use crate::{
+    instrumentation::InstrumentationHook,
+    zpool::{ZpoolError, ZpoolResult},
+};
+
+/// Sector sizes of a block device in bytes.
+#[derive(Debug, Clone, Copy, Getters, PartialEq, Eq)]
+#[get = "pub"]
+pub struct SectorSize {
+    /// Smallest unit device can address.
+    logical: u32,
+    /// Smallest unit device can write without read-modify-write.
+    physical: u32,
+}
+
+impl SectorSize {
+    pub fn new(logical: u32, physical: u32) -> SectorSize {
+        SectorSize { logical, physical }
+    }
+
+    /// Smallest `ashift` that matches physical sector size.
+    pub fn ashift(&self) -> u8 {
+        // Physical sector size is a power of two.
+        (31 - self.physical.max(1).leading_zeros()) as u8
+    }
+}
+
+/// Name of the device as kernel knows it: symlinks (like `/dev/disk/by-id/...`) are resolved and
+/// `/dev/` is stripped. Bare names are treated as relative to `/dev/`.
+fn kernel_name(device: &Path) -> ZpoolResult<String> {
+    let absolute = if device.is_relative() {
+        Path::new("/dev").join(device)
+    } else {
+        device.to_path_buf()
+    };
+    // `From<io::Error>` treats `NotFound` as missing `zpool`, so I/O errors are wrapped explicitly.
+    let resolved = std::fs::canonicalize(&absolute).map_err(ZpoolError::Io)?;
+    let name = resolved
+        .strip_prefix("/dev")
+        .unwrap_or(&resolved)
+        .to_string_lossy()
+        .into_owned();
+    Ok(name)
+}
+
+/// Sector sizes of the device. Fails if device isn't a block device, for example if it's a file.
+#[cfg(target_os = "linux")]
+pub fn sector_size<P: AsRef<Path>>(device: P) -> ZpoolResult<SectorSize> {
+    let name = kernel_name(device.as_ref())?;
+    sector_size_from_sysfs(Path::new("/sys/class/block"), &name)
+}
+
+/// Same as [`sector_size`](fn.sector_size.html). Nothing is spawned on Linux.
+#[cfg(target_os = "linux")]
+pub(crate) fn sector_size_within(
+    device: &Path,
+    _logger: &Logger,
+    _hook: Option<&Arc<InstrumentationHook>>,
+    _timeout: Option<Duration>,
+) -> ZpoolResult<SectorSize> {
+    sector_size(device)
+}
+
+/// Sector sizes of the device. Fails if device isn't a block device, for example if it's a file.
+#[cfg(target_os = "freebsd")]
+pub fn sector_size<P: AsRef<Path>>(device: P) -> ZpoolResult<SectorSize> {
+    sector_size_within(
+        device.as_ref(),
+        GlobalLogger::get(),
+        None,
+        Some(instrumentation::SYSTEM_COMMAND_TIMEOUT),
+    )
+}
+
+/// Same as [`sector_size`](fn.sector_size.html), but `sysctl` is logged to `logger`, reported to
+/// `hook` and killed if it doesn't exit within `timeout`.
+#[cfg(target_os = "freebsd")]
+pub(crate) fn sector_size_within(
+    device: &Path,
+    logger: &Logger,
+    hook: Option<&Arc<InstrumentationHook>>,
+    timeout: Option<Duration>,
+) -> ZpoolResult<SectorSize> {
+    let name = kernel_name(device)?;
+    let mut sysctl = instrumentation::isolated_command("sysctl");
+    sysctl.args(["-n", "kern.geom.confxml"]);
+    debug!(logger, "executing"; "cmd" => format_args!("{:?}", sysctl));
+    let out = instrumentation::run_command_within(logger, hook, &mut sysctl, timeout)
+        .map_err(ZpoolError::Io)?;
+    if !out.status.success() {
+        return Err(ZpoolError::Other(
+            String::from_utf8_lossy(&out.stderr).into_owned(),
+        ));
+    }
+    sector_size_from_confxml(&String::from_utf8_lossy(&out.stdout), &name)
+}
+
+/// Read `queue/{logical,physical}_block_size` of the device from sysfs. Partitions don't have a
+/// `queue` of their own, so it's taken from the disk they're on.
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+pub(crate) fn sector_size_from_sysfs(
+    sys_class_block: &Path,
+    name: &str,
+) -> ZpoolResult<SectorSize> {
+    let mut dir: PathBuf =
+        std::fs::canonicalize(sys_class_block.join(name)).map_err(ZpoolError::Io)?;
+    if !dir.join("queue").is_dir() && dir.join("partition").is_file() {
+        dir.pop();
+    }
+    let read = |file: &str| -> ZpoolResult<u32> {
+        let raw = std::fs::read_to_string(dir.join("queue").join(file)).map_err(ZpoolError::Io)?;
+        raw.trim().parse().map_err(|_| ZpoolError::ParseError)
+    };
+    Ok(SectorSize {
+        logical: read("logical_block_size")?,
+        physical: read("physical_block_size")?,
+    })
+}
+
+/// Find provider `name` in output of `sysctl kern.geom.confxml`. Physical sector size is
+/// `stripesize` when GEOM knows it and `sectorsize` otherwise.
+#[cfg_attr(not(target_os = "freebsd"), allow(dead_code))]
+pub(crate) fn sector_size_from_confxml(xml: &str, name: &str) -> ZpoolResult<SectorSize> {
+    lazy_static! {
+        static ref RE_PROVIDER: Regex = Regex::new(r#"(?s)<provider id="[^"]*">(.*?)</provider>"#)
+            .expect("failed to compile RE_PROVIDER");
+    }
+    let tag = |body: &str, tag: &str| -> Option<String> {
+        let open = format!("<{}>", tag);
+        let close = format!("</{}>", tag);
+        let start = body.find(&open)? + open.len();
+        let end = body[start..].find(&close)? + start;
+        Some(body[start..end].trim().to_string())
+    };
+    let body = RE_PROVIDER
+        .captures_iter(xml)
+        .filter_map(|caps| caps.get(1))
+        .map(|body| body.as_str())
+        .find(|body| tag(body, "name").as_deref() == Some(name))
+        .ok_or(ZpoolError::NoSuchDevice)?;
+    let number = |name: &str| -> ZpoolResult<u32> {
+        tag(body, name)
+            .and_then(|value| value.parse().ok())
+            .ok_or(ZpoolError::ParseError)
+    };
+    let logical = number("sectorsize")?;
+    let stripe = number("stripesize").unwrap_or(0);
+    Ok(SectorSize {
+        logical,
+        physical: if stripe > logical { stripe } else { logical },
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn sysfs() -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("src/zpool/fixtures/sys_class_block")
+    }
+
+    #[test]
+    fn sysfs_disk_and_partition() {
+        let expected = SectorSize::new(512, 4096);
+        assert_eq!(expected, sector_size_from_sysfs(&sysfs(), "sda").unwrap());
+        assert_eq!(expected, sector_size_from_sysfs(&sysfs(), "sda1").unwrap());
+        assert_eq!(
+            SectorSize::new(4096, 4096),
+            sector_size_from_sysfs(&sysfs(), "nvme0n1").unwrap()
+        );
+        assert_eq!(
+            ZpoolError::Io(std::io::Error::from(std::io::ErrorKind::NotFound)).kind(),
+            sector_size_from_sysfs(&sysfs(), "sdz").unwrap_err().kind()
+        );
+    }
+
+    #[test]
+    fn confxml_providers() {
+        let xml = include_str!("fixtures/geom_confxml");
+        assert_eq!(
+            SectorSize::new(512, 4096),
+            sector_size_from_confxml(xml, "ada0").unwrap()
+        );
+        assert_eq!(
+            SectorSize::new(512, 4096),
+            sector_size_from_confxml(xml, "ada0p3").unwrap()
+        );
+        assert_eq!(
+            SectorSize::new(512, 512),
+            sector_size_from_confxml(xml, "ada1").unwrap()
+        );
+        assert_eq!(
+            ZpoolError::NoSuchDevice.kind(),
+            sector_size_from_confxml(xml, "da9").unwrap_err().kind()
+        );
+    }
+
+    #[test]
+    fn ashift_of_sector_size() {
+        assert_eq!(9, SectorSize::new(512, 512).ashift());
+        assert_eq!(12, SectorSize::new(512, 4096).ashift());
+        assert_eq!(13, SectorSize::new(8192, 8192).ashift());
+    }
+}
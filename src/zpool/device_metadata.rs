@@ -0,0 +1,402 @@
+//! Model and serial number of block devices.
+//!
+//! `zpool status` only knows devices by path, which is not what is printed on the label of a
+//! drive that has to be pulled out of a chassis.
+//! [`Zpool::enrich_devices`](../description/struct.Zpool.html#method.enrich_devices) fills in
+//! model and serial number of every device with a
+//! [`DeviceMetadataResolver`](trait.DeviceMetadataResolver.html). Each platform has a default one,
+//! [`SystemResolver`](struct.SystemResolver.html): on Linux it reads
+//! `/sys/class/block/<dev>/device/{model,serial}` and falls back to names in `/dev/disk/by-id`,
+//! on FreeBSD it asks `geom disk list` once for all devices.
+#[cfg(target_os = "freebsd")]
+use std::time::Duration;
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+#[cfg(target_os = "freebsd")]
+use slog::Logger;
+
+use crate::zpool::ZpoolResult;
+#[cfg(any(target_os = "linux", target_os = "freebsd"))]
+use crate::zpool::{device_resolver, ZpoolError};
+#[cfg(target_os = "freebsd")]
+use crate::{instrumentation, GlobalLogger};
+
+/// What a drive says about itself.
+#[derive(Debug, Clone, Default, Getters, PartialEq, Eq)]
+#[get = "pub"]
+pub struct DeviceMetadata {
+    /// Model of the drive, e.g. `WDC WD40EFRX-68N32N0`.
+    model: Option<String>,
+    /// Serial number of the drive.
+    serial: Option<String>,
+}
+
+impl DeviceMetadata {
+    pub fn new(model: Option<String>, serial: Option<String>) -> DeviceMetadata {
+        DeviceMetadata { model, serial }
+    }
+}
+
+/// Source of [`DeviceMetadata`](struct.DeviceMetadata.html).
+pub trait DeviceMetadataResolver {
+    /// Model and serial number of the drive behind `device`. For a partition it's the drive the
+    /// partition is on. Bare names are treated as relative to `/dev/`.
+    fn resolve(&self, device: &Path) -> ZpoolResult<DeviceMetadata>;
+
+    /// Metadata of every device in `devices`, in the same order.
+    /// [`Zpool::enrich_devices`](../description/struct.Zpool.html#method.enrich_devices) calls it
+    /// once with all devices of the pool, so a resolver that asks the system about every drive at
+    /// once can do it a single time. Calls `resolve` for each device by default.
+    fn resolve_all(&self, devices: &[PathBuf]) -> Vec<ZpoolResult<DeviceMetadata>> {
+        devices.iter().map(|device| self.resolve(device)).collect()
+    }
+}
+
+/// Reads model and serial number from sysfs, falling back to `/dev/disk/by-id` for whatever
+/// sysfs doesn't have. SATA drives behind libata, for example, have no `serial` in sysfs and
+/// their `model` is cut at 16 characters.
+#[cfg(target_os = "linux")]
+#[derive(Debug, Clone)]
+pub struct SystemResolver {
+    dev: PathBuf,
+    sys_class_block: PathBuf,
+}
+
+#[cfg(target_os = "linux")]
+impl Default for SystemResolver {
+    fn default() -> SystemResolver {
+        SystemResolver {
+            dev: PathBuf::from("/dev"),
+            sys_class_block: PathBuf::from("/sys/class/block"),
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl DeviceMetadataResolver for SystemResolver {
+    fn resolve(&self, device: &Path) -> ZpoolResult<DeviceMetadata> {
+        metadata_from_sysfs(&self.dev, &self.sys_class_block, device)
+    }
+}
+
+/// How long `geom disk list` may run by default.
+#[cfg(target_os = "freebsd")]
+const GEOM_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Asks `geom disk list` for description and ident of the disk. `geom` runs once per
+/// [`resolve_all`](trait.DeviceMetadataResolver.html#method.resolve_all) call.
+#[cfg(target_os = "freebsd")]
+#[derive(Debug, Clone)]
+pub struct SystemResolver {
+    logger: Logger,
+    command_timeout: Option<Duration>,
+}
+
+#[cfg(target_os = "freebsd")]
+impl Default for SystemResolver {
+    fn default() -> SystemResolver {
+        let logger = GlobalLogger::get()
+            .new(o!("zetta_module" => "zpool", "zpool_impl" => "device_metadata"));
+        SystemResolver {
+            logger,
+            command_timeout: Some(GEOM_TIMEOUT),
+        }
+    }
+}
+
+#[cfg(target_os = "freebsd")]
+impl SystemResolver {
+    /// Kill `geom` if it doesn't exit within `timeout` and fail with `ZpoolError::TimedOut`.
+    /// Defaults to 30 seconds, `None` waits forever.
+    pub fn set_command_timeout(&mut self, timeout: Option<Duration>) {
+        self.command_timeout = timeout;
+    }
+
+    fn geom_disk_list(&self) -> ZpoolResult<HashMap<String, DeviceMetadata>> {
+        let mut geom = instrumentation::isolated_command("geom");
+        geom.args(["disk", "list"]);
+        debug!(self.logger, "executing"; "cmd" => format_args!("{:?}", geom));
+        let out = instrumentation::run_command_within(
+            &self.logger,
+            None,
+            &mut geom,
+            self.command_timeout,
+        )?;
+        if !out.status.success() {
+            return Err(ZpoolError::Other(
+                String::from_utf8_lossy(&out.stderr).into_owned(),
+            ));
+        }
+        Ok(parse_geom_disk_list(&String::from_utf8_lossy(&out.stdout)))
+    }
+}
+
+#[cfg(target_os = "freebsd")]
+impl DeviceMetadataResolver for SystemResolver {
+    fn resolve(&self, device: &Path) -> ZpoolResult<DeviceMetadata> {
+        let disks = self.geom_disk_list()?;
+        metadata_from_geom(&disks, device)
+    }
+
+    /// If `geom` fails, the first device gets its error and the rest get its message as
+    /// `ZpoolError::Other`.
+    fn resolve_all(&self, devices: &[PathBuf]) -> Vec<ZpoolResult<DeviceMetadata>> {
+        match self.geom_disk_list() {
+            Ok(disks) => devices
+                .iter()
+                .map(|device| metadata_from_geom(&disks, device))
+                .collect(),
+            Err(e) => {
+                let message = e.to_string();
+                let mut first = Some(e);
+                devices
+                    .iter()
+                    .map(|_| {
+                        Err(first
+                            .take()
+                            .unwrap_or_else(|| ZpoolError::Other(message.clone())))
+                    })
+                    .collect()
+            }
+        }
+    }
+}
+
+/// Metadata of the disk `device` is, or is a partition of, among `disks` listed by `geom`.
+#[cfg(target_os = "freebsd")]
+fn metadata_from_geom(
+    disks: &HashMap<String, DeviceMetadata>,
+    device: &Path,
+) -> ZpoolResult<DeviceMetadata> {
+    let not_found = || ZpoolError::DeviceNotFound(device.to_path_buf());
+    let resolved = device_resolver::resolve(device)?;
+    let provider = resolved
+        .canonical()
+        .strip_prefix("/dev")
+        .map_err(|_| not_found())?
+        .to_string_lossy()
+        .into_owned();
+    disk_of_provider(disks, &provider)
+        .cloned()
+        .ok_or_else(not_found)
+}
+
+/// Find `device` under `dev`, read what sysfs knows about the drive it's on and fill the gaps
+/// from its `/dev/disk/by-id` names. Bare names are looked up in `dev` and in every
+/// `dev/disk/by-*`, the way `zpool status` prints them.
+#[cfg(target_os = "linux")]
+pub(crate) fn metadata_from_sysfs(
+    dev: &Path,
+    sys_class_block: &Path,
+    device: &Path,
+) -> ZpoolResult<DeviceMetadata> {
+    let not_found = || ZpoolError::DeviceNotFound(device.to_path_buf());
+    let relative = device.strip_prefix("/dev").unwrap_or(device);
+    if relative.is_absolute() {
+        return Err(not_found());
+    }
+    let by_dirs = std::fs::read_dir(dev.join("disk"))
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_name().to_string_lossy().starts_with("by-"))
+        .map(|entry| Path::new("disk").join(entry.file_name()).join(relative));
+    let node = std::iter::once(relative.to_path_buf())
+        .chain(by_dirs)
+        .find(|candidate| dev.join(candidate).exists())
+        .ok_or_else(not_found)?;
+    let resolved = device_resolver::resolve_in_dev(dev, &node)?;
+    let name = resolved.canonical().file_name().ok_or_else(not_found)?;
+
+    let mut dir = std::fs::canonicalize(sys_class_block.join(name)).map_err(|_| not_found())?;
+    if dir.join("partition").is_file() {
+        dir.pop();
+    }
+    let read = |file: &str| {
+        std::fs::read_to_string(dir.join("device").join(file))
+            .ok()
+            .map(|value| value.trim().to_string())
+            .filter(|value| !value.is_empty())
+    };
+    let mut metadata = DeviceMetadata::new(read("model"), read("serial"));
+    if metadata.model.is_none() || metadata.serial.is_none() {
+        let by_id = resolved
+            .aliases()
+            .iter()
+            .filter(|alias| alias.parent().and_then(Path::file_name) == Some("by-id".as_ref()))
+            .filter_map(|alias| parse_by_id(&alias.file_name()?.to_string_lossy()))
+            .next();
+        if let Some(by_id) = by_id {
+            metadata.model = metadata.model.or(by_id.model);
+            metadata.serial = metadata.serial.or(by_id.serial);
+        }
+    }
+    Ok(metadata)
+}
+
+/// Model and serial number from a `/dev/disk/by-id` name like
+/// `ata-WDC_WD40EFRX-68N32N0_WD-WCC7K1234567` or
+/// `nvme-Samsung_SSD_970_EVO_Plus_1TB_S4EWNX0R123456-part1`: udev joins them with `_` and replaces
+/// spaces in the model with `_` too. Names that don't carry both, like `wwn-*` or
+/// `nvme-eui.*`, give `None`.
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+pub(crate) fn parse_by_id(name: &str) -> Option<DeviceMetadata> {
+    lazy_static! {
+        static ref RE_BY_ID: regex::Regex =
+            regex::Regex::new(r"^(?:ata-|nvme-|scsi-S)(.+)_([^_]+?)(?:-part[0-9]+)?$")
+                .expect("failed to compile RE_BY_ID");
+    }
+    let caps = RE_BY_ID.captures(name)?;
+    Some(DeviceMetadata::new(
+        Some(caps[1].replace('_', " ")),
+        Some(caps[2].to_string()),
+    ))
+}
+
+/// Metadata of every disk by its name from output of `geom disk list`. `descr` is the model and
+/// `ident` is the serial number; `(null)` means disk didn't report one.
+#[cfg_attr(not(target_os = "freebsd"), allow(dead_code))]
+pub(crate) fn parse_geom_disk_list(text: &str) -> HashMap<String, DeviceMetadata> {
+    let mut disks: Vec<(String, DeviceMetadata)> = Vec::new();
+    for line in text.lines() {
+        let line = line.trim();
+        if let Some(name) = line.strip_prefix("Geom name:") {
+            disks.push((name.trim().to_string(), DeviceMetadata::default()));
+            continue;
+        }
+        let (key, value) = match line.find(':') {
+            Some(idx) => (&line[..idx], line[idx + 1..].trim()),
+            None => continue,
+        };
+        let value = match value {
+            "" | "(null)" => None,
+            value => Some(value.to_string()),
+        };
+        if let Some((_, metadata)) = disks.last_mut() {
+            match key {
+                "descr" => metadata.model = value,
+                "ident" => metadata.serial = value,
+                _ => {}
+            }
+        }
+    }
+    disks.into_iter().collect()
+}
+
+/// Disk `provider` is on: the disk itself or the one it's a partition (`ada0p3`, `ada0s1a`) of.
+#[cfg_attr(not(target_os = "freebsd"), allow(dead_code))]
+pub(crate) fn disk_of_provider<'a>(
+    disks: &'a HashMap<String, DeviceMetadata>,
+    provider: &str,
+) -> Option<&'a DeviceMetadata> {
+    disks
+        .iter()
+        .filter(|(disk, _)| match provider.strip_prefix(disk.as_str()) {
+            Some("") => true,
+            Some(rest) => {
+                (rest.starts_with('p') || rest.starts_with('s'))
+                    && rest[1..].starts_with(|c: char| c.is_ascii_digit())
+            }
+            None => false,
+        })
+        .max_by_key(|(disk, _)| disk.len())
+        .map(|(_, metadata)| metadata)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn metadata(model: &str, serial: &str) -> DeviceMetadata {
+        DeviceMetadata::new(Some(model.to_string()), Some(serial.to_string()))
+    }
+
+    #[test]
+    fn by_id_names() {
+        assert_eq!(
+            Some(metadata("WDC WD40EFRX-68N32N0", "WD-WCC7K1234567")),
+            parse_by_id("ata-WDC_WD40EFRX-68N32N0_WD-WCC7K1234567")
+        );
+        assert_eq!(
+            Some(metadata("Samsung SSD 970 EVO Plus 1TB", "S4EWNX0R123456")),
+            parse_by_id("nvme-Samsung_SSD_970_EVO_Plus_1TB_S4EWNX0R123456-part1")
+        );
+        assert_eq!(
+            Some(metadata("SEAGATE ST4000NM0023", "Z1Z2ABCD")),
+            parse_by_id("scsi-SSEAGATE_ST4000NM0023_Z1Z2ABCD")
+        );
+        assert_eq!(None, parse_by_id("wwn-0x50014ee20d7f9e6c"));
+        assert_eq!(None, parse_by_id("nvme-eui.0025385b91b12345"));
+    }
+
+    #[test]
+    fn geom_disk_list() {
+        let disks = parse_geom_disk_list(include_str!("fixtures/geom_disk_list"));
+        let mut names: Vec<&str> = disks.keys().map(String::as_str).collect();
+        names.sort_unstable();
+        assert_eq!(vec!["ada0", "da0", "nvd0"], names);
+
+        let ada0 = metadata("WDC WD40EFRX-68N32N0", "WD-WCC7K1234567");
+        assert_eq!(Some(&ada0), disk_of_provider(&disks, "ada0"));
+        assert_eq!(Some(&ada0), disk_of_provider(&disks, "ada0p3"));
+        assert_eq!(
+            Some(&metadata("Samsung SSD 970 EVO Plus 1TB", "S4EWNX0R123456")),
+            disk_of_provider(&disks, "nvd0p2")
+        );
+        assert_eq!(
+            Some(&DeviceMetadata::new(
+                Some("Generic Flash Disk".into()),
+                None
+            )),
+            disk_of_provider(&disks, "da0s1a")
+        );
+        assert_eq!(None, disk_of_provider(&disks, "ada1p3"));
+        assert_eq!(None, disk_of_provider(&disks, "ada0.eli"));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn sysfs_with_by_id_fallback() {
+        use std::os::unix::fs::symlink;
+
+        let sysfs =
+            Path::new(env!("CARGO_MANIFEST_DIR")).join("src/zpool/fixtures/sys_class_block");
+        let dev = tempfile::tempdir().unwrap();
+        let by_id = dev.path().join("disk/by-id");
+        std::fs::create_dir_all(&by_id).unwrap();
+        for name in &["sda", "sda1", "nvme0n1"] {
+            std::fs::write(dev.path().join(name), b"").unwrap();
+        }
+        symlink(
+            "../../sda",
+            by_id.join("ata-WDC_WD40EFRX-68N32N0_WD-WCC7K1234567"),
+        )
+        .unwrap();
+        symlink(
+            "../../sda1",
+            by_id.join("ata-WDC_WD40EFRX-68N32N0_WD-WCC7K1234567-part1"),
+        )
+        .unwrap();
+        symlink("../../sda1", by_id.join("wwn-0x50014ee20d7f9e6c-part1")).unwrap();
+        let resolve = |device: &str| metadata_from_sysfs(dev.path(), &sysfs, Path::new(device));
+
+        // Model comes from sysfs even though it's cut, serial only from by-id.
+        let sda = DeviceMetadata::new(
+            Some("WDC WD40EFRX-68N".into()),
+            Some("WD-WCC7K1234567".into()),
+        );
+        assert_eq!(sda, resolve("sda").unwrap());
+        assert_eq!(sda, resolve("/dev/sda1").unwrap());
+        assert_eq!(sda, resolve("wwn-0x50014ee20d7f9e6c-part1").unwrap());
+        assert_eq!(
+            metadata("Samsung SSD 970 EVO Plus 1TB", "S4EWNX0R123456"),
+            resolve("nvme0n1").unwrap()
+        );
+
+        assert!(resolve("sdb").is_err());
+        assert!(resolve("/var/tmp/disk.img").is_err());
+    }
+}
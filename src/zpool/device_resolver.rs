@@ -0,0 +1,337 @@
+//! Every name a block device goes by.
+//!
+//! The same disk can be `sda`, `/dev/disk/by-id/wwn-...` and `/dev/disk/by-path/...` on Linux or
+//! `ada0p3`, `gpt/zfs-data-0` and `gptid/...` on FreeBSD. `zpool create` only notices that one
+//! disk is listed twice under different names when it's halfway through, with a confusing error.
+//! [`resolve`](fn.resolve.html) maps a name to the device behind it and its other names: on Linux
+//! by following symlinks in `/dev/disk/by-*`, on FreeBSD by asking `glabel status`.
+//!
+//! Files are resolved to their canonical path and have no aliases.
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use crate::zpool::{ZpoolError, ZpoolResult};
+#[cfg(target_os = "freebsd")]
+use crate::{instrumentation, GlobalLogger};
+
+/// Device behind a name.
+#[derive(Debug, Clone, Getters, PartialEq, Eq)]
+#[get = "pub"]
+pub struct Resolved {
+    /// Name kernel knows the device by, e.g. `/dev/sda1` or `/dev/ada0p3`.
+    canonical: PathBuf,
+    /// Other names of the same device, e.g. `/dev/disk/by-id/wwn-...` or `/dev/gpt/zfs-data-0`,
+    /// sorted.
+    aliases: Vec<PathBuf>,
+}
+
+/// Device behind `device` and its other names. Bare names are looked up in `/dev/` and then in
+/// `/dev/disk/by-id/` and other `/dev/disk/by-*` directories, same as `zpool` does, so
+/// `ata-XXX` works. Fails with `DeviceNotFound` if there is no such device.
+#[cfg(target_os = "linux")]
+pub fn resolve<P: AsRef<Path>>(device: P) -> ZpoolResult<Resolved> {
+    resolve_in_dev(Path::new("/dev"), device.as_ref())
+}
+
+/// Device behind `device` and its other names. Bare names are treated as relative to `/dev/`.
+/// Fails with `DeviceNotFound` if there is no such device.
+#[cfg(target_os = "freebsd")]
+pub fn resolve<P: AsRef<Path>>(device: P) -> ZpoolResult<Resolved> {
+    let mut glabel = instrumentation::isolated_command("glabel");
+    glabel.arg("status");
+    let out = instrumentation::run_command_within(
+        GlobalLogger::get(),
+        None,
+        &mut glabel,
+        Some(instrumentation::SYSTEM_COMMAND_TIMEOUT),
+    )
+    .map_err(ZpoolError::Io)?;
+    if !out.status.success() {
+        return Err(ZpoolError::Other(
+            String::from_utf8_lossy(&out.stderr).into_owned(),
+        ));
+    }
+    let labels = parse_glabel_status(&String::from_utf8_lossy(&out.stdout));
+    resolve_with_labels(&labels, device.as_ref(), |path| path.exists())
+}
+
+/// Resolve every device and make sure none of them is listed twice, under the same name or not.
+pub(crate) fn check_unique<'a, I, F>(devices: I, resolve: F) -> ZpoolResult<()>
+where
+    I: IntoIterator<Item = &'a PathBuf>,
+    F: Fn(&Path) -> ZpoolResult<Resolved>,
+{
+    let mut seen: HashMap<PathBuf, &PathBuf> = HashMap::new();
+    for device in devices {
+        let resolved = resolve(device)?;
+        if let Some(first) = seen.insert(resolved.canonical, device) {
+            return Err(ZpoolError::DuplicateDevice(first.clone(), device.clone()));
+        }
+    }
+    Ok(())
+}
+
+/// Resolve `device` with symlinks under `dev`: the device itself and `disk/by-*/*`.
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+pub(crate) fn resolve_in_dev(dev: &Path, device: &Path) -> ZpoolResult<Resolved> {
+    let not_found = || ZpoolError::DeviceNotFound(device.to_path_buf());
+    let mut by_dirs: Vec<PathBuf> = std::fs::read_dir(dev.join("disk"))
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_name().to_string_lossy().starts_with("by-"))
+        .map(|entry| entry.path())
+        .collect();
+    // `by-id` names are the most common bare names in configs, they win over other directories.
+    by_dirs.sort_by_key(|dir| (!dir.ends_with("by-id"), dir.clone()));
+    let candidates = std::iter::once(dev.join(device));
+    let candidates = candidates.chain(
+        by_dirs
+            .iter()
+            .filter(|_| device.is_relative())
+            .map(|dir| dir.join(device)),
+    );
+    let canonical = candidates
+        .filter_map(|candidate| std::fs::canonicalize(candidate).ok())
+        .next()
+        .ok_or_else(not_found)?;
+    let mut aliases = Vec::new();
+    for by_dir in by_dirs {
+        let links = std::fs::read_dir(by_dir)
+            .into_iter()
+            .flatten()
+            .filter_map(|entry| entry.ok());
+        for link in links {
+            if std::fs::canonicalize(link.path()).ok().as_ref() == Some(&canonical) {
+                aliases.push(link.path());
+            }
+        }
+    }
+    aliases.sort();
+    Ok(Resolved { canonical, aliases })
+}
+
+/// Pairs of label and provider it points to from output of `glabel status`. Labels spanning
+/// several providers only get the first one.
+#[cfg_attr(not(target_os = "freebsd"), allow(dead_code))]
+pub(crate) fn parse_glabel_status(text: &str) -> Vec<(String, String)> {
+    text.lines()
+        .filter_map(|line| {
+            let mut words = line.split_whitespace();
+            match (words.next(), words.next(), words.next()) {
+                (Some("Name"), Some("Status"), _) => None,
+                (Some(label), Some(_), Some(provider)) => {
+                    Some((String::from(label), String::from(provider)))
+                }
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+/// Resolve `device` with labels from `glabel status`. Providers that have no labels are checked
+/// with `exists`.
+#[cfg_attr(not(target_os = "freebsd"), allow(dead_code))]
+pub(crate) fn resolve_with_labels<F: Fn(&Path) -> bool>(
+    labels: &[(String, String)],
+    device: &Path,
+    exists: F,
+) -> ZpoolResult<Resolved> {
+    let not_found = || ZpoolError::DeviceNotFound(device.to_path_buf());
+    let dev = Path::new("/dev");
+    if device.is_absolute() && !device.starts_with(dev) {
+        let canonical = std::fs::canonicalize(device).map_err(|_| not_found())?;
+        return Ok(Resolved {
+            canonical,
+            aliases: Vec::new(),
+        });
+    }
+    let name = device.strip_prefix(dev).unwrap_or(device).to_string_lossy();
+    let provider = labels
+        .iter()
+        .find(|(label, _)| label.as_str() == name)
+        .map_or(&*name, |(_, provider)| provider.as_str());
+    let known = labels.iter().any(|(_, known)| known == provider);
+    if !known && !exists(&dev.join(provider)) {
+        return Err(not_found());
+    }
+    let mut aliases: Vec<PathBuf> = labels
+        .iter()
+        .filter(|(_, of)| of == provider)
+        .map(|(label, _)| dev.join(label))
+        .collect();
+    aliases.sort();
+    Ok(Resolved {
+        canonical: dev.join(provider),
+        aliases,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::zpool::ZpoolErrorKind;
+
+    fn labels() -> Vec<(String, String)> {
+        parse_glabel_status(include_str!("fixtures/glabel_status"))
+    }
+
+    #[test]
+    fn glabel_status() {
+        let labels = labels();
+        assert_eq!(6, labels.len());
+        assert_eq!(
+            (String::from("gpt/zfs-data-0"), String::from("ada0p3")),
+            labels[2]
+        );
+        assert_eq!(
+            (
+                String::from("diskid/DISK-S3Z9NB0K123456"),
+                String::from("ada1")
+            ),
+            labels[5]
+        );
+        assert!(parse_glabel_status("").is_empty());
+    }
+
+    #[test]
+    fn geom_labels() {
+        let labels = labels();
+        let nothing_else = |_: &Path| false;
+        let expected = Resolved {
+            canonical: PathBuf::from("/dev/ada0p3"),
+            aliases: vec![
+                PathBuf::from("/dev/gpt/zfs-data-0"),
+                PathBuf::from("/dev/gptid/50a1c3de-5a2b-11eb-9c3a-0cc47a6c1d4e"),
+            ],
+        };
+        for name in &[
+            "gpt/zfs-data-0",
+            "/dev/gpt/zfs-data-0",
+            "ada0p3",
+            "/dev/ada0p3",
+        ] {
+            assert_eq!(
+                expected,
+                resolve_with_labels(&labels, Path::new(name), nothing_else).unwrap()
+            );
+        }
+
+        let unlabeled = resolve_with_labels(&labels, Path::new("da0"), |path| {
+            path == Path::new("/dev/da0")
+        })
+        .unwrap();
+        assert_eq!(Path::new("/dev/da0"), unlabeled.canonical());
+        assert!(unlabeled.aliases().is_empty());
+
+        assert_eq!(
+            ZpoolErrorKind::DeviceNotFound,
+            resolve_with_labels(&labels, Path::new("gpt/zfs-data-9"), nothing_else)
+                .unwrap_err()
+                .kind()
+        );
+    }
+
+    #[test]
+    fn linux_symlinks() {
+        use std::os::unix::fs::symlink;
+
+        let dir = tempfile::tempdir().unwrap();
+        let dev = dir.path();
+        std::fs::write(dev.join("sda"), b"").unwrap();
+        std::fs::write(dev.join("sdb"), b"").unwrap();
+        std::fs::create_dir_all(dev.join("disk/by-id")).unwrap();
+        std::fs::create_dir_all(dev.join("disk/by-path")).unwrap();
+        symlink("../../sda", dev.join("disk/by-id/wwn-0x5000c500a1b2c3d4")).unwrap();
+        symlink("../../sdb", dev.join("disk/by-id/wwn-0x5000c500deadbeef")).unwrap();
+        symlink("../../sda", dev.join("disk/by-path/pci-0000:00:1f.2-ata-1")).unwrap();
+
+        let canonical = std::fs::canonicalize(dev.join("sda")).unwrap();
+        let expected = Resolved {
+            canonical,
+            aliases: vec![
+                dev.join("disk/by-id/wwn-0x5000c500a1b2c3d4"),
+                dev.join("disk/by-path/pci-0000:00:1f.2-ata-1"),
+            ],
+        };
+        assert_eq!(expected, resolve_in_dev(dev, Path::new("sda")).unwrap());
+        assert_eq!(
+            expected,
+            resolve_in_dev(dev, &dev.join("disk/by-id/wwn-0x5000c500a1b2c3d4")).unwrap()
+        );
+        assert_eq!(
+            expected,
+            resolve_in_dev(dev, Path::new("wwn-0x5000c500a1b2c3d4")).unwrap()
+        );
+        assert_eq!(
+            ZpoolErrorKind::DeviceNotFound,
+            resolve_in_dev(dev, Path::new("sdz")).unwrap_err().kind()
+        );
+    }
+
+    #[test]
+    fn linux_bare_by_id_names() {
+        use std::os::unix::fs::symlink;
+
+        let dir = tempfile::tempdir().unwrap();
+        let dev = dir.path();
+        std::fs::write(dev.join("sda"), b"").unwrap();
+        std::fs::write(dev.join("sdb"), b"").unwrap();
+        std::fs::create_dir_all(dev.join("disk/by-id")).unwrap();
+        std::fs::create_dir_all(dev.join("disk/by-label")).unwrap();
+        symlink("../../sda", dev.join("disk/by-id/ata-WDC_WD40EFRX_WD-1")).unwrap();
+        // Same name in a directory that is searched later points elsewhere.
+        symlink("../../sdb", dev.join("disk/by-label/ata-WDC_WD40EFRX_WD-1")).unwrap();
+        symlink("../../sdb", dev.join("disk/by-label/data")).unwrap();
+
+        let sda = std::fs::canonicalize(dev.join("sda")).unwrap();
+        let resolved = resolve_in_dev(dev, Path::new("ata-WDC_WD40EFRX_WD-1")).unwrap();
+        assert_eq!(&sda, resolved.canonical());
+        assert_eq!(
+            &vec![dev.join("disk/by-id/ata-WDC_WD40EFRX_WD-1")],
+            resolved.aliases()
+        );
+
+        let sdb = std::fs::canonicalize(dev.join("sdb")).unwrap();
+        assert_eq!(
+            &sdb,
+            resolve_in_dev(dev, Path::new("data")).unwrap().canonical()
+        );
+        // Absolute paths are taken as they are.
+        assert_eq!(
+            ZpoolErrorKind::DeviceNotFound,
+            resolve_in_dev(dev, Path::new("/ata-WDC_WD40EFRX_WD-1"))
+                .unwrap_err()
+                .kind()
+        );
+    }
+
+    #[test]
+    fn duplicates() {
+        let labels = labels();
+        let resolve = |device: &Path| resolve_with_labels(&labels, device, |_| false);
+        let distinct = vec![PathBuf::from("gpt/zfs-data-0"), PathBuf::from("ada1p1")];
+        assert!(check_unique(&distinct, resolve).is_ok());
+
+        let aliased = vec![
+            PathBuf::from("gpt/zfs-data-0"),
+            PathBuf::from("gpt/zfs-data-1"),
+            PathBuf::from("/dev/ada0p3"),
+        ];
+        match check_unique(&aliased, resolve).unwrap_err() {
+            ZpoolError::DuplicateDevice(first, second) => {
+                assert_eq!(PathBuf::from("gpt/zfs-data-0"), first);
+                assert_eq!(PathBuf::from("/dev/ada0p3"), second);
+            }
+            e => panic!("unexpected error: {:?}", e),
+        }
+
+        let missing = vec![PathBuf::from("gpt/missing")];
+        assert_eq!(
+            ZpoolErrorKind::DeviceNotFound,
+            check_unique(&missing, resolve).unwrap_err().kind()
+        );
+    }
+}
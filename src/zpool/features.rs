@@ -0,0 +1,320 @@
+//! Pool features to enable or disable at creation and `compatibility` presets.
+//!
+//! ```rust
+//! use libzetta::zpool::{Compatibility, CreateVdevRequest, CreateZpoolRequest, FeatureToggle};
+//!
+//! let request = CreateZpoolRequest::builder()
+//!     .name("boot")
+//!     .vdev(CreateVdevRequest::SingleDisk("sda2".into()))
+//!     .compatibility_preset(Compatibility::Grub2)
+//!     .feature("encryption", FeatureToggle::Disabled)
+//!     .build()
+//!     .unwrap();
+//! assert_eq!(&Some(String::from("grub2")), request.compatibility());
+//! ```
+use std::{
+    collections::BTreeSet,
+    fmt, fs, io,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use crate::zpool::{ZpoolError, ZpoolResult};
+
+/// Directories `zpool(8)` looks up compatibility files in, in order.
+pub const COMPATIBILITY_DIRS: [&str; 2] =
+    ["/etc/zfs/compatibility.d", "/usr/share/zfs/compatibility.d"];
+
+/// Features OpenZFS knows about, without `feature@` prefix. Used to check names when creating a
+/// pool with `strict_features`.
+pub const KNOWN_FEATURES: &[&str] = &[
+    "allocation_classes",
+    "async_destroy",
+    "blake3",
+    "block_cloning",
+    "bookmark_v2",
+    "bookmark_written",
+    "bookmarks",
+    "device_rebuild",
+    "device_removal",
+    "draid",
+    "edonr",
+    "embedded_data",
+    "empty_bpobj",
+    "enabled_txg",
+    "encryption",
+    "extensible_dataset",
+    "fast_dedup",
+    "filesystem_limits",
+    "head_errlog",
+    "hole_birth",
+    "large_blocks",
+    "large_dnode",
+    "large_microzap",
+    "livelist",
+    "log_spacemap",
+    "longname",
+    "lz4_compress",
+    "multi_vdev_crash_dump",
+    "obsolete_counts",
+    "project_quota",
+    "raidz_expansion",
+    "redacted_datasets",
+    "redaction_bookmarks",
+    "redaction_list_spill",
+    "resilver_defer",
+    "sha512",
+    "skein",
+    "spacemap_histogram",
+    "spacemap_v2",
+    "userobj_accounting",
+    "vdev_zaps_v2",
+    "zilsaxattr",
+    "zpool_checkpoint",
+    "zstd_compress",
+];
+
+const GRUB2: &[&str] = &[
+    "allocation_classes",
+    "async_destroy",
+    "bookmarks",
+    "device_rebuild",
+    "embedded_data",
+    "empty_bpobj",
+    "enabled_txg",
+    "extensible_dataset",
+    "filesystem_limits",
+    "hole_birth",
+    "large_blocks",
+    "livelist",
+    "log_spacemap",
+    "lz4_compress",
+    "project_quota",
+    "resilver_defer",
+    "spacemap_histogram",
+    "spacemap_v2",
+    "userobj_accounting",
+    "zpool_checkpoint",
+];
+
+const OPENZFS_2_0: &[&str] = &[
+    "allocation_classes",
+    "async_destroy",
+    "bookmark_v2",
+    "bookmark_written",
+    "bookmarks",
+    "device_rebuild",
+    "device_removal",
+    #[cfg(not(target_os = "freebsd"))]
+    "edonr",
+    "embedded_data",
+    "empty_bpobj",
+    "enabled_txg",
+    "encryption",
+    "extensible_dataset",
+    "filesystem_limits",
+    "hole_birth",
+    "large_blocks",
+    "large_dnode",
+    "livelist",
+    "log_spacemap",
+    "lz4_compress",
+    "multi_vdev_crash_dump",
+    "obsolete_counts",
+    "project_quota",
+    "redacted_datasets",
+    "redaction_bookmarks",
+    "resilver_defer",
+    "sha512",
+    "skein",
+    "spacemap_histogram",
+    "spacemap_v2",
+    "userobj_accounting",
+    "zpool_checkpoint",
+    "zstd_compress",
+];
+
+const FREEBSD_12: &[&str] = &[
+    "async_destroy",
+    "bookmarks",
+    "device_removal",
+    "embedded_data",
+    "empty_bpobj",
+    "enabled_txg",
+    "extensible_dataset",
+    "filesystem_limits",
+    "hole_birth",
+    "large_blocks",
+    "lz4_compress",
+    "multi_vdev_crash_dump",
+    "obsolete_counts",
+    "sha512",
+    "skein",
+    "spacemap_histogram",
+    "zpool_checkpoint",
+];
+
+/// Whether to enable a feature on a new pool, value of `-o feature@<name>=`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FeatureToggle {
+    /// Enable the feature.
+    Enabled,
+    /// Leave the feature disabled, so older systems can import the pool.
+    Disabled,
+}
+
+impl FeatureToggle {
+    #[doc(hidden)]
+    pub fn as_str(&self) -> &str {
+        match *self {
+            FeatureToggle::Enabled => "enabled",
+            FeatureToggle::Disabled => "disabled",
+        }
+    }
+}
+
+impl fmt::Display for FeatureToggle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for FeatureToggle {
+    type Err = ZpoolError;
+
+    fn from_str(source: &str) -> ZpoolResult<FeatureToggle> {
+        match source {
+            "enabled" => Ok(FeatureToggle::Enabled),
+            "disabled" => Ok(FeatureToggle::Disabled),
+            _ => Err(ZpoolError::ParseError),
+        }
+    }
+}
+
+/// Named feature sets for `compatibility` property, one per file `zpool(8)` ships in
+/// `compatibility.d`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compatibility {
+    /// Features GRUB2 can read, for boot pools.
+    Grub2,
+    /// Features of OpenZFS 2.0 on the current platform.
+    OpenZfs2_0,
+    /// Features of ZFS in FreeBSD 12.0 base.
+    FreeBSD12,
+}
+
+impl Compatibility {
+    /// Name of the compatibility file, value of `compatibility` property.
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            Compatibility::Grub2 => "grub2",
+            #[cfg(target_os = "freebsd")]
+            Compatibility::OpenZfs2_0 => "openzfs-2.0-freebsd",
+            #[cfg(not(target_os = "freebsd"))]
+            Compatibility::OpenZfs2_0 => "openzfs-2.0-linux",
+            Compatibility::FreeBSD12 => "freebsd-12.0",
+        }
+    }
+
+    /// Features the preset allows. Read from compatibility file installed with ZFS, if there is
+    /// one, so the set matches what `zpool` is going to use. Otherwise built-in list is used.
+    pub fn features(&self) -> BTreeSet<String> {
+        let dirs: Vec<PathBuf> = COMPATIBILITY_DIRS.iter().map(PathBuf::from).collect();
+        self.features_in(&dirs)
+    }
+
+    fn features_in(&self, dirs: &[PathBuf]) -> BTreeSet<String> {
+        dirs.iter()
+            .find_map(|dir| read_compatibility_file(dir.join(self.as_str())).ok())
+            .unwrap_or_else(|| self.builtin().iter().map(|f| String::from(*f)).collect())
+    }
+
+    fn builtin(&self) -> &'static [&'static str] {
+        match *self {
+            Compatibility::Grub2 => GRUB2,
+            Compatibility::OpenZfs2_0 => OPENZFS_2_0,
+            Compatibility::FreeBSD12 => FREEBSD_12,
+        }
+    }
+}
+
+impl fmt::Display for Compatibility {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Read features listed in a compatibility file: names separated by whitespace, commas or
+/// newlines, `#` starts a comment.
+pub fn read_compatibility_file<P: AsRef<Path>>(path: P) -> io::Result<BTreeSet<String>> {
+    let content = fs::read_to_string(path)?;
+    Ok(content
+        .lines()
+        .map(|line| line.split('#').next().unwrap_or(""))
+        .flat_map(|line| line.split(|c: char| c.is_whitespace() || c == ','))
+        .filter(|name| !name.is_empty())
+        .map(String::from)
+        .collect())
+}
+
+/// Feature is one of [`KNOWN_FEATURES`](constant.KNOWN_FEATURES.html).
+pub fn is_known_feature(name: &str) -> bool {
+    KNOWN_FEATURES.contains(&name)
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Write;
+
+    use super::*;
+
+    #[test]
+    fn toggle_round_trip() {
+        for toggle in &[FeatureToggle::Enabled, FeatureToggle::Disabled] {
+            assert_eq!(
+                *toggle,
+                toggle.to_string().parse::<FeatureToggle>().unwrap()
+            );
+        }
+        assert!("active".parse::<FeatureToggle>().is_err());
+    }
+
+    #[test]
+    fn compatibility_file_overrides_builtin() {
+        let first = tempfile::tempdir().unwrap();
+        let second = tempfile::tempdir().unwrap();
+        let dirs = vec![first.path().to_path_buf(), second.path().to_path_buf()];
+
+        let builtin = Compatibility::Grub2.features_in(&dirs);
+        assert!(builtin.contains("lz4_compress"));
+        assert!(!builtin.contains("encryption"));
+        assert_eq!(GRUB2.len(), builtin.len());
+
+        let mut file = fs::File::create(second.path().join("grub2")).unwrap();
+        file.write_all(b"# Features supported by GRUB2\nasync_destroy\nbookmarks # comment\n\nlz4_compress,hole_birth\n")
+            .unwrap();
+        let expected: BTreeSet<String> =
+            ["async_destroy", "bookmarks", "hole_birth", "lz4_compress"]
+                .iter()
+                .map(|f| String::from(*f))
+                .collect();
+        assert_eq!(expected, Compatibility::Grub2.features_in(&dirs));
+        assert_eq!(
+            FREEBSD_12.len(),
+            Compatibility::FreeBSD12.features_in(&dirs).len()
+        );
+    }
+
+    #[test]
+    fn builtin_presets_use_known_features() {
+        for preset in &[
+            Compatibility::Grub2,
+            Compatibility::OpenZfs2_0,
+            Compatibility::FreeBSD12,
+        ] {
+            for feature in preset.builtin() {
+                assert!(is_known_feature(feature), "{} of {}", feature, preset);
+            }
+        }
+    }
+}
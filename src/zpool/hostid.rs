@@ -0,0 +1,96 @@
+//! Host ID of this system as ZFS sees it.
+//!
+//! Pools with `multihost=on` refuse to import while another host keeps writing to them, and tell
+//! hosts apart by host ID. Before forcing an import it's worth checking that the pool was last
+//! imported by this very host: compare [`hostid`](fn.hostid.html) with
+//! [`ZpoolError::ActiveOnOtherHost`](../enum.ZpoolError.html). On Linux host ID is taken from the
+//! kernel module when it's loaded and from `/etc/hostid` otherwise, on FreeBSD from
+//! `sysctl kern.hostid`.
+use std::path::Path;
+
+use crate::zpool::{ZpoolError, ZpoolResult};
+#[cfg(target_os = "freebsd")]
+use crate::{instrumentation, GlobalLogger};
+
+/// Host ID of this system. `None` if it isn't set: multihost pools can't be imported then.
+#[cfg(target_os = "linux")]
+pub fn hostid() -> ZpoolResult<Option<u64>> {
+    match std::fs::read_to_string("/proc/sys/kernel/spl/hostid") {
+        Ok(text) => parse_hex_hostid(&text),
+        Err(_) => hostid_from_file(Path::new("/etc/hostid")),
+    }
+}
+
+/// Host ID of this system. `None` if it isn't set: multihost pools can't be imported then.
+#[cfg(target_os = "freebsd")]
+pub fn hostid() -> ZpoolResult<Option<u64>> {
+    let mut sysctl = instrumentation::isolated_command("sysctl");
+    sysctl.args(["-n", "kern.hostid"]);
+    let out = instrumentation::run_command_within(
+        GlobalLogger::get(),
+        None,
+        &mut sysctl,
+        Some(instrumentation::SYSTEM_COMMAND_TIMEOUT),
+    )?;
+    if !out.status.success() {
+        return Err(ZpoolError::Other(
+            String::from_utf8_lossy(&out.stderr).into_owned(),
+        ));
+    }
+    let hostid: u64 = String::from_utf8_lossy(&out.stdout).trim().parse()?;
+    Ok(Some(hostid).filter(|hostid| *hostid != 0))
+}
+
+/// Parse host ID the way kernel module reports it: hexadecimal, with or without `0x`.
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+pub(crate) fn parse_hex_hostid(text: &str) -> ZpoolResult<Option<u64>> {
+    let text = text.trim();
+    let digits = text.trim_start_matches("0x");
+    let hostid = u64::from_str_radix(digits, 16).map_err(|_| ZpoolError::ParseError)?;
+    Ok(Some(hostid).filter(|hostid| *hostid != 0))
+}
+
+/// Read `/etc/hostid`-like file: 4 bytes in native byte order. Missing file means host ID isn't
+/// set.
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+pub(crate) fn hostid_from_file(path: &Path) -> ZpoolResult<Option<u64>> {
+    let raw = match std::fs::read(path) {
+        Ok(raw) => raw,
+        Err(ref e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(ZpoolError::Io(e)),
+    };
+    if raw.len() < 4 {
+        return Err(ZpoolError::ParseError);
+    }
+    let hostid = u32::from_ne_bytes([raw[0], raw[1], raw[2], raw[3]]);
+    Ok(Some(u64::from(hostid)).filter(|hostid| *hostid != 0))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn hex_hostid() {
+        assert_eq!(Some(0x8a3d_5c11), parse_hex_hostid("8a3d5c11\n").unwrap());
+        assert_eq!(Some(0x2ad8_ac3a), parse_hex_hostid("0x2ad8ac3a").unwrap());
+        assert_eq!(None, parse_hex_hostid("0\n").unwrap());
+        assert!(parse_hex_hostid("hostid").is_err());
+    }
+
+    #[test]
+    fn hostid_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("hostid");
+        std::fs::write(&path, 0x8a3d_5c11u32.to_ne_bytes()).unwrap();
+        assert_eq!(Some(0x8a3d_5c11), hostid_from_file(&path).unwrap());
+
+        std::fs::write(&path, [0u8; 4]).unwrap();
+        assert_eq!(None, hostid_from_file(&path).unwrap());
+
+        std::fs::write(&path, [1u8; 2]).unwrap();
+        assert!(hostid_from_file(&path).is_err());
+
+        assert_eq!(None, hostid_from_file(&dir.path().join("missing")).unwrap());
+    }
+}
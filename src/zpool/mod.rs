@@ -17,27 +17,50 @@ use std::{
     ffi::OsStr,
     io,
     num::{ParseFloatError, ParseIntError},
+    ops::Range,
     path::PathBuf,
+    time::Duration,
 };
 
-use crate::zpool::open3::StatusOptions;
+use chrono::Utc;
+use strum_macros::{Display, EnumString};
+
+use crate::{
+    instrumentation::CommandTimedOut,
+    zpool::open3::{AddOptions, AttachOptions, ExportOptions, ImportOptions, StatusOptions},
+};
 use regex::Regex;
 
 pub use self::{
-    description::{Reason, Zpool},
+    description::{DiskRole, PermanentError, PoolStatus, Reason, ScanStatus, Zpool},
+    features::{Compatibility, FeatureToggle},
+    name::{validate_pool_name, PoolNameError, MAX_POOL_NAME_LEN},
     open3::ZpoolOpen3,
     properties::{
-        CacheType, FailMode, Health, PropPair, ZpoolProperties, ZpoolPropertiesWrite,
-        ZpoolPropertiesWriteBuilder,
+        CacheType, FailMode, FeatureState, Health, PoolListEntry, PoolPropertySource, PropPair,
+        ZpoolProperties, ZpoolPropertiesWrite, ZpoolPropertiesWriteBuilder,
     },
     topology::{CreateZpoolRequest, CreateZpoolRequestBuilder},
-    vdev::{CreateVdevRequest, Disk, Vdev, VdevType},
+    vdev::{AsDeviceArg, CreateVdevRequest, Disk, MatchStrategy, ResilverStatus, Vdev, VdevType},
 };
 
+#[cfg(any(target_os = "linux", target_os = "freebsd"))]
+pub mod device_info;
+pub mod device_metadata;
+#[cfg(any(target_os = "linux", target_os = "freebsd"))]
+pub mod device_resolver;
+pub mod features;
+#[cfg(any(target_os = "linux", target_os = "freebsd"))]
+pub mod hostid;
+pub mod monitor;
+pub mod name;
 pub mod open3;
 pub mod properties;
+#[cfg(feature = "serde")]
+mod status_json;
 pub mod topology;
 pub mod vdev;
+pub mod zdb;
 
 pub mod description;
 lazy_static! {
@@ -54,7 +77,19 @@ lazy_static! {
     static ref RE_NO_SUCH_DEVICE: Regex = Regex::new(r"cannot attach \S+ to \S+: no such device in pool").expect("failed to compile RE_NO_SUCH_DEVICE");
     static ref RE_ONLY_DEVICE: Regex = Regex::new(r"cannot detach \S+ only applicable to mirror and replacing vdevs").expect("failed to compile RE_ONLY_DEVICE");
     static ref RE_MISMATCH_REPLICATION: Regex = Regex::new(r"invalid vdev specification\nuse '-f' to override the following errors:\nmismatched replication level:.+").expect("failed to compile RE_MISMATCHED_REPLICATION");
+    static ref RE_MISMATCH_KINDS: Regex = Regex::new(r"pool uses (.+) and new vdev (?:is|uses) (.+)").expect("failed to compile RE_MISMATCH_KINDS");
     static ref RE_INVALID_CACHE_DEVICE: Regex = Regex::new(r"cannot add to \S+: cache device must be a disk or disk slice\n?").expect("failed to compile RE_INVALID_CACHE_DEVICE");
+    static ref RE_POOL_IN_USE: Regex = Regex::new(r"cannot import \S+: pool (?:may be|was previously) in use from (?:an)?other system").expect("failed to compile RE_POOL_IN_USE");
+    static ref RE_ACTIVE_ON_OTHER_HOST: Regex = Regex::new(r"cannot import \S+: pool is (?:imported on host '([^']+)'|in use from another system; pool was last accessed by (\S+)) \(hostid[=:] ?(?:0x)?([[:xdigit:]]+)\)").expect("failed to compile RE_ACTIVE_ON_OTHER_HOST");
+    static ref RE_LAST_ACCESSED_BY: Regex = Regex::new(r"[Ll]ast accessed by (\S+)").expect("failed to compile RE_LAST_ACCESSED_BY");
+    static ref RE_DEVICES_UNAVAILABLE: Regex = Regex::new(r"cannot import \S+: one or more devices is currently unavailable").expect("failed to compile RE_DEVICES_UNAVAILABLE");
+    static ref RE_UNSUPPORTED: Regex = Regex::new(r"unrecognized command '\S+'|this action requires the \S+ feature|operation not supported on this type of pool").expect("failed to compile RE_UNSUPPORTED");
+    static ref RE_CURRENTLY_RESILVERING: Regex = Regex::new(r"cannot (?:scrub|restart resilver on) \S+: currently resilvering").expect("failed to compile RE_CURRENTLY_RESILVERING");
+    static ref RE_POOL_BUSY: Regex = Regex::new(r"cannot unmount '([^']+)': (?:pool or dataset is busy|Device busy)|umount: ([^:\n]+): target is busy|cannot (?:export|reguid) '[^']+': pool is busy").expect("failed to compile RE_POOL_BUSY");
+    static ref RE_POOL_SUSPENDED: Regex = Regex::new(r"pool I/O is currently suspended|pool is suspended").expect("failed to compile RE_POOL_SUSPENDED");
+    static ref RE_UNSUPPORTED_PROPERTY: Regex = Regex::new(r"invalid property '([^']+)'|property '([^']+)' (?:is )?not (?:supported|a valid pool property)").expect("failed to compile RE_UNSUPPORTED_PROPERTY");
+    static ref RE_NO_RESILVER: Regex = Regex::new(r"cannot restart resilver on \S+: (?:there is no active scrub|no resilver in progress)").expect("failed to compile RE_NO_RESILVER");
+    static ref RE_CURRENTLY_SCRUBBING: Regex = Regex::new(r"cannot (?:scrub|restart resilver on) \S+: currently scrubbing").expect("failed to compile RE_CURRENTLY_SCRUBBING");
 }
 
 quick_error! {
@@ -75,11 +110,22 @@ quick_error! {
         VdevReuse(vdev: String, pool: String) {
             display("{} is part of {}", vdev, pool)
         }
+        /// Same as `VdevReuse`, but `zpool` didn't tell which vdev: ZFS on Linux only says that
+        /// vdevs refer to the same device or one of them is part of an active md or lvm device.
+        DeviceInUse {}
         /// Failed to parse value. Ideally you never see it, if you see it - it's a bug.
         ParseError {
             from(ParseIntError)
             from(ParseFloatError)
         }
+        /// `zpool` succeeded, but its output couldn't be parsed. Contains the output. Ideally you
+        /// never see it, if you see it - it's a bug.
+        UnparsedOutput(stdout: String) {}
+        /// Output of `zpool status` or `zpool import` couldn't be parsed. Contains the output and
+        /// byte range of the line where parsing failed.
+        UnparsedStatus(stdout: String, span: Range<usize>) {
+            display("can't parse status output at {:?}", span)
+        }
         /// Device used in CreateZpoolRequest is smaller than 64M (or 128M on some platforms).
         DeviceTooSmall {}
         /// Permission denied to create zpool. This might happened because:
@@ -99,10 +145,73 @@ quick_error! {
         /// Trying to detach a device from vdev without any valid replicas left.
         OnlyDevice {}
         /// Trying to add vdev with wrong replication level to existing zpool with different replication level.
-        /// For example: mirror to zpool.
-        MismatchedReplicationLevel {}
+        /// For example: mirror to zpool. Contains replication level of the pool and of the new
+        /// vdev if known.
+        MismatchedReplicationLevel(pool: String, new: String) {
+            display("pool uses {} and new vdev is {}", pool, new)
+        }
+        /// Same as `MismatchedReplicationLevel`, but `zpool` didn't name both levels, e.g. when it
+        /// says that both mirror and raidz vdevs are present.
+        InconsistentReplication {}
         /// Cache device must a disk or disk slice/partition.
         InvalidCacheDevice {}
+        /// Trying to import a pool that was last accessed by another system. Contains host name
+        /// of that system if `zpool` reported it.
+        PoolInUse(host: Option<String>) {}
+        /// Trying to import a pool with `multihost=on` that another system has imported and keeps
+        /// writing to. Contains host name and host ID of that system.
+        ActiveOnOtherHost { host: String, hostid: u64 } {
+            display("pool is imported on host {} (hostid={:x})", host, hostid)
+        }
+        /// Trying to import a pool with one or more devices missing.
+        DevicesUnavailable {}
+        /// Installed `zpool` doesn't have this subcommand or pool lacks required feature.
+        Unsupported {}
+        /// Installed `zpool` doesn't know this pool property, e.g. `autotrim` on older FreeBSD.
+        UnsupportedProperty(property: String) {
+            display("property {} is not supported", property)
+        }
+        /// Operation is refused because pool is being resilvered.
+        ResilverInProgress {}
+        /// Resilver can't be restarted because there is no resilver, running or deferred.
+        NoResilverInProgress {}
+        /// Operation is refused because pool is being scrubbed.
+        ScrubInProgress {}
+        /// Pool or one of its datasets is busy and can't be unmounted. Contains dataset or mount
+        /// point that refused to unmount if known.
+        PoolBusy(dataset: Option<PathBuf>) {}
+        /// Pool has no hot spare that is available: there are no spares or all of them are in use.
+        NoAvailableSpare {}
+        /// Pool I/O is suspended because of too many device failures. Pool has to be cleared
+        /// before it accepts writes again.
+        PoolSuspended {}
+        /// Command didn't finish within given timeout and was killed. Contains command line and
+        /// the timeout.
+        TimedOut { command: String, after: Duration } {
+            display("{} didn't exit within {:?}", command, after)
+        }
+        /// [`update_properties`](trait.ZpoolEngine.html#method.update_properties) failed to set
+        /// property `key`. Properties set before it were restored: `rollback` has every one of
+        /// them and whether restoring it succeeded.
+        UpdateFailed { key: String, cause: Box<ZpoolError>, rollback: Vec<(String, bool)> } {
+            display("failed to set {}: {}", key, cause)
+            cause(&**cause)
+        }
+        /// Device of the request doesn't exist, e.g. GEOM label is misspelled.
+        DeviceNotFound(device: PathBuf) {
+            display("no such device: {}", device.display())
+        }
+        /// The same physical device is used twice in one request under different names, e.g. as
+        /// `sda` and `/dev/disk/by-id/wwn-...`.
+        DuplicateDevice(first: PathBuf, second: PathBuf) {
+            display("{} and {} are the same device", first.display(), second.display())
+        }
+        /// Name can't be used as a pool name, e.g. it starts with a digit or has a space in it.
+        InvalidPoolName(name: String, reason: PoolNameError) {
+            display("invalid pool name {:?}: {}", name, reason)
+        }
+        /// Engine doesn't implement this operation.
+        Unimplemented {}
         /// Don't know (yet) how to categorize this error. If you see this error - open an issue.
         Other(err: String) {}
     }
@@ -116,8 +225,10 @@ impl ZpoolError {
             ZpoolError::Io(_) => ZpoolErrorKind::Io,
             ZpoolError::PoolNotFound => ZpoolErrorKind::PoolNotFound,
             ZpoolError::InvalidTopology => ZpoolErrorKind::InvalidTopology,
-            ZpoolError::VdevReuse(..) => ZpoolErrorKind::VdevReuse,
-            ZpoolError::ParseError => ZpoolErrorKind::ParseError,
+            ZpoolError::VdevReuse(..) | ZpoolError::DeviceInUse => ZpoolErrorKind::VdevReuse,
+            ZpoolError::ParseError
+            | ZpoolError::UnparsedOutput(_)
+            | ZpoolError::UnparsedStatus(..) => ZpoolErrorKind::ParseError,
             ZpoolError::DeviceTooSmall => ZpoolErrorKind::DeviceTooSmall,
             ZpoolError::PermissionDenied => ZpoolErrorKind::PermissionDenied,
             ZpoolError::NoActiveScrubs => ZpoolErrorKind::NoActiveScrubs,
@@ -126,8 +237,27 @@ impl ZpoolError {
             ZpoolError::CannotAttach => ZpoolErrorKind::CannotAttach,
             ZpoolError::NoSuchDevice => ZpoolErrorKind::NoSuchDevice,
             ZpoolError::OnlyDevice => ZpoolErrorKind::OnlyDevice,
-            ZpoolError::MismatchedReplicationLevel => ZpoolErrorKind::MismatchedReplicationLevel,
+            ZpoolError::MismatchedReplicationLevel(..) | ZpoolError::InconsistentReplication => {
+                ZpoolErrorKind::MismatchedReplicationLevel
+            }
             ZpoolError::InvalidCacheDevice => ZpoolErrorKind::InvalidCacheDevice,
+            ZpoolError::PoolInUse(_) => ZpoolErrorKind::PoolInUse,
+            ZpoolError::ActiveOnOtherHost { .. } => ZpoolErrorKind::ActiveOnOtherHost,
+            ZpoolError::DevicesUnavailable => ZpoolErrorKind::DevicesUnavailable,
+            ZpoolError::Unsupported => ZpoolErrorKind::Unsupported,
+            ZpoolError::UnsupportedProperty(_) => ZpoolErrorKind::UnsupportedProperty,
+            ZpoolError::ResilverInProgress => ZpoolErrorKind::ResilverInProgress,
+            ZpoolError::NoResilverInProgress => ZpoolErrorKind::NoResilverInProgress,
+            ZpoolError::ScrubInProgress => ZpoolErrorKind::ScrubInProgress,
+            ZpoolError::PoolBusy(_) => ZpoolErrorKind::PoolBusy,
+            ZpoolError::NoAvailableSpare => ZpoolErrorKind::NoAvailableSpare,
+            ZpoolError::PoolSuspended => ZpoolErrorKind::PoolSuspended,
+            ZpoolError::TimedOut { .. } => ZpoolErrorKind::TimedOut,
+            ZpoolError::DeviceNotFound(_) => ZpoolErrorKind::DeviceNotFound,
+            ZpoolError::DuplicateDevice(..) => ZpoolErrorKind::DuplicateDevice,
+            ZpoolError::InvalidPoolName(..) => ZpoolErrorKind::InvalidPoolName,
+            ZpoolError::UpdateFailed { .. } => ZpoolErrorKind::UpdateFailed,
+            ZpoolError::Unimplemented => ZpoolErrorKind::Unimplemented,
             ZpoolError::Other(_) => ZpoolErrorKind::Other,
         }
     }
@@ -179,6 +309,38 @@ pub enum ZpoolErrorKind {
     MismatchedReplicationLevel,
     /// Cache device must be a disk or disk slice/partition.
     InvalidCacheDevice,
+    /// Trying to import a pool that was last accessed by another system.
+    PoolInUse,
+    /// Trying to import a multihost pool that another system has imported.
+    ActiveOnOtherHost,
+    /// Trying to import a pool with one or more devices missing.
+    DevicesUnavailable,
+    /// Installed `zpool` doesn't have this subcommand or pool lacks required feature.
+    Unsupported,
+    /// Installed `zpool` doesn't know this pool property.
+    UnsupportedProperty,
+    /// Operation is refused because pool is being resilvered.
+    ResilverInProgress,
+    /// There is no resilver to restart.
+    NoResilverInProgress,
+    /// Operation is refused because pool is being scrubbed.
+    ScrubInProgress,
+    /// Pool or one of its datasets is busy and can't be unmounted.
+    PoolBusy,
+    /// Pool has no hot spare that is available.
+    NoAvailableSpare,
+    /// Pool I/O is suspended because of too many device failures.
+    PoolSuspended,
+    /// Command didn't finish within given timeout and was killed.
+    TimedOut,
+    /// Failed to set one of the properties while updating them.
+    UpdateFailed,
+    /// The same physical device is used twice in one request.
+    DuplicateDevice,
+    /// Name can't be used as a pool name.
+    InvalidPoolName,
+    /// Engine doesn't implement this operation.
+    Unimplemented,
     /// Don't know (yet) how to categorize this error. If you see this error -
     /// open an issue.
     Other,
@@ -189,7 +351,13 @@ impl From<io::Error> for ZpoolError {
     fn from(err: io::Error) -> ZpoolError {
         match err.kind() {
             io::ErrorKind::NotFound => ZpoolError::CmdNotFound,
-            _ => ZpoolError::Io(err),
+            _ => match CommandTimedOut::from_io(&err) {
+                Some(timed_out) => ZpoolError::TimedOut {
+                    command: timed_out.command.clone(),
+                    after: timed_out.after,
+                },
+                None => ZpoolError::Io(err),
+            },
         }
     }
 }
@@ -218,7 +386,7 @@ impl ZpoolError {
                 caps.get(2).unwrap().as_str().into(),
             )
         } else if RE_REUSE_VDEV_ZOL.is_match(&stderr) {
-            ZpoolError::VdevReuse(String::new(), String::new())
+            ZpoolError::DeviceInUse
         } else if RE_TOO_SMALL.is_match(&stderr) {
             ZpoolError::DeviceTooSmall
         } else if RE_PERMISSION_DENIED.is_match(&stderr) {
@@ -236,9 +404,47 @@ impl ZpoolError {
         } else if RE_ONLY_DEVICE.is_match(&stderr) {
             ZpoolError::OnlyDevice
         } else if RE_MISMATCH_REPLICATION.is_match(&stderr) {
-            ZpoolError::MismatchedReplicationLevel
+            match RE_MISMATCH_KINDS.captures(&stderr) {
+                Some(caps) => ZpoolError::MismatchedReplicationLevel(
+                    caps.get(1).unwrap().as_str().into(),
+                    caps.get(2).unwrap().as_str().into(),
+                ),
+                None => ZpoolError::InconsistentReplication,
+            }
         } else if RE_INVALID_CACHE_DEVICE.is_match(&stderr) {
             ZpoolError::InvalidCacheDevice
+        } else if let Some(caps) = RE_ACTIVE_ON_OTHER_HOST.captures(&stderr) {
+            let host = caps.get(1).or_else(|| caps.get(2)).unwrap().as_str().into();
+            match u64::from_str_radix(caps.get(3).unwrap().as_str(), 16) {
+                Ok(hostid) => ZpoolError::ActiveOnOtherHost { host, hostid },
+                Err(_) => ZpoolError::Other(stderr.into()),
+            }
+        } else if RE_POOL_IN_USE.is_match(&stderr) {
+            let host = RE_LAST_ACCESSED_BY
+                .captures(&stderr)
+                .map(|caps| caps.get(1).unwrap().as_str().into());
+            ZpoolError::PoolInUse(host)
+        } else if RE_DEVICES_UNAVAILABLE.is_match(&stderr) {
+            ZpoolError::DevicesUnavailable
+        } else if RE_UNSUPPORTED.is_match(&stderr) {
+            ZpoolError::Unsupported
+        } else if let Some(caps) = RE_UNSUPPORTED_PROPERTY.captures(&stderr) {
+            let property = caps.get(1).or_else(|| caps.get(2)).unwrap().as_str();
+            ZpoolError::UnsupportedProperty(property.into())
+        } else if RE_CURRENTLY_RESILVERING.is_match(&stderr) {
+            ZpoolError::ResilverInProgress
+        } else if RE_NO_RESILVER.is_match(&stderr) {
+            ZpoolError::NoResilverInProgress
+        } else if RE_CURRENTLY_SCRUBBING.is_match(&stderr) {
+            ZpoolError::ScrubInProgress
+        } else if RE_POOL_BUSY.is_match(&stderr) {
+            let dataset = RE_POOL_BUSY
+                .captures(&stderr)
+                .and_then(|caps| caps.get(1).or_else(|| caps.get(2)))
+                .map(|m| PathBuf::from(m.as_str()));
+            ZpoolError::PoolBusy(dataset)
+        } else if RE_POOL_SUSPENDED.is_match(&stderr) {
+            ZpoolError::PoolSuspended
         } else {
             ZpoolError::Other(stderr.into())
         }
@@ -248,19 +454,25 @@ impl ZpoolError {
 /// Type alias to `Result<T, ZpoolError>`.
 pub type ZpoolResult<T> = Result<T, ZpoolError>;
 
+// Strategies below are displayed in kebab case (`until-reboot`) and parsed ignoring case, so they
+// can come straight from command line or config files.
+
 /// Strategy to use when bringing device offline.
-#[derive(Clone, PartialEq, Eq, Debug)]
+#[derive(Clone, PartialEq, Eq, Debug, Display, EnumString)]
+#[strum(serialize_all = "kebab-case", ascii_case_insensitive)]
 pub enum OfflineMode {
     /// Device will be taken offline until operator manually bring it back
     /// online.
     Permanent,
     /// Upon reboot, the specified physical device reverts to its previous
     /// state.
+    #[strum(to_string = "until-reboot", serialize = "temporary")]
     UntilReboot,
 }
 
 /// Strategy to use when bringing device online.
-#[derive(Clone, PartialEq, Eq, Debug)]
+#[derive(Clone, PartialEq, Eq, Debug, Display, EnumString)]
+#[strum(serialize_all = "kebab-case", ascii_case_insensitive)]
 pub enum OnlineMode {
     /// Bring device online as is.
     Simple,
@@ -271,7 +483,8 @@ pub enum OnlineMode {
 }
 
 /// Strategy to use when creating Zpool.
-#[derive(Clone, PartialEq, Eq, Debug)]
+#[derive(Clone, PartialEq, Eq, Debug, Display, EnumString)]
+#[strum(serialize_all = "kebab-case", ascii_case_insensitive)]
 pub enum CreateMode {
     /// Forces use of vdevs, even if they appear in use or specify a conflicting
     /// replication level. Not all devices can be overridden in this manner.
@@ -280,7 +493,8 @@ pub enum CreateMode {
     Gentle,
 }
 /// Strategy to use when destroying Zpool.
-#[derive(Clone, PartialEq, Eq, Debug)]
+#[derive(Clone, PartialEq, Eq, Debug, Display, EnumString)]
+#[strum(serialize_all = "kebab-case", ascii_case_insensitive)]
 pub enum DestroyMode {
     /// Forces any active datasets contained within the pool to be unmounted. Might result in
     /// corruption.
@@ -290,7 +504,8 @@ pub enum DestroyMode {
 }
 
 /// Strategy to use when exporting Zpool.
-#[derive(Clone, PartialEq, Eq, Debug)]
+#[derive(Clone, PartialEq, Eq, Debug, Display, EnumString)]
+#[strum(serialize_all = "kebab-case", ascii_case_insensitive)]
 pub enum ExportMode {
     /// Forcefully unmount all datasets. Might result in corruption.
     Force,
@@ -298,12 +513,155 @@ pub enum ExportMode {
     Gentle,
 }
 
+/// What [`ensure_scrubbed_within`](trait.ZpoolEngine.html#method.ensure_scrubbed_within) did.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ScrubOutcome {
+    /// New scrub was started or paused one was resumed.
+    Started,
+    /// Scrub is already running.
+    AlreadyRunning,
+    /// Last scrub is recent enough.
+    NotNeeded,
+}
+
+/// Name of replication level the way `zpool` reports it in errors.
+fn replication_level(kind: &VdevType) -> &'static str {
+    match kind {
+        // Both are temporary groups in place of a single device.
+        VdevType::SingleDisk | VdevType::Replacing | VdevType::Spare => "disk",
+        VdevType::Mirror => "mirror",
+        VdevType::RaidZ => "raidz",
+        VdevType::RaidZ2 => "raidz2",
+        VdevType::RaidZ3 => "raidz3",
+    }
+}
+
+/// Check that new vdev has the same replication level as top-level vdevs of the pool, just like
+/// `zpool add` does without `-f`.
+pub(crate) fn check_replication_level(
+    existing: &[Vdev],
+    new_vdev: &CreateVdevRequest,
+) -> ZpoolResult<()> {
+    let new = replication_level(&new_vdev.kind());
+    match existing
+        .iter()
+        .map(|vdev| replication_level(vdev.kind()))
+        .find(|pool| *pool != new)
+    {
+        Some(pool) => Err(ZpoolError::MismatchedReplicationLevel(
+            pool.into(),
+            new.into(),
+        )),
+        None => Ok(()),
+    }
+}
+
 impl Default for CreateMode {
     fn default() -> CreateMode {
         CreateMode::Gentle
     }
 }
 
+/// Property `update_properties` has to set, with its current and desired values as `zpool set`
+/// takes them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct PropertyChange {
+    key: String,
+    old: String,
+    new: String,
+}
+
+impl PropertyChange {
+    fn new<P: PropPair>(key: &str, old: &P, new: &P) -> PropertyChange {
+        PropertyChange {
+            key: String::from(key),
+            old: pair_value(old),
+            new: pair_value(new),
+        }
+    }
+}
+
+/// Value part of `key=value` pair.
+fn pair_value<P: PropPair>(value: &P) -> String {
+    value.to_pair("").split_off(1)
+}
+
+/// Every property of `props` that differs from `current`, in order they are set.
+pub(crate) fn properties_delta(
+    current: &ZpoolProperties,
+    props: &ZpoolPropertiesWrite,
+) -> Vec<PropertyChange> {
+    let mut changes = Vec::new();
+    if current.auto_expand() != props.auto_expand() {
+        changes.push(PropertyChange::new(
+            "autoexpand",
+            current.auto_expand(),
+            props.auto_expand(),
+        ));
+    }
+    if current.auto_replace() != props.auto_replace() {
+        changes.push(PropertyChange::new(
+            "autoreplace",
+            current.auto_replace(),
+            props.auto_replace(),
+        ));
+    }
+    if current.cache_file() != props.cache_file() {
+        changes.push(PropertyChange::new(
+            "cachefile",
+            current.cache_file(),
+            props.cache_file(),
+        ));
+    }
+    // Empty comment removes it.
+    let current_comment = current.comment().clone().unwrap_or_default();
+    if &current_comment != props.comment() {
+        changes.push(PropertyChange::new(
+            "comment",
+            &current_comment,
+            props.comment(),
+        ));
+    }
+    if current.delegation() != props.delegation() {
+        changes.push(PropertyChange::new(
+            "delegation",
+            current.delegation(),
+            props.delegation(),
+        ));
+    }
+    if current.fail_mode() != props.fail_mode() {
+        changes.push(PropertyChange::new(
+            "failmode",
+            current.fail_mode(),
+            props.fail_mode(),
+        ));
+    }
+    if let (Some(old), Some(new)) = (current.multihost(), props.multihost()) {
+        if old != new {
+            changes.push(PropertyChange::new("multihost", old, new));
+        }
+    }
+    if let (Some(old), Some(new)) = (current.auto_trim(), props.auto_trim()) {
+        if old != new {
+            changes.push(PropertyChange::new("autotrim", old, new));
+        }
+    }
+    // Missing user property is the same as empty one.
+    let mut user_properties: Vec<_> = props.user_properties().iter().collect();
+    user_properties.sort();
+    for (key, new) in user_properties {
+        let old = current
+            .unknown_properties()
+            .get(key)
+            .cloned()
+            .unwrap_or_default();
+        if &old != new {
+            changes.push(PropertyChange::new(key, &old, new));
+        }
+    }
+    changes
+}
+
 /// Interface to manage zpools. This documentation implies that you know how to use [`zpool(8)`](https://www.freebsd.org/cgi/man.cgi?zpool(8)).
 pub trait ZpoolEngine {
     /// Check if pool with given name exists. NOTE: this won't return
@@ -319,18 +677,47 @@ pub trait ZpoolEngine {
     ///   [`CreateZpoolRequest`](vdev/enum.CreateVdevRequest.html) for more information.
     fn create(&self, request: CreateZpoolRequest) -> ZpoolResult<()>;
 
-    /// Destroy zpool. NOTE: returns `Ok(())` if pool doesn't exist.
+    /// Check what `create` would do without creating anything (`zpool create -n`). Request goes
+    /// through the same validation as in `create` and errors are reported the same way.
+    ///
+    /// Returns layout zpool would have. Since pool doesn't exist yet, health of the pool and every
+    /// device in it is [`Health::Online`](enum.Health.html) and error counters are zeroes.
+    ///
+    /// * request - A request to create a zpool.
+    fn create_dry_run(&self, request: CreateZpoolRequest) -> ZpoolResult<Zpool>;
+
+    /// Destroy zpool. Returns [`ZpoolError::PoolNotFound`](enum.ZpoolError.html) if pool doesn't
+    /// exist, use [`destroy_if_exists`](#method.destroy_if_exists) if that's fine.
     ///
     /// * `name` - Name of the zpool.
     /// * `mode` - Strategy to use when destroying the pool.
     fn destroy<N: AsRef<str>>(&self, name: N, mode: DestroyMode) -> ZpoolResult<()>;
 
-    /// Read properties of the pool. NOTE: doesn't support custom properties.
+    /// Destroy zpool if it exists. Returns `Ok(false)` if there was no such pool, every other
+    /// error is reported as is.
+    ///
+    /// * `name` - Name of the zpool.
+    /// * `mode` - Strategy to use when destroying the pool.
+    fn destroy_if_exists<N: AsRef<str>>(&self, name: N, mode: DestroyMode) -> ZpoolResult<bool> {
+        match self.destroy(name, mode) {
+            Ok(()) => Ok(true),
+            Err(ZpoolError::PoolNotFound) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Read properties of the pool. User properties end up in
+    /// [`unknown_properties`](properties/struct.ZpoolProperties.html#method.unknown_properties).
     ///
     /// * `name` - Name of the zpool.
     fn read_properties<N: AsRef<str>>(&self, name: N) -> ZpoolResult<ZpoolProperties>;
 
-    /// Update zpool properties.
+    /// Update zpool properties. Every property that differs from its current value is set, one
+    /// at a time. If setting one fails, properties set before it are restored to their previous
+    /// values and [`ZpoolError::UpdateFailed`](enum.ZpoolError.html) tells what failed and what
+    /// was restored. Returns names of properties that were changed. Fails with
+    /// `UnsupportedProperty` before changing anything if `auto_trim` is set, but installed
+    /// version of ZFS doesn't support it.
     ///
     /// * `name` - Name of the zpool.
     /// * `props` - Set of new properties for the pool.
@@ -338,44 +725,37 @@ pub trait ZpoolEngine {
         &self,
         name: N,
         props: ZpoolPropertiesWrite,
-    ) -> ZpoolResult<ZpoolProperties> {
+    ) -> ZpoolResult<Vec<String>> {
         if !self.exists(&name)? {
             return Err(ZpoolError::PoolNotFound);
         }
 
         let current = self.read_properties(&name)?;
-
-        if current.auto_expand() != props.auto_expand() {
-            self.set_property(&name, "autoexpand", props.auto_expand())?;
+        if props.auto_trim().is_some() && current.auto_trim().is_none() {
+            return Err(ZpoolError::UnsupportedProperty(String::from("autotrim")));
         }
-
-        if current.auto_replace() != props.auto_replace() {
-            self.set_property(&name, "autoreplace", props.auto_replace())?;
+        let changes = properties_delta(&current, &props);
+
+        let mut applied: Vec<&PropertyChange> = Vec::with_capacity(changes.len());
+        for change in &changes {
+            if let Err(e) = self.set_property(&name, &change.key, &change.new) {
+                let rollback = applied
+                    .iter()
+                    .rev()
+                    .map(|done| {
+                        let restored = self.set_property(&name, &done.key, &done.old).is_ok();
+                        (done.key.clone(), restored)
+                    })
+                    .collect();
+                return Err(ZpoolError::UpdateFailed {
+                    key: change.key.clone(),
+                    cause: Box::new(e),
+                    rollback,
+                });
+            }
+            applied.push(change);
         }
-
-        if current.cache_file() != props.cache_file() {
-            self.set_property(&name, "cachefile", props.cache_file())?;
-        }
-
-        // remove comment
-        let desired = if props.comment().is_empty() {
-            None
-        } else {
-            Some(props.comment().clone())
-        };
-        if current.comment() != &desired {
-            self.set_property(&name, "comment", props.comment())?;
-        }
-
-        if current.delegation() != props.delegation() {
-            self.set_property(&name, "delegation", props.delegation())?;
-        }
-
-        if current.fail_mode() != props.fail_mode() {
-            self.set_property(&name, "failmode", props.fail_mode())?;
-        }
-
-        self.read_properties(name)
+        Ok(changes.into_iter().map(|change| change.key).collect())
     }
 
     /// Internal function used to set values. Prefer
@@ -394,8 +774,17 @@ pub trait ZpoolEngine {
     /// Exports the given pools from the system.
     ///
     /// * `name` - Name of the zpool.
-    /// * `mode` - Strategy to use when destroying the pool.
-    fn export<N: AsRef<str>>(&self, name: N, mode: ExportMode) -> ZpoolResult<()>;
+    /// * `mode` - Strategy to use when exporting the pool.
+    fn export<N: AsRef<str>>(&self, name: N, mode: ExportMode) -> ZpoolResult<()> {
+        self.export_with(name, ExportOptions::from(mode))
+    }
+
+    /// Exports the given pools from the system. Fails with `PoolBusy` if some dataset of the pool
+    /// can't be unmounted.
+    ///
+    /// * `name` - Name of the zpool.
+    /// * `opts` - Force mode and whether to unmount datasets one by one first.
+    fn export_with<N: AsRef<str>>(&self, name: N, opts: ExportOptions) -> ZpoolResult<()>;
 
     /// List of pools available for import in `/dev/` directory.
     fn available(&self) -> ZpoolResult<Vec<Zpool>>;
@@ -406,14 +795,56 @@ pub trait ZpoolEngine {
     ///   from files.
     fn available_in_dir(&self, dir: PathBuf) -> ZpoolResult<Vec<Zpool>>;
 
+    /// Import pool from `/dev/` using default [`ImportOptions`](open3/struct.ImportOptions.html).
+    fn import<N: AsRef<str>>(&self, name: N) -> ZpoolResult<()> {
+        self.import_with(name, ImportOptions::default())
+    }
+
     /// Import pool from `/dev/`.
-    fn import<N: AsRef<str>>(&self, name: N) -> ZpoolResult<()>;
+    ///
+    /// * `name` - Name of the zpool.
+    /// * `opts` - Altroot, read-only mode, missing log handling and etc.
+    fn import_with<N: AsRef<str>>(&self, name: N, opts: ImportOptions) -> ZpoolResult<()>;
+
+    /// Import pool from `dir` using default [`ImportOptions`](open3/struct.ImportOptions.html).
+    ///
+    /// * `dir` - Directory to look for pools. Useful when you are looking for pool that created
+    ///   from files.
+    fn import_from_dir<N: AsRef<str>>(&self, name: N, dir: PathBuf) -> ZpoolResult<()> {
+        self.import_from_dir_with(name, dir, ImportOptions::default())
+    }
 
     /// Import pool from `dir`.
     ///
+    /// * `name` - Name of the zpool.
     /// * `dir` - Directory to look for pools. Useful when you are looking for pool that created
     ///   from files.
-    fn import_from_dir<N: AsRef<str>>(&self, name: N, dir: PathBuf) -> ZpoolResult<()>;
+    /// * `opts` - Altroot, read-only mode, missing log handling and etc.
+    fn import_from_dir_with<N: AsRef<str>>(
+        &self,
+        name: N,
+        dir: PathBuf,
+        opts: ImportOptions,
+    ) -> ZpoolResult<()>;
+
+    /// Import pool under a different name, e.g. because another pool with the same name is
+    /// already imported. `new_name` is checked before `zpool` is called, invalid name results in
+    /// [`ZpoolError::InvalidPoolName`](enum.ZpoolError.html).
+    ///
+    /// * `current` - Name the pool was exported with.
+    /// * `new_name` - Name to import the pool as.
+    /// * `dir` - Directory to look for pools. Pool is looked up in `/dev/` if `None`.
+    /// * `opts` - Altroot, read-only mode, missing log handling and etc.
+    #[cfg_attr(tarpaulin, skip)]
+    fn import_renamed<N: AsRef<str>>(
+        &self,
+        _current: N,
+        _new_name: N,
+        _dir: Option<PathBuf>,
+        _opts: ImportOptions,
+    ) -> ZpoolResult<()> {
+        Err(ZpoolError::Unimplemented)
+    }
 
     /// Get the detailed status of the given pools.
     fn status<N: AsRef<str>>(&self, name: N, opts: StatusOptions) -> ZpoolResult<Zpool>;
@@ -421,6 +852,22 @@ pub trait ZpoolEngine {
     /// Query status with options
     fn status_all(&self, opts: StatusOptions) -> ZpoolResult<Vec<Zpool>>;
 
+    /// Health of the pool. Much cheaper than [`status`](#method.status) when that's all that is
+    /// needed.
+    ///
+    /// * `name` - Name of the zpool.
+    fn health<N: AsRef<str>>(&self, name: N) -> ZpoolResult<Health>;
+
+    /// Names and health of every imported pool.
+    fn health_all(&self) -> ZpoolResult<Vec<(String, Health)>>;
+
+    /// Sizes, capacity and health of every imported pool in a single `zpool list` call. Much
+    /// cheaper than [`status_all`](#method.status_all) when topology isn't needed.
+    #[cfg_attr(tarpaulin, skip)]
+    fn list_pools(&self) -> ZpoolResult<Vec<PoolListEntry>> {
+        Err(ZpoolError::Unimplemented)
+    }
+
     /// Begins a scrub or resumes a paused scrub. The scrub examines all data
     /// in the specified pools to verify that it checksums correctly. For
     /// replicated (mirror or raidz) devices, ZFS automatically repairs any
@@ -443,13 +890,72 @@ pub trait ZpoolEngine {
     /// * `name` - Name of the zpool.
     fn stop_scrub<N: AsRef<str>>(&self, name: N) -> ZpoolResult<()>;
 
+    /// Start scrub unless one is already running or the last one completed less than `max_age`
+    /// ago. Meant to be called periodically. Paused scrub is resumed. Fails with
+    /// [`ZpoolError::ResilverInProgress`](enum.ZpoolError.html) if pool is being resilvered.
+    ///
+    /// * `name` - Name of the zpool.
+    /// * `max_age` - How long ago last scrub is allowed to complete.
+    fn ensure_scrubbed_within<N: AsRef<str>>(
+        &self,
+        name: N,
+        max_age: Duration,
+    ) -> ZpoolResult<ScrubOutcome> {
+        let zpool = self.status(&name, StatusOptions::default())?;
+        let scan = zpool.scan().as_ref();
+        if scan.is_some_and(ScanStatus::is_scrubbing) {
+            return Ok(ScrubOutcome::AlreadyRunning);
+        }
+        if let Some(finished_at) = scan.and_then(ScanStatus::last_scrub_finished_at) {
+            let age = Utc::now().timestamp() - finished_at;
+            if age < 0 || (age as u64) < max_age.as_secs() {
+                return Ok(ScrubOutcome::NotNeeded);
+            }
+        }
+        self.scrub(name)?;
+        Ok(ScrubOutcome::Started)
+    }
+
+    /// Force all in-core dirty data to be written to the pool, that is wait for the current
+    /// transaction group to be committed. Fails with
+    /// [`ZpoolError::PoolSuspended`](enum.ZpoolError.html) if pool I/O is suspended.
+    ///
+    /// * `name` - Name of the zpool. `None` to sync all pools.
+    fn sync(&self, name: Option<&str>) -> ZpoolResult<()> {
+        self.sync_with_timeout(name, None)
+    }
+
+    /// Same as `sync`, but give up after `timeout`. Sync of a pool with failing devices might
+    /// hang for a long time, in that case `zpool` is killed and
+    /// [`ZpoolError::TimedOut`](enum.ZpoolError.html) is returned. Transaction group is still
+    /// committed eventually.
+    ///
+    /// * `name` - Name of the zpool. `None` to sync all pools.
+    /// * `timeout` - How long to wait. `None` to wait as long as any other command of the engine.
+    fn sync_with_timeout(&self, name: Option<&str>, timeout: Option<Duration>) -> ZpoolResult<()>;
+
+    /// Restart resilver. Deferred resilver starts right away. Requires `resilver_defer` feature,
+    /// returns [`ZpoolError::Unsupported`](enum.ZpoolError.html) if pool or `zpool` lacks it and
+    /// [`ZpoolError::NoResilverInProgress`](enum.ZpoolError.html) if there is nothing to restart.
+    ///
+    /// * `name` - Name of the zpool.
+    fn resilver<N: AsRef<str>>(&self, name: N) -> ZpoolResult<()>;
+
+    /// Generate a new unique identifier for the pool. Useful when pool was copied (e.g. disk
+    /// image was cloned) and both copies have to be imported on the same system. Returns
+    /// [`ZpoolError::PoolBusy`](enum.ZpoolError.html) if pool is busy and
+    /// [`ZpoolError::Unsupported`](enum.ZpoolError.html) if pool can't be reguided.
+    ///
+    /// * `name` - Name of the zpool.
+    fn reguid<N: AsRef<str>>(&self, name: N) -> ZpoolResult<()>;
+
     /// Takes the specified physical device offline. While the device is
     /// offline, no attempt is made to read or write to the device.
     ///
     /// * `name` - Name of the zpool.
-    /// * `device` - Name of the device or path to sparse file.
+    /// * `device` - Name, path, GUID or [`Disk`](vdev/struct.Disk.html) of the device.
     /// * `mode` - Strategy to use when taking device offline
-    fn take_offline<N: AsRef<str>, D: AsRef<OsStr>>(
+    fn take_offline<N: AsRef<str>, D: AsDeviceArg>(
         &self,
         name: N,
         device: D,
@@ -459,9 +965,9 @@ pub trait ZpoolEngine {
     /// Brings the specified physical device online.
     ///
     /// * `name` - Name of the zpool.
-    /// * `device` - Name of the device or path to sparse file.
-    /// * `mode` - Strategy to use when taking device online
-    fn bring_online<N: AsRef<str>, D: AsRef<OsStr>>(
+    /// * `device` - Name, path, GUID or [`Disk`](vdev/struct.Disk.html) of the device.
+    /// * `mode` - Strategy to use when bringing device online
+    fn bring_online<N: AsRef<str>, D: AsDeviceArg>(
         &self,
         name: N,
         device: D,
@@ -475,21 +981,41 @@ pub trait ZpoolEngine {
     /// new_device.
     ///
     /// * `name` - Name of the zpool.
-    /// * `device` - Name of the device that you want to replace.
+    /// * `device` - Existing device, anything that implements
+    ///   [`AsDeviceArg`](vdev/trait.AsDeviceArg.html).
     /// * `new_device` - Name of the device that you want to use in place of old device.
-    fn attach<N: AsRef<str>, D: AsRef<OsStr>>(
+    fn attach<N: AsRef<str>, D: AsDeviceArg, O: AsRef<OsStr>>(
+        &self,
+        name: N,
+        device: D,
+        new_device: O,
+    ) -> ZpoolResult<()> {
+        self.attach_with(name, device, new_device, AttachOptions::default())
+    }
+
+    /// Same as [`attach`](#method.attach), but with [`AttachOptions`](open3/struct.AttachOptions.html).
+    /// With `wait` set, call doesn't return until new device has finished resilvering. A warning
+    /// is logged if physical sector size of the new device is bigger than `ashift` it gets.
+    ///
+    /// * `name` - Name of the zpool.
+    /// * `device` - Existing device that new device will mirror. Anything that implements
+    ///   [`AsDeviceArg`](vdev/trait.AsDeviceArg.html).
+    /// * `new_device` - Name of the device to attach.
+    /// * `opts` - Force, sequential resilver and wait flags, `ashift` override.
+    fn attach_with<N: AsRef<str>, D: AsDeviceArg, O: AsRef<OsStr>>(
         &self,
         name: N,
         device: D,
-        new_device: D,
+        new_device: O,
+        opts: AttachOptions,
     ) -> ZpoolResult<()>;
 
     /// Detaches device from a mirror. The operation is refused if there are no
     /// other valid replicas of the data.
     ///
     /// * `name` - Name of the zpool
-    /// * `device` - Name of the device or path to sparse file.
-    fn detach<N: AsRef<str>, D: AsRef<OsStr>>(&self, name: N, device: D) -> ZpoolResult<()>;
+    /// * `device` - Name, path, GUID or [`Disk`](vdev/struct.Disk.html) of the device.
+    fn detach<N: AsRef<str>, D: AsDeviceArg>(&self, name: N, device: D) -> ZpoolResult<()>;
 
     /// Add a VDEV to existing Zpool.
     ///
@@ -501,8 +1027,46 @@ pub trait ZpoolEngine {
         name: N,
         new_vdev: CreateVdevRequest,
         add_mode: CreateMode,
+    ) -> ZpoolResult<()> {
+        self.add_vdev_with(name, new_vdev, add_mode, AddOptions::default())
+    }
+
+    /// Same as [`add_vdev`](#method.add_vdev), but with [`AddOptions`](open3/struct.AddOptions.html).
+    /// A warning is logged if physical sector size of a new disk is bigger than `ashift` it gets.
+    ///
+    /// * `name` - Name of the zpool
+    /// * `new_vdev` - New VDEV
+    /// * `add_mode` - Disable some safety checks
+    /// * `opts` - `ashift` override.
+    fn add_vdev_with<N: AsRef<str>>(
+        &self,
+        name: N,
+        new_vdev: CreateVdevRequest,
+        add_mode: CreateMode,
+        opts: AddOptions,
     ) -> ZpoolResult<()>;
 
+    /// Same as [`add_vdev`](#method.add_vdev), but in `Gentle` mode replication level of the new
+    /// vdev is compared with top-level vdevs of the pool first. On mismatch returns
+    /// [`ZpoolError::MismatchedReplicationLevel`](enum.ZpoolError.html) naming both levels without
+    /// running `zpool add`. `Force` mode skips the check.
+    ///
+    /// * `name` - Name of the zpool
+    /// * `new_vdev` - New VDEV
+    /// * `add_mode` - Disable some safety checks
+    fn add_vdev_checked<N: AsRef<str>>(
+        &self,
+        name: N,
+        new_vdev: CreateVdevRequest,
+        add_mode: CreateMode,
+    ) -> ZpoolResult<()> {
+        if add_mode == CreateMode::Gentle {
+            let zpool = self.status(&name, StatusOptions::default())?;
+            check_replication_level(zpool.vdevs(), &new_vdev)?;
+        }
+        self.add_vdev(name, new_vdev, add_mode)
+    }
+
     /// Add a ZIL to existing Zpool.
     ///
     /// * `name` - Name of the zpool
@@ -541,26 +1105,123 @@ pub trait ZpoolEngine {
 
     /// [Replace](https://docs.oracle.com/cd/E19253-01/819-5461/gazgd/index.html) a device with another.
     ///
-    /// * `old_disk` - A disk to be replaced.
+    /// * `old_disk` - A disk to be replaced. Can be a GUID or [`Disk`](vdev/struct.Disk.html) from
+    ///   status if device is missing.
     /// * `new_disk` - A new disk.
-    fn replace_disk<N: AsRef<str>, D: AsRef<OsStr>, O: AsRef<OsStr>>(
+    fn replace_disk<N: AsRef<str>, D: AsDeviceArg, O: AsRef<OsStr>>(
         &self,
         name: N,
         old_disk: D,
         new_disk: O,
+    ) -> ZpoolResult<()> {
+        self.replace_disk_with(name, old_disk, new_disk, AttachOptions::default())
+    }
+
+    /// Same as [`replace_disk`](#method.replace_disk), but with
+    /// [`AttachOptions`](open3/struct.AttachOptions.html). With `wait` set, call doesn't return
+    /// until new disk has finished resilvering. A warning is logged if physical sector size of the
+    /// new disk is bigger than `ashift` it gets.
+    ///
+    /// * `old_disk` - A disk to be replaced. Can be a GUID or [`Disk`](vdev/struct.Disk.html) from
+    ///   status if device is missing.
+    /// * `new_disk` - A new disk.
+    /// * `opts` - Force, sequential resilver and wait flags, `ashift` override.
+    fn replace_disk_with<N: AsRef<str>, D: AsDeviceArg, O: AsRef<OsStr>>(
+        &self,
+        name: N,
+        old_disk: D,
+        new_disk: O,
+        opts: AttachOptions,
     ) -> ZpoolResult<()>;
 
+    /// Replace a device with the first hot spare of the pool that is available (`AVAIL`). Returns
+    /// path of the chosen spare. Fails with
+    /// [`ZpoolError::NoAvailableSpare`](enum.ZpoolError.html) if pool has no spares or all of
+    /// them are in use.
+    ///
+    /// * `name` - Name of the zpool.
+    /// * `failed_device` - A disk to be replaced. Can be a GUID or
+    ///   [`Disk`](vdev/struct.Disk.html) from status if device is missing.
+    fn replace_with_spare<N: AsRef<str>, D: AsDeviceArg>(
+        &self,
+        name: N,
+        failed_device: D,
+    ) -> ZpoolResult<PathBuf> {
+        let spare = self.replace_with_spare_dry_run(&name)?;
+        self.replace_disk(name, failed_device, &spare)?;
+        Ok(spare)
+    }
+
+    /// Spare [`replace_with_spare`](#method.replace_with_spare) would pick, without replacing
+    /// anything.
+    ///
+    /// * `name` - Name of the zpool.
+    fn replace_with_spare_dry_run<N: AsRef<str>>(&self, name: N) -> ZpoolResult<PathBuf> {
+        let opts = StatusOptions::builder()
+            .full_paths(true)
+            .build()
+            .expect("Failed to build StatusOptions");
+        let zpool = self.status(name, opts)?;
+        zpool
+            .spares()
+            .iter()
+            .find(|spare| spare.health() == &Health::Available)
+            .map(|spare| spare.path().clone())
+            .ok_or(ZpoolError::NoAvailableSpare)
+    }
+
     /// Remove Spare, Cache or log device
     ///
     /// * `name` - Name of the zpool
-    /// * `device` - Name of the device or path to sparse file.
-    fn remove<N: AsRef<str>, D: AsRef<OsStr>>(&self, name: N, device: D) -> ZpoolResult<()>;
+    /// * `device` - Name, path, GUID or [`Disk`](vdev/struct.Disk.html) of the device.
+    fn remove<N: AsRef<str>, D: AsDeviceArg>(&self, name: N, device: D) -> ZpoolResult<()>;
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
 
+    #[test]
+    fn modes_round_trip() {
+        use std::{fmt::Debug, fmt::Display, str::FromStr};
+
+        fn check<T>(all: &[T], names: &[&str])
+        where
+            T: Display + FromStr + PartialEq + Debug,
+            <T as FromStr>::Err: Debug,
+        {
+            assert_eq!(all.len(), names.len());
+            for (mode, name) in all.iter().zip(names) {
+                assert_eq!(*name, mode.to_string());
+                assert_eq!(mode, &name.parse::<T>().unwrap());
+                assert_eq!(mode, &name.to_uppercase().parse::<T>().unwrap());
+            }
+            assert!("".parse::<T>().is_err());
+            assert!("sometimes".parse::<T>().is_err());
+        }
+        check(
+            &[OfflineMode::Permanent, OfflineMode::UntilReboot],
+            &["permanent", "until-reboot"],
+        );
+        assert_eq!(Ok(OfflineMode::UntilReboot), "temporary".parse());
+        check(
+            &[OnlineMode::Simple, OnlineMode::Expand],
+            &["simple", "expand"],
+        );
+        check(
+            &[CreateMode::Force, CreateMode::Gentle],
+            &["force", "gentle"],
+        );
+        check(
+            &[DestroyMode::Force, DestroyMode::Gentle],
+            &["force", "gentle"],
+        );
+        check(
+            &[ExportMode::Force, ExportMode::Gentle],
+            &["force", "gentle"],
+        );
+    }
+
     #[test]
     fn error_parsing() {
         let vdev_reuse_text = b"invalid vdev specification\nuse '-f' to override the following errors:\n/vdevs/vdev0 is part of active pool 'tank'";
@@ -583,6 +1244,7 @@ mod test {
         let vdev_reuse_text = b"cannot create \'tests-8804202574521870666\': one or more vdevs refer to the same device, or one of\nthe devices is part of an active md or lvm device\n";
         let err = ZpoolError::from_stderr(vdev_reuse_text);
         assert_eq!(ZpoolErrorKind::VdevReuse, err.kind());
+        assert!(matches!(err, ZpoolError::DeviceInUse));
 
         let vdev_reuse_text = b"invalid vdev specification\nuse '-f' to override the following errors:\n/vdevs/vdev0 is part of potentially active pool 'tests-9706865472708603696'\n";
         let err = ZpoolError::from_stderr(vdev_reuse_text);
@@ -604,6 +1266,27 @@ mod test {
         assert_eq!(ZpoolErrorKind::Io, err.kind());
     }
 
+    #[test]
+    fn timed_out_from() {
+        // Only commands killed by the engine time out, other timeouts are I/O errors.
+        let timed_out = io::Error::new(io::ErrorKind::TimedOut, "too slow");
+        let err = ZpoolError::from(timed_out);
+        assert_eq!(ZpoolErrorKind::Io, err.kind());
+
+        let killed = CommandTimedOut {
+            command: String::from("zpool sync tank"),
+            after: Duration::from_secs(1),
+        };
+        let err = ZpoolError::from(io::Error::new(io::ErrorKind::TimedOut, killed));
+        match err {
+            ZpoolError::TimedOut { command, after } => {
+                assert_eq!("zpool sync tank", command);
+                assert_eq!(Duration::from_secs(1), after);
+            }
+            other => panic!("unexpected error: {:?}", other),
+        }
+    }
+
     //noinspection RsTypeCheck
     #[test]
     fn num_error_from() {
@@ -691,6 +1374,47 @@ mod test {
         let text = b"invalid vdev specification\nuse \'-f\' to override the following errors:\nmismatched replication level: pool uses raidz and new vdev is mirror";
         let err = ZpoolError::from_stderr(text);
         assert_eq!(ZpoolErrorKind::MismatchedReplicationLevel, err.kind());
+        if let ZpoolError::MismatchedReplicationLevel(pool, new) = err {
+            assert_eq!("raidz", pool);
+            assert_eq!("mirror", new);
+        } else {
+            panic!("unexpected error: {:?}", err);
+        }
+
+        let text = b"invalid vdev specification\nuse '-f' to override the following errors:\nmismatched replication level: both mirror and raidz vdevs are present\n";
+        let err = ZpoolError::from_stderr(text);
+        assert_eq!(ZpoolErrorKind::MismatchedReplicationLevel, err.kind());
+        assert!(matches!(err, ZpoolError::InconsistentReplication));
+    }
+
+    #[test]
+    fn test_check_replication_level() {
+        let vdev = |kind| {
+            Vdev::builder()
+                .kind(kind)
+                .health(Health::Online)
+                .disks(vec![])
+                .build()
+                .unwrap()
+        };
+        let mirror = CreateVdevRequest::Mirror(vec!["/a".into(), "/b".into()]);
+        let disk = CreateVdevRequest::SingleDisk("/c".into());
+
+        assert!(check_replication_level(&[], &mirror).is_ok());
+        assert!(check_replication_level(&[vdev(VdevType::Mirror)], &mirror).is_ok());
+        assert!(check_replication_level(&[vdev(VdevType::Replacing)], &disk).is_ok());
+
+        let err = check_replication_level(&[vdev(VdevType::RaidZ)], &mirror).unwrap_err();
+        assert_eq!("pool uses raidz and new vdev is mirror", err.to_string());
+        let err =
+            check_replication_level(&[vdev(VdevType::SingleDisk), vdev(VdevType::Mirror)], &disk)
+                .unwrap_err();
+        if let ZpoolError::MismatchedReplicationLevel(pool, new) = err {
+            assert_eq!("mirror", pool);
+            assert_eq!("disk", new);
+        } else {
+            panic!("unexpected error: {:?}", err);
+        }
     }
 
     #[test]
@@ -699,4 +1423,137 @@ mod test {
         let err = ZpoolError::from_stderr(text);
         assert_eq!(ZpoolErrorKind::InvalidCacheDevice, err.kind());
     }
+
+    #[test]
+    fn test_pool_busy() {
+        let text = b"cannot unmount '/tank/data': pool or dataset is busy\ncould not export 'tank': failed to unmount datasets\n";
+        let err = ZpoolError::from_stderr(text);
+        assert_eq!(ZpoolErrorKind::PoolBusy, err.kind());
+        if let ZpoolError::PoolBusy(dataset) = err {
+            assert_eq!(Some(PathBuf::from("/tank/data")), dataset);
+        }
+
+        let text = b"cannot unmount '/tank': Device busy\n";
+        let err = ZpoolError::from_stderr(text);
+        assert_eq!(ZpoolErrorKind::PoolBusy, err.kind());
+
+        let text = b"cannot export 'tank': pool is busy\n";
+        let err = ZpoolError::from_stderr(text);
+        assert_eq!(ZpoolErrorKind::PoolBusy, err.kind());
+        if let ZpoolError::PoolBusy(dataset) = err {
+            assert_eq!(None, dataset);
+        }
+
+        let text = b"cannot reguid 'tank': pool is busy\n";
+        let err = ZpoolError::from_stderr(text);
+        assert_eq!(ZpoolErrorKind::PoolBusy, err.kind());
+    }
+
+    #[test]
+    fn test_reguid_unsupported() {
+        let text = b"cannot reguid 'tank': operation not supported on this type of pool\n";
+        let err = ZpoolError::from_stderr(text);
+        assert_eq!(ZpoolErrorKind::Unsupported, err.kind());
+    }
+
+    #[test]
+    fn test_unsupported_property() {
+        let text = b"cannot set property for 'tank': invalid property 'autotrim'\n";
+        let err = ZpoolError::from_stderr(text);
+        assert_eq!(ZpoolErrorKind::UnsupportedProperty, err.kind());
+        if let ZpoolError::UnsupportedProperty(property) = err {
+            assert_eq!("autotrim", property);
+        }
+
+        let text = b"property 'autotrim' is not a valid pool property\n";
+        let err = ZpoolError::from_stderr(text);
+        assert_eq!(ZpoolErrorKind::UnsupportedProperty, err.kind());
+    }
+
+    #[test]
+    fn test_resilver_errors() {
+        let text = b"unrecognized command 'resilver'\nusage: zpool command args ...\n";
+        let err = ZpoolError::from_stderr(text);
+        assert_eq!(ZpoolErrorKind::Unsupported, err.kind());
+
+        let text =
+            b"cannot restart resilver on tank: this action requires the resilver_defer feature\n";
+        let err = ZpoolError::from_stderr(text);
+        assert_eq!(ZpoolErrorKind::Unsupported, err.kind());
+
+        let text = b"cannot scrub tank: currently resilvering\n";
+        let err = ZpoolError::from_stderr(text);
+        assert_eq!(ZpoolErrorKind::ResilverInProgress, err.kind());
+
+        let text = b"cannot restart resilver on tank: currently scrubbing; use 'zpool scrub -s' to cancel current scrub\n";
+        let err = ZpoolError::from_stderr(text);
+        assert_eq!(ZpoolErrorKind::ScrubInProgress, err.kind());
+
+        let text = b"cannot restart resilver on tank: there is no active scrub\n";
+        let err = ZpoolError::from_stderr(text);
+        assert_eq!(ZpoolErrorKind::NoResilverInProgress, err.kind());
+
+        let text = b"cannot restart resilver on tank: no resilver in progress\n";
+        let err = ZpoolError::from_stderr(text);
+        assert_eq!(ZpoolErrorKind::NoResilverInProgress, err.kind());
+    }
+
+    #[test]
+    fn test_pool_in_use() {
+        let text = b"cannot import 'tank': pool may be in use from other system, it was last accessed by storage-01 (hostid: 0x2ad8ac3a) on Sat Mar  7 11:03:53 2020\nuse '-f' to import anyway\n";
+        let err = ZpoolError::from_stderr(text);
+        assert_eq!(ZpoolErrorKind::PoolInUse, err.kind());
+        if let ZpoolError::PoolInUse(host) = err {
+            assert_eq!(Some(String::from("storage-01")), host);
+        }
+
+        let text = b"cannot import 'tank': pool was previously in use from another system.\nLast accessed by storage-02 (hostid=8a3d5c11) at Sat Mar  7 11:03:53 2020\nThe pool can be imported, use 'zpool import -f' to import the pool.\n";
+        let err = ZpoolError::from_stderr(text);
+        assert_eq!(ZpoolErrorKind::PoolInUse, err.kind());
+        if let ZpoolError::PoolInUse(host) = err {
+            assert_eq!(Some(String::from("storage-02")), host);
+        }
+
+        let text = b"cannot import 'tank': pool may be in use from other system\n";
+        let err = ZpoolError::from_stderr(text);
+        assert_eq!(ZpoolErrorKind::PoolInUse, err.kind());
+        if let ZpoolError::PoolInUse(host) = err {
+            assert_eq!(None, host);
+        }
+    }
+
+    #[test]
+    fn test_active_on_other_host() {
+        let text = include_bytes!("fixtures/import_active_on_other_host_linux");
+        match ZpoolError::from_stderr(text) {
+            ZpoolError::ActiveOnOtherHost { host, hostid } => {
+                assert_eq!("storage-02", host);
+                assert_eq!(0x8a3d_5c11, hostid);
+            }
+            other => panic!("unexpected error: {:?}", other),
+        }
+
+        let text = include_bytes!("fixtures/import_active_on_other_host_freebsd");
+        match ZpoolError::from_stderr(text) {
+            ZpoolError::ActiveOnOtherHost { host, hostid } => {
+                assert_eq!("storage-01", host);
+                assert_eq!(0x2ad8_ac3a, hostid);
+            }
+            other => panic!("unexpected error: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_pool_suspended() {
+        let text = b"cannot sync 'tank': pool I/O is currently suspended\n";
+        let err = ZpoolError::from_stderr(text);
+        assert_eq!(ZpoolErrorKind::PoolSuspended, err.kind());
+    }
+
+    #[test]
+    fn test_devices_unavailable() {
+        let text = b"cannot import 'tank': one or more devices is currently unavailable\n";
+        let err = ZpoolError::from_stderr(text);
+        assert_eq!(ZpoolErrorKind::DevicesUnavailable, err.kind());
+    }
 }
@@ -0,0 +1,318 @@
+//! Watch error counters of pool devices over time.
+//!
+//! `zpool status` only shows how many read, write and checksum errors every device had since the
+//! last `zpool clear`. A flaky disk shows up as counters that keep growing, which is only visible
+//! when status is looked at repeatedly. [`ErrorMonitor`](struct.ErrorMonitor.html) does that in a
+//! background thread, keeps recent samples and tells how much counters grew.
+//!
+//! ### Usage
+//! ```rust,no_run
+//! use libzetta::zpool::{monitor::ErrorMonitor, ZpoolOpen3};
+//! use std::time::Duration;
+//!
+//! let monitor = ErrorMonitor::new(ZpoolOpen3::default(), "tank", Duration::from_secs(60));
+//! monitor.on_increase(|device, delta| {
+//!     eprintln!("{} got {} more checksum errors", device.display(), delta.checksum);
+//! });
+//! // ... later
+//! for (device, delta) in monitor.deltas_since(Duration::from_secs(3600)) {
+//!     println!("{}: {:?}", device.display(), delta);
+//! }
+//! ```
+use std::{
+    collections::{HashMap, VecDeque},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    utils::{lock, Poller},
+    zpool::{open3::StatusOptions, vdev::ErrorStatistics, Zpool, ZpoolEngine, ZpoolResult},
+    GlobalLogger,
+};
+
+/// How many samples are kept. Older samples are dropped.
+const HISTORY_LEN: usize = 1024;
+
+/// Error counters of every device at one point in time.
+struct Sample {
+    taken_at: Instant,
+    devices: HashMap<PathBuf, ErrorStatistics>,
+}
+
+impl Sample {
+    fn of(zpool: &Zpool, taken_at: Instant) -> Sample {
+        let mut devices = HashMap::new();
+        // A spare that took over a device is listed twice, the vdev entry has the counters.
+        for (_, disk) in zpool.all_disks() {
+            devices
+                .entry(disk.path().clone())
+                .or_insert_with(|| disk.error_statistics().clone());
+        }
+        Sample { taken_at, devices }
+    }
+
+    /// How much counters grew since `earlier`, for devices where any of them did. Counters that
+    /// went down (`zpool clear`) count as no growth.
+    fn increase_since(&self, earlier: &Sample) -> Vec<(PathBuf, ErrorStatistics)> {
+        let zero = ErrorStatistics::default();
+        let mut increases: Vec<(PathBuf, ErrorStatistics)> = self
+            .devices
+            .iter()
+            .map(|(device, now)| {
+                let then = earlier.devices.get(device).unwrap_or(&zero);
+                let delta = ErrorStatistics {
+                    read: now.read.saturating_sub(then.read),
+                    write: now.write.saturating_sub(then.write),
+                    checksum: now.checksum.saturating_sub(then.checksum),
+                };
+                (device.clone(), delta)
+            })
+            .filter(|(_, delta)| *delta != zero)
+            .collect();
+        increases.sort_by(|a, b| a.0.cmp(&b.0));
+        increases
+    }
+}
+
+/// Samples error counters of pool devices in a background thread. The thread is stopped and
+/// joined when the monitor is dropped.
+pub struct ErrorMonitor {
+    history: Arc<Mutex<VecDeque<Sample>>>,
+    poller: Poller<(PathBuf, ErrorStatistics)>,
+}
+
+impl ErrorMonitor {
+    /// Start sampling `status` of `pool` every `interval`. The engine is moved to the sampling
+    /// thread. Failed samples are logged and skipped. If the engine panics, sampling stops and
+    /// the engine is dropped along with the thread, see
+    /// [`is_running`](#method.is_running).
+    pub fn new<E, N>(engine: E, pool: N, interval: Duration) -> ErrorMonitor
+    where
+        E: ZpoolEngine + Send + 'static,
+        N: Into<String>,
+    {
+        let pool = pool.into();
+        // Exact counters, human readable ones stop changing past a thousand.
+        let opts = StatusOptions::builder()
+            .parseable(true)
+            .build()
+            .expect("Failed to build StatusOptions");
+        ErrorMonitor::with_sampler(move || engine.status(&pool, opts.clone()), interval)
+    }
+
+    /// Start sampling with `sample` in place of `zpool status`.
+    pub(crate) fn with_sampler<F>(mut sample: F, interval: Duration) -> ErrorMonitor
+    where
+        F: FnMut() -> ZpoolResult<Zpool> + Send + 'static,
+    {
+        let history = Arc::new(Mutex::new(VecDeque::with_capacity(HISTORY_LEN)));
+        let logger =
+            GlobalLogger::get().new(o!("zetta_module" => "zpool", "zpool_impl" => "monitor"));
+        let thread_history = Arc::clone(&history);
+        let poll_logger = logger.clone();
+        let poller = Poller::start(
+            move || match sample() {
+                Ok(zpool) => record(&thread_history, Sample::of(&zpool, Instant::now())),
+                Err(e) => {
+                    warn!(poll_logger, "failed to sample pool status"; "error" => %e);
+                    Vec::new()
+                }
+            },
+            interval,
+            logger,
+        );
+        ErrorMonitor { history, poller }
+    }
+
+    /// Call `callback` with the device and how much its counters grew every time a sample shows
+    /// growth since the previous one. Callbacks run on the sampling thread, one that panics is
+    /// logged and sampling goes on.
+    pub fn on_increase<F>(&self, callback: F)
+    where
+        F: Fn(&Path, &ErrorStatistics) + Send + 'static,
+    {
+        self.poller
+            .on_event(move |(device, delta)| callback(device, delta));
+    }
+
+    /// How much counters grew between the last sample taken at least `period` ago (or the oldest
+    /// sample kept) and the latest one. Only devices whose counters grew are listed, sorted by
+    /// path.
+    pub fn deltas_since(&self, period: Duration) -> Vec<(PathBuf, ErrorStatistics)> {
+        let history = lock(&self.history);
+        let latest = match history.back() {
+            Some(latest) => latest,
+            None => return Vec::new(),
+        };
+        let baseline = history
+            .iter()
+            .rev()
+            .find(|sample| latest.taken_at.duration_since(sample.taken_at) >= period)
+            .or_else(|| history.front())
+            .unwrap_or(latest);
+        latest.increase_since(baseline)
+    }
+
+    /// How many samples are kept right now.
+    pub fn samples(&self) -> usize {
+        lock(&self.history).len()
+    }
+
+    /// Whether the sampling thread is still alive. It only stops on its own if the engine
+    /// panicked.
+    pub fn is_running(&self) -> bool {
+        self.poller.is_running()
+    }
+}
+
+/// Add `sample` to history and tell which devices got more errors since the previous sample.
+fn record(history: &Mutex<VecDeque<Sample>>, sample: Sample) -> Vec<(PathBuf, ErrorStatistics)> {
+    let mut history = lock(history);
+    let increases = history
+        .back()
+        .map(|previous| sample.increase_since(previous))
+        .unwrap_or_default();
+    if history.len() == HISTORY_LEN {
+        history.pop_front();
+    }
+    history.push_back(sample);
+    increases
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::zpool::{Disk, Health, Vdev, VdevType, ZpoolError};
+    use std::{sync::mpsc, thread};
+
+    fn zpool(errors: &[(&str, u64, u64, u64)]) -> Zpool {
+        let disks = errors
+            .iter()
+            .map(|(path, read, write, checksum)| {
+                Disk::builder()
+                    .path(*path)
+                    .health(Health::Online)
+                    .error_statistics(ErrorStatistics {
+                        read: *read,
+                        write: *write,
+                        checksum: *checksum,
+                    })
+                    .build()
+                    .unwrap()
+            })
+            .collect();
+        let vdev = Vdev::builder()
+            .kind(VdevType::Mirror)
+            .health(Health::Online)
+            .disks(disks)
+            .build()
+            .unwrap();
+        Zpool::builder()
+            .name("tank")
+            .health(Health::Online)
+            .vdevs(vec![vdev])
+            .build()
+            .unwrap()
+    }
+
+    /// Sampler that returns `script` one by one and reports on `done` once it runs out.
+    fn scripted(
+        script: Vec<ZpoolResult<Zpool>>,
+        done: mpsc::Sender<()>,
+    ) -> impl FnMut() -> ZpoolResult<Zpool> + Send + 'static {
+        let mut script = script.into_iter();
+        move || match script.next() {
+            Some(status) => status,
+            None => {
+                let _ = done.send(());
+                Err(ZpoolError::PoolNotFound)
+            }
+        }
+    }
+
+    #[test]
+    fn deltas_and_callbacks() {
+        let (done, finished) = mpsc::channel();
+        let script = vec![
+            Ok(zpool(&[("sda", 0, 0, 0), ("sdb", 1, 0, 0)])),
+            Ok(zpool(&[("sda", 0, 0, 2), ("sdb", 1, 0, 0)])),
+            Err(ZpoolError::PoolNotFound),
+            // `zpool clear` in between.
+            Ok(zpool(&[("sda", 0, 0, 0), ("sdb", 1, 3, 0)])),
+        ];
+        let (increased, increases) = mpsc::channel();
+        let monitor = ErrorMonitor::with_sampler(scripted(script, done), Duration::from_millis(1));
+        monitor.on_increase(move |device, delta| {
+            let _ = increased.send((device.to_path_buf(), delta.clone()));
+        });
+        finished.recv_timeout(Duration::from_secs(10)).unwrap();
+
+        assert_eq!(3, monitor.samples());
+        assert!(monitor.is_running());
+        let since_start = monitor.deltas_since(Duration::from_secs(3600));
+        let expected = vec![(
+            PathBuf::from("sdb"),
+            ErrorStatistics {
+                read: 0,
+                write: 3,
+                checksum: 0,
+            },
+        )];
+        assert_eq!(expected, since_start);
+        assert!(monitor.deltas_since(Duration::from_secs(0)).is_empty());
+
+        // Callback may have been registered after the first increase was seen.
+        let seen: Vec<(PathBuf, ErrorStatistics)> = increases.try_iter().collect();
+        assert!(seen.contains(&expected[0]));
+        drop(monitor);
+    }
+
+    #[test]
+    fn panics_stop_sampling_but_not_drop() {
+        let monitor = ErrorMonitor::with_sampler(
+            || -> ZpoolResult<Zpool> { panic!("engine exploded") },
+            Duration::from_millis(1),
+        );
+        let started = Instant::now();
+        while monitor.is_running() && started.elapsed() < Duration::from_secs(10) {
+            thread::yield_now();
+        }
+        assert!(!monitor.is_running());
+        assert_eq!(0, monitor.samples());
+        assert!(monitor.deltas_since(Duration::from_secs(1)).is_empty());
+    }
+
+    #[test]
+    fn panicking_callback_is_survived() {
+        let (done, finished) = mpsc::channel();
+        let script = vec![
+            Ok(zpool(&[("sda", 0, 0, 0)])),
+            Ok(zpool(&[("sda", 1, 0, 0)])),
+            Ok(zpool(&[("sda", 2, 0, 0)])),
+        ];
+        let (called, calls) = mpsc::channel();
+        // Samples are only taken once callbacks are registered and the gate is opened.
+        let (gate, opened) = mpsc::channel::<()>();
+        let mut sampler = scripted(script, done);
+        let monitor = ErrorMonitor::with_sampler(
+            move || {
+                let _ = opened.recv_timeout(Duration::from_secs(10));
+                sampler()
+            },
+            Duration::from_millis(1),
+        );
+        monitor.on_increase(|_, _| panic!("callback exploded"));
+        monitor.on_increase(move |_, delta| {
+            let _ = called.send(delta.read);
+        });
+        for _ in 0..4 {
+            gate.send(()).unwrap();
+        }
+        drop(gate);
+        finished.recv_timeout(Duration::from_secs(10)).unwrap();
+        assert_eq!(vec![1, 1], calls.try_iter().collect::<Vec<u64>>());
+        assert!(monitor.is_running());
+    }
+}
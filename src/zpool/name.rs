@@ -0,0 +1,133 @@
+//! Rules `zpool(8)` has for pool names, checked before `zpool` is called so a bad name is reported
+//! as such rather than as whatever `zpool` prints about it.
+//!
+//! ```rust
+//! use libzetta::zpool::{validate_pool_name, PoolNameError};
+//!
+//! assert_eq!(Ok(()), validate_pool_name("tank-01.backup"));
+//! assert_eq!(Err(PoolNameError::Reserved("mirror")), validate_pool_name("mirrored"));
+//! ```
+
+/// Longest pool name: `ZFS_MAX_DATASET_NAME_LEN` less room for `<pool>/$ORIGIN@$ORIGIN`, the
+/// snapshot every pool has.
+pub const MAX_POOL_NAME_LEN: usize = 256 - 2 - 2 * "$ORIGIN".len() - 1;
+
+/// Names that are reserved along with every name starting with them.
+const RESERVED_PREFIXES: [&str; 4] = ["mirror", "raidz", "draid", "spare"];
+/// Names that are reserved, but only as a whole.
+const RESERVED_NAMES: [&str; 1] = ["log"];
+
+quick_error! {
+    /// Rule of `zpool(8)` a pool name breaks.
+    #[derive(Debug, Clone, Eq, PartialEq)]
+    pub enum PoolNameError {
+        /// Name is empty.
+        Empty {
+            display("name is empty")
+        }
+        /// Name is longer than [`MAX_POOL_NAME_LEN`](constant.MAX_POOL_NAME_LEN.html). Contains
+        /// length of the name.
+        TooLong(len: usize) {
+            display("name is {} bytes long, at most {} allowed", len, MAX_POOL_NAME_LEN)
+        }
+        /// Name doesn't start with a letter, e.g. it starts with a digit, `-` or `.`.
+        InvalidFirstCharacter(ch: char) {
+            display("name must start with a letter, not {:?}", ch)
+        }
+        /// Name has a character other than alphanumeric, `_`, `-`, `.` and `:`.
+        InvalidCharacter(ch: char) {
+            display("{:?} isn't allowed in a name", ch)
+        }
+        /// Name is, or starts with, a word `zpool` uses in vdev specifications.
+        Reserved(word: &'static str) {
+            display("{:?} is reserved for vdev specifications", word)
+        }
+        /// Name starts with `c` and a digit, so it looks like a Solaris disk name.
+        DiskLike {
+            display("names starting with c[0-9] are reserved")
+        }
+    }
+}
+
+/// Check `name` against rules `zpool(8)` has for pool names:
+///  * it's between 1 and [`MAX_POOL_NAME_LEN`](constant.MAX_POOL_NAME_LEN.html) bytes long,
+///  * it starts with a letter and has only alphanumeric characters, `_`, `-`, `.` and `:`,
+///  * it isn't `log` and doesn't start with `mirror`, `raidz`, `draid`, `spare` or `c[0-9]`.
+///
+/// Name made of digits only would be taken for a pool GUID by `zpool import`, that's covered by the
+/// first letter rule.
+pub fn validate_pool_name(name: &str) -> Result<(), PoolNameError> {
+    let first = name.chars().next().ok_or(PoolNameError::Empty)?;
+    if name.len() > MAX_POOL_NAME_LEN {
+        return Err(PoolNameError::TooLong(name.len()));
+    }
+    if let Some(ch) = name
+        .chars()
+        .find(|c| !(c.is_ascii_alphanumeric() || "_-.:".contains(*c)))
+    {
+        return Err(PoolNameError::InvalidCharacter(ch));
+    }
+    if !first.is_ascii_alphabetic() {
+        return Err(PoolNameError::InvalidFirstCharacter(first));
+    }
+    if let Some(word) = RESERVED_PREFIXES
+        .iter()
+        .find(|prefix| name.starts_with(*prefix))
+    {
+        return Err(PoolNameError::Reserved(word));
+    }
+    if let Some(word) = RESERVED_NAMES.iter().find(|word| name == **word) {
+        return Err(PoolNameError::Reserved(word));
+    }
+    if first == 'c' && name[1..].starts_with(|c: char| c.is_ascii_digit()) {
+        return Err(PoolNameError::DiskLike);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn pool_name_rules() {
+        let longest = format!("t{}", "a".repeat(MAX_POOL_NAME_LEN - 1));
+        let too_long = format!("t{}", "a".repeat(MAX_POOL_NAME_LEN));
+        let table: Vec<(&str, Result<(), PoolNameError>)> = vec![
+            ("tank", Ok(())),
+            ("tank-01.backup", Ok(())),
+            ("backup:tank_2", Ok(())),
+            ("logs", Ok(())),
+            ("cache", Ok(())),
+            ("c", Ok(())),
+            ("ca0", Ok(())),
+            ("Mirror", Ok(())),
+            (&longest, Ok(())),
+            ("", Err(PoolNameError::Empty)),
+            (
+                &too_long,
+                Err(PoolNameError::TooLong(MAX_POOL_NAME_LEN + 1)),
+            ),
+            ("1234567890", Err(PoolNameError::InvalidFirstCharacter('1'))),
+            ("-tank", Err(PoolNameError::InvalidFirstCharacter('-'))),
+            (".tank", Err(PoolNameError::InvalidFirstCharacter('.'))),
+            ("_tank", Err(PoolNameError::InvalidFirstCharacter('_'))),
+            ("back up", Err(PoolNameError::InvalidCharacter(' '))),
+            ("tank/a", Err(PoolNameError::InvalidCharacter('/'))),
+            ("tank@a", Err(PoolNameError::InvalidCharacter('@'))),
+            ("tänk", Err(PoolNameError::InvalidCharacter('ä'))),
+            ("mirror", Err(PoolNameError::Reserved("mirror"))),
+            ("mirror0", Err(PoolNameError::Reserved("mirror"))),
+            ("raidz", Err(PoolNameError::Reserved("raidz"))),
+            ("raidz2-pool", Err(PoolNameError::Reserved("raidz"))),
+            ("draid1", Err(PoolNameError::Reserved("draid"))),
+            ("spares", Err(PoolNameError::Reserved("spare"))),
+            ("log", Err(PoolNameError::Reserved("log"))),
+            ("c0", Err(PoolNameError::DiskLike)),
+            ("c1t0d0", Err(PoolNameError::DiskLike)),
+        ];
+        for (name, expected) in table {
+            assert_eq!(expected, validate_pool_name(name), "{:?}", name);
+        }
+    }
+}
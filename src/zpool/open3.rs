@@ -24,37 +24,47 @@
 use std::{
     env,
     ffi::{OsStr, OsString},
-    path::PathBuf,
+    io,
+    path::{Path, PathBuf},
     process::{Command, Output, Stdio},
+    sync::Arc,
+    time::Duration,
 };
 
 use crate::{
+    instrumentation::{self, InstrumentationHook},
     parsers::{Rule, StdoutParser},
+    zfs::{self, ZfsEngine, ZfsOpen3},
     zpool::description::Zpool,
     GlobalLogger,
 };
+#[cfg(feature = "serde")]
+use chrono::Utc;
+#[cfg(feature = "serde")]
+use once_cell::sync::OnceCell;
 use pest::Parser;
 use slog::Logger;
 
+#[cfg(any(target_os = "linux", target_os = "freebsd"))]
+use super::device_info::{self, SectorSize};
+#[cfg(feature = "serde")]
+use super::status_json;
 use super::{
-    CreateMode, CreateVdevRequest, CreateZpoolRequest, DestroyMode, ExportMode, OfflineMode,
-    OnlineMode, PropPair, ZpoolEngine, ZpoolError, ZpoolProperties, ZpoolResult,
+    validate_pool_name, AsDeviceArg, CreateMode, CreateVdevRequest, CreateZpoolRequest,
+    DestroyMode, ExportMode, Health, OfflineMode, OnlineMode, PoolListEntry, PropPair, ZpoolEngine,
+    ZpoolError, ZpoolProperties, ZpoolResult,
 };
 
-lazy_static! {
-    static ref ZPOOL_PROP_ARG: OsString = {
-        let mut arg = OsString::with_capacity(171);
-        arg.push("alloc,cap,comment,dedupratio,expandsize,fragmentation,free,");
-        arg.push("freeing,guid,health,size,leaked,altroot,readonly,autoexpand,");
-        arg.push("autoreplace,bootfs,cachefile,dedupditto,delegation,failmode");
-        arg
-    };
-}
 /// Open3 implementation of [`ZpoolEngine`](../trait.ZpoolEngine.html). You can use
 /// `ZpoolOpen3::default` to create it.
 pub struct ZpoolOpen3 {
     cmd_name: OsString,
     logger: Logger,
+    instrumentation: Option<Arc<InstrumentationHook>>,
+    command_timeout: Option<Duration>,
+    /// Whether `zpool status` takes `-j`, checked on first use.
+    #[cfg(feature = "serde")]
+    status_json: OnceCell<bool>,
 }
 
 impl Default for ZpoolOpen3 {
@@ -68,7 +78,14 @@ impl Default for ZpoolOpen3 {
 
         let logger =
             GlobalLogger::get().new(o!("zetta_module" => "zpool", "zpool_impl" => "open3"));
-        ZpoolOpen3 { cmd_name, logger }
+        ZpoolOpen3 {
+            cmd_name,
+            logger,
+            instrumentation: None,
+            command_timeout: None,
+            #[cfg(feature = "serde")]
+            status_json: OnceCell::new(),
+        }
     }
 }
 impl ZpoolOpen3 {
@@ -80,10 +97,85 @@ impl ZpoolOpen3 {
         z
     }
 
+    /// Install a hook that receives [`CommandMetrics`](../../instrumentation/struct.CommandMetrics.html)
+    /// of every spawned command, including `zfs` commands used to unmount datasets on export.
+    pub fn set_instrumentation(&mut self, hook: InstrumentationHook) {
+        self.instrumentation = Some(Arc::new(hook));
+    }
+
+    /// Kill commands that don't exit within `timeout` and fail with `ZpoolError::TimedOut`.
+    /// Commands that hang in the kernel (e.g. on a suspended pool with `failmode=wait`) would
+    /// otherwise block the caller forever. Applies to `zfs` commands used on export as well.
+    /// `None`, the default, waits forever.
+    pub fn set_command_timeout(&mut self, timeout: Option<Duration>) {
+        self.command_timeout = timeout;
+    }
+
+    /// Run command to completion within command timeout, timing it.
+    fn output(&self, z: &mut Command) -> io::Result<Output> {
+        instrumentation::run_command_within(
+            &self.logger,
+            self.instrumentation.as_ref(),
+            z,
+            self.command_timeout,
+        )
+    }
+
     fn zpool(&self) -> Command {
-        Command::new(&self.cmd_name)
+        instrumentation::isolated_command(&self.cmd_name)
+    }
+
+    /// `ashift` property of the pool. `None` if it can't be read, `Some(0)` means auto-detection.
+    fn pool_ashift(&self, name: &str) -> Option<u8> {
+        let mut z = self.zpool();
+        z.args(["get", "-Hp", "-o", "value", "ashift", name]);
+        debug!(self.logger, "executing"; "cmd" => format_args!("{:?}", z));
+        let out = self.output(&mut z).ok()?;
+        if !out.status.success() {
+            return None;
+        }
+        String::from_utf8_lossy(&out.stdout).trim().parse().ok()
+    }
+
+    /// Log a warning for every device whose physical sector size is bigger than `ashift` it's
+    /// about to get: the override if set, otherwise `ashift` of the pool. Devices that aren't
+    /// block devices are skipped. Nothing is checked if `ashift` is left to auto-detection.
+    #[cfg(any(target_os = "linux", target_os = "freebsd"))]
+    fn warn_on_sector_size(&self, name: &str, devices: &[&OsStr], ashift: Option<u8>) {
+        let sizes: Vec<(&OsStr, SectorSize)> = devices
+            .iter()
+            .filter_map(|device| {
+                device_info::sector_size_within(
+                    Path::new(device),
+                    &self.logger,
+                    self.instrumentation.as_ref(),
+                    self.command_timeout,
+                )
+                .ok()
+                .map(|size| (*device, size))
+            })
+            .collect();
+        if sizes.is_empty() {
+            return;
+        }
+        let ashift = match ashift.or_else(|| self.pool_ashift(name)) {
+            Some(ashift) if ashift > 0 => ashift,
+            _ => return,
+        };
+        for (device, size) in sizes {
+            if size.ashift() > ashift {
+                warn!(self.logger, "physical sector size of the device is bigger than ashift";
+                      "pool" => name,
+                      "device" => device.to_string_lossy().as_ref(),
+                      "physical_sector_size" => size.physical(),
+                      "ashift" => ashift);
+            }
+        }
     }
 
+    #[cfg(not(any(target_os = "linux", target_os = "freebsd")))]
+    fn warn_on_sector_size(&self, _name: &str, _devices: &[&OsStr], _ashift: Option<u8>) {}
+
     #[allow(dead_code)]
     /// Force disable logging by using `/dev/null` as drain.
     fn zpool_mute(&self) -> Command {
@@ -93,12 +185,113 @@ impl ZpoolOpen3 {
         z
     }
 
+    /// Build `zpool create` command for the request. With `dry_run` nothing is created and
+    /// `zpool` prints layout instead.
+    fn create_command(&self, request: CreateZpoolRequest, dry_run: bool) -> ZpoolResult<Command> {
+        if !request.is_suitable_for_create() {
+            return Err(ZpoolError::InvalidTopology);
+        }
+        if *request.resolve_devices() {
+            request.validate_devices()?;
+        }
+        let mut z = self.zpool();
+        z.arg("create");
+        if dry_run {
+            z.arg("-n");
+        }
+        if request.create_mode() == &CreateMode::Force {
+            z.arg("-f");
+        }
+        if let Some(props) = request.props().clone() {
+            for arg in props.into_args() {
+                z.arg("-o");
+                z.arg(arg);
+            }
+        }
+        for arg in request.feature_args() {
+            z.arg("-o");
+            z.arg(arg);
+        }
+        if let Some(mount) = request.mount().clone() {
+            z.arg("-m");
+            z.arg(mount);
+        }
+        if let Some(altroot) = request.altroot().clone() {
+            z.arg("-R");
+            z.arg(altroot);
+        }
+        z.arg(request.name());
+        z.args(request.into_args());
+        Ok(z)
+    }
+
+    /// Unmount every mounted dataset of the pool, children before parents.
+    fn unmount_datasets(&self, name: &str) -> ZpoolResult<()> {
+        let mut zfs = ZfsOpen3::new();
+        if let Some(hook) = &self.instrumentation {
+            zfs.share_instrumentation(Arc::clone(hook));
+        }
+        zfs.set_command_timeout(self.command_timeout);
+        let mount_table = zfs.mounted_datasets().map_err(from_zfs_error)?;
+        for dataset in datasets_to_unmount(name, mount_table) {
+            debug!(self.logger, "unmounting before export"; "dataset" => dataset.display());
+            if let Err(err) = zfs.unmount(dataset.clone()) {
+                return Err(unmount_error(dataset, err));
+            }
+        }
+        Ok(())
+    }
+
+    /// `zpool status` of one pool or every pool. JSON output is read if it's preferred and `zpool`
+    /// supports it, text is parsed otherwise.
+    fn status_of(&self, name: Option<&str>, opts: StatusOptions) -> ZpoolResult<Vec<Zpool>> {
+        #[cfg(feature = "serde")]
+        {
+            if opts.prefer_json && !opts.verbose && self.status_json.get() != Some(&false) {
+                if let Some(zpools) = self.status_from_json(name, &opts)? {
+                    return Ok(zpools);
+                }
+            }
+        }
+        let mut z = self.zpool();
+        z.arg("status");
+        z.args(opts.into_args());
+        z.args(name);
+        debug!(self.logger, "executing"; "cmd" => format_args!("{:?}", z));
+        let out = self.output(&mut z)?;
+        self.zpools_from_import(out)
+    }
+
+    /// `None` if `zpool` doesn't know `-j`, which is remembered so it's not tried again.
+    #[cfg(feature = "serde")]
+    fn status_from_json(
+        &self,
+        name: Option<&str>,
+        opts: &StatusOptions,
+    ) -> ZpoolResult<Option<Vec<Zpool>>> {
+        let mut z = self.zpool();
+        z.arg("status");
+        z.args(opts.json_args());
+        z.args(name);
+        debug!(self.logger, "executing"; "cmd" => format_args!("{:?}", z));
+        let out = self.output(&mut z)?;
+        let supported = out.status.success()
+            || !String::from_utf8_lossy(&out.stderr).contains("invalid option 'j'");
+        let _ = self.status_json.set(supported);
+        if !supported {
+            return Ok(None);
+        }
+        let stdout = String::from_utf8_lossy(&out.stdout);
+        if !out.status.success() || stdout.trim() == "no pools available" {
+            return self.zpools_from_import(out).map(Some);
+        }
+        let queried_at = opts.timestamp.map(|_| Utc::now().timestamp());
+        status_json::zpools_from_json(&stdout, queried_at).map(Some)
+    }
+
     fn zpools_from_import(&self, out: Output) -> ZpoolResult<Vec<Zpool>> {
         if out.status.success() {
-            let stdout: String = String::from_utf8_lossy(&out.stdout).into();
-            StdoutParser::parse(Rule::zpools, stdout.as_ref())
-                .map_err(|_| ZpoolError::ParseError)
-                .map(|pairs| pairs.map(Zpool::from_pest_pair).collect())
+            Zpool::parse_status(&String::from_utf8_lossy(&out.stdout))
         } else {
             if out.stderr.is_empty() && out.stdout.is_empty() {
                 return Ok(Vec::new());
@@ -108,14 +301,324 @@ impl ZpoolOpen3 {
     }
 }
 
-#[derive(Default, Builder, Debug, Clone, Getters)]
+/// Error of a `zfs` command run on behalf of the pool. What `zfs` printed is mapped the same way as
+/// output of `zpool`.
+fn from_zfs_error(err: zfs::Error) -> ZpoolError {
+    match err {
+        zfs::Error::Io(err) => ZpoolError::from(err),
+        zfs::Error::TimedOut { command, after } => ZpoolError::TimedOut { command, after },
+        zfs::Error::UnknownSoFar(stderr) => ZpoolError::from_stderr(stderr.as_bytes()),
+        err => ZpoolError::Other(format!("{:?}", err)),
+    }
+}
+
+/// Only a busy dataset is reported as `PoolBusy`, with the dataset that refused to unmount.
+fn unmount_error(dataset: PathBuf, err: zfs::Error) -> ZpoolError {
+    match from_zfs_error(err) {
+        ZpoolError::PoolBusy(_) => ZpoolError::PoolBusy(Some(dataset)),
+        err => err,
+    }
+}
+
+/// Mounted datasets of the pool `name` in the order they can be unmounted: deepest first. Dataset
+/// mounted more than once is listed once.
+pub(crate) fn datasets_to_unmount(
+    name: &str,
+    mount_table: Vec<(PathBuf, PathBuf)>,
+) -> Vec<PathBuf> {
+    let mut datasets: Vec<PathBuf> = mount_table
+        .into_iter()
+        .map(|(dataset, _)| dataset)
+        .filter(|dataset| dataset.starts_with(name))
+        .collect();
+    datasets.sort_by(|a, b| {
+        b.components()
+            .count()
+            .cmp(&a.components().count())
+            .then(a.cmp(b))
+    });
+    datasets.dedup();
+    datasets
+}
+
+/// Parse output of `zpool list -H -o name,health`.
+pub(crate) fn parse_health_list(stdout: &[u8]) -> ZpoolResult<Vec<(String, Health)>> {
+    let stdout = String::from_utf8_lossy(stdout);
+    let mut pools = Vec::new();
+    for line in stdout.lines() {
+        let line = line.trim();
+        if line.is_empty() || line == "no pools available" {
+            continue;
+        }
+        let mut columns = line.split('\t');
+        match (columns.next(), columns.next(), columns.next()) {
+            (Some(name), Some(health), None) => {
+                pools.push((name.to_string(), Health::try_from_str(Some(health))?));
+            }
+            _ => return Err(ZpoolError::UnparsedOutput(stdout.to_string())),
+        }
+    }
+    Ok(pools)
+}
+
+/// How `zpool status -T` prints timestamp.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimestampFormat {
+    /// Seconds since epoch (`-T u`).
+    Unix,
+    /// Date as `date(1)` prints it (`-T d`).
+    Date,
+}
+
+impl TimestampFormat {
+    fn as_arg(self) -> &'static str {
+        match self {
+            TimestampFormat::Unix => "u",
+            TimestampFormat::Date => "d",
+        }
+    }
+}
+
+#[derive(Builder, Debug, Clone, Getters)]
 #[builder(setter(into))]
 #[get = "pub"]
 pub struct StatusOptions {
+    /// Display full paths for vdevs instead of only the last component of the path (`-P`).
+    /// Combine with `resolve_links` to get stable paths for
+    /// [`Vdev::matches_request`](../vdev/struct.Vdev.html#method.matches_request).
     #[builder(default)]
     full_paths: bool,
+    /// Display real paths for vdevs resolving all symbolic links (`-L`).
     #[builder(default)]
     resolve_links: bool,
+    /// Display exact values instead of human readable ones (`-p`). Off by default, error counters
+    /// that don't fit get `u64::MAX` then, same as in plain `zpool status`.
+    #[builder(default)]
+    parseable: bool,
+    /// Print when status was queried (`-T`). Available as
+    /// [`Zpool::queried_at`](../description/struct.Zpool.html#method.queried_at).
+    #[builder(default)]
+    timestamp: Option<TimestampFormat>,
+    /// List files with permanent errors (`-v`). Available as
+    /// [`Zpool::permanent_errors`](../description/struct.Zpool.html#method.permanent_errors).
+    #[builder(default)]
+    verbose: bool,
+    /// Read JSON output of `zpool status -j` instead of parsing text. On by default, only
+    /// available with `serde` feature. Whether `zpool` supports `-j` is checked once per engine,
+    /// text output is used if it doesn't. Text is always used with `verbose`.
+    #[builder(default = "true")]
+    prefer_json: bool,
+}
+
+impl StatusOptions {
+    /// A preferred way to create this.
+    pub fn builder() -> StatusOptionsBuilder {
+        StatusOptionsBuilder::default()
+    }
+
+    pub(crate) fn into_args(self) -> Vec<OsString> {
+        let mut ret = Vec::with_capacity(5);
+        if self.parseable {
+            ret.push("-p".into());
+        }
+        if self.full_paths {
+            ret.push("-P".into());
+        }
+        if self.resolve_links {
+            ret.push("-L".into());
+        }
+        if self.verbose {
+            ret.push("-v".into());
+        }
+        if let Some(format) = self.timestamp {
+            ret.push("-T".into());
+            ret.push(format.as_arg().into());
+        }
+        ret
+    }
+
+    /// Arguments for `zpool status -j`. Timestamp isn't printed in JSON, so it's left out.
+    #[cfg(feature = "serde")]
+    pub(crate) fn json_args(&self) -> Vec<OsString> {
+        let mut ret = vec![OsString::from("-j")];
+        if self.parseable {
+            ret.push("-p".into());
+        }
+        if self.full_paths {
+            ret.push("-P".into());
+        }
+        if self.resolve_links {
+            ret.push("-L".into());
+        }
+        ret
+    }
+}
+
+impl Default for StatusOptions {
+    fn default() -> Self {
+        StatusOptions::builder()
+            .build()
+            .expect("Failed to build default StatusOptions")
+    }
+}
+
+/// Options for [`attach_with`](../trait.ZpoolEngine.html#tymethod.attach_with) and
+/// [`replace_disk_with`](../trait.ZpoolEngine.html#tymethod.replace_disk_with).
+#[derive(Default, Builder, Debug, Clone, Getters, PartialEq, Eq)]
+#[builder(setter(into))]
+#[get = "pub"]
+pub struct AttachOptions {
+    /// Forces use of new device, even if it appears to be in use (`-f`).
+    #[builder(default)]
+    force: bool,
+    /// Reconstruct new device sequentially instead of a healing resilver (`-s`). Only works for
+    /// mirrors.
+    #[builder(default)]
+    sequential: bool,
+    /// Don't return until new device has finished resilvering (`-w`).
+    #[builder(default)]
+    wait: bool,
+    /// Override `ashift` of the new device (`-o ashift=N`). Older `zpool` on FreeBSD doesn't
+    /// accept it.
+    #[builder(default)]
+    ashift: Option<u8>,
+}
+
+impl AttachOptions {
+    /// A preferred way to create this.
+    pub fn builder() -> AttachOptionsBuilder {
+        AttachOptionsBuilder::default()
+    }
+
+    /// Make AttachOptions usable as arg for [`Command`](https://doc.rust-lang.org/std/process/struct.Command.html).
+    pub(crate) fn into_args(self) -> Vec<OsString> {
+        let mut ret: Vec<OsString> = Vec::with_capacity(5);
+        if self.force {
+            ret.push("-f".into());
+        }
+        if self.sequential {
+            ret.push("-s".into());
+        }
+        if self.wait {
+            ret.push("-w".into());
+        }
+        if let Some(ashift) = self.ashift {
+            ret.push("-o".into());
+            ret.push(format!("ashift={}", ashift).into());
+        }
+        ret
+    }
+}
+
+/// Options for [`add_vdev_with`](../trait.ZpoolEngine.html#tymethod.add_vdev_with).
+#[derive(Default, Builder, Debug, Clone, Getters, PartialEq, Eq)]
+#[builder(setter(into))]
+#[get = "pub"]
+pub struct AddOptions {
+    /// Override `ashift` of the new vdev (`-o ashift=N`). Older `zpool` on FreeBSD doesn't
+    /// accept it.
+    #[builder(default)]
+    ashift: Option<u8>,
+}
+
+impl AddOptions {
+    /// A preferred way to create this.
+    pub fn builder() -> AddOptionsBuilder {
+        AddOptionsBuilder::default()
+    }
+
+    /// Make AddOptions usable as arg for [`Command`](https://doc.rust-lang.org/std/process/struct.Command.html).
+    pub(crate) fn into_args(self) -> Vec<OsString> {
+        let mut ret: Vec<OsString> = Vec::with_capacity(2);
+        if let Some(ashift) = self.ashift {
+            ret.push("-o".into());
+            ret.push(format!("ashift={}", ashift).into());
+        }
+        ret
+    }
+}
+
+/// Options for [`export_with`](../trait.ZpoolEngine.html#tymethod.export_with).
+#[derive(Default, Builder, Debug, Clone, Getters, PartialEq, Eq)]
+#[builder(setter(into))]
+#[get = "pub"]
+pub struct ExportOptions {
+    /// Forcefully unmount all datasets (`-f`). Might result in corruption.
+    #[builder(default)]
+    force: bool,
+    /// Unmount every mounted dataset of the pool one by one, deepest first, before exporting.
+    /// Unlike failed export, failed unmount tells which dataset is busy.
+    #[builder(default)]
+    unmount_first: bool,
+}
+
+impl ExportOptions {
+    /// A preferred way to create this.
+    pub fn builder() -> ExportOptionsBuilder {
+        ExportOptionsBuilder::default()
+    }
+}
+
+impl From<ExportMode> for ExportOptions {
+    fn from(mode: ExportMode) -> ExportOptions {
+        ExportOptions {
+            force: mode == ExportMode::Force,
+            unmount_first: false,
+        }
+    }
+}
+
+/// Options for [`import_with`](../trait.ZpoolEngine.html#tymethod.import_with) and
+/// [`import_from_dir_with`](../trait.ZpoolEngine.html#tymethod.import_from_dir_with).
+#[derive(Default, Builder, Debug, Clone, Getters, PartialEq, Eq)]
+#[builder(setter(into))]
+#[get = "pub"]
+pub struct ImportOptions {
+    /// Sets the `cachefile` property to `none` and `altroot` property to given path (`-R`).
+    #[builder(default)]
+    altroot: Option<PathBuf>,
+    /// Import the pool in read-only mode (`-o readonly=on`).
+    #[builder(default)]
+    readonly: bool,
+    /// Allows a pool to import when there is a missing log device (`-m`).
+    #[builder(default)]
+    missing_log: bool,
+    /// Forces import, even if the pool appears to be potentially active (`-f`).
+    #[builder(default)]
+    force: bool,
+    /// Import the pool without mounting any file systems (`-N`).
+    #[builder(default)]
+    no_mount: bool,
+}
+
+impl ImportOptions {
+    /// A preferred way to create this.
+    pub fn builder() -> ImportOptionsBuilder {
+        ImportOptionsBuilder::default()
+    }
+
+    /// Make ImportOptions usable as arg for [`Command`](https://doc.rust-lang.org/std/process/struct.Command.html).
+    pub(crate) fn into_args(self) -> Vec<OsString> {
+        let mut ret: Vec<OsString> = Vec::with_capacity(6);
+        if let Some(altroot) = self.altroot {
+            ret.push("-R".into());
+            ret.push(altroot.into_os_string());
+        }
+        if self.readonly {
+            ret.push("-o".into());
+            ret.push("readonly=on".into());
+        }
+        if self.missing_log {
+            ret.push("-m".into());
+        }
+        if self.force {
+            ret.push("-f".into());
+        }
+        if self.no_mount {
+            ret.push("-N".into());
+        }
+        ret
+    }
 }
 
 impl ZpoolEngine for ZpoolOpen3 {
@@ -123,37 +626,14 @@ impl ZpoolEngine for ZpoolOpen3 {
         let mut z = self.zpool_mute();
         z.arg("list").arg(name.as_ref());
         debug!(self.logger, "executing"; "cmd" => format_args!("{:?}", z));
-        let status = z.status()?;
+        let status = self.output(&mut z)?.status;
         Ok(status.success())
     }
 
     fn create(&self, request: CreateZpoolRequest) -> ZpoolResult<()> {
-        if !request.is_suitable_for_create() {
-            return Err(ZpoolError::InvalidTopology);
-        }
-        let mut z = self.zpool();
-        z.arg("create");
-        if request.create_mode() == &CreateMode::Force {
-            z.arg("-f");
-        }
-        if let Some(props) = request.props().clone() {
-            for arg in props.into_args() {
-                z.arg("-o");
-                z.arg(arg);
-            }
-        }
-        if let Some(mount) = request.mount().clone() {
-            z.arg("-m");
-            z.arg(mount);
-        }
-        if let Some(altroot) = request.altroot().clone() {
-            z.arg("-R");
-            z.arg(altroot);
-        }
-        z.arg(request.name());
-        z.args(request.into_args());
+        let mut z = self.create_command(request, false)?;
         debug!(self.logger, "executing"; "cmd" => format_args!("{:?}", z));
-        let out = z.output()?;
+        let out = self.output(&mut z)?;
         if out.status.success() {
             Ok(())
         } else {
@@ -161,26 +641,46 @@ impl ZpoolEngine for ZpoolOpen3 {
         }
     }
 
+    fn create_dry_run(&self, request: CreateZpoolRequest) -> ZpoolResult<Zpool> {
+        let mut z = self.create_command(request, true)?;
+        debug!(self.logger, "executing"; "cmd" => format_args!("{:?}", z));
+        let out = self.output(&mut z)?;
+        if out.status.success() {
+            let stdout = String::from_utf8_lossy(&out.stdout);
+            StdoutParser::parse(Rule::dry_run, &stdout)
+                .map_err(|_| ZpoolError::ParseError)
+                .map(|mut pairs| {
+                    Zpool::from_dry_run_pair(pairs.next().expect("Failed to unwrap dry run"))
+                })
+        } else {
+            Err(ZpoolError::from_stderr(&out.stderr))
+        }
+    }
+
     fn destroy<N: AsRef<str>>(&self, name: N, mode: DestroyMode) -> ZpoolResult<()> {
-        let mut z = self.zpool_mute();
+        let mut z = self.zpool();
         z.arg("destroy");
         if let DestroyMode::Force = mode {
             z.arg("-f");
         }
         z.arg(name.as_ref());
         debug!(self.logger, "executing"; "cmd" => format_args!("{:?}", z));
-        z.status().map(|_| Ok(()))?
+        let out = self.output(&mut z)?;
+        if out.status.success() {
+            Ok(())
+        } else {
+            Err(ZpoolError::from_stderr(&out.stderr))
+        }
     }
 
     fn read_properties<N: AsRef<str>>(&self, name: N) -> ZpoolResult<ZpoolProperties> {
         let mut z = self.zpool();
-        z.args(&["list", "-p", "-H", "-o"]);
-        z.arg(&*ZPOOL_PROP_ARG);
+        z.args(["get", "-H", "-p", "-o", "property,value,source", "all"]);
         z.arg(name.as_ref());
         debug!(self.logger, "executing"; "cmd" => format_args!("{:?}", z));
-        let out = z.output()?;
+        let out = self.output(&mut z)?;
         if out.status.success() {
-            ZpoolProperties::try_from_stdout(&out.stdout)
+            ZpoolProperties::try_from_stdout_with_sources(&out.stdout)
         } else {
             Err(ZpoolError::from_stderr(&out.stderr))
         }
@@ -197,7 +697,7 @@ impl ZpoolEngine for ZpoolOpen3 {
         z.arg(OsString::from(PropPair::to_pair(value, key)));
         z.arg(name.as_ref());
         debug!(self.logger, "executing"; "cmd" => format_args!("{:?}", z));
-        let out = z.output()?;
+        let out = self.output(&mut z)?;
         if out.status.success() {
             Ok(())
         } else {
@@ -205,15 +705,18 @@ impl ZpoolEngine for ZpoolOpen3 {
         }
     }
 
-    fn export<N: AsRef<str>>(&self, name: N, mode: ExportMode) -> ZpoolResult<()> {
+    fn export_with<N: AsRef<str>>(&self, name: N, opts: ExportOptions) -> ZpoolResult<()> {
+        if opts.unmount_first {
+            self.unmount_datasets(name.as_ref())?;
+        }
         let mut z = self.zpool();
         z.arg("export");
-        if let ExportMode::Force = mode {
+        if opts.force {
             z.arg("-f");
         }
         z.arg(name.as_ref());
         debug!(self.logger, "executing"; "cmd" => format_args!("{:?}", z));
-        let out = z.output()?;
+        let out = self.output(&mut z)?;
         if out.status.success() {
             Ok(())
         } else {
@@ -225,7 +728,7 @@ impl ZpoolEngine for ZpoolOpen3 {
         let mut z = self.zpool();
         z.arg("import");
         debug!(self.logger, "executing"; "cmd" => format_args!("{:?}", z));
-        let out = z.output()?;
+        let out = self.output(&mut z)?;
         self.zpools_from_import(out)
     }
 
@@ -235,16 +738,17 @@ impl ZpoolEngine for ZpoolOpen3 {
         z.arg("-d");
         z.arg(dir);
         debug!(self.logger, "executing"; "cmd" => format_args!("{:?}", z));
-        let out = z.output()?;
+        let out = self.output(&mut z)?;
         self.zpools_from_import(out)
     }
 
-    fn import<N: AsRef<str>>(&self, name: N) -> Result<(), ZpoolError> {
+    fn import_with<N: AsRef<str>>(&self, name: N, opts: ImportOptions) -> ZpoolResult<()> {
         let mut z = self.zpool();
         z.arg("import");
+        z.args(opts.into_args());
         z.arg(name.as_ref());
         debug!(self.logger, "executing"; "cmd" => format_args!("{:?}", z));
-        let out = z.output()?;
+        let out = self.output(&mut z)?;
         if out.status.success() {
             Ok(())
         } else {
@@ -252,14 +756,20 @@ impl ZpoolEngine for ZpoolOpen3 {
         }
     }
 
-    fn import_from_dir<N: AsRef<str>>(&self, name: N, dir: PathBuf) -> ZpoolResult<()> {
+    fn import_from_dir_with<N: AsRef<str>>(
+        &self,
+        name: N,
+        dir: PathBuf,
+        opts: ImportOptions,
+    ) -> ZpoolResult<()> {
         let mut z = self.zpool();
         z.arg("import");
         z.arg("-d");
         z.arg(dir);
+        z.args(opts.into_args());
         z.arg(name.as_ref());
         debug!(self.logger, "executing"; "cmd" => format_args!("{:?}", z));
-        let out = z.output()?;
+        let out = self.output(&mut z)?;
         if out.status.success() {
             Ok(())
         } else {
@@ -267,44 +777,80 @@ impl ZpoolEngine for ZpoolOpen3 {
         }
     }
 
-    fn status<N: AsRef<str>>(&self, name: N, opts: StatusOptions) -> ZpoolResult<Zpool> {
+    fn import_renamed<N: AsRef<str>>(
+        &self,
+        current: N,
+        new_name: N,
+        dir: Option<PathBuf>,
+        opts: ImportOptions,
+    ) -> ZpoolResult<()> {
+        validate_pool_name(new_name.as_ref())
+            .map_err(|reason| ZpoolError::InvalidPoolName(new_name.as_ref().into(), reason))?;
         let mut z = self.zpool();
-        z.arg("status");
-        z.arg("-p");
-        if opts.full_paths {
-            z.arg("-P");
-        }
-        if opts.resolve_links {
-            z.arg("-L");
+        z.arg("import");
+        if let Some(dir) = dir {
+            z.arg("-d");
+            z.arg(dir);
         }
-        z.arg(name.as_ref());
+        z.args(opts.into_args());
+        z.arg(current.as_ref());
+        z.arg(new_name.as_ref());
         debug!(self.logger, "executing"; "cmd" => format_args!("{:?}", z));
-        let out = z.output()?;
-        let zpools = self
-            .zpools_from_import(out)
-            .expect("Failed to unwrap zpool from status check");
-        if zpools.is_empty() {
-            return Err(ZpoolError::PoolNotFound);
-        }
-        let zpool = zpools.into_iter().next().expect("Can't build zpool out of pair. Please report at: https://github.com/Inner-Heaven/libzetta-rs");
-        if zpool.name().as_str() != name.as_ref() {
-            unreachable!();
+        let out = self.output(&mut z)?;
+        if out.status.success() {
+            Ok(())
+        } else {
+            Err(ZpoolError::from_stderr(&out.stderr))
         }
-        Ok(zpool)
+    }
+
+    fn status<N: AsRef<str>>(&self, name: N, opts: StatusOptions) -> ZpoolResult<Zpool> {
+        self.status_of(Some(name.as_ref()), opts)?
+            .into_iter()
+            .find(|zpool| zpool.name().as_str() == name.as_ref())
+            .ok_or(ZpoolError::PoolNotFound)
     }
 
     fn status_all(&self, opts: StatusOptions) -> ZpoolResult<Vec<Zpool>> {
+        self.status_of(None, opts)
+    }
+
+    fn health<N: AsRef<str>>(&self, name: N) -> ZpoolResult<Health> {
         let mut z = self.zpool();
-        z.arg("status");
-        if opts.full_paths {
-            z.arg("-P");
+        z.args(["list", "-H", "-o", "health"]);
+        z.arg(name.as_ref());
+        debug!(self.logger, "executing"; "cmd" => format_args!("{:?}", z));
+        let out = self.output(&mut z)?;
+        if out.status.success() {
+            let stdout = String::from_utf8_lossy(&out.stdout);
+            Health::try_from_str(Some(stdout.trim()))
+        } else {
+            Err(ZpoolError::from_stderr(&out.stderr))
         }
-        if opts.resolve_links {
-            z.arg("-L");
+    }
+
+    fn health_all(&self) -> ZpoolResult<Vec<(String, Health)>> {
+        let mut z = self.zpool();
+        z.args(["list", "-H", "-o", "name,health"]);
+        debug!(self.logger, "executing"; "cmd" => format_args!("{:?}", z));
+        let out = self.output(&mut z)?;
+        if out.status.success() {
+            parse_health_list(&out.stdout)
+        } else {
+            Err(ZpoolError::from_stderr(&out.stderr))
         }
+    }
+
+    fn list_pools(&self) -> ZpoolResult<Vec<PoolListEntry>> {
+        let mut z = self.zpool();
+        z.args(["list", "-Hp", "-o", PoolListEntry::COLUMNS]);
         debug!(self.logger, "executing"; "cmd" => format_args!("{:?}", z));
-        let out = z.output()?;
-        self.zpools_from_import(out)
+        let out = self.output(&mut z)?;
+        if out.status.success() {
+            PoolListEntry::try_from_stdout(&out.stdout)
+        } else {
+            Err(ZpoolError::from_stderr(&out.stderr))
+        }
     }
 
     fn scrub<N: AsRef<str>>(&self, name: N) -> ZpoolResult<()> {
@@ -312,7 +858,53 @@ impl ZpoolEngine for ZpoolOpen3 {
         z.arg("scrub");
         z.arg(name.as_ref());
         debug!(self.logger, "executing"; "cmd" => format_args!("{:?}", z));
-        let out = z.output()?;
+        let out = self.output(&mut z)?;
+        if out.status.success() {
+            Ok(())
+        } else {
+            Err(ZpoolError::from_stderr(&out.stderr))
+        }
+    }
+
+    fn resilver<N: AsRef<str>>(&self, name: N) -> ZpoolResult<()> {
+        let mut z = self.zpool();
+        z.arg("resilver");
+        z.arg(name.as_ref());
+        debug!(self.logger, "executing"; "cmd" => format_args!("{:?}", z));
+        let out = self.output(&mut z)?;
+        if out.status.success() {
+            Ok(())
+        } else {
+            Err(ZpoolError::from_stderr(&out.stderr))
+        }
+    }
+
+    fn reguid<N: AsRef<str>>(&self, name: N) -> ZpoolResult<()> {
+        let mut z = self.zpool();
+        z.arg("reguid");
+        z.arg(name.as_ref());
+        debug!(self.logger, "executing"; "cmd" => format_args!("{:?}", z));
+        let out = self.output(&mut z)?;
+        if out.status.success() {
+            Ok(())
+        } else {
+            Err(ZpoolError::from_stderr(&out.stderr))
+        }
+    }
+
+    fn sync_with_timeout(&self, name: Option<&str>, timeout: Option<Duration>) -> ZpoolResult<()> {
+        let mut z = self.zpool();
+        z.arg("sync");
+        if let Some(name) = name {
+            z.arg(name);
+        }
+        debug!(self.logger, "executing"; "cmd" => format_args!("{:?}", z));
+        let out = instrumentation::run_command_within(
+            &self.logger,
+            self.instrumentation.as_ref(),
+            &mut z,
+            timeout.or(self.command_timeout),
+        )?;
         if out.status.success() {
             Ok(())
         } else {
@@ -326,7 +918,7 @@ impl ZpoolEngine for ZpoolOpen3 {
         z.arg("-p");
         z.arg(name.as_ref());
         debug!(self.logger, "executing"; "cmd" => format_args!("{:?}", z));
-        let out = z.output()?;
+        let out = self.output(&mut z)?;
         if out.status.success() {
             Ok(())
         } else {
@@ -340,7 +932,7 @@ impl ZpoolEngine for ZpoolOpen3 {
         z.arg("-s");
         z.arg(name.as_ref());
         debug!(self.logger, "executing"; "cmd" => format_args!("{:?}", z));
-        let out = z.output()?;
+        let out = self.output(&mut z)?;
         if out.status.success() {
             Ok(())
         } else {
@@ -348,7 +940,7 @@ impl ZpoolEngine for ZpoolOpen3 {
         }
     }
 
-    fn take_offline<N: AsRef<str>, D: AsRef<OsStr>>(
+    fn take_offline<N: AsRef<str>, D: AsDeviceArg>(
         &self,
         name: N,
         device: D,
@@ -360,9 +952,9 @@ impl ZpoolEngine for ZpoolOpen3 {
             z.arg("-t");
         }
         z.arg(name.as_ref());
-        z.arg(device.as_ref());
+        z.arg(device.as_device_arg());
         debug!(self.logger, "executing"; "cmd" => format_args!("{:?}", z));
-        let out = z.output()?;
+        let out = self.output(&mut z)?;
         if out.status.success() {
             Ok(())
         } else {
@@ -370,7 +962,7 @@ impl ZpoolEngine for ZpoolOpen3 {
         }
     }
 
-    fn bring_online<N: AsRef<str>, D: AsRef<OsStr>>(
+    fn bring_online<N: AsRef<str>, D: AsDeviceArg>(
         &self,
         name: N,
         device: D,
@@ -382,9 +974,9 @@ impl ZpoolEngine for ZpoolOpen3 {
             z.arg("-e");
         }
         z.arg(name.as_ref());
-        z.arg(device.as_ref());
+        z.arg(device.as_device_arg());
         debug!(self.logger, "executing"; "cmd" => format_args!("{:?}", z));
-        let out = z.output()?;
+        let out = self.output(&mut z)?;
         if out.status.success() {
             Ok(())
         } else {
@@ -392,19 +984,22 @@ impl ZpoolEngine for ZpoolOpen3 {
         }
     }
 
-    fn attach<N: AsRef<str>, D: AsRef<OsStr>>(
+    fn attach_with<N: AsRef<str>, D: AsDeviceArg, O: AsRef<OsStr>>(
         &self,
         name: N,
         device: D,
-        new_device: D,
+        new_device: O,
+        opts: AttachOptions,
     ) -> ZpoolResult<()> {
+        self.warn_on_sector_size(name.as_ref(), &[new_device.as_ref()], opts.ashift);
         let mut z = self.zpool();
         z.arg("attach");
+        z.args(opts.into_args());
         z.arg(name.as_ref());
-        z.arg(device.as_ref());
+        z.arg(device.as_device_arg());
         z.arg(new_device.as_ref());
         debug!(self.logger, "executing"; "cmd" => format_args!("{:?}", z));
-        let out = z.output()?;
+        let out = self.output(&mut z)?;
         if out.status.success() {
             Ok(())
         } else {
@@ -412,13 +1007,13 @@ impl ZpoolEngine for ZpoolOpen3 {
         }
     }
 
-    fn detach<N: AsRef<str>, D: AsRef<OsStr>>(&self, name: N, device: D) -> ZpoolResult<()> {
+    fn detach<N: AsRef<str>, D: AsDeviceArg>(&self, name: N, device: D) -> ZpoolResult<()> {
         let mut z = self.zpool();
         z.arg("detach");
         z.arg(name.as_ref());
-        z.arg(device.as_ref());
+        z.arg(device.as_device_arg());
         debug!(self.logger, "executing"; "cmd" => format_args!("{:?}", z));
-        let out = z.output()?;
+        let out = self.output(&mut z)?;
         if out.status.success() {
             Ok(())
         } else {
@@ -426,21 +1021,25 @@ impl ZpoolEngine for ZpoolOpen3 {
         }
     }
 
-    fn add_vdev<N: AsRef<str>>(
+    fn add_vdev_with<N: AsRef<str>>(
         &self,
         name: N,
         new_vdev: CreateVdevRequest,
         add_mode: CreateMode,
+        opts: AddOptions,
     ) -> Result<(), ZpoolError> {
+        let devices: Vec<&OsStr> = new_vdev.disks().iter().map(|d| d.as_os_str()).collect();
+        self.warn_on_sector_size(name.as_ref(), &devices, opts.ashift);
         let mut z = self.zpool();
         z.arg("add");
         if add_mode == CreateMode::Force {
             z.arg("-f");
         }
+        z.args(opts.into_args());
         z.arg(name.as_ref());
         z.args(new_vdev.into_args());
         debug!(self.logger, "executing"; "cmd" => format_args!("{:?}", z));
-        let out = z.output()?;
+        let out = self.output(&mut z)?;
         if out.status.success() {
             Ok(())
         } else {
@@ -463,7 +1062,7 @@ impl ZpoolEngine for ZpoolOpen3 {
         z.arg("log");
         z.args(new_zil.into_args());
         debug!(self.logger, "executing"; "cmd" => format_args!("{:?}", z));
-        let out = z.output()?;
+        let out = self.output(&mut z)?;
         if out.status.success() {
             Ok(())
         } else {
@@ -486,7 +1085,7 @@ impl ZpoolEngine for ZpoolOpen3 {
         z.arg("cache");
         z.arg(new_cache.as_ref());
         debug!(self.logger, "executing"; "cmd" => format_args!("{:?}", z));
-        let out = z.output()?;
+        let out = self.output(&mut z)?;
         if out.status.success() {
             Ok(())
         } else {
@@ -509,7 +1108,7 @@ impl ZpoolEngine for ZpoolOpen3 {
         z.arg("spare");
         z.arg(new_spare.as_ref());
         debug!(self.logger, "executing"; "cmd" => format_args!("{:?}", z));
-        let out = z.output()?;
+        let out = self.output(&mut z)?;
         if out.status.success() {
             Ok(())
         } else {
@@ -517,19 +1116,22 @@ impl ZpoolEngine for ZpoolOpen3 {
         }
     }
 
-    fn replace_disk<N: AsRef<str>, D: AsRef<OsStr>, O: AsRef<OsStr>>(
+    fn replace_disk_with<N: AsRef<str>, D: AsDeviceArg, O: AsRef<OsStr>>(
         &self,
         name: N,
         old_disk: D,
         new_disk: O,
+        opts: AttachOptions,
     ) -> Result<(), ZpoolError> {
+        self.warn_on_sector_size(name.as_ref(), &[new_disk.as_ref()], opts.ashift);
         let mut z = self.zpool();
         z.arg("replace");
+        z.args(opts.into_args());
         z.arg(name.as_ref());
-        z.arg(old_disk.as_ref());
+        z.arg(old_disk.as_device_arg());
         z.arg(new_disk.as_ref());
         debug!(self.logger, "executing"; "cmd" => format_args!("{:?}", z));
-        let out = z.output()?;
+        let out = self.output(&mut z)?;
         if out.status.success() {
             Ok(())
         } else {
@@ -537,13 +1139,13 @@ impl ZpoolEngine for ZpoolOpen3 {
         }
     }
 
-    fn remove<N: AsRef<str>, D: AsRef<OsStr>>(&self, name: N, device: D) -> ZpoolResult<()> {
+    fn remove<N: AsRef<str>, D: AsDeviceArg>(&self, name: N, device: D) -> ZpoolResult<()> {
         let mut z = self.zpool();
         z.arg("remove");
         z.arg(name.as_ref());
-        z.arg(device.as_ref());
+        z.arg(device.as_device_arg());
         debug!(self.logger, "executing"; "cmd" => format_args!("{:?}", z));
-        let out = z.output()?;
+        let out = self.output(&mut z)?;
         if out.status.success() {
             Ok(())
         } else {
@@ -554,14 +1156,263 @@ impl ZpoolEngine for ZpoolOpen3 {
 
 #[cfg(test)]
 mod test {
-    use std::assert_eq;
+    use std::{assert_eq, path::Path};
 
     use super::*;
+    use crate::{
+        utils::shim,
+        zpool::{
+            CreateVdevRequest, Health, PoolStatus, Reason, ResilverStatus, VdevType, ZpoolErrorKind,
+        },
+    };
     fn parse_zpools(stdout: &str) -> Result<Vec<Zpool>, ZpoolError> {
         StdoutParser::parse(Rule::zpools, stdout.as_ref())
             .map_err(|_| ZpoolError::ParseError)
-            .map(|pairs| pairs.map(Zpool::from_pest_pair).collect())
+            .map(Zpool::from_pest_pairs)
+    }
+    #[test]
+    fn instrumentation_hook_receives_metrics() {
+        use crate::instrumentation::CommandMetrics;
+        use std::{sync::Mutex, time::Duration};
+
+        let dir = tempfile::tempdir().unwrap();
+        let shim = shim(
+            dir.path(),
+            "zpool",
+            "sleep 0.1\necho \"cannot open 'tank': no such pool\" >&2\nexit 1\n",
+        );
+
+        let collected: Arc<Mutex<Vec<CommandMetrics>>> = Arc::default();
+        let sink = Arc::clone(&collected);
+        let mut zpool = ZpoolOpen3::with_cmd(shim.as_os_str());
+        zpool.set_instrumentation(Box::new(move |m| sink.lock().unwrap().push(m)));
+
+        let err = zpool.scrub("tank").unwrap_err();
+        assert_eq!(ZpoolError::PoolNotFound.kind(), err.kind());
+
+        let collected = collected.lock().unwrap();
+        assert_eq!(1, collected.len());
+        let metrics = &collected[0];
+        assert_eq!(&shim.to_string_lossy(), metrics.command());
+        assert_eq!(&Some(String::from("scrub")), metrics.subcommand());
+        assert!(*metrics.duration() >= Duration::from_millis(100));
+        assert!(*metrics.duration() < Duration::from_secs(10));
+        assert_eq!(&Some(1), metrics.exit_code());
+        assert!(!metrics.success());
+        assert_eq!(33, *metrics.stderr_len());
+    }
+
+    #[test]
+    fn sync_args() {
+        use std::fs;
+
+        let dir = tempfile::tempdir().unwrap();
+        let args = dir.path().join("args");
+        let shim = shim(
+            dir.path(),
+            "zpool",
+            &format!("echo \"$@\" >> {}\n", args.display()),
+        );
+
+        let zpool = ZpoolOpen3::with_cmd(shim.as_os_str());
+        zpool.sync(Some("tank")).unwrap();
+        zpool.sync(None).unwrap();
+        zpool
+            .sync_with_timeout(Some("tank"), Some(Duration::from_secs(10)))
+            .unwrap();
+
+        assert_eq!(
+            "sync tank\nsync\nsync tank\n",
+            fs::read_to_string(&args).unwrap()
+        );
+    }
+
+    #[test]
+    fn create_args_with_features() {
+        use crate::zpool::{Compatibility, CreateZpoolRequest, FeatureToggle};
+        use std::fs;
+
+        let dir = tempfile::tempdir().unwrap();
+        let args = dir.path().join("args");
+        let shim = shim(
+            dir.path(),
+            "zpool",
+            &format!("echo \"$@\" >> {}\n", args.display()),
+        );
+
+        let request = CreateZpoolRequest::builder()
+            .name("boot")
+            .vdev(CreateVdevRequest::SingleDisk("sda2".into()))
+            .compatibility_preset(Compatibility::Grub2)
+            .feature("encryption", FeatureToggle::Disabled)
+            .mount(std::path::PathBuf::from("none"))
+            .build()
+            .unwrap();
+        let zpool = ZpoolOpen3::with_cmd(shim.as_os_str());
+        zpool.create(request).unwrap();
+
+        assert_eq!(
+            "create -o compatibility=grub2 -o feature@encryption=disabled -m none boot sda2\n",
+            fs::read_to_string(&args).unwrap()
+        );
+    }
+
+    #[test]
+    fn command_timeout() {
+        let dir = tempfile::tempdir().unwrap();
+        let shim = shim(dir.path(), "zpool", "exec sleep 30\n");
+
+        let mut zpool = ZpoolOpen3::with_cmd(shim.as_os_str());
+        let err = zpool
+            .sync_with_timeout(Some("tank"), Some(Duration::from_millis(200)))
+            .unwrap_err();
+        match err {
+            ZpoolError::TimedOut { command, after } => {
+                assert_eq!(format!("{} sync tank", shim.display()), command);
+                assert_eq!(Duration::from_millis(200), after);
+            }
+            e => panic!("unexpected error: {:?}", e),
+        }
+
+        zpool.set_command_timeout(Some(Duration::from_millis(100)));
+        match zpool.exists("tank").unwrap_err() {
+            ZpoolError::TimedOut { command, after } => {
+                assert_eq!(format!("{} list tank", shim.display()), command);
+                assert_eq!(Duration::from_millis(100), after);
+            }
+            e => panic!("unexpected error: {:?}", e),
+        }
     }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn zpool_is_isolated_from_parent() {
+        use std::fs;
+
+        let dir = tempfile::tempdir().unwrap();
+        let report = dir.path().join("report");
+        // Descriptor without close-on-exec, like the ones a daemon gets from C libraries. It's
+        // far from the ones the shell uses for itself.
+        let leaky = unsafe { libc::fcntl(2, libc::F_DUPFD, 500) };
+        assert!(leaky >= 500);
+        let shim = shim(
+            dir.path(),
+            "zpool",
+            &format!(
+                "{{\n  readlink /proc/$$/fd/0\n  [ -e /proc/$$/fd/{fd} ] && echo leaked || \
+             echo closed\n  [ \"$(cut -d' ' -f5 /proc/$$/stat)\" = $$ ] && echo leader || echo \
+             member\n}} > {report}\n",
+                fd = leaky,
+                report = report.display()
+            ),
+        );
+
+        let zpool = ZpoolOpen3::with_cmd(shim.as_os_str());
+        zpool.exists("tank").unwrap();
+        unsafe { libc::close(leaky) };
+        assert_eq!(
+            "/dev/null\nclosed\nleader\n",
+            fs::read_to_string(&report).unwrap()
+        );
+    }
+
+    #[test]
+    fn create_dry_run_parses_layout() {
+        use std::fs;
+
+        let dir = tempfile::tempdir().unwrap();
+        let args = dir.path().join("args");
+        let shim = shim(
+            dir.path(),
+            "zpool",
+            &format!(
+                "echo \"$@\" > {}\nprintf \"would create 'tank' with the following \
+             layout:\\n\\n\\ttank\\n\\t  mirror\\n\\t    /vdevs/a\\n\\t    \
+             /vdevs/b\\n\\t  /vdevs/c\\n\\tlogs\\n\\t  /vdevs/d\\n\\tcache\\n\\t  \
+             /vdevs/e\\n\\tspares\\n\\t  /vdevs/f\\n\"\n",
+                args.display()
+            ),
+        );
+
+        let request = CreateZpoolRequest::builder()
+            .name("tank")
+            .vdev(CreateVdevRequest::Mirror(vec![
+                PathBuf::from("/vdevs/a"),
+                PathBuf::from("/vdevs/b"),
+            ]))
+            .vdev(CreateVdevRequest::disk("/vdevs/c"))
+            .zil(CreateVdevRequest::disk("/vdevs/d"))
+            .cache(PathBuf::from("/vdevs/e"))
+            .spare(PathBuf::from("/vdevs/f"))
+            .build()
+            .unwrap();
+        let zpool = ZpoolOpen3::with_cmd(shim.as_os_str());
+        let layout = zpool.create_dry_run(request.clone()).unwrap();
+
+        assert_eq!(
+            "create -n tank mirror /vdevs/a /vdevs/b /vdevs/c log /vdevs/d cache /vdevs/e spare \
+             /vdevs/f\n",
+            fs::read_to_string(&args).unwrap()
+        );
+        assert_eq!("tank", layout.name());
+        assert_eq!(&Health::Online, layout.health());
+        assert_eq!(&VdevType::Mirror, layout.vdevs()[0].kind());
+        assert_eq!(layout, request);
+
+        let invalid = CreateZpoolRequest::builder().name("tank").build().unwrap();
+        assert_eq!(
+            ZpoolError::InvalidTopology.kind(),
+            zpool.create_dry_run(invalid).unwrap_err().kind()
+        );
+    }
+
+    #[test]
+    fn ashift_override_is_passed() {
+        use std::fs;
+
+        let dir = tempfile::tempdir().unwrap();
+        let args = dir.path().join("args");
+        let shim = shim(
+            dir.path(),
+            "zpool",
+            &format!("echo \"$@\" >> {}\n", args.display()),
+        );
+
+        let zpool = ZpoolOpen3::with_cmd(shim.as_os_str());
+        let opts = AttachOptions::builder().ashift(12).build().unwrap();
+        zpool
+            .attach_with("tank", "/vdevs/a", "/vdevs/b", opts.clone())
+            .unwrap();
+        zpool
+            .replace_disk_with("tank", "/vdevs/a", "/vdevs/c", opts)
+            .unwrap();
+        let opts = AddOptions::builder().ashift(13).build().unwrap();
+        zpool
+            .add_vdev_with(
+                "tank",
+                CreateVdevRequest::Mirror(vec![
+                    PathBuf::from("/vdevs/d"),
+                    PathBuf::from("/vdevs/e"),
+                ]),
+                CreateMode::Force,
+                opts,
+            )
+            .unwrap();
+        zpool
+            .add_vdev(
+                "tank",
+                CreateVdevRequest::disk("/vdevs/f"),
+                CreateMode::Gentle,
+            )
+            .unwrap();
+
+        assert_eq!(
+            "attach -o ashift=12 tank /vdevs/a /vdevs/b\nreplace -o ashift=12 tank /vdevs/a \
+             /vdevs/c\nadd -f -o ashift=13 tank mirror /vdevs/d /vdevs/e\nadd tank /vdevs/f\n",
+            fs::read_to_string(&args).unwrap()
+        );
+    }
+
     #[test]
     fn correctly_parses_vdevs() {
         let stdout = include_str!("fixtures/status_with_block_device_nested");
@@ -587,6 +1438,229 @@ mod test {
         assert_eq!(&expected, drives);
     }
 
+    #[test]
+    fn correctly_parses_replacing_freebsd() {
+        let stdout = include_str!("fixtures/status_replacing_freebsd");
+        let zpools = parse_zpools(stdout).unwrap();
+        let vdevs = zpools[0].vdevs();
+        assert_eq!(2, vdevs.len());
+
+        let mirror = &vdevs[0];
+        assert_eq!(&VdevType::Mirror, mirror.kind());
+        let paths: Vec<&Path> = mirror.disks().iter().map(|d| d.path().as_path()).collect();
+        assert_eq!(
+            vec![
+                Path::new("ada0p3"),
+                Path::new("/dev/ada1p3"),
+                Path::new("ada2p3")
+            ],
+            paths
+        );
+        assert_eq!(1, mirror.groups().len());
+
+        let replacing = &mirror.groups()[0];
+        assert_eq!(&VdevType::Replacing, replacing.kind());
+        assert_eq!(&Health::Degraded, replacing.health());
+        let old = &replacing.disks()[0];
+        assert_eq!(&Health::Unavailable, old.health());
+        assert_eq!(&Some(PathBuf::from("/dev/ada1p3")), old.was());
+        assert_eq!(&Some(3981229393434284532), old.guid());
+        assert_eq!(&None, old.resilver());
+        let new = &replacing.disks()[1];
+        assert_eq!(&Some(ResilverStatus::Resilvering), new.resilver());
+        assert_eq!(&None, new.reason());
+
+        let top_level = &vdevs[1];
+        assert_eq!(&VdevType::Replacing, top_level.kind());
+        assert!(top_level.groups().is_empty());
+        assert_eq!(&Health::Offline, top_level.disks()[0].health());
+        assert_eq!(
+            &Some(ResilverStatus::AwaitingResilver),
+            top_level.disks()[1].resilver()
+        );
+    }
+
+    #[test]
+    fn correctly_parses_replacing_linux() {
+        let stdout = include_str!("fixtures/status_replacing_linux");
+        let zpools = parse_zpools(stdout).unwrap();
+        let raidz = &zpools[0].vdevs()[0];
+        assert_eq!(&VdevType::RaidZ, raidz.kind());
+        assert_eq!(5, raidz.disks().len());
+        assert_eq!(2, raidz.groups().len());
+
+        let replacing = &raidz.groups()[0];
+        assert_eq!(&VdevType::Replacing, replacing.kind());
+        let old = &replacing.disks()[0];
+        assert_eq!(&Health::Faulted, old.health());
+        assert_eq!(12, old.error_statistics().write);
+        assert_eq!(
+            &Some(Reason::Other(String::from("too many errors"))),
+            old.reason()
+        );
+        assert_eq!(&Some(ResilverStatus::Resilvering), old.resilver());
+
+        let spare = &raidz.groups()[1];
+        assert_eq!(&VdevType::Spare, spare.kind());
+        assert_eq!(
+            &Some(Reason::Other(String::from("cannot open"))),
+            spare.disks()[0].reason()
+        );
+        assert_eq!(
+            &Some(ResilverStatus::Resilvering),
+            spare.disks()[1].resilver()
+        );
+        assert_eq!(spare.disks()[1].path(), zpools[0].spares()[0].path());
+        assert_eq!(&Health::Inuse, zpools[0].spares()[0].health());
+    }
+
+    #[test]
+    fn correctly_parses_missing_device_guid() {
+        let stdout = include_str!("fixtures/status_with_missing_device");
+        let zpools = parse_zpools(stdout).unwrap();
+        let vdevs = zpools[0].vdevs();
+
+        let offline = &vdevs[0].disks()[0];
+        assert_eq!(&Some(14808325297596192025), offline.guid());
+        assert_eq!(&PathBuf::from("/vdevs/vdev0"), offline.path());
+        assert_eq!(&Health::Offline, offline.health());
+        let online = &vdevs[0].disks()[1];
+        assert_eq!(&None, online.guid());
+        assert_eq!(&PathBuf::from("/vdevs/vdev1"), online.path());
+
+        // Without `was` there is nothing better than GUID.
+        let unavail = &vdevs[1].disks()[1];
+        assert_eq!(&Some(9123456789012345678), unavail.guid());
+        assert_eq!(&PathBuf::from("9123456789012345678"), unavail.path());
+
+        let expected = CreateVdevRequest::Mirror(vec![
+            PathBuf::from("/vdevs/vdev0"),
+            PathBuf::from("/vdevs/vdev1"),
+        ]);
+        assert_eq!(&expected, &vdevs[0]);
+    }
+
+    #[test]
+    fn parses_parseable_status_with_timestamp_freebsd() {
+        let stdout = include_str!("fixtures/status_parseable_timestamp_freebsd");
+        let zpools = parse_zpools(stdout).unwrap();
+        assert_eq!(2, zpools.len());
+
+        let tank = &zpools[0];
+        assert_eq!("tank", tank.name());
+        assert_eq!(&Some(1_673_876_543), tank.queried_at());
+        assert_eq!(1523, tank.vdevs()[0].disks()[1].error_statistics().write);
+        // Timestamp is printed once for all pools.
+        assert_eq!("zroot", zpools[1].name());
+        assert_eq!(&Some(1_673_876_543), zpools[1].queried_at());
+    }
+
+    #[test]
+    fn parses_parseable_status_with_timestamp_linux() {
+        let stdout = include_str!("fixtures/status_parseable_timestamp_linux");
+        let zpools = parse_zpools(stdout).unwrap();
+        let zpool = &zpools[0];
+        assert_eq!(&Some(1_673_876_543), zpool.queried_at());
+        let disks = zpool.vdevs()[0].disks();
+        assert_eq!(1234, disks[1].error_statistics().read);
+        assert_eq!(56789, disks[2].error_statistics().checksum);
+
+        // Same rules handle output without timestamp and with a date.
+        let human = stdout.splitn(2, '\n').nth(1).unwrap();
+        let zpools = parse_zpools(human).unwrap();
+        assert_eq!(&None, zpools[0].queried_at());
+
+        for date in &["Mon Jan 16 12:02:23 UTC 2023", "Mon Jan 16 12:02:23 2023"] {
+            let dated = format!("{}\n{}", date, human);
+            let zpools = parse_zpools(&dated).unwrap();
+            assert!(zpools[0].queried_at().is_some(), "{}", date);
+            assert_eq!(zpool.vdevs(), zpools[0].vdevs());
+        }
+    }
+
+    #[test]
+    fn status_options_args() {
+        let args: Vec<OsString> = StatusOptions::default().into_args();
+        assert!(args.is_empty());
+
+        let opts = StatusOptions::builder()
+            .parseable(true)
+            .full_paths(true)
+            .timestamp(Some(TimestampFormat::Unix))
+            .verbose(true)
+            .build()
+            .unwrap();
+        let expected: Vec<OsString> = vec![
+            "-p".into(),
+            "-P".into(),
+            "-v".into(),
+            "-T".into(),
+            "u".into(),
+        ];
+        assert_eq!(expected, opts.into_args());
+    }
+
+    #[test]
+    fn correctly_parses_special_vdevs_freebsd() {
+        let stdout = include_str!("fixtures/status_with_special_freebsd");
+        let zpools = parse_zpools(stdout).unwrap();
+        let zpool = &zpools[0];
+
+        assert_eq!(1, zpool.vdevs().len());
+        let special =
+            CreateVdevRequest::Mirror(vec![PathBuf::from("nvd0p1"), PathBuf::from("nvd1p1")]);
+        assert_eq!(1, zpool.special_vdevs().len());
+        assert_eq!(&special, &zpool.special_vdevs()[0]);
+        assert!(zpool.dedup_vdevs().is_empty());
+        assert_eq!(&VdevType::SingleDisk, zpool.logs()[0].kind());
+        assert_eq!(&PathBuf::from("nvd0p2"), zpool.logs()[0].disks()[0].path());
+        assert_eq!(&PathBuf::from("nvd1p2"), zpool.caches()[0].path());
+        assert!(zpool.other_sections().is_empty());
+        assert_eq!(&None, zpool.errors());
+    }
+
+    #[test]
+    fn correctly_parses_special_and_dedup_vdevs_linux() {
+        let stdout = include_str!("fixtures/status_with_special_linux");
+        let zpools = parse_zpools(stdout).unwrap();
+        let zpool = &zpools[0];
+
+        assert_eq!(&VdevType::RaidZ2, zpool.vdevs()[0].kind());
+        assert_eq!(4, zpool.vdevs()[0].disks().len());
+
+        let dedup = CreateVdevRequest::Mirror(vec![
+            PathBuf::from("nvme-Samsung_SSD_970_EVO_1TB_S467NX0M"),
+            PathBuf::from("nvme-Samsung_SSD_970_EVO_1TB_S467NX0N"),
+        ]);
+        assert_eq!(1, zpool.dedup_vdevs().len());
+        assert_eq!(&dedup, &zpool.dedup_vdevs()[0]);
+        let special =
+            CreateVdevRequest::Mirror(vec![PathBuf::from("nvme0n1p3"), PathBuf::from("nvme1n1p3")]);
+        assert_eq!(1, zpool.special_vdevs().len());
+        assert_eq!(&special, &zpool.special_vdevs()[0]);
+
+        let cache = &zpool.caches()[0];
+        assert_eq!(&PathBuf::from("nvme2n1"), cache.path());
+        assert_eq!(&None, cache.reason());
+        assert_eq!(&Health::Available, zpool.spares()[0].health());
+        assert!(zpool.logs().is_empty());
+    }
+
+    #[test]
+    fn preserves_unknown_sections() {
+        let stdout = "  pool: tank\n state: ONLINE\nconfig:\n\n\tNAME        STATE     READ WRITE CKSUM\n\ttank        ONLINE       0     0     0\n\t  sda       ONLINE       0     0     0\n\tembedded\t\n\t  mirror-1  ONLINE       0     0     0\n\t    sdb     ONLINE       0     0     0\n\t    sdc     ONLINE       0     0     0\n\tcache\n\t  sdd       ONLINE       0     0     0\n\nerrors: No known data errors\n";
+        let zpools = parse_zpools(stdout).unwrap();
+        let zpool = &zpools[0];
+
+        assert_eq!(1, zpool.vdevs().len());
+        assert_eq!(&PathBuf::from("sdd"), zpool.caches()[0].path());
+        let expected = vec![(
+            String::from("embedded"),
+            String::from("\t  mirror-1  ONLINE       0     0     0\n\t    sdb     ONLINE       0     0     0\n\t    sdc     ONLINE       0     0     0\n"),
+        )];
+        assert_eq!(&expected, zpool.other_sections());
+    }
+
     #[test]
     fn correctly_parse_import_with_empty_comment() {
         let stdout = include_str!("fixtures/import_with_empty_comment");
@@ -594,4 +1668,447 @@ mod test {
         assert_eq!("t2", zpools[0].name());
         assert_eq!(5333885354421686613 as u64, zpools[0].id().unwrap());
     }
+
+    #[test]
+    fn skips_warning_banner() {
+        let stdout = include_str!("fixtures/status_with_warning_banner");
+        let zpools = parse_zpools(stdout).unwrap();
+        assert_eq!(1, zpools.len());
+        assert_eq!("tank", zpools[0].name());
+        assert_eq!(&None, zpools[0].queried_at());
+        assert_eq!(2, zpools[0].vdevs()[0].disks().len());
+    }
+
+    #[test]
+    fn status_returns_errors_instead_of_panicking() {
+        let dir = tempfile::tempdir().unwrap();
+        let fixture = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("src/zpool/fixtures/status_with_warning_banner");
+        let shim = shim(dir.path(), "zpool", &format!(
+"[ \"$2\" = -j ] && echo \"invalid option 'j'\" >&2 && exit 2\nfor name; do :; done\ncase \"$name\" in\n  tank|tank2) cat {};;\n  garbage) printf '  pool: garbage\\nsomething else\\n';;\n  *) echo \"cannot open '$name': no such pool\" >&2; exit 1;;\nesac\n",
+fixture.display()
+));
+
+        let zpool = ZpoolOpen3::with_cmd(shim.as_os_str());
+        let tank = zpool.status("tank", StatusOptions::default()).unwrap();
+        assert_eq!("tank", tank.name());
+
+        let err = zpool
+            .status("garbage", StatusOptions::default())
+            .unwrap_err();
+        assert_eq!(ZpoolError::ParseError.kind(), err.kind());
+        match err {
+            ZpoolError::UnparsedStatus(stdout, span) => {
+                assert_eq!("something else", &stdout[span]);
+            }
+            other => panic!("unexpected error: {:?}", other),
+        }
+
+        let err = zpool
+            .status("missing", StatusOptions::default())
+            .unwrap_err();
+        assert_eq!(ZpoolError::PoolNotFound.kind(), err.kind());
+        // Pool missing from output of `zpool status` is reported the same way.
+        let err = zpool.status("tank2", StatusOptions::default()).unwrap_err();
+        assert_eq!(ZpoolError::PoolNotFound.kind(), err.kind());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn status_json_detected_once() {
+        use std::fs;
+
+        let dir = tempfile::tempdir().unwrap();
+        let args = dir.path().join("args");
+        let fixtures = Path::new(env!("CARGO_MANIFEST_DIR")).join("src/zpool/fixtures");
+        let write_shim =
+            |name: &str, json: bool| {
+                let shim =
+                    shim(
+                        dir.path(),
+                        name,
+                        &format!(
+"echo \"$@\" >> {}\nif [ \"$2\" = -j ]; then\n  {}\nelse\n  cat {}\nfi\n",
+args.display(),
+                if json {
+                    format!("cat {}", fixtures.join("status_json_degraded.json").display())
+                } else {
+                    String::from("echo \"invalid option 'j'\" >&2; exit 2")
+                },
+                fixtures.join("status_json_degraded").display()
+),
+                    );
+                shim
+            };
+        let calls = || {
+            let calls = fs::read_to_string(&args).unwrap();
+            fs::remove_file(&args).unwrap();
+            calls
+        };
+
+        // Old zpool: `-j` is tried once, then text is used right away.
+        let zpool = ZpoolOpen3::with_cmd(write_shim("old", false));
+        let from_text = zpool.status("tank", StatusOptions::default()).unwrap();
+        zpool.status_all(StatusOptions::default()).unwrap();
+        assert_eq!("status -j tank\nstatus tank\nstatus\n", calls());
+
+        let zpool = ZpoolOpen3::with_cmd(write_shim("new", true));
+        let opts = StatusOptions::builder()
+            .timestamp(TimestampFormat::Unix)
+            .build()
+            .unwrap();
+        let from_json = zpool.status("tank", opts).unwrap();
+        assert_eq!(from_text.vdevs(), from_json.vdevs());
+        assert_eq!(from_text.spares(), from_json.spares());
+        assert!(from_json.queried_at().is_some());
+        let opts = StatusOptions::builder().verbose(true).build().unwrap();
+        zpool.status("tank", opts).unwrap();
+        let opts = StatusOptions::builder()
+            .prefer_json(false)
+            .parseable(true)
+            .build()
+            .unwrap();
+        zpool.status("tank", opts).unwrap();
+        assert_eq!("status -j tank\nstatus -v tank\nstatus -p tank\n", calls());
+    }
+
+    #[test]
+    fn multihost_suspended() {
+        let stdout = include_str!("fixtures/status_multihost_suspended");
+        let zpools = parse_zpools(stdout).unwrap();
+        assert_eq!(&Health::Suspended, zpools[0].health());
+        assert_eq!(&Some(PoolStatus::MultihostSuspended), zpools[0].status());
+
+        let stdout = include_str!("fixtures/status_with_missing_device");
+        let zpools = parse_zpools(stdout).unwrap();
+        match zpools[0].status() {
+            Some(PoolStatus::Other(text)) => assert!(!text.contains('\n')),
+            other => panic!("unexpected status: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn health_list() {
+        let pools = parse_health_list(b"tank\tONLINE\nbackup\tSUSPENDED\nold\tDEGRADED\n").unwrap();
+        let expected = vec![
+            (String::from("tank"), Health::Online),
+            (String::from("backup"), Health::Suspended),
+            (String::from("old"), Health::Degraded),
+        ];
+        assert_eq!(expected, pools);
+
+        assert!(parse_health_list(b"no pools available\n")
+            .unwrap()
+            .is_empty());
+        assert!(parse_health_list(b"").unwrap().is_empty());
+
+        let err = parse_health_list(b"tank ONLINE\n").unwrap_err();
+        assert_eq!(ZpoolError::ParseError.kind(), err.kind());
+        let err = parse_health_list(b"tank\tHAPPY\n").unwrap_err();
+        assert_eq!(ZpoolError::ParseError.kind(), err.kind());
+    }
+
+    #[test]
+    fn update_properties_rolls_back() {
+        use crate::zpool::{FailMode, ZpoolPropertiesWriteBuilder};
+        use std::fs;
+
+        let dir = tempfile::tempdir().unwrap();
+        let props = dir.path().join("props");
+        let args = dir.path().join("args");
+        fs::write(
+            &props,
+            "allocated\t69120\t-\ncapacity\t0\t-\ncomment\t-\tdefault\ndedupratio\t1.00x\t-\n\
+             expandsize\t-\t-\nfragmentation\t1%\t-\nfree\t67039744\t-\nfreeing\t0\t-\n\
+             guid\t4957928072935098740\t-\nhealth\tONLINE\t-\nsize\t67108864\t-\nleaked\t0\t-\n\
+             altroot\t-\tdefault\nreadonly\toff\t-\nautoexpand\toff\tdefault\n\
+             autoreplace\toff\tdefault\nbootfs\t-\tdefault\ncachefile\t-\tdefault\n\
+             dedupditto\t0\tdefault\ndelegation\ton\tdefault\nfailmode\twait\tdefault\n",
+        )
+        .unwrap();
+        let shim = shim(dir.path(), "zpool", &format!(
+"case \"$1\" in
+  list) exit 0;;
+  get) cat {props};;
+  set)
+    echo \"$2\" >> {args}
+    case \"$2\" in
+      failmode=panic|autoreplace=off) echo \"cannot set property for 'tank': permission denied\" >&2; exit 1;;
+    esac;;
+esac
+",
+props = props.display(),
+            args = args.display()
+));
+        let zpool = ZpoolOpen3::with_cmd(shim.as_os_str());
+        let current = zpool.read_properties("tank").unwrap();
+
+        let update = ZpoolPropertiesWriteBuilder::from_props(&current)
+            .auto_expand(true)
+            .build()
+            .unwrap();
+        assert_eq!(
+            vec![String::from("autoexpand")],
+            zpool.update_properties("tank", update).unwrap()
+        );
+        fs::remove_file(&args).unwrap();
+
+        // Properties set before the failed one are restored in reverse order.
+        let update = ZpoolPropertiesWriteBuilder::from_props(&current)
+            .auto_expand(true)
+            .comment("Wat")
+            .fail_mode(FailMode::Panic)
+            .build()
+            .unwrap();
+        match zpool.update_properties("tank", update).unwrap_err() {
+            ZpoolError::UpdateFailed {
+                key,
+                cause,
+                rollback,
+            } => {
+                assert_eq!("failmode", key);
+                assert_eq!(ZpoolError::Other(String::new()).kind(), cause.kind());
+                let expected = vec![
+                    (String::from("comment"), true),
+                    (String::from("autoexpand"), true),
+                ];
+                assert_eq!(expected, rollback);
+            }
+            other => panic!("unexpected error: {:?}", other),
+        }
+        assert_eq!(
+            "autoexpand=on\ncomment=Wat\nfailmode=panic\ncomment=\nautoexpand=off\n",
+            fs::read_to_string(&args).unwrap()
+        );
+
+        let update = ZpoolPropertiesWriteBuilder::from_props(&current)
+            .auto_replace(true)
+            .fail_mode(FailMode::Panic)
+            .build()
+            .unwrap();
+        match zpool.update_properties("tank", update).unwrap_err() {
+            ZpoolError::UpdateFailed { key, rollback, .. } => {
+                assert_eq!("failmode", key);
+                assert_eq!(vec![(String::from("autoreplace"), false)], rollback);
+            }
+            other => panic!("unexpected error: {:?}", other),
+        }
+        fs::remove_file(&args).unwrap();
+
+        // This `zpool` doesn't report autotrim, so it's refused before anything is set.
+        let update = ZpoolPropertiesWriteBuilder::from_props(&current)
+            .auto_expand(true)
+            .auto_trim(Some(true))
+            .build()
+            .unwrap();
+        let err = zpool.update_properties("tank", update).unwrap_err();
+        assert_eq!(
+            ZpoolError::UnsupportedProperty(String::new()).kind(),
+            err.kind()
+        );
+        assert!(!args.exists());
+    }
+
+    #[test]
+    fn import_renamed_through_shim() {
+        use std::fs;
+
+        let dir = tempfile::tempdir().unwrap();
+        let args = dir.path().join("args");
+        let shim = shim(
+            dir.path(),
+            "zpool",
+            &format!("echo \"$@\" >> {}\n", args.display()),
+        );
+
+        let zpool = ZpoolOpen3::with_cmd(shim.as_os_str());
+        zpool
+            .import_renamed("tank", "tank-01.old", None, ImportOptions::default())
+            .unwrap();
+        let opts = ImportOptions::builder().no_mount(true).build().unwrap();
+        zpool
+            .import_renamed("tank", "backup:tank", Some(PathBuf::from("/vdevs")), opts)
+            .unwrap();
+        for new_name in &[
+            "",
+            "1234567890",
+            "-tank",
+            "back up",
+            "tank/a",
+            "mirror1",
+            "c0d0",
+        ] {
+            let err = zpool
+                .import_renamed("tank", new_name, None, ImportOptions::default())
+                .unwrap_err();
+            assert_eq!(ZpoolErrorKind::InvalidPoolName, err.kind());
+        }
+        let err = zpool
+            .import_renamed("tank", "log", None, ImportOptions::default())
+            .unwrap_err();
+        assert_eq!(
+            "invalid pool name \"log\": \"log\" is reserved for vdev specifications",
+            err.to_string()
+        );
+
+        let called = fs::read_to_string(&args).unwrap();
+        assert_eq!(
+            "import tank tank-01.old\nimport -d /vdevs -N tank backup:tank\n",
+            called
+        );
+    }
+
+    #[test]
+    fn health_through_shim() {
+        let dir = tempfile::tempdir().unwrap();
+        let shim = shim(dir.path(), "zpool", "for name; do :; done\ncase \"$name\" in\n  name,health) printf 'tank\\tONLINE\\nstuck\\tSUSPENDED\\n';;\n  tank) echo ONLINE;;\n  stuck) echo SUSPENDED;;\n  *) echo \"cannot open '$name': no such pool\" >&2; exit 1;;\nesac\n");
+
+        let zpool = ZpoolOpen3::with_cmd(shim.as_os_str());
+        assert_eq!(Health::Online, zpool.health("tank").unwrap());
+        assert_eq!(Health::Suspended, zpool.health("stuck").unwrap());
+        let err = zpool.health("missing").unwrap_err();
+        assert_eq!(ZpoolError::PoolNotFound.kind(), err.kind());
+
+        let all = zpool.health_all().unwrap();
+        assert_eq!(
+            vec![
+                (String::from("tank"), Health::Online),
+                (String::from("stuck"), Health::Suspended)
+            ],
+            all
+        );
+    }
+
+    #[test]
+    fn replace_with_first_available_spare() {
+        use std::fs;
+
+        let dir = tempfile::tempdir().unwrap();
+        let args = dir.path().join("args");
+        let fixtures = Path::new(env!("CARGO_MANIFEST_DIR")).join("src/zpool/fixtures");
+        let shim = shim(dir.path(), "zpool", &format!(
+"[ \"$2\" = -j ] && echo \"invalid option 'j'\" >&2 && exit 2\nfor name; do :; done\ncase \"$1:$name\" in\n  status:tank) cat {};;\n  status:rpool) cat {};;\n  *) echo \"$@\" >> {};;\nesac\n",
+fixtures.join("status_with_spares").display(),
+            fixtures.join("status_replacing_linux").display(),
+            args.display()
+));
+
+        let zpool = ZpoolOpen3::with_cmd(shim.as_os_str());
+        assert_eq!(
+            PathBuf::from("/dev/ada4"),
+            zpool.replace_with_spare_dry_run("tank").unwrap()
+        );
+        assert!(!args.exists());
+
+        let spare = zpool.replace_with_spare("tank", "/dev/ada1").unwrap();
+        assert_eq!(PathBuf::from("/dev/ada4"), spare);
+        assert_eq!(
+            "replace tank /dev/ada1 /dev/ada4\n",
+            fs::read_to_string(&args).unwrap()
+        );
+
+        // The only spare is in use.
+        let err = zpool
+            .replace_with_spare("rpool", "ata-WDC_WD40EFRX-68N32N0_WD-WCC7K0000001")
+            .unwrap_err();
+        assert_eq!(ZpoolError::NoAvailableSpare.kind(), err.kind());
+        assert_eq!(
+            "replace tank /dev/ada1 /dev/ada4\n",
+            fs::read_to_string(&args).unwrap()
+        );
+    }
+
+    #[test]
+    fn datasets_to_unmount_deepest_first() {
+        let mount_table = vec![
+            (PathBuf::from("tank"), PathBuf::from("/tank")),
+            (PathBuf::from("tank/a"), PathBuf::from("/tank/a")),
+            (PathBuf::from("tank2/a"), PathBuf::from("/tank2/a")),
+            (PathBuf::from("tank/a/b"), PathBuf::from("/tank/a/b")),
+            (PathBuf::from("tank/c"), PathBuf::from("/tank/c")),
+            (PathBuf::from("tank/a"), PathBuf::from("/srv/a")),
+        ];
+        let expected: Vec<PathBuf> = vec!["tank/a/b", "tank/a", "tank/c", "tank"]
+            .into_iter()
+            .map(PathBuf::from)
+            .collect();
+        assert_eq!(expected, datasets_to_unmount("tank", mount_table));
+    }
+
+    #[test]
+    fn only_busy_unmount_is_pool_busy() {
+        let dataset = PathBuf::from("tank/a");
+        let busy = |stderr: &str| {
+            unmount_error(
+                dataset.clone(),
+                zfs::Error::UnknownSoFar(String::from(stderr)),
+            )
+        };
+
+        let err = busy("cannot unmount '/tank/a': pool or dataset is busy\n");
+        assert_eq!(Some(dataset.as_path()), pool_busy_dataset(&err));
+        let err = busy("cannot unmount '/tank/a': Device busy\n");
+        assert_eq!(Some(dataset.as_path()), pool_busy_dataset(&err));
+        let err =
+            busy("umount: /tank/a: target is busy.\ncannot unmount '/tank/a': unmount failed\n");
+        assert_eq!(Some(dataset.as_path()), pool_busy_dataset(&err));
+
+        let err = busy("cannot unmount 'tank/a': permission denied\n");
+        assert_eq!(ZpoolErrorKind::Other, err.kind());
+        let err = unmount_error(
+            dataset.clone(),
+            zfs::Error::Io(io::Error::from(io::ErrorKind::PermissionDenied)),
+        );
+        assert_eq!(ZpoolErrorKind::Io, err.kind());
+        let err = unmount_error(dataset.clone(), zfs::Error::DatasetNotFound(dataset));
+        assert_eq!(ZpoolErrorKind::Other, err.kind());
+    }
+
+    fn pool_busy_dataset(err: &ZpoolError) -> Option<&Path> {
+        match err {
+            ZpoolError::PoolBusy(dataset) => dataset.as_deref(),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn attach_options_into_args() {
+        let args = AttachOptions::default().into_args();
+        assert!(args.is_empty());
+
+        let args = AttachOptions::builder()
+            .force(true)
+            .sequential(true)
+            .wait(true)
+            .ashift(12)
+            .build()
+            .unwrap()
+            .into_args();
+        let expected: Vec<OsString> = ["-f", "-s", "-w", "-o", "ashift=12"]
+            .iter()
+            .map(OsString::from)
+            .collect();
+        assert_eq!(expected, args);
+    }
+
+    #[test]
+    fn import_options_into_args() {
+        let args = ImportOptions::default().into_args();
+        assert!(args.is_empty());
+
+        let args = ImportOptions::builder()
+            .altroot(PathBuf::from("/mnt"))
+            .readonly(true)
+            .missing_log(true)
+            .force(true)
+            .no_mount(true)
+            .build()
+            .unwrap()
+            .into_args();
+        let expected: Vec<OsString> = ["-R", "/mnt", "-o", "readonly=on", "-m", "-f", "-N"]
+            .iter()
+            .map(OsString::from)
+            .collect();
+        assert_eq!(expected, args);
+    }
 }
@@ -1,6 +1,6 @@
 //! Consumer friendly representation of Zpool's properties.
 
-use std::{ffi::OsString, path::PathBuf};
+use std::{collections::HashMap, ffi::OsString, fmt, path::PathBuf, str::FromStr};
 
 use super::{ZpoolError, ZpoolResult};
 use crate::utils::parse_float;
@@ -38,24 +38,39 @@ impl PropPair for String {
 
 /// Represent state of zpool or vdev. Read
 /// [more](https://docs.oracle.com/cd/E19253-01/819-5461/gamno/index.html).
-#[derive(Debug, Clone, Eq, PartialEq)]
+///
+/// States are ordered from worst to best, so `health >= Health::Degraded` means "still
+/// operational": `Suspended < Faulted < Unavailable < Removed < Offline < Degraded < Inuse <
+/// Available < Online`.
+///
+/// ```rust
+/// use libzetta::zpool::Health;
+///
+/// let min: Health = "degraded".parse().unwrap();
+/// assert!(Health::Online > min);
+/// assert!(Health::Faulted < min);
+/// assert_eq!("DEGRADED", min.to_string());
+/// ```
+#[derive(Debug, Clone, Eq, PartialEq, PartialOrd, Ord)]
 pub enum Health {
-    /// Healthy and operational.
-    Online,
-    /// Unhealthy, but operational.
-    Degraded,
+    /// Pool I/O is suspended because of too many device failures, see `failmode`.
+    Suspended,
     /// Not operational.
     Faulted,
-    /// Taken offline by admin.
-    Offline,
-    /// Spare is ready to take over failed device.
-    Available,
     /// Can't open device.
     Unavailable,
     /// Physically removed while the system was running.
     Removed,
+    /// Taken offline by admin.
+    Offline,
+    /// Unhealthy, but operational.
+    Degraded,
     /// Spare has taken over for failed device.
     Inuse,
+    /// Spare is ready to take over failed device.
+    Available,
+    /// Healthy and operational.
+    Online,
 }
 
 impl Health {
@@ -72,9 +87,46 @@ impl Health {
             "UNAVAIL" => Ok(Health::Unavailable),
             "REMOVED" => Ok(Health::Removed),
             "INUSE" => Ok(Health::Inuse),
+            "SUSPENDED" => Ok(Health::Suspended),
             _ => Err(ZpoolError::ParseError),
         }
     }
+
+    pub(crate) fn as_str(&self) -> &str {
+        match *self {
+            Health::Online => "ONLINE",
+            Health::Degraded => "DEGRADED",
+            Health::Faulted => "FAULTED",
+            Health::Offline => "OFFLINE",
+            Health::Available => "AVAIL",
+            Health::Unavailable => "UNAVAIL",
+            Health::Removed => "REMOVED",
+            Health::Inuse => "INUSE",
+            Health::Suspended => "SUSPENDED",
+        }
+    }
+}
+
+impl fmt::Display for Health {
+    /// Same spelling as `zpool status`, e.g. `ONLINE`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Health {
+    type Err = ZpoolError;
+
+    /// Accepts `zpool status` spelling (`ONLINE`, `AVAIL`) as well as lower case and unabridged
+    /// forms (`online`, `available`).
+    fn from_str(source: &str) -> ZpoolResult<Health> {
+        match source.to_ascii_uppercase().as_str() {
+            "AVAILABLE" => Ok(Health::Available),
+            "UNAVAILABLE" => Ok(Health::Unavailable),
+            "IN USE" | "IN-USE" => Ok(Health::Inuse),
+            other => Health::try_from_str(Some(other)),
+        }
+    }
 }
 
 /// Controls the system behavior in the event of catastrophic pool failure.
@@ -148,6 +200,101 @@ impl CacheType {
     }
 }
 
+/// State of a pool feature, value of `feature@<name>` property.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FeatureState {
+    /// Pool can't use the feature until it's enabled.
+    Disabled,
+    /// Pool can use the feature, but doesn't yet.
+    Enabled,
+    /// Pool uses the feature, on-disk format depends on it.
+    Active,
+}
+
+impl FeatureState {
+    #[doc(hidden)]
+    pub fn as_str(&self) -> &str {
+        match *self {
+            FeatureState::Disabled => "disabled",
+            FeatureState::Enabled => "enabled",
+            FeatureState::Active => "active",
+        }
+    }
+
+    /// Feature is enabled or active, so datasets of the pool can use it.
+    pub fn is_usable(&self) -> bool {
+        *self != FeatureState::Disabled
+    }
+}
+
+impl fmt::Display for FeatureState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for FeatureState {
+    type Err = ZpoolError;
+
+    fn from_str(source: &str) -> ZpoolResult<FeatureState> {
+        match source {
+            "disabled" => Ok(FeatureState::Disabled),
+            "enabled" => Ok(FeatureState::Enabled),
+            "active" => Ok(FeatureState::Active),
+            _ => Err(ZpoolError::ParseError),
+        }
+    }
+}
+
+/// Where value of a pool property comes from. `SOURCE` column of `zpool get`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PoolPropertySource {
+    /// Set on the pool.
+    Local,
+    /// Not set, default value is used.
+    Default,
+    /// Set for this import only.
+    Temporary,
+    /// Read-only or otherwise not settable property (`-`).
+    None,
+    /// Spelling this library doesn't know yet.
+    Unknown(String),
+}
+
+impl PoolPropertySource {
+    #[doc(hidden)]
+    pub fn as_str(&self) -> &str {
+        match *self {
+            PoolPropertySource::Local => "local",
+            PoolPropertySource::Default => "default",
+            PoolPropertySource::Temporary => "temporary",
+            PoolPropertySource::None => "-",
+            PoolPropertySource::Unknown(ref source) => source,
+        }
+    }
+}
+
+impl fmt::Display for PoolPropertySource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for PoolPropertySource {
+    type Err = ZpoolError;
+
+    /// Never fails, unknown spellings end up in `Unknown`.
+    fn from_str(source: &str) -> ZpoolResult<PoolPropertySource> {
+        Ok(match source {
+            "local" => PoolPropertySource::Local,
+            "default" => PoolPropertySource::Default,
+            "temporary" => PoolPropertySource::Temporary,
+            "-" | "" => PoolPropertySource::None,
+            other => PoolPropertySource::Unknown(String::from(other)),
+        })
+    }
+}
+
 /// Available properties for write at run time. This doesn't include properties
 /// that are writable
 /// only during creation/import of zpool. See `zpool(8)` for more information.
@@ -165,6 +312,7 @@ impl CacheType {
 /// assert!(props.is_ok());
 /// ```
 #[derive(Getters, Builder, Debug, Clone, PartialEq, Eq)]
+#[builder(build_fn(validate = "Self::validate"))]
 #[get = "pub"]
 pub struct ZpoolPropertiesWrite {
     /// Make zpool readonly. This can only be changed during import.
@@ -182,6 +330,11 @@ pub struct ZpoolPropertiesWrite {
     #[builder(default = "false")]
     auto_replace: bool,
 
+    /// Controls automatic TRIM of space freed on devices that support it. `None` leaves it as is,
+    /// which is the only option on systems without autotrim support.
+    #[builder(default)]
+    auto_trim: Option<bool>,
+
     ///  Identifies the default bootable dataset for the root pool.
     #[builder(default)]
     boot_fs: Option<String>,
@@ -191,7 +344,8 @@ pub struct ZpoolPropertiesWrite {
     cache_file: CacheType,
 
     /// An administrator can provide additional information about a pool using
-    /// this property.
+    /// this property. Can't contain control characters (new lines, tabs) and can't be longer than
+    /// 32 bytes.
     #[builder(default)]
     #[builder(setter(into))]
     comment: String,
@@ -206,6 +360,40 @@ pub struct ZpoolPropertiesWrite {
     /// devices within the pool.
     #[builder(default = "FailMode::Wait")]
     fail_mode: FailMode,
+    /// Protect the pool from being imported by two systems at once (MMP). `None` leaves it as
+    /// is, which is the only option on systems without multihost support.
+    #[builder(default)]
+    multihost: Option<bool>,
+    /// User properties of the pool, e.g. `org.example:owner`. Names are lower case and must
+    /// contain `:`, values can't contain control characters. Only properties listed here are
+    /// set, the rest are left as they are. Empty value removes the property.
+    #[builder(default)]
+    user_properties: HashMap<String, String>,
+}
+
+/// Longest comment `zpool` accepts: `#define ZPROP_MAX_COMMENT 32` in OpenZFS
+/// `include/sys/fs/zfs.h`, checked by `zpool_valid_proplist` in `lib/libzfs/libzfs_pool.c`.
+/// Longer comments are refused with "comment may be no longer than 32 characters", 1024 is
+/// `ZFS_MAXPROPLEN` and applies to user properties only.
+const MAX_COMMENT_LEN: usize = 32;
+/// Longest name of a user property, `ZAP_MAXNAMELEN` minus terminating NUL.
+const MAX_USER_PROPERTY_NAME_LEN: usize = 255;
+/// Longest value of a user property, `ZFS_MAXPROPLEN` minus terminating NUL.
+const MAX_USER_PROPERTY_VALUE_LEN: usize = 8191;
+
+/// Whether `name` is a valid name of a user property: lower case letters, digits, `-_.:` and at
+/// least one `:`.
+pub(crate) fn is_user_property(name: &str) -> bool {
+    name.contains(':')
+        && name.len() <= MAX_USER_PROPERTY_NAME_LEN
+        && name.chars().all(|c| {
+            c.is_ascii_lowercase()
+                || c.is_ascii_digit()
+                || c == '-'
+                || c == '_'
+                || c == '.'
+                || c == ':'
+        })
 }
 
 impl ZpoolPropertiesWrite {
@@ -226,21 +414,76 @@ impl ZpoolPropertiesWrite {
         if let Some(ref btfs) = self.boot_fs {
             ret.push(PropPair::to_pair(btfs, "bootfs"));
         }
+        if let Some(multihost) = self.multihost {
+            ret.push(PropPair::to_pair(&multihost, "multihost"));
+        }
+        if let Some(auto_trim) = self.auto_trim {
+            ret.push(PropPair::to_pair(&auto_trim, "autotrim"));
+        }
+        let mut user_properties: Vec<_> = self.user_properties.iter().collect();
+        user_properties.sort();
+        for (key, value) in user_properties {
+            ret.push(PropPair::to_pair(value, key));
+        }
         ret.iter().map(OsString::from).collect()
     }
 }
 
 impl ZpoolPropertiesWriteBuilder {
+    fn validate(&self) -> Result<(), String> {
+        if let Some(ref comment) = self.comment {
+            if comment.contains(char::is_control) {
+                return Err(String::from("comment can't contain control characters"));
+            }
+            if comment.len() > MAX_COMMENT_LEN {
+                return Err(format!(
+                    "comment can't be longer than {} bytes",
+                    MAX_COMMENT_LEN
+                ));
+            }
+        }
+        for (key, value) in self.user_properties.iter().flatten() {
+            if !is_user_property(key) {
+                return Err(format!("{:?} is not a valid user property name", key));
+            }
+            if value.contains(char::is_control) {
+                return Err(format!("value of {} can't contain control characters", key));
+            }
+            if value.len() > MAX_USER_PROPERTY_VALUE_LEN {
+                return Err(format!(
+                    "value of {} can't be longer than {} bytes",
+                    key, MAX_USER_PROPERTY_VALUE_LEN
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Add a user property to set, e.g. `org.example:owner`.
+    pub fn user_property<K: Into<String>, V: Into<String>>(
+        &mut self,
+        key: K,
+        value: V,
+    ) -> &mut Self {
+        self.user_properties
+            .get_or_insert_with(HashMap::new)
+            .insert(key.into(), value.into());
+        self
+    }
+
     /// Construct new builder given existing properties. Useful for updates.
     pub fn from_props(props: &ZpoolProperties) -> ZpoolPropertiesWriteBuilder {
         let mut b = ZpoolPropertiesWriteBuilder::default();
         b.read_only(props.read_only);
         b.auto_expand(props.auto_expand);
         b.auto_replace(props.auto_replace);
+        b.auto_trim(props.auto_trim);
         b.boot_fs(props.boot_fs.clone());
         b.cache_file(props.cache_file.clone());
         b.delegation(props.delegation);
         b.fail_mode(props.fail_mode.clone());
+        b.multihost(props.multihost);
+        b.user_properties(props.user_properties());
         if let Some(ref comment) = props.comment {
             b.comment(comment.clone());
         }
@@ -250,6 +493,7 @@ impl ZpoolPropertiesWriteBuilder {
 
 /// All pre-defined properties of Zpool - both immutable and mutable. Majority of this documentation
 /// lifted from manual page.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[derive(Debug, Clone, PartialEq, Getters)]
 #[get = "pub"]
 pub struct ZpoolProperties {
@@ -285,18 +529,25 @@ pub struct ZpoolProperties {
     /// the amount of space remaining to be reclaimed.  Over time
     /// freeing will decrease while free increases.
     freeing: i64,
-    /// A unique identifier for the pool.
+    /// A unique identifier for the pool. Changes after `zpool reguid`.
     guid: u64,
+    /// A unique identifier of the pool that changes every time pool is imported. `None` if
+    /// installed version of ZFS doesn't report it.
+    load_guid: Option<u64>,
     /// The current health of the pool.
     health: Health,
     /// Total size of the storage pool.
     size: usize,
     /// Leaked space?
     leaked: usize,
+    /// Sector size the pool uses for new vdevs, as a power of two. `0` means it's detected for
+    /// every vdev. `None` if installed version of ZFS doesn't report it.
+    ashift: Option<u64>,
     // writable
     /// Alternate root directory, can only be set during creation or import.
     alt_root: Option<PathBuf>,
-    /// Pool is read only
+    /// Pool is read only. It can only be set at import (`-o readonly=on`) and lasts until export,
+    /// `zpool get` reports no source (`-`) for it either way.
     read_only: bool,
     /// Controls automatic pool expansion when the underlying LUN is grown.
     auto_expand: bool,
@@ -306,6 +557,9 @@ pub struct ZpoolProperties {
     /// pool, is automatically
     /// formatted and replaced. The default behavior is "off".
     auto_replace: bool,
+    /// Whether space freed on devices is trimmed automatically. `None` if installed version of
+    /// ZFS doesn't support it.
+    auto_trim: Option<bool>,
     ///  Identifies the default bootable dataset for the root pool.
     boot_fs: Option<String>,
     /// Controls the location of where the pool configuration is cached.
@@ -325,6 +579,20 @@ pub struct ZpoolProperties {
     /// connectivity to the underlying storage device(s) or a failure of all
     /// devices within the pool.
     fail_mode: FailMode,
+    /// Whether pool is protected from being imported by two systems at once (MMP). `None` if
+    /// installed version of ZFS doesn't support it.
+    multihost: Option<bool>,
+    /// User properties and properties this library failed to recognize. Features (`feature@`)
+    /// aren't included.
+    unknown_properties: HashMap<String, String>,
+    /// Source of every property, features included. Empty if output was parsed without `source`
+    /// column.
+    #[cfg_attr(feature = "serde", serde(default))]
+    property_sources: HashMap<String, PoolPropertySource>,
+    /// State of every feature installed ZFS knows about, by name without `feature@` prefix.
+    /// Features in a state this library doesn't know are left out.
+    #[cfg_attr(feature = "serde", serde(default))]
+    features: HashMap<String, FeatureState>,
 }
 
 fn parse_bool(val: Option<&str>) -> ZpoolResult<bool> {
@@ -347,76 +615,177 @@ fn parse_u64(val: Option<&str>) -> ZpoolResult<u64> {
     let val_str = val.ok_or(ZpoolError::ParseError)?;
     Ok(val_str.parse()?)
 }
+/// `dedupratio` is a multiplier with `x` suffix, e.g. `1.76x`.
+fn parse_ratio(val: Option<&str>) -> ZpoolResult<f64> {
+    let mut val_string = val.map(String::from).ok_or(ZpoolError::ParseError)?;
+    Ok(parse_float(&mut val_string)?)
+}
+/// Percentages have `%` suffix unless `-p` is used.
+fn parse_percent<T: FromStr>(val: Option<&str>) -> ZpoolResult<T>
+where
+    ZpoolError: From<T::Err>,
+{
+    let val_str = val.ok_or(ZpoolError::ParseError)?;
+    Ok(val_str.trim_end_matches('%').parse()?)
+}
+/// Properties that have a field in `ZpoolProperties`. Order doesn't matter.
+pub(crate) const PROPERTY_NAMES: [&str; 25] = [
+    "allocated",
+    "capacity",
+    "comment",
+    "dedupratio",
+    "expandsize",
+    "fragmentation",
+    "free",
+    "freeing",
+    "guid",
+    "health",
+    "size",
+    "leaked",
+    "altroot",
+    "readonly",
+    "autoexpand",
+    "autoreplace",
+    "bootfs",
+    "cachefile",
+    "dedupditto",
+    "delegation",
+    "failmode",
+    "load_guid",
+    "multihost",
+    "ashift",
+    "autotrim",
+];
+
 impl ZpoolProperties {
+    /// User properties of the pool, e.g. `org.example:owner`.
+    pub fn user_properties(&self) -> HashMap<String, String> {
+        self.unknown_properties
+            .iter()
+            .filter(|(key, _)| is_user_property(key))
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect()
+    }
+
+    /// Parse output of `zpool get -Hp -o property,value all <pool>` captured elsewhere, e.g. on a
+    /// remote machine. Every property this structure has must be present.
+    pub fn parse_list_output(text: &str) -> ZpoolResult<ZpoolProperties> {
+        ZpoolProperties::try_from_stdout(text.as_bytes())
+    }
+
+    /// Same as [`parse_list_output`](#method.parse_list_output), but for
+    /// `zpool get -Hp -o property,value,source all <pool>`, so `property_sources` is filled.
+    pub fn parse_list_output_with_sources(text: &str) -> ZpoolResult<ZpoolProperties> {
+        ZpoolProperties::try_from_stdout_with_sources(text.as_bytes())
+    }
+
+    /// Parse output of `zpool get -Hp -o property,value`. Every property is on its own line and
+    /// only the first tab separates name from value, so tabs in a value (e.g. `comment`) stay in
+    /// that value.
     pub(crate) fn try_from_stdout(out: &[u8]) -> ZpoolResult<ZpoolProperties> {
-        let mut stdout: String = String::from_utf8_lossy(out).into();
-        // remove new line at the end.
-        stdout.pop();
-        let mut cols = stdout.split('\t');
+        ZpoolProperties::from_rows(out, false)
+    }
+
+    /// Parse output of `zpool get -Hp -o property,value,source`. Source is after the last tab.
+    pub(crate) fn try_from_stdout_with_sources(out: &[u8]) -> ZpoolResult<ZpoolProperties> {
+        ZpoolProperties::from_rows(out, true)
+    }
+
+    fn from_rows(out: &[u8], with_sources: bool) -> ZpoolResult<ZpoolProperties> {
+        let stdout = String::from_utf8_lossy(out);
+        let mut values = HashMap::with_capacity(PROPERTY_NAMES.len());
+        let mut property_sources = HashMap::new();
+        for line in stdout.lines() {
+            let mut row = line.splitn(2, '\t');
+            let property = row.next().ok_or(ZpoolError::ParseError)?;
+            let mut value = row.next().ok_or(ZpoolError::ParseError)?;
+            if with_sources {
+                let mut rest = value.rsplitn(2, '\t');
+                let source = rest.next().ok_or(ZpoolError::ParseError)?;
+                value = rest.next().ok_or(ZpoolError::ParseError)?;
+                property_sources.insert(String::from(property), source.parse()?);
+            }
+            values.insert(property, value);
+        }
+        let get = |property: &str| values.get(property).cloned();
+        let unknown_properties = values
+            .iter()
+            .filter(|(property, _)| {
+                !PROPERTY_NAMES.contains(property) && !property.starts_with("feature@")
+            })
+            .map(|(property, value)| (String::from(*property), String::from(*value)))
+            .collect();
+        let features = values
+            .iter()
+            .filter_map(|(property, value)| {
+                let feature = property.strip_prefix("feature@")?;
+                let state = value.parse().ok()?;
+                Some((String::from(feature), state))
+            })
+            .collect();
 
-        let alloc = parse_usize(cols.next())?;
+        let alloc = parse_usize(get("allocated"))?;
 
-        let cap_str = cols.next().ok_or(ZpoolError::ParseError)?;
-        let cap: u8 = cap_str.parse()?;
+        let cap: u8 = parse_percent(get("capacity"))?;
 
-        let comment_str = cols.next().ok_or(ZpoolError::ParseError)?;
+        let comment_str = get("comment").ok_or(ZpoolError::ParseError)?;
         let comment = match comment_str {
             "-" | "" => None,
             c => Some(String::from(c)),
         };
 
-        let mut dedup_ratio_string = cols
-            .next()
-            .ok_or(ZpoolError::ParseError)
-            .map(String::from)?;
-        let dedup_ratio: f64 = parse_float(&mut dedup_ratio_string)?;
+        let dedup_ratio = parse_ratio(get("dedupratio"))?;
 
-        let expand_size_str = cols.next().ok_or(ZpoolError::ParseError)?;
+        let expand_size_str = get("expandsize").ok_or(ZpoolError::ParseError)?;
         let expand_size: Option<usize> = match expand_size_str {
             "-" => None,
             c => Some(c.parse()?),
         };
 
-        // remove '%'
-        let mut frag_string = cols
-            .next()
-            .ok_or(ZpoolError::ParseError)
-            .map(String::from)?;
-        let last_char = {
-            let chars = frag_string.chars();
-            chars.last()
-        };
-        if last_char == Some('%') {
-            frag_string.pop();
-        }
-        let fragmentation: i8 = frag_string.parse()?;
+        let fragmentation: i8 = parse_percent(get("fragmentation"))?;
 
-        let free = parse_i64(cols.next())?;
-        let freeing = parse_i64(cols.next())?;
-        let guid = parse_u64(cols.next())?;
-        let health = Health::try_from_str(cols.next())?;
-        let size = parse_usize(cols.next())?;
-        let leaked = parse_usize(cols.next())?;
+        let free = parse_i64(get("free"))?;
+        let freeing = parse_i64(get("freeing"))?;
+        let guid = parse_u64(get("guid"))?;
+        let load_guid = match get("load_guid") {
+            None | Some("-") => None,
+            value => Some(parse_u64(value)?),
+        };
+        let health = Health::try_from_str(get("health"))?;
+        let size = parse_usize(get("size"))?;
+        let leaked = parse_usize(get("leaked"))?;
+        let ashift = match get("ashift") {
+            None | Some("-") => None,
+            value => Some(parse_u64(value)?),
+        };
 
-        let alt_root_str = cols.next().ok_or(ZpoolError::ParseError)?;
+        let alt_root_str = get("altroot").ok_or(ZpoolError::ParseError)?;
         let alt_root = match alt_root_str {
             "-" => None,
             r => Some(PathBuf::from(r)),
         };
 
-        let read_only = parse_bool(cols.next())?;
-        let auto_expand = parse_bool(cols.next())?;
-        let auto_replace = parse_bool(cols.next())?;
+        let read_only = parse_bool(get("readonly"))?;
+        let auto_expand = parse_bool(get("autoexpand"))?;
+        let auto_replace = parse_bool(get("autoreplace"))?;
+        let auto_trim = match get("autotrim") {
+            None | Some("-") => None,
+            value => Some(parse_bool(value)?),
+        };
 
-        let boot_fs_str = cols.next().ok_or(ZpoolError::ParseError)?;
+        let boot_fs_str = get("bootfs").ok_or(ZpoolError::ParseError)?;
         let boot_fs = match boot_fs_str {
             "-" => None,
             r => Some(String::from(r)),
         };
-        let cache_file = CacheType::try_from_str(cols.next())?;
-        let dedup_ditto = parse_usize(cols.next())?;
-        let delegation = parse_bool(cols.next())?;
-        let fail_mode = FailMode::try_from_str(cols.next())?;
+        let cache_file = CacheType::try_from_str(get("cachefile"))?;
+        let dedup_ditto = parse_usize(get("dedupditto"))?;
+        let delegation = parse_bool(get("delegation"))?;
+        let fail_mode = FailMode::try_from_str(get("failmode"))?;
+        let multihost = match get("multihost") {
+            None | Some("-") => None,
+            value => Some(parse_bool(value)?),
+        };
 
         Ok(ZpoolProperties {
             alloc,
@@ -428,26 +797,163 @@ impl ZpoolProperties {
             free,
             freeing,
             guid,
+            load_guid,
             health,
             size,
             leaked,
+            ashift,
             alt_root,
             read_only,
             auto_expand,
             auto_replace,
+            auto_trim,
             boot_fs,
             cache_file,
             dedup_ditto,
             delegation,
             fail_mode,
+            multihost,
+            unknown_properties,
+            property_sources,
+            features,
         })
     }
 }
 
+/// Sizes and health of a pool as `zpool list` shows them. Much cheaper to get for every pool
+/// than [`ZpoolProperties`](struct.ZpoolProperties.html) or status.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, PartialEq, Getters)]
+#[get = "pub"]
+pub struct PoolListEntry {
+    /// Name of the pool.
+    name: String,
+    /// Total size of the storage pool.
+    size: usize,
+    /// Amount of storage space within the pool that has been physically allocated.
+    alloc: usize,
+    /// The amount of free space available in the pool.
+    free: i64,
+    /// The amount of fragmentation in the pool. `None` if pool doesn't have `spacemap_histogram`
+    /// feature enabled.
+    fragmentation: Option<i8>,
+    /// Percentage of pool space used.
+    capacity: u8,
+    /// The deduplication ratio of the pool.
+    dedup_ratio: f64,
+    /// The current health of the pool.
+    health: Health,
+    /// Alternate root directory the pool is imported with.
+    alt_root: Option<PathBuf>,
+}
+
+impl PoolListEntry {
+    /// Columns `list_pools` asks `zpool list -Hp -o` for, in the order they are parsed.
+    pub(crate) const COLUMNS: &'static str =
+        "name,size,alloc,free,fragmentation,capacity,dedupratio,health,altroot";
+
+    /// Parse output of `zpool list -Hp -o` with [`COLUMNS`](#associatedconstant.COLUMNS), a pool
+    /// per line. `altroot` is the last column, so tabs in it stay in it.
+    pub(crate) fn try_from_stdout(out: &[u8]) -> ZpoolResult<Vec<PoolListEntry>> {
+        let stdout = String::from_utf8_lossy(out);
+        let mut pools = Vec::new();
+        for line in stdout.lines() {
+            if line.trim().is_empty() || line.trim() == "no pools available" {
+                continue;
+            }
+            let columns: Vec<&str> = line.splitn(9, '\t').collect();
+            if columns.len() != 9 {
+                return Err(ZpoolError::UnparsedOutput(stdout.to_string()));
+            }
+            let get = |idx: usize| Some(columns[idx]);
+            let fragmentation = match columns[4] {
+                "-" => None,
+                _ => Some(parse_percent(get(4))?),
+            };
+            let alt_root = match columns[8] {
+                "-" => None,
+                r => Some(PathBuf::from(r)),
+            };
+            pools.push(PoolListEntry {
+                name: String::from(columns[0]),
+                size: parse_usize(get(1))?,
+                alloc: parse_usize(get(2))?,
+                free: parse_i64(get(3))?,
+                fragmentation,
+                capacity: parse_percent(get(5))?,
+                dedup_ratio: parse_ratio(get(6))?,
+                health: Health::try_from_str(get(7))?,
+                alt_root,
+            });
+        }
+        Ok(pools)
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
 
+    /// Turn values in order of `PROPERTY_NAMES` into `zpool get -H -o property,value` output.
+    fn get_output(values: &[u8]) -> Vec<u8> {
+        let values = String::from_utf8_lossy(values);
+        let rows: Vec<String> = PROPERTY_NAMES
+            .iter()
+            .zip(values.trim_end_matches('\n').split('\t'))
+            .map(|(property, value)| format!("{}\t{}\n", property, value))
+            .collect();
+        rows.concat().into_bytes()
+    }
+
+    #[test]
+    fn pool_list_linux() {
+        let pools =
+            PoolListEntry::try_from_stdout(include_bytes!("fixtures/list_pools_linux")).unwrap();
+        assert_eq!(3, pools.len());
+        let tank = &pools[1];
+        assert_eq!("tank", tank.name());
+        assert_eq!(&7_984_378_822_656, tank.size());
+        assert_eq!(&5_356_849_201_152, tank.alloc());
+        assert_eq!(&2_627_529_621_504, tank.free());
+        assert_eq!(&Some(31), tank.fragmentation());
+        assert_eq!(&67, tank.capacity());
+        assert_eq!(&1.27, tank.dedup_ratio());
+        assert_eq!(&Health::Degraded, tank.health());
+        assert_eq!(&None, tank.alt_root());
+        assert_eq!(&Some(PathBuf::from("/mnt/backup")), pools[2].alt_root());
+        assert_eq!(&Some(0), pools[2].fragmentation());
+    }
+
+    #[test]
+    fn pool_list_freebsd() {
+        let pools =
+            PoolListEntry::try_from_stdout(include_bytes!("fixtures/list_pools_freebsd")).unwrap();
+        let names: Vec<&str> = pools.iter().map(|pool| pool.name().as_str()).collect();
+        assert_eq!(vec!["zroot", "storage", "old"], names);
+        let storage = &pools[1];
+        assert_eq!(&None, storage.fragmentation());
+        assert_eq!(&99, storage.capacity());
+        assert_eq!(&2.05, storage.dedup_ratio());
+        assert_eq!(&Some(PathBuf::from("/storage")), storage.alt_root());
+        assert_eq!(&0, pools[2].free());
+        assert_eq!(&100, pools[2].capacity());
+        assert_eq!(&Health::Faulted, pools[2].health());
+    }
+
+    #[test]
+    fn pool_list_edge_cases() {
+        assert!(PoolListEntry::try_from_stdout(b"no pools available\n")
+            .unwrap()
+            .is_empty());
+        assert!(PoolListEntry::try_from_stdout(b"").unwrap().is_empty());
+
+        let err = PoolListEntry::try_from_stdout(b"tank\t1\t1\t0\t-\t100\n").unwrap_err();
+        assert_eq!(ZpoolError::ParseError.kind(), err.kind());
+        let err = PoolListEntry::try_from_stdout(b"tank\t1\t1\t0\t-\t100\tlots\tONLINE\t-\n")
+            .unwrap_err();
+        assert_eq!(ZpoolError::ParseError.kind(), err.kind());
+    }
+
     #[test]
     fn test_defaults() {
         let built = ZpoolPropertiesWriteBuilder::default().build().unwrap();
@@ -455,11 +961,14 @@ mod test {
             read_only: false,
             auto_expand: false,
             auto_replace: false,
+            auto_trim: None,
             boot_fs: None,
             cache_file: CacheType::Default,
             comment: String::new(),
             delegation: false,
             fail_mode: FailMode::Wait,
+            multihost: None,
+            user_properties: HashMap::new(),
         };
 
         assert_eq!(handmade, built);
@@ -547,47 +1056,242 @@ mod test {
     #[test]
     fn parsing_props_u64_guid() {
         let line = b"69120\t0\t-\t1.00x\t-\t1%\t67039744\t0\t15867762423891129245\tONLINE\t67108864\t0\t-\toff\toff\toff\t-\t-\t0\ton\twait\n";
-        let props = ZpoolProperties::try_from_stdout(line);
+        let props = ZpoolProperties::try_from_stdout(&get_output(line));
         assert!(props.is_ok());
     }
 
     #[test]
     fn parsing_on_zol() {
         let line = b"99840\t0\t-\t1.00\t-\t1\t67009024\t0\t5667188105885376774\tONLINE\t67108864\t0\t-\toff\toff\toff\t-\t-\t0\ton\twait\n";
-        let props = ZpoolProperties::try_from_stdout(line);
+        let props = ZpoolProperties::try_from_stdout(&get_output(line));
         assert!(props.is_ok());
     }
 
     #[test]
     fn parsing_props() {
         let line = b"69120\t0\t-\t1.50x\t-\t22%\t67039744\t0\t4957928072935098740\tONLINE\t67108864\t0\t-\toff\toff\toff\t-\t-\t0\ton\twait\n";
-        let props = ZpoolProperties::try_from_stdout(line);
+        let props = ZpoolProperties::try_from_stdout(&get_output(line));
         assert!(props.is_ok());
 
         let line = b"69120\t0\ttouch it\t1.50x\t-\t22%\t67039744\t0\t4957928072935098740\tONLINE\t67108864\t0\t-\toff\toff\toff\t-\t-\t0\ton\tpanic\n";
-        let props = ZpoolProperties::try_from_stdout(line).unwrap();
+        let props = ZpoolProperties::try_from_stdout(&get_output(line)).unwrap();
         assert_eq!(Some(String::from("touch it")), props.comment);
         assert_eq!(FailMode::Panic, props.fail_mode);
 
         let line = b"69120\t0\ttouch it\t1.50x\t-\t22%\t67039744\t0\t4957928072935098740\tOFFLINE\t67108864\t0\t/mnt/\toff\toff\toff\t-\t-\t0\ton\twait\n";
-        let props = ZpoolProperties::try_from_stdout(line).unwrap();
+        let props = ZpoolProperties::try_from_stdout(&get_output(line)).unwrap();
         assert_eq!(Health::Offline, props.health);
         assert_eq!(Some(PathBuf::from("/mnt")), props.alt_root);
 
         let line = b"waf\tasd";
-        let props = ZpoolProperties::try_from_stdout(line);
+        let props = ZpoolProperties::try_from_stdout(&get_output(line));
         assert!(props.is_err());
 
         let line = b"69120\t0\ttouch it\t1.50x\t1\t22%\t67039744\t0\t4957928072935098740\tOFFLINE\t67108864\t0\t/mnt/\toff\toff\toff\tz/ROOT/default\t-\t0\ton\twait\n";
-        let props = ZpoolProperties::try_from_stdout(line).unwrap();
+        let props = ZpoolProperties::try_from_stdout(&get_output(line)).unwrap();
         assert_eq!(Some(String::from("z/ROOT/default")), props.boot_fs);
         assert_eq!(Some(1), props.expand_size);
 
         let line = b"69120\t0\t-\t1.50x\t-\t22%\t67039744\t0\t4957928072935098740\tONLINE\t67108864\t0\t-\toff\toff\toff\t-\t-\t0\tomn\twait\n";
-        let props = ZpoolProperties::try_from_stdout(line);
+        let props = ZpoolProperties::try_from_stdout(&get_output(line));
+        assert!(props.is_err());
+    }
+
+    #[test]
+    fn parse_list_output() {
+        let out = get_output(b"69120\t0\t-\t1.50x\t-\t22%\t67039744\t0\t4957928072935098740\tONLINE\t67108864\t0\t-\toff\toff\toff\t-\t-\t0\ton\twait\n");
+        let props = ZpoolProperties::parse_list_output(&String::from_utf8(out).unwrap()).unwrap();
+        assert_eq!(22, props.fragmentation);
+        assert_eq!(Health::Online, props.health);
+        assert_eq!(None, props.load_guid);
+
+        let out = get_output(b"69120\t0\t-\t1.50x\t-\t22%\t67039744\t0\t4957928072935098740\tONLINE\t67108864\t0\t-\toff\toff\toff\t-\t-\t0\ton\twait\t1288349386052399115\n");
+        let props = ZpoolProperties::parse_list_output(&String::from_utf8(out).unwrap()).unwrap();
+        assert_eq!(4957928072935098740, props.guid);
+        assert_eq!(Some(1288349386052399115), props.load_guid);
+        assert_eq!(None, props.multihost);
+
+        let out = get_output(b"69120\t0\t-\t1.50x\t-\t22%\t67039744\t0\t4957928072935098740\tONLINE\t67108864\t0\t-\toff\toff\toff\t-\t-\t0\ton\twait\t-\ton\n");
+        let props = ZpoolProperties::parse_list_output(&String::from_utf8(out).unwrap()).unwrap();
+        assert_eq!(None, props.load_guid);
+        assert_eq!(Some(true), props.multihost);
+        let write = ZpoolPropertiesWriteBuilder::from_props(&props)
+            .build()
+            .unwrap();
+        assert_eq!(&Some(true), write.multihost());
+        assert!(write.into_args().contains(&OsString::from("multihost=on")));
+
+        let props = ZpoolProperties::parse_list_output("health\tONLINE\n");
+        assert_eq!(ZpoolError::ParseError.kind(), props.unwrap_err().kind());
+    }
+
+    #[test]
+    fn parsing_comment_with_tab() {
+        let mut out = get_output(b"69120\t0\t-\t1.50x\t-\t22%\t67039744\t0\t4957928072935098740\tONLINE\t67108864\t0\t-\toff\toff\toff\t-\t-\t0\ton\tpanic\n");
+        out.extend_from_slice(b"comment\ttouch\tit\n");
+        let props = ZpoolProperties::try_from_stdout(&out).unwrap();
+        assert_eq!(Some(String::from("touch\tit")), props.comment);
+        assert_eq!(Health::Online, props.health);
+        assert_eq!(FailMode::Panic, props.fail_mode);
+
+        let props = ZpoolProperties::try_from_stdout(b"allocated\t69120\n");
         assert!(props.is_err());
     }
 
+    const ALL_HEALTH: [Health; 9] = [
+        Health::Suspended,
+        Health::Faulted,
+        Health::Unavailable,
+        Health::Removed,
+        Health::Offline,
+        Health::Degraded,
+        Health::Inuse,
+        Health::Available,
+        Health::Online,
+    ];
+
+    #[test]
+    fn health_round_trip() {
+        for health in ALL_HEALTH.iter() {
+            assert_eq!(health.as_str(), health.to_string());
+            assert_eq!(
+                Some(health),
+                health.to_string().parse::<Health>().ok().as_ref()
+            );
+            assert_eq!(
+                Some(health),
+                health
+                    .to_string()
+                    .to_lowercase()
+                    .parse::<Health>()
+                    .ok()
+                    .as_ref()
+            );
+        }
+        let aliases = [
+            ("available", Health::Available),
+            ("Unavailable", Health::Unavailable),
+            ("unavail", Health::Unavailable),
+            ("in use", Health::Inuse),
+            ("in-use", Health::Inuse),
+            ("inuse", Health::Inuse),
+        ];
+        for (alias, health) in aliases.iter() {
+            assert_eq!(
+                Some(health),
+                alias.parse::<Health>().ok().as_ref(),
+                "{}",
+                alias
+            );
+        }
+        for bad in &["", "healthy", "ONLINE ", "avail able"] {
+            let err = bad.parse::<Health>().unwrap_err();
+            assert_eq!(ZpoolError::ParseError.kind(), err.kind());
+        }
+    }
+
+    #[test]
+    fn health_order() {
+        let mut shuffled = ALL_HEALTH.to_vec();
+        shuffled.reverse();
+        shuffled.sort();
+        assert_eq!(ALL_HEALTH.to_vec(), shuffled);
+        assert_eq!(Some(&Health::Online), ALL_HEALTH.iter().max());
+        assert!(Health::Degraded > Health::Faulted);
+        assert!(Health::Suspended < Health::Faulted);
+    }
+
+    #[test]
+    fn comment_is_validated() {
+        for comment in &["touch\nit", "touch\tit", "touch\rit", "touch\u{7f}it"] {
+            let props = ZpoolPropertiesWriteBuilder::default()
+                .comment(*comment)
+                .build();
+            assert!(props.is_err(), "{:?}", comment);
+        }
+        let props = ZpoolPropertiesWriteBuilder::default()
+            .comment("x".repeat(MAX_COMMENT_LEN + 1))
+            .build();
+        assert!(props.is_err());
+
+        let props = ZpoolPropertiesWriteBuilder::default()
+            .comment(format!("a = \"{}\"", "x".repeat(MAX_COMMENT_LEN - 6)))
+            .build()
+            .unwrap();
+        assert_eq!(MAX_COMMENT_LEN, props.comment().len());
+    }
+
+    #[test]
+    fn user_properties_are_validated() {
+        assert!(is_user_property("org.example:owner"));
+        assert!(is_user_property("com.sun:auto-snapshot_1"));
+        assert!(!is_user_property("owner"));
+        assert!(!is_user_property("Org.Example:owner"));
+        assert!(!is_user_property("org example:owner"));
+        assert!(!is_user_property(&format!("a:{}", "b".repeat(254))));
+
+        let rejected = [
+            ("owner", "team"),
+            ("org.example:owner", "team\nops"),
+            ("org.example:owner", "team\tops"),
+        ];
+        for (key, value) in rejected.iter() {
+            let props = ZpoolPropertiesWriteBuilder::default()
+                .user_property(*key, *value)
+                .build();
+            assert!(props.is_err(), "{}={:?}", key, value);
+        }
+        let props = ZpoolPropertiesWriteBuilder::default()
+            .user_property(
+                "org.example:note",
+                "x".repeat(MAX_USER_PROPERTY_VALUE_LEN + 1),
+            )
+            .build();
+        assert!(props.is_err());
+    }
+
+    #[test]
+    fn user_properties_round_trip() {
+        let props = ZpoolPropertiesWriteBuilder::default()
+            .user_property("org.example:owner", "team = ops")
+            .user_property("org.example:note", "Grüße, 世界 \"quoted\"")
+            .build()
+            .unwrap();
+        let args = props.into_args();
+        assert_eq!(
+            &[
+                OsString::from("org.example:note=Grüße, 世界 \"quoted\""),
+                OsString::from("org.example:owner=team = ops")
+            ],
+            &args[args.len() - 2..]
+        );
+
+        let mut out = get_output(b"69120\t0\t-\t1.50x\t-\t22%\t67039744\t0\t4957928072935098740\tONLINE\t67108864\t0\t-\toff\toff\toff\t-\t-\t0\ton\twait\n");
+        out.extend_from_slice("org.example:note\tGrüße, 世界 \"quoted\"\n".as_bytes());
+        out.extend_from_slice(b"org.example:owner\tteam = ops\n");
+        out.extend_from_slice(b"feature@async_destroy\tenabled\n");
+        out.extend_from_slice(b"feature@from_the_future\tsleeping\n");
+        out.extend_from_slice(b"bcloneused\t0\n");
+        let props = ZpoolProperties::try_from_stdout(&out).unwrap();
+        assert_eq!(1, props.features().len());
+        assert_eq!(2 + 1, props.unknown_properties().len());
+        assert_eq!(
+            Some(&String::from("0")),
+            props.unknown_properties().get("bcloneused")
+        );
+        let user_properties = props.user_properties();
+        assert_eq!(2, user_properties.len());
+        assert_eq!(
+            Some(&String::from("Grüße, 世界 \"quoted\"")),
+            user_properties.get("org.example:note")
+        );
+
+        let write = ZpoolPropertiesWriteBuilder::from_props(&props)
+            .build()
+            .unwrap();
+        assert_eq!(&user_properties, write.user_properties());
+    }
+
     #[test]
     fn to_arg() {
         let props = ZpoolPropertiesWriteBuilder::default().build().unwrap();
@@ -646,6 +1350,7 @@ mod test {
 
         let props = ZpoolPropertiesWriteBuilder::default()
             .auto_replace(true)
+            .auto_trim(Some(true))
             .comment("a test")
             .build()
             .unwrap();
@@ -656,6 +1361,7 @@ mod test {
             "comment=a test",
             "delegation=off",
             "failmode=wait",
+            "autotrim=on",
         ]
         .into_iter()
         .map(OsString::from)
@@ -668,4 +1374,81 @@ mod test {
     fn write_builder() {
         let _right: ZpoolPropertiesWriteBuilder = ZpoolPropertiesWrite::builder();
     }
+
+    #[test]
+    fn sources_after_readonly_import() {
+        let linux = ZpoolProperties::parse_list_output_with_sources(include_str!(
+            "fixtures/get_readonly_import_linux"
+        ))
+        .unwrap();
+        let freebsd = ZpoolProperties::try_from_stdout_with_sources(include_bytes!(
+            "fixtures/get_readonly_import_freebsd"
+        ))
+        .unwrap();
+        for props in &[&linux, &freebsd] {
+            assert!(props.read_only);
+            assert_eq!(CacheType::None, props.cache_file);
+            assert_eq!(
+                Some(&PoolPropertySource::None),
+                props.property_sources.get("readonly")
+            );
+            assert_eq!(
+                Some(&PoolPropertySource::Local),
+                props.property_sources.get("cachefile")
+            );
+            assert_eq!(
+                Some(&PoolPropertySource::Default),
+                props.property_sources.get("failmode")
+            );
+            assert_eq!(
+                Some(&PoolPropertySource::Local),
+                props.property_sources.get("feature@async_destroy")
+            );
+            assert_eq!(
+                Some(&FeatureState::Enabled),
+                props.features.get("async_destroy")
+            );
+            assert_eq!(
+                Some(&FeatureState::Active),
+                props.features.get("empty_bpobj")
+            );
+        }
+        assert_eq!(
+            Some(&FeatureState::Active),
+            linux.features.get("lz4_compress")
+        );
+        assert_eq!(None, freebsd.features.get("lz4_compress"));
+        assert_eq!(Some(5183710382318765436), linux.load_guid);
+        assert_eq!(Some(12), linux.ashift);
+        assert_eq!(Some(false), linux.auto_trim);
+        assert_eq!(None, freebsd.ashift);
+        assert_eq!(None, freebsd.auto_trim);
+        assert_eq!(
+            Some(&String::from("backup")),
+            linux.user_properties().get("org.example:owner")
+        );
+        assert_eq!(Some(String::from("offsite\tcopy")), freebsd.comment);
+        assert_eq!(Some(PathBuf::from("/mnt")), freebsd.alt_root);
+    }
+
+    #[test]
+    fn property_source_spellings() {
+        let parsed: Vec<PoolPropertySource> = ["local", "default", "temporary", "-", "received"]
+            .iter()
+            .map(|source| source.parse().unwrap())
+            .collect();
+        let expected = vec![
+            PoolPropertySource::Local,
+            PoolPropertySource::Default,
+            PoolPropertySource::Temporary,
+            PoolPropertySource::None,
+            PoolPropertySource::Unknown(String::from("received")),
+        ];
+        assert_eq!(expected, parsed);
+        assert_eq!("received", expected[4].to_string());
+
+        let out = get_output(b"69120\t0\t-\t1.50x\t-\t22%\t67039744\t0\t4957928072935098740\tONLINE\t67108864\t0\t-\ton\toff\toff\t-\t-\t0\ton\twait\n");
+        let props = ZpoolProperties::try_from_stdout(&out).unwrap();
+        assert!(props.property_sources.is_empty());
+    }
 }
@@ -0,0 +1,466 @@
+//! `zpool status -j` output turned into [Zpool](../description/struct.Zpool.html). JSON is read
+//! into structures that mirror its schema first, then mapped onto the same types text output is
+//! parsed into, following the same rules: a device reported by its GUID gets the path it `was`,
+//! a lone device becomes a single disk vdev and so on.
+//!
+//! Fields text output doesn't have are ignored. Things that differ between the two:
+//!
+//!  - `(resilvering)` and `(awaiting resilver)` notes are not mapped, `Disk::resilver` is `None`.
+//!  - `queried_at` is when output was read, because `-T` doesn't apply to JSON.
+use std::{convert::TryFrom, fmt, marker::PhantomData, path::PathBuf, str::FromStr};
+
+use serde::{
+    de::{MapAccess, Visitor},
+    Deserialize, Deserializer,
+};
+
+use crate::zpool::{
+    description::{parse_scan_date, unwrap_text, PoolStatus, Reason, ScanStatus, Zpool},
+    vdev::{ErrorStatistics, Vdev, VdevType},
+    Disk, Health, ZpoolError, ZpoolResult,
+};
+
+/// Parse the whole output of `zpool status -j`. `queried_at` is given to every pool.
+pub(crate) fn zpools_from_json(json: &str, queried_at: Option<i64>) -> ZpoolResult<Vec<Zpool>> {
+    let output: StatusOutput =
+        serde_json::from_str(json).map_err(|_| ZpoolError::UnparsedOutput(json.into()))?;
+    output
+        .pools
+        .into_iter()
+        .map(|(_, pool)| pool.into_zpool(queried_at))
+        .collect()
+}
+
+#[derive(Deserialize)]
+struct StatusOutput {
+    #[serde(default, deserialize_with = "in_order")]
+    pools: Vec<(String, PoolJson)>,
+}
+
+#[derive(Deserialize)]
+struct PoolJson {
+    name: String,
+    state: String,
+    #[serde(default)]
+    status: Option<String>,
+    #[serde(default)]
+    action: Option<String>,
+    #[serde(default)]
+    scan_stats: Option<ScanJson>,
+    #[serde(default, deserialize_with = "in_order")]
+    vdevs: Vec<(String, VdevJson)>,
+    #[serde(default, deserialize_with = "in_order")]
+    logs: Vec<(String, VdevJson)>,
+    #[serde(default, deserialize_with = "in_order")]
+    special: Vec<(String, VdevJson)>,
+    #[serde(default, deserialize_with = "in_order")]
+    dedup: Vec<(String, VdevJson)>,
+    #[serde(default, deserialize_with = "in_order")]
+    l2cache: Vec<(String, VdevJson)>,
+    #[serde(default, deserialize_with = "in_order")]
+    spares: Vec<(String, VdevJson)>,
+    #[serde(default)]
+    error_count: Option<Number>,
+}
+
+#[derive(Deserialize)]
+struct VdevJson {
+    name: String,
+    vdev_type: String,
+    state: String,
+    #[serde(default)]
+    path: Option<String>,
+    #[serde(default)]
+    aux: Option<String>,
+    #[serde(default)]
+    not_present: Option<Number>,
+    #[serde(default)]
+    read_errors: Option<Number>,
+    #[serde(default)]
+    write_errors: Option<Number>,
+    #[serde(default)]
+    checksum_errors: Option<Number>,
+    #[serde(default, deserialize_with = "in_order")]
+    vdevs: Vec<(String, VdevJson)>,
+}
+
+#[derive(Deserialize)]
+struct ScanJson {
+    function: String,
+    state: String,
+    #[serde(default)]
+    start_time: Option<Number>,
+    #[serde(default)]
+    end_time: Option<Number>,
+    #[serde(default)]
+    errors: Option<Number>,
+    #[serde(default)]
+    scrub_pause: Option<Number>,
+}
+
+/// Numbers are strings unless `--json-int` is given. Times are dates unless `-p` is given.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Number {
+    Int(u64),
+    Text(String),
+}
+
+impl Number {
+    fn as_u64(&self) -> Option<u64> {
+        match self {
+            Number::Int(number) => Some(*number),
+            Number::Text(text) => text.trim().parse().ok(),
+        }
+    }
+
+    /// Seconds since epoch, or a date in local time. `-` and zero mean there is no date.
+    fn as_timestamp(&self) -> Option<i64> {
+        match self {
+            Number::Int(0) => None,
+            Number::Int(number) => i64::try_from(*number).ok(),
+            Number::Text(text) => match text.trim().parse::<i64>() {
+                Ok(0) => None,
+                Ok(number) => Some(number),
+                Err(_) => parse_scan_date(text),
+            },
+        }
+    }
+}
+
+/// JSON object as a list of its entries, in the order `zpool` printed them.
+fn in_order<'de, D, V>(deserializer: D) -> Result<Vec<(String, V)>, D::Error>
+where
+    D: Deserializer<'de>,
+    V: Deserialize<'de>,
+{
+    struct InOrder<V>(PhantomData<V>);
+
+    impl<'de, V: Deserialize<'de>> Visitor<'de> for InOrder<V> {
+        type Value = Vec<(String, V)>;
+
+        fn expecting(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+            formatter.write_str("a map")
+        }
+
+        fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+            let mut entries = Vec::with_capacity(map.size_hint().unwrap_or(0));
+            while let Some(entry) = map.next_entry()? {
+                entries.push(entry);
+            }
+            Ok(entries)
+        }
+    }
+
+    deserializer.deserialize_map(InOrder(PhantomData))
+}
+
+impl PoolJson {
+    fn into_zpool(self, queried_at: Option<i64>) -> ZpoolResult<Zpool> {
+        // Pool line of the config is the `root` vdev, everything else is nested in it.
+        let root = self
+            .vdevs
+            .into_iter()
+            .next()
+            .map(|(_, root)| root)
+            .ok_or(ZpoolError::ParseError)?;
+        let errors = self
+            .error_count
+            .and_then(|count| count.as_u64())
+            .filter(|count| *count > 0)
+            .map(|count| format!("{} data errors, use '-v' for a list\n", count));
+        let scan = match self.scan_stats {
+            Some(scan) => scan.into_scan(),
+            None => None,
+        };
+
+        Ok(Zpool::builder()
+            .name(self.name)
+            .health(Health::try_from_str(Some(&self.state))?)
+            .error_statistics(root.error_statistics())
+            .reason(root.reason(false))
+            .vdevs(top_level_vdevs(root.vdevs)?)
+            .logs(top_level_vdevs(self.logs)?)
+            .special_vdevs(top_level_vdevs(self.special)?)
+            .dedup_vdevs(top_level_vdevs(self.dedup)?)
+            .caches(disks(self.l2cache, false)?)
+            .spares(disks(self.spares, true)?)
+            .status(self.status.map(|text| PoolStatus::from_status_text(&text)))
+            .action(self.action.as_deref().map(unwrap_text))
+            .errors(errors)
+            .scan(scan)
+            .queried_at(queried_at)
+            .build()
+            .expect("Failed to build zpool"))
+    }
+}
+
+impl ScanJson {
+    /// `None` when there was never a scan, text output has no `scan:` line then.
+    fn into_scan(self) -> Option<ScanStatus> {
+        let start = self.start_time.as_ref().and_then(Number::as_timestamp);
+        let end = self.end_time.as_ref().and_then(Number::as_timestamp);
+        let paused = self.scrub_pause.as_ref().and_then(Number::as_timestamp);
+        let errors = self.errors.as_ref().and_then(Number::as_u64).unwrap_or(0);
+        let scan = match (self.function.as_str(), self.state.as_str()) {
+            ("NONE", _) => return None,
+            ("SCRUB", "SCANNING") => match paused {
+                Some(since) => Some(ScanStatus::ScrubPaused { since }),
+                None => start.map(|since| ScanStatus::ScrubInProgress { since }),
+            },
+            ("SCRUB", "FINISHED") => end.map(|finished_at| ScanStatus::ScrubFinished {
+                errors,
+                finished_at,
+            }),
+            ("SCRUB", "CANCELED") => end.map(|at| ScanStatus::ScrubCanceled { at }),
+            ("RESILVER", "SCANNING") => start.map(|since| ScanStatus::ResilverInProgress { since }),
+            ("RESILVER", "FINISHED") => end.map(|finished_at| ScanStatus::ResilverFinished {
+                errors,
+                finished_at,
+            }),
+            _ => None,
+        };
+        Some(scan.unwrap_or_else(|| {
+            ScanStatus::Other(format!(
+                "{} {}",
+                self.function.to_lowercase(),
+                self.state.to_lowercase()
+            ))
+        }))
+    }
+}
+
+impl VdevJson {
+    fn health(&self) -> ZpoolResult<Health> {
+        Health::try_from_str(Some(&self.state))
+    }
+
+    fn error_statistics(&self) -> ErrorStatistics {
+        // Human readable counters, e.g. `1.2K`, don't fit just like in text output.
+        let count = |number: &Option<Number>| {
+            number
+                .as_ref()
+                .map_or(0, |number| number.as_u64().unwrap_or(u64::MAX))
+        };
+        ErrorStatistics {
+            read: count(&self.read_errors),
+            write: count(&self.write_errors),
+            checksum: count(&self.checksum_errors),
+        }
+    }
+
+    /// Path of a device that is missing. Text output shows it as `was <path>`.
+    fn was(&self) -> Option<PathBuf> {
+        self.not_present.as_ref()?;
+        self.path.as_ref().map(PathBuf::from)
+    }
+
+    fn reason(&self, spare: bool) -> Option<Reason> {
+        if let Some(was) = self.was() {
+            return Some(Reason::from(format!("was {}", was.display()).as_str()));
+        }
+        match self.aux.as_deref() {
+            Some(aux) => Some(Reason::from(aux_message(aux))),
+            None if spare && self.state == "INUSE" => Some(Reason::from("currently in use")),
+            None => None,
+        }
+    }
+
+    fn into_disk(self, spare: bool) -> ZpoolResult<Disk> {
+        let health = self.health()?;
+        let reason = self.reason(spare);
+        let was = self.was();
+        // Missing devices are reported by their GUID.
+        let guid = self.name.parse::<u64>().ok();
+        let path = match (guid, &was) {
+            (Some(_), Some(was)) => was.clone(),
+            _ => PathBuf::from(&self.name),
+        };
+        Ok(Disk::builder()
+            .path(path)
+            .guid(guid)
+            .health(health)
+            .error_statistics(self.error_statistics())
+            .reason(reason)
+            .was(was)
+            .build()
+            .expect("Failed to build disk"))
+    }
+
+    /// `replacing-N` or `spare-N` group.
+    fn into_group(self) -> ZpoolResult<Vdev> {
+        let kind = VdevType::from_str(&self.vdev_type)?;
+        let health = self.health()?;
+        let error_statistics = self.error_statistics();
+        let reason = self.reason(false);
+        let disks = self
+            .vdevs
+            .into_iter()
+            .map(|(_, disk)| disk.into_disk(false))
+            .collect::<ZpoolResult<Vec<Disk>>>()?;
+        Ok(Vdev::builder()
+            .kind(kind)
+            .health(health)
+            .disks(disks)
+            .error_statistics(error_statistics)
+            .reason(reason)
+            .build()
+            .expect("Failed to build vdev"))
+    }
+
+    fn into_top_level_vdev(self) -> ZpoolResult<Vdev> {
+        match self.vdev_type.as_str() {
+            "disk" | "file" => {
+                let disk = self.into_disk(false)?;
+                Ok(Vdev::builder()
+                    .kind(VdevType::SingleDisk)
+                    .health(disk.health().clone())
+                    .reason(disk.reason().clone())
+                    .disks(vec![disk])
+                    .build()
+                    .expect("Failed to build vdev"))
+            }
+            "replacing" | "spare" => self.into_group(),
+            _ => {
+                // Name is `raidz2-0`, type is only `raidz`.
+                let raid_name = self.name.rsplitn(2, '-').last().unwrap_or_default();
+                let kind = VdevType::from_str(raid_name)?;
+                let health = self.health()?;
+                let error_statistics = self.error_statistics();
+                let reason = self.reason(false);
+                let mut disks = Vec::new();
+                let mut groups = Vec::new();
+                for (_, member) in self.vdevs {
+                    if member.vdevs.is_empty() {
+                        disks.push(member.into_disk(false)?);
+                    } else {
+                        let group = member.into_group()?;
+                        disks.extend(group.disks().iter().cloned());
+                        groups.push(group);
+                    }
+                }
+                Ok(Vdev::builder()
+                    .kind(kind)
+                    .health(health)
+                    .disks(disks)
+                    .error_statistics(error_statistics)
+                    .reason(reason)
+                    .groups(groups)
+                    .build()
+                    .expect("Failed to build vdev"))
+            }
+        }
+    }
+}
+
+fn top_level_vdevs(vdevs: Vec<(String, VdevJson)>) -> ZpoolResult<Vec<Vdev>> {
+    vdevs
+        .into_iter()
+        .map(|(_, vdev)| vdev.into_top_level_vdev())
+        .collect()
+}
+
+fn disks(disks: Vec<(String, VdevJson)>, spares: bool) -> ZpoolResult<Vec<Disk>> {
+    disks
+        .into_iter()
+        .map(|(_, disk)| disk.into_disk(spares))
+        .collect()
+}
+
+/// Message text output prints for `aux` state of a device, unknown states are kept as they are.
+fn aux_message(aux: &str) -> &str {
+    match aux {
+        "OPEN_FAILED" => "cannot open",
+        "CORRUPT_DATA" => "corrupted data",
+        "NO_REPLICAS" => "insufficient replicas",
+        "BAD_GUID_SUM" => "missing device",
+        "TOO_SMALL" => "device is too small",
+        "BAD_LABEL" => "invalid label",
+        "VERSION_NEWER" => "newer version",
+        "VERSION_OLDER" => "older version",
+        "UNSUP_FEAT" => "unsupported feature(s)",
+        "SPARED" | "ACTIVE" => "currently in use",
+        "ERR_EXCEEDED" => "too many errors",
+        "IO_FAILURE" => "experienced I/O failures",
+        "BAD_LOG" => "bad intent log",
+        "EXTERNAL" | "EXTERNAL_PERSIST" => "external device fault",
+        "SPLIT_POOL" => "split into new pool",
+        "CHILDREN_OFFLINE" => "all children offline",
+        "ASHIFT_TOO_BIG" => "unsupported minimum blocksize",
+        other => other,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn json_matches_text() {
+        let pairs = [
+            (
+                include_str!("fixtures/status_json_degraded"),
+                include_str!("fixtures/status_json_degraded.json"),
+            ),
+            (
+                include_str!("fixtures/status_json_replacing"),
+                include_str!("fixtures/status_json_replacing.json"),
+            ),
+        ];
+        for (text, json) in pairs.iter() {
+            let from_text = Zpool::parse_status(text).unwrap();
+            let from_json = zpools_from_json(json, None).unwrap();
+            assert!(!from_text.is_empty());
+            assert_eq!(from_text, from_json);
+        }
+    }
+
+    #[test]
+    fn scan_stats() {
+        let scan = |json: &str| serde_json::from_str::<ScanJson>(json).unwrap().into_scan();
+        assert_eq!(None, scan(r#"{"function": "NONE", "state": "NONE"}"#));
+        assert_eq!(
+            Some(ScanStatus::ScrubInProgress { since: 1673141806 }),
+            scan(
+                r#"{"function": "SCRUB", "state": "SCANNING", "start_time": "1673141806", "end_time": "0", "scrub_pause": "-"}"#
+            )
+        );
+        assert_eq!(
+            Some(ScanStatus::ScrubPaused { since: 1673141900 }),
+            scan(
+                r#"{"function": "SCRUB", "state": "SCANNING", "start_time": 1673141806, "scrub_pause": 1673141900}"#
+            )
+        );
+        assert_eq!(
+            Some(ScanStatus::ScrubFinished {
+                errors: 2,
+                finished_at: 1673141900
+            }),
+            scan(
+                r#"{"function": "SCRUB", "state": "FINISHED", "start_time": "1673141806", "end_time": "1673141900", "errors": "2"}"#
+            )
+        );
+        assert_eq!(
+            Some(ScanStatus::ScrubCanceled { at: 1673141900 }),
+            scan(r#"{"function": "SCRUB", "state": "CANCELED", "end_time": "1673141900"}"#)
+        );
+        assert_eq!(
+            Some(ScanStatus::Other(String::from("errorscrub scanning"))),
+            scan(r#"{"function": "ERRORSCRUB", "state": "SCANNING"}"#)
+        );
+    }
+
+    #[test]
+    fn unknown_json() {
+        assert_eq!(
+            Vec::<Zpool>::new(),
+            zpools_from_json(
+                r#"{"output_version": {"vers_major": 0}, "pools": {}}"#,
+                None
+            )
+            .unwrap()
+        );
+        let err = zpools_from_json("no pools available", None).unwrap_err();
+        assert_eq!(crate::zpool::ZpoolErrorKind::ParseError, err.kind());
+    }
+}
@@ -39,11 +39,22 @@
 //!     .unwrap();
 //! ```
 
-use std::{ffi::OsString, path::PathBuf};
-
-use crate::zpool::{properties::ZpoolPropertiesWrite, vdev::CreateVdevRequest, CreateMode};
+use std::{collections::HashMap, ffi::OsString, path::PathBuf};
+
+#[cfg(any(target_os = "linux", target_os = "freebsd"))]
+use crate::zpool::device_resolver;
+#[cfg(not(any(target_os = "linux", target_os = "freebsd")))]
+use crate::zpool::ZpoolError;
+use crate::zpool::{
+    features::{is_known_feature, Compatibility, FeatureToggle},
+    name::validate_pool_name,
+    properties::ZpoolPropertiesWrite,
+    vdev::CreateVdevRequest,
+    CreateMode, ZpoolResult,
+};
 #[derive(Default, Builder, Debug, Clone, Getters, PartialEq, Eq)]
 #[builder(setter(into))]
+#[builder(build_fn(validate = "Self::validate"))]
 #[get = "pub"]
 /// Consumer friendly representation of zpool structure.
 pub struct CreateZpoolRequest {
@@ -85,6 +96,47 @@ pub struct CreateZpoolRequest {
     /// fails, the hot spare automatically replaces the failed device.
     #[builder(default)]
     spares: Vec<PathBuf>,
+    /// Resolve every device before creating the pool, see
+    /// [`validate_devices`](#method.validate_devices).
+    #[builder(default)]
+    resolve_devices: bool,
+    /// Features to enable or disable, by name without `feature@` prefix. Passed as
+    /// `-o feature@<name>=<toggle>`.
+    #[builder(default)]
+    features: HashMap<String, FeatureToggle>,
+    /// Value of `compatibility` property: `off`, `legacy` or comma-separated list of
+    /// compatibility files, see [`Compatibility`](../features/enum.Compatibility.html) for presets.
+    #[builder(default)]
+    compatibility: Option<String>,
+    /// Reject feature names this library doesn't know, see
+    /// [`KNOWN_FEATURES`](../features/constant.KNOWN_FEATURES.html). Off by default, because new
+    /// features appear with almost every ZFS release.
+    #[builder(default)]
+    strict_features: bool,
+}
+
+impl CreateZpoolRequestBuilder {
+    /// Pool name must follow rules of [`validate_pool_name`](../fn.validate_pool_name.html) and,
+    /// with `strict_features`, every feature must be known.
+    fn validate(&self) -> Result<(), String> {
+        if let Some(ref name) = self.name {
+            validate_pool_name(name).map_err(|e| format!("invalid pool name {:?}: {}", name, e))?;
+        }
+        if self.strict_features == Some(true) {
+            if let Some(ref features) = self.features {
+                let mut unknown: Vec<&str> = features
+                    .keys()
+                    .map(String::as_str)
+                    .filter(|name| !is_known_feature(name))
+                    .collect();
+                unknown.sort_unstable();
+                if !unknown.is_empty() {
+                    return Err(format!("unknown features: {}", unknown.join(", ")));
+                }
+            }
+        }
+        Ok(())
+    }
 }
 
 impl CreateZpoolRequest {
@@ -118,6 +170,45 @@ impl CreateZpoolRequest {
         self.is_suitable_for_update()
     }
 
+    /// Every device of the request: vdevs, logs, caches and spares.
+    pub fn devices(&self) -> impl Iterator<Item = &PathBuf> {
+        self.vdevs
+            .iter()
+            .chain(self.logs.iter())
+            .flat_map(CreateVdevRequest::disks)
+            .chain(self.caches.iter())
+            .chain(self.spares.iter())
+    }
+
+    /// Make sure every device exists and no physical device is listed twice under different
+    /// names (e.g. `ada0p3` and `gpt/zfs-data-0`). Devices are resolved with
+    /// [`device_resolver::resolve`](../device_resolver/fn.resolve.html).
+    #[cfg(any(target_os = "linux", target_os = "freebsd"))]
+    pub fn validate_devices(&self) -> ZpoolResult<()> {
+        device_resolver::check_unique(self.devices(), |device| device_resolver::resolve(device))
+    }
+
+    /// Devices can't be resolved on this platform, always fails with `Unsupported`.
+    #[cfg(not(any(target_os = "linux", target_os = "freebsd")))]
+    pub fn validate_devices(&self) -> ZpoolResult<()> {
+        Err(ZpoolError::Unsupported)
+    }
+
+    /// `-o` values for features and compatibility, features sorted by name.
+    pub(crate) fn feature_args(&self) -> Vec<String> {
+        let mut features: Vec<(&String, &FeatureToggle)> = self.features.iter().collect();
+        features.sort_unstable_by_key(|(name, _)| *name);
+        self.compatibility
+            .iter()
+            .map(|compatibility| format!("compatibility={}", compatibility))
+            .chain(
+                features
+                    .into_iter()
+                    .map(|(name, toggle)| format!("feature@{}={}", name, toggle)),
+            )
+            .collect()
+    }
+
     /// Make CreateZpoolRequest usable as arg for [`Command`](https://doc.rust-lang.org/std/process/struct.Command.html).
     pub(crate) fn into_args(self) -> Vec<OsString> {
         let mut ret: Vec<OsString> = Vec::with_capacity(13);
@@ -206,6 +297,29 @@ impl CreateZpoolRequestBuilder {
         }
         self
     }
+
+    /// Enable or disable a feature on new pool.
+    ///
+    /// * `name` - feature name without `feature@` prefix, e.g. `encryption`.
+    pub fn feature<N: Into<String>>(
+        &mut self,
+        name: N,
+        toggle: FeatureToggle,
+    ) -> &mut CreateZpoolRequestBuilder {
+        self.features
+            .get_or_insert_with(HashMap::new)
+            .insert(name.into(), toggle);
+        self
+    }
+
+    /// Restrict features of new pool to one of preset sets.
+    pub fn compatibility_preset(
+        &mut self,
+        preset: Compatibility,
+    ) -> &mut CreateZpoolRequestBuilder {
+        self.compatibility = Some(Some(String::from(preset.as_str())));
+        self
+    }
 }
 
 #[cfg(test)]
@@ -269,6 +383,81 @@ mod test {
         assert!(!topo.is_suitable_for_create());
     }
 
+    #[test]
+    fn builder_rejects_invalid_pool_name() {
+        let build = |name: &str| {
+            CreateZpoolRequestBuilder::default()
+                .name(name)
+                .vdev(CreateVdevRequest::SingleDisk("sda".into()))
+                .build()
+        };
+        assert!(build("tank-01.backup").is_ok());
+        let err = build("raidz-tank").unwrap_err();
+        assert_eq!(
+            "invalid pool name \"raidz-tank\": \"raidz\" is reserved for vdev specifications",
+            err.to_string()
+        );
+        assert!(build("c0t0d0").is_err());
+        assert!(build("back up").is_err());
+    }
+
+    #[test]
+    fn feature_args() {
+        let build = |strict: bool| {
+            CreateZpoolRequestBuilder::default()
+                .name("boot")
+                .vdev(CreateVdevRequest::SingleDisk("sda".into()))
+                .compatibility_preset(Compatibility::Grub2)
+                .feature("lz4_compress", FeatureToggle::Enabled)
+                .feature("encryption", FeatureToggle::Disabled)
+                .feature("org.example:shiny", FeatureToggle::Enabled)
+                .strict_features(strict)
+                .build()
+        };
+        let topo = build(false).unwrap();
+        assert_eq!(
+            vec![
+                "compatibility=grub2",
+                "feature@encryption=disabled",
+                "feature@lz4_compress=enabled",
+                "feature@org.example:shiny=enabled",
+            ],
+            topo.feature_args()
+        );
+        assert_eq!(
+            "unknown features: org.example:shiny",
+            build(true).unwrap_err().to_string()
+        );
+
+        let plain = CreateZpoolRequestBuilder::default()
+            .name("tank")
+            .vdev(CreateVdevRequest::SingleDisk("sda".into()))
+            .strict_features(true)
+            .build()
+            .unwrap();
+        assert!(plain.feature_args().is_empty());
+    }
+
+    #[test]
+    fn devices_of_request() {
+        let topo = CreateZpoolRequestBuilder::default()
+            .name("tank")
+            .vdev(CreateVdevRequest::Mirror(vec!["sda".into(), "sdb".into()]))
+            .zil(CreateVdevRequest::SingleDisk("nvme0n1".into()))
+            .cache("nvme1n1".into())
+            .spare("sdc".into())
+            .build()
+            .unwrap();
+        let devices: Vec<&PathBuf> = topo.devices().collect();
+        assert_eq!(
+            vec!["sda", "sdb", "nvme0n1", "nvme1n1", "sdc"],
+            devices
+                .iter()
+                .map(|device| device.to_str().unwrap())
+                .collect::<Vec<_>>()
+        );
+    }
+
     #[test]
     fn test_builder() {
         let result = CreateZpoolRequest::builder().build();
@@ -26,16 +26,27 @@
 
 use std::{
     default::Default,
-    ffi::OsString,
+    ffi::{OsStr, OsString},
+    fmt, fs,
     path::{Path, PathBuf},
     str::FromStr,
 };
 
-use crate::zpool::{Health, Reason, ZpoolError};
+use crate::zpool::{device_metadata::DeviceMetadata, Health, Reason, ZpoolError};
+use regex::Regex;
+
+lazy_static! {
+    static ref RE_PART_SUFFIX: Regex =
+        Regex::new(r"^(.+)-part\d+$").expect("failed to compile RE_PART_SUFFIX");
+    static ref RE_DEV_PARTITION: Regex =
+        Regex::new(r"^(nvme\d+n\d+|mmcblk\d+)p\d+$|^((?:x?vd|[hs]d)[a-z]+)\d+$")
+            .expect("failed to compile RE_DEV_PARTITION");
+}
 
 /// Error statistics.
 ///
 /// NOTE: Due to imperfections of our world number of errors limited to [`std::u64::MAX`](https://doc.rust-lang.org/std/u64/constant.MAX.html).
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[derive(Debug, Clone, Eq, PartialEq)]
 pub struct ErrorStatistics {
     /// I/O errors that occurred while issuing a read request
@@ -62,13 +73,18 @@ impl Default for ErrorStatistics {
 /// It can be backed by a entire block device, a partition or a file. This particular structure
 /// represents backing of existing vdev. If disk is part of active zpool then it will also
 /// have error counts.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[derive(Debug, Clone, Getters, Eq, Builder)]
 #[builder(setter(into))]
 #[get = "pub"]
 pub struct Disk {
     /// Path to a backing device or file. If path is relative, then it's
-    /// relative to `/dev/`.
+    /// relative to `/dev/`. For a missing device `zpool status` only reports its GUID, in that
+    /// case this is the path it had before it went missing, if known, or GUID otherwise.
     path: PathBuf,
+    /// GUID of the device when `zpool status` reports device by its GUID instead of a path.
+    #[builder(default)]
+    guid: Option<u64>,
     /// Current health of this specific device.
     health: Health,
     /// Reason why device is in this state.
@@ -78,18 +94,144 @@ pub struct Disk {
     /// reset.
     #[builder(default)]
     error_statistics: ErrorStatistics,
+    /// Path device had before it went missing. Device itself is reported by its guid in that case.
+    #[builder(default)]
+    was: Option<PathBuf>,
+    /// Whether device is being resilvered or waits for its turn.
+    #[builder(default)]
+    resilver: Option<ResilverStatus>,
+    /// Model of the drive. Only known after
+    /// [`Zpool::enrich_devices`](../description/struct.Zpool.html#method.enrich_devices).
+    #[builder(default)]
+    #[cfg_attr(feature = "serde", serde(default))]
+    model: Option<String>,
+    /// Serial number of the drive. Only known after
+    /// [`Zpool::enrich_devices`](../description/struct.Zpool.html#method.enrich_devices).
+    #[builder(default)]
+    #[cfg_attr(feature = "serde", serde(default))]
+    serial: Option<String>,
+}
+
+/// Resilver annotation `zpool status` puts next to a device.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResilverStatus {
+    /// `(resilvering)` - device is being resilvered right now.
+    Resilvering,
+    /// `(awaiting resilver)` - device will be resilvered once current resilver is done.
+    AwaitingResilver,
+}
+
+impl fmt::Display for ResilverStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            ResilverStatus::Resilvering => f.write_str("resilvering"),
+            ResilverStatus::AwaitingResilver => f.write_str("awaiting resilver"),
+        }
+    }
+}
+
+impl FromStr for ResilverStatus {
+    type Err = ZpoolError;
+
+    fn from_str(source: &str) -> Result<ResilverStatus, ZpoolError> {
+        match source {
+            "resilvering" => Ok(ResilverStatus::Resilvering),
+            "awaiting resilver" => Ok(ResilverStatus::AwaitingResilver),
+            _ => Err(ZpoolError::ParseError),
+        }
+    }
 }
 
 impl Disk {
     pub fn builder() -> DiskBuilder {
         DiskBuilder::default()
     }
+
+    /// Path to a backing device with aliases removed: relative paths are treated as relative to
+    /// `/dev/`, symlinks are resolved if path exists and partition suffixes that ZFS adds to whole
+    /// disks (`-part1`, `p1` for NVMe, `1` for `sda`) are stripped.
+    pub fn canonical_path(&self) -> PathBuf {
+        canonicalize_device(&self.path)
+    }
+
+    /// Whether this is a block device a drive can be found behind: not a file and not a missing
+    /// device reported by its GUID.
+    pub(crate) fn is_block_device(&self) -> bool {
+        let file = self.path.is_absolute() && !self.path.starts_with("/dev");
+        self.guid.is_none() && !file
+    }
+
+    pub(crate) fn set_metadata(&mut self, metadata: DeviceMetadata) {
+        self.model = metadata.model().clone();
+        self.serial = metadata.serial().clone();
+    }
+}
+
+/// Strip partition suffix ZFS adds to whole disks from the last component of the path. Kernel
+/// device names like `sda1` are only recognized directly in `/dev/`.
+fn strip_partition_suffix(path: &Path) -> PathBuf {
+    let file_name = match path.file_name().and_then(|name| name.to_str()) {
+        Some(name) => name,
+        None => return path.to_path_buf(),
+    };
+    let in_dev = path
+        .parent()
+        .is_none_or(|parent| parent.as_os_str().is_empty() || parent == Path::new("/dev"));
+    let caps = match RE_PART_SUFFIX.captures(file_name) {
+        Some(caps) => Some(caps),
+        None if in_dev => RE_DEV_PARTITION.captures(file_name),
+        None => None,
+    };
+    match caps.and_then(|caps| caps.iter().skip(1).flatten().next()) {
+        Some(disk) => path.with_file_name(disk.as_str()),
+        None => path.to_path_buf(),
+    }
+}
+
+fn canonicalize_device(path: &Path) -> PathBuf {
+    let absolute = if path.is_relative() {
+        Path::new("/dev").join(path)
+    } else {
+        path.to_path_buf()
+    };
+    let resolved = fs::canonicalize(&absolute).unwrap_or(absolute);
+    strip_partition_suffix(&resolved)
+}
+
+/// Strategy to use when comparing [`Vdev`](struct.Vdev.html) reported by `zpool` with
+/// [`CreateVdevRequest`](enum.CreateVdevRequest.html).
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum MatchStrategy {
+    /// Paths must be exactly the same. The same as `==`.
+    Exact,
+    /// Paths are compared after [`Disk::canonical_path`](struct.Disk.html#method.canonical_path).
+    /// Touches file system to resolve symlinks.
+    Canonicalized,
+    /// Only last components of paths are compared, after partition suffixes are stripped. Doesn't
+    /// touch file system.
+    BaseName,
+}
+
+impl MatchStrategy {
+    fn matches(self, disk: &Disk, path: &Path) -> bool {
+        match self {
+            MatchStrategy::Exact => disk == path,
+            MatchStrategy::Canonicalized => disk.canonical_path() == canonicalize_device(path),
+            MatchStrategy::BaseName => {
+                strip_partition_suffix(disk.path()).file_name()
+                    == strip_partition_suffix(path).file_name()
+            }
+        }
+    }
 }
 
 /// Equal if path is the same.
 impl PartialEq for Disk {
     fn eq(&self, other: &Disk) -> bool {
-        self.path == other.path
+        match (self.guid, other.guid) {
+            (Some(l), Some(r)) if l == r => true,
+            _ => self.path == other.path,
+        }
     }
 }
 
@@ -117,6 +259,49 @@ impl PartialEq<Disk> for Path {
     }
 }
 
+/// Anything that can identify an existing device of a pool on `zpool` command line: a path, a
+/// [`Disk`](struct.Disk.html) from status output or a GUID. GUID is the only way to refer to a
+/// device whose path no longer exists.
+pub trait AsDeviceArg {
+    /// Token to pass to `zpool`.
+    fn as_device_arg(&self) -> OsString;
+}
+
+macro_rules! impl_as_device_arg {
+    ($($t:ty),*) => {
+        $(impl AsDeviceArg for $t {
+            fn as_device_arg(&self) -> OsString {
+                let arg: &OsStr = self.as_ref();
+                arg.to_os_string()
+            }
+        })*
+    };
+}
+
+impl_as_device_arg!(&str, String, &String, &Path, PathBuf, &PathBuf, &OsStr, OsString, &OsString);
+
+/// Missing device is identified by its GUID, because its path no longer exists.
+impl AsDeviceArg for Disk {
+    fn as_device_arg(&self) -> OsString {
+        match self.guid {
+            Some(guid) => guid.as_device_arg(),
+            None => self.path.as_os_str().to_os_string(),
+        }
+    }
+}
+
+impl AsDeviceArg for &Disk {
+    fn as_device_arg(&self) -> OsString {
+        (*self).as_device_arg()
+    }
+}
+
+impl AsDeviceArg for u64 {
+    fn as_device_arg(&self) -> OsString {
+        self.to_string().into()
+    }
+}
+
 /// A [type](https://www.freebsd.org/doc/handbook/zfs-term.html) of Vdev.
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum VdevType {
@@ -132,18 +317,50 @@ pub enum VdevType {
     RaidZ2,
     /// The same as RAID-Z, but with 3 parity drives.
     RaidZ3,
+    /// `replacing-N` - temporary group of old and new device while `zpool replace` is in
+    /// progress. Never created directly.
+    Replacing,
+    /// `spare-N` - temporary group of failed device and hot spare that took its place. Never
+    /// created directly.
+    Spare,
+}
+
+impl VdevType {
+    /// Name of the type as `zpool status` shows it, without `-N` suffix. Single disks are `disk`.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            VdevType::SingleDisk => "disk",
+            VdevType::Mirror => "mirror",
+            VdevType::RaidZ => "raidz1",
+            VdevType::RaidZ2 => "raidz2",
+            VdevType::RaidZ3 => "raidz3",
+            VdevType::Replacing => "replacing",
+            VdevType::Spare => "spare",
+        }
+    }
+}
+
+impl fmt::Display for VdevType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
 }
 
 impl FromStr for VdevType {
     type Err = ZpoolError;
 
+    /// Accepts names `zpool status` uses (`raidz1`) in any case, as well as `raidz` the way
+    /// `zpool create` takes it.
     fn from_str(source: &str) -> Result<VdevType, ZpoolError> {
-        match source {
+        match source.to_ascii_lowercase().as_str() {
+            "disk" => Ok(VdevType::SingleDisk),
             "mirror" => Ok(VdevType::Mirror),
-            "raidz1" => Ok(VdevType::RaidZ),
+            "raidz" | "raidz1" => Ok(VdevType::RaidZ),
             "raidz2" => Ok(VdevType::RaidZ2),
             "raidz3" => Ok(VdevType::RaidZ3),
-            n => Err(ZpoolError::UnknownRaidType(String::from(n))),
+            "replacing" => Ok(VdevType::Replacing),
+            "spare" => Ok(VdevType::Spare),
+            _ => Err(ZpoolError::UnknownRaidType(String::from(source))),
         }
     }
 }
@@ -222,11 +439,86 @@ impl CreateVdevRequest {
         }
     }
 
+    /// Disks of this vdev.
+    pub fn disks(&self) -> &[PathBuf] {
+        match self {
+            CreateVdevRequest::SingleDisk(disk) => std::slice::from_ref(disk),
+            CreateVdevRequest::Mirror(disks)
+            | CreateVdevRequest::RaidZ(disks)
+            | CreateVdevRequest::RaidZ2(disks)
+            | CreateVdevRequest::RaidZ3(disks) => disks,
+        }
+    }
+
     /// Short-cut to CreateVdevRequest::SingleDisk(disk)
     pub fn disk<O: Into<PathBuf>>(value: O) -> CreateVdevRequest {
         CreateVdevRequest::SingleDisk(value.into())
     }
 
+    /// Short-cut to CreateVdevRequest::Mirror(disks). In debug builds panics if there are fewer
+    /// disks than [`is_valid`](#method.is_valid) wants.
+    ///
+    /// ```rust
+    /// use libzetta::zpool::{CreateVdevRequest, CreateZpoolRequest};
+    ///
+    /// let topo = CreateZpoolRequest::builder()
+    ///     .name("tank")
+    ///     .vdev(CreateVdevRequest::mirror(vec!["/dev/ada0", "/dev/ada1"]))
+    ///     .vdev(CreateVdevRequest::raidz(vec!["/dev/ada2", "/dev/ada3", "/dev/ada4"]))
+    ///     .build()
+    ///     .unwrap();
+    /// assert_eq!(2, topo.vdevs().len());
+    /// assert!(topo.is_suitable_for_update());
+    /// ```
+    pub fn mirror<I, O>(disks: I) -> CreateVdevRequest
+    where
+        I: IntoIterator<Item = O>,
+        O: Into<PathBuf>,
+    {
+        CreateVdevRequest::checked(CreateVdevRequest::Mirror(collect_disks(disks)))
+    }
+
+    /// Short-cut to CreateVdevRequest::RaidZ(disks). In debug builds panics if there are fewer
+    /// disks than [`is_valid`](#method.is_valid) wants.
+    pub fn raidz<I, O>(disks: I) -> CreateVdevRequest
+    where
+        I: IntoIterator<Item = O>,
+        O: Into<PathBuf>,
+    {
+        CreateVdevRequest::checked(CreateVdevRequest::RaidZ(collect_disks(disks)))
+    }
+
+    /// Short-cut to CreateVdevRequest::RaidZ2(disks). In debug builds panics if there are fewer
+    /// disks than [`is_valid`](#method.is_valid) wants.
+    pub fn raidz2<I, O>(disks: I) -> CreateVdevRequest
+    where
+        I: IntoIterator<Item = O>,
+        O: Into<PathBuf>,
+    {
+        CreateVdevRequest::checked(CreateVdevRequest::RaidZ2(collect_disks(disks)))
+    }
+
+    /// Short-cut to CreateVdevRequest::RaidZ3(disks). In debug builds panics if there are fewer
+    /// disks than [`is_valid`](#method.is_valid) wants.
+    pub fn raidz3<I, O>(disks: I) -> CreateVdevRequest
+    where
+        I: IntoIterator<Item = O>,
+        O: Into<PathBuf>,
+    {
+        CreateVdevRequest::checked(CreateVdevRequest::RaidZ3(collect_disks(disks)))
+    }
+
+    #[inline]
+    fn checked(request: CreateVdevRequest) -> CreateVdevRequest {
+        debug_assert!(
+            request.is_valid(),
+            "not enough disks for {:?}: {:?}",
+            request.kind(),
+            request.disks()
+        );
+        request
+    }
+
     /// Get kind
     pub fn kind(&self) -> VdevType {
         match self {
@@ -239,6 +531,33 @@ impl CreateVdevRequest {
     }
 }
 
+fn collect_disks<I, O>(disks: I) -> Vec<PathBuf>
+where
+    I: IntoIterator<Item = O>,
+    O: Into<PathBuf>,
+{
+    disks.into_iter().map(Into::into).collect()
+}
+
+/// Single disk vdev.
+///
+/// ```rust
+/// use libzetta::zpool::{CreateVdevRequest, CreateZpoolRequest};
+/// use std::path::PathBuf;
+///
+/// let topo = CreateZpoolRequest::builder()
+///     .name("tank")
+///     .vdev(PathBuf::from("/dev/ada0").into())
+///     .build()
+///     .unwrap();
+/// assert_eq!(&[CreateVdevRequest::disk("/dev/ada0")], topo.vdevs().as_slice());
+/// ```
+impl From<PathBuf> for CreateVdevRequest {
+    fn from(disk: PathBuf) -> CreateVdevRequest {
+        CreateVdevRequest::SingleDisk(disk)
+    }
+}
+
 impl PartialEq<Vdev> for CreateVdevRequest {
     fn eq(&self, other: &Vdev) -> bool {
         other == self
@@ -251,6 +570,7 @@ impl PartialEq<Vdev> for CreateVdevRequest {
 /// disk or a group of disks, in the case of a RAID transform. When multiple
 /// vdevs are used, ZFS spreads data across the vdevs to increase performance
 /// and maximize usable space.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[derive(Debug, Clone, Getters, Builder, Eq)]
 #[get = "pub"]
 pub struct Vdev {
@@ -261,12 +581,16 @@ pub struct Vdev {
     /// Reason why vdev is in this state
     #[builder(default)]
     reason: Option<Reason>,
-    /// Backing devices for this vdev
+    /// Backing devices for this vdev. Devices of nested `replacing-N` and `spare-N` groups are
+    /// listed here as well, in the same order as `zpool status` shows them.
     disks: Vec<Disk>,
     /// How many read, write and checksum errors device encountered since last
     /// reset.
     #[builder(default)]
     error_statistics: ErrorStatistics,
+    /// `replacing-N` and `spare-N` groups nested in this vdev.
+    #[builder(default)]
+    groups: Vec<Vdev>,
 }
 
 impl Vdev {
@@ -274,6 +598,63 @@ impl Vdev {
     pub fn builder() -> VdevBuilder {
         VdevBuilder::default()
     }
+
+    /// Backing devices of this vdev along with their copies in nested groups.
+    pub(crate) fn disks_mut(&mut self) -> impl Iterator<Item = &mut Disk> {
+        let grouped = self
+            .groups
+            .iter_mut()
+            .flat_map(|group| group.disks.iter_mut());
+        self.disks.iter_mut().chain(grouped)
+    }
+
+    /// How many devices of this vdev can fail without losing data, judging by layout alone:
+    /// `n - 1` for a mirror of `n` devices, parity level for raidz and `0` for a single disk.
+    /// Devices that already failed are not taken into account.
+    pub fn fault_tolerance(&self) -> u32 {
+        match self.kind {
+            VdevType::SingleDisk | VdevType::Replacing | VdevType::Spare => 0,
+            VdevType::Mirror => {
+                // Every nested group counts as one side of the mirror. Vdev put together by hand
+                // might not list devices of its groups in `disks`.
+                let in_group = |disk: &Disk| {
+                    self.groups
+                        .iter()
+                        .any(|group| group.disks.iter().any(|d| d.path == disk.path))
+                };
+                let standalone = self.disks.iter().filter(|disk| !in_group(disk)).count();
+                (standalone + self.groups.len()).saturating_sub(1) as u32
+            }
+            VdevType::RaidZ => 1,
+            VdevType::RaidZ2 => 2,
+            VdevType::RaidZ3 => 3,
+        }
+    }
+
+    /// Check if this vdev is what given request would have created, comparing backing devices
+    /// with given strategy. Unlike `==` this can see through device aliases `zpool status` likes
+    /// to report. Querying status with
+    /// [`StatusOptions`](../open3/struct.StatusOptions.html) `full_paths` and `resolve_links`
+    /// makes paths more stable to begin with.
+    ///
+    /// * `request` - A request to compare with.
+    /// * `strategy` - How to compare paths of backing devices.
+    pub fn matches_request(&self, request: &CreateVdevRequest, strategy: MatchStrategy) -> bool {
+        let paths: &[PathBuf] = match request {
+            CreateVdevRequest::SingleDisk(ref disk) => std::slice::from_ref(disk),
+            CreateVdevRequest::Mirror(ref disks)
+            | CreateVdevRequest::RaidZ(ref disks)
+            | CreateVdevRequest::RaidZ2(ref disks)
+            | CreateVdevRequest::RaidZ3(ref disks) => disks,
+        };
+        self.kind() == &request.kind()
+            && self.disks().len() == paths.len()
+            && self
+                .disks()
+                .iter()
+                .zip(paths)
+                .all(|(disk, path)| strategy.matches(disk, path))
+    }
 }
 /// Vdevs are equal of their type and backing disks are equal.
 impl PartialEq for Vdev {
@@ -306,10 +687,78 @@ mod test {
 
     use super::*;
 
+    #[test]
+    fn vdev_type_round_trip() {
+        let all = [
+            VdevType::SingleDisk,
+            VdevType::Mirror,
+            VdevType::RaidZ,
+            VdevType::RaidZ2,
+            VdevType::RaidZ3,
+            VdevType::Replacing,
+            VdevType::Spare,
+        ];
+        for kind in all.iter() {
+            assert_eq!(kind.as_str(), kind.to_string());
+            assert_eq!(
+                Some(kind),
+                kind.to_string().parse::<VdevType>().ok().as_ref()
+            );
+            assert_eq!(
+                Some(kind),
+                kind.to_string()
+                    .to_uppercase()
+                    .parse::<VdevType>()
+                    .ok()
+                    .as_ref()
+            );
+        }
+        assert_eq!(Some(VdevType::RaidZ), "raidz".parse::<VdevType>().ok());
+        assert_eq!(Some(VdevType::RaidZ2), "RaidZ2".parse::<VdevType>().ok());
+        match "raidz4".parse::<VdevType>() {
+            Err(ZpoolError::UnknownRaidType(source)) => assert_eq!("raidz4", source),
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
     fn get_disks(num: usize, path: &PathBuf) -> Vec<PathBuf> {
         (0..num).map(|_| path.clone()).collect()
     }
 
+    #[test]
+    fn shortcuts() {
+        let disks = |n: usize| (0..n).map(|i| format!("/dev/ada{}", i)).collect::<Vec<_>>();
+        let paths = |n: usize| disks(n).into_iter().map(PathBuf::from).collect::<Vec<_>>();
+
+        assert_eq!(
+            CreateVdevRequest::Mirror(paths(2)),
+            CreateVdevRequest::mirror(disks(2))
+        );
+        assert_eq!(
+            CreateVdevRequest::RaidZ(paths(3)),
+            CreateVdevRequest::raidz(disks(3))
+        );
+        assert_eq!(
+            CreateVdevRequest::RaidZ2(paths(5)),
+            CreateVdevRequest::raidz2(disks(5))
+        );
+        assert_eq!(
+            CreateVdevRequest::RaidZ3(paths(8)),
+            CreateVdevRequest::raidz3(disks(8))
+        );
+        assert_eq!(
+            CreateVdevRequest::disk("/dev/ada0"),
+            CreateVdevRequest::from(PathBuf::from("/dev/ada0"))
+        );
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "not enough disks for Mirror")]
+    fn shortcut_checks_disk_count() {
+        CreateVdevRequest::mirror(vec!["/dev/ada0"]);
+    }
+
     #[test]
     fn test_raid_validation_naked() {
         let tmp_dir = TempDir::new("zpool-tests").unwrap();
@@ -553,4 +1002,128 @@ mod test {
 
         assert_ne!(left, right);
     }
+
+    #[test]
+    fn test_strip_partition_suffix() {
+        let cases = [
+            (
+                "/dev/disk/by-id/ata-WDC_WD40EFRX-68N32N0_WD-WCC7K4ZS4VT3-part1",
+                "/dev/disk/by-id/ata-WDC_WD40EFRX-68N32N0_WD-WCC7K4ZS4VT3",
+            ),
+            ("/dev/sda1", "/dev/sda"),
+            ("/dev/xvdb9", "/dev/xvdb"),
+            ("/dev/nvme0n1p1", "/dev/nvme0n1"),
+            ("/dev/nvme0n1", "/dev/nvme0n1"),
+            ("/dev/sda", "/dev/sda"),
+            ("/dev/ada0p3", "/dev/ada0p3"),
+            ("/vdevs/vdev0", "/vdevs/vdev0"),
+            ("/vdevs/sda1", "/vdevs/sda1"),
+            ("sdb2", "sdb"),
+        ];
+        for (path, expected) in cases.iter() {
+            assert_eq!(
+                PathBuf::from(expected),
+                strip_partition_suffix(Path::new(path)),
+                "{}",
+                path
+            );
+        }
+    }
+
+    #[test]
+    fn test_canonical_path() {
+        let tmp_dir = TempDir::new("zpool-tests").unwrap();
+        let file_path = tmp_dir.path().join("vdev0");
+        let _valid_file = File::create(file_path.clone()).unwrap();
+        let link_path = tmp_dir.path().join("alias-part1");
+        std::os::unix::fs::symlink(&file_path, &link_path).unwrap();
+
+        let disk = Disk::builder()
+            .path(link_path)
+            .health(Health::Online)
+            .build()
+            .unwrap();
+        assert_eq!(fs::canonicalize(&file_path).unwrap(), disk.canonical_path());
+
+        let disk = Disk::builder()
+            .path("ada0p3")
+            .health(Health::Online)
+            .build()
+            .unwrap();
+        assert_eq!(PathBuf::from("/dev/ada0p3"), disk.canonical_path());
+    }
+
+    #[test]
+    fn test_vdev_matches_request() {
+        let disks = vec![
+            Disk::builder()
+                .path("/dev/disk/by-id/ata-disk0-part1")
+                .health(Health::Online)
+                .build()
+                .unwrap(),
+            Disk::builder()
+                .path("/dev/disk/by-id/ata-disk1-part1")
+                .health(Health::Online)
+                .build()
+                .unwrap(),
+        ];
+        let vdev = Vdev::builder()
+            .kind(VdevType::Mirror)
+            .health(Health::Online)
+            .disks(disks)
+            .build()
+            .unwrap();
+
+        let request = CreateVdevRequest::Mirror(vec![
+            PathBuf::from("/dev/disk/by-id/ata-disk0"),
+            PathBuf::from("/dev/disk/by-id/ata-disk1"),
+        ]);
+        assert_ne!(vdev, request);
+        assert!(!vdev.matches_request(&request, MatchStrategy::Exact));
+        assert!(vdev.matches_request(&request, MatchStrategy::Canonicalized));
+        assert!(vdev.matches_request(&request, MatchStrategy::BaseName));
+
+        let request =
+            CreateVdevRequest::Mirror(vec![PathBuf::from("ata-disk0"), PathBuf::from("ata-disk1")]);
+        assert!(!vdev.matches_request(&request, MatchStrategy::Canonicalized));
+        assert!(vdev.matches_request(&request, MatchStrategy::BaseName));
+
+        let request = CreateVdevRequest::RaidZ(vec![
+            PathBuf::from("/dev/disk/by-id/ata-disk0"),
+            PathBuf::from("/dev/disk/by-id/ata-disk1"),
+        ]);
+        assert!(!vdev.matches_request(&request, MatchStrategy::BaseName));
+
+        let request = CreateVdevRequest::Mirror(vec![PathBuf::from("/dev/disk/by-id/ata-disk0")]);
+        assert!(!vdev.matches_request(&request, MatchStrategy::BaseName));
+    }
+
+    #[test]
+    fn device_args() {
+        let disk = Disk::builder()
+            .path("/vdevs/vdev0")
+            .health(Health::Online)
+            .build()
+            .unwrap();
+        assert_eq!(OsString::from("/vdevs/vdev0"), disk.as_device_arg());
+        assert_eq!(OsString::from("/vdevs/vdev0"), (&disk).as_device_arg());
+
+        let missing = Disk::builder()
+            .path("/vdevs/vdev1")
+            .guid(Some(14808325297596192025))
+            .was(Some(PathBuf::from("/vdevs/vdev1")))
+            .health(Health::Unavailable)
+            .build()
+            .unwrap();
+        assert_eq!(
+            OsString::from("14808325297596192025"),
+            missing.as_device_arg()
+        );
+        assert_eq!(OsString::from("42"), 42u64.as_device_arg());
+        assert_eq!(OsString::from("ada0p3"), "ada0p3".as_device_arg());
+        assert_eq!(
+            OsString::from("/dev/ada0"),
+            PathBuf::from("/dev/ada0").as_device_arg()
+        );
+    }
 }
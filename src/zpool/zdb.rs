@@ -0,0 +1,540 @@
+//! Read-only diagnostics with `zdb`.
+//!
+//! Some questions, like what takes space in a pool or what its on-disk configuration really is,
+//! can only be answered by `zdb`. [`Zdb`](struct.Zdb.html) wraps the invocations that only read:
+//! `zdb -C` (configuration from MOS), `zdb -bb` (block statistics) and `zdb -h` (history).
+//!
+//! **Warning**: `zdb` reads the pool from disks behind the back of the kernel. `-bb` traverses
+//! every block of the pool, which takes hours and a lot of memory on big pools and competes with
+//! regular I/O. Don't run these casually against production pools.
+//!
+//! ### Usage
+//! ```rust,no_run
+//! use libzetta::zpool::zdb::Zdb;
+//!
+//! let zdb = Zdb::default();
+//! let config = zdb.config("tank").unwrap();
+//! let path = config.lookup("vdev_tree/children[0]/path");
+//! println!("first device: {:?}", path.and_then(|path| path.as_str()));
+//! ```
+use std::{
+    env,
+    ffi::{OsStr, OsString},
+    time::Duration,
+};
+
+use chrono::NaiveDateTime;
+use slog::Logger;
+
+use crate::{
+    instrumentation,
+    zfs::ByteSize,
+    zpool::{ZpoolError, ZpoolResult},
+    GlobalLogger,
+};
+
+/// Value in configuration printed by `zdb -C`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConfigValue {
+    /// Number, e.g. `txg: 4`.
+    Number(u64),
+    /// Quoted text or anything that isn't a number, e.g. `path: '/dev/sda1'`.
+    String(String),
+    /// Key without a value, e.g. a feature under `features_for_read`.
+    Flag,
+    /// Nested list in order it was printed, e.g. `vdev_tree` or `children[0]`.
+    List(Vec<(String, ConfigValue)>),
+}
+
+impl ConfigValue {
+    /// Value of `key` if this is a list that has it.
+    pub fn get(&self, key: &str) -> Option<&ConfigValue> {
+        match self {
+            ConfigValue::List(entries) => entries
+                .iter()
+                .find(|(name, _)| name == key)
+                .map(|(_, value)| value),
+            _ => None,
+        }
+    }
+
+    /// Value at `path` of keys separated by `/`, e.g. `vdev_tree/children[0]/path`.
+    pub fn lookup(&self, path: &str) -> Option<&ConfigValue> {
+        path.split('/')
+            .filter(|key| !key.is_empty())
+            .try_fold(self, |value, key| value.get(key))
+    }
+
+    /// Number if this is one.
+    pub fn as_u64(&self) -> Option<u64> {
+        match self {
+            ConfigValue::Number(number) => Some(*number),
+            _ => None,
+        }
+    }
+
+    /// Text if this is one.
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            ConfigValue::String(text) => Some(text),
+            _ => None,
+        }
+    }
+}
+
+/// Totals of one block type from `zdb -bb`, e.g. `DMU dnode`.
+#[derive(Debug, Clone, Getters, PartialEq, Eq)]
+#[get = "pub"]
+pub struct BlockTypeStatistics {
+    /// Name of the type as printed by `zdb`.
+    kind: String,
+    /// Number of blocks.
+    blocks: u64,
+    /// Size before compression. Sizes are rounded by `zdb`.
+    logical: ByteSize,
+    /// Size after compression.
+    physical: ByteSize,
+    /// Space allocated for them, including redundancy.
+    allocated: ByteSize,
+}
+
+/// Number of blocks of one size from block size histogram of `zdb -bb`.
+#[derive(Debug, Clone, Getters, PartialEq, Eq)]
+#[get = "pub"]
+pub struct BlockSizeBucket {
+    /// Block size of this bucket.
+    size: ByteSize,
+    /// Blocks with physical size in this bucket.
+    physical: u64,
+    /// Blocks with logical size in this bucket.
+    logical: u64,
+    /// Blocks with allocated size in this bucket.
+    allocated: u64,
+}
+
+/// Summary of `zdb -bb`.
+#[derive(Debug, Clone, Getters, PartialEq, Eq)]
+#[get = "pub"]
+pub struct BlockStatistics {
+    /// Number of block pointers in the pool.
+    blocks: u64,
+    /// Size of all blocks before compression.
+    logical: ByteSize,
+    /// Size of all blocks after compression.
+    physical: ByteSize,
+    /// Space allocated for all blocks, including redundancy.
+    allocated: ByteSize,
+    /// Totals by block type, without the `Total` row and types that have no blocks.
+    by_type: Vec<BlockTypeStatistics>,
+    /// Block size histogram. Empty if `zdb` doesn't print it.
+    by_size: Vec<BlockSizeBucket>,
+}
+
+/// Entry of pool history from `zdb -h`.
+#[derive(Debug, Clone, Getters, PartialEq, Eq)]
+#[get = "pub"]
+pub struct HistoryEntry {
+    /// When the command was run, local time of the host that ran it.
+    time: NaiveDateTime,
+    /// Command line, e.g. `zfs create tank/home`.
+    command: String,
+}
+
+/// Runs `zdb`. You can use `Zdb::default` to create it.
+pub struct Zdb {
+    cmd_name: OsString,
+    logger: Logger,
+    command_timeout: Option<Duration>,
+}
+
+impl Default for Zdb {
+    /// Tries to use `ZDB_CMD` from environment if variable is missing then it uses `zdb` from
+    /// `$PATH`.
+    fn default() -> Zdb {
+        let cmd_name = match env::var_os("ZDB_CMD") {
+            Some(val) => val,
+            None => "zdb".into(),
+        };
+        let logger = GlobalLogger::get().new(o!("zetta_module" => "zpool", "zpool_impl" => "zdb"));
+        Zdb {
+            cmd_name,
+            logger,
+            command_timeout: None,
+        }
+    }
+}
+
+impl Zdb {
+    /// Create new using supplied path as zdb cmd.
+    pub fn with_cmd<I: Into<OsString>>(cmd_name: I) -> Zdb {
+        Zdb {
+            cmd_name: cmd_name.into(),
+            ..Zdb::default()
+        }
+    }
+
+    /// Kill `zdb` if it doesn't exit within `timeout` and fail with `ZpoolError::TimedOut`.
+    /// `None`, the default, waits forever.
+    pub fn set_command_timeout(&mut self, timeout: Option<Duration>) {
+        self.command_timeout = timeout;
+    }
+
+    /// Configuration of the pool as stored in MOS (`zdb -C <pool>`).
+    pub fn config<N: AsRef<str>>(&self, pool: N) -> ZpoolResult<ConfigValue> {
+        let stdout = self.run(&["-C".as_ref(), pool.as_ref().as_ref()])?;
+        parse_config(&stdout)
+    }
+
+    /// Block statistics of the pool (`zdb -bb <pool>`). Traverses the whole pool, see the
+    /// warning in [module docs](index.html).
+    pub fn block_statistics<N: AsRef<str>>(&self, pool: N) -> ZpoolResult<BlockStatistics> {
+        let stdout = self.run(&["-bb".as_ref(), pool.as_ref().as_ref()])?;
+        parse_block_statistics(&stdout)
+    }
+
+    /// Commands that changed the pool, oldest first (`zdb -h <pool>`).
+    pub fn history<N: AsRef<str>>(&self, pool: N) -> ZpoolResult<Vec<HistoryEntry>> {
+        let stdout = self.run(&["-h".as_ref(), pool.as_ref().as_ref()])?;
+        Ok(parse_history(&stdout))
+    }
+
+    fn run(&self, args: &[&OsStr]) -> ZpoolResult<String> {
+        let mut z = instrumentation::isolated_command(&self.cmd_name);
+        z.args(args);
+        debug!(self.logger, "executing"; "cmd" => format_args!("{:?}", z));
+        let out =
+            instrumentation::run_command_within(&self.logger, None, &mut z, self.command_timeout)?;
+        if out.status.success() {
+            Ok(String::from_utf8_lossy(&out.stdout).into_owned())
+        } else {
+            let stderr = String::from_utf8_lossy(&out.stderr);
+            if stderr.contains("can't open") && stderr.contains("No such file or directory") {
+                Err(ZpoolError::PoolNotFound)
+            } else {
+                Err(ZpoolError::from_stderr(&out.stderr))
+            }
+        }
+    }
+}
+
+/// Parse indented output of `zdb -C`. Output of a single pool is wrapped into
+/// `MOS Configuration:`, which is removed.
+pub(crate) fn parse_config(text: &str) -> ZpoolResult<ConfigValue> {
+    let lines: Vec<(usize, &str)> = text
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| (line.len() - line.trim_start().len(), line.trim()))
+        .collect();
+    let mut position = 0;
+    let indent = lines.first().map_or(0, |(indent, _)| *indent);
+    let mut entries = parse_config_list(&lines, &mut position, indent)?;
+    if position != lines.len() {
+        return Err(ZpoolError::ParseError);
+    }
+    if entries.len() == 1 && entries[0].0 == "MOS Configuration" {
+        return Ok(entries.remove(0).1);
+    }
+    Ok(ConfigValue::List(entries))
+}
+
+fn parse_config_list(
+    lines: &[(usize, &str)],
+    position: &mut usize,
+    indent: usize,
+) -> ZpoolResult<Vec<(String, ConfigValue)>> {
+    let mut entries = Vec::new();
+    while let Some((line_indent, line)) = lines.get(*position) {
+        if *line_indent < indent {
+            break;
+        }
+        if *line_indent > indent {
+            return Err(ZpoolError::ParseError);
+        }
+        *position += 1;
+        // Keys can have `:` in them (`com.delphix:hole_birth`), values are after `: `.
+        let entry = if let Some((key, value)) = line.split_once(": ") {
+            (String::from(key), config_scalar(value.trim()))
+        } else if let Some(key) = line.strip_suffix(':') {
+            let nested = match lines.get(*position) {
+                Some((nested_indent, _)) if *nested_indent > indent => {
+                    parse_config_list(lines, position, *nested_indent)?
+                }
+                _ => Vec::new(),
+            };
+            (String::from(key), ConfigValue::List(nested))
+        } else {
+            (String::from(*line), ConfigValue::Flag)
+        };
+        entries.push(entry);
+    }
+    Ok(entries)
+}
+
+fn config_scalar(value: &str) -> ConfigValue {
+    if let Some(text) = value
+        .strip_prefix('\'')
+        .and_then(|value| value.strip_suffix('\''))
+    {
+        return ConfigValue::String(String::from(text));
+    }
+    value
+        .parse()
+        .map(ConfigValue::Number)
+        .unwrap_or_else(|_| ConfigValue::String(String::from(value)))
+}
+
+/// Parse output of `zdb -bb`.
+pub(crate) fn parse_block_statistics(text: &str) -> ZpoolResult<BlockStatistics> {
+    let summary = |label: &str| -> ZpoolResult<u64> {
+        text.lines()
+            .find_map(|line| line.trim().strip_prefix(label))
+            .and_then(|rest| rest.split_whitespace().next())
+            .and_then(|value| value.parse().ok())
+            .ok_or(ZpoolError::ParseError)
+    };
+    let mut by_type = Vec::new();
+    let mut by_size = Vec::new();
+    for line in text.lines() {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        if words.len() >= 8 {
+            if let Some(row) = block_type_row(&words) {
+                if row.kind != "Total" {
+                    by_type.push(row);
+                }
+                continue;
+            }
+        }
+        if words.len() == 10 {
+            if let Some(bucket) = block_size_row(&words) {
+                by_size.push(bucket);
+            }
+        }
+    }
+    Ok(BlockStatistics {
+        blocks: summary("bp count:")?,
+        logical: ByteSize::from(summary("bp logical:")?),
+        physical: ByteSize::from(summary("bp physical:")?),
+        allocated: ByteSize::from(summary("bp allocated:")?),
+        by_type,
+        by_size,
+    })
+}
+
+/// `Blocks LSIZE PSIZE ASIZE avg comp %Total Type` row. Type is the rest of the line.
+fn block_type_row(words: &[&str]) -> Option<BlockTypeStatistics> {
+    let blocks = words[0].parse().ok()?;
+    let size = |word: &str| word.parse::<ByteSize>().ok();
+    // `comp` and `%Total` are numbers, this tells rows from other lines ending with text.
+    words[5].parse::<f64>().ok()?;
+    words[6].parse::<f64>().ok()?;
+    Some(BlockTypeStatistics {
+        kind: words[7..].join(" "),
+        blocks,
+        logical: size(words[1])?,
+        physical: size(words[2])?,
+        allocated: size(words[3])?,
+    })
+}
+
+/// `size: Count Size Cum.` row of histogram for physical, logical and allocated sizes.
+fn block_size_row(words: &[&str]) -> Option<BlockSizeBucket> {
+    let size = words[0].strip_suffix(':')?.parse().ok()?;
+    let count = |word: &str| word.parse::<u64>().ok();
+    Some(BlockSizeBucket {
+        size,
+        physical: count(words[1])?,
+        logical: count(words[4])?,
+        allocated: count(words[7])?,
+    })
+}
+
+/// Parse output of `zdb -h`. Lines that aren't `<time> <command>` are skipped.
+pub(crate) fn parse_history(text: &str) -> Vec<HistoryEntry> {
+    text.lines()
+        .filter_map(|line| {
+            let (time, command) = line.split_once(' ')?;
+            let time = NaiveDateTime::parse_from_str(time, "%Y-%m-%d.%H:%M:%S").ok()?;
+            Some(HistoryEntry {
+                time,
+                command: String::from(command.trim()),
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::utils::shim;
+
+    #[test]
+    fn config_linux() {
+        let config = parse_config(include_str!("fixtures/zdb_config_linux")).unwrap();
+        assert_eq!(
+            Some("tank"),
+            config.get("name").and_then(ConfigValue::as_str)
+        );
+        assert_eq!(Some(4), config.get("txg").and_then(ConfigValue::as_u64));
+        assert_eq!(
+            Some(&ConfigValue::Flag),
+            config.get("com.delphix:has_per_vdev_zaps")
+        );
+        assert_eq!(
+            Some(12),
+            config
+                .lookup("vdev_tree/children[0]/ashift")
+                .and_then(ConfigValue::as_u64)
+        );
+        assert_eq!(
+            Some("/dev/disk/by-id/wwn-0x5000c500deadbeef-part1"),
+            config
+                .lookup("vdev_tree/children[0]/children[1]/path")
+                .and_then(ConfigValue::as_str)
+        );
+        assert_eq!(
+            Some(130),
+            config
+                .lookup("vdev_tree/children[0]/children[0]/com.delphix:vdev_zap_leaf")
+                .and_then(ConfigValue::as_u64)
+        );
+        let features = vec![
+            (String::from("com.delphix:hole_birth"), ConfigValue::Flag),
+            (String::from("com.delphix:embedded_data"), ConfigValue::Flag),
+        ];
+        assert_eq!(
+            Some(&ConfigValue::List(features)),
+            config.get("features_for_read")
+        );
+        assert_eq!(None, config.lookup("vdev_tree/children[1]"));
+    }
+
+    #[test]
+    fn config_freebsd() {
+        let config = parse_config(include_str!("fixtures/zdb_config_freebsd")).unwrap();
+        assert_eq!(
+            Some("zroot"),
+            config.get("name").and_then(ConfigValue::as_str)
+        );
+        assert_eq!(
+            Some("/dev/gpt/zfs-data-0"),
+            config
+                .lookup("vdev_tree/children[0]/path")
+                .and_then(ConfigValue::as_str)
+        );
+        assert_eq!(
+            Some("id1,enc@n3061686369656d30/type@0/slot@1/elmdesc@Slot_00/p3"),
+            config
+                .lookup("/vdev_tree/children[0]/phys_path")
+                .and_then(ConfigValue::as_str)
+        );
+        assert_eq!(
+            Some(11_924_539_129_862_402_617),
+            config
+                .lookup("vdev_tree/children[0]/guid")
+                .and_then(ConfigValue::as_u64)
+        );
+    }
+
+    #[test]
+    fn config_of_unexpected_shape() {
+        assert_eq!(
+            Ok(ConfigValue::List(Vec::new())),
+            parse_config("\n").map_err(|e| e.kind())
+        );
+        let misindented = "MOS Configuration:\n        version: 5000\n            name: 'tank'\n";
+        assert_eq!(
+            ZpoolError::ParseError.kind(),
+            parse_config(misindented).unwrap_err().kind()
+        );
+    }
+
+    #[test]
+    fn blocks_linux() {
+        let stats = parse_block_statistics(include_str!("fixtures/zdb_blocks_linux")).unwrap();
+        assert_eq!(&46, stats.blocks());
+        assert_eq!(&ByteSize::from(545_792), stats.logical());
+        assert_eq!(&ByteSize::from(69_120), stats.physical());
+        assert_eq!(&ByteSize::from(165_888), stats.allocated());
+        let kinds: Vec<&str> = stats
+            .by_type()
+            .iter()
+            .map(|row| row.kind().as_str())
+            .collect();
+        assert_eq!(
+            vec![
+                "object directory",
+                "object array",
+                "DMU dnode",
+                "ZFS directory"
+            ],
+            kinds
+        );
+        let dnode = BlockTypeStatistics {
+            kind: String::from("DMU dnode"),
+            blocks: 14,
+            logical: ByteSize::from(224 * 1024),
+            physical: "33.5K".parse().unwrap(),
+            allocated: ByteSize::from(100 * 1024),
+        };
+        assert_eq!(dnode, stats.by_type()[2]);
+
+        assert_eq!(5, stats.by_size().len());
+        let bucket = BlockSizeBucket {
+            size: ByteSize::from(16 * 1024),
+            physical: 0,
+            logical: 28,
+            allocated: 0,
+        };
+        assert_eq!(bucket, stats.by_size()[4]);
+        assert_eq!(&ByteSize::from(512), stats.by_size()[0].size());
+    }
+
+    #[test]
+    fn blocks_freebsd() {
+        let stats = parse_block_statistics(include_str!("fixtures/zdb_blocks_freebsd")).unwrap();
+        assert_eq!(&89230, stats.blocks());
+        assert_eq!(&ByteSize::from(2_835_496_960), stats.allocated());
+        assert_eq!(3, stats.by_type().len());
+        assert_eq!("ZFS plain file", stats.by_type()[2].kind());
+        assert_eq!(&71236, stats.by_type()[2].blocks());
+        assert!(stats.by_size().is_empty());
+
+        let err = parse_block_statistics("Traversing all blocks ...\n").unwrap_err();
+        assert_eq!(ZpoolError::ParseError.kind(), err.kind());
+    }
+
+    #[test]
+    fn history() {
+        let history = parse_history(include_str!("fixtures/zdb_history"));
+        assert_eq!(3, history.len());
+        assert_eq!(
+            "zfs create -o compression=lz4 tank/home",
+            history[1].command()
+        );
+        assert_eq!(
+            &NaiveDateTime::parse_from_str("2021-01-17 03:00:01", "%Y-%m-%d %H:%M:%S").unwrap(),
+            history[2].time()
+        );
+    }
+
+    #[test]
+    fn zdb_through_shim() {
+        let dir = tempfile::tempdir().unwrap();
+        let fixtures = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("src/zpool/fixtures");
+        let shim = shim(dir.path(), "zdb", &format!(
+"case \"$1:$2\" in\n  -C:tank) cat {};;\n  -h:tank) cat {};;\n  *) echo \"zdb: can't open '$2': No such file or directory\" >&2; exit 1;;\nesac\n",
+fixtures.join("zdb_config_linux").display(),
+            fixtures.join("zdb_history").display()
+));
+
+        let zdb = Zdb::with_cmd(shim.as_os_str());
+        let config = zdb.config("tank").unwrap();
+        assert_eq!(
+            Some(5000),
+            config.get("version").and_then(ConfigValue::as_u64)
+        );
+        assert_eq!(3, zdb.history("tank").unwrap().len());
+        let err = zdb.config("missing").unwrap_err();
+        assert_eq!(ZpoolError::PoolNotFound.kind(), err.kind());
+    }
+}
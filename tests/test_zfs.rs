@@ -15,14 +15,23 @@ use rand::Rng;
 use libzetta::{
     slog::*,
     zfs::{
-        BookmarkRequest, Copies, CreateDatasetRequest, DatasetKind, Error, Properties, SendFlags,
-        SnapDir, ZfsEngine, ZfsLzc,
+        properties::{
+            AclInheritMode, CanMount, CaseSensitivity, Dedup, LogBias, Normalization,
+            RedundantMetadata, SyncMode,
+        },
+        BookmarkRequest, ByteSize, Checksum, Compression, Copies, CreateDatasetRequest,
+        CreateDatasetRequestBuilder, DatasetKind, Error, ErrorKind, FilesystemProperties,
+        Properties, ReceiveOptions, RenameOptions, ReplicationSendOptions, SendFlags, ShareOptions,
+        SnapDir, ValidationError, ZfsEngine, ZfsLzc, ZfsOpen3,
     },
     zpool::{CreateVdevRequest, CreateZpoolRequest, ZpoolEngine, ZpoolOpen3},
 };
 
 use libzetta::{
-    zfs::{DelegatingZfsEngine, DestroyTiming},
+    zfs::{
+        be, replication, DelegatingZfsEngine, DelegationGrant, DelegationScope, DestroyOutcome,
+        DestroyTiming, Permission, Principal,
+    },
     zpool::CreateMode,
 };
 
@@ -269,6 +278,289 @@ fn create_and_list() {
     assert_eq!(expected, datasets);
 }
 
+#[test]
+fn rename_with_children() {
+    let zpool = SHARED_ZPOOL.clone();
+    let zfs = DelegatingZfsEngine::new().expect("Failed to initialize ZfsLzc");
+    let root = PathBuf::from(format!("{}/{}", zpool, get_dataset_name()));
+    let a = root.join("a");
+    let b = a.join("b");
+    let c = b.join("c");
+    for path in [&root, &a, &b, &c].iter() {
+        let request = CreateDatasetRequest::builder()
+            .name(PathBuf::from(path))
+            .kind(DatasetKind::Filesystem)
+            .build()
+            .unwrap();
+        zfs.create(request).expect("Failed to create a dataset");
+    }
+
+    let x = root.join("x");
+    let outcome = zfs
+        .rename(a.clone(), x.clone(), RenameOptions::default())
+        .unwrap();
+    let expected = vec![
+        (a.clone(), x.clone()),
+        (b.clone(), x.join("b")),
+        (c.clone(), x.join("b/c")),
+    ];
+    assert_eq!(&expected, outcome.renamed());
+    assert!(zfs.exists(x.join("b/c")).unwrap());
+    assert!(!zfs.exists(a.clone()).unwrap());
+
+    let orphan = root.join("missing/parent");
+    let err = zfs
+        .rename(x.clone(), orphan.clone(), RenameOptions::default())
+        .unwrap_err();
+    assert_eq!(
+        Error::from(ValidationError::ParentNotFound(orphan.clone())),
+        err
+    );
+
+    let opts = RenameOptions::builder()
+        .create_parents(true)
+        .build()
+        .unwrap();
+    let outcome = zfs.rename(x.clone(), orphan.clone(), opts).unwrap();
+    assert_eq!(
+        Some(&orphan.join("b/c")),
+        outcome.new_name_of(x.join("b/c"))
+    );
+    assert!(zfs.exists(orphan.join("b/c")).unwrap());
+}
+
+#[test]
+fn rename_without_remount() {
+    let zpool = SHARED_ZPOOL.clone();
+    let zfs = DelegatingZfsEngine::new().expect("Failed to initialize ZfsLzc");
+    let root = PathBuf::from(format!("{}/{}", zpool, get_dataset_name()));
+    let a = root.join("a");
+    for path in [&root, &a].iter() {
+        let request = CreateDatasetRequest::builder()
+            .name(PathBuf::from(path))
+            .kind(DatasetKind::Filesystem)
+            .build()
+            .unwrap();
+        zfs.create(request).expect("Failed to create a dataset");
+    }
+    let old_mount_point = zfs.mount_point_of(a.clone()).unwrap().unwrap();
+
+    let b = root.join("b");
+    let opts = RenameOptions::builder().no_remount(true).build().unwrap();
+    match zfs.rename(a.clone(), b.clone(), opts) {
+        Ok(_) => assert_eq!(
+            Some(old_mount_point),
+            zfs.mount_point_of(b.clone()).unwrap()
+        ),
+        Err(e) => {
+            assert_eq!(ErrorKind::Unsupported, e.kind());
+            return;
+        }
+    }
+
+    let c = root.join("c");
+    zfs.rename(b.clone(), c.clone(), RenameOptions::default())
+        .unwrap();
+    let new_mount_point = zfs.mount_point_of(c.clone()).unwrap().unwrap();
+    assert!(new_mount_point.ends_with("c"));
+}
+
+#[test]
+fn snapshot_across_pools() {
+    let zpool = SHARED_ZPOOL.clone();
+    let zfs = DelegatingZfsEngine::new().expect("Failed to initialize ZfsLzc");
+    let root = PathBuf::from(format!("{}/{}", zpool, get_dataset_name()));
+    let other = PathBuf::from(format!("{}-other/{}", zpool, get_dataset_name()));
+    let snapshots = vec![
+        PathBuf::from(format!("{}@snap", root.display())),
+        PathBuf::from(format!("{}@snap", other.display())),
+    ];
+
+    let err = zfs.snapshot(&snapshots, None).unwrap_err();
+    assert_eq!(
+        Error::ValidationErrors(vec![ValidationError::CrossPoolOperation(vec![snapshots
+            [1]
+        .clone()])]),
+        err
+    );
+}
+
+#[test]
+fn snapshot_partial_failure() {
+    let zpool = SHARED_ZPOOL.clone();
+    let zfs = DelegatingZfsEngine::new().expect("Failed to initialize ZfsLzc");
+    let root_name = get_dataset_name();
+    let root = PathBuf::from(format!("{}/{}", zpool, &root_name));
+    let request = CreateDatasetRequest::builder()
+        .name(root.clone())
+        .kind(DatasetKind::Filesystem)
+        .build()
+        .unwrap();
+    zfs.create(request)
+        .expect("Failed to create a root dataset");
+    let child = root.join("child");
+    let request = CreateDatasetRequest::builder()
+        .name(child.clone())
+        .kind(DatasetKind::Filesystem)
+        .build()
+        .unwrap();
+    zfs.create(request)
+        .expect("Failed to create a child dataset");
+
+    let existing = PathBuf::from(format!("{}@snap", child.display()));
+    zfs.snapshot(&[existing.clone()], None).unwrap();
+
+    // `snapshot_limit` isn't enforced for root, so an existing snapshot is used to fail the batch.
+    let new = PathBuf::from(format!("{}@snap", root.display()));
+    let err = zfs
+        .snapshot(&[new.clone(), existing.clone()], None)
+        .unwrap_err();
+    assert_eq!(Error::SnapshotsFailed(vec![(existing, libc::EEXIST)]), err);
+    // Either all or none are created.
+    assert_eq!(Ok(false), zfs.exists(new));
+}
+
+#[test]
+fn destroy_held_snapshot_is_deferred() {
+    let zpool = SHARED_ZPOOL.clone();
+    let zfs = DelegatingZfsEngine::new().expect("Failed to initialize ZfsLzc");
+    let root_name = get_dataset_name();
+    let root = PathBuf::from(format!("{}/{}", zpool, &root_name));
+    let request = CreateDatasetRequest::builder()
+        .name(root.clone())
+        .kind(DatasetKind::Filesystem)
+        .build()
+        .unwrap();
+    zfs.create(request)
+        .expect("Failed to create a root dataset");
+
+    let held = PathBuf::from(format!("{}@held", root.display()));
+    let free = PathBuf::from(format!("{}@free", root.display()));
+    zfs.snapshot(&[held.clone(), free.clone()], None).unwrap();
+    let hold = vec![(held.clone(), String::from("replication"))];
+    zfs.hold(&hold).unwrap();
+    assert_eq!(
+        Ok(vec![String::from("replication")]),
+        zfs.holds(held.clone())
+    );
+
+    let outcome = zfs
+        .destroy_snapshots_verbose(&[held.clone(), free.clone()], DestroyTiming::Defer)
+        .unwrap();
+    assert_eq!(
+        vec![
+            (
+                held.clone(),
+                DestroyOutcome::Deferred {
+                    holds: vec![String::from("replication")]
+                }
+            ),
+            (free.clone(), DestroyOutcome::Destroyed),
+        ],
+        outcome
+    );
+    assert_eq!(Ok(true), zfs.exists(held.clone()));
+
+    // Releasing the last hold destroys deferred snapshot.
+    zfs.release(&hold).unwrap();
+    assert_eq!(Ok(false), zfs.exists(held));
+}
+
+#[test]
+fn deferred_snapshot_report_names_hold() {
+    let zpool = SHARED_ZPOOL.clone();
+    let zfs = DelegatingZfsEngine::new().expect("Failed to initialize ZfsLzc");
+    let root = PathBuf::from(format!("{}/{}", zpool, get_dataset_name()));
+    let request = CreateDatasetRequest::builder()
+        .name(root.clone())
+        .kind(DatasetKind::Filesystem)
+        .build()
+        .unwrap();
+    zfs.create(request)
+        .expect("Failed to create a root dataset");
+
+    let held = PathBuf::from(format!("{}@held", root.display()));
+    zfs.snapshot(&[held.clone()], None).unwrap();
+    assert_eq!(Ok(Vec::new()), zfs.deferred_snapshots(root.clone()));
+
+    let hold = vec![(held.clone(), String::from("offsite"))];
+    zfs.hold(&hold).unwrap();
+    zfs.destroy_snapshots(&[held.clone()], DestroyTiming::Defer)
+        .unwrap();
+    assert_eq!(Ok(vec![held.clone()]), zfs.deferred_snapshots(root.clone()));
+
+    let report = zfs.reap_deferred(root.clone()).unwrap();
+    assert_eq!(1, report.len());
+    assert_eq!(&held, report[0].snapshot());
+    assert_eq!(&vec![String::from("offsite")], report[0].holds());
+    assert!(report[0].clones().is_empty());
+
+    zfs.release(&hold).unwrap();
+    assert_eq!(Ok(Vec::new()), zfs.reap_deferred(root));
+}
+
+#[test]
+fn exists_all_over_tree() {
+    let zpool = SHARED_ZPOOL.clone();
+    let zfs = DelegatingZfsEngine::new().expect("Failed to initialize ZfsLzc");
+    let open3 = ZfsOpen3::new();
+    let root = PathBuf::from(format!("{}/{}", zpool, get_dataset_name()));
+    let data = root.join("data");
+    for name in &[&root, &data] {
+        let request = CreateDatasetRequest::builder()
+            .name(PathBuf::from(name))
+            .kind(DatasetKind::Filesystem)
+            .build()
+            .unwrap();
+        zfs.create(request).expect("Failed to create a dataset");
+    }
+    let snapshot = PathBuf::from(format!("{}@snap", data.display()));
+    zfs.snapshot(&[snapshot.clone()], None).unwrap();
+
+    let names = vec![
+        data.clone(),
+        root.join("database"),
+        snapshot.clone(),
+        data.clone(),
+        PathBuf::from("nonexistent-pool/data"),
+    ];
+    let expected = vec![
+        (data.clone(), true),
+        (root.join("database"), false),
+        (snapshot, true),
+        (data, true),
+        (PathBuf::from("nonexistent-pool/data"), false),
+    ];
+    assert_eq!(Ok(expected.clone()), open3.exists_all(names.clone()));
+    assert_eq!(Ok(expected), zfs.exists_all(names));
+}
+
+#[test]
+fn share_over_nfs() {
+    let zpool = SHARED_ZPOOL.clone();
+    let zfs = DelegatingZfsEngine::new().expect("Failed to initialize ZfsLzc");
+    let root = PathBuf::from(format!("{}/{}", zpool, get_dataset_name()));
+    let request = CreateDatasetRequest::builder()
+        .name(root.clone())
+        .kind(DatasetKind::Filesystem)
+        .build()
+        .unwrap();
+    zfs.create(request)
+        .expect("Failed to create a root dataset");
+
+    zfs.set_share_nfs(root.clone(), ShareOptions::On).unwrap();
+    zfs.share(root.clone()).unwrap();
+    match zfs.read_properties(root.clone()).unwrap() {
+        Properties::Filesystem(properties) => {
+            assert_eq!(&ShareOptions::On, properties.share_nfs());
+            assert_eq!(&ShareOptions::Off, properties.share_smb());
+        }
+        other => panic!("Expected filesystem properties, got {:?}", other),
+    }
+    zfs.unshare(root.clone()).unwrap();
+    zfs.set_share_nfs(root, ShareOptions::Off).unwrap();
+}
+
 #[test]
 fn easy_snapshot_and_bookmark() {
     let zpool = SHARED_ZPOOL.clone();
@@ -317,6 +609,94 @@ fn easy_snapshot_and_bookmark() {
     assert!(bookmarks.is_empty())
 }
 
+#[test]
+fn destroy_snapshots_reports_failed_snapshot() {
+    let zpool = SHARED_ZPOOL.clone();
+    let zfs = DelegatingZfsEngine::new().expect("Failed to initialize ZfsLzc");
+    let root_name = get_dataset_name();
+    let root = PathBuf::from(format!("{}/{}", zpool, &root_name));
+    let request = CreateDatasetRequest::builder()
+        .name(root.clone())
+        .kind(DatasetKind::Filesystem)
+        .build()
+        .unwrap();
+    zfs.create(request)
+        .expect("Failed to create a root dataset");
+    let snapshots = vec![
+        PathBuf::from(format!("{}/{}@snap-1", zpool, &root_name)),
+        PathBuf::from(format!("{}/{}@snap-2", zpool, &root_name)),
+    ];
+    zfs.snapshot(&snapshots, None)
+        .expect("Failed to create snapshots");
+
+    let status = std::process::Command::new("zfs")
+        .arg("hold")
+        .arg("keep")
+        .arg(&snapshots[1])
+        .status()
+        .expect("Failed to hold snapshot");
+    assert!(status.success());
+
+    let result = zfs.destroy_snapshots(&snapshots, DestroyTiming::RightNow);
+    let expected = Error::DestroySnapshotsFailed(vec![(snapshots[1].clone(), libc::EBUSY)]);
+    assert_eq!(Err(expected), result);
+    assert_eq!(Ok(true), zfs.exists(snapshots[0].clone()));
+
+    let status = std::process::Command::new("zfs")
+        .arg("release")
+        .arg("keep")
+        .arg(&snapshots[1])
+        .status()
+        .expect("Failed to release snapshot");
+    assert!(status.success());
+    zfs.destroy_snapshots(&snapshots, DestroyTiming::RightNow)
+        .unwrap();
+}
+
+#[test]
+fn destroy_snapshot_recursive_over_tree() {
+    let zpool = SHARED_ZPOOL.clone();
+    let zfs = DelegatingZfsEngine::new().expect("Failed to initialize ZfsLzc");
+    let root = PathBuf::from(format!("{}/{}", zpool, get_dataset_name()));
+    let tree = vec![root.clone(), root.join("a"), root.join("a/b")];
+    for dataset in &tree {
+        let request = CreateDatasetRequest::builder()
+            .name(dataset.clone())
+            .kind(DatasetKind::Filesystem)
+            .build()
+            .unwrap();
+        zfs.create(request).expect("Failed to create a dataset");
+    }
+    let snapshots: Vec<PathBuf> = tree
+        .iter()
+        .map(|dataset| PathBuf::from(format!("{}@daily", dataset.display())))
+        .collect();
+    zfs.snapshot(&snapshots, None)
+        .expect("Failed to create snapshots");
+    let kept = PathBuf::from(format!("{}@weekly", root.display()));
+    zfs.snapshot(&[kept.clone()], None)
+        .expect("Failed to create snapshot");
+
+    let result = zfs.destroy_snapshot_recursive(root.clone(), "da@ly");
+    assert_eq!(
+        Some(ErrorKind::ValidationErrors),
+        result.err().map(|e| e.kind())
+    );
+
+    zfs.destroy_snapshot_recursive(root.clone(), "daily")
+        .expect("Failed to destroy snapshots recursively");
+    for snapshot in &snapshots {
+        assert_eq!(Ok(false), zfs.exists(snapshot.clone()));
+    }
+    assert_eq!(Ok(true), zfs.exists(kept));
+    assert_eq!(
+        Some(ErrorKind::DatasetNotFound),
+        zfs.destroy_snapshot_recursive(root, "daily")
+            .err()
+            .map(|e| e.kind())
+    );
+}
+
 #[test]
 fn read_properties_of_filesystem() {
     let zpool = SHARED_ZPOOL.clone();
@@ -340,6 +720,124 @@ fn read_properties_of_filesystem() {
     }
 }
 
+fn create_with_properties<F>(build: F) -> FilesystemProperties
+where
+    F: FnOnce(&mut CreateDatasetRequestBuilder),
+{
+    let zpool = SHARED_ZPOOL.clone();
+    let zfs = DelegatingZfsEngine::new().expect("Failed to initialize ZfsLzc");
+    let root = PathBuf::from(format!("{}/{}", zpool, get_dataset_name()));
+    let mut request = CreateDatasetRequest::builder();
+    request.name(root.clone()).kind(DatasetKind::Filesystem);
+    build(&mut request);
+    zfs.create(request.build().unwrap())
+        .expect("Failed to create a root dataset");
+    match zfs.read_properties(&root).unwrap() {
+        Properties::Filesystem(properties) => properties,
+        _ => panic!("Read not fs properties"),
+    }
+}
+
+#[test]
+fn enum_properties_round_trip() {
+    let checksums = [
+        Checksum::On,
+        Checksum::Off,
+        Checksum::Fletcher2,
+        Checksum::Fletcher4,
+        Checksum::SHA256,
+        Checksum::NoParity,
+        Checksum::SHA512,
+        Checksum::Skein,
+    ];
+    for checksum in checksums.iter() {
+        let properties = create_with_properties(|r| {
+            r.checksum(*checksum);
+        });
+        assert_eq!(checksum, properties.checksum());
+    }
+
+    let compressions = [
+        Compression::On,
+        Compression::Off,
+        Compression::LZJB,
+        Compression::LZ4,
+        Compression::ZLE,
+        Compression::Gzip1,
+        Compression::Gzip2,
+        Compression::Gzip3,
+        Compression::Gzip4,
+        Compression::Gzip5,
+        Compression::Gzip6,
+        Compression::Gzip7,
+        Compression::Gzip8,
+        Compression::Gzip9,
+    ];
+    for compression in compressions.iter() {
+        let properties = create_with_properties(|r| {
+            r.compression(*compression);
+        });
+        assert_eq!(compression, properties.compression());
+    }
+
+    let acl_inherit = [
+        (AclInheritMode::Discard, AclInheritMode::Discard),
+        (AclInheritMode::Noallow, AclInheritMode::Noallow),
+        (AclInheritMode::Restricted, AclInheritMode::Restricted),
+        (AclInheritMode::Secure, AclInheritMode::Restricted),
+        (AclInheritMode::Passthrough, AclInheritMode::Passthrough),
+        (AclInheritMode::PassthroughX, AclInheritMode::PassthroughX),
+    ];
+    for (requested, expected) in acl_inherit.iter() {
+        let properties = create_with_properties(|r| {
+            r.acl_inherit(*requested);
+        });
+        assert_eq!(expected, properties.acl_inherit());
+    }
+}
+
+#[test]
+fn creation_only_properties_round_trip() {
+    let properties = create_with_properties(|r| {
+        r.case_sensitivity(CaseSensitivity::Insensitive)
+            .normalization(Normalization::FormD)
+            .utf8_only(true);
+    });
+    assert_eq!(&CaseSensitivity::Insensitive, properties.case_sensitivity());
+    assert_eq!(&Normalization::FormD, properties.normalization());
+    assert_eq!(&Some(true), properties.utf8_only());
+
+    let normalizations = [
+        Normalization::FormC,
+        Normalization::FormD,
+        Normalization::FormKC,
+        Normalization::FormKD,
+    ];
+    for normalization in normalizations.iter() {
+        let properties = create_with_properties(|r| {
+            r.normalization(*normalization).utf8_only(true);
+        });
+        assert_eq!(normalization, properties.normalization());
+    }
+
+    let properties = create_with_properties(|r| {
+        r.case_sensitivity(CaseSensitivity::Mixed)
+            .normalization(Normalization::None)
+            .utf8_only(false)
+            .sync(SyncMode::Always)
+            .log_bias(LogBias::Throughput)
+            .redundant_metadata(RedundantMetadata::Most)
+            .dedup(Dedup::Verify);
+    });
+    assert_eq!(&CaseSensitivity::Mixed, properties.case_sensitivity());
+    assert_eq!(&Normalization::None, properties.normalization());
+    assert_eq!(&Some(false), properties.utf8_only());
+    assert_eq!(&SyncMode::Always, properties.sync());
+    assert_eq!(&LogBias::Throughput, properties.log_bias());
+    assert_eq!(&RedundantMetadata::Most, properties.redundant_metadata());
+    assert_eq!(&Dedup::Verify, properties.dedup());
+}
+
 #[test]
 #[cfg(target_os = "freebsd")]
 fn read_properties_of_snapshot_and_bookmark_blessed_os() {
@@ -447,6 +945,385 @@ fn read_properties_of_volume() {
         panic!("Read not fs properties");
     }
 }
+#[test]
+fn resize_volume() {
+    let zpool = SHARED_ZPOOL.clone();
+    let zfs = DelegatingZfsEngine::new().expect("Failed to initialize ZfsLzc");
+    let root = PathBuf::from(format!("{}/{}", zpool, get_dataset_name()));
+    let request = CreateDatasetRequest::builder()
+        .name(root.clone())
+        .kind(DatasetKind::Volume)
+        .volume_size(ByteSize::gib(1))
+        .build()
+        .unwrap();
+    zfs.create(request)
+        .expect("Failed to create a root dataset");
+
+    zfs.resize_volume(&root, ByteSize::gib(2), false).unwrap();
+    if let Properties::Volume(properties) = zfs.read_properties(&root).unwrap() {
+        assert_eq!(ByteSize::gib(2), properties.volume_size_bytes());
+    } else {
+        panic!("Read not volume properties");
+    }
+
+    let err = zfs
+        .resize_volume(&root, ByteSize::gib(1), false)
+        .unwrap_err();
+    assert_eq!(ErrorKind::VolumeShrinkRefused, err.kind());
+    if let Error::VolumeShrinkRefused {
+        current, requested, ..
+    } = err
+    {
+        assert_eq!(ByteSize::gib(2), current);
+        assert_eq!(ByteSize::gib(1), requested);
+    }
+
+    zfs.resize_volume(&root, ByteSize::gib(1), true).unwrap();
+    if let Properties::Volume(properties) = zfs.read_properties(&root).unwrap() {
+        assert_eq!(ByteSize::gib(1), properties.volume_size_bytes());
+    } else {
+        panic!("Read not volume properties");
+    }
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn snap_dev_exposes_snapshots() {
+    use libzetta::zfs::{properties::VolumeMode, SnapDev};
+
+    let zpool = SHARED_ZPOOL.clone();
+    let zfs = DelegatingZfsEngine::new().expect("Failed to initialize ZfsLzc");
+    let root = PathBuf::from(format!("{}/{}", zpool, get_dataset_name()));
+    let request = CreateDatasetRequest::builder()
+        .name(root.clone())
+        .kind(DatasetKind::Volume)
+        .volume_size(ByteSize::mib(16))
+        .build()
+        .unwrap();
+    zfs.create(request)
+        .expect("Failed to create a root dataset");
+    let snapshot = PathBuf::from(format!("{}@snap", root.display()));
+    zfs.snapshot(&[snapshot.clone()], None).unwrap();
+
+    zfs.set_snap_dev(&root, SnapDev::Visible).unwrap();
+    if let Properties::Volume(properties) = zfs.read_properties(&root).unwrap() {
+        assert_eq!(&Some(SnapDev::Visible), properties.snap_dev());
+    } else {
+        panic!("Read not volume properties");
+    }
+    // udev creates links asynchronously.
+    let device = Path::new("/dev/zvol").join(&snapshot);
+    for _ in 0..50 {
+        if device.exists() {
+            break;
+        }
+        std::thread::sleep(std::time::Duration::from_millis(100));
+    }
+    assert!(device.exists(), "{} doesn't exist", device.display());
+
+    assert!(zfs.set_volume_mode(&root, VolumeMode::None).unwrap());
+    assert!(!zfs.set_volume_mode(&root, VolumeMode::None).unwrap());
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn wait_for_volume_device() {
+    use libzetta::zfs::properties::VolumeMode;
+    use std::{io::Read, time::Duration};
+
+    let zpool = SHARED_ZPOOL.clone();
+    let zfs = DelegatingZfsEngine::new().expect("Failed to initialize ZfsLzc");
+    let volume = PathBuf::from(format!("{}/{}", zpool, get_dataset_name()));
+    let request = CreateDatasetRequest::builder()
+        .name(volume.clone())
+        .kind(DatasetKind::Volume)
+        .volume_size(ByteSize::mib(16))
+        .build()
+        .unwrap();
+    zfs.create(request).expect("Failed to create a volume");
+
+    let device = zfs
+        .wait_for_volume_device(&volume, Duration::from_secs(30))
+        .unwrap();
+    assert_eq!(Path::new("/dev/zvol").join(&volume), device);
+    let mut buf = [0u8; 512];
+    fs::File::open(&device)
+        .and_then(|mut file| file.read_exact(&mut buf))
+        .expect("Failed to read the volume");
+
+    zfs.set_volume_mode(&volume, VolumeMode::None).unwrap();
+    assert_eq!(
+        Some(ErrorKind::NoDeviceExposed),
+        zfs.wait_for_volume_device(&volume, Duration::from_secs(30))
+            .err()
+            .map(|e| e.kind())
+    );
+    assert_eq!(
+        Some(ErrorKind::ValidationErrors),
+        zfs.volume_device_path(zpool).err().map(|e| e.kind())
+    );
+    let snapshot = PathBuf::from(format!("{}@device", volume.display()));
+    zfs.snapshot(&[snapshot.clone()], None).unwrap();
+    let expected = Error::from(ValidationError::WrongDatasetKind {
+        dataset: snapshot.clone(),
+        expected: DatasetKind::Volume,
+        found: DatasetKind::Snapshot,
+    });
+    assert_eq!(expected, zfs.volume_device_path(&snapshot).unwrap_err());
+
+    zfs.destroy(snapshot).unwrap();
+    zfs.destroy(volume).unwrap();
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn project_quota_accounting() {
+    let zpool = SHARED_ZPOOL.clone();
+    let zfs = DelegatingZfsEngine::new().expect("Failed to initialize ZfsLzc");
+    let root = PathBuf::from(format!("{}/{}", zpool, get_dataset_name()));
+    let request = CreateDatasetRequest::builder()
+        .name(root.clone())
+        .kind(DatasetKind::Filesystem)
+        .build()
+        .unwrap();
+    zfs.create(request).unwrap();
+    let mount_point = zfs.mount_point_of(&root).unwrap().unwrap();
+    let project_dir = mount_point.join("project");
+    fs::create_dir(&project_dir).unwrap();
+    fs::write(project_dir.join("before"), vec![1u8; 4096]).unwrap();
+
+    zfs.set_project(&project_dir, 42, true).unwrap();
+    assert_eq!(42, zfs.get_project(&project_dir).unwrap());
+    assert_eq!(42, zfs.get_project(project_dir.join("before")).unwrap());
+    fs::write(project_dir.join("after"), vec![1u8; 4096]).unwrap();
+    assert_eq!(42, zfs.get_project(project_dir.join("after")).unwrap());
+
+    zfs.set_project_quota(&root, 42, Some(ByteSize::mib(10)))
+        .unwrap();
+    ZpoolOpen3::default().sync(Some(zpool.as_str())).unwrap();
+    let space = zfs.project_space(&root).unwrap();
+    let project = space
+        .iter()
+        .find(|entry| *entry.project_id() == 42)
+        .expect("project 42 is not listed");
+    assert_eq!(&Some(ByteSize::mib(10).as_u64()), project.quota());
+    assert!(*project.used() > 0);
+
+    zfs.set_project_quota(&root, 42, None).unwrap();
+    let space = zfs.project_space(&root).unwrap();
+    let project = space
+        .iter()
+        .find(|entry| *entry.project_id() == 42)
+        .unwrap();
+    assert_eq!(&None, project.quota());
+}
+
+#[test]
+fn restore_file_from_snapshot() {
+    use libzetta::zfs::SnapDir;
+
+    let zpool = SHARED_ZPOOL.clone();
+    let zfs = DelegatingZfsEngine::new().expect("Failed to initialize ZfsLzc");
+    let root = PathBuf::from(format!("{}/{}", zpool, get_dataset_name()));
+    let request = CreateDatasetRequest::builder()
+        .name(root.clone())
+        .kind(DatasetKind::Filesystem)
+        .build()
+        .unwrap();
+    zfs.create(request).unwrap();
+    let mount_point = zfs.mount_point_of(&root).unwrap().unwrap();
+    fs::write(mount_point.join("notes"), "before").unwrap();
+    let snapshot = PathBuf::from(format!("{}@snap", root.display()));
+    zfs.snapshot(&[snapshot.clone()], None).unwrap();
+    fs::write(mount_point.join("notes"), "after").unwrap();
+
+    zfs.with_snapshot_mounted(&snapshot, |snapshot_root| {
+        fs::copy(snapshot_root.join("notes"), mount_point.join("notes")).unwrap();
+    })
+    .unwrap();
+    assert_eq!(
+        "before",
+        fs::read_to_string(mount_point.join("notes")).unwrap()
+    );
+    if let Properties::Filesystem(properties) = zfs.read_properties(&root).unwrap() {
+        assert_eq!(&SnapDir::Hidden, properties.snap_dir());
+    } else {
+        panic!("Read not fs properties");
+    }
+
+    let missing = PathBuf::from(format!("{}@missing", root.display()));
+    let err = zfs.with_snapshot_mounted(&missing, |_| ()).unwrap_err();
+    assert_eq!(ErrorKind::DatasetNotFound, err.kind());
+}
+
+#[test]
+fn kind_of_and_kind_checks() {
+    let zpool = SHARED_ZPOOL.clone();
+    let mut zfs = DelegatingZfsEngine::new().expect("Failed to initialize ZfsLzc");
+    let fs = PathBuf::from(format!("{}/{}", zpool, get_dataset_name()));
+    let vol = PathBuf::from(format!("{}/{}", zpool, get_dataset_name()));
+    zfs.create(
+        CreateDatasetRequest::builder()
+            .name(fs.clone())
+            .kind(DatasetKind::Filesystem)
+            .build()
+            .unwrap(),
+    )
+    .unwrap();
+    zfs.create(
+        CreateDatasetRequest::builder()
+            .name(vol.clone())
+            .kind(DatasetKind::Volume)
+            .volume_size(ByteSize::mib(16))
+            .build()
+            .unwrap(),
+    )
+    .unwrap();
+    let snapshot = PathBuf::from(format!("{}@kind", fs.display()));
+    zfs.snapshot(&[snapshot.clone()], None).unwrap();
+
+    assert_eq!(DatasetKind::Filesystem, zfs.kind_of(&fs).unwrap());
+    assert_eq!(DatasetKind::Volume, zfs.kind_of(&vol).unwrap());
+    assert_eq!(DatasetKind::Snapshot, zfs.kind_of(&snapshot).unwrap());
+
+    let expected = Error::from(ValidationError::WrongDatasetKind {
+        dataset: fs.clone(),
+        expected: DatasetKind::Volume,
+        found: DatasetKind::Filesystem,
+    });
+    assert_eq!(
+        expected,
+        zfs.resize_volume(&fs, ByteSize::mib(32), false)
+            .unwrap_err()
+    );
+    let expected = Error::from(ValidationError::WrongDatasetKind {
+        dataset: vol.clone(),
+        expected: DatasetKind::Filesystem,
+        found: DatasetKind::Volume,
+    });
+    assert_eq!(expected, zfs.unmount(&vol).unwrap_err());
+
+    zfs.set_check_kinds(false);
+    let err = zfs.unmount(&vol).unwrap_err();
+    assert_ne!(ErrorKind::ValidationErrors, err.kind());
+}
+
+#[test]
+fn set_quota_and_refquota() {
+    let zpool = SHARED_ZPOOL.clone();
+    let zfs = DelegatingZfsEngine::new().expect("Failed to initialize ZfsLzc");
+    let root = PathBuf::from(format!("{}/{}", zpool, get_dataset_name()));
+    let request = CreateDatasetRequest::builder()
+        .name(root.clone())
+        .kind(DatasetKind::Filesystem)
+        .build()
+        .unwrap();
+    zfs.create(request)
+        .expect("Failed to create a root dataset");
+
+    zfs.set_quota(&root, Some(ByteSize::mib(10))).unwrap();
+    zfs.set_refquota(&root, Some(ByteSize::mib(5))).unwrap();
+    if let Properties::Filesystem(properties) = zfs.read_properties(&root).unwrap() {
+        assert_eq!(Some(ByteSize::mib(10)), properties.quota_size());
+        assert_eq!(Some(ByteSize::mib(5)), properties.ref_quota_size());
+    } else {
+        panic!("Read not fs properties");
+    }
+
+    zfs.set_quota(&root, None).unwrap();
+    zfs.set_refquota(&root, None).unwrap();
+    if let Properties::Filesystem(properties) = zfs.read_properties(&root).unwrap() {
+        assert_eq!(None, properties.quota_size());
+        assert_eq!(None, properties.ref_quota_size());
+    } else {
+        panic!("Read not fs properties");
+    }
+
+    // Quota below what is already used.
+    let mount_point = zfs.mount_point_of(&root).unwrap().unwrap();
+    fs::write(mount_point.join("data"), vec![1u8; 2 * 1024 * 1024]).unwrap();
+    std::process::Command::new("sync").status().unwrap();
+    let err = zfs.set_quota(&root, Some(ByteSize::kib(512))).unwrap_err();
+    assert_eq!(ErrorKind::SizeBelowUsage, err.kind());
+}
+
+#[test]
+fn allow_and_unallow() {
+    let zpool = SHARED_ZPOOL.clone();
+    let zfs = DelegatingZfsEngine::new().expect("Failed to initialize ZfsLzc");
+    let root = PathBuf::from(format!("{}/{}", zpool, get_dataset_name()));
+    let request = CreateDatasetRequest::builder()
+        .name(root.clone())
+        .kind(DatasetKind::Filesystem)
+        .build()
+        .unwrap();
+    zfs.create(request)
+        .expect("Failed to create a root dataset");
+
+    let nobody = Principal::User(String::from("nobody"));
+    let grant = DelegationGrant::builder()
+        .principal(nobody.clone())
+        .permissions(vec![Permission::Snapshot, Permission::Send])
+        .scope(DelegationScope::Local)
+        .build()
+        .unwrap();
+    zfs.allow(&root, grant.clone()).unwrap();
+
+    let delegations = zfs.read_delegations(&root).unwrap();
+    let delegation = delegations
+        .iter()
+        .find(|d| d.dataset() == &root && d.principal() == &nobody)
+        .expect("Delegation is missing");
+    assert_eq!(&DelegationScope::Local, delegation.scope());
+    assert!(delegation.permissions().contains(&Permission::Snapshot));
+    assert!(delegation.permissions().contains(&Permission::Send));
+
+    zfs.unallow(&root, grant).unwrap();
+    let delegations = zfs.read_delegations(&root).unwrap();
+    assert!(delegations.iter().all(|d| d.principal() != &nobody));
+
+    let err = zfs
+        .read_delegations(format!("{}/missing", root.display()))
+        .unwrap_err();
+    assert_eq!(ErrorKind::DatasetNotFound, err.kind());
+}
+
+#[test]
+fn written_since_and_snapshot_deltas() {
+    let zpool = SHARED_ZPOOL.clone();
+    let zfs = DelegatingZfsEngine::new().expect("Failed to initialize ZfsLzc");
+    let root = PathBuf::from(format!("{}/{}", zpool, get_dataset_name()));
+    let request = CreateDatasetRequest::builder()
+        .name(root.clone())
+        .kind(DatasetKind::Filesystem)
+        .build()
+        .unwrap();
+    zfs.create(request)
+        .expect("Failed to create a root dataset");
+    let first = PathBuf::from(format!("{}@first", root.display()));
+    let second = PathBuf::from(format!("{}@second", root.display()));
+
+    zfs.snapshot(&[first.clone()], None).unwrap();
+    let mount_point = zfs.mount_point_of(&root).unwrap().unwrap();
+    let mut rng = rand::thread_rng();
+    let data: Vec<u8> = (0..2 * 1024 * 1024).map(|_| rng.gen()).collect();
+    fs::write(mount_point.join("data"), data).unwrap();
+    std::process::Command::new("sync").status().unwrap();
+    zfs.snapshot(&[second.clone()], None).unwrap();
+
+    let written = zfs.written_since(&root, "first").unwrap();
+    assert!(written >= 2 * 1024 * 1024, "written: {}", written);
+
+    let deltas = zfs.snapshot_deltas(&root).unwrap();
+    assert_eq!(2, deltas.len());
+    assert_eq!(first, deltas[0].0);
+    assert_eq!(second, deltas[1].0);
+    assert!(deltas[1].1 >= 2 * 1024 * 1024);
+
+    let err = zfs.written_since(&root, "missing").unwrap_err();
+    assert_ne!(ErrorKind::Unimplemented, err.kind());
+}
+
 #[test]
 fn send_snapshot() {
     let zpool = SHARED_ZPOOL.clone();
@@ -503,3 +1380,290 @@ fn send_snapshot_incremental() {
     zfs.send_incremental(snapshot, src_snapshot, tmpfile, SendFlags::empty())
         .unwrap();
 }
+
+#[test]
+fn common_snapshots_after_receive() {
+    use std::io::{Seek, SeekFrom};
+
+    let zpool = SHARED_ZPOOL.clone();
+    let zfs = DelegatingZfsEngine::new().expect("Failed to initialize ZfsLzc");
+    let src = PathBuf::from(format!("{}/{}", zpool, get_dataset_name()));
+    let request = CreateDatasetRequest::builder()
+        .name(src.clone())
+        .kind(DatasetKind::Filesystem)
+        .build()
+        .unwrap();
+    zfs.create(request).expect("Failed to create a dataset");
+    let snap =
+        |dataset: &PathBuf, name: &str| PathBuf::from(format!("{}@{}", dataset.display(), name));
+    for name in &["first", "second"] {
+        zfs.snapshot(&[snap(&src, name)], None)
+            .expect("Failed to create snapshot");
+    }
+
+    let mut stream = tempfile::tempfile().unwrap();
+    zfs.send_replication(
+        snap(&src, "second"),
+        None,
+        stream.try_clone().unwrap(),
+        ReplicationSendOptions::default(),
+    )
+    .unwrap();
+    stream.seek(SeekFrom::Start(0)).unwrap();
+    let dst = PathBuf::from(format!("{}/{}", zpool, get_dataset_name()));
+    zfs.receive(dst.clone(), stream, ReceiveOptions::default())
+        .unwrap();
+
+    // Same names, different lineage.
+    zfs.snapshot(&[snap(&src, "third")], None).unwrap();
+    zfs.snapshot(&[snap(&dst, "third")], None).unwrap();
+
+    let guid = zfs.snapshot_guid(snap(&src, "first")).unwrap();
+    assert_eq!(guid, zfs.snapshot_guid(snap(&dst, "first")).unwrap());
+    assert!(replication::verify_replication(
+        &zfs,
+        snap(&src, "second"),
+        &zfs,
+        snap(&dst, "second")
+    )
+    .unwrap());
+    assert!(
+        !replication::verify_replication(&zfs, snap(&src, "third"), &zfs, snap(&dst, "third"))
+            .unwrap()
+    );
+
+    let common = replication::common_snapshots(&zfs, &src, &zfs, &dst).unwrap();
+    let expected = vec![
+        (snap(&src, "first"), snap(&dst, "first")),
+        (snap(&src, "second"), snap(&dst, "second")),
+    ];
+    assert_eq!(expected, common);
+
+    let err = zfs.snapshot_guid(&src).unwrap_err();
+    assert_eq!(ErrorKind::ValidationErrors, err.kind());
+}
+
+#[test]
+fn send_replication_and_receive() {
+    use std::io::{Seek, SeekFrom};
+
+    let zpool = SHARED_ZPOOL.clone();
+    let zfs = DelegatingZfsEngine::new().expect("Failed to initialize ZfsLzc");
+    let src_name = get_dataset_name();
+    let src = PathBuf::from(format!("{}/{}", zpool, &src_name));
+    let tree = vec![src.clone(), src.join("b"), src.join("c")];
+    for dataset in &tree {
+        let request = CreateDatasetRequest::builder()
+            .name(dataset.clone())
+            .kind(DatasetKind::Filesystem)
+            .build()
+            .unwrap();
+        zfs.create(request).expect("Failed to create a dataset");
+    }
+    for snap in &["first", "second"] {
+        let snapshots: Vec<PathBuf> = tree
+            .iter()
+            .map(|dataset| PathBuf::from(format!("{}@{}", dataset.display(), snap)))
+            .collect();
+        zfs.snapshot(&snapshots, None)
+            .expect("Failed to create snapshots");
+    }
+
+    let mut stream = tempfile::tempfile().unwrap();
+    let to_snapshot = PathBuf::from(format!("{}@second", src.display()));
+    zfs.send_replication(
+        to_snapshot,
+        None,
+        stream.try_clone().unwrap(),
+        ReplicationSendOptions::default(),
+    )
+    .unwrap();
+    stream.seek(SeekFrom::Start(0)).unwrap();
+
+    let dst = PathBuf::from(format!("{}/{}", zpool, get_dataset_name()));
+    let request = CreateDatasetRequest::builder()
+        .name(dst.clone())
+        .kind(DatasetKind::Filesystem)
+        .build()
+        .unwrap();
+    zfs.create(request).expect("Failed to create a dataset");
+    let opts = ReceiveOptions::builder()
+        .force(true)
+        .discard_pool_name(true)
+        .build()
+        .unwrap();
+    zfs.receive(dst.clone(), stream, opts).unwrap();
+
+    let received = dst.join(&src_name);
+    let expected: Vec<PathBuf> = vec!["", "b", "c"]
+        .into_iter()
+        .flat_map(|child| {
+            let dataset = if child.is_empty() {
+                received.clone()
+            } else {
+                received.join(child)
+            };
+            vec![
+                PathBuf::from(format!("{}@first", dataset.display())),
+                PathBuf::from(format!("{}@second", dataset.display())),
+            ]
+        })
+        .collect();
+    let mut snapshots = zfs.list_snapshots(received).unwrap();
+    snapshots.sort();
+    assert_eq!(expected, snapshots);
+}
+
+#[test]
+fn clone_tree_and_activate() {
+    let zpool = SHARED_ZPOOL.clone();
+    let zfs = DelegatingZfsEngine::new().expect("Failed to initialize ZfsLzc");
+    let src = PathBuf::from(format!("{}/{}", zpool, get_dataset_name()));
+    let tree = vec![src.clone(), src.join("b"), src.join("b/c")];
+    for dataset in &tree {
+        let request = CreateDatasetRequest::builder()
+            .name(dataset.clone())
+            .kind(DatasetKind::Filesystem)
+            .build()
+            .unwrap();
+        zfs.create(request).expect("Failed to create a dataset");
+    }
+    let snapshots: Vec<PathBuf> = tree
+        .iter()
+        .map(|dataset| PathBuf::from(format!("{}@be", dataset.display())))
+        .collect();
+    zfs.snapshot(&snapshots, None)
+        .expect("Failed to create snapshots");
+
+    let dst = PathBuf::from(format!("{}/{}", zpool, get_dataset_name()));
+    let source_snapshot = PathBuf::from(format!("{}@be", src.display()));
+    let created = be::clone_tree(&zfs, source_snapshot.clone(), dst.clone()).unwrap();
+    assert_eq!(vec![dst.clone(), dst.join("b"), dst.join("b/c")], created);
+    for clone in &created {
+        if let Properties::Filesystem(props) = zfs.read_properties(clone).unwrap() {
+            assert_eq!(&CanMount::NoAuto, props.can_mount());
+            assert!(props.origin().as_ref().unwrap().ends_with("@be"));
+        } else {
+            panic!("Not a filesystem");
+        }
+    }
+
+    // Target already exists, nothing to roll back.
+    let err = be::clone_tree(&zfs, source_snapshot, dst.clone()).unwrap_err();
+    assert_eq!(ErrorKind::CloneTreeFailed, err.kind());
+    if let Error::CloneTreeFailed {
+        dataset,
+        left_behind,
+        ..
+    } = err
+    {
+        assert_eq!(dst, dataset);
+        assert!(left_behind.is_empty());
+    }
+
+    be::activate(&zfs, dst.clone()).unwrap();
+    for (original, clone) in tree.iter().zip(created.iter()) {
+        if let Properties::Filesystem(props) = zfs.read_properties(original).unwrap() {
+            let expected = format!("{}@be", clone.display());
+            assert_eq!(Some(&expected), props.origin().as_ref());
+        } else {
+            panic!("Not a filesystem");
+        }
+    }
+    // Nothing left to promote.
+    be::activate(&zfs, dst).unwrap();
+}
+
+#[test]
+fn latest_and_oldest_snapshot() {
+    let zpool = SHARED_ZPOOL.clone();
+    let zfs = DelegatingZfsEngine::new().expect("Failed to initialize ZfsLzc");
+    let root = PathBuf::from(format!("{}/{}", zpool, get_dataset_name()));
+    let request = CreateDatasetRequest::builder()
+        .name(root.clone())
+        .kind(DatasetKind::Filesystem)
+        .build()
+        .unwrap();
+    zfs.create(request).expect("Failed to create a dataset");
+    assert_eq!(Ok(None), zfs.latest_snapshot(root.clone()));
+    assert_eq!(Ok(None), zfs.oldest_snapshot(root.clone()));
+
+    let snapshots: Vec<PathBuf> = ["c", "a", "b"]
+        .iter()
+        .map(|name| PathBuf::from(format!("{}@{}", root.display(), name)))
+        .collect();
+    zfs.snapshot(&snapshots[..1], None)
+        .expect("Failed to create snapshot");
+    assert_eq!(
+        Ok(Some(snapshots[0].clone())),
+        zfs.latest_snapshot(root.clone())
+    );
+    assert_eq!(
+        Ok(Some(snapshots[0].clone())),
+        zfs.oldest_snapshot(root.clone())
+    );
+
+    for snapshot in &snapshots[1..] {
+        zfs.snapshot(&[snapshot.clone()], None)
+            .expect("Failed to create snapshot");
+    }
+    assert_eq!(
+        Ok(Some(snapshots[2].clone())),
+        zfs.latest_snapshot(root.clone())
+    );
+    assert_eq!(
+        Ok(Some(snapshots[0].clone())),
+        zfs.oldest_snapshot(root.clone())
+    );
+
+    let missing = root.join("missing");
+    assert_eq!(
+        Some(ErrorKind::DatasetNotFound),
+        zfs.latest_snapshot(missing).err().map(|e| e.kind())
+    );
+}
+
+#[test]
+fn partial_receive_list_and_abort() {
+    use std::io::{Seek, SeekFrom};
+
+    let zpool = SHARED_ZPOOL.clone();
+    let zfs = DelegatingZfsEngine::new().expect("Failed to initialize ZfsLzc");
+    let src = PathBuf::from(format!("{}/{}", zpool, get_dataset_name()));
+    let request = CreateDatasetRequest::builder()
+        .name(src.clone())
+        .kind(DatasetKind::Filesystem)
+        .build()
+        .unwrap();
+    zfs.create(request).expect("Failed to create a dataset");
+    let snapshot = PathBuf::from(format!("{}@partial", src.display()));
+    zfs.snapshot(&[snapshot.clone()], None)
+        .expect("Failed to create snapshot");
+
+    // Cut the stream in half, so the receive is interrupted.
+    let mut stream = tempfile::tempfile().unwrap();
+    zfs.send_full(snapshot, stream.try_clone().unwrap(), SendFlags::empty())
+        .unwrap();
+    let len = stream.metadata().unwrap().len();
+    stream.set_len(len / 2).unwrap();
+    stream.seek(SeekFrom::Start(0)).unwrap();
+
+    let dst = PathBuf::from(format!("{}/{}", zpool, get_dataset_name()));
+    let opts = ReceiveOptions::builder().resumable(true).build().unwrap();
+    assert!(zfs.receive(dst.clone(), stream, opts).is_err());
+
+    let partial = zfs.partial_receives(zpool.clone()).unwrap();
+    let entry = partial
+        .iter()
+        .find(|entry| entry.dataset() == &dst)
+        .expect("partial receive is not listed");
+    assert!(entry.token().starts_with("1-"));
+
+    zfs.abort_partial_receive(dst.clone()).unwrap();
+    let partial = zfs.partial_receives(zpool).unwrap();
+    assert!(partial.iter().all(|entry| entry.dataset() != &dst));
+    assert_eq!(
+        Some(ErrorKind::NoResumableReceive),
+        zfs.abort_partial_receive(src).err().map(|e| e.kind())
+    );
+}
@@ -9,6 +9,8 @@ use std::{
     panic,
     path::{Path, PathBuf},
     sync::Mutex,
+    thread,
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
 use cavity::{fill, Bytes, WriteMode};
@@ -17,10 +19,14 @@ use rand::Rng;
 use libzetta::{
     slog::*,
     zpool::{
-        open3::{StatusOptions, StatusOptionsBuilder},
-        CreateMode, CreateVdevRequest, CreateZpoolRequestBuilder, DestroyMode, ExportMode,
-        FailMode, Health, OfflineMode, OnlineMode, Zpool, ZpoolEngine, ZpoolError, ZpoolErrorKind,
-        ZpoolOpen3, ZpoolPropertiesWriteBuilder,
+        open3::{
+            AttachOptions, ExportOptions, ImportOptions, StatusOptions, StatusOptionsBuilder,
+            TimestampFormat,
+        },
+        Compatibility, CreateMode, CreateVdevRequest, CreateZpoolRequestBuilder, DestroyMode,
+        ExportMode, FailMode, FeatureState, FeatureToggle, Health, OfflineMode, OnlineMode,
+        ScanStatus, ScrubOutcome, Zpool, ZpoolEngine, ZpoolError, ZpoolErrorKind, ZpoolOpen3,
+        ZpoolPropertiesWriteBuilder,
     },
 };
 
@@ -78,10 +84,11 @@ where
     });
 
     let zpool = ZpoolOpen3::default();
-    let _ = zpool.destroy(&name, DestroyMode::Force);
+    let teardown = zpool.destroy_if_exists(&name, DestroyMode::Force);
     drop(lock);
 
     result.unwrap();
+    teardown.unwrap();
 }
 
 #[cfg(target_os = "freebsd")]
@@ -107,6 +114,212 @@ fn get_logger() -> Logger {
     )
 }
 
+#[test]
+fn create_dry_run_does_not_create() {
+    run_test(|name| {
+        let zpool = ZpoolOpen3::default();
+        let topo = CreateZpoolRequestBuilder::default()
+            .name(name.clone())
+            .vdev(CreateVdevRequest::Mirror(vec![
+                "/vdevs/vdev0".into(),
+                "/vdevs/vdev1".into(),
+            ]))
+            .zil(CreateVdevRequest::SingleDisk("/vdevs/vdev2".into()))
+            .build()
+            .unwrap();
+
+        let layout = zpool.create_dry_run(topo.clone()).unwrap();
+        assert_eq!(&name, layout.name());
+        assert_eq!(layout, topo);
+        assert!(!zpool.exists(&name).unwrap());
+    });
+}
+
+#[test]
+fn create_with_resolved_devices() {
+    run_test(|name| {
+        let zpool = ZpoolOpen3::default();
+        let topo = CreateZpoolRequestBuilder::default()
+            .name(name.clone())
+            .vdev(CreateVdevRequest::Mirror(vec![
+                "/vdevs/vdev0".into(),
+                "/vdevs/vdev1".into(),
+            ]))
+            .resolve_devices(true)
+            .build()
+            .unwrap();
+        topo.validate_devices().unwrap();
+
+        zpool.create(topo).unwrap();
+        assert!(zpool.exists(&name).unwrap());
+        zpool.destroy(&name, DestroyMode::Force).unwrap();
+    });
+}
+
+#[test]
+fn comment_and_user_properties_round_trip() {
+    run_test(|name| {
+        let zpool = ZpoolOpen3::default();
+        let topo = CreateZpoolRequestBuilder::default()
+            .name(name.clone())
+            .vdev(CreateVdevRequest::SingleDisk("/vdevs/vdev0".into()))
+            .build()
+            .unwrap();
+        zpool.create(topo).unwrap();
+
+        let props = zpool.read_properties(&name).unwrap();
+        let comment = String::from("a = \"test\" b=c");
+        let updated_props = ZpoolPropertiesWriteBuilder::from_props(&props)
+            .comment(comment.clone())
+            .user_property("org.example:owner", "team = ops")
+            .user_property("org.example:note", "Grüße, 世界 \"quoted\"")
+            .build()
+            .unwrap();
+        // Pools only have user properties since OpenZFS 2.2. Comment set before them is restored
+        // on older versions.
+        match zpool.update_properties(&name, updated_props) {
+            Ok(changed) => {
+                assert_eq!(
+                    vec!["comment", "org.example:note", "org.example:owner"],
+                    changed
+                );
+                let props = zpool.read_properties(&name).unwrap();
+                assert_eq!(&Some(comment), props.comment());
+                let user_properties = props.user_properties();
+                assert_eq!(
+                    Some(&String::from("team = ops")),
+                    user_properties.get("org.example:owner")
+                );
+                assert_eq!(
+                    Some(&String::from("Grüße, 世界 \"quoted\"")),
+                    user_properties.get("org.example:note")
+                );
+            }
+            Err(ZpoolError::UpdateFailed {
+                key,
+                cause,
+                rollback,
+            }) => {
+                assert_eq!("org.example:note", key);
+                assert_eq!(ZpoolErrorKind::UnsupportedProperty, cause.kind());
+                assert_eq!(vec![(String::from("comment"), true)], rollback);
+                let props = zpool.read_properties(&name).unwrap();
+                assert_eq!(&None, props.comment());
+            }
+            Err(e) => panic!("unexpected error: {:?}", e),
+        }
+        let props = zpool.read_properties(&name).unwrap();
+        assert_eq!(&Health::Online, props.health());
+
+        let updated_props = ZpoolPropertiesWriteBuilder::from_props(&props)
+            .comment("touch\tit")
+            .build();
+        assert!(updated_props.is_err());
+        let updated_props = ZpoolPropertiesWriteBuilder::from_props(&props)
+            .comment("touch\nit")
+            .build();
+        assert!(updated_props.is_err());
+    });
+}
+
+#[test]
+fn reguid_changes_guid() {
+    run_test(|name| {
+        let zpool = ZpoolOpen3::default();
+        let topo = CreateZpoolRequestBuilder::default()
+            .name(name.clone())
+            .vdev(CreateVdevRequest::SingleDisk("/vdevs/vdev0".into()))
+            .build()
+            .unwrap();
+        zpool.create(topo).unwrap();
+
+        let before = zpool.read_properties(&name).unwrap();
+        zpool.reguid(&name).unwrap();
+        let after = zpool.read_properties(&name).unwrap();
+        assert_ne!(before.guid(), after.guid());
+        assert_eq!(&Health::Online, after.health());
+    });
+}
+
+#[test]
+fn health_of_new_pool() {
+    run_test(|name| {
+        let zpool = ZpoolOpen3::default();
+        let topo = CreateZpoolRequestBuilder::default()
+            .name(name.clone())
+            .vdev(CreateVdevRequest::SingleDisk("/vdevs/vdev0".into()))
+            .build()
+            .unwrap();
+        zpool.create(topo).unwrap();
+
+        assert_eq!(Health::Online, zpool.health(&name).unwrap());
+        let all = zpool.health_all().unwrap();
+        assert!(all.contains(&(name.clone(), Health::Online)));
+
+        let result = zpool.health("fake_pool_that_does_not_exist");
+        assert_eq!(ZpoolErrorKind::PoolNotFound, result.unwrap_err().kind());
+    });
+}
+
+#[test]
+fn list_pools_has_new_pool() {
+    run_test(|name| {
+        let zpool = ZpoolOpen3::default();
+        let topo = CreateZpoolRequestBuilder::default()
+            .name(name.clone())
+            .vdev(CreateVdevRequest::SingleDisk("/vdevs/vdev0".into()))
+            .build()
+            .unwrap();
+        zpool.create(topo).unwrap();
+
+        let pools = zpool.list_pools().unwrap();
+        let entry = pools
+            .iter()
+            .find(|pool| pool.name() == &name)
+            .expect("new pool is not listed");
+        let props = zpool.read_properties(&name).unwrap();
+        assert_eq!(props.size(), entry.size());
+        assert_eq!(&Health::Online, entry.health());
+        assert_eq!(&None, entry.alt_root());
+    });
+}
+
+#[test]
+fn create_with_grub2_compatibility() {
+    run_test(|name| {
+        let zpool = ZpoolOpen3::default();
+        let topo = CreateZpoolRequestBuilder::default()
+            .name(name.clone())
+            .vdev(CreateVdevRequest::SingleDisk("/vdevs/vdev0".into()))
+            .compatibility_preset(Compatibility::Grub2)
+            .feature("lz4_compress", FeatureToggle::Enabled)
+            .build()
+            .unwrap();
+        zpool.create(topo).unwrap();
+
+        let allowed = Compatibility::Grub2.features();
+        let props = zpool.read_properties(&name).unwrap();
+        let usable: Vec<&String> = props
+            .features()
+            .iter()
+            .filter(|(_, state)| state.is_usable())
+            .map(|(feature, _)| feature)
+            .collect();
+        assert!(!usable.is_empty());
+        for feature in usable {
+            assert!(
+                allowed.contains(feature),
+                "{} is not allowed by grub2",
+                feature
+            );
+        }
+        assert!(props.features()["lz4_compress"].is_usable());
+        if let Some(state) = props.features().get("encryption") {
+            assert_eq!(&FeatureState::Disabled, state);
+        }
+    });
+}
+
 #[test]
 fn create_check_update_delete() {
     run_test(|name| {
@@ -132,7 +345,11 @@ fn create_check_update_delete() {
             .build()
             .unwrap();
 
-        zpool.update_properties(&name, updated_props).unwrap();
+        let changed = zpool.update_properties(&name, updated_props).unwrap();
+        assert_eq!(
+            vec!["autoexpand", "autoreplace", "comment", "failmode"],
+            changed
+        );
         let props = zpool.read_properties(&name).unwrap();
         assert_eq!(&true, props.auto_expand());
         assert_eq!(&true, props.auto_replace());
@@ -143,7 +360,8 @@ fn create_check_update_delete() {
             .comment("Wat")
             .build()
             .unwrap();
-        zpool.update_properties(&name, updated_props).unwrap();
+        let changed = zpool.update_properties(&name, updated_props).unwrap();
+        assert!(changed.is_empty());
         let props = zpool.read_properties(&name).unwrap();
         assert_eq!(&true, props.auto_expand());
         assert_eq!(&true, props.auto_replace());
@@ -164,6 +382,42 @@ fn create_check_update_delete() {
 
         let result = zpool.exists(&name).unwrap();
         assert!(!result);
+        let err = zpool.destroy(&name, DestroyMode::Force).unwrap_err();
+        assert_eq!(ZpoolErrorKind::PoolNotFound, err.kind());
+        assert!(!zpool.destroy_if_exists(&name, DestroyMode::Gentle).unwrap());
+    })
+}
+
+#[test]
+fn autotrim_round_trip() {
+    run_test(|name| {
+        let zpool = ZpoolOpen3::default();
+
+        let topo = CreateZpoolRequestBuilder::default()
+            .name(name.clone())
+            .vdev(CreateVdevRequest::SingleDisk("/vdevs/vdev0".into()))
+            .build()
+            .unwrap();
+        zpool.create(topo).unwrap();
+
+        let props = zpool.read_properties(&name).unwrap();
+        let updated_props = ZpoolPropertiesWriteBuilder::from_props(&props)
+            .auto_trim(Some(true))
+            .build()
+            .unwrap();
+        if cfg!(target_os = "linux") || props.auto_trim().is_some() {
+            assert_eq!(&Some(false), props.auto_trim());
+            assert!(props.ashift().is_some());
+            let changed = zpool.update_properties(&name, updated_props).unwrap();
+            assert_eq!(vec!["autotrim"], changed);
+            let props = zpool.read_properties(&name).unwrap();
+            assert_eq!(&Some(true), props.auto_trim());
+        } else {
+            let err = zpool.update_properties(&name, updated_props).unwrap_err();
+            assert_eq!(ZpoolErrorKind::UnsupportedProperty, err.kind());
+        }
+
+        zpool.destroy(&name, DestroyMode::Force).unwrap();
     })
 }
 
@@ -392,6 +646,79 @@ fn test_export_import() {
     });
 }
 
+#[test]
+fn test_import_renamed() {
+    run_test(|name| {
+        let vdev_dir = Path::new("/vdevs/import");
+        setup_vdev(vdev_dir.join("vdev0"), &Bytes::MegaBytes(64 + 10));
+        let zpool = ZpoolOpen3::default();
+
+        let topo = CreateZpoolRequestBuilder::default()
+            .name(name.clone())
+            .vdev(CreateVdevRequest::SingleDisk("/vdevs/import/vdev0".into()))
+            .build()
+            .unwrap();
+        zpool
+            .create(topo)
+            .expect("Failed to create pool for export");
+        zpool.export(&name, ExportMode::Gentle).unwrap();
+
+        let new_name = format!("{}-renamed", name);
+        zpool
+            .import_renamed(
+                name.clone(),
+                new_name.clone(),
+                Some(PathBuf::from(vdev_dir)),
+                ImportOptions::default(),
+            )
+            .unwrap();
+        assert!(zpool.exists(&new_name).unwrap());
+        assert!(!zpool.exists(&name).unwrap());
+
+        zpool.destroy(&new_name, DestroyMode::Force).unwrap();
+    });
+}
+
+#[test]
+fn test_export_busy() {
+    run_test(|name| {
+        let vdev_dir = Path::new("/vdevs/import");
+        setup_vdev(vdev_dir.join("vdev0"), &Bytes::MegaBytes(64 + 10));
+        let zpool = ZpoolOpen3::default();
+
+        let topo = CreateZpoolRequestBuilder::default()
+            .name(name.clone())
+            .vdev(CreateVdevRequest::SingleDisk("/vdevs/import/vdev0".into()))
+            .build()
+            .unwrap();
+        zpool
+            .create(topo)
+            .expect("Failed to create pool for export");
+
+        let busy_file = fs::File::create(Path::new("/").join(&name).join("busy")).unwrap();
+
+        let err = zpool.export(&name, ExportMode::Gentle).unwrap_err();
+        assert_eq!(ZpoolErrorKind::PoolBusy, err.kind());
+
+        let opts = ExportOptions::builder()
+            .unmount_first(true)
+            .build()
+            .unwrap();
+        let err = zpool.export_with(&name, opts.clone()).unwrap_err();
+        if let ZpoolError::PoolBusy(dataset) = err {
+            assert_eq!(Some(PathBuf::from(&name)), dataset);
+        } else {
+            panic!("Expected PoolBusy, got {:?}", err);
+        }
+
+        drop(busy_file);
+        zpool.export_with(&name, opts).unwrap();
+        zpool
+            .import_from_dir(&name, PathBuf::from(vdev_dir))
+            .unwrap();
+    });
+}
+
 #[test]
 fn test_export_import_force() {
     run_test(|name| {
@@ -440,6 +767,20 @@ fn test_status() {
         let result = zpool.status(&name, StatusOptions::default()).unwrap();
         assert_eq!(&name, result.name());
         assert_eq!(&result, &topo);
+        assert_eq!(&None, result.queried_at());
+
+        let opts = StatusOptions::builder()
+            .timestamp(Some(TimestampFormat::Unix))
+            .build()
+            .unwrap();
+        let before = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as i64;
+        let result = zpool.status(&name, opts).unwrap();
+        assert_eq!(&result, &topo);
+        let queried_at = result.queried_at().unwrap();
+        assert!(queried_at >= before && queried_at - before < 60);
     });
 }
 #[test]
@@ -519,6 +860,66 @@ fn test_zpool_scrub() {
     });
 }
 
+#[test]
+fn test_zpool_sync() {
+    run_test(|name| {
+        let zpool = ZpoolOpen3::default();
+        let vdev_path = setup_vdev("/vdevs/vdev0", &Bytes::MegaBytes(64 + 10));
+        let topo = CreateZpoolRequestBuilder::default()
+            .name(name.clone())
+            .vdev(CreateVdevRequest::SingleDisk(vdev_path))
+            .build()
+            .unwrap();
+        zpool.create(topo).unwrap();
+
+        zpool.sync(Some(&name)).unwrap();
+        zpool.sync(None).unwrap();
+        zpool
+            .sync_with_timeout(Some(&name), Some(Duration::from_secs(60)))
+            .unwrap();
+
+        let result = zpool.sync(Some("non-existent"));
+        assert_eq!(ZpoolErrorKind::PoolNotFound, result.unwrap_err().kind());
+    });
+}
+
+#[test]
+fn test_zpool_ensure_scrubbed_within() {
+    run_test(|name| {
+        let zpool = ZpoolOpen3::default();
+        let vdev_path = setup_vdev("/vdevs/vdev0", &Bytes::MegaBytes(64 + 10));
+        let topo = CreateZpoolRequestBuilder::default()
+            .name(name.clone())
+            .vdev(CreateVdevRequest::SingleDisk(vdev_path))
+            .build()
+            .unwrap();
+        zpool.create(topo).unwrap();
+
+        let status = zpool.status(&name, StatusOptions::default()).unwrap();
+        assert_eq!(&Some(ScanStatus::NoneRequested), status.scan());
+
+        let day = Duration::from_secs(24 * 60 * 60);
+        let result = zpool.ensure_scrubbed_within(&name, day).unwrap();
+        assert_eq!(ScrubOutcome::Started, result);
+
+        for _ in 0..30 {
+            let status = zpool.status(&name, StatusOptions::default()).unwrap();
+            if status
+                .scan()
+                .as_ref()
+                .map_or(false, ScanStatus::is_scrubbing)
+            {
+                thread::sleep(Duration::from_secs(1));
+            } else {
+                break;
+            }
+        }
+
+        let result = zpool.ensure_scrubbed_within(&name, day).unwrap();
+        assert_eq!(ScrubOutcome::NotNeeded, result);
+    });
+}
+
 #[test]
 fn test_zpool_take_single_device_offline() {
     run_test(|name| {
@@ -568,6 +969,62 @@ fn test_zpool_take_device_from_mirror_offline() {
     });
 }
 
+#[test]
+fn test_zpool_detach_missing_device_by_guid() {
+    run_test(|name| {
+        let zpool = ZpoolOpen3::default();
+        let vdev_dir = Path::new("/vdevs/missing");
+        let vdev0_path = setup_vdev(vdev_dir.join("vdev0"), &Bytes::MegaBytes(64 + 10));
+        let vdev1_path = setup_vdev(vdev_dir.join("vdev1"), &Bytes::MegaBytes(64 + 10));
+        let vdev2_path = setup_vdev(vdev_dir.join("vdev2"), &Bytes::MegaBytes(64 + 10));
+        let topo = CreateZpoolRequestBuilder::default()
+            .name(name.clone())
+            .create_mode(CreateMode::Force)
+            .vdev(CreateVdevRequest::Mirror(vec![
+                vdev0_path.clone(),
+                vdev1_path.clone(),
+                vdev2_path.clone(),
+            ]))
+            .build()
+            .unwrap();
+        zpool.create(topo).unwrap();
+
+        zpool
+            .take_offline(&name, &vdev1_path, OfflineMode::Permanent)
+            .unwrap();
+        zpool.export(&name, ExportMode::Gentle).unwrap();
+        fs::remove_file(&vdev2_path).unwrap();
+        zpool
+            .import_from_dir(&name, PathBuf::from(vdev_dir))
+            .unwrap();
+
+        let z = zpool.status(&name, StatusOptions::default()).unwrap();
+        let missing = z.vdevs()[0]
+            .disks()
+            .iter()
+            .find(|disk| disk.guid().is_some())
+            .cloned()
+            .expect("Missing device isn't reported by GUID");
+        assert_eq!(&vdev2_path, missing.path());
+        zpool.detach(&name, missing.guid().unwrap()).unwrap();
+
+        let z = zpool.status(&name, StatusOptions::default()).unwrap();
+        let disks = z.vdevs()[0].disks();
+        assert_eq!(2, disks.len());
+        assert!(disks.iter().all(|disk| disk.guid().is_none()));
+
+        let offline = disks
+            .iter()
+            .find(|disk| disk.health() == &Health::Offline)
+            .unwrap();
+        zpool
+            .bring_online(&name, offline, OnlineMode::Simple)
+            .unwrap();
+        let z = zpool.status(&name, StatusOptions::default()).unwrap();
+        assert_eq!(&Health::Online, z.health());
+    });
+}
+
 #[test]
 fn test_zpool_take_device_from_mirror_offline_expand() {
     run_test(|name| {
@@ -598,6 +1055,44 @@ fn test_zpool_take_device_from_mirror_offline_expand() {
     });
 }
 
+#[test]
+fn test_zpool_attach_sequential_and_wait() {
+    run_test(|name| {
+        let zpool = ZpoolOpen3::default();
+        let vdev0_path = setup_vdev("/vdevs/vdev1", &Bytes::MegaBytes(64 + 10));
+        let vdev1_path = setup_vdev("/vdevs/vdev2", &Bytes::MegaBytes(64 + 10));
+        let topo = CreateZpoolRequestBuilder::default()
+            .name(name.clone())
+            .create_mode(CreateMode::Force)
+            .vdev(CreateVdevRequest::SingleDisk(vdev0_path.clone()))
+            .build()
+            .unwrap();
+        zpool.create(topo).unwrap();
+
+        let opts = AttachOptions::builder()
+            .sequential(true)
+            .wait(true)
+            .build()
+            .unwrap();
+        zpool
+            .attach_with(&name, &vdev0_path, &vdev1_path, opts)
+            .unwrap();
+
+        let z = zpool.status(&name, StatusOptions::default()).unwrap();
+        let topo_expected = CreateZpoolRequestBuilder::default()
+            .name(name.clone())
+            .create_mode(CreateMode::Force)
+            .vdev(CreateVdevRequest::Mirror(vec![
+                vdev0_path.clone(),
+                vdev1_path.clone(),
+            ]))
+            .build()
+            .unwrap();
+        assert_eq!(&z, &topo_expected);
+        assert!(z.vdevs()[0].disks().iter().all(|d| d.resilver().is_none()));
+    });
+}
+
 #[test]
 fn test_zpool_attach_then_detach_single() {
     run_test(|name| {
@@ -772,13 +1267,28 @@ fn test_zpool_add_mirror_to_raidz() {
 
         let new_vdev = CreateVdevRequest::Mirror(vec![vdev3_path.clone(), vdev4_path.clone()]);
 
-        let result = zpool.add_vdev(&name, new_vdev, CreateMode::default());
+        let result = zpool.add_vdev(&name, new_vdev.clone(), CreateMode::default());
 
         assert!(result.is_err());
 
         if let Err(r) = result {
             assert_eq!(ZpoolErrorKind::MismatchedReplicationLevel, r.kind());
         }
+
+        let result = zpool.add_vdev_checked(&name, new_vdev.clone(), CreateMode::default());
+        match result {
+            Err(ZpoolError::MismatchedReplicationLevel(pool, new)) => {
+                assert_eq!("raidz", pool);
+                assert_eq!("mirror", new);
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+
+        zpool
+            .add_vdev_checked(&name, new_vdev, CreateMode::Force)
+            .unwrap();
+        let z = zpool.status(&name, StatusOptions::default()).unwrap();
+        assert_eq!(2, z.vdevs().len());
     });
 }
 
@@ -909,9 +1419,40 @@ fn test_zpool_add_spare() {
 }
 
 #[test]
-fn test_zpool_replace_disk() {
-    use std::{thread, time};
+fn test_zpool_replace_with_spare() {
+    run_test(|name| {
+        let zpool = ZpoolOpen3::default();
+        let vdev0_path = setup_vdev("/vdevs/vdev0", &Bytes::MegaBytes(64 + 10));
+        let vdev1_path = setup_vdev("/vdevs/vdev1", &Bytes::MegaBytes(64 + 10));
+        let vdev2_path = setup_vdev("/vdevs/vdev2", &Bytes::MegaBytes(64 + 10));
+        let topo = CreateZpoolRequestBuilder::default()
+            .name(name.clone())
+            .create_mode(CreateMode::Force)
+            .vdev(CreateVdevRequest::Mirror(vec![
+                vdev0_path.clone(),
+                vdev1_path.clone(),
+            ]))
+            .spare(vdev2_path.clone())
+            .build()
+            .unwrap();
+        zpool.create(topo).unwrap();
 
+        assert_eq!(vdev2_path, zpool.replace_with_spare_dry_run(&name).unwrap());
+        zpool
+            .take_offline(&name, &vdev0_path, OfflineMode::UntilReboot)
+            .unwrap();
+        let spare = zpool.replace_with_spare(&name, &vdev0_path).unwrap();
+        assert_eq!(vdev2_path, spare);
+
+        let z = zpool.status(&name, StatusOptions::default()).unwrap();
+        assert_eq!(&Health::Inuse, z.spares()[0].health());
+        let err = zpool.replace_with_spare(&name, &vdev1_path).unwrap_err();
+        assert_eq!(ZpoolErrorKind::NoAvailableSpare, err.kind());
+    });
+}
+
+#[test]
+fn test_zpool_replace_disk() {
     run_test(|name| {
         let zpool = ZpoolOpen3::default();
         let vdev0_path = setup_vdev("/vdevs/vdev0", &Bytes::MegaBytes(64 + 10));
@@ -928,7 +1469,8 @@ fn test_zpool_replace_disk() {
             .unwrap();
         zpool.create(topo.clone()).unwrap();
 
-        let result = zpool.replace_disk(&name, &vdev0_path, &vdev2_path);
+        let opts = AttachOptions::builder().wait(true).build().unwrap();
+        let result = zpool.replace_disk_with(&name, &vdev0_path, &vdev2_path, opts);
         assert!(result.is_ok());
 
         let topo_expected = CreateZpoolRequestBuilder::default()
@@ -941,10 +1483,6 @@ fn test_zpool_replace_disk() {
             .build()
             .unwrap();
 
-        // otherwise, test _might_ fail.
-        let wait_time = time::Duration::from_secs(13);
-        thread::sleep(wait_time);
-
         let opts = StatusOptionsBuilder::default()
             .full_paths(true)
             .build()